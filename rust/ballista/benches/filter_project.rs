@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ballista::arrow::datatypes::{DataType, Field, Schema};
+use ballista::datafusion::logicalplan::{col_index, Expr, Operator, ScalarValue};
+use ballista::distributed::executor::{DefaultContext, DiscoveryMode, ExecutorConfig};
+use ballista::execution::expressions::{add, boolean, col, compare, lit};
+use ballista::execution::operators::{FilterExec, InMemoryTableScanExec};
+use ballista::execution::physical_plan::{ExecutionPlan, PhysicalPlan};
+use ballista::utils::datagen::DataGen;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut gen = DataGen::default();
+
+    let schema = Schema::new(vec![
+        Field::new("c0", DataType::Int32, true),
+        Field::new("c1", DataType::Int64, false),
+        Field::new("c2", DataType::Float64, false),
+    ]);
+    let batches: Vec<_> = (0..16)
+        .map(|_| gen.create_batch(&schema, 8192).unwrap())
+        .collect();
+    let batch = &batches[0];
+
+    let lt = compare(col(1, "c1"), &Operator::Lt, lit(ScalarValue::Int64(0)));
+    c.bench_function("compare column with literal", |b| {
+        b.iter(|| lt.evaluate(batch).unwrap())
+    });
+
+    let lt_columns = compare(col(1, "c1"), &Operator::Lt, add(col(1, "c1"), col(1, "c1")));
+    c.bench_function("compare columns", |b| {
+        b.iter(|| lt_columns.evaluate(batch).unwrap())
+    });
+
+    let and = boolean(
+        compare(col(1, "c1"), &Operator::Lt, lit(ScalarValue::Int64(0))),
+        &Operator::And,
+        compare(col(2, "c2"), &Operator::Gt, lit(ScalarValue::Float64(0.0))),
+    );
+    c.bench_function("and of comparisons", |b| {
+        b.iter(|| and.evaluate(batch).unwrap())
+    });
+
+    let project = add(col(2, "c2"), col(2, "c2"));
+    c.bench_function("project arithmetic", |b| {
+        b.iter(|| project.evaluate(batch).unwrap())
+    });
+
+    let scan = PhysicalPlan::InMemoryTableScan(Arc::new(InMemoryTableScanExec::new(batches)));
+    let predicate = Expr::BinaryExpr {
+        left: Box::new(col_index(1).lt(&Expr::Literal(ScalarValue::Int64(0)))),
+        op: Operator::And,
+        right: Box::new(col_index(0).gt_eq(&Expr::Literal(ScalarValue::Int32(0)))),
+    };
+    let filter = FilterExec::new(&scan, &predicate);
+    let config = ExecutorConfig::new(DiscoveryMode::Standalone, "", 0, "");
+    let ctx = Arc::new(DefaultContext::new(&config, HashMap::new()));
+    c.bench_function("filter 128k rows", |b| {
+        b.iter(|| {
+            smol::run(async {
+                let stream = filter.execute(ctx.clone(), 0).await.unwrap();
+                let mut rows = 0;
+                while let Some(batch) = stream.next().await.unwrap() {
+                    rows += batch.num_rows();
+                }
+                rows
+            })
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
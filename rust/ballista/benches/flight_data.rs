@@ -0,0 +1,46 @@
+use ballista::arrow::datatypes::{DataType, Field, Schema};
+use ballista::distributed::compression::ShuffleCompression;
+use ballista::distributed::flight_data::{FlightDataDecoder, FlightDataEncoder};
+use ballista::utils::datagen::DataGen;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut gen = DataGen::default();
+
+    let schema = Schema::new(vec![
+        Field::new("c0", DataType::Int32, true),
+        Field::new("c1", DataType::Int64, false),
+        Field::new("c2", DataType::Float64, false),
+        Field::new("c3", DataType::UInt8, false),
+    ]);
+    let batch = gen.create_batch(&schema, 8192).unwrap().to_arrow().unwrap();
+    let schema = batch.schema();
+
+    for compression in &[ShuffleCompression::None, ShuffleCompression::Lz4] {
+        c.bench_function(
+            &format!("encode 8k rows compression={}", compression),
+            |b| b.iter(|| FlightDataEncoder::new(*compression).encode(&batch).unwrap()),
+        );
+
+        let flights = FlightDataEncoder::new(*compression).encode(&batch).unwrap();
+        c.bench_function(
+            &format!("decode 8k rows compression={}", compression),
+            |b| {
+                b.iter(|| {
+                    let mut decoder = FlightDataDecoder::new(schema.clone());
+                    let mut rows = 0;
+                    for flight in &flights {
+                        if let Some(batch) = decoder.decode(flight.clone()).unwrap() {
+                            rows += batch.num_rows();
+                        }
+                    }
+                    rows
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
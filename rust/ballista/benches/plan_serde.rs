@@ -0,0 +1,85 @@
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use ballista::arrow::datatypes::{DataType, Field, Schema};
+use ballista::dataframe::sum;
+use ballista::datafusion::execution::physical_plan::csv::CsvReadOptions;
+use ballista::datafusion::logicalplan::{col, lit_str, LogicalPlanBuilder};
+use ballista::distributed::scheduler::QuerySettings;
+use ballista::execution::operators::{HashAggregateExec, ShuffleReaderExec};
+use ballista::execution::physical_plan::{
+    Action, AggregateMode, Partitioning, PhysicalPlan, ShuffleId,
+};
+use ballista::protobuf;
+use ballista::serde::{decode_protobuf, encode_protobuf};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use prost::Message;
+use uuid::Uuid;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::Int32, false),
+        Field::new("first_name", DataType::Utf8, false),
+        Field::new("last_name", DataType::Utf8, false),
+        Field::new("state", DataType::Utf8, false),
+        Field::new("salary", DataType::Int32, false),
+    ]);
+
+    let plan = LogicalPlanBuilder::scan_csv(
+        "employee.csv",
+        CsvReadOptions::new().schema(&schema).has_header(true),
+        None,
+    )
+    .and_then(|plan| plan.filter(col("state").eq(&lit_str("CO"))))
+    .and_then(|plan| plan.project(vec![col("id"), col("salary")]))
+    .and_then(|plan| plan.build())
+    .unwrap();
+    let query = Action::InteractiveQuery {
+        plan,
+        settings: QuerySettings::default(),
+    };
+    c.bench_function("encode logical plan", |b| {
+        b.iter(|| encode_protobuf(&query).unwrap())
+    });
+    let bytes = encode_protobuf(&query).unwrap();
+    c.bench_function("decode logical plan", |b| {
+        b.iter(|| decode_protobuf(&bytes).unwrap())
+    });
+
+    let shuffle_ids: Vec<_> = (0..16)
+        .map(|i| ShuffleId::new(Uuid::new_v4(), 1, i))
+        .collect();
+    let reader = ShuffleReaderExec::new(Arc::new(schema), shuffle_ids).with_partitioning(
+        Partitioning::HashPartitioning(16, vec![Arc::new(col("state"))]),
+    );
+    let aggregate = HashAggregateExec::try_new(
+        AggregateMode::FinalPartitioned,
+        vec![col("state")],
+        vec![sum(col("salary"))],
+        Arc::new(PhysicalPlan::ShuffleReader(Arc::new(reader))),
+    )
+    .unwrap();
+    let plan = PhysicalPlan::HashAggregate(Arc::new(aggregate));
+    c.bench_function("encode physical plan", |b| {
+        b.iter(|| {
+            let proto: protobuf::PhysicalPlanNode = (&plan).try_into().unwrap();
+            let mut buf = Vec::with_capacity(proto.encoded_len());
+            proto.encode(&mut buf).unwrap();
+            buf
+        })
+    });
+    let proto: protobuf::PhysicalPlanNode = (&plan).try_into().unwrap();
+    let mut bytes = Vec::with_capacity(proto.encoded_len());
+    proto.encode(&mut bytes).unwrap();
+    c.bench_function("decode physical plan", |b| {
+        b.iter(|| {
+            let proto = protobuf::PhysicalPlanNode::decode(bytes.as_slice()).unwrap();
+            let plan: PhysicalPlan = (&proto).try_into().unwrap();
+            plan
+        })
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
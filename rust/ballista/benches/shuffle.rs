@@ -0,0 +1,60 @@
+use ballista::arrow::datatypes::{DataType, Field, Schema};
+use ballista::distributed::compression::ShuffleCompression;
+use ballista::distributed::executor::ShufflePartition;
+use ballista::distributed::shuffle_store::ShuffleStore;
+use ballista::execution::physical_plan::ShuffleId;
+use ballista::utils::datagen::DataGen;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::StreamExt;
+use uuid::Uuid;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut gen = DataGen::default();
+
+    let schema = Schema::new(vec![
+        Field::new("c0", DataType::Int32, true),
+        Field::new("c1", DataType::Int64, false),
+        Field::new("c2", DataType::Float64, false),
+    ]);
+    let batches: Vec<_> = (0..8)
+        .map(|_| gen.create_batch(&schema, 8192).unwrap().to_arrow().unwrap())
+        .collect();
+    let schema = batches[0].schema().as_ref().clone();
+
+    let work_dir = std::env::temp_dir().join(format!("ballista-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+
+    // a budget of zero spills every partition to disk
+    for (name, memory_budget) in &[("memory", usize::MAX), ("disk", 0)] {
+        let store = ShuffleStore::new(work_dir.clone(), *memory_budget);
+        for compression in &[ShuffleCompression::None, ShuffleCompression::Lz4] {
+            let partition = ShufflePartition::new(schema.clone(), batches.clone(), *compression);
+            c.bench_function(
+                &format!(
+                    "write and read 64k rows storage={} compression={}",
+                    name, compression
+                ),
+                |b| {
+                    b.iter(|| {
+                        let shuffle_id = ShuffleId::new(Uuid::new_v4(), 1, 0);
+                        store.store(&shuffle_id, partition.clone()).unwrap();
+                        let (_, mut stream) = store.take(&shuffle_id).unwrap();
+                        smol::run(async {
+                            let mut rows = 0;
+                            while let Some(batch) = stream.next().await {
+                                rows += batch.unwrap().num_rows();
+                            }
+                            rows
+                        })
+                    })
+                },
+            );
+        }
+    }
+
+    std::fs::remove_dir_all(&work_dir).unwrap();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -13,6 +13,12 @@
 // limitations under the License.
 
 fn main() {
-    prost_build::compile_protos(&["proto/ballista.proto"], &["proto"])
+    tonic_build::configure()
+        .build_client(true)
+        .build_server(true)
+        .compile(
+            &["proto/ballista.proto", "proto/flight_sql.proto"],
+            &["proto"],
+        )
         .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
 }
@@ -0,0 +1,303 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks that measure the performance of queries across releases.
+//!
+//! The TPC-H runner registers the tables of a data set, runs the selected queries a number
+//! of times against a Ballista cluster, and optionally against DataFusion in this process for
+//! comparison, and reports the time of each run as JSON. Queries that fail are reported with
+//! their error rather than stopping the run, since not every query is supported yet.
+
+pub mod tpch;
+pub mod tpch_gen;
+
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::arrow::record_batch::RecordBatch;
+use crate::dataframe::{Context, CsvReadOptions};
+use crate::datafusion::execution::context::ExecutionContext as DFContext;
+use crate::error::{ballista_error, Result};
+use crate::BALLISTA_VERSION;
+
+use log::{info, warn};
+use serde_json::{json, Value};
+
+/// Engine that the benchmark queries are run on
+pub const BALLISTA_ENGINE: &str = "ballista";
+pub const DATAFUSION_ENGINE: &str = "datafusion";
+
+/// Data set and queries of a TPC-H benchmark run
+#[derive(Debug, Clone)]
+pub struct TpchConfig {
+    /// Directory with a directory for each table
+    pub path: String,
+    /// Format of the tables, `csv` for pipe-delimited files without a header, or `parquet`
+    pub format: String,
+    /// Queries to run, numbered from 1 to 22
+    pub queries: Vec<usize>,
+    /// Number of times that each query is run
+    pub iterations: usize,
+    /// Batch size of queries run on DataFusion
+    pub batch_size: usize,
+}
+
+impl TpchConfig {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.trim_end_matches('/').to_owned(),
+            format: "csv".to_owned(),
+            queries: (1..=tpch::QUERY_COUNT).collect(),
+            iterations: 3,
+            batch_size: 8192,
+        }
+    }
+
+    pub fn with_format(mut self, format: &str) -> Self {
+        self.format = format.to_owned();
+        self
+    }
+
+    pub fn with_queries(mut self, queries: Vec<usize>) -> Self {
+        self.queries = queries;
+        self
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    fn table_path(&self, table: &str) -> String {
+        format!("{}/{}", self.path, table)
+    }
+
+    fn check_format(&self) -> Result<()> {
+        match self.format.as_str() {
+            "csv" | "parquet" => Ok(()),
+            other => Err(ballista_error(&format!(
+                "Unsupported TPC-H data format '{}', expected csv or parquet",
+                other
+            ))),
+        }
+    }
+}
+
+/// Outcome of the runs of a query on an engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    pub query: usize,
+    pub engine: String,
+    /// Time of each run in milliseconds
+    pub durations_ms: Vec<u128>,
+    /// Number of rows of the results of the last run
+    pub rows: usize,
+    /// Error of the first run that failed, after which the query is not run again
+    pub error: Option<String>,
+}
+
+impl QueryResult {
+    fn new(query: usize, engine: &str) -> Self {
+        Self {
+            query,
+            engine: engine.to_owned(),
+            durations_ms: vec![],
+            rows: 0,
+            error: None,
+        }
+    }
+
+    /// Record a run of the query that started at `start`, returning false if it failed
+    fn record(&mut self, start: Instant, results: Result<Vec<RecordBatch>>) -> bool {
+        match results {
+            Ok(batches) => {
+                self.durations_ms.push(start.elapsed().as_millis());
+                self.rows = batches.iter().map(|b| b.num_rows()).sum();
+                info!(
+                    "TPC-H query={} engine={} duration_ms={} rows={}",
+                    self.query,
+                    self.engine,
+                    start.elapsed().as_millis(),
+                    self.rows
+                );
+                true
+            }
+            Err(e) => {
+                warn!(
+                    "TPC-H query failed query={} engine={} error={:?}",
+                    self.query, self.engine, e
+                );
+                self.error = Some(format!("{:?}", e));
+                false
+            }
+        }
+    }
+
+    pub fn min_ms(&self) -> Option<u128> {
+        self.durations_ms.iter().min().copied()
+    }
+
+    pub fn avg_ms(&self) -> Option<f64> {
+        if self.durations_ms.is_empty() {
+            return None;
+        }
+        Some(self.durations_ms.iter().sum::<u128>() as f64 / self.durations_ms.len() as f64)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "query": self.query,
+            "engine": self.engine,
+            "durations_ms": self.durations_ms.iter().map(|d| *d as u64).collect::<Vec<_>>(),
+            "min_ms": self.min_ms().map(|d| d as u64),
+            "avg_ms": self.avg_ms(),
+            "rows": self.rows,
+            "error": self.error,
+        })
+    }
+}
+
+/// Run the queries of a benchmark on a Ballista cluster through the executor at `host:port`
+pub async fn run_tpch_ballista(
+    config: &TpchConfig,
+    host: &str,
+    port: usize,
+) -> Result<Vec<QueryResult>> {
+    config.check_format()?;
+    let mut ctx = Context::remote(host, port, HashMap::new());
+    for table in tpch::TABLES {
+        let path = config.table_path(table);
+        if config.format == "parquet" {
+            ctx.register_parquet(table, &path)?;
+        } else {
+            let schema = tpch::schema(table)?;
+            let options = CsvReadOptions::new()
+                .schema(&schema)
+                .has_header(false)
+                .delimiter(b'|');
+            ctx.register_csv(table, &path, options)?;
+        }
+    }
+
+    let mut results = vec![];
+    for query in &config.queries {
+        let sql = tpch::query(*query)?;
+        let mut result = QueryResult::new(*query, BALLISTA_ENGINE);
+        for _ in 0..config.iterations {
+            let start = Instant::now();
+            let batches = match ctx.sql(sql) {
+                Ok(df) => df.collect().await,
+                Err(e) => Err(e),
+            };
+            if !result.record(start, batches) {
+                break;
+            }
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Run the queries of a benchmark on DataFusion in this process
+pub fn run_tpch_datafusion(config: &TpchConfig) -> Result<Vec<QueryResult>> {
+    config.check_format()?;
+    let mut ctx = DFContext::new();
+    for table in tpch::TABLES {
+        let path = config.table_path(table);
+        if config.format == "parquet" {
+            ctx.register_parquet(table, &path)?;
+        } else {
+            let schema = tpch::schema(table)?;
+            let options = CsvReadOptions::new()
+                .schema(&schema)
+                .has_header(false)
+                .delimiter(b'|');
+            ctx.register_csv(table, &path, options)?;
+        }
+    }
+
+    let mut results = vec![];
+    for query in &config.queries {
+        let sql = tpch::query(*query)?;
+        let mut result = QueryResult::new(*query, DATAFUSION_ENGINE);
+        for _ in 0..config.iterations {
+            let start = Instant::now();
+            let batches = ctx.sql(sql, config.batch_size).map_err(|e| e.into());
+            if !result.record(start, batches) {
+                break;
+            }
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Report of a benchmark run as JSON, with the settings of the run and the results of each
+/// query on each engine
+pub fn tpch_report(
+    config: &TpchConfig,
+    scale_factor: Option<f64>,
+    results: &[QueryResult],
+) -> Value {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    json!({
+        "benchmark": "tpch",
+        "version": BALLISTA_VERSION,
+        "timestamp": timestamp,
+        "path": config.path,
+        "format": config.format,
+        "scale_factor": scale_factor,
+        "iterations": config.iterations,
+        "queries": results.iter().map(|r| r.to_json()).collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_failed_and_successful_queries() {
+        let config = TpchConfig::new("/data/tpch/").with_iterations(2);
+        let mut ok = QueryResult::new(1, BALLISTA_ENGINE);
+        ok.durations_ms = vec![30, 10];
+        ok.rows = 4;
+        let mut failed = QueryResult::new(2, DATAFUSION_ENGINE);
+        failed.error = Some("unsupported".to_owned());
+
+        let report = tpch_report(&config, Some(1.0), &[ok, failed]);
+        assert_eq!("/data/tpch", report["path"]);
+        assert_eq!(2, report["iterations"]);
+        assert_eq!(10, report["queries"][0]["min_ms"]);
+        assert_eq!(20.0, report["queries"][0]["avg_ms"]);
+        assert_eq!(Value::Null, report["queries"][1]["min_ms"]);
+        assert_eq!("unsupported", report["queries"][1]["error"]);
+    }
+
+    #[test]
+    fn reject_unknown_format() {
+        assert!(TpchConfig::new("/data")
+            .with_format("orc")
+            .check_format()
+            .is_err());
+    }
+}
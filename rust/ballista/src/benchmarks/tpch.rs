@@ -0,0 +1,458 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tables and queries of the TPC-H benchmark.
+//!
+//! Dates are stored as `YYYY-MM-DD` strings and decimals as doubles, so the queries compare
+//! dates as strings, with the date arithmetic of the specification already applied to the
+//! substitution parameters of the validation run, and extract years from dates with `substr`.
+
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::error::{ballista_error, Result};
+
+/// Names of the TPC-H tables
+pub const TABLES: &[&str] = &[
+    "part", "supplier", "partsupp", "customer", "orders", "lineitem", "nation", "region",
+];
+
+/// Number of TPC-H queries
+pub const QUERY_COUNT: usize = 22;
+
+/// Schema of a TPC-H table
+pub fn schema(table: &str) -> Result<Schema> {
+    let fields = match table {
+        "part" => vec![
+            Field::new("p_partkey", DataType::Int64, false),
+            Field::new("p_name", DataType::Utf8, false),
+            Field::new("p_mfgr", DataType::Utf8, false),
+            Field::new("p_brand", DataType::Utf8, false),
+            Field::new("p_type", DataType::Utf8, false),
+            Field::new("p_size", DataType::Int32, false),
+            Field::new("p_container", DataType::Utf8, false),
+            Field::new("p_retailprice", DataType::Float64, false),
+            Field::new("p_comment", DataType::Utf8, false),
+        ],
+        "supplier" => vec![
+            Field::new("s_suppkey", DataType::Int64, false),
+            Field::new("s_name", DataType::Utf8, false),
+            Field::new("s_address", DataType::Utf8, false),
+            Field::new("s_nationkey", DataType::Int64, false),
+            Field::new("s_phone", DataType::Utf8, false),
+            Field::new("s_acctbal", DataType::Float64, false),
+            Field::new("s_comment", DataType::Utf8, false),
+        ],
+        "partsupp" => vec![
+            Field::new("ps_partkey", DataType::Int64, false),
+            Field::new("ps_suppkey", DataType::Int64, false),
+            Field::new("ps_availqty", DataType::Int32, false),
+            Field::new("ps_supplycost", DataType::Float64, false),
+            Field::new("ps_comment", DataType::Utf8, false),
+        ],
+        "customer" => vec![
+            Field::new("c_custkey", DataType::Int64, false),
+            Field::new("c_name", DataType::Utf8, false),
+            Field::new("c_address", DataType::Utf8, false),
+            Field::new("c_nationkey", DataType::Int64, false),
+            Field::new("c_phone", DataType::Utf8, false),
+            Field::new("c_acctbal", DataType::Float64, false),
+            Field::new("c_mktsegment", DataType::Utf8, false),
+            Field::new("c_comment", DataType::Utf8, false),
+        ],
+        "orders" => vec![
+            Field::new("o_orderkey", DataType::Int64, false),
+            Field::new("o_custkey", DataType::Int64, false),
+            Field::new("o_orderstatus", DataType::Utf8, false),
+            Field::new("o_totalprice", DataType::Float64, false),
+            Field::new("o_orderdate", DataType::Utf8, false),
+            Field::new("o_orderpriority", DataType::Utf8, false),
+            Field::new("o_clerk", DataType::Utf8, false),
+            Field::new("o_shippriority", DataType::Int32, false),
+            Field::new("o_comment", DataType::Utf8, false),
+        ],
+        "lineitem" => vec![
+            Field::new("l_orderkey", DataType::Int64, false),
+            Field::new("l_partkey", DataType::Int64, false),
+            Field::new("l_suppkey", DataType::Int64, false),
+            Field::new("l_linenumber", DataType::Int32, false),
+            Field::new("l_quantity", DataType::Float64, false),
+            Field::new("l_extendedprice", DataType::Float64, false),
+            Field::new("l_discount", DataType::Float64, false),
+            Field::new("l_tax", DataType::Float64, false),
+            Field::new("l_returnflag", DataType::Utf8, false),
+            Field::new("l_linestatus", DataType::Utf8, false),
+            Field::new("l_shipdate", DataType::Utf8, false),
+            Field::new("l_commitdate", DataType::Utf8, false),
+            Field::new("l_receiptdate", DataType::Utf8, false),
+            Field::new("l_shipinstruct", DataType::Utf8, false),
+            Field::new("l_shipmode", DataType::Utf8, false),
+            Field::new("l_comment", DataType::Utf8, false),
+        ],
+        "nation" => vec![
+            Field::new("n_nationkey", DataType::Int64, false),
+            Field::new("n_name", DataType::Utf8, false),
+            Field::new("n_regionkey", DataType::Int64, false),
+            Field::new("n_comment", DataType::Utf8, false),
+        ],
+        "region" => vec![
+            Field::new("r_regionkey", DataType::Int64, false),
+            Field::new("r_name", DataType::Utf8, false),
+            Field::new("r_comment", DataType::Utf8, false),
+        ],
+        _ => return Err(ballista_error(&format!("{} is not a TPC-H table", table))),
+    };
+    Ok(Schema::new(fields))
+}
+
+/// SQL of a TPC-H query, numbered from 1 to 22
+pub fn query(query: usize) -> Result<&'static str> {
+    let sql = match query {
+        1 => {
+            "select l_returnflag, l_linestatus, sum(l_quantity) as sum_qty, \
+             sum(l_extendedprice) as sum_base_price, \
+             sum(l_extendedprice * (1 - l_discount)) as sum_disc_price, \
+             sum(l_extendedprice * (1 - l_discount) * (1 + l_tax)) as sum_charge, \
+             avg(l_quantity) as avg_qty, avg(l_extendedprice) as avg_price, \
+             avg(l_discount) as avg_disc, count(*) as count_order \
+             from lineitem \
+             where l_shipdate <= '1998-09-02' \
+             group by l_returnflag, l_linestatus \
+             order by l_returnflag, l_linestatus"
+        }
+        2 => {
+            "select s_acctbal, s_name, n_name, p_partkey, p_mfgr, s_address, s_phone, s_comment \
+             from part, supplier, partsupp, nation, region \
+             where p_partkey = ps_partkey and s_suppkey = ps_suppkey and p_size = 15 \
+             and p_type like '%BRASS' and s_nationkey = n_nationkey \
+             and n_regionkey = r_regionkey and r_name = 'EUROPE' \
+             and ps_supplycost = ( \
+                 select min(ps_supplycost) from partsupp, supplier, nation, region \
+                 where p_partkey = ps_partkey and s_suppkey = ps_suppkey \
+                 and s_nationkey = n_nationkey and n_regionkey = r_regionkey \
+                 and r_name = 'EUROPE') \
+             order by s_acctbal desc, n_name, s_name, p_partkey \
+             limit 100"
+        }
+        3 => {
+            "select l_orderkey, sum(l_extendedprice * (1 - l_discount)) as revenue, \
+             o_orderdate, o_shippriority \
+             from customer, orders, lineitem \
+             where c_mktsegment = 'BUILDING' and c_custkey = o_custkey \
+             and l_orderkey = o_orderkey and o_orderdate < '1995-03-15' \
+             and l_shipdate > '1995-03-15' \
+             group by l_orderkey, o_orderdate, o_shippriority \
+             order by revenue desc, o_orderdate \
+             limit 10"
+        }
+        4 => {
+            "select o_orderpriority, count(*) as order_count \
+             from orders \
+             where o_orderdate >= '1993-07-01' and o_orderdate < '1993-10-01' \
+             and exists ( \
+                 select * from lineitem \
+                 where l_orderkey = o_orderkey and l_commitdate < l_receiptdate) \
+             group by o_orderpriority \
+             order by o_orderpriority"
+        }
+        5 => {
+            "select n_name, sum(l_extendedprice * (1 - l_discount)) as revenue \
+             from customer, orders, lineitem, supplier, nation, region \
+             where c_custkey = o_custkey and l_orderkey = o_orderkey \
+             and l_suppkey = s_suppkey and c_nationkey = s_nationkey \
+             and s_nationkey = n_nationkey and n_regionkey = r_regionkey \
+             and r_name = 'ASIA' and o_orderdate >= '1994-01-01' \
+             and o_orderdate < '1995-01-01' \
+             group by n_name \
+             order by revenue desc"
+        }
+        6 => {
+            "select sum(l_extendedprice * l_discount) as revenue \
+             from lineitem \
+             where l_shipdate >= '1994-01-01' and l_shipdate < '1995-01-01' \
+             and l_discount >= 0.05 and l_discount <= 0.07 and l_quantity < 24"
+        }
+        7 => {
+            "select supp_nation, cust_nation, l_year, sum(volume) as revenue \
+             from ( \
+                 select n1.n_name as supp_nation, n2.n_name as cust_nation, \
+                 substr(l_shipdate, 1, 4) as l_year, \
+                 l_extendedprice * (1 - l_discount) as volume \
+                 from supplier, lineitem, orders, customer, nation n1, nation n2 \
+                 where s_suppkey = l_suppkey and o_orderkey = l_orderkey \
+                 and c_custkey = o_custkey and s_nationkey = n1.n_nationkey \
+                 and c_nationkey = n2.n_nationkey \
+                 and ((n1.n_name = 'FRANCE' and n2.n_name = 'GERMANY') \
+                     or (n1.n_name = 'GERMANY' and n2.n_name = 'FRANCE')) \
+                 and l_shipdate >= '1995-01-01' and l_shipdate <= '1996-12-31' \
+             ) as shipping \
+             group by supp_nation, cust_nation, l_year \
+             order by supp_nation, cust_nation, l_year"
+        }
+        8 => {
+            "select o_year, \
+             sum(case when nation = 'BRAZIL' then volume else 0 end) / sum(volume) as mkt_share \
+             from ( \
+                 select substr(o_orderdate, 1, 4) as o_year, \
+                 l_extendedprice * (1 - l_discount) as volume, n2.n_name as nation \
+                 from part, supplier, lineitem, orders, customer, nation n1, nation n2, region \
+                 where p_partkey = l_partkey and s_suppkey = l_suppkey \
+                 and l_orderkey = o_orderkey and o_custkey = c_custkey \
+                 and c_nationkey = n1.n_nationkey and n1.n_regionkey = r_regionkey \
+                 and r_name = 'AMERICA' and s_nationkey = n2.n_nationkey \
+                 and o_orderdate >= '1995-01-01' and o_orderdate <= '1996-12-31' \
+                 and p_type = 'ECONOMY ANODIZED STEEL' \
+             ) as all_nations \
+             group by o_year \
+             order by o_year"
+        }
+        9 => {
+            "select nation, o_year, sum(amount) as sum_profit \
+             from ( \
+                 select n_name as nation, substr(o_orderdate, 1, 4) as o_year, \
+                 l_extendedprice * (1 - l_discount) - ps_supplycost * l_quantity as amount \
+                 from part, supplier, lineitem, partsupp, orders, nation \
+                 where s_suppkey = l_suppkey and ps_suppkey = l_suppkey \
+                 and ps_partkey = l_partkey and p_partkey = l_partkey \
+                 and o_orderkey = l_orderkey and s_nationkey = n_nationkey \
+                 and p_name like '%green%' \
+             ) as profit \
+             group by nation, o_year \
+             order by nation, o_year desc"
+        }
+        10 => {
+            "select c_custkey, c_name, sum(l_extendedprice * (1 - l_discount)) as revenue, \
+             c_acctbal, n_name, c_address, c_phone, c_comment \
+             from customer, orders, lineitem, nation \
+             where c_custkey = o_custkey and l_orderkey = o_orderkey \
+             and o_orderdate >= '1993-10-01' and o_orderdate < '1994-01-01' \
+             and l_returnflag = 'R' and c_nationkey = n_nationkey \
+             group by c_custkey, c_name, c_acctbal, c_phone, n_name, c_address, c_comment \
+             order by revenue desc \
+             limit 20"
+        }
+        11 => {
+            "select ps_partkey, sum(ps_supplycost * ps_availqty) as value \
+             from partsupp, supplier, nation \
+             where ps_suppkey = s_suppkey and s_nationkey = n_nationkey \
+             and n_name = 'GERMANY' \
+             group by ps_partkey \
+             having sum(ps_supplycost * ps_availqty) > ( \
+                 select sum(ps_supplycost * ps_availqty) * 0.0001 \
+                 from partsupp, supplier, nation \
+                 where ps_suppkey = s_suppkey and s_nationkey = n_nationkey \
+                 and n_name = 'GERMANY') \
+             order by value desc"
+        }
+        12 => {
+            "select l_shipmode, \
+             sum(case when o_orderpriority = '1-URGENT' or o_orderpriority = '2-HIGH' \
+                 then 1 else 0 end) as high_line_count, \
+             sum(case when o_orderpriority <> '1-URGENT' and o_orderpriority <> '2-HIGH' \
+                 then 1 else 0 end) as low_line_count \
+             from orders, lineitem \
+             where o_orderkey = l_orderkey and l_shipmode in ('MAIL', 'SHIP') \
+             and l_commitdate < l_receiptdate and l_shipdate < l_commitdate \
+             and l_receiptdate >= '1994-01-01' and l_receiptdate < '1995-01-01' \
+             group by l_shipmode \
+             order by l_shipmode"
+        }
+        13 => {
+            "select c_count, count(*) as custdist \
+             from ( \
+                 select c_custkey, count(o_orderkey) as c_count \
+                 from customer left outer join orders \
+                 on c_custkey = o_custkey and o_comment not like '%special%requests%' \
+                 group by c_custkey \
+             ) as c_orders \
+             group by c_count \
+             order by custdist desc, c_count desc"
+        }
+        14 => {
+            "select 100.00 * sum(case when p_type like 'PROMO%' \
+                 then l_extendedprice * (1 - l_discount) else 0 end) \
+             / sum(l_extendedprice * (1 - l_discount)) as promo_revenue \
+             from lineitem, part \
+             where l_partkey = p_partkey and l_shipdate >= '1995-09-01' \
+             and l_shipdate < '1995-10-01'"
+        }
+        15 => {
+            "select s_suppkey, s_name, s_address, s_phone, total_revenue \
+             from supplier, ( \
+                 select l_suppkey as supplier_no, \
+                 sum(l_extendedprice * (1 - l_discount)) as total_revenue \
+                 from lineitem \
+                 where l_shipdate >= '1996-01-01' and l_shipdate < '1996-04-01' \
+                 group by l_suppkey \
+             ) as revenue0 \
+             where s_suppkey = supplier_no and total_revenue = ( \
+                 select max(total_revenue) from ( \
+                     select l_suppkey as supplier_no, \
+                     sum(l_extendedprice * (1 - l_discount)) as total_revenue \
+                     from lineitem \
+                     where l_shipdate >= '1996-01-01' and l_shipdate < '1996-04-01' \
+                     group by l_suppkey \
+                 ) as revenue1) \
+             order by s_suppkey"
+        }
+        16 => {
+            "select p_brand, p_type, p_size, count(distinct ps_suppkey) as supplier_cnt \
+             from partsupp, part \
+             where p_partkey = ps_partkey and p_brand <> 'Brand#45' \
+             and p_type not like 'MEDIUM POLISHED%' \
+             and p_size in (49, 14, 23, 45, 19, 3, 36, 9) \
+             and ps_suppkey not in ( \
+                 select s_suppkey from supplier \
+                 where s_comment like '%Customer%Complaints%') \
+             group by p_brand, p_type, p_size \
+             order by supplier_cnt desc, p_brand, p_type, p_size"
+        }
+        17 => {
+            "select sum(l_extendedprice) / 7.0 as avg_yearly \
+             from lineitem, part \
+             where p_partkey = l_partkey and p_brand = 'Brand#23' \
+             and p_container = 'MED BOX' \
+             and l_quantity < ( \
+                 select 0.2 * avg(l_quantity) from lineitem where l_partkey = p_partkey)"
+        }
+        18 => {
+            "select c_name, c_custkey, o_orderkey, o_orderdate, o_totalprice, sum(l_quantity) \
+             from customer, orders, lineitem \
+             where o_orderkey in ( \
+                 select l_orderkey from lineitem \
+                 group by l_orderkey having sum(l_quantity) > 300) \
+             and c_custkey = o_custkey and o_orderkey = l_orderkey \
+             group by c_name, c_custkey, o_orderkey, o_orderdate, o_totalprice \
+             order by o_totalprice desc, o_orderdate \
+             limit 100"
+        }
+        19 => {
+            "select sum(l_extendedprice * (1 - l_discount)) as revenue \
+             from lineitem, part \
+             where (p_partkey = l_partkey and p_brand = 'Brand#12' \
+                 and p_container in ('SM CASE', 'SM BOX', 'SM PACK', 'SM PKG') \
+                 and l_quantity >= 1 and l_quantity <= 11 and p_size between 1 and 5 \
+                 and l_shipmode in ('AIR', 'AIR REG') \
+                 and l_shipinstruct = 'DELIVER IN PERSON') \
+             or (p_partkey = l_partkey and p_brand = 'Brand#23' \
+                 and p_container in ('MED BAG', 'MED BOX', 'MED PKG', 'MED PACK') \
+                 and l_quantity >= 10 and l_quantity <= 20 and p_size between 1 and 10 \
+                 and l_shipmode in ('AIR', 'AIR REG') \
+                 and l_shipinstruct = 'DELIVER IN PERSON') \
+             or (p_partkey = l_partkey and p_brand = 'Brand#34' \
+                 and p_container in ('LG CASE', 'LG BOX', 'LG PACK', 'LG PKG') \
+                 and l_quantity >= 20 and l_quantity <= 30 and p_size between 1 and 15 \
+                 and l_shipmode in ('AIR', 'AIR REG') \
+                 and l_shipinstruct = 'DELIVER IN PERSON')"
+        }
+        20 => {
+            "select s_name, s_address \
+             from supplier, nation \
+             where s_suppkey in ( \
+                 select ps_suppkey from partsupp \
+                 where ps_partkey in (select p_partkey from part where p_name like 'forest%') \
+                 and ps_availqty > ( \
+                     select 0.5 * sum(l_quantity) from lineitem \
+                     where l_partkey = ps_partkey and l_suppkey = ps_suppkey \
+                     and l_shipdate >= '1994-01-01' and l_shipdate < '1995-01-01')) \
+             and s_nationkey = n_nationkey and n_name = 'CANADA' \
+             order by s_name"
+        }
+        21 => {
+            "select s_name, count(*) as numwait \
+             from supplier, lineitem l1, orders, nation \
+             where s_suppkey = l1.l_suppkey and o_orderkey = l1.l_orderkey \
+             and o_orderstatus = 'F' and l1.l_receiptdate > l1.l_commitdate \
+             and exists ( \
+                 select * from lineitem l2 \
+                 where l2.l_orderkey = l1.l_orderkey and l2.l_suppkey <> l1.l_suppkey) \
+             and not exists ( \
+                 select * from lineitem l3 \
+                 where l3.l_orderkey = l1.l_orderkey and l3.l_suppkey <> l1.l_suppkey \
+                 and l3.l_receiptdate > l3.l_commitdate) \
+             and s_nationkey = n_nationkey and n_name = 'SAUDI ARABIA' \
+             group by s_name \
+             order by numwait desc, s_name \
+             limit 100"
+        }
+        22 => {
+            "select cntrycode, count(*) as numcust, sum(c_acctbal) as totacctbal \
+             from ( \
+                 select substr(c_phone, 1, 2) as cntrycode, c_acctbal \
+                 from customer \
+                 where substr(c_phone, 1, 2) in ('13', '31', '23', '29', '30', '18', '17') \
+                 and c_acctbal > ( \
+                     select avg(c_acctbal) from customer \
+                     where c_acctbal > 0.00 \
+                     and substr(c_phone, 1, 2) in ('13', '31', '23', '29', '30', '18', '17')) \
+                 and not exists (select * from orders where o_custkey = c_custkey) \
+             ) as custsale \
+             group by cntrycode \
+             order by cntrycode"
+        }
+        _ => {
+            return Err(ballista_error(&format!(
+                "TPC-H has no query {}, queries are numbered from 1 to {}",
+                query, QUERY_COUNT
+            )))
+        }
+    };
+    Ok(sql)
+}
+
+/// Parse a list of query numbers such as `1,3,5-7`, where an empty list selects all queries
+pub fn parse_queries(list: &str) -> Result<Vec<usize>> {
+    if list.trim().is_empty() {
+        return Ok((1..=QUERY_COUNT).collect());
+    }
+    let invalid = || ballista_error(&format!("Invalid list of TPC-H queries '{}'", list));
+    let mut queries = vec![];
+    for part in list.split(',').map(str::trim) {
+        let range: Vec<&str> = part.splitn(2, '-').collect();
+        let first: usize = range[0].trim().parse().map_err(|_| invalid())?;
+        let last: usize = match range.get(1) {
+            Some(last) => last.trim().parse().map_err(|_| invalid())?,
+            None => first,
+        };
+        if first == 0 || last > QUERY_COUNT || first > last {
+            return Err(invalid());
+        }
+        queries.extend(first..=last);
+    }
+    Ok(queries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_query_is_defined() -> Result<()> {
+        for q in 1..=QUERY_COUNT {
+            assert!(query(q)?.starts_with("select"));
+        }
+        assert!(query(23).is_err());
+        for table in TABLES {
+            schema(table)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_query_lists() -> Result<()> {
+        assert_eq!(vec![1, 3, 5, 6, 7], parse_queries("1,3,5-7")?);
+        assert_eq!(QUERY_COUNT, parse_queries("")?.len());
+        assert!(parse_queries("0").is_err());
+        assert!(parse_queries("21-23").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,647 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generator of TPC-H data.
+//!
+//! The tables have the row counts, keys, value ranges, and correlations between columns that
+//! the specification describes, such as line items shipping after their order is placed and
+//! return flags that depend on the receipt date, so that the queries select similar fractions
+//! of the data as they do on data from `dbgen`. Comments and names are drawn from smaller
+//! vocabularies, so the data is not identical to that of `dbgen`, but it is the same for a
+//! given scale factor and number of partitions on every run.
+//!
+//! Each table is written to a directory of its name, as pipe-delimited files without a header
+//! named `part-{n}.csv`, the same layout that the benchmark runner reads.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::benchmarks::tpch::TABLES;
+use crate::error::Result;
+
+use log::info;
+use random_fast_rng::{FastRng, Random};
+
+const REGIONS: &[&str] = &["AFRICA", "AMERICA", "ASIA", "EUROPE", "MIDDLE EAST"];
+
+/// Nations with the keys of their regions, in the order of their keys
+const NATIONS: &[(&str, i64)] = &[
+    ("ALGERIA", 0),
+    ("ARGENTINA", 1),
+    ("BRAZIL", 1),
+    ("CANADA", 1),
+    ("EGYPT", 4),
+    ("ETHIOPIA", 0),
+    ("FRANCE", 3),
+    ("GERMANY", 3),
+    ("INDIA", 2),
+    ("INDONESIA", 2),
+    ("IRAN", 4),
+    ("IRAQ", 4),
+    ("JAPAN", 2),
+    ("JORDAN", 4),
+    ("KENYA", 0),
+    ("MOROCCO", 0),
+    ("MOZAMBIQUE", 0),
+    ("PERU", 1),
+    ("CHINA", 2),
+    ("ROMANIA", 3),
+    ("SAUDI ARABIA", 4),
+    ("VIETNAM", 2),
+    ("RUSSIA", 3),
+    ("UNITED KINGDOM", 3),
+    ("UNITED STATES", 1),
+];
+
+const COLORS: &[&str] = &[
+    "almond",
+    "antique",
+    "aquamarine",
+    "azure",
+    "beige",
+    "bisque",
+    "black",
+    "blanched",
+    "blue",
+    "blush",
+    "brown",
+    "burlywood",
+    "chartreuse",
+    "chocolate",
+    "coral",
+    "cornflower",
+    "cream",
+    "cyan",
+    "dark",
+    "deep",
+    "dim",
+    "dodger",
+    "drab",
+    "firebrick",
+    "floral",
+    "forest",
+    "frosted",
+    "gainsboro",
+    "ghost",
+    "goldenrod",
+    "green",
+    "grey",
+    "honeydew",
+    "hot",
+    "indian",
+    "ivory",
+    "khaki",
+    "lace",
+    "lavender",
+    "lawn",
+    "lemon",
+    "light",
+    "lime",
+    "linen",
+    "magenta",
+    "maroon",
+    "medium",
+    "metallic",
+    "midnight",
+    "mint",
+    "misty",
+    "moccasin",
+    "navajo",
+    "navy",
+    "olive",
+    "orange",
+    "orchid",
+    "pale",
+    "papaya",
+    "peach",
+    "peru",
+    "pink",
+    "plum",
+    "powder",
+    "puff",
+    "purple",
+    "red",
+    "rose",
+    "rosy",
+    "royal",
+    "saddle",
+    "salmon",
+    "sandy",
+    "seashell",
+    "sienna",
+    "sky",
+    "slate",
+    "smoke",
+    "snow",
+    "spring",
+    "steel",
+    "tan",
+    "thistle",
+    "tomato",
+    "turquoise",
+    "violet",
+    "wheat",
+    "white",
+    "yellow",
+];
+
+const TYPE_SIZES: &[&str] = &["STANDARD", "SMALL", "MEDIUM", "LARGE", "ECONOMY", "PROMO"];
+const TYPE_FINISHES: &[&str] = &["ANODIZED", "BURNISHED", "PLATED", "POLISHED", "BRUSHED"];
+const TYPE_MATERIALS: &[&str] = &["TIN", "NICKEL", "BRASS", "STEEL", "COPPER"];
+const CONTAINER_SIZES: &[&str] = &["SM", "LG", "MED", "JUMBO", "WRAP"];
+const CONTAINER_TYPES: &[&str] = &["CASE", "BOX", "BAG", "JAR", "PKG", "PACK", "CAN", "DRUM"];
+const SEGMENTS: &[&str] = &[
+    "AUTOMOBILE",
+    "BUILDING",
+    "FURNITURE",
+    "MACHINERY",
+    "HOUSEHOLD",
+];
+const PRIORITIES: &[&str] = &["1-URGENT", "2-HIGH", "3-MEDIUM", "4-NOT SPECIFIED", "5-LOW"];
+const INSTRUCTIONS: &[&str] = &[
+    "DELIVER IN PERSON",
+    "COLLECT COD",
+    "NONE",
+    "TAKE BACK RETURN",
+];
+const SHIP_MODES: &[&str] = &["REG AIR", "AIR", "RAIL", "SHIP", "TRUCK", "MAIL", "FOB"];
+
+const WORDS: &[&str] = &[
+    "furiously",
+    "quickly",
+    "carefully",
+    "blithely",
+    "slyly",
+    "regular",
+    "final",
+    "express",
+    "pending",
+    "ironic",
+    "bold",
+    "even",
+    "unusual",
+    "packages",
+    "deposits",
+    "accounts",
+    "instructions",
+    "theodolites",
+    "foxes",
+    "pinto",
+    "beans",
+    "dependencies",
+    "platelets",
+    "sleep",
+    "haggle",
+    "nag",
+    "wake",
+    "cajole",
+    "use",
+    "detect",
+    "boost",
+    "among",
+    "about",
+    "above",
+];
+
+/// Days from 1970-01-01 to 1992-01-01, the first order date
+const START_DATE: i64 = 8035;
+/// Days from 1970-01-01 to 1998-12-31, the last order date plus the time to ship and receive
+const END_DATE: i64 = 10591;
+/// Days from 1970-01-01 to 1995-06-17, the date that line items are open or returned by
+const CURRENT_DATE: i64 = 9298;
+
+/// Generator of the TPC-H tables at a scale factor
+#[derive(Debug, Clone)]
+pub struct TpchGenerator {
+    scale_factor: f64,
+    partitions: usize,
+}
+
+impl TpchGenerator {
+    pub fn new(scale_factor: f64) -> Self {
+        Self {
+            scale_factor,
+            partitions: 1,
+        }
+    }
+
+    /// Split each table, other than the nation and region tables, into `partitions` files
+    pub fn with_partitions(mut self, partitions: usize) -> Self {
+        self.partitions = partitions.max(1);
+        self
+    }
+
+    fn scaled(&self, rows: usize) -> i64 {
+        ((rows as f64 * self.scale_factor) as i64).max(1)
+    }
+
+    fn suppliers(&self) -> i64 {
+        self.scaled(10_000)
+    }
+
+    fn parts(&self) -> i64 {
+        self.scaled(200_000)
+    }
+
+    fn customers(&self) -> i64 {
+        self.scaled(150_000)
+    }
+
+    fn orders(&self) -> i64 {
+        self.scaled(1_500_000)
+    }
+
+    /// Write every table to a directory of its name under `dir`, returning the number of rows
+    /// written to each table
+    pub fn generate(&self, dir: &Path) -> Result<Vec<(String, usize)>> {
+        let mut counts = vec![];
+        for table in TABLES {
+            let rows = match *table {
+                // line items are generated with their orders
+                "lineitem" => continue,
+                "orders" => {
+                    let (orders, line_items) = self.generate_orders(dir)?;
+                    counts.push(("lineitem".to_owned(), line_items));
+                    orders
+                }
+                _ => self.generate_table(dir, table)?,
+            };
+            counts.push((table.to_string(), rows));
+        }
+        for (table, rows) in &counts {
+            info!("Generated TPC-H table table={} rows={}", table, rows);
+        }
+        Ok(counts)
+    }
+
+    fn generate_table(&self, dir: &Path, table: &str) -> Result<usize> {
+        let (total, partitions) = match table {
+            "region" => (REGIONS.len() as i64, 1),
+            "nation" => (NATIONS.len() as i64, 1),
+            "supplier" => (self.suppliers(), self.partitions),
+            "customer" => (self.customers(), self.partitions),
+            "part" | "partsupp" => (self.parts(), self.partitions),
+            _ => unreachable!(),
+        };
+        let mut rows = 0;
+        for partition in 0..partitions {
+            let mut out = table_writer(dir, table, partition)?;
+            let mut rng = rng(table, partition);
+            let (first, last) = key_range(total, partitions, partition);
+            for key in first..=last {
+                rows += match table {
+                    "region" => write_region(&mut out, &mut rng, key - 1)?,
+                    "nation" => write_nation(&mut out, &mut rng, key - 1)?,
+                    "supplier" => write_supplier(&mut out, &mut rng, key)?,
+                    "customer" => write_customer(&mut out, &mut rng, key)?,
+                    "part" => write_part(&mut out, &mut rng, key)?,
+                    _ => self.write_partsupps(&mut out, &mut rng, key)?,
+                };
+            }
+            out.flush()?;
+        }
+        Ok(rows)
+    }
+
+    fn write_partsupps(&self, out: &mut impl Write, rng: &mut FastRng, part: i64) -> Result<usize> {
+        for i in 0..4 {
+            writeln!(
+                out,
+                "{}|{}|{}|{:.2}|{}",
+                part,
+                self.partsupp_supplier(part, i),
+                range(rng, 1, 9999),
+                range(rng, 100, 100_000) as f64 / 100.0,
+                text(rng, 10)
+            )?;
+        }
+        Ok(4)
+    }
+
+    /// Key of the `i`th of the four suppliers of a part
+    fn partsupp_supplier(&self, part: i64, i: i64) -> i64 {
+        let suppliers = self.suppliers();
+        (part + i * (suppliers / 4 + (part - 1) / suppliers)) % suppliers + 1
+    }
+
+    /// Write the orders and their line items, returning the number of each
+    fn generate_orders(&self, dir: &Path) -> Result<(usize, usize)> {
+        let (mut orders, mut line_items) = (0, 0);
+        let customers = self.customers();
+        let clerks = self.scaled(1000);
+        for partition in 0..self.partitions {
+            let mut orders_out = table_writer(dir, "orders", partition)?;
+            let mut line_items_out = table_writer(dir, "lineitem", partition)?;
+            let mut rng = rng("orders", partition);
+            let (first, last) = key_range(self.orders(), self.partitions, partition);
+            for order in first..=last {
+                // a third of the customers have no orders
+                let mut customer = range(&mut rng, 1, customers);
+                if customer % 3 == 0 && customers > 2 {
+                    customer -= 1;
+                }
+                let order_date = range(&mut rng, START_DATE, END_DATE - 151);
+                let mut total_price = 0.0;
+                let (mut open, mut filled) = (0, 0);
+                let lines = range(&mut rng, 1, 7);
+                for line in 1..=lines {
+                    let part = range(&mut rng, 1, self.parts());
+                    let supplier = self.partsupp_supplier(part, range(&mut rng, 0, 3));
+                    let quantity = range(&mut rng, 1, 50);
+                    let price = quantity as f64 * retail_price(part);
+                    let discount = range(&mut rng, 0, 10) as f64 / 100.0;
+                    let tax = range(&mut rng, 0, 8) as f64 / 100.0;
+                    let ship_date = order_date + range(&mut rng, 1, 121);
+                    let commit_date = order_date + range(&mut rng, 30, 90);
+                    let receipt_date = ship_date + range(&mut rng, 1, 30);
+                    let return_flag = if receipt_date > CURRENT_DATE {
+                        "N"
+                    } else if rng.get_u8() % 2 == 0 {
+                        "R"
+                    } else {
+                        "A"
+                    };
+                    let line_status = if ship_date > CURRENT_DATE {
+                        open += 1;
+                        "O"
+                    } else {
+                        filled += 1;
+                        "F"
+                    };
+                    total_price += price * (1.0 + tax) * (1.0 - discount);
+                    writeln!(
+                        line_items_out,
+                        "{}|{}|{}|{}|{}|{:.2}|{:.2}|{:.2}|{}|{}|{}|{}|{}|{}|{}|{}",
+                        order,
+                        part,
+                        supplier,
+                        line,
+                        quantity,
+                        price,
+                        discount,
+                        tax,
+                        return_flag,
+                        line_status,
+                        date(ship_date),
+                        date(commit_date),
+                        date(receipt_date),
+                        pick(&mut rng, INSTRUCTIONS),
+                        pick(&mut rng, SHIP_MODES),
+                        text(&mut rng, 5)
+                    )?;
+                }
+                let status = match (open, filled) {
+                    (_, 0) => "O",
+                    (0, _) => "F",
+                    _ => "P",
+                };
+                let comment = if rng.get_u8() % 100 == 0 {
+                    format!(
+                        "{} special {} requests",
+                        text(&mut rng, 2),
+                        text(&mut rng, 1)
+                    )
+                } else {
+                    text(&mut rng, 8)
+                };
+                writeln!(
+                    orders_out,
+                    "{}|{}|{}|{:.2}|{}|{}|Clerk#{:09}|0|{}",
+                    order,
+                    customer,
+                    status,
+                    total_price,
+                    date(order_date),
+                    pick(&mut rng, PRIORITIES),
+                    range(&mut rng, 1, clerks),
+                    comment
+                )?;
+                orders += 1;
+                line_items += lines as usize;
+            }
+            orders_out.flush()?;
+            line_items_out.flush()?;
+        }
+        Ok((orders, line_items))
+    }
+}
+
+fn write_region(out: &mut impl Write, rng: &mut FastRng, key: i64) -> Result<usize> {
+    writeln!(out, "{}|{}|{}", key, REGIONS[key as usize], text(rng, 8))?;
+    Ok(1)
+}
+
+fn write_nation(out: &mut impl Write, rng: &mut FastRng, key: i64) -> Result<usize> {
+    let (name, region) = NATIONS[key as usize];
+    writeln!(out, "{}|{}|{}|{}", key, name, region, text(rng, 8))?;
+    Ok(1)
+}
+
+fn write_supplier(out: &mut impl Write, rng: &mut FastRng, key: i64) -> Result<usize> {
+    let nation = range(rng, 0, 24);
+    // a few suppliers have had complaints from customers
+    let comment = if key % 2000 == 7 {
+        format!("{} Customer {} Complaints", text(rng, 2), text(rng, 1))
+    } else {
+        text(rng, 8)
+    };
+    writeln!(
+        out,
+        "{}|Supplier#{:09}|{}|{}|{}|{:.2}|{}",
+        key,
+        key,
+        address(rng),
+        nation,
+        phone(rng, nation),
+        range(rng, -99_999, 999_999) as f64 / 100.0,
+        comment
+    )?;
+    Ok(1)
+}
+
+fn write_customer(out: &mut impl Write, rng: &mut FastRng, key: i64) -> Result<usize> {
+    let nation = range(rng, 0, 24);
+    writeln!(
+        out,
+        "{}|Customer#{:09}|{}|{}|{}|{:.2}|{}|{}",
+        key,
+        key,
+        address(rng),
+        nation,
+        phone(rng, nation),
+        range(rng, -99_999, 999_999) as f64 / 100.0,
+        pick(rng, SEGMENTS),
+        text(rng, 8)
+    )?;
+    Ok(1)
+}
+
+fn write_part(out: &mut impl Write, rng: &mut FastRng, key: i64) -> Result<usize> {
+    let name: Vec<&str> = (0..5).map(|_| pick(rng, COLORS)).collect();
+    let manufacturer = range(rng, 1, 5);
+    writeln!(
+        out,
+        "{}|{}|Manufacturer#{}|Brand#{}{}|{} {} {}|{}|{} {}|{:.2}|{}",
+        key,
+        name.join(" "),
+        manufacturer,
+        manufacturer,
+        range(rng, 1, 5),
+        pick(rng, TYPE_SIZES),
+        pick(rng, TYPE_FINISHES),
+        pick(rng, TYPE_MATERIALS),
+        range(rng, 1, 50),
+        pick(rng, CONTAINER_SIZES),
+        pick(rng, CONTAINER_TYPES),
+        retail_price(key),
+        text(rng, 4)
+    )?;
+    Ok(1)
+}
+
+/// Retail price of a part, which is derived from its key
+fn retail_price(part: i64) -> f64 {
+    (90_000 + (part / 10) % 20_001 + 100 * (part % 1000)) as f64 / 100.0
+}
+
+/// Open the file of a partition of a table
+fn table_writer(dir: &Path, table: &str, partition: usize) -> Result<BufWriter<File>> {
+    let dir = dir.join(table);
+    fs::create_dir_all(&dir)?;
+    let file = File::create(dir.join(format!("part-{}.csv", partition)))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Random number generator of a partition of a table, which is seeded so that the data is the
+/// same on every run
+fn rng(table: &str, partition: usize) -> FastRng {
+    let table = TABLES.iter().position(|t| *t == table).unwrap_or(0);
+    FastRng::seed(table as u64 + 1, partition as u64 + 1)
+}
+
+/// First and last key of a partition of a table, where keys start from one
+fn key_range(total: i64, partitions: usize, partition: usize) -> (i64, i64) {
+    let partitions = partitions as i64;
+    let partition = partition as i64;
+    let first = total * partition / partitions + 1;
+    let last = total * (partition + 1) / partitions;
+    (first, last)
+}
+
+/// A random number between `low` and `high`, inclusive
+fn range(rng: &mut FastRng, low: i64, high: i64) -> i64 {
+    low + (rng.get_u64() % (high - low + 1) as u64) as i64
+}
+
+fn pick<'a>(rng: &mut FastRng, values: &[&'a str]) -> &'a str {
+    values[rng.get_u64() as usize % values.len()]
+}
+
+/// Random words, without the delimiter of the files
+fn text(rng: &mut FastRng, words: usize) -> String {
+    (0..words)
+        .map(|_| pick(rng, WORDS))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn address(rng: &mut FastRng) -> String {
+    let len = range(rng, 10, 40) as usize;
+    (0..len)
+        .map(|_| (b'a' + (rng.get_u8() % 26)) as char)
+        .collect()
+}
+
+/// Phone number, whose country code is derived from the nation
+fn phone(rng: &mut FastRng, nation: i64) -> String {
+    format!(
+        "{}-{}-{}-{}",
+        nation + 10,
+        range(rng, 100, 999),
+        range(rng, 100, 999),
+        range(rng, 1000, 9999)
+    )
+}
+
+/// Format days since 1970-01-01 as a `YYYY-MM-DD` date
+fn date(days: i64) -> String {
+    // civil from days, from Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_dates() {
+        assert_eq!("1970-01-01", date(0));
+        assert_eq!("1992-01-01", date(START_DATE));
+        assert_eq!("1995-06-17", date(CURRENT_DATE));
+        assert_eq!("1998-12-31", date(END_DATE));
+    }
+
+    #[test]
+    fn partitions_cover_all_keys() {
+        assert_eq!((1, 3), key_range(10, 3, 0));
+        assert_eq!((4, 6), key_range(10, 3, 1));
+        assert_eq!((7, 10), key_range(10, 3, 2));
+    }
+
+    #[test]
+    fn generate_tables() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ballista-tpch-{}", std::process::id()));
+        let counts = TpchGenerator::new(0.001)
+            .with_partitions(2)
+            .generate(&dir)?;
+        let count = |table: &str| {
+            counts
+                .iter()
+                .find(|(t, _)| t == table)
+                .map(|(_, rows)| *rows)
+                .unwrap_or(0)
+        };
+        assert_eq!(5, count("region"));
+        assert_eq!(25, count("nation"));
+        assert_eq!(10, count("supplier"));
+        assert_eq!(800, count("partsupp"));
+        assert_eq!(1500, count("orders"));
+        assert!(count("lineitem") >= 1500);
+        for table in TABLES {
+            assert!(dir.join(table).join("part-0.csv").exists());
+        }
+
+        // the data is the same on every run
+        let lineitem = fs::read_to_string(dir.join("lineitem").join("part-1.csv"))?;
+        TpchGenerator::new(0.001)
+            .with_partitions(2)
+            .generate(&dir)?;
+        assert_eq!(
+            lineitem,
+            fs::read_to_string(dir.join("lineitem").join("part-1.csv"))?
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
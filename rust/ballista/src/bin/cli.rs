@@ -0,0 +1,368 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ballista SQL shell, which runs SQL statements against an executor and prints the results as
+//! tables. Statements are read interactively, from script files, or from the command line.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+use ballista::arrow::util::pretty;
+use ballista::config::{
+    BallistaConfig, AUTH_TOKEN, CLIENT_HOST, CLIENT_PORT, TLS_CA_CERT, TLS_DOMAIN_NAME,
+};
+use ballista::dataframe::{Context, CsvReadOptions};
+use ballista::distributed::explain::{DISTRIBUTED_PLAN, JOB_CONFIG, LOGICAL_PLAN, PHYSICAL_PLAN};
+use ballista::distributed::scheduler::QuerySettings;
+use ballista::error::{ballista_error, Result};
+use ballista::execution::operators::JsonReadOptions;
+use ballista::BALLISTA_VERSION;
+
+use futures::future::LocalBoxFuture;
+use structopt::StructOpt;
+
+const HELP: &str = "\
+Statements end with `;` and may span several lines. Commands:
+  \\q                               quit
+  \\?                               show this help
+  \\timing                          toggle printing how long each statement took
+  \\set [<name> <value>]            set a setting of the following queries, where name is one of
+                                   `batch_size`, `target_partitions`, `memory_limit`,
+                                   `timeout_ms`, or `task_timeout_ms`, or show the settings
+                                   when none is given
+  \\explain [analyze] <sql>         show the logical, physical, and distributed plans of a query,
+                                   running it first when `analyze` is given
+  \\i <file>                        run the statements in a script file
+  \\register <format> <name> <path> register a table, where format is one of `csv`, `parquet`,
+                                   `json`, `avro`, or `arrow`
+  \\d                               list the registered tables";
+
+/// SQL shell for running queries on a Ballista cluster
+#[derive(StructOpt, Debug)]
+#[structopt(name = "ballista-cli")]
+struct Opt {
+    /// TOML or YAML file to read settings from, which environment variables and flags override
+    #[structopt(long)]
+    config: Option<String>,
+
+    /// host of the executor that queries are sent to
+    #[structopt(short, long)]
+    host: Option<String>,
+
+    /// port of the executor that queries are sent to
+    #[structopt(short, long)]
+    port: Option<usize>,
+
+    /// shared token to present to the executor
+    #[structopt(long)]
+    auth_token: Option<String>,
+
+    /// CA certificate to verify the executor with, which enables TLS
+    #[structopt(long)]
+    tls_ca_cert: Option<String>,
+
+    /// domain name to verify the certificate of the executor against
+    #[structopt(long)]
+    tls_domain_name: Option<String>,
+
+    /// script files to run before reading statements interactively
+    #[structopt(short, long)]
+    file: Vec<String>,
+
+    /// statements to run, after any script files, rather than reading statements interactively
+    #[structopt(short, long)]
+    command: Option<String>,
+
+    /// print how long each statement took
+    #[structopt(long)]
+    timing: bool,
+}
+
+struct Shell {
+    ctx: Context,
+    /// Registered tables, with the format and path of each
+    tables: BTreeMap<String, (String, String)>,
+    /// Settings sent with each query, overriding the configuration of the executor
+    settings: QuerySettings,
+    timing: bool,
+}
+
+impl Shell {
+    /// Run a statement or command, returning false when the shell should exit
+    async fn run(&mut self, input: &str) -> Result<bool> {
+        let input = input.trim().trim_end_matches(';').trim();
+        if input.is_empty() {
+            return Ok(true);
+        }
+        if !input.starts_with('\\') {
+            self.timed(|shell| shell.query(input)).await?;
+            return Ok(true);
+        }
+
+        let (command, args) = match input.find(char::is_whitespace) {
+            Some(i) => (&input[..i], input[i..].trim()),
+            None => (input, ""),
+        };
+        match command {
+            "\\q" => return Ok(false),
+            "\\?" => println!("{}", HELP),
+            "\\timing" => {
+                self.timing = !self.timing;
+                println!("Timing is {}", if self.timing { "on" } else { "off" });
+            }
+            "\\set" => {
+                let args: Vec<&str> = args.split_whitespace().collect();
+                match args.as_slice() {
+                    [] => println!("{:?}", self.settings),
+                    [name, value] => self.set(name, value)?,
+                    _ => return Err(ballista_error("Usage: \\set [<name> <value>]")),
+                }
+            }
+            "\\explain" => {
+                let (analyze, sql) = match args.find(char::is_whitespace) {
+                    Some(i) if args[..i].eq_ignore_ascii_case("analyze") => {
+                        (true, args[i..].trim())
+                    }
+                    _ => (false, args),
+                };
+                self.timed(|shell| shell.explain(sql, analyze)).await?;
+            }
+            "\\i" => {
+                if !self.run_file(args).await? {
+                    return Ok(false);
+                }
+            }
+            "\\register" => {
+                let args: Vec<&str> = args.split_whitespace().collect();
+                match args.as_slice() {
+                    [format, name, path] => self.register(format, name, path)?,
+                    _ => return Err(ballista_error("Usage: \\register <format> <name> <path>")),
+                }
+            }
+            "\\d" => {
+                for (name, (format, path)) in &self.tables {
+                    println!("{} ({} at {})", name, format, path);
+                }
+            }
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Unknown command {}, try \\? for help",
+                    command
+                )))
+            }
+        }
+        Ok(true)
+    }
+
+    async fn timed<'a, F, Fut>(&'a self, f: F) -> Result<()>
+    where
+        F: FnOnce(&'a Self) -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        let start = Instant::now();
+        f(self).await?;
+        if self.timing {
+            println!("Time: {} ms", start.elapsed().as_millis());
+        }
+        Ok(())
+    }
+
+    async fn query(&self, sql: &str) -> Result<()> {
+        let df = self.ctx.sql(sql)?.with_settings(self.settings);
+        let batches = df.collect().await?;
+        pretty::print_batches(&batches)?;
+        let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        println!("{} row{}", rows, if rows == 1 { "" } else { "s" });
+        Ok(())
+    }
+
+    async fn explain(&self, sql: &str, analyze: bool) -> Result<()> {
+        let df = self.ctx.sql(sql)?.with_settings(self.settings);
+        let explanation = if analyze {
+            df.explain_analyze().await?
+        } else {
+            df.explain_plans().await?
+        };
+        for plan_type in &[JOB_CONFIG, LOGICAL_PLAN, PHYSICAL_PLAN, DISTRIBUTED_PLAN] {
+            if let Some(plan) = explanation.plan(plan_type) {
+                println!("{}:\n{}\n", plan_type, plan.trim_end());
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let value: usize = value
+            .parse()
+            .map_err(|_| ballista_error(&format!("Invalid value {} for {}", value, name)))?;
+        self.settings = match name {
+            "batch_size" => self.settings.with_batch_size(value),
+            "target_partitions" => self.settings.with_target_partitions(value),
+            "memory_limit" => self.settings.with_memory_limit(value),
+            "timeout_ms" => self
+                .settings
+                .with_timeout(Duration::from_millis(value as u64)),
+            "task_timeout_ms" => self
+                .settings
+                .with_task_timeout(Duration::from_millis(value as u64)),
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Unknown setting {}, expected one of `batch_size`, `target_partitions`, `memory_limit`, `timeout_ms`, or `task_timeout_ms`",
+                    name
+                )))
+            }
+        };
+        Ok(())
+    }
+
+    fn register(&mut self, format: &str, name: &str, path: &str) -> Result<()> {
+        match format {
+            "csv" => self.ctx.register_csv(name, path, CsvReadOptions::new())?,
+            "parquet" => self.ctx.register_parquet(name, path)?,
+            "json" => self.ctx.register_json(name, path, JsonReadOptions::new())?,
+            "avro" => self.ctx.register_avro(name, path)?,
+            "arrow" => self.ctx.register_arrow(name, path)?,
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Unsupported format {}, expected one of `csv`, `parquet`, `json`, `avro`, or `arrow`",
+                    format
+                )))
+            }
+        }
+        self.tables
+            .insert(name.to_owned(), (format.to_owned(), path.to_owned()));
+        Ok(())
+    }
+
+    /// Run the statements of a script file, stopping at the first statement that fails. The
+    /// future is boxed because scripts can run other scripts with `\\i`.
+    fn run_file<'a>(&'a mut self, path: &'a str) -> LocalBoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let script = fs::read_to_string(path)?;
+            for statement in split_statements(&script) {
+                if !self.run(&statement).await? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    /// Read statements from stdin until the input ends or the shell is told to quit, printing
+    /// the errors of statements that fail
+    async fn repl(&mut self) -> Result<()> {
+        println!(
+            "Ballista v{} SQL shell. Type \\? for help.",
+            BALLISTA_VERSION
+        );
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut buffer = String::new();
+        loop {
+            print!(
+                "{}",
+                if buffer.is_empty() {
+                    "ballista> "
+                } else {
+                    "       -> "
+                }
+            );
+            io::stdout().flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            buffer.push_str(&line);
+            buffer.push('\n');
+            // commands take the rest of their line, while SQL runs once terminated with `;`
+            let complete = buffer.trim_start().starts_with('\\') || line.trim_end().ends_with(';');
+            if !complete {
+                continue;
+            }
+            let input = std::mem::take(&mut buffer);
+            match self.run(&input).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => eprintln!("Error: {:?}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a script into statements terminated with `;`, and commands that each take up a line.
+/// Lines starting with `--` are comments.
+fn split_statements(script: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut buffer = String::new();
+    for line in script.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("--") || (trimmed.is_empty() && buffer.is_empty()) {
+            continue;
+        }
+        if buffer.is_empty() && trimmed.starts_with('\\') {
+            statements.push(trimmed.to_owned());
+            continue;
+        }
+        buffer.push_str(line);
+        buffer.push('\n');
+        if trimmed.ends_with(';') {
+            statements.push(std::mem::take(&mut buffer));
+        }
+    }
+    if !buffer.trim().is_empty() {
+        statements.push(buffer);
+    }
+    statements
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opt = Opt::from_args();
+
+    let config = BallistaConfig::load(opt.config.as_deref())?
+        .with_flag(CLIENT_HOST, opt.host.as_ref())?
+        .with_flag(CLIENT_PORT, opt.port)?
+        .with_flag(AUTH_TOKEN, opt.auth_token.as_ref())?
+        .with_flag(TLS_CA_CERT, opt.tls_ca_cert.as_ref())?
+        .with_flag(TLS_DOMAIN_NAME, opt.tls_domain_name.as_ref())?;
+
+    let mut shell = Shell {
+        ctx: Context::remote_with_config(&config)?,
+        tables: BTreeMap::new(),
+        settings: QuerySettings::default(),
+        timing: opt.timing,
+    };
+
+    for file in &opt.file {
+        if !shell.run_file(file).await? {
+            return Ok(());
+        }
+    }
+    match &opt.command {
+        Some(command) => {
+            for statement in split_statements(command) {
+                if !shell.run(&statement).await? {
+                    break;
+                }
+            }
+        }
+        // only read statements interactively when nothing else was given to run
+        None if opt.file.is_empty() => shell.repl().await?,
+        None => {}
+    }
+    Ok(())
+}
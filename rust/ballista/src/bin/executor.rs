@@ -15,19 +15,42 @@
 //! Ballista Rust executor binary.
 
 use std::sync::Arc;
+use std::time::Duration;
 
+use ballista::config::*;
+use ballista::distributed::audit::audit_sink;
+use ballista::distributed::auth::{
+    anonymous_interceptor, StaticTokenAuthenticator, ANONYMOUS_IDENTITY, CLUSTER_IDENTITY,
+};
+use ballista::distributed::compression::ShuffleCompression;
+use ballista::distributed::connection_pool::{connection_pool, ClientOptions};
 use ballista::distributed::executor::{BallistaExecutor, DiscoveryMode, Executor, ExecutorConfig};
+#[cfg(feature = "fault-injection")]
+use ballista::distributed::fault_injection::{FaultInjectionConfig, FaultInjector};
 use ballista::distributed::flight_service::BallistaFlightService;
+use ballista::distributed::k8s::{KubernetesConfig, KubernetesSource};
+use ballista::distributed::metrics::serve_metrics;
+use ballista::distributed::plan_validation::PlanValidator;
+use ballista::distributed::scheduler::JobConfig;
+use ballista::distributed::tls::TlsConfig;
+use ballista::distributed::tracing::{set_span_exporter, ZipkinExporter};
+use ballista::execution::udf::udf_registry;
 use ballista::flight::flight_service_server::FlightServiceServer;
 use ballista::BALLISTA_VERSION;
 
+use log::{error, info, warn};
 use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
 use tonic::transport::Server;
 
 /// A basic example
 #[derive(StructOpt, Debug)]
 #[structopt(name = "basic")]
 struct Opt {
+    /// TOML or YAML file to read settings from, which environment variables and flags override
+    #[structopt(long)]
+    config: Option<String>,
+
     /// discovery mode
     #[structopt(short, long)]
     mode: Option<String>,
@@ -36,6 +59,39 @@ struct Opt {
     #[structopt(long)]
     etcd_urls: Option<String>,
 
+    /// namespace of the executor pods when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_namespace: Option<String>,
+
+    /// headless service governing the executor pods when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_service: Option<String>,
+
+    /// label selector for listing executor pods with the API server when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_label_selector: Option<String>,
+
+    /// find executor pods through the DNS SRV records of this named service port instead of
+    /// the API server when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_dns_port_name: Option<String>,
+
+    /// seconds between lookups of the executor pods when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_refresh_secs: Option<u64>,
+
+    /// host:port of the executor acting as the registry when discovery mode is `registry`
+    #[structopt(long)]
+    registry: Option<String>,
+
+    /// seconds without a heartbeat after which an executor is removed from this registry
+    #[structopt(long)]
+    heartbeat_timeout_secs: Option<u64>,
+
+    /// memory available to this executor in bytes, announced to the registry
+    #[structopt(long)]
+    memory_bytes: Option<u64>,
+
     #[structopt(long)]
     bind_host: Option<String>,
 
@@ -44,44 +100,572 @@ struct Opt {
 
     /// bind port
     #[structopt(short, long)]
-    port: usize,
+    port: Option<usize>,
 
     /// max concurrent tasks
     #[structopt(short, long)]
-    concurrent_tasks: usize,
+    concurrent_tasks: Option<usize>,
+
+    /// max number of tasks waiting for a free slot before new tasks are rejected
+    #[structopt(long)]
+    queue_depth: Option<usize>,
+
+    /// number of cores that tasks run on, the max number of concurrent tasks when not given
+    #[structopt(long)]
+    cores: Option<usize>,
+
+    /// max number of threads that a task uses when other tasks leave cores idle
+    #[structopt(long)]
+    task_parallelism: Option<usize>,
+
+    /// shared token that clients and other executors must present to use this executor
+    #[structopt(long)]
+    auth_token: Option<String>,
+
+    /// PEM-encoded certificate to serve TLS with and to present to other executors
+    #[structopt(long)]
+    tls_cert: Option<String>,
+
+    /// PEM-encoded private key for the TLS certificate
+    #[structopt(long)]
+    tls_key: Option<String>,
+
+    /// PEM-encoded CA certificate used to verify other executors and clients
+    #[structopt(long)]
+    tls_ca_cert: Option<String>,
+
+    /// require clients to present a certificate signed by the CA certificate
+    #[structopt(long)]
+    tls_client_auth: bool,
+
+    /// directory that shuffle partitions are spilled to
+    #[structopt(long)]
+    work_dir: Option<String>,
+
+    /// max number of bytes of shuffle data to hold in memory before spilling to disk
+    #[structopt(long)]
+    shuffle_memory_budget: Option<usize>,
+
+    /// max number of bytes that sorts, aggregates, and joins of running tasks may hold in memory
+    /// before spilling to disk
+    #[structopt(long)]
+    operator_memory_budget: Option<usize>,
+
+    /// codec to compress shuffle partitions with in scheduled jobs, `none`, `lz4`, or `zstd`
+    #[structopt(long)]
+    shuffle_compression: Option<String>,
+
+    /// seconds after which the status of a finished task is discarded
+    #[structopt(long)]
+    task_status_ttl_secs: Option<u64>,
+
+    /// max number of task statuses to retain
+    #[structopt(long)]
+    max_task_statuses: Option<usize>,
+
+    /// max number of times a task is attempted before the job fails
+    #[structopt(long)]
+    task_max_attempts: Option<usize>,
+
+    /// delay in milliseconds before retrying a failed task, doubled on each retry
+    #[structopt(long)]
+    task_retry_backoff_ms: Option<u64>,
+
+    /// upper bound in milliseconds on the delay before retrying a failed task
+    #[structopt(long)]
+    task_max_retry_backoff_ms: Option<u64>,
+
+    /// time in milliseconds after which a job is cancelled if it has not completed
+    #[structopt(long)]
+    job_timeout_ms: Option<u64>,
+
+    /// time in milliseconds after which an attempt of a task is aborted if it has not completed
+    #[structopt(long)]
+    task_timeout_ms: Option<u64>,
+
+    /// task placement policy, either `locality` or `round-robin`
+    #[structopt(long)]
+    placement: Option<String>,
+
+    /// policy for sharing the cluster between tenants, either `fifo` or `fair`
+    #[structopt(long)]
+    scheduling: Option<String>,
+
+    /// number of tasks that the cluster runs at a time, shared between tenants by fair scheduling
+    #[structopt(long)]
+    task_slots: Option<usize>,
+
+    /// weights and max concurrent jobs of tenants, such as `etl=1/2,dashboards=3/8`
+    #[structopt(long)]
+    tenants: Option<String>,
+
+    /// max number of concurrent jobs of tenants that are not listed in `--tenants`
+    #[structopt(long)]
+    tenant_max_jobs: Option<usize>,
+
+    /// max number of rows in each batch that scans produce in scheduled jobs
+    #[structopt(long)]
+    batch_size: Option<usize>,
+
+    /// number of partitions to redistribute the output of scans into in scheduled jobs
+    #[structopt(long)]
+    target_partitions: Option<usize>,
+
+    /// join input partitions larger than this many bytes, and than `skew-factor` times the
+    /// median partition, are split into sub-partitions
+    #[structopt(long)]
+    skew_threshold_bytes: Option<u64>,
+
+    /// how many times larger than the median a partition must be to be split
+    #[structopt(long)]
+    skew_factor: Option<u64>,
+
+    /// size in bytes up to which consecutive small shuffle partitions are coalesced
+    #[structopt(long)]
+    target_partition_bytes: Option<u64>,
+
+    /// run stages as planned rather than adapting them to the output of earlier stages
+    #[structopt(long)]
+    no_adaptive_execution: bool,
+
+    /// leave tasks on the executors they were placed on rather than moving waiting tasks to
+    /// idle executors
+    #[structopt(long)]
+    no_work_stealing: bool,
+
+    /// port to serve Prometheus metrics on, at /metrics
+    #[structopt(long)]
+    metrics_port: Option<usize>,
+
+    /// time in milliseconds that accepted tasks are given to complete when shutting down before
+    /// they are cancelled
+    #[structopt(long)]
+    shutdown_grace_period_ms: Option<u64>,
+
+    /// host:port of the executor to hand off shuffle partitions to when shutting down
+    #[structopt(long)]
+    shuffle_handoff: Option<String>,
+
+    /// shared storage to write shuffle partitions to, such as `s3://bucket/shuffle` or a
+    /// directory on an NFS mount
+    #[structopt(long)]
+    shuffle_storage: Option<String>,
+
+    /// faults to inject for testing, such as `seed=7,task_failure=0.1,crash=0.01`, when built
+    /// with the `fault-injection` feature
+    #[structopt(long)]
+    fault_injection: Option<String>,
+
+    /// comma-separated paths and URIs that submitted plans may read and write files under,
+    /// such as `/data,s3://bucket/tables`
+    #[structopt(long)]
+    allowed_paths: Option<String>,
+
+    /// maximum depth of the plans of submitted queries and tasks
+    #[structopt(long)]
+    max_plan_depth: Option<usize>,
+
+    /// maximum number of partitions of any operator of submitted plans
+    #[structopt(long)]
+    max_plan_partitions: Option<usize>,
+
+    /// where to record submitted queries, tasks and management actions: `stdout`,
+    /// `file:<path>`, or `grpc:<host>:<port>`
+    #[structopt(long)]
+    audit_log: Option<String>,
+
+    /// comma-separated identities that may forward audit events to this executor
+    #[structopt(long)]
+    audit_forwarders: Option<String>,
+
+    /// comma-separated identities that may shut down and drain this executor and manage the
+    /// tasks and shuffle partitions of jobs
+    #[structopt(long)]
+    admins: Option<String>,
+
+    /// max size in bytes of the flight data messages that batches are split into
+    #[structopt(long)]
+    max_message_size: Option<usize>,
+
+    /// initial HTTP/2 flow control window of each stream in bytes
+    #[structopt(long)]
+    stream_window_size: Option<u32>,
+
+    /// initial HTTP/2 flow control window of each connection in bytes
+    #[structopt(long)]
+    connection_window_size: Option<u32>,
+
+    /// log level or filter, e.g. `info` or `ballista::distributed=debug`
+    #[structopt(long)]
+    log_level: Option<String>,
+
+    /// URL of a Zipkin-compatible collector to export spans to, e.g.
+    /// `http://jaeger:9411/api/v2/spans`
+    #[structopt(long)]
+    tracing_endpoint: Option<String>,
+
+    /// shared library to load user-defined functions from, may be specified more than once
+    #[structopt(long)]
+    udf_plugin: Vec<String>,
+}
+
+/// Load the configuration file and environment variables, and override them with the flags
+/// that were given
+fn load_config(opt: &Opt) -> Result<BallistaConfig, Box<dyn std::error::Error>> {
+    let config = BallistaConfig::load(opt.config.as_deref())?
+        .with_flag(DISCOVERY_MODE, opt.mode.as_ref())?
+        .with_flag(DISCOVERY_ETCD_URLS, opt.etcd_urls.as_ref())?
+        .with_flag(DISCOVERY_REGISTRY, opt.registry.as_ref())?
+        .with_flag(DISCOVERY_HEARTBEAT_TIMEOUT_SECS, opt.heartbeat_timeout_secs)?
+        .with_flag(DISCOVERY_K8S_NAMESPACE, opt.k8s_namespace.as_ref())?
+        .with_flag(DISCOVERY_K8S_SERVICE, opt.k8s_service.as_ref())?
+        .with_flag(
+            DISCOVERY_K8S_LABEL_SELECTOR,
+            opt.k8s_label_selector.as_ref(),
+        )?
+        .with_flag(DISCOVERY_K8S_DNS_PORT_NAME, opt.k8s_dns_port_name.as_ref())?
+        .with_flag(DISCOVERY_K8S_REFRESH_SECS, opt.k8s_refresh_secs)?
+        .with_flag(EXECUTOR_MEMORY_BYTES, opt.memory_bytes)?
+        .with_flag(EXECUTOR_BIND_HOST, opt.bind_host.as_ref())?
+        .with_flag(EXECUTOR_EXTERNAL_HOST, opt.external_host.as_ref())?
+        .with_flag(EXECUTOR_PORT, opt.port)?
+        .with_flag(EXECUTOR_CONCURRENT_TASKS, opt.concurrent_tasks)?
+        .with_flag(EXECUTOR_QUEUE_DEPTH, opt.queue_depth)?
+        .with_flag(EXECUTOR_CORES, opt.cores)?
+        .with_flag(EXECUTOR_TASK_PARALLELISM, opt.task_parallelism)?
+        .with_flag(AUTH_TOKEN, opt.auth_token.as_ref())?
+        .with_flag(TLS_CERT, opt.tls_cert.as_ref())?
+        .with_flag(TLS_KEY, opt.tls_key.as_ref())?
+        .with_flag(TLS_CA_CERT, opt.tls_ca_cert.as_ref())?
+        .with_flag(TLS_CLIENT_AUTH, Some(true).filter(|_| opt.tls_client_auth))?
+        .with_flag(EXECUTOR_WORK_DIR, opt.work_dir.as_ref())?
+        .with_flag(EXECUTOR_SHUFFLE_MEMORY_BUDGET, opt.shuffle_memory_budget)?
+        .with_flag(EXECUTOR_OPERATOR_MEMORY_BUDGET, opt.operator_memory_budget)?
+        .with_flag(
+            EXECUTOR_SHUFFLE_COMPRESSION,
+            opt.shuffle_compression.as_ref(),
+        )?
+        .with_flag(JOB_TASK_MAX_ATTEMPTS, opt.task_max_attempts)?
+        .with_flag(JOB_TASK_RETRY_BACKOFF_MS, opt.task_retry_backoff_ms)?
+        .with_flag(JOB_TASK_MAX_RETRY_BACKOFF_MS, opt.task_max_retry_backoff_ms)?
+        .with_flag(JOB_TIMEOUT_MS, opt.job_timeout_ms)?
+        .with_flag(JOB_TASK_TIMEOUT_MS, opt.task_timeout_ms)?
+        .with_flag(JOB_PLACEMENT, opt.placement.as_ref())?
+        .with_flag(JOB_SCHEDULING, opt.scheduling.as_ref())?
+        .with_flag(JOB_TASK_SLOTS, opt.task_slots)?
+        .with_flag(JOB_TENANTS, opt.tenants.as_ref())?
+        .with_flag(JOB_TENANT_MAX_JOBS, opt.tenant_max_jobs)?
+        .with_flag(JOB_BATCH_SIZE, opt.batch_size)?
+        .with_flag(JOB_TARGET_PARTITIONS, opt.target_partitions)?
+        .with_flag(JOB_SKEW_THRESHOLD_BYTES, opt.skew_threshold_bytes)?
+        .with_flag(JOB_SKEW_FACTOR, opt.skew_factor)?
+        .with_flag(JOB_TARGET_PARTITION_BYTES, opt.target_partition_bytes)?
+        .with_flag(
+            JOB_ADAPTIVE,
+            Some(false).filter(|_| opt.no_adaptive_execution),
+        )?
+        .with_flag(
+            JOB_WORK_STEALING,
+            Some(false).filter(|_| opt.no_work_stealing),
+        )?
+        .with_flag(EXECUTOR_METRICS_PORT, opt.metrics_port)?
+        .with_flag(
+            EXECUTOR_SHUTDOWN_GRACE_PERIOD_MS,
+            opt.shutdown_grace_period_ms,
+        )?
+        .with_flag(EXECUTOR_SHUFFLE_HANDOFF, opt.shuffle_handoff.as_ref())?
+        .with_flag(EXECUTOR_SHUFFLE_STORAGE, opt.shuffle_storage.as_ref())?
+        .with_flag(EXECUTOR_FAULT_INJECTION, opt.fault_injection.as_ref())?
+        .with_flag(EXECUTOR_ALLOWED_PATHS, opt.allowed_paths.as_ref())?
+        .with_flag(EXECUTOR_MAX_PLAN_DEPTH, opt.max_plan_depth)?
+        .with_flag(EXECUTOR_MAX_PLAN_PARTITIONS, opt.max_plan_partitions)?
+        .with_flag(EXECUTOR_AUDIT_LOG, opt.audit_log.as_ref())?
+        .with_flag(EXECUTOR_AUDIT_FORWARDERS, opt.audit_forwarders.as_ref())?
+        .with_flag(EXECUTOR_ADMINS, opt.admins.as_ref())?
+        .with_flag(EXECUTOR_TASK_STATUS_TTL_SECS, opt.task_status_ttl_secs)?
+        .with_flag(EXECUTOR_MAX_TASK_STATUSES, opt.max_task_statuses)?
+        .with_flag(GRPC_MAX_MESSAGE_SIZE, opt.max_message_size)?
+        .with_flag(GRPC_STREAM_WINDOW_SIZE, opt.stream_window_size)?
+        .with_flag(GRPC_CONNECTION_WINDOW_SIZE, opt.connection_window_size)?
+        .with_flag(LOG_LEVEL, opt.log_level.as_ref())?
+        .with_flag(TRACING_ENDPOINT, opt.tracing_endpoint.as_ref())?;
+    Ok(config)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::from_args();
+    let settings = load_config(&opt)?;
+
+    // RUST_LOG takes precedence over the log level setting
+    let log_level: String = settings.require(LOG_LEVEL)?;
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&log_level)).init();
+
+    if let Some(endpoint) = settings.get(TRACING_ENDPOINT) {
+        info!("Exporting spans tracing_endpoint={}", endpoint);
+        set_span_exporter(Arc::new(ZipkinExporter::new(endpoint, "ballista-executor")));
+    }
+
+    connection_pool().configure(ClientOptions::from_config(&settings)?);
+
+    for path in &opt.udf_plugin {
+        udf_registry().load_plugin(path)?;
+        info!("Loaded UDF plugin {}", path);
+    }
 
-    let mode = match opt.mode {
-        Some(s) => match s.as_str() {
-            "k8s" => DiscoveryMode::Kubernetes,
+    let mode = match settings.get(DISCOVERY_MODE) {
+        Some(s) => match s {
+            "k8s" => DiscoveryMode::Kubernetes(KubernetesConfig {
+                namespace: settings.require(DISCOVERY_K8S_NAMESPACE)?,
+                service: settings.require(DISCOVERY_K8S_SERVICE)?,
+                source: match settings.get(DISCOVERY_K8S_DNS_PORT_NAME) {
+                    Some(port_name) => KubernetesSource::Dns {
+                        port_name: port_name.to_owned(),
+                    },
+                    None => KubernetesSource::ApiServer {
+                        label_selector: settings.require(DISCOVERY_K8S_LABEL_SELECTOR)?,
+                    },
+                },
+                refresh_interval: Duration::from_secs(
+                    settings.require(DISCOVERY_K8S_REFRESH_SECS)?,
+                ),
+            }),
             "etcd" => DiscoveryMode::Etcd,
+            "registry" => {
+                let registry: String = settings.require(DISCOVERY_REGISTRY)?;
+                let host_port: Vec<&str> = registry.split(':').collect();
+                if host_port.len() != 2 {
+                    return Err("--registry must be of the form host:port".into());
+                }
+                DiscoveryMode::Registry {
+                    host: host_port[0].to_owned(),
+                    port: host_port[1].parse()?,
+                }
+            }
             _ => unimplemented!(),
         },
         _ => DiscoveryMode::Standalone,
     };
 
-    let external_host = opt.external_host.unwrap_or_else(|| "localhost".to_owned());
-    let bind_host = opt.bind_host.unwrap_or_else(|| "0.0.0.0".to_owned());
-    let etcd_urls = opt.etcd_urls.unwrap_or_else(|| "localhost:2379".to_owned());
-    let port = opt.port;
+    let external_host: String = settings.require(EXECUTOR_EXTERNAL_HOST)?;
+    let bind_host: String = settings.require(EXECUTOR_BIND_HOST)?;
+    let etcd_urls: String = settings.require(DISCOVERY_ETCD_URLS)?;
+    let port: usize = settings.require(EXECUTOR_PORT)?;
+    let concurrent_tasks: usize = settings.require(EXECUTOR_CONCURRENT_TASKS)?;
+    let cores = settings
+        .get_as::<usize>(EXECUTOR_CORES)?
+        .unwrap_or(concurrent_tasks);
 
-    let config = ExecutorConfig::new(mode, &external_host, port, &etcd_urls);
+    let max_message_size: usize = settings.require(GRPC_MAX_MESSAGE_SIZE)?;
 
-    println!("Running with config: {:?}", config);
+    let config = ExecutorConfig::new(mode, &external_host, port, &etcd_urls)
+        .with_resources(cores, settings.require(EXECUTOR_MEMORY_BYTES)?)
+        .with_max_message_size(max_message_size);
+    let auth_token = settings.get_as::<String>(AUTH_TOKEN)?;
+    let config = match &auth_token {
+        Some(auth_token) => config.with_auth_token(auth_token),
+        None => config,
+    };
+
+    let work_dir = settings
+        .get_as::<String>(EXECUTOR_WORK_DIR)?
+        .unwrap_or_else(|| {
+            std::env::temp_dir()
+                .join("ballista")
+                .to_string_lossy()
+                .into_owned()
+        });
+    let shuffle_memory_budget = settings
+        .get_as(EXECUTOR_SHUFFLE_MEMORY_BUDGET)?
+        .unwrap_or(usize::MAX);
+    let config = config.with_shuffle_spill(&work_dir, shuffle_memory_budget);
+    let operator_memory_budget = settings
+        .get_as(EXECUTOR_OPERATOR_MEMORY_BUDGET)?
+        .unwrap_or(usize::MAX);
+    let config = config.with_operator_memory_budget(operator_memory_budget);
+    let shuffle_compression: String = settings.require(EXECUTOR_SHUFFLE_COMPRESSION)?;
+    let config =
+        config.with_shuffle_compression(ShuffleCompression::from_name(&shuffle_compression)?);
+    let config = match settings.get(EXECUTOR_SHUFFLE_STORAGE) {
+        Some(root) => config.with_shuffle_storage(root),
+        None => config,
+    };
+    let policies = settings.job_policies()?;
+    let config = config
+        .with_retry_policy(policies.retry)
+        .with_placement_policy(policies.placement)
+        .with_scheduling_policy(policies.scheduling);
+    let job_config = JobConfig::default()
+        .with_batch_size(settings.require(JOB_BATCH_SIZE)?)
+        .with_skew_threshold(
+            settings.require(JOB_SKEW_THRESHOLD_BYTES)?,
+            settings.require(JOB_SKEW_FACTOR)?,
+        )
+        .with_target_partition_bytes(settings.require(JOB_TARGET_PARTITION_BYTES)?)
+        .with_adaptive_execution(settings.require(JOB_ADAPTIVE)?)
+        .with_work_stealing(settings.require(JOB_WORK_STEALING)?);
+    let job_config = match settings.get_as(JOB_TARGET_PARTITIONS)? {
+        Some(n) => job_config.with_target_partitions(n),
+        None => job_config,
+    };
+    let job_config = match settings.get_as(JOB_TIMEOUT_MS)? {
+        Some(ms) => job_config.with_timeout(Duration::from_millis(ms)),
+        None => job_config,
+    };
+    let job_config = match settings.get_as(JOB_TASK_TIMEOUT_MS)? {
+        Some(ms) => job_config.with_task_timeout(Duration::from_millis(ms)),
+        None => job_config,
+    };
+    let config = config.with_job_config(job_config);
+
+    let tls = match (settings.get(TLS_CERT), settings.get(TLS_KEY)) {
+        (Some(cert), Some(key)) => {
+            let tls = TlsConfig::new()
+                .with_identity(cert, key)?
+                .with_client_auth(settings.require(TLS_CLIENT_AUTH)?);
+            Some(match settings.get(TLS_CA_CERT) {
+                Some(ca_cert) => tls.with_ca_cert(ca_cert)?,
+                None => tls,
+            })
+        }
+        (None, None) => None,
+        _ => return Err("Both --tls-cert and --tls-key must be specified".into()),
+    };
+    let config = match &tls {
+        Some(tls) => config.with_tls(tls.clone()),
+        None => config,
+    };
+
+    info!("Running with settings: {}", settings);
+    info!("Running with config: {:?}", config);
 
     let addr = format!("{}:{}", bind_host, port);
     let addr = addr.parse()?;
     let executor: Arc<dyn Executor> = Arc::new(BallistaExecutor::new(config));
-    let service = BallistaFlightService::new(executor, opt.concurrent_tasks);
-    let server = FlightServiceServer::new(service);
-    println!(
+    let service = BallistaFlightService::new(
+        executor,
+        concurrent_tasks,
+        settings.require(EXECUTOR_QUEUE_DEPTH)?,
+    )
+    .with_retention(
+        Duration::from_secs(settings.require(EXECUTOR_TASK_STATUS_TTL_SECS)?),
+        settings.require(EXECUTOR_MAX_TASK_STATUSES)?,
+    )
+    .with_heartbeat_timeout(Duration::from_secs(
+        settings.require(DISCOVERY_HEARTBEAT_TIMEOUT_SECS)?,
+    ))
+    .with_task_parallelism(cores, settings.require(EXECUTOR_TASK_PARALLELISM)?)
+    .with_resources(cores, settings.require(EXECUTOR_MEMORY_BYTES)?)
+    .with_max_message_size(max_message_size)
+    .with_shutdown_grace_period(Duration::from_millis(
+        settings.require(EXECUTOR_SHUTDOWN_GRACE_PERIOD_MS)?,
+    ));
+    let service = match settings.get(EXECUTOR_SHUFFLE_HANDOFF) {
+        Some(peer) => {
+            let host_port: Vec<&str> = peer.split(':').collect();
+            if host_port.len() != 2 {
+                return Err("--shuffle-handoff must be of the form host:port".into());
+            }
+            service.with_shuffle_handoff(host_port[0], host_port[1].parse()?)
+        }
+        None => service,
+    };
+    let plan_validator = match settings.get(EXECUTOR_ALLOWED_PATHS) {
+        Some(paths) => {
+            PlanValidator::new().with_allowed_paths(paths.split(',').map(String::from).collect())
+        }
+        None => PlanValidator::new(),
+    };
+    let plan_validator = match settings.get_as(EXECUTOR_MAX_PLAN_DEPTH)? {
+        Some(max_depth) => plan_validator.with_max_depth(max_depth),
+        None => plan_validator,
+    };
+    let plan_validator = match settings.get_as(EXECUTOR_MAX_PLAN_PARTITIONS)? {
+        Some(max_partitions) => plan_validator.with_max_partitions(max_partitions),
+        None => plan_validator,
+    };
+    let service = service.with_plan_validator(plan_validator);
+    let service = match settings.get(EXECUTOR_AUDIT_LOG) {
+        Some(audit_log) => {
+            info!("Recording audit events audit_log={}", audit_log);
+            service.with_audit_sink(audit_sink(audit_log, auth_token.clone(), tls.clone())?)
+        }
+        None => service,
+    };
+    let audit_forwarders: String = settings.require(EXECUTOR_AUDIT_FORWARDERS)?;
+    let service =
+        service.with_audit_forwarders(audit_forwarders.split(',').map(String::from).collect());
+    let admins: String = settings.require(EXECUTOR_ADMINS)?;
+    let service = service.with_admins(admins.split(',').map(String::from).collect());
+    #[cfg(feature = "fault-injection")]
+    let service = match settings.get(EXECUTOR_FAULT_INJECTION) {
+        Some(spec) => {
+            let faults = FaultInjector::new(FaultInjectionConfig::from_spec(spec)?);
+            warn!("Injecting faults config={:?}", faults.config());
+            service.with_fault_injection(faults.with_process_exit())
+        }
+        None => service,
+    };
+    #[cfg(not(feature = "fault-injection"))]
+    {
+        if settings.get(EXECUTOR_FAULT_INJECTION).is_some() {
+            warn!("Ignoring fault injection, which requires the fault-injection feature");
+        }
+    }
+    let service = match auth_token {
+        Some(auth_token) => service.with_authenticator(Arc::new(
+            StaticTokenAuthenticator::new().with_token(&auth_token, CLUSTER_IDENTITY),
+        )),
+        None => {
+            if !admins.split(',').any(|admin| admin == ANONYMOUS_IDENTITY) {
+                warn!(
+                    "Clients are not authenticated, so the scheduler cannot manage this executor \
+                     unless {} includes {}",
+                    EXECUTOR_ADMINS, ANONYMOUS_IDENTITY
+                );
+            }
+            service
+        }
+    };
+    let shutdown = service.shutdown_signal();
+
+    // Kubernetes sends SIGTERM when it stops the pod, such as during a rolling upgrade
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let terminating = service.clone();
+    tokio::spawn(async move {
+        if sigterm.recv().await.is_some() {
+            info!("Received SIGTERM, shutting down gracefully");
+            terminating.shutdown();
+        }
+    });
+
+    if let Some(metrics_port) = settings.get_as::<usize>(EXECUTOR_METRICS_PORT)? {
+        let metrics_addr = format!("{}:{}", bind_host, metrics_port).parse()?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(metrics_addr, move || service.metrics_text()).await {
+                error!("Metrics server failed: {:?}", e);
+            }
+        });
+    }
+
+    let server = match service.session_manager() {
+        Some(sessions) => FlightServiceServer::with_interceptor(service, sessions.interceptor()),
+        None => FlightServiceServer::with_interceptor(service, anonymous_interceptor()),
+    };
+    info!(
         "Ballista v{} Rust Executor listening on {:?}",
         BALLISTA_VERSION, addr
     );
-    Server::builder().add_service(server).serve(addr).await?;
+    let mut builder = Server::builder()
+        .initial_stream_window_size(settings.get_as::<u32>(GRPC_STREAM_WINDOW_SIZE)?)
+        .initial_connection_window_size(settings.get_as::<u32>(GRPC_CONNECTION_WINDOW_SIZE)?);
+    if let Some(tls) = &tls {
+        builder = builder.tls_config(tls.server_config());
+    }
+    builder
+        .add_service(server)
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
     Ok(())
 }
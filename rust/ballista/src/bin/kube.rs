@@ -0,0 +1,122 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deploys Ballista clusters to Kubernetes: generates the manifests of a cluster from its
+//! settings, applies them to the current kubectl context, and scales or deletes the cluster.
+
+use ballista::config::{BallistaConfig, LOG_LEVEL, SCHEDULER_PORT};
+use ballista::distributed::k8s_deploy::{default_image, ClusterSpec};
+use ballista::error::Result;
+
+use structopt::StructOpt;
+
+/// Deploy Ballista clusters to Kubernetes
+#[derive(StructOpt, Debug)]
+#[structopt(name = "ballista-kube")]
+struct Opt {
+    /// TOML or YAML file with the settings of the executors and the scheduler of the cluster
+    #[structopt(long)]
+    config: Option<String>,
+
+    /// name of the cluster, which prefixes the names of its objects
+    #[structopt(long, default_value = "ballista")]
+    name: String,
+
+    /// namespace to deploy the cluster to
+    #[structopt(short, long, default_value = "default")]
+    namespace: String,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// print the manifests of the cluster as YAML, to apply with kubectl
+    Generate(Deployment),
+    /// create the cluster, or update it to match the settings
+    Apply(Deployment),
+    /// change the number of executors of the cluster
+    Scale {
+        /// number of executors
+        executors: usize,
+    },
+    /// delete the objects of the cluster
+    Delete,
+}
+
+#[derive(StructOpt, Debug)]
+struct Deployment {
+    /// number of executors
+    #[structopt(short, long, default_value = "1")]
+    executors: usize,
+
+    /// image of the executors and the scheduler, the release of this version when not set
+    #[structopt(long)]
+    image: Option<String>,
+
+    /// CPU request and limit of each executor
+    #[structopt(long, default_value = "1")]
+    executor_cpu: String,
+
+    /// memory request and limit of each executor
+    #[structopt(long, default_value = "1024Mi")]
+    executor_memory: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opt = Opt::from_args();
+    let config = BallistaConfig::load(opt.config.as_deref())?;
+
+    // RUST_LOG takes precedence over the log level setting
+    let log_level: String = config.require(LOG_LEVEL)?;
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&log_level)).init();
+
+    let spec = ClusterSpec::new(&opt.name)
+        .with_namespace(&opt.namespace)
+        .with_config(config);
+    let with_deployment = |spec: ClusterSpec, deployment: &Deployment| {
+        spec.with_executors(deployment.executors)
+            .with_image(deployment.image.as_deref().unwrap_or(&default_image()))
+            .with_executor_resources(&deployment.executor_cpu, &deployment.executor_memory)
+    };
+
+    match &opt.command {
+        Command::Generate(deployment) => {
+            print!("{}", with_deployment(spec, deployment).to_yaml()?);
+        }
+        Command::Apply(deployment) => {
+            let spec = with_deployment(spec, deployment);
+            spec.apply().await?;
+            println!(
+                "Applied cluster {} with {} executors; clients connect to {}-scheduler.{}:{}",
+                spec.name,
+                spec.executors,
+                spec.name,
+                spec.namespace,
+                spec.config.get(SCHEDULER_PORT).unwrap_or("")
+            );
+        }
+        Command::Scale { executors } => {
+            spec.scale(*executors).await?;
+            println!("Scaled cluster {} to {} executors", spec.name, executors);
+        }
+        Command::Delete => {
+            let deleted = spec.delete().await?;
+            println!("Deleted {} objects of cluster {}", deleted, spec.name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,455 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ballista Rust scheduler binary.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ballista::config::*;
+use ballista::distributed::auth::{
+    anonymous_interceptor, SessionManager, StaticTokenAuthenticator, CLUSTER_IDENTITY,
+};
+use ballista::distributed::connection_pool::{connection_pool, ClientOptions};
+use ballista::distributed::executor::{DiscoveryMode, ExecutorConfig};
+use ballista::distributed::job_state::{
+    EtcdJobStateStore, InMemoryJobStateStore, JobStateStore, SledJobStateStore,
+};
+use ballista::distributed::k8s::{KubernetesConfig, KubernetesSource};
+use ballista::distributed::plan_validation::PlanValidator;
+use ballista::distributed::scheduler::JobConfig;
+use ballista::distributed::scheduler_server::SchedulerServer;
+use ballista::distributed::table_store::{
+    EtcdTableStore, InMemoryTableStore, SledTableStore, TableStore,
+};
+use ballista::distributed::tracing::{set_span_exporter, ZipkinExporter};
+use ballista::distributed::web_ui::serve_ui;
+use ballista::protobuf::scheduler_grpc_server::SchedulerGrpcServer;
+use ballista::BALLISTA_VERSION;
+
+use log::{error, info};
+use structopt::StructOpt;
+use tonic::transport::Server;
+
+/// Standalone scheduler that runs jobs on a cluster of executors
+#[derive(StructOpt, Debug)]
+#[structopt(name = "scheduler")]
+struct Opt {
+    /// TOML or YAML file to read settings from, which environment variables and flags override
+    #[structopt(long)]
+    config: Option<String>,
+
+    /// discovery mode used to find executors: `etcd`, `k8s` or `registry`
+    #[structopt(short, long)]
+    mode: Option<String>,
+
+    /// etcd urls for use when discovery mode is `etcd`
+    #[structopt(long)]
+    etcd_urls: Option<String>,
+
+    /// host:port of the executor acting as the registry when discovery mode is `registry`
+    #[structopt(long)]
+    registry: Option<String>,
+
+    /// namespace of the executor pods when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_namespace: Option<String>,
+
+    /// headless service governing the executor pods when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_service: Option<String>,
+
+    /// label selector for listing executor pods with the API server when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_label_selector: Option<String>,
+
+    /// find executor pods through the DNS SRV records of this named service port instead of
+    /// the API server when discovery mode is `k8s`
+    #[structopt(long)]
+    k8s_dns_port_name: Option<String>,
+
+    #[structopt(long)]
+    bind_host: Option<String>,
+
+    /// bind port
+    #[structopt(short, long)]
+    port: Option<usize>,
+
+    /// shared token to present to executors
+    #[structopt(long)]
+    auth_token: Option<String>,
+
+    /// max number of times a task is attempted before the job fails
+    #[structopt(long)]
+    task_max_attempts: Option<usize>,
+
+    /// delay in milliseconds before retrying a failed task, doubled on each retry
+    #[structopt(long)]
+    task_retry_backoff_ms: Option<u64>,
+
+    /// upper bound in milliseconds on the delay before retrying a failed task
+    #[structopt(long)]
+    task_max_retry_backoff_ms: Option<u64>,
+
+    /// time in milliseconds after which a job is cancelled if it has not completed
+    #[structopt(long)]
+    job_timeout_ms: Option<u64>,
+
+    /// time in milliseconds after which an attempt of a task is aborted if it has not completed
+    #[structopt(long)]
+    task_timeout_ms: Option<u64>,
+
+    /// task placement policy, either `locality` or `round-robin`
+    #[structopt(long)]
+    placement: Option<String>,
+
+    /// policy for sharing the cluster between tenants, either `fifo` or `fair`
+    #[structopt(long)]
+    scheduling: Option<String>,
+
+    /// number of tasks that the cluster runs at a time, shared between tenants by fair scheduling
+    #[structopt(long)]
+    task_slots: Option<usize>,
+
+    /// weights and max concurrent jobs of tenants, such as `etl=1/2,dashboards=3/8`
+    #[structopt(long)]
+    tenants: Option<String>,
+
+    /// max number of concurrent jobs of tenants that are not listed in `--tenants`
+    #[structopt(long)]
+    tenant_max_jobs: Option<usize>,
+
+    /// max number of rows in each batch that scans produce in scheduled jobs
+    #[structopt(long)]
+    batch_size: Option<usize>,
+
+    /// number of partitions to redistribute the output of scans into in scheduled jobs
+    #[structopt(long)]
+    target_partitions: Option<usize>,
+
+    /// join input partitions larger than this many bytes, and than `skew-factor` times the
+    /// median partition, are split into sub-partitions
+    #[structopt(long)]
+    skew_threshold_bytes: Option<u64>,
+
+    /// how many times larger than the median a partition must be to be split
+    #[structopt(long)]
+    skew_factor: Option<u64>,
+
+    /// size in bytes up to which consecutive small shuffle partitions are coalesced
+    #[structopt(long)]
+    target_partition_bytes: Option<u64>,
+
+    /// run stages as planned rather than adapting them to the output of earlier stages
+    #[structopt(long)]
+    no_adaptive_execution: bool,
+
+    /// leave tasks on the executors they were placed on rather than moving waiting tasks to
+    /// idle executors
+    #[structopt(long)]
+    no_work_stealing: bool,
+
+    /// store that job state is persisted in so that jobs survive a restart: `memory`, `sled`
+    /// or `etcd`
+    #[structopt(long)]
+    job_state_store: Option<String>,
+
+    /// directory of the sled database when the job state store is `sled`
+    #[structopt(long)]
+    job_state_path: Option<String>,
+
+    /// store that external table definitions are persisted in so that they survive a restart:
+    /// `memory`, `sled` or `etcd`
+    #[structopt(long)]
+    catalog_store: Option<String>,
+
+    /// directory of the sled database when the catalog store is `sled`
+    #[structopt(long)]
+    catalog_path: Option<String>,
+
+    /// time in milliseconds to serve the results of repeated queries of unchanged files from
+    /// the results of earlier jobs
+    #[structopt(long)]
+    result_cache_ttl_ms: Option<u64>,
+
+    /// time in milliseconds that jobs may read the output of stages of earlier jobs that scan
+    /// the same data, such as a scan with the same filter and aggregate, rather than running
+    /// the stages again
+    #[structopt(long)]
+    stage_reuse_ttl_ms: Option<u64>,
+
+    /// shared storage that executors write shuffle partitions to, which the scheduler reads
+    /// the output of jobs from and does not recompute when an executor is lost
+    #[structopt(long)]
+    shuffle_storage: Option<String>,
+
+    /// time in milliseconds after which a client session that has not been used is closed,
+    /// dropping the tables registered with it
+    #[structopt(long)]
+    session_ttl_ms: Option<u64>,
+
+    /// max number of finished jobs whose queries and metrics are kept in the job history
+    #[structopt(long)]
+    job_history_size: Option<usize>,
+
+    /// comma-separated paths and URIs that tables may be registered and created under, which
+    /// should be the paths that executors allow plans to read
+    #[structopt(long)]
+    allowed_paths: Option<String>,
+
+    /// comma-separated identities that may create and drop external tables
+    #[structopt(long)]
+    admins: Option<String>,
+
+    /// port to serve the web UI on, which shows the jobs, stages, tasks, and executors
+    #[structopt(long)]
+    ui_port: Option<usize>,
+
+    /// log level or filter, e.g. `info` or `ballista::distributed=debug`
+    #[structopt(long)]
+    log_level: Option<String>,
+
+    /// URL of a Zipkin-compatible collector to export spans to, e.g.
+    /// `http://jaeger:9411/api/v2/spans`
+    #[structopt(long)]
+    tracing_endpoint: Option<String>,
+}
+
+/// Load the configuration file and environment variables, and override them with the flags
+/// that were given
+fn load_config(opt: &Opt) -> Result<BallistaConfig, Box<dyn std::error::Error>> {
+    let config = BallistaConfig::load(opt.config.as_deref())?
+        .with_flag(DISCOVERY_MODE, opt.mode.as_ref())?
+        .with_flag(DISCOVERY_ETCD_URLS, opt.etcd_urls.as_ref())?
+        .with_flag(DISCOVERY_REGISTRY, opt.registry.as_ref())?
+        .with_flag(DISCOVERY_K8S_NAMESPACE, opt.k8s_namespace.as_ref())?
+        .with_flag(DISCOVERY_K8S_SERVICE, opt.k8s_service.as_ref())?
+        .with_flag(
+            DISCOVERY_K8S_LABEL_SELECTOR,
+            opt.k8s_label_selector.as_ref(),
+        )?
+        .with_flag(DISCOVERY_K8S_DNS_PORT_NAME, opt.k8s_dns_port_name.as_ref())?
+        .with_flag(SCHEDULER_BIND_HOST, opt.bind_host.as_ref())?
+        .with_flag(SCHEDULER_PORT, opt.port)?
+        .with_flag(AUTH_TOKEN, opt.auth_token.as_ref())?
+        .with_flag(JOB_TASK_MAX_ATTEMPTS, opt.task_max_attempts)?
+        .with_flag(JOB_TASK_RETRY_BACKOFF_MS, opt.task_retry_backoff_ms)?
+        .with_flag(JOB_TASK_MAX_RETRY_BACKOFF_MS, opt.task_max_retry_backoff_ms)?
+        .with_flag(JOB_TIMEOUT_MS, opt.job_timeout_ms)?
+        .with_flag(JOB_TASK_TIMEOUT_MS, opt.task_timeout_ms)?
+        .with_flag(JOB_PLACEMENT, opt.placement.as_ref())?
+        .with_flag(JOB_SCHEDULING, opt.scheduling.as_ref())?
+        .with_flag(JOB_TASK_SLOTS, opt.task_slots)?
+        .with_flag(JOB_TENANTS, opt.tenants.as_ref())?
+        .with_flag(JOB_TENANT_MAX_JOBS, opt.tenant_max_jobs)?
+        .with_flag(JOB_BATCH_SIZE, opt.batch_size)?
+        .with_flag(JOB_TARGET_PARTITIONS, opt.target_partitions)?
+        .with_flag(JOB_SKEW_THRESHOLD_BYTES, opt.skew_threshold_bytes)?
+        .with_flag(JOB_SKEW_FACTOR, opt.skew_factor)?
+        .with_flag(JOB_TARGET_PARTITION_BYTES, opt.target_partition_bytes)?
+        .with_flag(
+            JOB_ADAPTIVE,
+            Some(false).filter(|_| opt.no_adaptive_execution),
+        )?
+        .with_flag(
+            JOB_WORK_STEALING,
+            Some(false).filter(|_| opt.no_work_stealing),
+        )?
+        .with_flag(SCHEDULER_JOB_STATE_STORE, opt.job_state_store.as_ref())?
+        .with_flag(SCHEDULER_JOB_STATE_PATH, opt.job_state_path.as_ref())?
+        .with_flag(SCHEDULER_CATALOG_STORE, opt.catalog_store.as_ref())?
+        .with_flag(SCHEDULER_CATALOG_PATH, opt.catalog_path.as_ref())?
+        .with_flag(SCHEDULER_RESULT_CACHE_TTL_MS, opt.result_cache_ttl_ms)?
+        .with_flag(SCHEDULER_STAGE_REUSE_TTL_MS, opt.stage_reuse_ttl_ms)?
+        .with_flag(EXECUTOR_SHUFFLE_STORAGE, opt.shuffle_storage.as_ref())?
+        .with_flag(SCHEDULER_SESSION_TTL_MS, opt.session_ttl_ms)?
+        .with_flag(SCHEDULER_JOB_HISTORY_SIZE, opt.job_history_size)?
+        .with_flag(EXECUTOR_ALLOWED_PATHS, opt.allowed_paths.as_ref())?
+        .with_flag(SCHEDULER_ADMINS, opt.admins.as_ref())?
+        .with_flag(SCHEDULER_UI_PORT, opt.ui_port)?
+        .with_flag(LOG_LEVEL, opt.log_level.as_ref())?
+        .with_flag(TRACING_ENDPOINT, opt.tracing_endpoint.as_ref())?;
+    Ok(config)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::from_args();
+    let settings = load_config(&opt)?;
+
+    // RUST_LOG takes precedence over the log level setting
+    let log_level: String = settings.require(LOG_LEVEL)?;
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&log_level)).init();
+
+    if let Some(endpoint) = settings.get(TRACING_ENDPOINT) {
+        info!("Exporting spans tracing_endpoint={}", endpoint);
+        set_span_exporter(Arc::new(ZipkinExporter::new(
+            endpoint,
+            "ballista-scheduler",
+        )));
+    }
+
+    connection_pool().configure(ClientOptions::from_config(&settings)?);
+
+    let mode = match settings.get(DISCOVERY_MODE) {
+        Some("k8s") => DiscoveryMode::Kubernetes(KubernetesConfig {
+            namespace: settings.require(DISCOVERY_K8S_NAMESPACE)?,
+            service: settings.require(DISCOVERY_K8S_SERVICE)?,
+            source: match settings.get(DISCOVERY_K8S_DNS_PORT_NAME) {
+                Some(port_name) => KubernetesSource::Dns {
+                    port_name: port_name.to_owned(),
+                },
+                None => KubernetesSource::ApiServer {
+                    label_selector: settings.require(DISCOVERY_K8S_LABEL_SELECTOR)?,
+                },
+            },
+            refresh_interval: Duration::from_secs(settings.require(DISCOVERY_K8S_REFRESH_SECS)?),
+        }),
+        Some("etcd") => DiscoveryMode::Etcd,
+        Some("registry") => {
+            let registry: String = settings.require(DISCOVERY_REGISTRY)?;
+            let host_port: Vec<&str> = registry.split(':').collect();
+            if host_port.len() != 2 {
+                return Err("--registry must be of the form host:port".into());
+            }
+            DiscoveryMode::Registry {
+                host: host_port[0].to_owned(),
+                port: host_port[1].parse()?,
+            }
+        }
+        _ => return Err("--mode must be one of `etcd`, `k8s` or `registry`".into()),
+    };
+
+    let bind_host: String = settings.require(SCHEDULER_BIND_HOST)?;
+    let port: usize = settings.require(SCHEDULER_PORT)?;
+    let etcd_urls: String = settings.require(DISCOVERY_ETCD_URLS)?;
+    let config = ExecutorConfig::new(mode, &bind_host, port, &etcd_urls);
+    let config = match settings.get(AUTH_TOKEN) {
+        Some(auth_token) => config.with_auth_token(auth_token),
+        None => config,
+    };
+    let config = match settings.get(EXECUTOR_SHUFFLE_STORAGE) {
+        Some(root) => config.with_shuffle_storage(root),
+        None => config,
+    };
+    let policies = settings.job_policies()?;
+    let config = config
+        .with_retry_policy(policies.retry)
+        .with_placement_policy(policies.placement)
+        .with_scheduling_policy(policies.scheduling);
+    let job_config = JobConfig::default()
+        .with_batch_size(settings.require(JOB_BATCH_SIZE)?)
+        .with_skew_threshold(
+            settings.require(JOB_SKEW_THRESHOLD_BYTES)?,
+            settings.require(JOB_SKEW_FACTOR)?,
+        )
+        .with_target_partition_bytes(settings.require(JOB_TARGET_PARTITION_BYTES)?)
+        .with_adaptive_execution(settings.require(JOB_ADAPTIVE)?)
+        .with_work_stealing(settings.require(JOB_WORK_STEALING)?);
+    let job_config = match settings.get_as(JOB_TARGET_PARTITIONS)? {
+        Some(n) => job_config.with_target_partitions(n),
+        None => job_config,
+    };
+    let job_config = match settings.get_as(JOB_TIMEOUT_MS)? {
+        Some(ms) => job_config.with_timeout(Duration::from_millis(ms)),
+        None => job_config,
+    };
+    let job_config = match settings.get_as(JOB_TASK_TIMEOUT_MS)? {
+        Some(ms) => job_config.with_task_timeout(Duration::from_millis(ms)),
+        None => job_config,
+    };
+    let config = config.with_job_config(job_config);
+    let config = match settings.get_as(SCHEDULER_STAGE_REUSE_TTL_MS)? {
+        Some(ms) => config.with_stage_reuse(Duration::from_millis(ms)),
+        None => config,
+    };
+
+    let job_state_store: Arc<dyn JobStateStore> = match settings.get(SCHEDULER_JOB_STATE_STORE) {
+        Some("memory") => Arc::new(InMemoryJobStateStore::default()),
+        Some("sled") => Arc::new(SledJobStateStore::open(
+            &settings.require::<String>(SCHEDULER_JOB_STATE_PATH)?,
+        )?),
+        Some("etcd") => Arc::new(EtcdJobStateStore::new(&etcd_urls, "default")),
+        _ => return Err("--job-state-store must be one of `memory`, `sled` or `etcd`".into()),
+    };
+
+    let table_store: Arc<dyn TableStore> = match settings.get(SCHEDULER_CATALOG_STORE) {
+        Some("memory") => Arc::new(InMemoryTableStore::default()),
+        Some("sled") => Arc::new(SledTableStore::open(
+            &settings.require::<String>(SCHEDULER_CATALOG_PATH)?,
+        )?),
+        Some("etcd") => Arc::new(EtcdTableStore::new(&etcd_urls, "default")),
+        _ => return Err("--catalog-store must be one of `memory`, `sled` or `etcd`".into()),
+    };
+
+    info!("Running with settings: {}", settings);
+    info!("Running with config: {:?}", config);
+
+    let session_ttl = Duration::from_millis(settings.require(SCHEDULER_SESSION_TTL_MS)?);
+    let admins: String = settings.require(SCHEDULER_ADMINS)?;
+    let mut scheduler = SchedulerServer::new(config)
+        .with_admins(admins.split(',').map(String::from).collect())
+        .with_job_state_store(job_state_store)
+        .with_table_store(table_store)
+        .with_session_ttl(session_ttl)
+        .with_job_history(settings.require(SCHEDULER_JOB_HISTORY_SIZE)?);
+    if let Some(ttl_ms) = settings.get_as::<u64>(SCHEDULER_RESULT_CACHE_TTL_MS)? {
+        scheduler = scheduler.with_result_cache(Duration::from_millis(ttl_ms));
+    }
+    if let Some(paths) = settings.get(EXECUTOR_ALLOWED_PATHS) {
+        scheduler = scheduler.with_plan_validator(
+            PlanValidator::new().with_allowed_paths(paths.split(',').map(String::from).collect()),
+        );
+    }
+    let resumed = scheduler.recover().await?;
+    info!("Resumed jobs count={}", resumed);
+
+    // close idle sessions in the background, since clients may go away without closing them
+    let sessions = scheduler.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::delay_for(session_ttl / 2).await;
+            sessions.expire_sessions();
+        }
+    });
+
+    if let Some(ui_port) = settings.get_as::<usize>(SCHEDULER_UI_PORT)? {
+        let ui_addr = format!("{}:{}", bind_host, ui_port).parse()?;
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_ui(ui_addr, scheduler).await {
+                error!("Web UI server failed: {:?}", e);
+            }
+        });
+    }
+
+    let addr = format!("{}:{}", bind_host, port).parse()?;
+    // only the processes of the cluster authenticate with the scheduler, as the cluster identity,
+    // and the identity that any other client claims is discarded
+    let server = match settings.get(AUTH_TOKEN) {
+        Some(auth_token) => {
+            let sessions = SessionManager::new(Arc::new(
+                StaticTokenAuthenticator::new().with_token(auth_token, CLUSTER_IDENTITY),
+            ));
+            SchedulerGrpcServer::with_interceptor(scheduler, sessions.interceptor())
+        }
+        None => SchedulerGrpcServer::with_interceptor(scheduler, anonymous_interceptor()),
+    };
+    info!(
+        "Ballista v{} Rust Scheduler listening on {:?}",
+        BALLISTA_VERSION, addr
+    );
+    Server::builder().add_service(server).serve(addr).await?;
+    Ok(())
+}
@@ -0,0 +1,92 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runs a scheduler and executors on localhost, for local development without etcd or
+//! Kubernetes. The cluster runs until the process is interrupted.
+
+use std::path::PathBuf;
+
+use ballista::distributed::standalone::{
+    bin_dir, StandaloneCluster, StandaloneConfig, STANDALONE_HOST,
+};
+use ballista::BALLISTA_VERSION;
+
+use log::info;
+use structopt::StructOpt;
+
+/// Local Ballista cluster of a scheduler and executors
+#[derive(StructOpt, Debug)]
+#[structopt(name = "ballista-standalone")]
+struct Opt {
+    /// number of executors
+    #[structopt(short, long, default_value = "2")]
+    executors: usize,
+
+    /// port of the scheduler
+    #[structopt(long, default_value = "50050")]
+    scheduler_port: usize,
+
+    /// port of the first executor, with the other executors on the ports that follow it
+    #[structopt(long, default_value = "50051")]
+    executor_port: usize,
+
+    /// number of tasks that each executor runs concurrently
+    #[structopt(long, default_value = "2")]
+    concurrent_tasks: usize,
+
+    /// run the executors and the scheduler as child processes rather than in this process
+    #[structopt(long)]
+    processes: bool,
+
+    /// directory of the `executor` and `scheduler` binaries when running child processes,
+    /// the directory of this binary when not set
+    #[structopt(long)]
+    bin_dir: Option<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::from_args();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = StandaloneConfig::new(opt.executors)
+        .with_ports(opt.scheduler_port, opt.executor_port)
+        .with_concurrent_tasks(opt.concurrent_tasks);
+    let cluster = if opt.processes {
+        let bin_dir = match &opt.bin_dir {
+            Some(bin_dir) => bin_dir.clone(),
+            None => bin_dir()?,
+        };
+        StandaloneCluster::spawn(config, &bin_dir).await?
+    } else {
+        StandaloneCluster::start(config).await?
+    };
+    info!(
+        "Ballista v{} standalone cluster of {} executors on ports {:?}, scheduler on {}:{}",
+        BALLISTA_VERSION,
+        opt.executors,
+        cluster.config().executor_ports(),
+        STANDALONE_HOST,
+        cluster.scheduler_port()
+    );
+    info!(
+        "Run queries with: ballista-cli --host {} --port {}",
+        STANDALONE_HOST, opt.executor_port
+    );
+
+    tokio::signal::ctrl_c().await?;
+    info!("Interrupted, stopping the cluster");
+    cluster.shutdown();
+    Ok(())
+}
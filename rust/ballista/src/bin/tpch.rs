@@ -0,0 +1,140 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generates TPC-H data and runs the TPC-H queries against a Ballista cluster, and optionally
+//! against DataFusion for comparison, writing a JSON report of the time of each query.
+
+use std::fs;
+use std::path::Path;
+
+use ballista::benchmarks::tpch::parse_queries;
+use ballista::benchmarks::tpch_gen::TpchGenerator;
+use ballista::benchmarks::{run_tpch_ballista, run_tpch_datafusion, tpch_report, TpchConfig};
+use ballista::error::{ballista_error, Result};
+
+use structopt::StructOpt;
+
+/// TPC-H benchmark for Ballista
+#[derive(StructOpt, Debug)]
+#[structopt(name = "ballista-tpch")]
+enum Opt {
+    /// generate the TPC-H tables as pipe-delimited files
+    Generate {
+        /// directory to write a directory of files for each table to
+        #[structopt(short, long)]
+        path: String,
+
+        /// scale factor, where 1 is roughly 1 GB of data
+        #[structopt(short, long, default_value = "1")]
+        scale_factor: f64,
+
+        /// number of files that each table is split into
+        #[structopt(long, default_value = "1")]
+        partitions: usize,
+    },
+    /// run the TPC-H queries and report their times
+    Run {
+        /// directory with a directory of files for each table
+        #[structopt(short, long)]
+        path: String,
+
+        /// format of the tables, `csv` for pipe-delimited files or `parquet`
+        #[structopt(short, long, default_value = "csv")]
+        format: String,
+
+        /// host of the executor to submit the queries to
+        #[structopt(long, default_value = "localhost")]
+        host: String,
+
+        /// port of the executor to submit the queries to
+        #[structopt(long, default_value = "50051")]
+        port: usize,
+
+        /// queries to run, such as `1,3,5-7`, all queries when not set
+        #[structopt(short, long, default_value = "")]
+        queries: String,
+
+        /// number of times that each query is run
+        #[structopt(short, long, default_value = "3")]
+        iterations: usize,
+
+        /// also run the queries on DataFusion in this process
+        #[structopt(long)]
+        datafusion: bool,
+
+        /// only run the queries on DataFusion, without a Ballista cluster
+        #[structopt(long)]
+        datafusion_only: bool,
+
+        /// scale factor of the data, which is recorded in the report
+        #[structopt(long)]
+        scale_factor: Option<f64>,
+
+        /// file to write the JSON report to, standard output when not set
+        #[structopt(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    match Opt::from_args() {
+        Opt::Generate {
+            path,
+            scale_factor,
+            partitions,
+        } => {
+            let counts = TpchGenerator::new(scale_factor)
+                .with_partitions(partitions)
+                .generate(Path::new(&path))?;
+            for (table, rows) in counts {
+                println!("{}: {} rows", table, rows);
+            }
+        }
+        Opt::Run {
+            path,
+            format,
+            host,
+            port,
+            queries,
+            iterations,
+            datafusion,
+            datafusion_only,
+            scale_factor,
+            output,
+        } => {
+            let config = TpchConfig::new(&path)
+                .with_format(&format)
+                .with_queries(parse_queries(&queries)?)
+                .with_iterations(iterations);
+            let mut results = vec![];
+            if !datafusion_only {
+                results.extend(run_tpch_ballista(&config, &host, port).await?);
+            }
+            if datafusion || datafusion_only {
+                results.extend(run_tpch_datafusion(&config)?);
+            }
+            let report = tpch_report(&config, scale_factor, &results);
+            let report = serde_json::to_string_pretty(&report)
+                .map_err(|e| ballista_error(&format!("{:?}", e)))?;
+            match output {
+                Some(output) => fs::write(output, report)?,
+                None => println!("{}", report),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,961 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Configuration of executors, schedulers, and clients.
+//!
+//! Settings are named with dotted keys, such as `executor.port`, and are loaded from these
+//! sources, each taking precedence over the ones before it:
+//!
+//! 1. the defaults of the settings
+//! 2. a TOML or YAML file, in which the part of a key before a dot is a table, e.g.
+//!    `port = 50051` in an `[executor]` table
+//! 3. environment variables named after the keys, e.g. `BALLISTA_EXECUTOR_PORT`
+//! 4. command-line flags
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::distributed::placement::{LocalityFirstPlacement, PlacementPolicy, RoundRobinPlacement};
+use crate::distributed::scheduler::RetryPolicy;
+use crate::distributed::scheduling::{
+    FairScheduling, FifoScheduling, SchedulingPolicy, TenantPolicy,
+};
+use crate::error::{ballista_error, Result};
+
+/// Environment variable naming the configuration file, when none is given on the command line
+pub const CONFIG_FILE_ENV: &str = "BALLISTA_CONFIG_FILE";
+
+/// Prefix of the environment variables that settings are read from
+const ENV_PREFIX: &str = "BALLISTA_";
+
+pub const LOG_LEVEL: &str = "log_level";
+pub const TRACING_ENDPOINT: &str = "tracing.endpoint";
+pub const AUTH_TOKEN: &str = "auth_token";
+pub const TLS_CERT: &str = "tls.cert";
+pub const TLS_KEY: &str = "tls.key";
+pub const TLS_CA_CERT: &str = "tls.ca_cert";
+pub const TLS_DOMAIN_NAME: &str = "tls.domain_name";
+pub const TLS_CLIENT_AUTH: &str = "tls.client_auth";
+pub const DISCOVERY_MODE: &str = "discovery.mode";
+pub const DISCOVERY_ETCD_URLS: &str = "discovery.etcd_urls";
+pub const DISCOVERY_REGISTRY: &str = "discovery.registry";
+pub const DISCOVERY_HEARTBEAT_TIMEOUT_SECS: &str = "discovery.heartbeat_timeout_secs";
+pub const DISCOVERY_K8S_NAMESPACE: &str = "discovery.k8s_namespace";
+pub const DISCOVERY_K8S_SERVICE: &str = "discovery.k8s_service";
+pub const DISCOVERY_K8S_LABEL_SELECTOR: &str = "discovery.k8s_label_selector";
+pub const DISCOVERY_K8S_DNS_PORT_NAME: &str = "discovery.k8s_dns_port_name";
+pub const DISCOVERY_K8S_REFRESH_SECS: &str = "discovery.k8s_refresh_secs";
+pub const EXECUTOR_BIND_HOST: &str = "executor.bind_host";
+pub const EXECUTOR_EXTERNAL_HOST: &str = "executor.external_host";
+pub const EXECUTOR_PORT: &str = "executor.port";
+pub const EXECUTOR_CONCURRENT_TASKS: &str = "executor.concurrent_tasks";
+pub const EXECUTOR_QUEUE_DEPTH: &str = "executor.queue_depth";
+pub const EXECUTOR_CORES: &str = "executor.cores";
+pub const EXECUTOR_TASK_PARALLELISM: &str = "executor.task_parallelism";
+pub const EXECUTOR_MEMORY_BYTES: &str = "executor.memory_bytes";
+pub const EXECUTOR_WORK_DIR: &str = "executor.work_dir";
+pub const EXECUTOR_SHUFFLE_MEMORY_BUDGET: &str = "executor.shuffle_memory_budget";
+pub const EXECUTOR_OPERATOR_MEMORY_BUDGET: &str = "executor.operator_memory_budget";
+pub const EXECUTOR_SHUFFLE_COMPRESSION: &str = "executor.shuffle_compression";
+pub const EXECUTOR_METRICS_PORT: &str = "executor.metrics_port";
+pub const EXECUTOR_SHUTDOWN_GRACE_PERIOD_MS: &str = "executor.shutdown_grace_period_ms";
+pub const EXECUTOR_SHUFFLE_HANDOFF: &str = "executor.shuffle_handoff";
+pub const EXECUTOR_SHUFFLE_STORAGE: &str = "executor.shuffle_storage";
+pub const EXECUTOR_FAULT_INJECTION: &str = "executor.fault_injection";
+pub const EXECUTOR_ALLOWED_PATHS: &str = "executor.allowed_paths";
+pub const EXECUTOR_MAX_PLAN_DEPTH: &str = "executor.max_plan_depth";
+pub const EXECUTOR_MAX_PLAN_PARTITIONS: &str = "executor.max_plan_partitions";
+pub const EXECUTOR_AUDIT_LOG: &str = "executor.audit_log";
+pub const EXECUTOR_AUDIT_FORWARDERS: &str = "executor.audit_forwarders";
+pub const EXECUTOR_ADMINS: &str = "executor.admins";
+pub const EXECUTOR_TASK_STATUS_TTL_SECS: &str = "executor.task_status_ttl_secs";
+pub const EXECUTOR_MAX_TASK_STATUSES: &str = "executor.max_task_statuses";
+pub const GRPC_MAX_MESSAGE_SIZE: &str = "grpc.max_message_size";
+pub const GRPC_STREAM_WINDOW_SIZE: &str = "grpc.stream_window_size";
+pub const GRPC_CONNECTION_WINDOW_SIZE: &str = "grpc.connection_window_size";
+pub const SCHEDULER_BIND_HOST: &str = "scheduler.bind_host";
+pub const SCHEDULER_PORT: &str = "scheduler.port";
+pub const SCHEDULER_JOB_STATE_STORE: &str = "scheduler.job_state_store";
+pub const SCHEDULER_JOB_STATE_PATH: &str = "scheduler.job_state_path";
+pub const SCHEDULER_CATALOG_STORE: &str = "scheduler.catalog_store";
+pub const SCHEDULER_CATALOG_PATH: &str = "scheduler.catalog_path";
+pub const SCHEDULER_RESULT_CACHE_TTL_MS: &str = "scheduler.result_cache_ttl_ms";
+pub const SCHEDULER_STAGE_REUSE_TTL_MS: &str = "scheduler.stage_reuse_ttl_ms";
+pub const SCHEDULER_SESSION_TTL_MS: &str = "scheduler.session_ttl_ms";
+pub const SCHEDULER_JOB_HISTORY_SIZE: &str = "scheduler.job_history_size";
+pub const SCHEDULER_UI_PORT: &str = "scheduler.ui_port";
+pub const SCHEDULER_ADMINS: &str = "scheduler.admins";
+pub const JOB_BATCH_SIZE: &str = "job.batch_size";
+pub const JOB_TARGET_PARTITIONS: &str = "job.target_partitions";
+pub const JOB_ADAPTIVE: &str = "job.adaptive";
+pub const JOB_WORK_STEALING: &str = "job.work_stealing";
+pub const JOB_SKEW_THRESHOLD_BYTES: &str = "job.skew_threshold_bytes";
+pub const JOB_SKEW_FACTOR: &str = "job.skew_factor";
+pub const JOB_TARGET_PARTITION_BYTES: &str = "job.target_partition_bytes";
+pub const JOB_TASK_MAX_ATTEMPTS: &str = "job.task_max_attempts";
+pub const JOB_TASK_RETRY_BACKOFF_MS: &str = "job.task_retry_backoff_ms";
+pub const JOB_TASK_MAX_RETRY_BACKOFF_MS: &str = "job.task_max_retry_backoff_ms";
+pub const JOB_TIMEOUT_MS: &str = "job.timeout_ms";
+pub const JOB_TASK_TIMEOUT_MS: &str = "job.task_timeout_ms";
+pub const JOB_PLACEMENT: &str = "job.placement";
+pub const JOB_SCHEDULING: &str = "job.scheduling";
+pub const JOB_TASK_SLOTS: &str = "job.task_slots";
+pub const JOB_TENANTS: &str = "job.tenants";
+pub const JOB_TENANT_MAX_JOBS: &str = "job.tenant_max_jobs";
+pub const CLIENT_HOST: &str = "client.host";
+pub const CLIENT_PORT: &str = "client.port";
+pub const CLIENT_REQUEST_TIMEOUT_MS: &str = "client.request_timeout_ms";
+pub const CLIENT_IDLE_TIMEOUT_SECS: &str = "client.idle_timeout_secs";
+pub const CLIENT_MAX_RETRIES: &str = "client.max_retries";
+pub const CLIENT_RETRY_BACKOFF_MS: &str = "client.retry_backoff_ms";
+
+/// A setting that can be configured, with its default value if it has one
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigEntry {
+    pub key: &'static str,
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl ConfigEntry {
+    /// Name of the environment variable that the setting is read from
+    pub fn env_var(&self) -> String {
+        format!(
+            "{}{}",
+            ENV_PREFIX,
+            self.key.replace('.', "_").to_uppercase()
+        )
+    }
+}
+
+const fn entry(
+    key: &'static str,
+    default: Option<&'static str>,
+    description: &'static str,
+) -> ConfigEntry {
+    ConfigEntry {
+        key,
+        default,
+        description,
+    }
+}
+
+/// All the settings that can be configured
+pub const ENTRIES: &[ConfigEntry] = &[
+    entry(
+        LOG_LEVEL,
+        Some("info"),
+        "Log level or filter, e.g. `info` or `ballista::distributed=debug`",
+    ),
+    entry(
+        TRACING_ENDPOINT,
+        None,
+        "URL of a Zipkin-compatible collector that spans are exported to, such as \
+        `http://jaeger:9411/api/v2/spans`, or unset to not export spans",
+    ),
+    entry(
+        AUTH_TOKEN,
+        None,
+        "Shared token that clients and executors authenticate with",
+    ),
+    entry(TLS_CERT, None, "PEM-encoded certificate to serve TLS with"),
+    entry(
+        TLS_KEY,
+        None,
+        "PEM-encoded private key for the TLS certificate",
+    ),
+    entry(
+        TLS_CA_CERT,
+        None,
+        "PEM-encoded CA certificate to verify peers with",
+    ),
+    entry(
+        TLS_DOMAIN_NAME,
+        None,
+        "Domain name to verify the certificates of peers against",
+    ),
+    entry(
+        TLS_CLIENT_AUTH,
+        Some("false"),
+        "Whether clients must present a certificate signed by the CA certificate",
+    ),
+    entry(
+        DISCOVERY_MODE,
+        None,
+        "How executors are found: `etcd`, `k8s` or `registry`, or standalone when not set",
+    ),
+    entry(
+        DISCOVERY_ETCD_URLS,
+        Some("localhost:2379"),
+        "etcd urls when the discovery mode is `etcd`",
+    ),
+    entry(
+        DISCOVERY_REGISTRY,
+        None,
+        "host:port of the registry executor when the discovery mode is `registry`",
+    ),
+    entry(
+        DISCOVERY_HEARTBEAT_TIMEOUT_SECS,
+        Some("15"),
+        "Seconds without a heartbeat after which an executor is removed from the registry",
+    ),
+    entry(
+        DISCOVERY_K8S_NAMESPACE,
+        Some("default"),
+        "Namespace of the executor pods when the discovery mode is `k8s`",
+    ),
+    entry(
+        DISCOVERY_K8S_SERVICE,
+        Some("ballista"),
+        "Headless service governing the executor pods when the discovery mode is `k8s`",
+    ),
+    entry(
+        DISCOVERY_K8S_LABEL_SELECTOR,
+        Some("ballista-cluster=ballista"),
+        "Label selector for listing the executor pods with the API server when the discovery \
+        mode is `k8s`",
+    ),
+    entry(
+        DISCOVERY_K8S_DNS_PORT_NAME,
+        None,
+        "Named service port whose DNS SRV records the executor pods are found through when the \
+        discovery mode is `k8s`, or unset to list them with the API server",
+    ),
+    entry(
+        DISCOVERY_K8S_REFRESH_SECS,
+        Some("10"),
+        "Seconds between lookups of the executor pods when the discovery mode is `k8s`",
+    ),
+    entry(
+        EXECUTOR_BIND_HOST,
+        Some("0.0.0.0"),
+        "Host that the executor binds to",
+    ),
+    entry(
+        EXECUTOR_EXTERNAL_HOST,
+        Some("localhost"),
+        "Host that other processes reach the executor on",
+    ),
+    entry(
+        EXECUTOR_PORT,
+        Some("50051"),
+        "Port that the executor listens on",
+    ),
+    entry(
+        EXECUTOR_CONCURRENT_TASKS,
+        Some("1"),
+        "Max number of tasks that the executor runs concurrently",
+    ),
+    entry(
+        EXECUTOR_QUEUE_DEPTH,
+        Some("1024"),
+        "Max number of tasks waiting for a free slot before new tasks are rejected",
+    ),
+    entry(
+        EXECUTOR_CORES,
+        None,
+        "Number of cores that tasks are packed onto, the max number of concurrent tasks when not set",
+    ),
+    entry(
+        EXECUTOR_TASK_PARALLELISM,
+        Some("1"),
+        "Max number of threads that a task uses when other tasks leave cores idle",
+    ),
+    entry(
+        EXECUTOR_MEMORY_BYTES,
+        Some("0"),
+        "Memory available to the executor in bytes, which tasks are packed into, or 0 for no limit",
+    ),
+    entry(
+        EXECUTOR_WORK_DIR,
+        None,
+        "Directory that shuffle partitions are spilled to, a temporary directory when not set",
+    ),
+    entry(
+        EXECUTOR_SHUFFLE_MEMORY_BUDGET,
+        None,
+        "Max number of bytes of shuffle data to hold in memory before spilling to disk",
+    ),
+    entry(
+        EXECUTOR_OPERATOR_MEMORY_BUDGET,
+        None,
+        "Max number of bytes that sorts, aggregates, and joins of running tasks may hold in memory \
+        before spilling to disk",
+    ),
+    entry(
+        EXECUTOR_SHUFFLE_COMPRESSION,
+        Some("none"),
+        "Codec that shuffle partitions are compressed with: `none`, `lz4` or `zstd`",
+    ),
+    entry(
+        EXECUTOR_METRICS_PORT,
+        None,
+        "Port to serve Prometheus metrics on",
+    ),
+    entry(
+        EXECUTOR_SHUTDOWN_GRACE_PERIOD_MS,
+        Some("30000"),
+        "Time in milliseconds that accepted tasks are given to complete when the executor shuts \
+        down before they are cancelled",
+    ),
+    entry(
+        EXECUTOR_SHUFFLE_HANDOFF,
+        None,
+        "host:port of the executor that shuffle partitions are handed off to when the executor \
+        shuts down, which are not handed off when not set",
+    ),
+    entry(
+        EXECUTOR_SHUFFLE_STORAGE,
+        None,
+        "Shared storage that shuffle partitions are written to, such as `s3://bucket/shuffle` or \
+        a directory on an NFS mount, which must be the same for the scheduler and all executors. \
+        Partitions are held by the executors that produce them when not set",
+    ),
+    entry(
+        EXECUTOR_FAULT_INJECTION,
+        None,
+        "Faults that the executor injects for testing, such as \
+        `seed=7,task_failure=0.1,dropped_fetch=0.1,crash=0.01`, which are only injected by \
+        executors built with the `fault-injection` feature",
+    ),
+    entry(
+        EXECUTOR_ALLOWED_PATHS,
+        None,
+        "Comma-separated paths and URIs under which submitted plans may read and write files, \
+        such as `/data,s3://bucket/tables`, and under which the scheduler allows tables to be \
+        registered and created. Plans may access any path when not set",
+    ),
+    entry(
+        EXECUTOR_MAX_PLAN_DEPTH,
+        None,
+        "Maximum depth of the plans of submitted queries and tasks, which is unlimited when \
+        not set",
+    ),
+    entry(
+        EXECUTOR_MAX_PLAN_PARTITIONS,
+        None,
+        "Maximum number of partitions of any operator of the plans of submitted queries and \
+        tasks, which is unlimited when not set",
+    ),
+    entry(
+        EXECUTOR_AUDIT_LOG,
+        None,
+        "Where submitted queries, tasks, and management actions are recorded: `stdout`, \
+        `file:<path>`, or `grpc:<host>:<port>` to forward them to a collecting executor. \
+        Nothing is recorded when not set",
+    ),
+    entry(
+        EXECUTOR_AUDIT_FORWARDERS,
+        Some("cluster"),
+        "Comma-separated identities that may forward audit events to the executor to be \
+        recorded, such as the `cluster` identity of executors that present the shared auth token",
+    ),
+    entry(
+        EXECUTOR_ADMINS,
+        Some("cluster"),
+        "Comma-separated identities that may shut down and drain the executor, manage the tasks \
+        of jobs and store shuffle partitions, such as the `cluster` identity of the processes \
+        that present the shared auth token. Without an auth token every client is `default`",
+    ),
+    entry(
+        EXECUTOR_TASK_STATUS_TTL_SECS,
+        Some("3600"),
+        "Seconds after which the status of a finished task is discarded",
+    ),
+    entry(
+        EXECUTOR_MAX_TASK_STATUSES,
+        Some("10000"),
+        "Max number of task statuses that the executor retains",
+    ),
+    entry(
+        GRPC_MAX_MESSAGE_SIZE,
+        Some("4194304"),
+        "Max size in bytes of the flight data messages that batches are split into",
+    ),
+    entry(
+        GRPC_STREAM_WINDOW_SIZE,
+        None,
+        "Initial HTTP/2 flow control window of each stream in bytes, the gRPC default when not set",
+    ),
+    entry(
+        GRPC_CONNECTION_WINDOW_SIZE,
+        None,
+        "Initial HTTP/2 flow control window of each connection in bytes, the gRPC default when \
+        not set",
+    ),
+    entry(
+        SCHEDULER_BIND_HOST,
+        Some("0.0.0.0"),
+        "Host that the scheduler binds to",
+    ),
+    entry(
+        SCHEDULER_PORT,
+        Some("50050"),
+        "Port that the scheduler listens on",
+    ),
+    entry(
+        SCHEDULER_JOB_STATE_STORE,
+        Some("memory"),
+        "Store that job state is persisted in: `memory`, `sled` or `etcd`",
+    ),
+    entry(
+        SCHEDULER_JOB_STATE_PATH,
+        Some("/tmp/ballista-scheduler"),
+        "Directory of the sled database when the job state store is `sled`",
+    ),
+    entry(
+        SCHEDULER_CATALOG_STORE,
+        Some("memory"),
+        "Store that external table definitions are persisted in: `memory`, `sled` or `etcd`",
+    ),
+    entry(
+        SCHEDULER_CATALOG_PATH,
+        Some("/tmp/ballista-catalog"),
+        "Directory of the sled database when the catalog store is `sled`",
+    ),
+    entry(
+        SCHEDULER_RESULT_CACHE_TTL_MS,
+        None,
+        "Time in milliseconds to serve the results of repeated queries from the results of \
+         earlier jobs, which are not cached when not set",
+    ),
+    entry(
+        SCHEDULER_STAGE_REUSE_TTL_MS,
+        None,
+        "Time in milliseconds that jobs may read the output of stages of earlier jobs with the \
+         same plan rather than running the stages again, which is not shared when not set",
+    ),
+    entry(
+        SCHEDULER_SESSION_TTL_MS,
+        Some("3600000"),
+        "Time in milliseconds after which a client session that has not been used is closed",
+    ),
+    entry(
+        SCHEDULER_JOB_HISTORY_SIZE,
+        Some("1000"),
+        "Max number of finished jobs whose queries and metrics are kept in the job history",
+    ),
+    entry(SCHEDULER_UI_PORT, None, "Port to serve the web UI on"),
+    entry(
+        SCHEDULER_ADMINS,
+        Some("cluster"),
+        "Comma-separated identities that may create and drop external tables, such as the \
+        `cluster` identity of the processes that present the shared auth token. Without an auth \
+        token every client is `default`",
+    ),
+    entry(
+        JOB_BATCH_SIZE,
+        Some("65536"),
+        "Max number of rows in each batch that scans produce",
+    ),
+    entry(
+        JOB_TARGET_PARTITIONS,
+        None,
+        "Number of partitions to redistribute the output of scans into",
+    ),
+    entry(
+        JOB_ADAPTIVE,
+        Some("true"),
+        "Whether stages are adapted to the output of earlier stages",
+    ),
+    entry(
+        JOB_WORK_STEALING,
+        Some("true"),
+        "Whether idle executors take over tasks that are waiting to run on other executors",
+    ),
+    entry(
+        JOB_SKEW_THRESHOLD_BYTES,
+        Some("268435456"),
+        "Size in bytes above which join input partitions may be split",
+    ),
+    entry(
+        JOB_SKEW_FACTOR,
+        Some("5"),
+        "How many times larger than the median a partition must be to be split",
+    ),
+    entry(
+        JOB_TARGET_PARTITION_BYTES,
+        Some("67108864"),
+        "Size in bytes up to which consecutive small partitions are coalesced",
+    ),
+    entry(
+        JOB_TASK_MAX_ATTEMPTS,
+        Some("3"),
+        "Max number of times a task is attempted before the job fails",
+    ),
+    entry(
+        JOB_TASK_RETRY_BACKOFF_MS,
+        Some("500"),
+        "Delay in milliseconds before retrying a failed task, doubled on each retry",
+    ),
+    entry(
+        JOB_TASK_MAX_RETRY_BACKOFF_MS,
+        Some("30000"),
+        "Upper bound in milliseconds on the delay before retrying a failed task",
+    ),
+    entry(
+        JOB_TIMEOUT_MS,
+        None,
+        "Time in milliseconds after which a job is cancelled if it has not completed",
+    ),
+    entry(
+        JOB_TASK_TIMEOUT_MS,
+        None,
+        "Time in milliseconds after which an attempt of a task is aborted if it has not completed",
+    ),
+    entry(
+        JOB_PLACEMENT,
+        Some("locality"),
+        "Task placement policy: `locality` or `round-robin`",
+    ),
+    entry(
+        JOB_SCHEDULING,
+        Some("fifo"),
+        "Policy for sharing the cluster between tenants: `fifo` or `fair`",
+    ),
+    entry(
+        JOB_TASK_SLOTS,
+        Some("16"),
+        "Number of tasks that the cluster runs at a time, which fair scheduling shares between tenants",
+    ),
+    entry(
+        JOB_TENANTS,
+        None,
+        "Weights and max concurrent jobs of tenants for fair scheduling, such as `etl=1/2,dashboards=3/8`",
+    ),
+    entry(
+        JOB_TENANT_MAX_JOBS,
+        Some("4"),
+        "Max number of concurrent jobs of tenants that are not listed in `job.tenants`",
+    ),
+    entry(
+        CLIENT_HOST,
+        Some("localhost"),
+        "Host of the executor that clients send queries to",
+    ),
+    entry(
+        CLIENT_PORT,
+        Some("50051"),
+        "Port of the executor that clients send queries to",
+    ),
+    entry(
+        CLIENT_REQUEST_TIMEOUT_MS,
+        None,
+        "Time to wait for the response to a request to an executor or scheduler, indefinitely \
+        when not set",
+    ),
+    entry(
+        CLIENT_IDLE_TIMEOUT_SECS,
+        Some("300"),
+        "Time after which pooled connections to executors and schedulers that are not used are \
+        closed",
+    ),
+    entry(
+        CLIENT_MAX_RETRIES,
+        Some("3"),
+        "Max number of times that idempotent requests, such as fetching shuffle partitions, are \
+        retried when the executor is unavailable",
+    ),
+    entry(
+        CLIENT_RETRY_BACKOFF_MS,
+        Some("100"),
+        "Delay before the first retry of a request, which doubles with each retry",
+    ),
+];
+
+fn find_entry(key: &str) -> Result<&'static ConfigEntry> {
+    ENTRIES
+        .iter()
+        .find(|e| e.key == key)
+        .ok_or_else(|| ballista_error(&format!("Unknown setting '{}'", key)))
+}
+
+/// Name of the environment variable that a setting is read from
+pub fn env_var(key: &str) -> Result<String> {
+    Ok(find_entry(key)?.env_var())
+}
+
+/// Settings of an executor, scheduler, or client
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BallistaConfig {
+    settings: BTreeMap<String, String>,
+}
+
+impl BallistaConfig {
+    /// Create a configuration with the default of each setting
+    pub fn new() -> Self {
+        let settings = ENTRIES
+            .iter()
+            .filter_map(|e| e.default.map(|d| (e.key.to_owned(), d.to_owned())))
+            .collect();
+        Self { settings }
+    }
+
+    /// Load the defaults, then the settings in the configuration file, if one is given or is
+    /// named by `BALLISTA_CONFIG_FILE`, then the settings in environment variables
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let path = path
+            .map(|p| p.to_owned())
+            .or_else(|| env::var(CONFIG_FILE_ENV).ok());
+        let config = match path {
+            Some(path) => Self::new().with_file(&path)?,
+            None => Self::new(),
+        };
+        config.with_env_vars(env::vars())
+    }
+
+    /// Read settings from a TOML or YAML file, depending on its extension
+    pub fn with_file(self, path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("toml") => self.with_toml(&text),
+            Some("yaml") | Some("yml") => self.with_yaml(&text),
+            _ => Err(ballista_error(&format!(
+                "Configuration file {} must be a .toml, .yaml or .yml file",
+                path
+            ))),
+        }
+    }
+
+    pub fn with_toml(mut self, text: &str) -> Result<Self> {
+        let value: toml::Value = text
+            .parse()
+            .map_err(|e| ballista_error(&format!("Invalid TOML configuration: {}", e)))?;
+        let mut settings = vec![];
+        flatten_toml("", &value, &mut settings)?;
+        for (key, value) in settings {
+            self.set(&key, &value)?;
+        }
+        Ok(self)
+    }
+
+    pub fn with_yaml(mut self, text: &str) -> Result<Self> {
+        let value: serde_yaml::Value = serde_yaml::from_str(text)
+            .map_err(|e| ballista_error(&format!("Invalid YAML configuration: {}", e)))?;
+        let mut settings = vec![];
+        flatten_yaml("", &value, &mut settings)?;
+        for (key, value) in settings {
+            self.set(&key, &value)?;
+        }
+        Ok(self)
+    }
+
+    /// Read settings from environment variables named after their keys. Variables that do not
+    /// name a setting are ignored.
+    pub fn with_env_vars<I: IntoIterator<Item = (String, String)>>(
+        mut self,
+        vars: I,
+    ) -> Result<Self> {
+        let vars: BTreeMap<String, String> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        for entry in ENTRIES {
+            if let Some(value) = vars.get(&entry.env_var()) {
+                self.set(entry.key, value)?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Override a setting with the value of a command-line flag, if the flag was given
+    pub fn with_flag<T: ToString>(mut self, key: &str, value: Option<T>) -> Result<Self> {
+        if let Some(value) = value {
+            self.set(key, &value.to_string())?;
+        }
+        Ok(self)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let entry = find_entry(key)?;
+        self.settings.insert(entry.key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.settings.get(key).map(|v| v.as_str())
+    }
+
+    /// Parse a setting, or return `None` if it is not set
+    pub fn get_as<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get(key)
+            .map(|v| {
+                v.parse().map_err(|e| {
+                    ballista_error(&format!(
+                        "Invalid value '{}' for setting '{}': {}",
+                        v, key, e
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// Parse a setting that must be set, either because it has a default or because it is
+    /// required in the context that it is read in
+    pub fn require<T>(&self, key: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get_as(key)?
+            .ok_or_else(|| ballista_error(&format!("Setting '{}' must be specified", key)))
+    }
+
+    /// Environment variables that configure the settings that differ from their defaults,
+    /// such as for passing the settings on to processes that are started elsewhere
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let defaults = Self::new();
+        ENTRIES
+            .iter()
+            .filter_map(|entry| {
+                let value = self.get(entry.key)?;
+                if defaults.get(entry.key) == Some(value) {
+                    return None;
+                }
+                Some((entry.env_var(), value.to_owned()))
+            })
+            .collect()
+    }
+
+    /// The policies for retrying, placing, and scheduling the tasks of jobs, which fails when
+    /// a policy is not one that is known
+    pub fn job_policies(&self) -> Result<JobPolicies> {
+        let retry = RetryPolicy::new(
+            self.require(JOB_TASK_MAX_ATTEMPTS)?,
+            Duration::from_millis(self.require(JOB_TASK_RETRY_BACKOFF_MS)?),
+            Duration::from_millis(self.require(JOB_TASK_MAX_RETRY_BACKOFF_MS)?),
+        );
+        let placement: Arc<dyn PlacementPolicy> = match self.get(JOB_PLACEMENT) {
+            Some("locality") => Arc::new(LocalityFirstPlacement::default()),
+            Some("round-robin") => Arc::new(RoundRobinPlacement::default()),
+            placement => {
+                return Err(ballista_error(&format!(
+                    "Setting '{}' must be `locality` or `round-robin`, not '{}'",
+                    JOB_PLACEMENT,
+                    placement.unwrap_or("")
+                )))
+            }
+        };
+        let scheduling: Arc<dyn SchedulingPolicy> = match self.get(JOB_SCHEDULING) {
+            Some("fifo") => Arc::new(FifoScheduling::default()),
+            Some("fair") => Arc::new(
+                FairScheduling::new(self.require(JOB_TASK_SLOTS)?)
+                    .with_default_policy(TenantPolicy {
+                        weight: 1,
+                        max_concurrent_jobs: self.require(JOB_TENANT_MAX_JOBS)?,
+                    })
+                    .with_tenants(self.get(JOB_TENANTS).unwrap_or(""))?,
+            ),
+            scheduling => {
+                return Err(ballista_error(&format!(
+                    "Setting '{}' must be `fifo` or `fair`, not '{}'",
+                    JOB_SCHEDULING,
+                    scheduling.unwrap_or("")
+                )))
+            }
+        };
+        Ok(JobPolicies {
+            retry,
+            placement,
+            scheduling,
+        })
+    }
+}
+
+/// How the tasks of jobs are retried, placed on executors, and shared between tenants
+pub struct JobPolicies {
+    pub retry: RetryPolicy,
+    pub placement: Arc<dyn PlacementPolicy>,
+    pub scheduling: Arc<dyn SchedulingPolicy>,
+}
+
+impl fmt::Display for BallistaConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let settings: Vec<String> = self
+            .settings
+            .iter()
+            .map(|(key, value)| match key.as_str() {
+                // keep secrets out of logs
+                AUTH_TOKEN => format!("{}=***", key),
+                _ => format!("{}={}", key, value),
+            })
+            .collect();
+        write!(f, "{}", settings.join(", "))
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn flatten_toml(prefix: &str, value: &toml::Value, out: &mut Vec<(String, String)>) -> Result<()> {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                flatten_toml(&join_key(prefix, key), value, out)?;
+            }
+        }
+        toml::Value::String(s) => out.push((prefix.to_owned(), s.clone())),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+            out.push((prefix.to_owned(), value.to_string()))
+        }
+        _ => {
+            return Err(ballista_error(&format!(
+                "Setting '{}' must be a string, number or boolean",
+                prefix
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn flatten_yaml(
+    prefix: &str,
+    value: &serde_yaml::Value,
+    out: &mut Vec<(String, String)>,
+) -> Result<()> {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                let key = key
+                    .as_str()
+                    .ok_or_else(|| ballista_error("Setting names must be strings"))?;
+                flatten_yaml(&join_key(prefix, key), value, out)?;
+            }
+        }
+        // an empty document or setting leaves the defaults in place
+        serde_yaml::Value::Null => {}
+        serde_yaml::Value::String(s) => out.push((prefix.to_owned(), s.clone())),
+        serde_yaml::Value::Number(n) => out.push((prefix.to_owned(), n.to_string())),
+        serde_yaml::Value::Bool(b) => out.push((prefix.to_owned(), b.to_string())),
+        _ => {
+            return Err(ballista_error(&format!(
+                "Setting '{}' must be a string, number or boolean",
+                prefix
+            )))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_of_sources() -> Result<()> {
+        let config = BallistaConfig::new().with_toml(
+            r#"
+            auth_token = "secret"
+
+            [executor]
+            port = 50060
+            concurrent_tasks = 8
+            "#,
+        )?;
+        assert_eq!(Some("secret"), config.get(AUTH_TOKEN));
+        assert_eq!(50060, config.require::<usize>(EXECUTOR_PORT)?);
+
+        let env = vec![
+            (
+                "BALLISTA_EXECUTOR_CONCURRENT_TASKS".to_owned(),
+                "16".to_owned(),
+            ),
+            ("BALLISTA_UNRELATED".to_owned(), "ignored".to_owned()),
+            ("HOME".to_owned(), "/root".to_owned()),
+        ];
+        let config = config
+            .with_env_vars(env)?
+            .with_flag(EXECUTOR_PORT, Some(50070))?
+            .with_flag::<usize>(EXECUTOR_QUEUE_DEPTH, None)?;
+
+        assert_eq!(50070, config.require::<usize>(EXECUTOR_PORT)?);
+        assert_eq!(16, config.require::<usize>(EXECUTOR_CONCURRENT_TASKS)?);
+        // defaults remain for settings that no source set
+        assert_eq!(1024, config.require::<usize>(EXECUTOR_QUEUE_DEPTH)?);
+        assert_eq!(None, config.get_as::<usize>(JOB_TARGET_PARTITIONS)?);
+        assert!(config.require::<String>(DISCOVERY_MODE).is_err());
+        assert!(!config.to_string().contains("secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn env_vars_of_changed_settings() -> Result<()> {
+        let config = BallistaConfig::new()
+            .with_flag(EXECUTOR_PORT, Some(50060))?
+            .with_flag(EXECUTOR_QUEUE_DEPTH, Some(1024))?;
+        assert_eq!(
+            vec![("BALLISTA_EXECUTOR_PORT".to_owned(), "50060".to_owned())],
+            config.env_vars()
+        );
+        let config = BallistaConfig::new().with_env_vars(config.env_vars())?;
+        assert_eq!(50060, config.require::<usize>(EXECUTOR_PORT)?);
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_configuration() -> Result<()> {
+        let config = BallistaConfig::new().with_yaml(
+            r#"
+            scheduler:
+              port: 50055
+              job_state_store: sled
+            job:
+              adaptive: false
+            "#,
+        )?;
+        assert_eq!(50055, config.require::<usize>(SCHEDULER_PORT)?);
+        assert_eq!(Some("sled"), config.get(SCHEDULER_JOB_STATE_STORE));
+        assert!(!config.require::<bool>(JOB_ADAPTIVE)?);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_settings() {
+        assert!(BallistaConfig::new()
+            .with_toml("[executor]\nprot = 1")
+            .is_err());
+        let config = BallistaConfig::new()
+            .with_flag(EXECUTOR_PORT, Some("not a port"))
+            .unwrap();
+        assert!(config.require::<usize>(EXECUTOR_PORT).is_err());
+    }
+
+    #[test]
+    fn reject_unknown_job_policies() -> Result<()> {
+        assert!(BallistaConfig::new().job_policies().is_ok());
+        let fair = BallistaConfig::new().with_flag(JOB_SCHEDULING, Some("fair"))?;
+        assert!(fair.job_policies().is_ok());
+
+        let config = BallistaConfig::new().with_flag(JOB_PLACEMENT, Some("random"))?;
+        assert!(config.job_policies().is_err());
+        let config = BallistaConfig::new().with_flag(JOB_SCHEDULING, Some("lifo"))?;
+        assert!(config.job_policies().is_err());
+        Ok(())
+    }
+}
@@ -16,23 +16,50 @@
 
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use crate::arrow::array::{StringArray, UInt32Array};
 use crate::arrow::datatypes::{DataType, Schema};
 use crate::arrow::record_batch::RecordBatch;
+use crate::config::{self as ballista_config, BallistaConfig};
 pub use crate::datafusion::datasource::csv::CsvReadOptions;
-use crate::datafusion::datasource::parquet::ParquetTable;
-use crate::datafusion::datasource::TableProvider;
 use crate::datafusion::logicalplan::Operator;
 use crate::datafusion::logicalplan::ScalarValue;
 use crate::datafusion::logicalplan::{Expr, FunctionMeta, LogicalPlan, LogicalPlanBuilder};
 use crate::datafusion::optimizer::utils::exprlist_to_fields;
 use crate::datafusion::sql::parser::{DFASTNode, DFParser};
 use crate::datafusion::sql::planner::{SchemaProvider, SqlToRel};
-use crate::distributed::client;
+use crate::distributed::catalog::table_names;
+use crate::distributed::client::{self, FlightBatchStream};
+use crate::distributed::connection_pool::{connection_pool, ClientOptions};
+use crate::distributed::explain::Explanation;
+use crate::distributed::scheduler::QuerySettings;
+use crate::distributed::table_store::TableDefinition;
+use crate::distributed::tls::TlsConfig;
 use crate::error::{BallistaError, Result};
+use crate::execution::expressions::{
+    encode_predicate, parameter, CaseParts, ScalarFunction, BETWEEN_FUNCTION_NAME,
+    CASE_FUNCTION_NAME, COALESCE_FUNCTION_NAME, IN_LIST_FUNCTION_NAME,
+};
+use crate::execution::operators::{
+    avro_table_schema, ipc_table_schema, parquet_table_schema, CsvFormatOptions, CsvScanExec,
+    JoinNode, JsonReadOptions, JsonScanExec, UnionNode, WindowExpr, WindowFunction, WriteFormat,
+    WriteSummary, ARROW_SCHEMA_NAME, AVRO_SCHEMA_NAME, JSON_SCHEMA_NAME,
+};
 use crate::execution::physical_plan::Action;
+pub use crate::execution::physical_plan::JoinType;
+use crate::execution::udf::{udf_registry, ScalarUdfSignature};
+use crate::object_store;
 
 pub const CSV_BATCH_SIZE: &str = "ballista.csv.batchSize";
+pub const AUTH_TOKEN: &str = "ballista.auth.token";
+pub const TLS_CA_CERT: &str = "ballista.tls.caCert";
+pub const TLS_DOMAIN_NAME: &str = "ballista.tls.domainName";
+pub const QUERY_BATCH_SIZE: &str = "ballista.query.batchSize";
+pub const QUERY_TARGET_PARTITIONS: &str = "ballista.query.targetPartitions";
+pub const QUERY_MEMORY_LIMIT: &str = "ballista.query.memoryLimit";
+pub const QUERY_TIMEOUT_MS: &str = "ballista.query.timeoutMs";
+pub const QUERY_TASK_TIMEOUT_MS: &str = "ballista.query.taskTimeoutMs";
 
 /// Configuration setting
 // struct ConfigSetting {
@@ -123,9 +150,8 @@ impl SchemaProvider for &ContextSchemaProvider {
             .map(|df| Arc::from(df.plan.schema().clone()))
     }
 
-    fn get_function_meta(&self, _name: &str) -> Option<Arc<FunctionMeta>> {
-        // TODO: support udf
-        None
+    fn get_function_meta(&self, name: &str) -> Option<Arc<FunctionMeta>> {
+        udf_registry().function_meta(name)
     }
 }
 
@@ -134,6 +160,10 @@ pub struct Context {
     pub state: Arc<ContextState>,
 }
 
+/// Client context for building queries with the DataFrame API and running them on a Ballista
+/// cluster, named after the equivalent DataFusion `ExecutionContext`
+pub type BallistaContext = Context;
+
 #[derive(Debug)]
 pub struct ContextState {
     pub schema_provider: RwLock<ContextSchemaProvider>,
@@ -181,6 +211,25 @@ impl Context {
         }
     }
 
+    /// Create a context for executing queries against the executor named by the `client.host`
+    /// and `client.port` settings, authenticating and connecting with TLS as configured
+    pub fn remote_with_config(config: &BallistaConfig) -> Result<Self> {
+        let host: String = config.require(ballista_config::CLIENT_HOST)?;
+        let port: usize = config.require(ballista_config::CLIENT_PORT)?;
+        let mut settings = HashMap::new();
+        for (key, setting) in &[
+            (ballista_config::AUTH_TOKEN, AUTH_TOKEN),
+            (ballista_config::TLS_CA_CERT, TLS_CA_CERT),
+            (ballista_config::TLS_DOMAIN_NAME, TLS_DOMAIN_NAME),
+        ] {
+            if let Some(value) = config.get(key) {
+                settings.insert(*setting, value);
+            }
+        }
+        connection_pool().configure(ClientOptions::from_config(config)?);
+        Ok(Self::remote(&host, port, settings))
+    }
+
     pub fn from(state: Arc<ContextState>) -> Self {
         Self { state }
     }
@@ -202,11 +251,24 @@ impl Context {
         path: &str,
         options: CsvReadOptions,
         projection: Option<Vec<usize>>,
+    ) -> Result<DataFrame> {
+        self.read_csv_with_format(path, options, CsvFormatOptions::new(), projection)
+    }
+
+    /// Read CSV files with other quoting or null conventions, compressed CSV files, or CSV
+    /// files with malformed rows
+    pub fn read_csv_with_format(
+        &self,
+        path: &str,
+        options: CsvReadOptions,
+        format: CsvFormatOptions,
+        projection: Option<Vec<usize>>,
     ) -> Result<DataFrame> {
         Ok(DataFrame::scan_csv(
             self.state.clone(),
             path,
             options,
+            format,
             projection,
         )?)
     }
@@ -219,6 +281,31 @@ impl Context {
         )?)
     }
 
+    /// Read a newline-delimited JSON file, or directory of JSON files
+    pub fn read_json(
+        &self,
+        path: &str,
+        options: JsonReadOptions,
+        projection: Option<Vec<usize>>,
+    ) -> Result<DataFrame> {
+        Ok(DataFrame::scan_json(
+            self.state.clone(),
+            path,
+            options,
+            projection,
+        )?)
+    }
+
+    /// Read an Avro file, or directory of Avro files
+    pub fn read_avro(&self, path: &str, projection: Option<Vec<usize>>) -> Result<DataFrame> {
+        Ok(DataFrame::scan_avro(self.state.clone(), path, projection)?)
+    }
+
+    /// Read an Arrow IPC file, or directory of Arrow IPC files
+    pub fn read_arrow(&self, path: &str, projection: Option<Vec<usize>>) -> Result<DataFrame> {
+        Ok(DataFrame::scan_arrow(self.state.clone(), path, projection)?)
+    }
+
     pub fn sql(&self, sql: &str) -> Result<DataFrame> {
         let ast = DFParser::parse_sql(sql)?;
         match ast {
@@ -233,19 +320,179 @@ impl Context {
         }
     }
 
+    /// Register the signature of a user-defined scalar function so that it can be used in
+    /// queries. Executors must have an implementation of the function registered.
+    pub fn register_udf(&self, signature: ScalarUdfSignature) -> Result<()> {
+        udf_registry().register_signature(signature)
+    }
+
     pub fn register_temp_table(&mut self, name: &str, df: DataFrame) -> Result<()> {
         let mut provider = self.state.schema_provider.write().unwrap();
         provider.register_temp_table(name, df)?;
         Ok(())
     }
 
+    /// Register external tables, such as those listed by a scheduler, so that SQL queries can
+    /// refer to them
+    pub fn register_external_tables(&mut self, tables: &[TableDefinition]) -> Result<()> {
+        for table in tables {
+            let df = DataFrame::from(self.state.clone(), table.to_plan()?);
+            self.register_temp_table(&table.name, df)?;
+        }
+        Ok(())
+    }
+
+    /// Register a CSV file, or directory of CSV files, as a table that SQL queries can refer to
+    pub fn register_csv(&mut self, name: &str, path: &str, options: CsvReadOptions) -> Result<()> {
+        let df = self.read_csv(path, options, None)?;
+        self.register_temp_table(name, df)
+    }
+
+    /// Register CSV files that are read with the given format options as a table that SQL
+    /// queries can refer to
+    pub fn register_csv_with_format(
+        &mut self,
+        name: &str,
+        path: &str,
+        options: CsvReadOptions,
+        format: CsvFormatOptions,
+    ) -> Result<()> {
+        let df = self.read_csv_with_format(path, options, format, None)?;
+        self.register_temp_table(name, df)
+    }
+
+    /// Register a Parquet file, or directory of Parquet files, as a table that SQL queries can
+    /// refer to
+    pub fn register_parquet(&mut self, name: &str, path: &str) -> Result<()> {
+        let df = self.read_parquet(path, None)?;
+        self.register_temp_table(name, df)
+    }
+
+    /// Register a newline-delimited JSON file, or directory of JSON files, as a table that SQL
+    /// queries can refer to
+    pub fn register_json(
+        &mut self,
+        name: &str,
+        path: &str,
+        options: JsonReadOptions,
+    ) -> Result<()> {
+        let df = self.read_json(path, options, None)?;
+        self.register_temp_table(name, df)
+    }
+
+    /// Register an Avro file, or directory of Avro files, as a table that SQL queries can refer
+    /// to
+    pub fn register_avro(&mut self, name: &str, path: &str) -> Result<()> {
+        let df = self.read_avro(path, None)?;
+        self.register_temp_table(name, df)
+    }
+
+    /// Register an Arrow IPC file, or directory of Arrow IPC files, as a table that SQL queries
+    /// can refer to
+    pub fn register_arrow(&mut self, name: &str, path: &str) -> Result<()> {
+        let df = self.read_arrow(path, None)?;
+        self.register_temp_table(name, df)
+    }
+
+    /// Collect statistics of a registered table, the equivalent of `ANALYZE TABLE`, so that the
+    /// executor can choose how to join the table from its size and the distribution of its
+    /// values
+    pub async fn analyze_table(&self, name: &str) -> Result<Vec<RecordBatch>> {
+        let df = {
+            let provider = self.state.schema_provider.read().unwrap();
+            provider.temp_tables.get(name).cloned().ok_or_else(|| {
+                BallistaError::General(format!("Table '{}' has not been registered", name))
+            })?
+        };
+        df.analyze().await
+    }
+
     pub async fn execute_action(
         &self,
         host: &str,
         port: usize,
         action: Action,
     ) -> Result<Vec<RecordBatch>> {
-        client::execute_action(host, port, &action).await
+        let (auth_token, tls) = self.connection_settings()?;
+        client::execute_action(host, port, &action, auth_token, tls.as_ref()).await
+    }
+
+    /// Send an action and return a stream of the resulting batches, which are received from
+    /// the executor as the stream is consumed
+    pub async fn execute_action_stream(
+        &self,
+        host: &str,
+        port: usize,
+        action: Action,
+    ) -> Result<FlightBatchStream> {
+        let (auth_token, tls) = self.connection_settings()?;
+        let (_, batches) =
+            client::execute_action_stream(host, port, &action, auth_token, tls.as_ref()).await?;
+        Ok(batches)
+    }
+
+    /// Ask an executor to infer the schema of a file, or directory of files, so that queries
+    /// can be planned against data that is only accessible to the executors
+    pub async fn infer_schema(
+        &self,
+        host: &str,
+        port: usize,
+        path: &str,
+        format: Option<&str>,
+    ) -> Result<Schema> {
+        let (auth_token, tls) = self.connection_settings()?;
+        client::get_schema(host, port, path, format, auth_token, tls.as_ref()).await
+    }
+
+    /// The auth token and TLS configuration to connect to executors with
+    fn connection_settings(&self) -> Result<(Option<&str>, Option<TlsConfig>)> {
+        let settings = match &self.state.backend {
+            ContextBackend::Remote { settings, .. } => settings,
+            ContextBackend::Spark { spark_settings, .. } => spark_settings,
+        };
+        let auth_token = settings.get(AUTH_TOKEN).map(|s| s.as_str());
+
+        // connect with TLS when a CA certificate has been configured
+        let tls = match settings.get(TLS_CA_CERT) {
+            Some(ca_cert) => {
+                let tls = TlsConfig::new().with_ca_cert(ca_cert)?;
+                Some(match settings.get(TLS_DOMAIN_NAME) {
+                    Some(domain_name) => tls.with_domain_name(domain_name),
+                    None => tls,
+                })
+            }
+            None => None,
+        };
+        Ok((auth_token, tls))
+    }
+}
+
+impl ContextState {
+    /// The settings that queries run with unless a DataFrame overrides them, read from the
+    /// `ballista.query.*` settings of the context
+    fn query_settings(&self) -> Result<QuerySettings> {
+        let settings = match &self.backend {
+            ContextBackend::Remote { settings, .. } => settings,
+            ContextBackend::Spark { spark_settings, .. } => spark_settings,
+        };
+        fn parse<T: std::str::FromStr>(
+            settings: &HashMap<String, String>,
+            key: &str,
+        ) -> Result<Option<T>> {
+            match settings.get(key) {
+                Some(value) => value.parse::<T>().map(Some).map_err(|_| {
+                    BallistaError::General(format!("Invalid value '{}' for {}", value, key))
+                }),
+                None => Ok(None),
+            }
+        }
+        Ok(QuerySettings {
+            batch_size: parse(settings, QUERY_BATCH_SIZE)?,
+            target_partitions: parse(settings, QUERY_TARGET_PARTITIONS)?,
+            memory_limit: parse(settings, QUERY_MEMORY_LIMIT)?,
+            timeout: parse::<u64>(settings, QUERY_TIMEOUT_MS)?.map(Duration::from_millis),
+            task_timeout: parse::<u64>(settings, QUERY_TASK_TIMEOUT_MS)?.map(Duration::from_millis),
+        })
     }
 }
 
@@ -262,12 +509,30 @@ fn parse_settings(settings: HashMap<&str, &str>) -> HashMap<String, String> {
 pub struct DataFrame {
     ctx_state: Arc<ContextState>,
     plan: LogicalPlan,
+    settings: QuerySettings,
 }
 
 impl DataFrame {
     /// Create a builder from an existing plan
     pub fn from(ctx_state: Arc<ContextState>, plan: LogicalPlan) -> Self {
-        Self { ctx_state, plan }
+        Self {
+            ctx_state,
+            plan,
+            settings: QuerySettings::default(),
+        }
+    }
+
+    /// Run the query with the given settings, which override the settings of the context and
+    /// the configuration of the executor for this query only. The settings are not carried
+    /// over to DataFrames built from this one.
+    pub fn with_settings(mut self, settings: QuerySettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// The settings to send with the actions of this DataFrame
+    fn query_settings(&self) -> Result<QuerySettings> {
+        Ok(self.settings.or(&self.ctx_state.query_settings()?))
     }
 
     /// Create an empty relation
@@ -280,27 +545,38 @@ impl DataFrame {
         )
     }
 
-    /// Scan a data source
+    /// Scan a data source. The format options are carried in the metadata of the schema.
     pub fn scan_csv(
         ctx_state: Arc<ContextState>,
         path: &str,
         options: CsvReadOptions,
+        format: CsvFormatOptions,
         projection: Option<Vec<usize>>,
     ) -> Result<Self> {
+        // DataFusion can only infer the schema of local, uncompressed files in its own format
+        let schema = match options.schema {
+            Some(schema) => schema.clone(),
+            None => {
+                let filenames = object_store::list_files(path, &format.file_extension())?;
+                CsvScanExec::try_infer_schema(&filenames, &options, &format)?
+            }
+        };
+        let schema = Schema::new_with_metadata(schema.fields().clone(), format.to_metadata());
+        let options = options.schema(&schema);
         Ok(Self::from(
             ctx_state,
             LogicalPlanBuilder::scan_csv(path, options, projection)?.build()?,
         ))
     }
 
-    /// Scan a data source
+    /// Scan a data source. Partition columns of files in hive-style `key=value` directories
+    /// follow the columns of the files.
     pub fn scan_parquet(
         ctx_state: Arc<ContextState>,
         path: &str,
         projection: Option<Vec<usize>>,
     ) -> Result<Self> {
-        let p = ParquetTable::try_new(path)?;
-        let schema = p.schema().as_ref().to_owned();
+        let schema = parquet_table_schema(path)?;
         let projected_schema = projection
             .clone()
             .map(|p| Schema::new(p.iter().map(|i| schema.field(*i).clone()).collect()));
@@ -316,6 +592,49 @@ impl DataFrame {
         ))
     }
 
+    /// Scan a newline-delimited JSON data source, inferring its schema unless one is provided
+    pub fn scan_json(
+        ctx_state: Arc<ContextState>,
+        path: &str,
+        options: JsonReadOptions,
+        projection: Option<Vec<usize>>,
+    ) -> Result<Self> {
+        let schema = match options.schema {
+            Some(schema) => schema.clone(),
+            None => JsonScanExec::try_infer_schema(path, &options)?,
+        };
+        Ok(Self::from(
+            ctx_state,
+            LogicalPlanBuilder::scan(JSON_SCHEMA_NAME, path, &schema, projection)?.build()?,
+        ))
+    }
+
+    /// Scan an Avro data source, with the schema of its first file
+    pub fn scan_avro(
+        ctx_state: Arc<ContextState>,
+        path: &str,
+        projection: Option<Vec<usize>>,
+    ) -> Result<Self> {
+        let schema = avro_table_schema(path)?;
+        Ok(Self::from(
+            ctx_state,
+            LogicalPlanBuilder::scan(AVRO_SCHEMA_NAME, path, &schema, projection)?.build()?,
+        ))
+    }
+
+    /// Scan an Arrow IPC data source in either the file or the stream format
+    pub fn scan_arrow(
+        ctx_state: Arc<ContextState>,
+        path: &str,
+        projection: Option<Vec<usize>>,
+    ) -> Result<Self> {
+        let schema = ipc_table_schema(path)?;
+        Ok(Self::from(
+            ctx_state,
+            LogicalPlanBuilder::scan(ARROW_SCHEMA_NAME, path, &schema, projection)?.build()?,
+        ))
+    }
+
     /// Apply a projection
     pub fn project(&self, expr: Vec<Expr>) -> Result<DataFrame> {
         let input_schema = self.plan.schema();
@@ -331,6 +650,10 @@ impl DataFrame {
         } else {
             expr
         };
+        let projected_expr = projected_expr
+            .into_iter()
+            .map(|e| resolve_conditional_type(e, input_schema))
+            .collect::<Result<Vec<_>>>()?;
 
         let schema = Schema::new(exprlist_to_fields(&projected_expr, input_schema)?);
 
@@ -346,6 +669,26 @@ impl DataFrame {
         Ok(df)
     }
 
+    /// Select expressions, in the same way as DataFusion's `DataFrame::select`
+    pub fn select(&self, expr: Vec<Expr>) -> Result<DataFrame> {
+        self.project(expr)
+    }
+
+    /// Select columns by name
+    pub fn select_columns(&self, columns: Vec<&str>) -> Result<DataFrame> {
+        let schema = self.plan.schema();
+        let expr = columns
+            .iter()
+            .map(|name| {
+                schema
+                    .index_of(name)
+                    .map(Expr::Column)
+                    .map_err(BallistaError::from)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.project(expr)
+    }
+
     /// Apply a filter
     pub fn filter(&self, expr: Expr) -> Result<DataFrame> {
         Ok(Self::from(
@@ -357,7 +700,39 @@ impl DataFrame {
         ))
     }
 
-    /// Apply a sort
+    /// Join with another DataFrame on equality of pairs of columns, given by name as the column
+    /// of this DataFrame and the column of the other. The result has the columns of this
+    /// DataFrame followed by the columns of the other.
+    pub fn join(
+        &self,
+        right: &DataFrame,
+        on: Vec<(&str, &str)>,
+        join_type: JoinType,
+    ) -> Result<DataFrame> {
+        let on = on
+            .into_iter()
+            .map(|(l, r)| (l.to_owned(), r.to_owned()))
+            .collect();
+        let join = JoinNode::try_new(self.plan.clone(), right.plan.clone(), on, join_type)?;
+        Ok(Self::from(self.ctx_state.clone(), join.into_plan()))
+    }
+
+    /// Combine the rows of this DataFrame with the rows of another, removing duplicate rows.
+    /// Columns are matched by position and take their names from this DataFrame, and numeric
+    /// columns whose types differ are cast to a common type.
+    pub fn union(&self, other: &DataFrame) -> Result<DataFrame> {
+        let union = UnionNode::try_new(vec![self.plan.clone(), other.plan.clone()], false)?;
+        Ok(Self::from(self.ctx_state.clone(), union.into_plan()))
+    }
+
+    /// Combine the rows of this DataFrame with the rows of another, keeping duplicate rows
+    pub fn union_all(&self, other: &DataFrame) -> Result<DataFrame> {
+        let union = UnionNode::try_new(vec![self.plan.clone(), other.plan.clone()], true)?;
+        Ok(Self::from(self.ctx_state.clone(), union.into_plan()))
+    }
+
+    /// Apply a sort, with each expression given as a sort expression such as
+    /// `Expr::Sort { expr, asc, nulls_first }`
     pub fn sort(&self, expr: Vec<Expr>) -> Result<DataFrame> {
         Ok(Self::from(
             self.ctx_state.clone(),
@@ -381,6 +756,16 @@ impl DataFrame {
         ))
     }
 
+    /// Evaluate window functions, appending one column per window function to the columns of
+    /// this DataFrame
+    pub fn window(&self, window_expr: Vec<WindowExpr>) -> Result<DataFrame> {
+        let mut expr: Vec<Expr> = (0..self.plan.schema().fields().len())
+            .map(Expr::Column)
+            .collect();
+        expr.extend(window_expr.iter().map(|w| w.to_expr()));
+        self.project(expr)
+    }
+
     /// Apply an aggregate
     pub fn aggregate(&self, group_expr: Vec<Expr>, aggr_expr: Vec<Expr>) -> Result<DataFrame> {
         let mut all_fields: Vec<Expr> = group_expr.clone();
@@ -403,45 +788,161 @@ impl DataFrame {
         println!("{:?}", self.plan);
     }
 
+    /// Describe how the executor plans the query, with its optimized logical plan, its physical
+    /// plan, and the stages and tasks that the physical plan is divided into
+    pub async fn explain_plans(&self) -> Result<Explanation> {
+        self.execute_explain(false).await
+    }
+
+    /// Execute the query and describe its plans, with each operator in the distributed plan
+    /// annotated with the rows, batches, bytes, and time that it produced across all tasks
+    pub async fn explain_analyze(&self) -> Result<Explanation> {
+        self.execute_explain(true).await
+    }
+
+    async fn execute_explain(&self, analyze: bool) -> Result<Explanation> {
+        let action = Action::Explain {
+            plan: self.plan.clone(),
+            analyze,
+            settings: self.query_settings()?,
+        };
+        Explanation::try_from_batches(&self.execute_action(action).await?)
+    }
+
     pub async fn collect(&self) -> Result<Vec<RecordBatch>> {
         let action = Action::InteractiveQuery {
             plan: self.plan.clone(),
+            settings: self.query_settings()?,
         };
+        self.execute_action(action).await
+    }
 
-        match &self.ctx_state.backend {
+    /// Execute the query and return a stream of the results, so that results that do not fit
+    /// in memory can be consumed incrementally. The executor fetches the results from the
+    /// cluster only as fast as the stream is consumed.
+    pub async fn collect_stream(&self) -> Result<FlightBatchStream> {
+        let action = Action::InteractiveQuery {
+            plan: self.plan.clone(),
+            settings: self.query_settings()?,
+        };
+        let (ctx, host, port) = self.register_tables().await?;
+        ctx.execute_action_stream(&host, port, action).await
+    }
+
+    /// Send an action for this DataFrame to the executor, after registering the tables that its
+    /// plan refers to
+    async fn execute_action(&self, action: Action) -> Result<Vec<RecordBatch>> {
+        let (ctx, host, port) = self.register_tables().await?;
+        ctx.execute_action(&host, port, action).await
+    }
+
+    /// Register the tables that the plan refers to with the executor, returning the context and
+    /// the address of the executor to send actions to
+    async fn register_tables(&self) -> Result<(Context, String, usize)> {
+        let (host, port) = match &self.ctx_state.backend {
             ContextBackend::Spark { spark_settings, .. } => {
                 let host = &spark_settings["spark.ballista.host"];
                 let port = &spark_settings["spark.ballista.port"];
-                Context::from(self.ctx_state.clone())
-                    .execute_action(host, port.parse::<usize>().unwrap(), action)
-                    .await
-            }
-            ContextBackend::Remote { host, port, .. } => {
-                Context::from(self.ctx_state.clone())
-                    .execute_action(host, *port, action)
-                    .await
+                (host.clone(), port.parse::<usize>().unwrap())
             }
+            ContextBackend::Remote { host, port, .. } => (host.clone(), *port),
+        };
+        let ctx = Context::from(self.ctx_state.clone());
+
+        // the executor needs to know which data sources the tables in the query refer to
+        for (name, plan) in self.referenced_tables()? {
+            ctx.execute_action(&host, port, Action::RegisterTable { name, plan })
+                .await?;
         }
+        Ok((ctx, host, port))
     }
 
-    #[allow(clippy::match_single_binding)]
-    pub fn write_csv(&self, _path: &str) -> Result<()> {
-        match &self.ctx_state.backend {
-            other => Err(BallistaError::NotImplemented(format!(
-                "write_csv() is not implemented for {:?} yet",
-                other
-            ))),
+    /// Find the registered tables that the plan scans, including tables that are scanned by
+    /// other tables
+    fn referenced_tables(&self) -> Result<Vec<(String, LogicalPlan)>> {
+        let provider = self.ctx_state.schema_provider.read().unwrap();
+        let mut tables: Vec<(String, LogicalPlan)> = vec![];
+        let mut pending = table_names(&self.plan);
+        while let Some(name) = pending.pop() {
+            if tables.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            let df = provider.temp_tables.get(&name).ok_or_else(|| {
+                BallistaError::General(format!("Table '{}' has not been registered", name))
+            })?;
+            pending.extend(table_names(&df.plan));
+            tables.push((name, df.plan.clone()));
         }
+        Ok(tables)
     }
 
-    #[allow(clippy::match_single_binding)]
-    pub fn write_parquet(&self, _path: &str) -> Result<()> {
-        match &self.ctx_state.backend {
-            other => Err(BallistaError::NotImplemented(format!(
-                "write_parquet() is not implemented for {:?} yet",
-                other
-            ))),
-        }
+    /// Write the results to CSV files in a directory, with one file per partition written by
+    /// the executors in parallel
+    pub async fn write_csv(&self, path: &str) -> Result<WriteSummary> {
+        self.write(path, WriteFormat::Csv).await
+    }
+
+    /// Write the results to Parquet files in a directory, with one file per partition written
+    /// by the executors in parallel
+    pub async fn write_parquet(&self, path: &str) -> Result<WriteSummary> {
+        self.write(path, WriteFormat::Parquet).await
+    }
+
+    /// Collect statistics of the results, with one row per column giving the row count, null
+    /// count, min and max values, and number of distinct values. When the DataFrame is a scan
+    /// of a whole table, the executor uses the statistics to plan later queries of the table.
+    pub async fn analyze(&self) -> Result<Vec<RecordBatch>> {
+        let action = Action::Analyze {
+            plan: self.plan.clone(),
+            settings: self.query_settings()?,
+        };
+        self.execute_action(action).await
+    }
+
+    /// Plan the query once on the executor so that it can be executed repeatedly with different
+    /// values bound to the parameters created with `param`, without planning it again
+    pub async fn prepare(&self) -> Result<PreparedStatement> {
+        let action = Action::Prepare {
+            plan: self.plan.clone(),
+            settings: self.query_settings()?,
+        };
+        let (ctx, host, port) = self.register_tables().await?;
+        let batches = ctx.execute_action(&host, port, action).await?;
+        let batch = batches
+            .first()
+            .filter(|batch| batch.num_rows() == 1 && batch.num_columns() == 2)
+            .ok_or_else(|| BallistaError::General("Invalid prepared query response".to_owned()))?;
+        let handle = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .ok_or_else(|| BallistaError::General("Invalid prepared query handle".to_owned()))?
+            .value(0)
+            .to_owned();
+        let num_parameters = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .ok_or_else(|| BallistaError::General("Invalid prepared query parameters".to_owned()))?
+            .value(0) as usize;
+        Ok(PreparedStatement {
+            ctx,
+            host,
+            port,
+            handle,
+            num_parameters,
+            schema: self.schema().clone(),
+        })
+    }
+
+    async fn write(&self, path: &str, format: WriteFormat) -> Result<WriteSummary> {
+        let action = Action::Write {
+            plan: self.plan.clone(),
+            path: path.to_owned(),
+            format,
+            settings: self.query_settings()?,
+        };
+        WriteSummary::try_from_batches(&self.execute_action(action).await?)
     }
 
     pub fn schema(&self) -> &Schema {
@@ -453,6 +954,73 @@ impl DataFrame {
     }
 }
 
+/// A query that has been planned on an executor, which can be executed repeatedly with
+/// different values bound to its parameters
+#[derive(Debug)]
+pub struct PreparedStatement {
+    ctx: Context,
+    host: String,
+    port: usize,
+    handle: String,
+    num_parameters: usize,
+    schema: Schema,
+}
+
+impl PreparedStatement {
+    /// Handle of the prepared query on the executor
+    pub fn handle(&self) -> &str {
+        &self.handle
+    }
+
+    /// Number of values that must be bound when the query is executed
+    pub fn num_parameters(&self) -> usize {
+        self.num_parameters
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Execute the query with the given values bound to its parameters, in order. Values of a
+    /// different type than their parameter are cast to the type of the parameter.
+    pub async fn execute(&self, params: Vec<ScalarValue>) -> Result<Vec<RecordBatch>> {
+        let action = self.execute_action(params)?;
+        self.ctx.execute_action(&self.host, self.port, action).await
+    }
+
+    /// Execute the query with the given values bound to its parameters and return a stream of
+    /// the results
+    pub async fn execute_stream(&self, params: Vec<ScalarValue>) -> Result<FlightBatchStream> {
+        let action = self.execute_action(params)?;
+        self.ctx
+            .execute_action_stream(&self.host, self.port, action)
+            .await
+    }
+
+    /// Discard the prepared query on the executor
+    pub async fn close(self) -> Result<()> {
+        let action = Action::ClosePrepared(self.handle.clone());
+        self.ctx
+            .execute_action(&self.host, self.port, action)
+            .await?;
+        Ok(())
+    }
+
+    fn execute_action(&self, params: Vec<ScalarValue>) -> Result<Action> {
+        if params.len() != self.num_parameters {
+            return Err(BallistaError::General(format!(
+                "Prepared query has {} parameters but {} values were bound",
+                self.num_parameters,
+                params.len()
+            )));
+        }
+        Ok(Action::ExecutePrepared {
+            handle: self.handle.clone(),
+            params,
+        })
+    }
+}
+
 pub fn min(expr: Expr) -> Expr {
     aggregate_expr("MIN", &expr)
 }
@@ -470,6 +1038,303 @@ pub fn avg(expr: Expr) -> Expr {
 pub fn count(expr: Expr) -> Expr {
     aggregate_expr("COUNT", &expr)
 }
+pub fn count_distinct(expr: Expr) -> Expr {
+    aggregate_expr("COUNT_DISTINCT", &expr)
+}
+
+/// Create a sample variance aggregate expression
+pub fn var(expr: Expr) -> Expr {
+    aggregate_expr("VAR", &expr)
+}
+
+/// Create a population variance aggregate expression
+pub fn var_pop(expr: Expr) -> Expr {
+    aggregate_expr("VAR_POP", &expr)
+}
+
+/// Create a sample standard deviation aggregate expression
+pub fn stddev(expr: Expr) -> Expr {
+    aggregate_expr("STDDEV", &expr)
+}
+
+/// Create a population standard deviation aggregate expression
+pub fn stddev_pop(expr: Expr) -> Expr {
+    aggregate_expr("STDDEV_POP", &expr)
+}
+
+/// Create an aggregate expression that estimates the given percentile (between 0 and 1) of
+/// the values using a t-digest
+pub fn approx_percentile(expr: Expr, percentile: f64) -> Expr {
+    Expr::AggregateFunction {
+        name: "APPROX_PERCENTILE".to_owned(),
+        args: vec![expr, Expr::Literal(ScalarValue::Float64(percentile))],
+        return_type: DataType::Float64,
+    }
+}
+
+/// Create an aggregate expression that estimates the median of the values
+pub fn median(expr: Expr) -> Expr {
+    approx_percentile(expr, 0.5)
+}
+
+/// Create an expression that invokes a built-in scalar function
+pub fn scalar_function(fun: ScalarFunction, args: Vec<Expr>) -> Expr {
+    Expr::ScalarFunction {
+        name: fun.name().to_owned(),
+        args,
+        return_type: fun.logical_return_type(),
+    }
+}
+
+pub fn abs(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Abs, vec![expr])
+}
+pub fn ceil(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Ceil, vec![expr])
+}
+pub fn floor(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Floor, vec![expr])
+}
+pub fn round(expr: Expr, digits: i64) -> Expr {
+    scalar_function(
+        ScalarFunction::Round,
+        vec![expr, Expr::Literal(ScalarValue::Int64(digits))],
+    )
+}
+pub fn sqrt(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Sqrt, vec![expr])
+}
+pub fn ln(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Ln, vec![expr])
+}
+pub fn exp(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Exp, vec![expr])
+}
+pub fn upper(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Upper, vec![expr])
+}
+pub fn lower(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Lower, vec![expr])
+}
+pub fn trim(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Trim, vec![expr])
+}
+pub fn ltrim(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Ltrim, vec![expr])
+}
+pub fn rtrim(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Rtrim, vec![expr])
+}
+pub fn length(expr: Expr) -> Expr {
+    scalar_function(ScalarFunction::Length, vec![expr])
+}
+
+/// Create a SUBSTRING expression, where `start` is one-based
+pub fn substring(expr: Expr, start: i64, length: Option<i64>) -> Expr {
+    let mut args = vec![expr, Expr::Literal(ScalarValue::Int64(start))];
+    if let Some(length) = length {
+        args.push(Expr::Literal(ScalarValue::Int64(length)));
+    }
+    scalar_function(ScalarFunction::Substring, args)
+}
+
+pub fn concat(args: Vec<Expr>) -> Expr {
+    scalar_function(ScalarFunction::Concat, args)
+}
+
+/// Create a DATE_TRUNC expression, where `granularity` is one of year, quarter, month, week,
+/// day, hour, minute, or second
+pub fn date_trunc(granularity: &str, expr: Expr) -> Expr {
+    scalar_function(
+        ScalarFunction::DateTrunc,
+        vec![
+            Expr::Literal(ScalarValue::Utf8(granularity.to_owned())),
+            expr,
+        ],
+    )
+}
+
+/// Create an EXTRACT expression, where `part` is one of year, quarter, month, day, dow, doy,
+/// hour, minute, or second
+pub fn extract(part: &str, expr: Expr) -> Expr {
+    scalar_function(
+        ScalarFunction::Extract,
+        vec![Expr::Literal(ScalarValue::Utf8(part.to_owned())), expr],
+    )
+}
+
+/// Create an expression that invokes a user-defined scalar function, which must have been
+/// registered with `Context::register_udf`
+pub fn udf(name: &str, args: Vec<Expr>) -> Result<Expr> {
+    let signature = udf_registry()
+        .signature(name)
+        .ok_or_else(|| BallistaError::General(format!("UDF {} is not registered", name)))?;
+    if args.len() != signature.arg_types.len() {
+        return Err(BallistaError::General(format!(
+            "UDF {} expects {} arguments but was given {}",
+            name,
+            signature.arg_types.len(),
+            args.len()
+        )));
+    }
+    Ok(Expr::ScalarFunction {
+        name: signature.name,
+        args,
+        return_type: signature.return_type,
+    })
+}
+
+/// Create an IN list predicate, or a NOT IN list predicate when `negated` is true
+pub fn in_list(expr: Expr, list: Vec<Expr>, negated: bool) -> Expr {
+    let mut args = vec![expr];
+    args.extend(list);
+    encode_predicate(IN_LIST_FUNCTION_NAME, negated, args)
+}
+
+/// Create a BETWEEN predicate, which includes both bounds
+pub fn between(expr: Expr, low: Expr, high: Expr) -> Expr {
+    encode_predicate(BETWEEN_FUNCTION_NAME, false, vec![expr, low, high])
+}
+
+/// Create a NOT BETWEEN predicate
+pub fn not_between(expr: Expr, low: Expr, high: Expr) -> Expr {
+    encode_predicate(BETWEEN_FUNCTION_NAME, true, vec![expr, low, high])
+}
+
+/// Create a CAST expression
+pub fn cast(expr: Expr, data_type: DataType) -> Expr {
+    Expr::Cast {
+        expr: Box::new(expr),
+        data_type,
+    }
+}
+
+/// Create a parameter of a prepared query, which refers to the value at `index` of the values
+/// that the query is executed with
+pub fn param(index: usize, data_type: DataType) -> Expr {
+    parameter(index, data_type)
+}
+
+/// Builder for CASE expressions, created with `when` or `case`
+#[derive(Debug, Clone)]
+pub struct CaseBuilder {
+    parts: CaseParts,
+}
+
+impl CaseBuilder {
+    pub fn when(mut self, when: Expr, then: Expr) -> Self {
+        self.parts.when_then_expr.push((when, then));
+        self
+    }
+
+    /// Complete the CASE expression with an ELSE expression
+    pub fn otherwise(mut self, else_expr: Expr) -> Expr {
+        self.parts.else_expr = Some(else_expr);
+        self.end()
+    }
+
+    /// Complete the CASE expression without an ELSE expression, so that rows that do not match
+    /// any WHEN expression produce null
+    pub fn end(self) -> Expr {
+        // the type is determined from the input schema when the expression is projected
+        self.parts.to_expr(DataType::Null)
+    }
+}
+
+/// Start a CASE expression where each WHEN expression is a condition
+pub fn when(when: Expr, then: Expr) -> CaseBuilder {
+    CaseBuilder {
+        parts: CaseParts {
+            expr: None,
+            when_then_expr: vec![(when, then)],
+            else_expr: None,
+        },
+    }
+}
+
+/// Start a CASE expression where each WHEN expression is compared to `expr`
+pub fn case(expr: Expr) -> CaseBuilder {
+    CaseBuilder {
+        parts: CaseParts {
+            expr: Some(expr),
+            when_then_expr: vec![],
+            else_expr: None,
+        },
+    }
+}
+
+/// Create a COALESCE expression, which produces the first of its arguments that is not null
+pub fn coalesce(args: Vec<Expr>) -> Expr {
+    Expr::ScalarFunction {
+        name: COALESCE_FUNCTION_NAME.to_owned(),
+        args,
+        // the type is determined from the input schema when the expression is projected
+        return_type: DataType::Null,
+    }
+}
+
+/// Determine the types of CASE and COALESCE expressions, which are created without knowing the
+/// types of their inputs, from the first of their possible results that is not null
+fn resolve_conditional_type(expr: Expr, schema: &Schema) -> Result<Expr> {
+    match expr {
+        Expr::Alias(expr, alias) => Ok(Expr::Alias(
+            Box::new(resolve_conditional_type(*expr, schema)?),
+            alias,
+        )),
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type: DataType::Null,
+        } if name == CASE_FUNCTION_NAME || name == COALESCE_FUNCTION_NAME => {
+            let args = args
+                .into_iter()
+                .map(|e| resolve_conditional_type(e, schema))
+                .collect::<Result<Vec<_>>>()?;
+            let results = if name == CASE_FUNCTION_NAME {
+                CaseParts::try_from_args(&args)?
+                    .result_exprs()
+                    .into_iter()
+                    .cloned()
+                    .collect()
+            } else {
+                args.clone()
+            };
+            let mut return_type = DataType::Null;
+            for result in &results {
+                return_type = result.get_type(schema)?;
+                if return_type != DataType::Null {
+                    break;
+                }
+            }
+            Ok(Expr::ScalarFunction {
+                name,
+                args,
+                return_type,
+            })
+        }
+        other => Ok(other),
+    }
+}
+
+/// Create a ROW_NUMBER window function
+pub fn row_number() -> WindowExpr {
+    WindowExpr::new(WindowFunction::RowNumber, vec![])
+}
+
+/// Create a RANK window function
+pub fn rank() -> WindowExpr {
+    WindowExpr::new(WindowFunction::Rank, vec![])
+}
+
+/// Create a DENSE_RANK window function
+pub fn dense_rank() -> WindowExpr {
+    WindowExpr::new(WindowFunction::DenseRank, vec![])
+}
+
+/// Evaluate an aggregate expression, such as `sum(col("a"))`, over a window
+pub fn over(aggr_expr: Expr) -> Result<WindowExpr> {
+    WindowExpr::aggregate(aggr_expr)
+}
 
 /// Create a column expression based on a column name
 pub fn col(name: &str) -> Expr {
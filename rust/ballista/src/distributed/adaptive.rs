@@ -0,0 +1,331 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Adaptive query execution.
+//!
+//! Stages run in dependency order, so by the time a stage runs, the stages that it reads from
+//! have completed and reported how many rows and bytes they produced. The scheduler uses these
+//! statistics to adapt the plans of the stages that have not run yet:
+//!
+//! - A partitioned hash join whose left input turns out to be small is changed into a broadcast
+//!   join. The stage that would have shuffled its right input does not run, and the right input
+//!   runs as part of the join stage instead.
+//! - Skewed partitions of the inputs of a partitioned hash join are split into sub-partitions.
+//! - Consecutive hash partitions that are smaller than the target partition size are coalesced,
+//!   so that a stage does not run many tasks that each do very little.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::distributed::scheduler::{JobConfig, JobProfile};
+use crate::distributed::skew::{partition_sizes, split_skewed_join};
+use crate::error::Result;
+use crate::execution::operators::ShuffleReaderExec;
+use crate::execution::physical_plan::{
+    ExecutorMeta, Partitioning, PhysicalPlan, ShuffleId, ShuffleLocation,
+};
+
+use log::info;
+
+/// Runtime statistics of a job, gathered from the stages that have completed
+pub struct RuntimeStatistics<'a> {
+    /// Locations of the shuffle partitions produced so far
+    pub shuffle_locations: &'a HashMap<ShuffleId, ExecutorMeta>,
+    pub profile: &'a JobProfile,
+    /// Plans of the stages that did not run because they run as part of the stage that reads
+    /// them instead, keyed by stage id
+    pub inlined_stages: &'a HashMap<usize, Arc<PhysicalPlan>>,
+    /// Shuffle partitions of stages of other jobs that are read instead of running the stages
+    /// of this job with the same output, keyed by stage id
+    pub reused_stages: &'a HashMap<usize, Vec<ShuffleLocation>>,
+}
+
+/// Find the partitioning of the output of each stage that is read by a shuffle reader,
+/// keyed by stage id
+pub fn stage_output_partitioning(plans: &[Arc<PhysicalPlan>]) -> HashMap<usize, Partitioning> {
+    let mut partitioning = HashMap::new();
+    for plan in plans {
+        visit(plan, &mut |plan| {
+            if let PhysicalPlan::ShuffleReader(exec) = plan {
+                for shuffle_id in &exec.shuffle_id {
+                    partitioning.insert(shuffle_id.stage_id, exec.partitioning.clone());
+                }
+            }
+        });
+    }
+    partitioning
+}
+
+/// Find the partitioned hash joins that could broadcast their left input instead, as a map from
+/// the id of the stage that shuffles the right input to the id of the stage that shuffles the
+/// left input
+pub fn broadcast_candidates(plans: &[Arc<PhysicalPlan>]) -> HashMap<usize, usize> {
+    let mut candidates = HashMap::new();
+    for plan in plans {
+        visit(plan, &mut |plan| {
+            if let PhysicalPlan::HashJoin(exec) = plan {
+                if exec.broadcast || !exec.can_broadcast() {
+                    return;
+                }
+                if let (Some(left), Some(right)) =
+                    (reader_stage(&exec.left), reader_stage(&exec.right))
+                {
+                    candidates.insert(right, left);
+                }
+            }
+        });
+    }
+    candidates
+}
+
+/// Size in bytes of the output of a stage that has completed
+pub fn stage_output_bytes(profile: &JobProfile, stage_id: usize) -> Option<u64> {
+    profile
+        .stages
+        .iter()
+        .find(|stage| stage.stage_id == stage_id)
+        .map(|stage| stage.total().shuffle_bytes as u64)
+}
+
+/// Rewrite the plan of a stage before it runs, based on the output of the stages it depends on.
+/// Shuffle readers read every partition that those stages produced and stages that were
+/// inlined run as part of this stage. Unless adaptive execution is disabled, joins are
+/// broadcast or have their skewed partitions split and small partitions are coalesced.
+pub fn adapt_stage_plan(
+    plan: &Arc<PhysicalPlan>,
+    stats: &RuntimeStatistics,
+    config: &JobConfig,
+) -> Result<Arc<PhysicalPlan>> {
+    let plan = adapt(plan, stats, config)?;
+    if config.adaptive {
+        Ok(coalesce_partitions(&plan, stats.profile, config))
+    } else {
+        Ok(plan)
+    }
+}
+
+fn adapt(
+    plan: &Arc<PhysicalPlan>,
+    stats: &RuntimeStatistics,
+    config: &JobConfig,
+) -> Result<Arc<PhysicalPlan>> {
+    match plan.as_ref() {
+        PhysicalPlan::ShuffleReader(exec) => {
+            let stage_id = match exec.shuffle_id.first() {
+                Some(shuffle_id) => shuffle_id.stage_id,
+                None => return Ok(plan.clone()),
+            };
+            if let Some(inlined) = stats.inlined_stages.get(&stage_id) {
+                return adapt(inlined, stats, config);
+            }
+            if let Some(locations) = stats.reused_stages.get(&stage_id) {
+                let mut exec = exec.as_ref().clone();
+                exec.shuffle_id = locations.iter().map(|loc| loc.shuffle_id).collect();
+                return Ok(Arc::new(PhysicalPlan::ShuffleReader(Arc::new(exec))));
+            }
+            let mut shuffle_id: Vec<ShuffleId> = stats
+                .shuffle_locations
+                .keys()
+                .filter(|s| s.stage_id == stage_id)
+                .cloned()
+                .collect();
+            if shuffle_id.is_empty() {
+                return Ok(plan.clone());
+            }
+            shuffle_id.sort_by_key(|s| s.partition_id);
+            let mut exec = exec.as_ref().clone();
+            exec.shuffle_id = shuffle_id;
+            Ok(Arc::new(PhysicalPlan::ShuffleReader(Arc::new(exec))))
+        }
+        PhysicalPlan::HashJoin(exec) if !exec.broadcast => {
+            let right_inlined = reader_stage(&exec.right).map_or(false, |stage_id| {
+                stats.inlined_stages.contains_key(&stage_id)
+            });
+            let left = adapt(&exec.left, stats, config)?;
+            let right = adapt(&exec.right, stats, config)?;
+            if right_inlined {
+                // every task of the join reads all of the left input
+                let left = match left.as_ref() {
+                    PhysicalPlan::ShuffleReader(reader) => {
+                        Arc::new(PhysicalPlan::ShuffleReader(Arc::new(
+                            reader
+                                .as_ref()
+                                .clone()
+                                .with_partitioning(Partitioning::UnknownPartitioning(1)),
+                        )))
+                    }
+                    _ => left,
+                };
+                let exec = exec.to_broadcast(left, right)?;
+                return Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(exec))));
+            }
+            if config.adaptive {
+                if let (PhysicalPlan::ShuffleReader(l), PhysicalPlan::ShuffleReader(r)) =
+                    (left.as_ref(), right.as_ref())
+                {
+                    if let Some(exec) = split_skewed_join(exec, l, r, stats.profile, config) {
+                        return Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(exec))));
+                    }
+                }
+            }
+            Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(
+                exec.with_new_children(vec![left, right]),
+            ))))
+        }
+        _ => {
+            let children = plan.as_execution_plan().children();
+            if children.is_empty() {
+                return Ok(plan.clone());
+            }
+            let children = children
+                .iter()
+                .map(|child| adapt(child, stats, config))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Arc::new(plan.with_new_children(children)))
+        }
+    }
+}
+
+/// Coalesce consecutive partitions of a stage that only reads hash-partitioned shuffles when
+/// they are smaller than the target partition size. All of the shuffles that the stage reads
+/// are coalesced in the same way so that partitions of joined inputs still line up.
+fn coalesce_partitions(
+    plan: &Arc<PhysicalPlan>,
+    profile: &JobProfile,
+    config: &JobConfig,
+) -> Arc<PhysicalPlan> {
+    let mut readers = vec![];
+    let mut other_leaves = false;
+    visit(plan, &mut |plan| match plan {
+        PhysicalPlan::ShuffleReader(exec) => readers.push(exec.clone()),
+        _ if plan.as_execution_plan().children().is_empty() => other_leaves = true,
+        _ => {}
+    });
+    if readers.is_empty() || other_leaves {
+        return plan.clone();
+    }
+
+    // the combined size of each partition of the shuffles
+    let mut sizes: Vec<u64> = vec![];
+    for reader in &readers {
+        if !reader.skew_splits.is_empty() || !reader.coalesced_partitions.is_empty() {
+            return plan.clone();
+        }
+        let reader_sizes = match partition_sizes(reader, profile) {
+            Some(reader_sizes) => reader_sizes,
+            None => return plan.clone(),
+        };
+        if sizes.is_empty() {
+            sizes = reader_sizes;
+        } else if sizes.len() == reader_sizes.len() {
+            for (size, reader_size) in sizes.iter_mut().zip(reader_sizes) {
+                *size += reader_size;
+            }
+        } else {
+            return plan.clone();
+        }
+    }
+
+    let groups = coalesce_groups(&sizes, config.target_partition_bytes);
+    if groups.len() == sizes.len() {
+        return plan.clone();
+    }
+    info!(
+        "Coalescing shuffle partitions partitions={} coalesced_partitions={}",
+        sizes.len(),
+        groups.len()
+    );
+    with_coalesced_partitions(plan, &groups)
+}
+
+fn with_coalesced_partitions(plan: &Arc<PhysicalPlan>, groups: &[usize]) -> Arc<PhysicalPlan> {
+    match plan.as_ref() {
+        PhysicalPlan::ShuffleReader(exec) => Arc::new(PhysicalPlan::ShuffleReader(Arc::new(
+            exec.as_ref()
+                .clone()
+                .with_coalesced_partitions(groups.to_vec()),
+        ))),
+        PhysicalPlan::HashJoin(exec) => {
+            let left = with_coalesced_partitions(&exec.left, groups);
+            let right = with_coalesced_partitions(&exec.right, groups);
+            Arc::new(PhysicalPlan::HashJoin(Arc::new(
+                exec.with_new_children(vec![left, right])
+                    .with_partition_count(groups.len()),
+            )))
+        }
+        _ => {
+            let children = plan.as_execution_plan().children();
+            if children.is_empty() {
+                return plan.clone();
+            }
+            let children = children
+                .iter()
+                .map(|child| with_coalesced_partitions(child, groups))
+                .collect();
+            Arc::new(plan.with_new_children(children))
+        }
+    }
+}
+
+/// Group consecutive partitions so that each group is no larger than the target size, unless it
+/// consists of a single partition that is larger on its own, and return the number of
+/// partitions in each group
+pub fn coalesce_groups(sizes: &[u64], target_bytes: u64) -> Vec<usize> {
+    let mut groups = vec![];
+    let mut count = 0;
+    let mut bytes = 0;
+    for size in sizes {
+        if count > 0 && bytes + size > target_bytes {
+            groups.push(count);
+            count = 0;
+            bytes = 0;
+        }
+        count += 1;
+        bytes += size;
+    }
+    if count > 0 {
+        groups.push(count);
+    }
+    groups
+}
+
+/// The id of the stage that a plan reads, if it is a shuffle reader
+fn reader_stage(plan: &PhysicalPlan) -> Option<usize> {
+    match plan {
+        PhysicalPlan::ShuffleReader(exec) => exec.shuffle_id.first().map(|s| s.stage_id),
+        _ => None,
+    }
+}
+
+/// Call `f` on each operator of a plan, parents before their children
+pub(crate) fn visit(plan: &PhysicalPlan, f: &mut dyn FnMut(&PhysicalPlan)) {
+    f(plan);
+    for child in plan.as_execution_plan().children() {
+        visit(&child, f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_small_partitions() {
+        assert_eq!(vec![3, 2], coalesce_groups(&[10, 20, 30, 50, 10], 60));
+        // partitions larger than the target are not combined with others
+        assert_eq!(vec![1, 1, 2], coalesce_groups(&[10, 100, 30, 30], 60));
+        assert_eq!(vec![4], coalesce_groups(&[0, 0, 0, 0], 60));
+        assert!(coalesce_groups(&[], 60).is_empty());
+    }
+}
@@ -0,0 +1,444 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Audit log of the queries, tasks, and management actions that executors receive.
+//!
+//! Each audited request is recorded as an event with the principal that sent it, a fingerprint
+//! of its plan, the times that it was received and completed, and its outcome. Events are
+//! written as JSON lines to stdout or a file, or forwarded to another executor that records
+//! the events of the whole cluster to its own sink.
+
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::client::record_audit_event;
+use crate::distributed::tls::TlsConfig;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::physical_plan::{Action, PhysicalPlan};
+use crate::protobuf;
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+use log::error;
+use prost::Message;
+use serde_json::{json, Value};
+
+/// Type of the Flight action that forwards an audit event, as JSON, to a collecting executor
+pub const RECORD_AUDIT_EVENT: &str = "RecordAuditEvent";
+
+/// Whether an audited request succeeded
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditOutcome {
+    Succeeded,
+    /// The request failed or was rejected, with the reason
+    Failed(String),
+}
+
+/// A request that an executor received
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    /// Authenticated identity that sent the request, or `default`
+    pub principal: String,
+    /// Kind of request, such as `InteractiveQuery` or `Manage(Drain)`
+    pub action: String,
+    /// What the request refers to, such as the key of a task or the path written to
+    pub detail: String,
+    /// Hash of the encoded plan of the request, if it has a plan
+    pub fingerprint: Option<u64>,
+    pub received_at: SystemTime,
+    pub completed_at: SystemTime,
+    pub outcome: AuditOutcome,
+    /// Authenticated identity of the executor that forwarded the event to the collecting
+    /// executor that recorded it, if it was forwarded
+    pub forwarded_by: Option<String>,
+}
+
+impl AuditEvent {
+    /// Start an event for a request that was just received, or `None` for requests that are
+    /// not audited, such as shuffle fetches and heartbeats
+    pub fn for_action(action: &Action, principal: &str) -> Option<Self> {
+        let (kind, detail, fingerprint) = match action {
+            Action::InteractiveQuery { plan, .. } => {
+                ("InteractiveQuery".to_owned(), String::new(), logical(plan))
+            }
+            Action::Execute(task) => (
+                "Execute".to_owned(),
+                format!("{} attempt {}", task.key(), task.attempt),
+                physical(&task.plan),
+            ),
+            Action::Write { plan, path, .. } => ("Write".to_owned(), path.clone(), logical(plan)),
+            Action::Analyze { plan, .. } => ("Analyze".to_owned(), String::new(), logical(plan)),
+            Action::Explain { plan, analyze, .. } => (
+                "Explain".to_owned(),
+                format!("analyze={}", analyze),
+                logical(plan),
+            ),
+            Action::Prepare { plan, .. } => ("Prepare".to_owned(), String::new(), logical(plan)),
+            Action::ExecutePrepared { handle, .. } => {
+                ("ExecutePrepared".to_owned(), handle.clone(), None)
+            }
+            Action::ClosePrepared(handle) => ("ClosePrepared".to_owned(), handle.clone(), None),
+            Action::RegisterTable { name, plan } => {
+                ("RegisterTable".to_owned(), name.clone(), logical(plan))
+            }
+            Action::Manage(action) => (format!("Manage({:?})", action), String::new(), None),
+            Action::CancelTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => (
+                "CancelTask".to_owned(),
+                format!("{}/{}/{}", job_uuid, stage_id, partition_id),
+                None,
+            ),
+            Action::WithdrawTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => (
+                "WithdrawTask".to_owned(),
+                format!("{}/{}/{}", job_uuid, stage_id, partition_id),
+                None,
+            ),
+            Action::ReleaseJob(job_uuid) => ("ReleaseJob".to_owned(), job_uuid.to_string(), None),
+            Action::RetainShuffles {
+                job_uuid,
+                shuffle_ids,
+            } => (
+                "RetainShuffles".to_owned(),
+                format!("{} shuffles={}", job_uuid, shuffle_ids.len()),
+                None,
+            ),
+            _ => return None,
+        };
+        let now = SystemTime::now();
+        Some(Self {
+            principal: principal.to_owned(),
+            action: kind,
+            detail,
+            fingerprint,
+            received_at: now,
+            completed_at: now,
+            outcome: AuditOutcome::Succeeded,
+            forwarded_by: None,
+        })
+    }
+
+    /// Complete the event with the outcome of the request
+    pub fn complete(mut self, outcome: AuditOutcome) -> Self {
+        self.completed_at = SystemTime::now();
+        self.outcome = outcome;
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let (outcome, error) = match &self.outcome {
+            AuditOutcome::Succeeded => ("succeeded", None),
+            AuditOutcome::Failed(reason) => ("failed", Some(reason.clone())),
+        };
+        json!({
+            "principal": self.principal,
+            "action": self.action,
+            "detail": self.detail,
+            "fingerprint": self.fingerprint.map(|fingerprint| format!("{:016x}", fingerprint)),
+            "received_at_ms": millis(self.received_at),
+            "completed_at_ms": millis(self.completed_at),
+            "outcome": outcome,
+            "error": error,
+            "forwarded_by": self.forwarded_by,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let string = |name: &str| {
+            value[name]
+                .as_str()
+                .map(|s| s.to_owned())
+                .ok_or_else(|| ballista_error(&format!("Audit event is missing {}", name)))
+        };
+        let time = |name: &str| {
+            value[name]
+                .as_u64()
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+                .ok_or_else(|| ballista_error(&format!("Audit event is missing {}", name)))
+        };
+        let fingerprint = match value["fingerprint"].as_str() {
+            Some(fingerprint) => Some(
+                u64::from_str_radix(fingerprint, 16)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+            ),
+            None => None,
+        };
+        let outcome = match string("outcome")?.as_str() {
+            "succeeded" => AuditOutcome::Succeeded,
+            "failed" => AuditOutcome::Failed(string("error").unwrap_or_default()),
+            other => {
+                return Err(ballista_error(&format!(
+                    "Invalid audit event outcome {}",
+                    other
+                )))
+            }
+        };
+        Ok(Self {
+            principal: string("principal")?,
+            action: string("action")?,
+            detail: string("detail")?,
+            fingerprint,
+            received_at: time("received_at_ms")?,
+            completed_at: time("completed_at_ms")?,
+            outcome,
+            forwarded_by: value["forwarded_by"].as_str().map(|s| s.to_owned()),
+        })
+    }
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn logical(plan: &LogicalPlan) -> Option<u64> {
+    let node: protobuf::LogicalPlanNode = plan.try_into().ok()?;
+    fingerprint(&node)
+}
+
+fn physical(plan: &PhysicalPlan) -> Option<u64> {
+    let node: protobuf::PhysicalPlanNode = plan.try_into().ok()?;
+    fingerprint(&node)
+}
+
+fn fingerprint<M: Message>(node: &M) -> Option<u64> {
+    let mut encoded = Vec::with_capacity(node.encoded_len());
+    node.encode(&mut encoded).ok()?;
+    let mut hasher = DefaultHasher::new();
+    encoded.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Pluggable destination of audit events
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: &AuditEvent) -> Result<()>;
+}
+
+/// Writes each event to stdout as a line of JSON
+pub struct StdoutAuditSink;
+
+impl AuditSink for StdoutAuditSink {
+    fn record(&self, event: &AuditEvent) -> Result<()> {
+        println!("{}", event.to_json());
+        Ok(())
+    }
+}
+
+/// Appends each event to a file as a line of JSON
+pub struct FileAuditSink {
+    file: Mutex<File>,
+}
+
+impl FileAuditSink {
+    pub fn try_new(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, event: &AuditEvent) -> Result<()> {
+        let mut file = self.file.lock().expect("failed to lock mutex");
+        writeln!(file, "{}", event.to_json())?;
+        // events must survive a crash of the executor
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Forwards events to an executor that collects the audit events of the cluster. Events are
+/// sent in order from a background thread so that requests are not delayed, and events that
+/// cannot be delivered are logged rather than dropped silently.
+pub struct ForwardingAuditSink {
+    tx: Mutex<mpsc::UnboundedSender<AuditEvent>>,
+}
+
+impl ForwardingAuditSink {
+    pub fn new(
+        host: &str,
+        port: usize,
+        auth_token: Option<String>,
+        tls: Option<TlsConfig>,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::unbounded::<AuditEvent>();
+        let host = host.to_owned();
+        thread::spawn(move || {
+            smol::run(async move {
+                while let Some(event) = rx.next().await {
+                    let result = record_audit_event(
+                        &host,
+                        port,
+                        &event,
+                        auth_token.as_deref(),
+                        tls.as_ref(),
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        error!(
+                            "Failed to forward audit event host={} port={} event={} error={:?}",
+                            host,
+                            port,
+                            event.to_json(),
+                            e
+                        );
+                    }
+                }
+            });
+        });
+        Self { tx: Mutex::new(tx) }
+    }
+}
+
+impl AuditSink for ForwardingAuditSink {
+    fn record(&self, event: &AuditEvent) -> Result<()> {
+        self.tx
+            .lock()
+            .expect("failed to lock mutex")
+            .unbounded_send(event.clone())
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))
+    }
+}
+
+/// Create the sink that an audit log setting names: `stdout`, `file:<path>`, or
+/// `grpc:<host>:<port>` to forward events to a collecting executor
+pub fn audit_sink(
+    setting: &str,
+    auth_token: Option<String>,
+    tls: Option<TlsConfig>,
+) -> Result<Arc<dyn AuditSink>> {
+    if setting == "stdout" {
+        Ok(Arc::new(StdoutAuditSink))
+    } else if let Some(path) = setting.strip_prefix("file:") {
+        Ok(Arc::new(FileAuditSink::try_new(path)?))
+    } else if let Some(address) = setting.strip_prefix("grpc:") {
+        let (host, port) = match address.rfind(':') {
+            Some(i) => (&address[..i], &address[i + 1..]),
+            None => (address, ""),
+        };
+        let port = port
+            .parse::<usize>()
+            .map_err(|_| ballista_error(&format!("Invalid audit collector {}", address)))?;
+        Ok(Arc::new(ForwardingAuditSink::new(
+            host, port, auth_token, tls,
+        )))
+    } else {
+        Err(ballista_error(&format!(
+            "Invalid audit log {}, expected stdout, file:<path> or grpc:<host>:<port>",
+            setting
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
+    use crate::datafusion::logicalplan::{col, LogicalPlanBuilder};
+    use crate::distributed::scheduler::QuerySettings;
+    use crate::execution::physical_plan::{ExecutorAction, ShuffleId};
+    use uuid::Uuid;
+
+    fn query(column: &str) -> Result<Action> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Int32, false),
+        ]);
+        let plan =
+            LogicalPlanBuilder::scan_csv("t.csv", CsvReadOptions::new().schema(&schema), None)?
+                .project(vec![col(column)])?
+                .build()?;
+        Ok(Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::new(),
+        })
+    }
+
+    #[test]
+    fn fingerprint_plans_of_queries() -> Result<()> {
+        let event = AuditEvent::for_action(&query("a")?, "alice").unwrap();
+        assert_eq!("InteractiveQuery", event.action);
+        assert_eq!("alice", event.principal);
+        let same = AuditEvent::for_action(&query("a")?, "bob").unwrap();
+        let other = AuditEvent::for_action(&query("b")?, "alice").unwrap();
+        assert!(event.fingerprint.is_some());
+        assert_eq!(event.fingerprint, same.fingerprint);
+        assert_ne!(event.fingerprint, other.fingerprint);
+
+        let event =
+            AuditEvent::for_action(&Action::Manage(ExecutorAction::Drain), "admin").unwrap();
+        assert_eq!("Manage(Drain)", event.action);
+        assert_eq!(None, event.fingerprint);
+
+        let fetch = Action::FetchShuffle(ShuffleId::new(Uuid::new_v4(), 1, 0));
+        assert!(AuditEvent::for_action(&fetch, "alice").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_events_as_json() -> Result<()> {
+        let event = AuditEvent::for_action(&query("a")?, "alice")
+            .unwrap()
+            .complete(AuditOutcome::Failed("denied".to_owned()));
+        // times are recorded to the millisecond
+        let event = AuditEvent::from_json(&event.to_json())?;
+        assert_eq!(event, AuditEvent::from_json(&event.to_json())?);
+        assert_eq!(AuditOutcome::Failed("denied".to_owned()), event.outcome);
+
+        assert!(AuditEvent::from_json(&json!({ "principal": "alice" })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn append_events_to_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("ballista-audit-{}.log", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_owned();
+        let sink = audit_sink(&format!("file:{}", path), None, None)?;
+        let event = AuditEvent::for_action(&query("a")?, "alice").unwrap();
+        sink.record(&event.clone().complete(AuditOutcome::Succeeded))?;
+        sink.record(&event.complete(AuditOutcome::Failed("denied".to_owned())))?;
+
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        let events = contents
+            .lines()
+            .map(|line| {
+                let value: Value = serde_json::from_str(line)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+                AuditEvent::from_json(&value)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(2, events.len());
+        assert_eq!(AuditOutcome::Succeeded, events[0].outcome);
+
+        assert!(audit_sink("syslog", None, None).is_err());
+        Ok(())
+    }
+}
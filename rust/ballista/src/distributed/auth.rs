@@ -0,0 +1,501 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication of clients connecting to the Flight service.
+//!
+//! Clients authenticate with the Flight handshake and receive a session token, which must be
+//! sent as a bearer token in the `authorization` header of subsequent requests.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::protobuf;
+use crate::utils::expiring_map::ExpiringMap;
+
+use prost::Message;
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Status};
+use uuid::Uuid;
+
+/// Header containing the bearer token
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// Header set by the interceptor once a request has been authenticated. Any value sent by the
+/// client is discarded.
+const IDENTITY_HEADER: &str = "x-ballista-identity";
+
+/// Identity of the clients that present the token shared by the processes of a cluster
+pub const CLUSTER_IDENTITY: &str = "cluster";
+
+/// Identity of every client of a service that does not authenticate its clients
+pub const ANONYMOUS_IDENTITY: &str = "default";
+
+/// Default time after which an authenticated session that has not been used expires
+pub const DEFAULT_AUTH_SESSION_TTL: Duration = Duration::from_secs(3600);
+
+/// Default maximum number of authenticated sessions to keep
+pub const DEFAULT_MAX_AUTH_SESSIONS: usize = 1000;
+
+/// Credentials presented by a client
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// Pre-shared token
+    Token(String),
+    /// Username and password
+    UsernamePassword { username: String, password: String },
+    /// DER-encoded certificate presented by the client during a mutual TLS handshake
+    Certificate(Vec<u8>),
+}
+
+impl Credentials {
+    /// Encode the credentials as the payload of a flight handshake. Certificates are presented
+    /// during the TLS handshake instead, so they are sent as an empty payload.
+    pub fn to_handshake_payload(&self) -> Result<Vec<u8>> {
+        let (token, username, password) = match self {
+            Credentials::Token(token) => (token.as_str(), "", ""),
+            Credentials::UsernamePassword { username, password } => {
+                ("", username.as_str(), password.as_str())
+            }
+            Credentials::Certificate(_) => ("", "", ""),
+        };
+        let payload = protobuf::HandshakeCredentials {
+            token: token.to_owned(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+        };
+        let mut buf: Vec<u8> = Vec::with_capacity(payload.encoded_len());
+        payload
+            .encode(&mut buf)
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        Ok(buf)
+    }
+
+    /// Decode the credentials sent in the payload of a flight handshake, falling back to the
+    /// certificate that the client presented during the TLS handshake. Returns `None` if the
+    /// client presented no credentials.
+    pub fn from_handshake_payload(
+        payload: &[u8],
+        peer_cert: Option<Vec<u8>>,
+    ) -> Result<Option<Credentials>> {
+        let payload = protobuf::HandshakeCredentials::decode(payload)
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        let credentials = if !payload.token.is_empty() {
+            Some(Credentials::Token(payload.token))
+        } else if !payload.username.is_empty() {
+            Some(Credentials::UsernamePassword {
+                username: payload.username,
+                password: payload.password,
+            })
+        } else {
+            peer_cert.map(Credentials::Certificate)
+        };
+        Ok(credentials)
+    }
+}
+
+/// Pluggable authentication mechanism for the Flight service
+pub trait Authenticator: Send + Sync {
+    /// Verify the credentials and return the identity of the client
+    fn authenticate(&self, credentials: &Credentials) -> Result<String>;
+}
+
+/// Authenticates clients that present one of a fixed set of tokens, each of which identifies
+/// its own client
+#[derive(Default)]
+pub struct StaticTokenAuthenticator {
+    /// Map from token to identity
+    tokens: HashMap<String, String>,
+}
+
+impl StaticTokenAuthenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept a token as the credentials of the client with the given identity
+    pub fn with_token(mut self, token: &str, identity: &str) -> Self {
+        self.tokens.insert(token.to_owned(), identity.to_owned());
+        self
+    }
+}
+
+impl Authenticator for StaticTokenAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Result<String> {
+        let identity = match credentials {
+            Credentials::Token(token) => self
+                .tokens
+                .iter()
+                .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+                .map(|(_, identity)| identity.to_owned()),
+            _ => None,
+        };
+        identity.ok_or_else(|| ballista_error("Invalid token"))
+    }
+}
+
+/// Authenticates clients with a username and password
+pub struct UsernamePasswordAuthenticator {
+    users: HashMap<String, String>,
+}
+
+impl UsernamePasswordAuthenticator {
+    pub fn new(users: HashMap<String, String>) -> Self {
+        Self { users }
+    }
+}
+
+impl Authenticator for UsernamePasswordAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Result<String> {
+        match credentials {
+            Credentials::UsernamePassword { username, password }
+                if self.users.get(username).map_or(false, |expected| {
+                    constant_time_eq(expected.as_bytes(), password.as_bytes())
+                }) =>
+            {
+                Ok(username.to_owned())
+            }
+            _ => Err(ballista_error("Invalid username or password")),
+        }
+    }
+}
+
+/// Authenticates clients by the certificate they presented during a mutual TLS handshake
+pub struct CertificateAuthenticator {
+    /// Map from DER-encoded certificate to identity
+    certificates: HashMap<Vec<u8>, String>,
+}
+
+impl CertificateAuthenticator {
+    pub fn new(certificates: HashMap<Vec<u8>, String>) -> Self {
+        Self { certificates }
+    }
+}
+
+impl Authenticator for CertificateAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Result<String> {
+        match credentials {
+            Credentials::Certificate(cert) => match self.certificates.get(cert) {
+                Some(identity) => Ok(identity.to_owned()),
+                None => Err(ballista_error("Unknown client certificate")),
+            },
+            _ => Err(ballista_error("Client certificate required")),
+        }
+    }
+}
+
+/// Compare two byte strings in time that only depends on their lengths, so that the time taken
+/// to reject a secret does not reveal how much of it was correct
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Issues session tokens to authenticated clients and validates them on later requests.
+/// Sessions expire once they have not been used for the time-to-live, and the least recently
+/// used sessions are dropped once there are more than the maximum number of sessions.
+#[derive(Clone)]
+pub struct SessionManager {
+    authenticator: Arc<dyn Authenticator>,
+    /// Map from session token to identity
+    sessions: Arc<Mutex<ExpiringMap<String>>>,
+}
+
+impl SessionManager {
+    pub fn new(authenticator: Arc<dyn Authenticator>) -> Self {
+        Self::with_limits(
+            authenticator,
+            DEFAULT_AUTH_SESSION_TTL,
+            DEFAULT_MAX_AUTH_SESSIONS,
+        )
+    }
+
+    pub fn with_limits(
+        authenticator: Arc<dyn Authenticator>,
+        ttl: Duration,
+        max_sessions: usize,
+    ) -> Self {
+        Self {
+            authenticator,
+            sessions: Arc::new(Mutex::new(ExpiringMap::new(ttl, max_sessions, |_| true))),
+        }
+    }
+
+    /// Authenticate the client and create a new session, returning the session token
+    pub fn login(&self, credentials: &Credentials) -> Result<String> {
+        let identity = self.authenticator.authenticate(credentials)?;
+        let token = Uuid::new_v4().to_string();
+        self.sessions
+            .lock()
+            .expect("failed to lock mutex")
+            .insert(token.clone(), identity);
+        Ok(token)
+    }
+
+    /// Return the identity for a bearer token, which keeps its session alive. Static tokens
+    /// are accepted without a handshake so that executors can call each other with a shared
+    /// cluster token.
+    pub fn validate(&self, token: &str) -> Option<String> {
+        if let Some(identity) = self
+            .sessions
+            .lock()
+            .expect("failed to lock mutex")
+            .touch(token)
+        {
+            return Some(identity.clone());
+        }
+        self.authenticator
+            .authenticate(&Credentials::Token(token.to_owned()))
+            .ok()
+    }
+
+    /// Create a tonic interceptor that validates the bearer token, if any, and records the
+    /// identity of the client in the request metadata. Requests without a token are let
+    /// through so that clients can perform the handshake, but any method that requires
+    /// authentication must check [`authenticated_identity`].
+    pub fn interceptor(
+        &self,
+    ) -> impl Fn(Request<()>) -> std::result::Result<Request<()>, Status> + Send + Sync + 'static
+    {
+        let sessions = self.clone();
+        move |mut request: Request<()>| {
+            request.metadata_mut().remove(IDENTITY_HEADER);
+
+            let token = match request.metadata().get(AUTHORIZATION_HEADER) {
+                Some(value) => {
+                    let value = value
+                        .to_str()
+                        .map_err(|_| Status::unauthenticated("Invalid authorization header"))?;
+                    if !value.starts_with("Bearer ") {
+                        return Err(Status::unauthenticated("Expected bearer token"));
+                    }
+                    value["Bearer ".len()..].to_owned()
+                }
+                None => return Ok(request),
+            };
+
+            let identity = sessions
+                .validate(&token)
+                .ok_or_else(|| Status::unauthenticated("Invalid or expired token"))?;
+            let identity = MetadataValue::from_str(&identity)
+                .map_err(|_| Status::unauthenticated("Invalid identity"))?;
+            request.metadata_mut().insert(IDENTITY_HEADER, identity);
+            Ok(request)
+        }
+    }
+}
+
+/// Create a tonic interceptor for a service that does not authenticate its clients, which
+/// replaces any identity sent by the client with the anonymous identity so that clients cannot
+/// claim to be another tenant or the cluster. Services must always be installed with either
+/// this interceptor or the interceptor of a [`SessionManager`].
+pub fn anonymous_interceptor(
+) -> impl Fn(Request<()>) -> std::result::Result<Request<()>, Status> + Send + Sync + 'static {
+    |mut request: Request<()>| {
+        request.metadata_mut().insert(
+            IDENTITY_HEADER,
+            MetadataValue::from_static(ANONYMOUS_IDENTITY),
+        );
+        Ok(request)
+    }
+}
+
+/// Return the identity of the client if the request was authenticated by the interceptor
+pub fn authenticated_identity<T>(request: &Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get(IDENTITY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_owned())
+}
+
+/// Attach a bearer token to a request
+pub fn with_bearer_token<T>(message: T, token: Option<&str>) -> Result<Request<T>> {
+    let mut request = Request::new(message);
+    if let Some(token) = token {
+        let value = MetadataValue::from_str(&format!("Bearer {}", token))
+            .map_err(|e| ballista_error(&format!("Invalid token: {:?}", e)))?;
+        request.metadata_mut().insert(AUTHORIZATION_HEADER, value);
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn users() -> UsernamePasswordAuthenticator {
+        let mut users = HashMap::new();
+        users.insert("alice".to_owned(), "secret".to_owned());
+        UsernamePasswordAuthenticator::new(users)
+    }
+
+    fn login(username: &str, password: &str) -> Credentials {
+        Credentials::UsernamePassword {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }
+    }
+
+    #[test]
+    fn authenticate_tokens_as_their_own_identity() {
+        let authenticator = StaticTokenAuthenticator::new()
+            .with_token("t1", "etl")
+            .with_token("t2", "dashboards");
+        let token = |token: &str| Credentials::Token(token.to_owned());
+        assert_eq!("etl", authenticator.authenticate(&token("t1")).unwrap());
+        assert_eq!(
+            "dashboards",
+            authenticator.authenticate(&token("t2")).unwrap()
+        );
+        assert!(authenticator.authenticate(&token("t3")).is_err());
+        assert!(authenticator.authenticate(&login("t1", "")).is_err());
+    }
+
+    #[test]
+    fn authenticate_username_and_password() {
+        let authenticator = users();
+        assert_eq!(
+            "alice",
+            authenticator
+                .authenticate(&login("alice", "secret"))
+                .unwrap()
+        );
+        assert!(authenticator
+            .authenticate(&login("alice", "secreT"))
+            .is_err());
+        assert!(authenticator
+            .authenticate(&login("alice", "secrets"))
+            .is_err());
+        assert!(authenticator.authenticate(&login("bob", "secret")).is_err());
+
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+
+    #[test]
+    fn authenticate_certificates() {
+        let mut certificates = HashMap::new();
+        certificates.insert(vec![1, 2, 3], "executor-1".to_owned());
+        let authenticator = CertificateAuthenticator::new(certificates);
+        let identity = authenticator.authenticate(&Credentials::Certificate(vec![1, 2, 3]));
+        assert_eq!("executor-1", identity.unwrap());
+        assert!(authenticator
+            .authenticate(&Credentials::Certificate(vec![4]))
+            .is_err());
+    }
+
+    #[test]
+    fn roundtrip_handshake_payload() -> Result<()> {
+        let credentials = login("alice", "secret");
+        let payload = credentials.to_handshake_payload()?;
+        match Credentials::from_handshake_payload(&payload, Some(vec![1]))? {
+            Some(Credentials::UsernamePassword { username, password }) => {
+                assert_eq!("alice", username);
+                assert_eq!("secret", password);
+            }
+            other => panic!("unexpected credentials {:?}", other),
+        }
+
+        // certificates are taken from the TLS handshake
+        let payload = Credentials::Certificate(vec![1]).to_handshake_payload()?;
+        assert!(matches!(
+            Credentials::from_handshake_payload(&payload, Some(vec![1]))?,
+            Some(Credentials::Certificate(cert)) if cert == vec![1]
+        ));
+        assert!(Credentials::from_handshake_payload(&payload, None)?.is_none());
+        assert!(Credentials::from_handshake_payload(&[0xff], None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn validate_sessions() -> Result<()> {
+        let authenticator = StaticTokenAuthenticator::new().with_token("cluster-token", "cluster");
+        let sessions = SessionManager::new(Arc::new(authenticator));
+        assert!(sessions.login(&login("alice", "secret")).is_err());
+
+        let token = sessions.login(&Credentials::Token("cluster-token".to_owned()))?;
+        assert_eq!(Some("cluster".to_owned()), sessions.validate(&token));
+        // static tokens are accepted without a handshake
+        assert_eq!(
+            Some("cluster".to_owned()),
+            sessions.validate("cluster-token")
+        );
+        assert_eq!(None, sessions.validate("unknown"));
+        Ok(())
+    }
+
+    #[test]
+    fn expire_and_bound_sessions() -> Result<()> {
+        let sessions = SessionManager::with_limits(Arc::new(users()), Duration::from_millis(0), 10);
+        let token = sessions.login(&login("alice", "secret"))?;
+        assert_eq!(None, sessions.validate(&token));
+
+        let sessions = SessionManager::with_limits(Arc::new(users()), Duration::from_secs(3600), 2);
+        let tokens = (0..3)
+            .map(|_| sessions.login(&login("alice", "secret")))
+            .collect::<Result<Vec<_>>>()?;
+        let valid = tokens
+            .iter()
+            .filter(|token| sessions.validate(token).is_some())
+            .count();
+        assert_eq!(2, valid);
+        Ok(())
+    }
+
+    #[test]
+    fn intercept_bearer_tokens() -> Result<()> {
+        let sessions = SessionManager::new(Arc::new(users()));
+        let token = sessions.login(&login("alice", "secret"))?;
+        let interceptor = sessions.interceptor();
+
+        // the identity header sent by the client is replaced with the authenticated identity
+        let mut request = with_bearer_token((), Some(&token))?;
+        request
+            .metadata_mut()
+            .insert(IDENTITY_HEADER, MetadataValue::from_static("admin"));
+        let request = interceptor(request).expect("request is authenticated");
+        assert_eq!(Some("alice".to_owned()), authenticated_identity(&request));
+
+        // requests without a token can perform the handshake but are not authenticated
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(IDENTITY_HEADER, MetadataValue::from_static("admin"));
+        let request = interceptor(request).expect("request is let through");
+        assert_eq!(None, authenticated_identity(&request));
+
+        assert!(interceptor(with_bearer_token((), Some("unknown"))?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn intercept_without_authentication() {
+        let interceptor = anonymous_interceptor();
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            IDENTITY_HEADER,
+            MetadataValue::from_static(CLUSTER_IDENTITY),
+        );
+        let request = interceptor(request).expect("request is let through");
+        assert_eq!(
+            Some(ANONYMOUS_IDENTITY.to_owned()),
+            authenticated_identity(&request)
+        );
+    }
+}
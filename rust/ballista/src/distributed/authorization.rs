@@ -0,0 +1,309 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authorization of the tables and columns that queries read.
+//!
+//! Before a query is planned, an authorizer is consulted for every scan in its plan with the
+//! principal that submitted the query, the registered table or path that is scanned, and the
+//! columns that are read. The authorizer can deny the query, or restrict the scan with a
+//! predicate that rows must satisfy and with expressions that replace the values of columns,
+//! which are applied directly above the scan so that the rest of the query only sees the rows
+//! and values that the principal may read.
+
+use std::collections::HashMap;
+
+use crate::datafusion::logicalplan::{col, Expr, LogicalPlan, LogicalPlanBuilder};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::PhysicalPlan;
+
+/// A scan that a query performs
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanAccess {
+    /// Name of the registered table, or path of the files, that is scanned
+    pub table: String,
+    /// Names of the columns that are read
+    pub columns: Vec<String>,
+}
+
+/// Restrictions on a scan that a principal is allowed to perform
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    /// Predicate that rows must satisfy to be read, which may only refer to columns that the
+    /// scan reads
+    pub row_filter: Option<Expr>,
+    /// Expressions that the values of columns are replaced with, by column name, such as a
+    /// literal or a hash of the column. Masks should have the type of the column they replace.
+    pub column_masks: HashMap<String, Expr>,
+}
+
+impl AccessPolicy {
+    /// Allow every row and column to be read
+    pub fn allow() -> Self {
+        Self::default()
+    }
+
+    pub fn with_row_filter(mut self, row_filter: Expr) -> Self {
+        self.row_filter = Some(row_filter);
+        self
+    }
+
+    pub fn with_column_mask(mut self, column: &str, mask: Expr) -> Self {
+        self.column_masks.insert(column.to_owned(), mask);
+        self
+    }
+}
+
+/// Pluggable authorization of the scans of queries
+pub trait Authorizer: Send + Sync {
+    /// Decide whether a principal may perform a scan, returning an error to deny the query, or
+    /// the restrictions that the scan is performed with
+    fn authorize(&self, principal: &str, access: &ScanAccess) -> Result<AccessPolicy>;
+}
+
+/// Authorizes scans with a fixed policy for each principal and table. Principal `*` matches
+/// every principal, and scans of tables without a policy for the principal are denied.
+#[derive(Default)]
+pub struct StaticAuthorizer {
+    policies: HashMap<(String, String), AccessPolicy>,
+}
+
+impl StaticAuthorizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_policy(mut self, principal: &str, table: &str, policy: AccessPolicy) -> Self {
+        self.policies
+            .insert((principal.to_owned(), table.to_owned()), policy);
+        self
+    }
+}
+
+impl Authorizer for StaticAuthorizer {
+    fn authorize(&self, principal: &str, access: &ScanAccess) -> Result<AccessPolicy> {
+        self.policies
+            .get(&(principal.to_owned(), access.table.clone()))
+            .or_else(|| self.policies.get(&("*".to_owned(), access.table.clone())))
+            .cloned()
+            .ok_or_else(|| {
+                ballista_error(&format!(
+                    "{} is not allowed to read {}",
+                    principal, access.table
+                ))
+            })
+    }
+}
+
+/// Apply the restrictions that the authorizer puts on each scan of a plan, or fail if any scan
+/// is denied. Restricted scans produce the same columns as the scans they replace, so the rest
+/// of the plan is unchanged. Plans that this function does not know how to restrict are denied
+/// rather than passed through unchecked.
+pub fn authorize_plan(
+    plan: &LogicalPlan,
+    principal: &str,
+    authorizer: &dyn Authorizer,
+) -> Result<LogicalPlan> {
+    let restrict = |input: &LogicalPlan| authorize_plan(input, principal, authorizer);
+    match plan {
+        LogicalPlan::Projection { input, expr, .. } => {
+            Ok(LogicalPlanBuilder::from(&restrict(input)?)
+                .project(expr.clone())?
+                .build()?)
+        }
+        LogicalPlan::Selection { input, expr } => Ok(LogicalPlanBuilder::from(&restrict(input)?)
+            .filter(expr.clone())?
+            .build()?),
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            ..
+        } => Ok(LogicalPlanBuilder::from(&restrict(input)?)
+            .aggregate(group_expr.clone(), aggr_expr.clone())?
+            .build()?),
+        LogicalPlan::Sort { input, expr, .. } => Ok(LogicalPlanBuilder::from(&restrict(input)?)
+            .sort(expr.clone())?
+            .build()?),
+        LogicalPlan::Limit { input, n, .. } => Ok(LogicalPlanBuilder::from(&restrict(input)?)
+            .limit(*n)?
+            .build()?),
+        LogicalPlan::Extension { node } => {
+            let inputs = node
+                .inputs()
+                .into_iter()
+                .map(restrict)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(LogicalPlan::Extension {
+                node: node.from_template(&node.expressions(), &inputs),
+            })
+        }
+        LogicalPlan::CsvScan { path, .. } | LogicalPlan::ParquetScan { path, .. } => {
+            restrict_scan(plan, path, principal, authorizer)
+        }
+        LogicalPlan::TableScan { table_name, .. } => {
+            restrict_scan(plan, table_name, principal, authorizer)
+        }
+        // these plans read no tables
+        LogicalPlan::InMemoryScan { .. } | LogicalPlan::EmptyRelation { .. } => Ok(plan.clone()),
+        other => Err(ballista_error(&format!(
+            "Cannot authorize plan {:?} of {}",
+            other, principal
+        ))),
+    }
+}
+
+/// Whether a physical plan reads files. The scans of physical plans are not authorized, so only
+/// the processes of the cluster, which plan them from authorized queries, may run them.
+pub fn reads_files(plan: &PhysicalPlan) -> bool {
+    match plan {
+        PhysicalPlan::CsvScan(_)
+        | PhysicalPlan::ParquetScan(_)
+        | PhysicalPlan::IpcScan(_)
+        | PhysicalPlan::JsonScan(_)
+        | PhysicalPlan::AvroScan(_) => true,
+        _ => plan
+            .as_execution_plan()
+            .children()
+            .iter()
+            .any(|child| reads_files(child)),
+    }
+}
+
+/// The scans of a plan, by the registered table or path that they scan
+pub fn scan_accesses(plan: &LogicalPlan) -> Vec<ScanAccess> {
+    match plan {
+        LogicalPlan::Projection { input, .. }
+        | LogicalPlan::Selection { input, .. }
+        | LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. } => scan_accesses(input),
+        LogicalPlan::Extension { node } => {
+            node.inputs().into_iter().flat_map(scan_accesses).collect()
+        }
+        LogicalPlan::CsvScan { path, .. } | LogicalPlan::ParquetScan { path, .. } => {
+            vec![scan_access(plan, path)]
+        }
+        LogicalPlan::TableScan { table_name, .. } => vec![scan_access(plan, table_name)],
+        _ => vec![],
+    }
+}
+
+fn scan_access(scan: &LogicalPlan, table: &str) -> ScanAccess {
+    ScanAccess {
+        table: table.to_owned(),
+        columns: scan
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect(),
+    }
+}
+
+/// Filter the rows and mask the columns of a scan, keeping the names and order of its columns
+fn restrict_scan(
+    scan: &LogicalPlan,
+    table: &str,
+    principal: &str,
+    authorizer: &dyn Authorizer,
+) -> Result<LogicalPlan> {
+    let access = scan_access(scan, table);
+    let policy = authorizer.authorize(principal, &access)?;
+    let mut builder = LogicalPlanBuilder::from(scan);
+    if let Some(row_filter) = policy.row_filter {
+        builder = builder.filter(row_filter)?;
+    }
+    if !policy.column_masks.is_empty() {
+        let expr = access
+            .columns
+            .iter()
+            .map(|name| match policy.column_masks.get(name) {
+                Some(mask) => mask.alias(name),
+                None => col(name),
+            })
+            .collect();
+        builder = builder.project(expr)?;
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
+    use crate::datafusion::logicalplan::{lit_str, ScalarValue};
+
+    fn employees() -> Result<LogicalPlanBuilder> {
+        let schema = Schema::new(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("state", DataType::Utf8, false),
+            Field::new("ssn", DataType::Utf8, false),
+        ]);
+        Ok(LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )?)
+    }
+
+    #[test]
+    fn deny_scans_without_policy() -> Result<()> {
+        let plan = employees()?.project(vec![col("name")])?.build()?;
+        let authorizer =
+            StaticAuthorizer::new().with_policy("alice", "employee.csv", AccessPolicy::allow());
+        assert!(authorize_plan(&plan, "alice", &authorizer).is_ok());
+        assert!(authorize_plan(&plan, "bob", &authorizer).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn filter_rows_and_mask_columns() -> Result<()> {
+        let plan = employees()?
+            .project(vec![col("name"), col("ssn")])?
+            .build()?;
+        let policy = AccessPolicy::allow()
+            .with_row_filter(col("state").eq(&lit_str("CO")))
+            .with_column_mask("ssn", Expr::Literal(ScalarValue::Utf8("***".to_owned())));
+        let authorizer = StaticAuthorizer::new().with_policy("*", "employee.csv", policy);
+
+        let restricted = authorize_plan(&plan, "alice", &authorizer)?;
+        assert_eq!(plan.schema(), restricted.schema());
+        let mask = match &restricted {
+            LogicalPlan::Projection { input, .. } => match input.as_ref() {
+                LogicalPlan::Projection { input, expr, .. } => {
+                    assert!(matches!(input.as_ref(), LogicalPlan::Selection { .. }));
+                    expr[2].clone()
+                }
+                other => panic!("unexpected plan {:?}", other),
+            },
+            other => panic!("unexpected plan {:?}", other),
+        };
+        assert!(matches!(mask, Expr::Alias(_, name) if name == "ssn"));
+        Ok(())
+    }
+
+    #[test]
+    fn list_scans_of_plan() -> Result<()> {
+        let plan = employees()?.project(vec![col("state")])?.build()?;
+        assert_eq!(
+            vec![ScanAccess {
+                table: "employee.csv".to_owned(),
+                columns: vec!["name".to_owned(), "state".to_owned(), "ssn".to_owned()],
+            }],
+            scan_accesses(&plan)
+        );
+        Ok(())
+    }
+}
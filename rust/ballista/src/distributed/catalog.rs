@@ -0,0 +1,364 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tables that clients have registered with an executor or with a session on the scheduler by
+//! name, so that queries planned from SQL can refer to them, the statistics that ANALYZE has
+//! collected about them, and the schemas of files that have not been registered.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::arrow::datatypes::Schema;
+use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
+use crate::datafusion::logicalplan::{Expr, FunctionMeta, LogicalPlan, LogicalPlanBuilder};
+use crate::datafusion::sql::parser::{DFASTNode, DFParser};
+use crate::datafusion::sql::planner::{SchemaProvider, SqlToRel};
+use crate::error::{ballista_error, Result};
+use crate::execution::operators::{
+    avro_table_schema, ipc_table_schema, parquet_table_schema, CsvCompression, CsvFormatOptions,
+    CsvScanExec, JsonReadOptions, JsonScanExec, ARROW_SCHEMA_NAME, AVRO_SCHEMA_NAME,
+    JSON_SCHEMA_NAME,
+};
+use crate::execution::statistics::Statistics;
+use crate::execution::udf::udf_registry;
+use crate::object_store;
+
+/// Named tables, each defined by the logical plan that produces its contents
+#[derive(Default)]
+pub struct TableCatalog {
+    tables: RwLock<HashMap<String, LogicalPlan>>,
+}
+
+impl TableCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a table, replacing any existing table with the same name
+    pub fn register(&self, name: &str, plan: LogicalPlan) {
+        let mut tables = self.tables.write().expect("failed to lock");
+        tables.insert(name.to_owned(), plan);
+    }
+
+    /// The registered tables, keyed by name
+    pub fn tables(&self) -> HashMap<String, LogicalPlan> {
+        self.tables.read().expect("failed to lock").clone()
+    }
+
+    /// Replace the scans of named tables in a plan with the plans of those tables
+    pub fn resolve(&self, plan: &LogicalPlan) -> Result<LogicalPlan> {
+        let tables = self.tables.read().expect("failed to lock");
+        resolve_tables(plan, &tables)
+    }
+
+    /// Plan a SQL query against the registered tables
+    pub fn plan_sql(&self, sql: &str) -> Result<LogicalPlan> {
+        self.resolve(&self.parse_sql(sql)?)
+    }
+
+    /// Plan a SQL query that scans registered tables by name, which are not yet replaced with
+    /// the plans of those tables
+    pub fn parse_sql(&self, sql: &str) -> Result<LogicalPlan> {
+        match DFParser::parse_sql(sql)? {
+            DFASTNode::ANSI(ansi) => Ok(SqlToRel::new(self).sql_to_rel(&ansi)?),
+            DFASTNode::CreateExternalTable { .. } => Err(ballista_error(
+                "CREATE EXTERNAL TABLE is not supported by the executor",
+            )),
+        }
+    }
+}
+
+impl SchemaProvider for TableCatalog {
+    fn get_table_meta(&self, name: &str) -> Option<Arc<Schema>> {
+        let tables = self.tables.read().expect("failed to lock");
+        tables
+            .get(name)
+            .map(|plan| Arc::from(plan.schema().clone()))
+    }
+
+    fn get_function_meta(&self, name: &str) -> Option<Arc<FunctionMeta>> {
+        udf_registry().function_meta(name)
+    }
+}
+
+/// Create a logical plan that scans the files at a path. The format is taken from the file
+/// extension when it is not given, and the schema is inferred when it is not given.
+pub fn file_scan_plan(
+    path: &str,
+    format: Option<&str>,
+    schema: Option<Schema>,
+) -> Result<LogicalPlan> {
+    let format = resolve_file_format(path, format)?;
+    let schema = match schema {
+        Some(schema) => schema,
+        None => infer_file_schema(path, Some(format))?,
+    };
+    match format {
+        "parquet" => Ok(LogicalPlan::ParquetScan {
+            path: path.to_owned(),
+            schema: Box::new(schema.clone()),
+            projection: None,
+            projected_schema: Box::new(schema),
+        }),
+        "csv" => {
+            // the format options are carried in the schema metadata of logical scans
+            let schema =
+                Schema::new_with_metadata(schema.fields().clone(), csv_format(path).to_metadata());
+            let options = CsvReadOptions::new().schema(&schema).has_header(true);
+            Ok(LogicalPlanBuilder::scan_csv(path, options, None)?.build()?)
+        }
+        "json" => Ok(LogicalPlanBuilder::scan(JSON_SCHEMA_NAME, path, &schema, None)?.build()?),
+        "avro" => Ok(LogicalPlanBuilder::scan(AVRO_SCHEMA_NAME, path, &schema, None)?.build()?),
+        "arrow" => Ok(LogicalPlanBuilder::scan(ARROW_SCHEMA_NAME, path, &schema, None)?.build()?),
+        other => Err(ballista_error(&format!(
+            "Unsupported file format '{}' for table",
+            other
+        ))),
+    }
+}
+
+/// Statistics collected by ANALYZE, keyed by the path of the files that they describe so that
+/// they apply to every table and query that scans those files
+#[derive(Default)]
+pub struct StatisticsCatalog {
+    statistics: RwLock<HashMap<String, Statistics>>,
+}
+
+impl StatisticsCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the statistics of all the columns of the files at a path, replacing any that
+    /// were collected before
+    pub fn register(&self, path: &str, statistics: Statistics) {
+        let mut map = self.statistics.write().expect("failed to lock");
+        map.insert(path.to_owned(), statistics);
+    }
+
+    pub fn get(&self, path: &str) -> Option<Statistics> {
+        let map = self.statistics.read().expect("failed to lock");
+        map.get(path).cloned()
+    }
+}
+
+/// The path of the files that a plan scans, if the plan is a scan of all of their columns
+pub fn scanned_path(plan: &LogicalPlan) -> Option<&str> {
+    match plan {
+        LogicalPlan::CsvScan {
+            path,
+            projection: None,
+            ..
+        }
+        | LogicalPlan::ParquetScan {
+            path,
+            projection: None,
+            ..
+        } => Some(path),
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            projection: None,
+            ..
+        } if !is_table(schema_name) => Some(table_name),
+        _ => None,
+    }
+}
+
+/// Infer the schema of a file, or directory of files, from the Parquet footer or a sample of
+/// the records. The format is one of `parquet`, `csv`, `json`, `avro`, or `arrow`, and is
+/// taken from the file extension when it is not given. CSV files are expected to have a
+/// header.
+pub fn infer_file_schema(path: &str, format: Option<&str>) -> Result<Schema> {
+    match resolve_file_format(path, format)? {
+        "parquet" => parquet_table_schema(path),
+        "csv" => {
+            let format = csv_format(path);
+            let filenames = object_store::list_files(path, &format.file_extension())?;
+            if filenames.is_empty() {
+                return Err(ballista_error(&format!("No CSV files found at {}", path)));
+            }
+            CsvScanExec::try_infer_schema(&filenames, &CsvReadOptions::new(), &format)
+        }
+        "json" => JsonScanExec::try_infer_schema(path, &JsonReadOptions::new()),
+        "avro" => avro_table_schema(path),
+        "arrow" => ipc_table_schema(path),
+        other => Err(ballista_error(&format!(
+            "Unsupported file format '{}' for schema inference",
+            other
+        ))),
+    }
+}
+
+/// The given format, or the format of a file from its extension
+pub(crate) fn resolve_file_format<'a>(path: &str, format: Option<&'a str>) -> Result<&'a str> {
+    match format {
+        Some(format) => Ok(format),
+        None => file_format(path).ok_or_else(|| {
+            ballista_error(&format!(
+                "Cannot determine the file format of '{}' from its extension",
+                path
+            ))
+        }),
+    }
+}
+
+/// Options of CSV files with a header, compressed as their extension says
+fn csv_format(path: &str) -> CsvFormatOptions {
+    let compression = if path.ends_with(".gz") {
+        CsvCompression::Gzip
+    } else if path.ends_with(".bz2") {
+        CsvCompression::Bzip2
+    } else {
+        CsvCompression::Uncompressed
+    };
+    CsvFormatOptions::new().compression(compression)
+}
+
+/// The format of a file from its extension, ignoring any compression extension
+fn file_format(path: &str) -> Option<&'static str> {
+    let path = path.trim_end_matches(".gz").trim_end_matches(".bz2");
+    let extension = path.rsplit('.').next()?;
+    match extension {
+        "parquet" => Some("parquet"),
+        "csv" => Some("csv"),
+        "json" | "ndjson" => Some("json"),
+        "avro" => Some("avro"),
+        "arrow" | "ipc" => Some("arrow"),
+        _ => None,
+    }
+}
+
+fn resolve_tables(
+    plan: &LogicalPlan,
+    tables: &HashMap<String, LogicalPlan>,
+) -> Result<LogicalPlan> {
+    match plan {
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            projection,
+            ..
+        } if is_table(schema_name) => {
+            let table = tables.get(table_name).ok_or_else(|| {
+                ballista_error(&format!("Table '{}' has not been registered", table_name))
+            })?;
+            let table = resolve_tables(table, tables)?;
+            match projection {
+                Some(p) => Ok(LogicalPlanBuilder::from(&table)
+                    .project(p.iter().map(|i| Expr::Column(*i)).collect())?
+                    .build()?),
+                None => Ok(table),
+            }
+        }
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => Ok(LogicalPlan::Projection {
+            expr: expr.clone(),
+            input: Box::new(resolve_tables(input, tables)?),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Selection { expr, input } => Ok(LogicalPlan::Selection {
+            expr: expr.clone(),
+            input: Box::new(resolve_tables(input, tables)?),
+        }),
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => Ok(LogicalPlan::Aggregate {
+            input: Box::new(resolve_tables(input, tables)?),
+            group_expr: group_expr.clone(),
+            aggr_expr: aggr_expr.clone(),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Sort {
+            expr,
+            input,
+            schema,
+        } => Ok(LogicalPlan::Sort {
+            expr: expr.clone(),
+            input: Box::new(resolve_tables(input, tables)?),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Limit { n, input, schema } => Ok(LogicalPlan::Limit {
+            n: *n,
+            input: Box::new(resolve_tables(input, tables)?),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Extension { node } => {
+            let inputs = node
+                .inputs()
+                .into_iter()
+                .map(|input| resolve_tables(input, tables))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(LogicalPlan::Extension {
+                node: node.from_template(&node.expressions(), &inputs),
+            })
+        }
+        _ => Ok(plan.clone()),
+    }
+}
+
+/// Scans of files in formats that DataFusion has no logical plan for are table scans with the
+/// format as the schema name, rather than scans of registered tables
+pub(crate) fn is_table(schema_name: &str) -> bool {
+    schema_name != JSON_SCHEMA_NAME
+        && schema_name != AVRO_SCHEMA_NAME
+        && schema_name != ARROW_SCHEMA_NAME
+}
+
+/// Names of the tables that a plan scans
+pub fn table_names(plan: &LogicalPlan) -> Vec<String> {
+    match plan {
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            ..
+        } if is_table(schema_name) => vec![table_name.clone()],
+        LogicalPlan::Projection { input, .. }
+        | LogicalPlan::Selection { input, .. }
+        | LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. } => table_names(input),
+        LogicalPlan::Extension { node } => {
+            node.inputs().into_iter().flat_map(table_names).collect()
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field};
+
+    #[test]
+    fn plan_sql_against_registered_tables() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let catalog = TableCatalog::new();
+        catalog.register(
+            "employee",
+            file_scan_plan("employee.parquet", None, Some(schema))?,
+        );
+        let plan = catalog.plan_sql("SELECT id FROM employee WHERE id > 1")?;
+        assert!(table_names(&plan).is_empty());
+        assert_eq!(1, plan.schema().fields().len());
+        assert!(catalog.plan_sql("SELECT id FROM department").is_err());
+        Ok(())
+    }
+}
@@ -12,82 +12,642 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Client API for sending requests to executors.
+//! Client API for sending requests to executors and schedulers.
 
 use std::convert::{TryFrom, TryInto};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::arrow::datatypes::Schema;
-use crate::arrow::flight::flight_data_to_batch;
+use crate::arrow::datatypes::{Schema, SchemaRef};
 use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::audit::{AuditEvent, RECORD_AUDIT_EVENT};
+use crate::distributed::auth::{with_bearer_token, Credentials};
+use crate::distributed::compression::ShuffleCompression;
+use crate::distributed::connection_pool::connection_pool;
+use crate::distributed::flight_data::{FlightDataDecoder, FlightDataEncoder};
+use crate::distributed::scheduler::{ExecutionTask, QuerySettings};
+use crate::distributed::scheduler_server::JobStatus;
+use crate::distributed::session::with_session_id;
+use crate::distributed::status::from_status;
+use crate::distributed::table_store::TableDefinition;
+use crate::distributed::tls::TlsConfig;
+use crate::distributed::tracing::{in_trace, inject_trace_context};
 use crate::error::{ballista_error, BallistaError};
-use crate::execution::physical_plan::Action;
+use crate::execution::physical_plan::{
+    Action, ExecutorAction, ShuffleId, TaskMetrics, TaskUpdate, TaskUpdateStream,
+};
 use crate::flight::flight_service_client::FlightServiceClient;
-use crate::flight::Ticket;
+use crate::flight::{flight_descriptor, FlightData, FlightDescriptor, HandshakeRequest, Ticket};
 use crate::protobuf;
+use crate::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
+use crate::serde::encode_protobuf;
 
+use futures::{Stream, TryStreamExt};
 use prost::Message;
+use tonic::transport::Channel;
+use tonic::Streaming;
+use uuid::Uuid;
+
+/// Stream of record batches received from a flight server
+pub type FlightBatchStream = Pin<Box<dyn Stream<Item = Result<RecordBatch, BallistaError>> + Send>>;
+
+/// Stream of the statuses of a job received from a scheduler
+pub type JobStatusStream = Pin<Box<dyn Stream<Item = Result<JobStatus, BallistaError>> + Send>>;
+
+/// Get a pooled connection to the flight server of an executor, using TLS if configured
+async fn connect(
+    host: &str,
+    port: usize,
+    tls: Option<&TlsConfig>,
+) -> Result<FlightServiceClient<Channel>, BallistaError> {
+    Ok(FlightServiceClient::new(
+        connect_channel(host, port, tls).await?,
+    ))
+}
+
+async fn connect_channel(
+    host: &str,
+    port: usize,
+    tls: Option<&TlsConfig>,
+) -> Result<Channel, BallistaError> {
+    connection_pool().channel(host, port, tls).await
+}
 
 pub async fn execute_action(
     host: &str,
     port: usize,
     action: &Action,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
 ) -> Result<Vec<RecordBatch>, BallistaError> {
-    //TODO need to avoid connecting per request
+    let (_, batches) = do_get(host, port, action, auth_token, tls).await?;
+    Ok(batches)
+}
 
-    let addr = format!("http://{}:{}", host, port);
+/// Send an action and return the schema of the results along with a stream of the record
+/// batches, which are received incrementally as the stream is consumed
+pub async fn execute_action_stream(
+    host: &str,
+    port: usize,
+    action: &Action,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<(SchemaRef, FlightBatchStream), BallistaError> {
+    let (_, schema, batches) = do_get_stream(host, port, action, auth_token, tls).await?;
+    Ok((schema, batches))
+}
 
-    //println!("Connecting to flight server at {}", addr);
+/// Ask an executor for the schema of a file, or directory of files, that it can read. The
+/// format is taken from the file extension when it is not given.
+pub async fn get_schema(
+    host: &str,
+    port: usize,
+    path: &str,
+    format: Option<&str>,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<Schema, BallistaError> {
+    let mut client = connect(host, port, tls).await?;
 
-    let mut client = FlightServiceClient::connect(addr)
-        .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    let mut descriptor_path = vec![path.to_owned()];
+    if let Some(format) = format {
+        descriptor_path.push(format.to_owned());
+    }
+    let descriptor = FlightDescriptor {
+        r#type: flight_descriptor::DescriptorType::Path as i32,
+        cmd: vec![],
+        path: descriptor_path,
+    };
+    let request = with_bearer_token(descriptor, auth_token)?;
 
-    let serialized_action: protobuf::Action = action.try_into()?;
-    let mut buf: Vec<u8> = Vec::with_capacity(serialized_action.encoded_len());
-    serialized_action
-        .encode(&mut buf)
+    let schema_result = connection_pool()
+        .check(host, port, tls, client.get_schema(request).await)?
+        .into_inner();
+    Ok(Schema::try_from(&schema_result)?)
+}
+
+/// Submit a task to an executor, or check on a task that was previously submitted. Returns the
+/// metrics of the task once it has completed, and an error describing its status otherwise.
+pub async fn execute_task(
+    host: &str,
+    port: usize,
+    task: &ExecutionTask,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<TaskMetrics, BallistaError> {
+    let action = Action::Execute(task.clone());
+    let submit = do_get(host, port, &action, auth_token, tls);
+    let (app_metadata, _) = match task.trace {
+        Some(trace) => in_trace(trace, submit).await?,
+        None => submit.await?,
+    };
+    let metrics = protobuf::TaskMetrics::decode(app_metadata.as_slice())
         .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    (&metrics).try_into()
+}
 
-    let request = tonic::Request::new(Ticket { ticket: buf });
+/// Send an action with do_get and return the app metadata of the schema message along with the
+/// record batches that follow it
+async fn do_get(
+    host: &str,
+    port: usize,
+    action: &Action,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<(Vec<u8>, Vec<RecordBatch>), BallistaError> {
+    let (app_metadata, _, stream) = do_get_stream(host, port, action, auth_token, tls).await?;
+    let batches = stream.try_collect().await?;
+    Ok((app_metadata, batches))
+}
 
-    let mut stream = client
-        .do_get(request)
-        .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-        .into_inner();
+/// Send an action with do_get and return the app metadata and schema of the schema message
+/// along with a stream of the record batches that follow it. Batches are only received as the
+/// stream is polled, so flow control stops the server from sending faster than the caller
+/// consumes them.
+async fn do_get_stream(
+    host: &str,
+    port: usize,
+    action: &Action,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<(Vec<u8>, SchemaRef, FlightBatchStream), BallistaError> {
+    let buf = encode_protobuf(action)?;
+    let open = || open_get_stream(host, port, &buf, auth_token, tls);
+    // a shuffle partition is only removed once it has been sent, so fetching it can be retried
+    // until the executor has accepted the request
+    let (stream, first) = match action {
+        Action::FetchShuffle(shuffle_id) => {
+            connection_pool()
+                .retry(&format!("FetchShuffle({:?})", shuffle_id), open)
+                .await?
+        }
+        _ => open().await?,
+    };
 
     // the schema should be the first message returned, else client should error
-    match stream
-        .message()
-        .await
-        .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-    {
+    match first {
         Some(flight_data) => {
-            // convert FlightData to a stream
             let schema = Arc::new(Schema::try_from(&flight_data)?);
+            let app_metadata = flight_data.app_metadata.clone();
 
-            // all the remaining stream messages should be dictionary and record batches
-            let mut batches = vec![];
-            while let Some(flight_data) = stream
-                .message()
-                .await
-                .map_err(|e| BallistaError::General(format!("{:?}", e)))?
-            {
-                match flight_data_to_batch(&flight_data, schema.clone())? {
-                    Some(batch) => batches.push(batch),
-                    _ => {
-                        return Err(ballista_error(
-                            "Error converting flight data to columnar batch",
-                        ))
+            // all the remaining stream messages should be dictionary and record batches, and
+            // the stream ends after the first error
+            let decoder = FlightDataDecoder::new(schema.clone());
+            let batches = futures::stream::unfold(Some((stream, decoder)), |state| async move {
+                let (mut stream, mut decoder) = state?;
+                loop {
+                    match stream.message().await {
+                        // dictionaries are kept by the decoder until the batches that use them
+                        Ok(Some(flight_data)) => match decoder.decode(flight_data) {
+                            Ok(Some(batch)) => return Some((Ok(batch), Some((stream, decoder)))),
+                            Ok(None) => continue,
+                            Err(e) => return Some((Err(e), None)),
+                        },
+                        Ok(None) => return None,
+                        Err(e) => return Some((Err(from_status(&e)), None)),
                     }
                 }
-            }
+            });
 
-            Ok(batches)
+            Ok((app_metadata, schema, Box::pin(batches)))
         }
         None => Err(ballista_error(
             "Did not receive schema batch from flight server",
         )),
     }
 }
+
+/// Send a ticket with do_get and wait for the first message of the response
+async fn open_get_stream(
+    host: &str,
+    port: usize,
+    ticket: &[u8],
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<(Streaming<FlightData>, Option<FlightData>), BallistaError> {
+    let pool = connection_pool();
+    let mut client = connect(host, port, tls).await?;
+    let mut request = with_bearer_token(
+        Ticket {
+            ticket: ticket.to_vec(),
+        },
+        auth_token,
+    )?;
+    // the executor records its spans for the request as children of the current span
+    inject_trace_context(&mut request);
+    let mut stream = pool
+        .check(host, port, tls, client.do_get(request).await)?
+        .into_inner();
+    let first = pool.check(host, port, tls, stream.message().await)?;
+    Ok((stream, first))
+}
+
+/// Push a shuffle partition to another executor so that it does not need to be fetched later
+#[allow(clippy::too_many_arguments)]
+pub async fn push_shuffle(
+    host: &str,
+    port: usize,
+    shuffle_id: &ShuffleId,
+    schema: &Schema,
+    batches: &[RecordBatch],
+    compression: ShuffleCompression,
+    max_message_size: usize,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<(), BallistaError> {
+    let mut client = connect(host, port, tls).await?;
+
+    // the first message describes the shuffle partition and contains the schema
+    let mut schema_flight_data = FlightData::from(schema);
+    schema_flight_data.flight_descriptor = Some(FlightDescriptor {
+        r#type: flight_descriptor::DescriptorType::Cmd as i32,
+        cmd: encode_protobuf(&Action::FetchShuffle(*shuffle_id))?,
+        path: vec![],
+    });
+
+    let mut flights = vec![schema_flight_data];
+    let mut encoder = FlightDataEncoder::new(compression).with_max_message_size(max_message_size);
+    for batch in batches {
+        flights.extend(encoder.encode(batch)?);
+    }
+
+    let request = with_bearer_token(futures::stream::iter(flights), auth_token)?;
+    let mut stream = connection_pool()
+        .check(host, port, tls, client.do_put(request).await)?
+        .into_inner();
+
+    while stream
+        .message()
+        .await
+        .map_err(|e| from_status(&e))?
+        .is_some()
+    {}
+
+    Ok(())
+}
+
+/// Send a management action to an executor and return the body of each result
+pub async fn manage_executor(
+    host: &str,
+    port: usize,
+    action: ExecutorAction,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<Vec<Vec<u8>>, BallistaError> {
+    let mut client = connect(host, port, tls).await?;
+
+    let request = with_bearer_token(
+        flight::Action {
+            r#type: format!("{:?}", action),
+            body: encode_protobuf(&Action::Manage(action))?,
+        },
+        auth_token,
+    )?;
+
+    let mut stream = connection_pool()
+        .check(host, port, tls, client.do_action(request).await)?
+        .into_inner();
+
+    let mut results = vec![];
+    while let Some(result) = stream.message().await.map_err(|e| from_status(&e))? {
+        results.push(result.body);
+    }
+    Ok(results)
+}
+
+/// Send an audit event to the executor that collects the audit events of the cluster
+pub async fn record_audit_event(
+    host: &str,
+    port: usize,
+    event: &AuditEvent,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<(), BallistaError> {
+    let mut client = connect(host, port, tls).await?;
+
+    let request = with_bearer_token(
+        flight::Action {
+            r#type: RECORD_AUDIT_EVENT.to_owned(),
+            body: event.to_json().to_string().into_bytes(),
+        },
+        auth_token,
+    )?;
+
+    let mut stream = connection_pool()
+        .check(host, port, tls, client.do_action(request).await)?
+        .into_inner();
+    while stream
+        .message()
+        .await
+        .map_err(|e| from_status(&e))?
+        .is_some()
+    {}
+    Ok(())
+}
+
+/// Ask an executor for statistics of its tasks, its resources, and the shuffle partitions that
+/// it holds
+pub async fn executor_stats(
+    host: &str,
+    port: usize,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<protobuf::ExecutorStats, BallistaError> {
+    let results = manage_executor(host, port, ExecutorAction::Stats, auth_token, tls).await?;
+    let body = results
+        .first()
+        .ok_or_else(|| ballista_error("Executor did not return statistics"))?;
+    protobuf::ExecutorStats::decode(body.as_slice())
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+}
+
+/// Ask an executor for the shuffle partitions that it holds
+pub async fn list_shuffles(
+    host: &str,
+    port: usize,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<Vec<ShuffleId>, BallistaError> {
+    let results =
+        manage_executor(host, port, ExecutorAction::ListShuffles, auth_token, tls).await?;
+    let body = results
+        .first()
+        .ok_or_else(|| ballista_error("Executor did not return its shuffle partitions"))?;
+    let held = protobuf::HeldShuffles::decode(body.as_slice())
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    held.shuffle_ids
+        .iter()
+        .map(|shuffle_id| shuffle_id.try_into())
+        .collect()
+}
+
+/// Subscribe to the transitions of the tasks of a job on an executor, which the executor pushes
+/// as they happen until the job is released
+pub async fn watch_tasks(
+    host: &str,
+    port: usize,
+    job_uuid: &Uuid,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<TaskUpdateStream, BallistaError> {
+    let mut client = connect(host, port, tls).await?;
+
+    let request = with_bearer_token(
+        flight::Action {
+            r#type: "WatchTasks".to_owned(),
+            body: encode_protobuf(&Action::WatchTasks(*job_uuid))?,
+        },
+        auth_token,
+    )?;
+
+    let updates = connection_pool()
+        .check(host, port, tls, client.do_action(request).await)?
+        .into_inner()
+        .map_err(|e| from_status(&e))
+        .and_then(|result| {
+            let update = protobuf::TaskStatusUpdate::decode(result.body.as_slice())
+                .map_err(|e| BallistaError::General(format!("{:?}", e)))
+                .and_then(|update| {
+                    let update: Result<TaskUpdate, BallistaError> = (&update).try_into();
+                    update
+                });
+            futures::future::ready(update)
+        });
+    Ok(Box::pin(updates))
+}
+
+/// Authenticate with an executor using the flight handshake and return a session token that
+/// can be passed to the other client functions
+pub async fn handshake(
+    host: &str,
+    port: usize,
+    credentials: &Credentials,
+    tls: Option<&TlsConfig>,
+) -> Result<String, BallistaError> {
+    let mut client = connect(host, port, tls).await?;
+
+    let request = futures::stream::iter(vec![HandshakeRequest {
+        protocol_version: 0,
+        payload: credentials.to_handshake_payload()?,
+    }]);
+
+    let mut stream = client
+        .handshake(request)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+
+    match stream.message().await.map_err(|e| from_status(&e))? {
+        Some(response) => String::from_utf8(response.payload)
+            .map_err(|e| BallistaError::General(format!("{:?}", e))),
+        None => Err(ballista_error(
+            "Did not receive handshake response from flight server",
+        )),
+    }
+}
+
+/// Watch a job that was submitted to a scheduler, receiving the status of the job, including
+/// the progress of its stages, at the given interval until the job finishes
+pub async fn watch_job(
+    host: &str,
+    port: usize,
+    job_uuid: &Uuid,
+    interval: Duration,
+    tls: Option<&TlsConfig>,
+) -> Result<JobStatusStream, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::WatchJobParams {
+        job_uuid: job_uuid.to_string(),
+        interval_ms: interval.as_millis() as u64,
+    };
+    let statuses = client
+        .watch_job(params)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner()
+        .map_err(|e| from_status(&e))
+        .and_then(|status| {
+            let status: Result<JobStatus, BallistaError> = (&status).try_into();
+            futures::future::ready(status)
+        });
+    Ok(Box::pin(statuses))
+}
+
+/// Create a session on a scheduler with the given settings, returning the id of the session,
+/// which later calls of the session must be made with
+pub async fn create_session(
+    host: &str,
+    port: usize,
+    settings: &QuerySettings,
+    tls: Option<&TlsConfig>,
+) -> Result<String, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::HandshakeParams {
+        settings: Some(settings.try_into()?),
+    };
+    let result = client
+        .handshake(params)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    Ok(result.session_id)
+}
+
+/// Override settings of a session on a scheduler, returning the resulting settings of the
+/// session
+pub async fn set_session_config(
+    host: &str,
+    port: usize,
+    session_id: &str,
+    settings: &QuerySettings,
+    tls: Option<&TlsConfig>,
+) -> Result<QuerySettings, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::SetSessionConfigParams {
+        settings: Some(settings.try_into()?),
+    };
+    let result = client
+        .set_session_config(with_session_id(params, Some(session_id))?)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    match &result.settings {
+        Some(settings) => settings.try_into(),
+        None => Ok(QuerySettings::default()),
+    }
+}
+
+/// Close a session on a scheduler, dropping its tables, returning false if there was no such
+/// session
+pub async fn close_session(
+    host: &str,
+    port: usize,
+    session_id: &str,
+    tls: Option<&TlsConfig>,
+) -> Result<bool, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::CloseSessionParams {};
+    let result = client
+        .close_session(with_session_id(params, Some(session_id))?)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    Ok(result.closed)
+}
+
+/// Register the files at a path as a table of a session on a scheduler, so that the jobs of the
+/// session can scan the table by name, returning the schema of the table
+pub async fn register_table(
+    host: &str,
+    port: usize,
+    session_id: &str,
+    name: &str,
+    path: &str,
+    format: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<Schema, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::RegisterTableParams {
+        name: name.to_owned(),
+        path: path.to_owned(),
+        file_format: format.unwrap_or_default().to_owned(),
+        schema: None,
+    };
+    let result = client
+        .register_table(with_session_id(params, Some(session_id))?)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    registered_schema(&result)
+}
+
+/// Register a logical plan as a view of a session on a scheduler, so that the jobs of the
+/// session can scan the view by name, returning the schema of the view
+pub async fn register_view(
+    host: &str,
+    port: usize,
+    session_id: &str,
+    name: &str,
+    plan: &LogicalPlan,
+    tls: Option<&TlsConfig>,
+) -> Result<Schema, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::RegisterViewParams {
+        name: name.to_owned(),
+        plan: Some(plan.try_into()?),
+    };
+    let result = client
+        .register_view(with_session_id(params, Some(session_id))?)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    registered_schema(&result)
+}
+
+fn registered_schema(result: &protobuf::RegisterTableResult) -> Result<Schema, BallistaError> {
+    result
+        .schema
+        .as_ref()
+        .ok_or_else(|| ballista_error("Scheduler did not return the schema of the table"))?
+        .try_into()
+}
+
+/// Define an external table on a scheduler, which the jobs of all clients can scan by name,
+/// returning the definition with its format and schema filled in
+pub async fn create_external_table(
+    host: &str,
+    port: usize,
+    name: &str,
+    location: &str,
+    format: Option<&str>,
+    partition_columns: Vec<String>,
+    tls: Option<&TlsConfig>,
+) -> Result<TableDefinition, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let params = protobuf::TableDefinition {
+        name: name.to_owned(),
+        location: location.to_owned(),
+        file_format: format.unwrap_or_default().to_owned(),
+        schema: None,
+        partition_columns,
+    };
+    let table = client
+        .create_external_table(params)
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    (&table).try_into()
+}
+
+/// List the external tables defined on a scheduler, such as to plan SQL queries that scan them
+pub async fn list_tables(
+    host: &str,
+    port: usize,
+    tls: Option<&TlsConfig>,
+) -> Result<Vec<TableDefinition>, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let result = client
+        .list_tables(protobuf::ListTablesParams {})
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    result.tables.iter().map(|table| table.try_into()).collect()
+}
+
+/// Drop the cached results of earlier jobs on a scheduler, returning the number that were
+/// dropped
+pub async fn invalidate_result_cache(
+    host: &str,
+    port: usize,
+    tls: Option<&TlsConfig>,
+) -> Result<u64, BallistaError> {
+    let mut client = SchedulerGrpcClient::new(connect_channel(host, port, tls).await?);
+    let result = client
+        .invalidate_result_cache(protobuf::InvalidateResultCacheParams {})
+        .await
+        .map_err(|e| from_status(&e))?
+        .into_inner();
+    Ok(result.invalidated)
+}
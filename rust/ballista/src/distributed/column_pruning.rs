@@ -0,0 +1,412 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Optimizer rule that removes columns that are not needed by the rest of the plan.
+//!
+//! Starting from the root of a physical plan, the columns that each operator needs from its
+//! input are computed from the columns that are needed from its output. Scans then only read
+//! the columns that are needed, and a projection is inserted below each shuffle exchange whose
+//! input produces more columns than are needed, so that the unused columns are not written to
+//! shuffle files or sent over the network.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::datafusion::logicalplan::{col_index, Expr};
+use crate::error::{ballista_error, Result};
+use crate::execution::operators::{
+    FilterExec, GlobalLimitExec, HashAggregateExec, LocalLimitExec, ProjectionExec,
+    RepartitionExec, ShuffleExchangeExec, SortExec, TopKExec, UnionExec, WindowExec, WindowExpr,
+};
+use crate::execution::physical_plan::{ExecutionPlan, Partitioning, PhysicalPlan};
+
+/// Remove the columns that are not needed to produce the output of the plan
+pub fn prune_columns(plan: &Arc<PhysicalPlan>) -> Result<Arc<PhysicalPlan>> {
+    let (plan, _) = prune(plan, &(0..num_columns(plan)).collect())?;
+    Ok(plan)
+}
+
+fn num_columns(plan: &PhysicalPlan) -> usize {
+    plan.as_execution_plan().schema().fields().len()
+}
+
+/// Rewrite a plan so that it produces at least the required columns. Returns the new plan and
+/// the indices, in the output of the original plan, of the columns that the new plan produces.
+fn prune(
+    plan: &Arc<PhysicalPlan>,
+    required: &BTreeSet<usize>,
+) -> Result<(Arc<PhysicalPlan>, Vec<usize>)> {
+    // always keep one column so that the number of rows is preserved
+    let mut required = required.clone();
+    if required.is_empty() && num_columns(plan) > 0 {
+        required.insert(0);
+    }
+
+    match plan.as_ref() {
+        PhysicalPlan::Projection(exec) => {
+            let expr: Vec<Expr> = required.iter().map(|i| exec.expr[*i].clone()).collect();
+            let (child, kept) = prune(&exec.child, &input_columns(&expr, &exec.child))?;
+            let exec = ProjectionExec::try_new(&remap_columns(&expr, &kept)?, child)?;
+            Ok((
+                Arc::new(PhysicalPlan::Projection(Arc::new(exec))),
+                required.into_iter().collect(),
+            ))
+        }
+        PhysicalPlan::Filter(exec) => {
+            let filter_expr = vec![exec.filter_expr.as_ref().clone()];
+            let needed = union(&required, &input_columns(&filter_expr, &exec.child));
+            let (child, kept) = prune(&exec.child, &needed)?;
+            let filter_expr = remap_columns(&filter_expr, &kept)?;
+            let exec = FilterExec::new(&child, &filter_expr[0]);
+            Ok((Arc::new(PhysicalPlan::Filter(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::Sort(exec) => {
+            let needed = union(&required, &input_columns(&exec.sort_expr, &exec.child));
+            let (child, kept) = prune(&exec.child, &needed)?;
+            let exec = SortExec::try_new(child, remap_columns(&exec.sort_expr, &kept)?)?;
+            Ok((Arc::new(PhysicalPlan::Sort(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::TopK(exec) => {
+            let needed = union(&required, &input_columns(&exec.sort_expr, &exec.child));
+            let (child, kept) = prune(&exec.child, &needed)?;
+            let exec = TopKExec::try_new(
+                child,
+                remap_columns(&exec.sort_expr, &kept)?,
+                exec.k,
+                exec.partial,
+            )?;
+            Ok((Arc::new(PhysicalPlan::TopK(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::GlobalLimit(exec) => {
+            let (child, kept) = prune(&exec.child, &required)?;
+            let exec = GlobalLimitExec::new(child, exec.limit);
+            Ok((Arc::new(PhysicalPlan::GlobalLimit(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::LocalLimit(exec) => {
+            let (child, kept) = prune(&exec.child, &required)?;
+            let exec = LocalLimitExec::new(child, exec.limit);
+            Ok((Arc::new(PhysicalPlan::LocalLimit(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::Repartition(exec) => {
+            let (child, kept) = prune(&exec.child, &required)?;
+            let exec = RepartitionExec::new(child, exec.partition_count);
+            Ok((Arc::new(PhysicalPlan::Repartition(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::Window(exec) => {
+            // the window function produces the last column and all others are passed through
+            let window_column = num_columns(&exec.child);
+            let window = &exec.window_expr;
+            let window_expr: Vec<Expr> = window
+                .args
+                .iter()
+                .chain(&window.partition_by)
+                .chain(&window.order_by)
+                .cloned()
+                .collect();
+            let mut needed = input_columns(&window_expr, &exec.child);
+            needed.extend(required.iter().filter(|i| **i < window_column));
+            let (child, mut kept) = prune(&exec.child, &needed)?;
+            let window_expr = WindowExpr {
+                args: remap_columns(&window.args, &kept)?,
+                partition_by: remap_columns(&window.partition_by, &kept)?,
+                order_by: remap_columns(&window.order_by, &kept)?,
+                ..window.clone()
+            };
+            let exec = WindowExec::try_new(child, window_expr)?;
+            kept.push(window_column);
+            Ok((Arc::new(PhysicalPlan::Window(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::HashAggregate(exec) => {
+            // the aggregate produces all of its columns, which are usually needed anyway
+            let mut expr = exec.group_expr.clone();
+            expr.extend(exec.aggr_expr.iter().cloned());
+            let (child, kept) = prune(&exec.child, &input_columns(&expr, &exec.child))?;
+            let exec = HashAggregateExec::try_new(
+                exec.mode.clone(),
+                remap_columns(&exec.group_expr, &kept)?,
+                remap_columns(&exec.aggr_expr, &kept)?,
+                child,
+            )?;
+            let kept = (0..num_columns(plan)).collect();
+            Ok((Arc::new(PhysicalPlan::HashAggregate(Arc::new(exec))), kept))
+        }
+        PhysicalPlan::ShuffleExchange(exec) => {
+            let partitioning = exec.output_partitioning();
+            let partition_expr: Vec<Expr> = match &partitioning {
+                Partitioning::HashPartitioning(_, exprs)
+                | Partitioning::RangePartitioning(_, exprs) => {
+                    exprs.iter().map(|e| e.as_ref().clone()).collect()
+                }
+                Partitioning::UnknownPartitioning(_) => vec![],
+            };
+            let needed = union(&required, &input_columns(&partition_expr, &exec.child));
+            let (child, kept) = prune(&exec.child, &needed)?;
+
+            // drop the columns that the input produces but that are not needed
+            let needed: Vec<usize> = needed.into_iter().collect();
+            let child = select_columns(child, &needed, &kept)?;
+
+            let remap_partitioning = |exprs: &[Arc<Expr>]| -> Result<Vec<Arc<Expr>>> {
+                exprs
+                    .iter()
+                    .map(|e| Ok(Arc::new(remap_column(e, &needed)?)))
+                    .collect()
+            };
+            let partitioning = match &partitioning {
+                Partitioning::HashPartitioning(n, exprs) => {
+                    Partitioning::HashPartitioning(*n, remap_partitioning(exprs)?)
+                }
+                Partitioning::RangePartitioning(n, exprs) => {
+                    Partitioning::RangePartitioning(*n, remap_partitioning(exprs)?)
+                }
+                other => other.clone(),
+            };
+            let exec = ShuffleExchangeExec::new(child, partitioning);
+            Ok((
+                Arc::new(PhysicalPlan::ShuffleExchange(Arc::new(exec))),
+                needed,
+            ))
+        }
+        PhysicalPlan::Union(exec) => {
+            // columns are matched by position, so every input must produce the same columns
+            let needed: Vec<usize> = required.iter().cloned().collect();
+            let inputs = exec
+                .inputs
+                .iter()
+                .map(|input| {
+                    let (input, kept) = prune(input, &required)?;
+                    select_columns(input, &needed, &kept)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let exec = UnionExec::try_new(inputs)?;
+            Ok((Arc::new(PhysicalPlan::Union(Arc::new(exec))), needed))
+        }
+        PhysicalPlan::ParquetScan(exec) => {
+            let mut needed = required;
+            if let Some(predicate) = &exec.predicate {
+                needed.extend(input_columns(&[predicate.clone()], plan));
+            }
+            let kept: Vec<usize> = needed.into_iter().collect();
+            let projection = match &exec.projection {
+                Some(p) => kept.iter().map(|i| p[*i]).collect(),
+                None => kept.clone(),
+            };
+            let mut scan = exec.with_projection(projection);
+            if let Some(predicate) = &exec.predicate {
+                scan.predicate = Some(remap_column(predicate, &kept)?);
+            }
+            Ok((Arc::new(PhysicalPlan::ParquetScan(Arc::new(scan))), kept))
+        }
+        PhysicalPlan::CsvScan(exec) => {
+            let kept: Vec<usize> = required.into_iter().collect();
+            let projection = match &exec.projection {
+                Some(p) => kept.iter().map(|i| p[*i]).collect(),
+                None => kept.clone(),
+            };
+            let scan = exec.with_projection(projection);
+            Ok((Arc::new(PhysicalPlan::CsvScan(Arc::new(scan))), kept))
+        }
+        PhysicalPlan::JsonScan(exec) => {
+            let kept: Vec<usize> = required.into_iter().collect();
+            let projection = match &exec.projection {
+                Some(p) => kept.iter().map(|i| p[*i]).collect(),
+                None => kept.clone(),
+            };
+            let scan = exec.with_projection(projection);
+            Ok((Arc::new(PhysicalPlan::JsonScan(Arc::new(scan))), kept))
+        }
+        PhysicalPlan::AvroScan(exec) => {
+            let kept: Vec<usize> = required.into_iter().collect();
+            let projection = match &exec.projection {
+                Some(p) => kept.iter().map(|i| p[*i]).collect(),
+                None => kept.clone(),
+            };
+            let scan = exec.with_projection(projection);
+            Ok((Arc::new(PhysicalPlan::AvroScan(Arc::new(scan))), kept))
+        }
+        PhysicalPlan::IpcScan(exec) => {
+            let kept: Vec<usize> = required.into_iter().collect();
+            let projection = match &exec.projection {
+                Some(p) => kept.iter().map(|i| p[*i]).collect(),
+                None => kept.clone(),
+            };
+            let scan = exec.with_projection(projection);
+            Ok((Arc::new(PhysicalPlan::IpcScan(Arc::new(scan))), kept))
+        }
+        _ => {
+            // joins refer to their keys by name, so their inputs are only pruned internally
+            let children = plan
+                .as_execution_plan()
+                .children()
+                .iter()
+                .map(prune_columns)
+                .collect::<Result<Vec<_>>>()?;
+            let plan = if children.is_empty() {
+                plan.clone()
+            } else {
+                Arc::new(plan.with_new_children(children))
+            };
+            let kept = (0..num_columns(&plan)).collect();
+            Ok((plan, kept))
+        }
+    }
+}
+
+/// Project the needed columns from a plan that produces the kept columns, unless they are the
+/// same columns
+fn select_columns(
+    plan: Arc<PhysicalPlan>,
+    needed: &[usize],
+    kept: &[usize],
+) -> Result<Arc<PhysicalPlan>> {
+    if kept == needed {
+        return Ok(plan);
+    }
+    let projection = remap_columns(
+        &needed.iter().map(|i| col_index(*i)).collect::<Vec<_>>(),
+        kept,
+    )?;
+    Ok(Arc::new(PhysicalPlan::Projection(Arc::new(
+        ProjectionExec::try_new(&projection, plan)?,
+    ))))
+}
+
+fn union(a: &BTreeSet<usize>, b: &BTreeSet<usize>) -> BTreeSet<usize> {
+    a.union(b).cloned().collect()
+}
+
+/// The columns of the input that the expressions refer to, or all columns of the input if the
+/// expressions cannot be analyzed
+fn input_columns(expr: &[Expr], input: &PhysicalPlan) -> BTreeSet<usize> {
+    let mut columns = BTreeSet::new();
+    if expr.iter().all(|e| collect_columns(e, &mut columns)) {
+        columns
+    } else {
+        (0..num_columns(input)).collect()
+    }
+}
+
+/// Add the columns that an expression refers to, returning false if the expression contains
+/// anything that may refer to columns in some other way
+fn collect_columns(expr: &Expr, columns: &mut BTreeSet<usize>) -> bool {
+    match expr {
+        Expr::Column(i) => {
+            columns.insert(*i);
+            true
+        }
+        Expr::Literal(_) => true,
+        Expr::Alias(expr, _)
+        | Expr::Not(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::Sort { expr, .. } => collect_columns(expr, columns),
+        Expr::BinaryExpr { left, right, .. } => {
+            collect_columns(left, columns) && collect_columns(right, columns)
+        }
+        Expr::ScalarFunction { args, .. } | Expr::AggregateFunction { args, .. } => {
+            args.iter().all(|e| collect_columns(e, columns))
+        }
+        _ => false,
+    }
+}
+
+fn remap_columns(expr: &[Expr], kept: &[usize]) -> Result<Vec<Expr>> {
+    expr.iter().map(|e| remap_column(e, kept)).collect()
+}
+
+/// Rewrite the column references of an expression for an input that only produces the kept
+/// columns, in order
+fn remap_column(expr: &Expr, kept: &[usize]) -> Result<Expr> {
+    let index: HashMap<usize, usize> = kept.iter().enumerate().map(|(j, i)| (*i, j)).collect();
+    remap(expr, &index)
+}
+
+fn remap(expr: &Expr, index: &HashMap<usize, usize>) -> Result<Expr> {
+    let remap_box = |expr: &Expr| -> Result<Box<Expr>> { Ok(Box::new(remap(expr, index)?)) };
+    let remap_list =
+        |args: &[Expr]| -> Result<Vec<Expr>> { args.iter().map(|e| remap(e, index)).collect() };
+    match expr {
+        Expr::Column(i) => match index.get(i) {
+            Some(j) => Ok(Expr::Column(*j)),
+            None => Err(ballista_error(&format!(
+                "Column {} was removed from the input but is still referenced",
+                i
+            ))),
+        },
+        Expr::Alias(expr, alias) => Ok(Expr::Alias(remap_box(expr)?, alias.clone())),
+        Expr::Not(expr) => Ok(Expr::Not(remap_box(expr)?)),
+        Expr::IsNull(expr) => Ok(Expr::IsNull(remap_box(expr)?)),
+        Expr::IsNotNull(expr) => Ok(Expr::IsNotNull(remap_box(expr)?)),
+        Expr::Cast { expr, data_type } => Ok(Expr::Cast {
+            expr: remap_box(expr)?,
+            data_type: data_type.clone(),
+        }),
+        Expr::Sort {
+            expr,
+            asc,
+            nulls_first,
+        } => Ok(Expr::Sort {
+            expr: remap_box(expr)?,
+            asc: *asc,
+            nulls_first: *nulls_first,
+        }),
+        Expr::BinaryExpr { left, op, right } => Ok(Expr::BinaryExpr {
+            left: remap_box(left)?,
+            op: op.clone(),
+            right: remap_box(right)?,
+        }),
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Ok(Expr::ScalarFunction {
+            name: name.clone(),
+            args: remap_list(args)?,
+            return_type: return_type.clone(),
+        }),
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Ok(Expr::AggregateFunction {
+            name: name.clone(),
+            args: remap_list(args)?,
+            return_type: return_type.clone(),
+        }),
+        other => Ok(other.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::logicalplan::{Operator, ScalarValue};
+
+    #[test]
+    fn remap_expression_columns() -> Result<()> {
+        let gt = |column: usize| Expr::BinaryExpr {
+            left: Box::new(col_index(column)),
+            op: Operator::Gt,
+            right: Box::new(Expr::Literal(ScalarValue::Int64(1))),
+        };
+        let mut columns = BTreeSet::new();
+        assert!(collect_columns(&gt(3), &mut columns));
+        assert_eq!(vec![3], columns.into_iter().collect::<Vec<_>>());
+
+        assert_eq!(gt(1), remap_column(&gt(3), &[1, 3])?);
+        assert!(remap_column(&gt(3), &[1, 2]).is_err());
+        Ok(())
+    }
+}
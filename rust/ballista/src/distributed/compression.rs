@@ -0,0 +1,155 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compression of shuffle partitions, both when they are spilled to disk and when they are
+//! transferred between executors.
+//!
+//! Each record batch is compressed separately. Only the body of the flight data is compressed,
+//! and the codec is named in the batch metadata so that the receiver can tell compressed and
+//! uncompressed batches apart.
+
+use std::fmt;
+
+use crate::distributed::flight_data::{batch_metadata, set_batch_metadata};
+use crate::error::{ballista_error, Result};
+use crate::flight::FlightData;
+
+/// Level that ZSTD compresses at, which favors speed over ratio
+const ZSTD_LEVEL: i32 = 1;
+
+/// Codec that shuffle partitions are compressed with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShuffleCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Default for ShuffleCompression {
+    fn default() -> Self {
+        ShuffleCompression::None
+    }
+}
+
+impl fmt::Display for ShuffleCompression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl ShuffleCompression {
+    /// Name of the codec, as used in serialized tasks and in flight data
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShuffleCompression::None => "none",
+            ShuffleCompression::Lz4 => "lz4",
+            ShuffleCompression::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "" | "none" => Ok(ShuffleCompression::None),
+            "lz4" => Ok(ShuffleCompression::Lz4),
+            "zstd" => Ok(ShuffleCompression::Zstd),
+            other => Err(ballista_error(&format!(
+                "Unsupported shuffle compression codec '{}'",
+                other
+            ))),
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            ShuffleCompression::None => Ok(data.to_vec()),
+            ShuffleCompression::Lz4 => Ok(lz4::block::compress(data, None, true)?),
+            ShuffleCompression::Zstd => Ok(zstd::stream::encode_all(data, ZSTD_LEVEL)?),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            ShuffleCompression::None => Ok(data.to_vec()),
+            ShuffleCompression::Lz4 => Ok(lz4::block::decompress(data, None)?),
+            ShuffleCompression::Zstd => Ok(zstd::stream::decode_all(data)?),
+        }
+    }
+
+    /// Compress the body of a flight data message and name the codec in its batch metadata
+    pub fn compress_flight_data(&self, mut flight_data: FlightData) -> Result<FlightData> {
+        if *self == ShuffleCompression::None {
+            return Ok(flight_data);
+        }
+        let mut metadata = batch_metadata(&flight_data)?;
+        metadata.compression = self.name().to_owned();
+        flight_data.data_body = self.compress(&flight_data.data_body)?;
+        set_batch_metadata(&mut flight_data, &metadata)?;
+        Ok(flight_data)
+    }
+
+    /// The codec that the body of a flight data message is compressed with
+    pub fn of_flight_data(flight_data: &FlightData) -> Result<Self> {
+        Self::from_name(&batch_metadata(flight_data)?.compression)
+    }
+}
+
+/// Decompress the body of a flight data message, if it was compressed
+pub fn decompress_flight_data(mut flight_data: FlightData) -> Result<FlightData> {
+    let mut metadata = batch_metadata(&flight_data)?;
+    let compression = ShuffleCompression::from_name(&metadata.compression)?;
+    if compression != ShuffleCompression::None {
+        flight_data.data_body = compression.decompress(&flight_data.data_body)?;
+        metadata.compression = String::new();
+        set_batch_metadata(&mut flight_data, &metadata)?;
+    }
+    Ok(flight_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_flight_data() -> Result<()> {
+        let body: Vec<u8> = (0..4096).map(|i| (i % 7) as u8).collect();
+        for compression in &[
+            ShuffleCompression::None,
+            ShuffleCompression::Lz4,
+            ShuffleCompression::Zstd,
+        ] {
+            assert_eq!(
+                *compression,
+                ShuffleCompression::from_name(compression.name())?
+            );
+            let flight_data = FlightData {
+                flight_descriptor: None,
+                data_header: vec![1, 2, 3],
+                app_metadata: vec![],
+                data_body: body.clone(),
+            };
+            let compressed = compression.compress_flight_data(flight_data)?;
+            assert_eq!(
+                *compression,
+                ShuffleCompression::of_flight_data(&compressed)?
+            );
+            if *compression != ShuffleCompression::None {
+                assert!(compressed.data_body.len() < body.len());
+            }
+            let decompressed = decompress_flight_data(compressed)?;
+            assert_eq!(body, decompressed.data_body);
+            assert_eq!(vec![1, 2, 3], decompressed.data_header);
+        }
+        Ok(())
+    }
+}
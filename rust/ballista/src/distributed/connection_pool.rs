@@ -0,0 +1,355 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pool of gRPC channels to executors and schedulers, keyed by endpoint, so that requests reuse
+//! connections rather than connecting per request.
+//!
+//! Channels are checked before they are handed out: channels that have been idle for longer
+//! than the idle timeout are closed, and channels whose requests failed because the endpoint
+//! was unavailable are evicted so that the next request reconnects. Idempotent requests are
+//! retried with exponential backoff and jitter.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{
+    BallistaConfig, CLIENT_IDLE_TIMEOUT_SECS, CLIENT_MAX_RETRIES, CLIENT_REQUEST_TIMEOUT_MS,
+    CLIENT_RETRY_BACKOFF_MS,
+};
+use crate::distributed::status::from_status;
+use crate::distributed::tls::TlsConfig;
+use crate::error::{BallistaError, Result};
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use random_fast_rng::{FastRng, Random};
+use tonic::transport::Channel;
+use tonic::{Code, Status};
+
+lazy_static! {
+    static ref CONNECTION_POOL: ConnectionPool = ConnectionPool::new(ClientOptions::default());
+}
+
+/// Connection pool shared by all clients in this process
+pub fn connection_pool() -> &'static ConnectionPool {
+    &CONNECTION_POOL
+}
+
+/// Timeouts and retries of the requests that clients send
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Time to wait for the response to a request, or `None` to wait indefinitely
+    pub request_timeout: Option<Duration>,
+    /// Time after which a channel that has not been used is closed
+    pub idle_timeout: Duration,
+    /// Max number of times an idempotent request is retried when the endpoint is unavailable
+    pub max_retries: usize,
+    /// Delay before the first retry, which doubles with each subsequent retry
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between retries
+    pub max_backoff: Duration,
+}
+
+impl ClientOptions {
+    /// Read the client options from the configuration
+    pub fn from_config(config: &BallistaConfig) -> Result<Self> {
+        Ok(Self {
+            request_timeout: config
+                .get_as::<u64>(CLIENT_REQUEST_TIMEOUT_MS)?
+                .map(Duration::from_millis),
+            idle_timeout: Duration::from_secs(config.require(CLIENT_IDLE_TIMEOUT_SECS)?),
+            max_retries: config.require(CLIENT_MAX_RETRIES)?,
+            initial_backoff: Duration::from_millis(config.require(CLIENT_RETRY_BACKOFF_MS)?),
+            ..Self::default()
+        })
+    }
+
+    /// Delay before retrying a request that has failed the given number of times, which is
+    /// between half and all of the exponential backoff so that clients do not retry in lockstep
+    pub fn backoff(&self, failed_attempts: usize) -> Duration {
+        let exponent = failed_attempts.saturating_sub(1).min(31) as u32;
+        let backoff = self
+            .initial_backoff
+            .checked_mul(1 << exponent)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+        let jitter = FastRng::new().get_u32() as f64 / u32::MAX as f64;
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: None,
+            idle_timeout: Duration::from_secs(300),
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+struct PooledChannel {
+    channel: Channel,
+    last_used: Instant,
+}
+
+/// Channels to endpoints, which are shared by concurrent requests to the same endpoint
+pub struct ConnectionPool {
+    options: RwLock<ClientOptions>,
+    channels: Mutex<HashMap<String, PooledChannel>>,
+}
+
+impl ConnectionPool {
+    pub fn new(options: ClientOptions) -> Self {
+        Self {
+            options: RwLock::new(options),
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replace the options of the pool. Channels that are already open keep their request
+    /// timeout until they are reconnected.
+    pub fn configure(&self, options: ClientOptions) {
+        debug!("Configured connection pool options={:?}", options);
+        *self.options.write().expect("failed to lock rwlock") = options;
+    }
+
+    pub fn options(&self) -> ClientOptions {
+        self.options.read().expect("failed to lock rwlock").clone()
+    }
+
+    /// Get a channel to an endpoint, connecting if there is no healthy channel to it
+    pub async fn channel(
+        &self,
+        host: &str,
+        port: usize,
+        tls: Option<&TlsConfig>,
+    ) -> Result<Channel> {
+        let url = endpoint_url(host, port, tls);
+        let options = self.options();
+        {
+            let mut channels = self.channels.lock().expect("failed to lock mutex");
+            let now = Instant::now();
+            channels.retain(|url, pooled| {
+                let active = now.duration_since(pooled.last_used) <= options.idle_timeout;
+                if !active {
+                    debug!("Closing idle channel endpoint={}", url);
+                }
+                active
+            });
+            if let Some(pooled) = channels.get_mut(&url) {
+                pooled.last_used = now;
+                return Ok(pooled.channel.clone());
+            }
+        }
+
+        debug!("Connecting to endpoint={}", url);
+        let endpoint = Channel::from_shared(url.clone())
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        let endpoint = match options.request_timeout {
+            Some(timeout) => endpoint.timeout(timeout),
+            None => endpoint,
+        };
+        let endpoint = match tls {
+            Some(tls) => endpoint.tls_config(tls.client_config()),
+            None => endpoint,
+        };
+        let channel = endpoint
+            .connect()
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+
+        // another request may have connected in the meantime, in which case both channels work
+        self.channels.lock().expect("failed to lock mutex").insert(
+            url,
+            PooledChannel {
+                channel: channel.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        Ok(channel)
+    }
+
+    /// Close the channel to an endpoint so that the next request reconnects
+    pub fn evict(&self, host: &str, port: usize, tls: Option<&TlsConfig>) {
+        let url = endpoint_url(host, port, tls);
+        if self
+            .channels
+            .lock()
+            .expect("failed to lock mutex")
+            .remove(&url)
+            .is_some()
+        {
+            warn!("Evicted unhealthy channel endpoint={}", url);
+        }
+    }
+
+    /// Check the outcome of a request, evicting the channel to the endpoint when the request
+    /// failed because the endpoint was unavailable. The error is reconstructed from the detail
+    /// attached to the status.
+    pub fn check<T>(
+        &self,
+        host: &str,
+        port: usize,
+        tls: Option<&TlsConfig>,
+        result: std::result::Result<T, Status>,
+    ) -> Result<T> {
+        result.map_err(|status| {
+            if is_unavailable(&status) {
+                self.evict(host, port, tls);
+            }
+            from_status(&status)
+        })
+    }
+
+    /// Run an idempotent request, retrying it with backoff while it fails because the endpoint
+    /// is unavailable or cannot be connected to
+    pub async fn retry<T, F, Fut>(&self, description: &str, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let options = self.options();
+        let mut failed_attempts = 0;
+        loop {
+            match request().await {
+                Err(e) if failed_attempts < options.max_retries && is_retryable(&e) => {
+                    failed_attempts += 1;
+                    let backoff = options.backoff(failed_attempts);
+                    warn!(
+                        "Retrying request request={} attempt={} backoff_ms={} error={:?}",
+                        description,
+                        failed_attempts,
+                        backoff.as_millis(),
+                        e
+                    );
+                    tokio::time::delay_for(backoff).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn endpoint_url(host: &str, port: usize, tls: Option<&TlsConfig>) -> String {
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    format!("{}://{}:{}", scheme, host, port)
+}
+
+/// Whether a request failed because the endpoint was unavailable, rather than because the
+/// endpoint rejected it, which it does with an error detail attached
+fn is_unavailable(status: &Status) -> bool {
+    if !status.details().is_empty() {
+        return false;
+    }
+    match status.code() {
+        Code::Unavailable => true,
+        Code::Unknown => status.message().contains("transport error"),
+        _ => false,
+    }
+}
+
+/// Whether a failed request may succeed when it is sent again, which is the case when the
+/// endpoint was unavailable or the connection could not be established
+fn is_retryable(e: &BallistaError) -> bool {
+    match e {
+        BallistaError::General(message) => {
+            message.contains("Unavailable")
+                || message.contains("Transport")
+                || message.contains("transport error")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_has_jitter_and_is_bounded() {
+        let options = ClientOptions {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..ClientOptions::default()
+        };
+        for _ in 0..100 {
+            let first = options.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let capped = options.backoff(10);
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+    }
+
+    #[test]
+    fn retry_idempotent_requests_while_unavailable() -> Result<()> {
+        smol::run(async {
+            let pool = ConnectionPool::new(ClientOptions {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+                ..ClientOptions::default()
+            });
+            let mut attempts = 0;
+            let result: Result<usize> = pool
+                .retry("test", || {
+                    attempts += 1;
+                    let attempt = attempts;
+                    async move {
+                        if attempt < 3 {
+                            Err(BallistaError::General(format!(
+                                "{:?}",
+                                Status::unavailable("connection refused")
+                            )))
+                        } else {
+                            Ok(attempt)
+                        }
+                    }
+                })
+                .await;
+            assert_eq!(3, result?);
+
+            // other errors are not retried
+            let mut attempts = 0;
+            let result: Result<()> = pool
+                .retry("test", || {
+                    attempts += 1;
+                    async { Err(BallistaError::General("invalid shuffle id".to_owned())) }
+                })
+                .await;
+            assert!(result.is_err());
+            assert_eq!(1, attempts);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn deadline_exceeded_status_maps_to_error() {
+        let pool = ConnectionPool::new(ClientOptions::default());
+        let status = Status::deadline_exceeded("Job did not complete within its timeout of 10 ms");
+        match pool.check::<()>("localhost", 50051, None, Err(status)) {
+            Err(BallistaError::DeadlineExceeded(message)) => {
+                assert_eq!("Job did not complete within its timeout of 10 ms", message)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        let status = Status::internal("invalid shuffle id");
+        match pool.check::<()>("localhost", 50051, None, Err(status)) {
+            Err(BallistaError::General(_)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cost model for choosing between alternative physical plans. The number of rows and bytes
+//! that a plan produces is estimated from the statistics of the files that it scans, which are
+//! collected by ANALYZE or read from Parquet metadata, and from the selectivity of its filters
+//! and joins.
+
+use crate::datafusion::logicalplan::{Expr, Operator, ScalarValue};
+use crate::distributed::catalog::StatisticsCatalog;
+use crate::distributed::scheduler::estimated_size;
+use crate::execution::physical_plan::{JoinType, PhysicalPlan};
+use crate::execution::statistics::{as_f64, compare_scalars, ColumnStatistics, Statistics};
+
+use std::cmp::Ordering;
+
+/// Fraction of rows that an equality predicate selects when the column has no statistics
+const DEFAULT_EQUALITY_SELECTIVITY: f64 = 0.1;
+
+/// Fraction of rows that a range predicate selects when the column has no statistics
+const DEFAULT_RANGE_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// Fraction of rows selected by predicates that the statistics cannot be used for
+const DEFAULT_SELECTIVITY: f64 = 0.5;
+
+/// Estimate the statistics of the output of a plan. Statistics collected by ANALYZE take
+/// precedence over Parquet metadata, and scans without either only have their size estimated
+/// from the sizes of their files.
+pub fn estimate_statistics(plan: &PhysicalPlan, catalog: &StatisticsCatalog) -> Statistics {
+    let num_columns = plan.as_execution_plan().schema().fields().len();
+    let analyzed = |path: &str, projection: &Option<Vec<usize>>| {
+        catalog.get(path).map(|stats| match projection {
+            Some(projection) => stats.project(projection),
+            None => stats,
+        })
+    };
+    let file_size = || Statistics {
+        total_bytes: estimated_size(plan),
+        ..Statistics::unknown(num_columns)
+    };
+    match plan {
+        PhysicalPlan::ParquetScan(exec) => analyzed(&exec.path, &exec.projection)
+            .or_else(|| exec.statistics().ok())
+            .unwrap_or_else(file_size),
+        PhysicalPlan::CsvScan(exec) => {
+            analyzed(&exec.path, &exec.projection).unwrap_or_else(file_size)
+        }
+        PhysicalPlan::JsonScan(exec) => {
+            analyzed(&exec.path, &exec.projection).unwrap_or_else(file_size)
+        }
+        PhysicalPlan::AvroScan(exec) => {
+            analyzed(&exec.path, &exec.projection).unwrap_or_else(file_size)
+        }
+        PhysicalPlan::IpcScan(exec) => {
+            analyzed(&exec.path, &exec.projection).unwrap_or_else(file_size)
+        }
+        PhysicalPlan::Filter(exec) => {
+            let input = estimate_statistics(&exec.child, catalog);
+            let selectivity = selectivity(&exec.filter_expr, &input);
+            scale(&input, selectivity)
+        }
+        PhysicalPlan::Projection(exec) => {
+            let input = estimate_statistics(&exec.child, catalog);
+            let column = |expr: &Expr| match expr {
+                Expr::Column(i) => input.columns.get(*i).cloned(),
+                Expr::Alias(expr, _) => match expr.as_ref() {
+                    Expr::Column(i) => input.columns.get(*i).cloned(),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let input_columns = input.columns.len().max(1) as u64;
+            Statistics {
+                num_rows: input.num_rows,
+                total_bytes: input
+                    .total_bytes
+                    .map(|bytes| bytes * exec.expr.len() as u64 / input_columns),
+                columns: exec
+                    .expr
+                    .iter()
+                    .map(|e| column(e).unwrap_or_default())
+                    .collect(),
+            }
+        }
+        PhysicalPlan::GlobalLimit(exec) => {
+            limit(&estimate_statistics(&exec.child, catalog), exec.limit)
+        }
+        PhysicalPlan::Repartition(exec) => estimate_statistics(&exec.child, catalog),
+        PhysicalPlan::ShuffleExchange(exec) => estimate_statistics(&exec.child, catalog),
+        PhysicalPlan::Sort(exec) => estimate_statistics(&exec.child, catalog),
+        PhysicalPlan::Union(exec) => exec
+            .inputs
+            .iter()
+            .map(|input| estimate_statistics(input, catalog))
+            .fold(None, |merged: Option<Statistics>, stats| match merged {
+                Some(merged) => Some(merged.merge(&stats)),
+                None => Some(stats),
+            })
+            .unwrap_or_else(|| Statistics::unknown(num_columns)),
+        PhysicalPlan::HashAggregate(exec) => {
+            let input = estimate_statistics(&exec.child, catalog);
+            // each group has a distinct combination of values of the grouping columns
+            let groups = exec
+                .group_expr
+                .iter()
+                .map(|e| match e {
+                    Expr::Column(i) => input.columns.get(*i)?.distinct_count,
+                    _ => None,
+                })
+                .fold(Some(1u64), |n, d| Some(n?.saturating_mul(d?)));
+            let num_rows = match (groups, input.num_rows) {
+                (Some(groups), Some(rows)) => Some(groups.min(rows.max(1))),
+                _ => None,
+            };
+            Statistics {
+                num_rows,
+                ..Statistics::unknown(num_columns)
+            }
+        }
+        PhysicalPlan::HashJoin(exec) => {
+            let left = estimate_statistics(&exec.left, catalog);
+            let right = estimate_statistics(&exec.right, catalog);
+            let left_schema = exec.left.as_execution_plan().schema();
+            let right_schema = exec.right.as_execution_plan().schema();
+            // each row matches the rows of the other input with the same key, assuming that
+            // the keys of the input with fewer distinct keys all occur in the other input
+            let distinct_keys = exec
+                .on
+                .iter()
+                .map(|(l, r)| {
+                    let l = left.columns.get(left_schema.index_of(l).ok()?)?;
+                    let r = right.columns.get(right_schema.index_of(r).ok()?)?;
+                    Some(l.distinct_count?.max(r.distinct_count?).max(1))
+                })
+                .fold(Some(1u64), |n, d| Some(n?.saturating_mul(d?)));
+            let num_rows = match (left.num_rows, right.num_rows) {
+                (Some(l), Some(r)) => {
+                    let matched = match distinct_keys {
+                        Some(d) => l.saturating_mul(r) / d,
+                        None => l.max(r),
+                    };
+                    // outer joins produce every row of the inputs that they preserve
+                    Some(match exec.join_type {
+                        JoinType::Inner => matched,
+                        JoinType::Left => matched.max(l),
+                        JoinType::Right => matched.max(r),
+                        JoinType::Full => matched.max(l + r),
+                    })
+                }
+                _ => None,
+            };
+            let row_bytes = |stats: &Statistics| match (stats.total_bytes, stats.num_rows) {
+                (Some(bytes), Some(rows)) => Some(bytes as f64 / rows.max(1) as f64),
+                _ => None,
+            };
+            let total_bytes = match (num_rows, row_bytes(&left), row_bytes(&right)) {
+                (Some(rows), Some(l), Some(r)) => Some((rows as f64 * (l + r)) as u64),
+                _ => None,
+            };
+            let mut columns = left.columns;
+            columns.extend(right.columns);
+            Statistics {
+                num_rows,
+                total_bytes,
+                columns: columns
+                    .into_iter()
+                    .map(|c| ColumnStatistics {
+                        distinct_count: bounded(c.distinct_count, num_rows),
+                        ..c
+                    })
+                    .collect(),
+            }
+        }
+        _ => Statistics::unknown(num_columns),
+    }
+}
+
+/// Estimate the fraction of the rows of an input that a predicate selects
+pub fn selectivity(predicate: &Expr, input: &Statistics) -> f64 {
+    let flip = |op: &Operator| match op {
+        Operator::Lt => Operator::Gt,
+        Operator::LtEq => Operator::GtEq,
+        Operator::Gt => Operator::Lt,
+        Operator::GtEq => Operator::LtEq,
+        other => other.clone(),
+    };
+    let selectivity = match predicate {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => selectivity(left, input) * selectivity(right, input),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => {
+            let (a, b) = (selectivity(left, input), selectivity(right, input));
+            a + b - a * b
+        }
+        Expr::BinaryExpr { left, op, right } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(i), Expr::Literal(value)) => {
+                comparison_selectivity(input.columns.get(*i), op, value)
+            }
+            (Expr::Literal(value), Expr::Column(i)) => {
+                comparison_selectivity(input.columns.get(*i), &flip(op), value)
+            }
+            _ => DEFAULT_SELECTIVITY,
+        },
+        Expr::Not(expr) => 1.0 - selectivity(expr, input),
+        Expr::IsNull(expr) => null_fraction(expr, input).unwrap_or(DEFAULT_SELECTIVITY),
+        Expr::IsNotNull(expr) => null_fraction(expr, input)
+            .map(|f| 1.0 - f)
+            .unwrap_or(DEFAULT_SELECTIVITY),
+        _ => DEFAULT_SELECTIVITY,
+    };
+    selectivity.max(0.0).min(1.0)
+}
+
+/// Estimate the fraction of rows where comparing a column to a value is true, assuming that
+/// the values are distributed uniformly between the min and max values of the column
+fn comparison_selectivity(
+    column: Option<&ColumnStatistics>,
+    op: &Operator,
+    value: &ScalarValue,
+) -> f64 {
+    let default = match op {
+        Operator::Eq => DEFAULT_EQUALITY_SELECTIVITY,
+        Operator::NotEq => 1.0 - DEFAULT_EQUALITY_SELECTIVITY,
+        Operator::Lt | Operator::LtEq | Operator::Gt | Operator::GtEq => DEFAULT_RANGE_SELECTIVITY,
+        _ => DEFAULT_SELECTIVITY,
+    };
+    let column = match column {
+        Some(column) => column,
+        None => return default,
+    };
+    let bounds = match (&column.min_value, &column.max_value) {
+        (Some(min), Some(max)) => Some((min, max)),
+        _ => None,
+    };
+    let equality = || {
+        // values outside of the bounds of the column match nothing
+        if let Some((min, max)) = bounds {
+            if compare_scalars(value, min) == Some(Ordering::Less)
+                || compare_scalars(value, max) == Some(Ordering::Greater)
+            {
+                return 0.0;
+            }
+        }
+        match column.distinct_count {
+            Some(d) if d > 0 => 1.0 / d as f64,
+            Some(_) => 0.0,
+            None => DEFAULT_EQUALITY_SELECTIVITY,
+        }
+    };
+    // fraction of the range of the column that is below the value
+    let below = || {
+        let (min, max) = bounds?;
+        let (min, max, value) = (as_f64(min)?, as_f64(max)?, as_f64(value)?);
+        if max <= min {
+            return Some(if value > min { 1.0 } else { 0.0 });
+        }
+        Some(((value - min) / (max - min)).max(0.0).min(1.0))
+    };
+    match op {
+        Operator::Eq => equality(),
+        Operator::NotEq => 1.0 - equality(),
+        Operator::Lt | Operator::LtEq => below().unwrap_or(default),
+        Operator::Gt | Operator::GtEq => below().map(|f| 1.0 - f).unwrap_or(default),
+        _ => default,
+    }
+}
+
+fn null_fraction(expr: &Expr, input: &Statistics) -> Option<f64> {
+    match expr {
+        Expr::Column(i) => {
+            let nulls = input.columns.get(*i)?.null_count?;
+            Some(nulls as f64 / input.num_rows?.max(1) as f64)
+        }
+        _ => None,
+    }
+}
+
+/// Statistics of the rows of an input that a filter with the given selectivity produces
+fn scale(input: &Statistics, selectivity: f64) -> Statistics {
+    let scale = |n: u64| (n as f64 * selectivity).ceil() as u64;
+    let num_rows = input.num_rows.map(scale);
+    Statistics {
+        num_rows,
+        total_bytes: input.total_bytes.map(scale),
+        columns: input
+            .columns
+            .iter()
+            .map(|c| ColumnStatistics {
+                null_count: c.null_count.map(scale),
+                distinct_count: bounded(c.distinct_count, num_rows),
+                ..c.clone()
+            })
+            .collect(),
+    }
+}
+
+fn limit(input: &Statistics, n: usize) -> Statistics {
+    match input.num_rows {
+        Some(rows) if rows > n as u64 => scale(input, n as f64 / rows as f64),
+        Some(_) => input.clone(),
+        None => Statistics {
+            num_rows: Some(n as u64),
+            ..input.clone()
+        },
+    }
+}
+
+/// A column cannot have more distinct values than there are rows
+fn bounded(distinct_count: Option<u64>, num_rows: Option<u64>) -> Option<u64> {
+    match (distinct_count, num_rows) {
+        (Some(d), Some(rows)) => Some(d.min(rows)),
+        (d, _) => d,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_selectivity() {
+        let input = Statistics {
+            num_rows: Some(1000),
+            total_bytes: Some(8000),
+            columns: vec![ColumnStatistics {
+                null_count: Some(100),
+                min_value: Some(ScalarValue::Int64(0)),
+                max_value: Some(ScalarValue::Int64(100)),
+                distinct_count: Some(50),
+            }],
+        };
+        let cmp = |op: Operator, n: i64| Expr::BinaryExpr {
+            left: Box::new(Expr::Column(0)),
+            op,
+            right: Box::new(Expr::Literal(ScalarValue::Int64(n))),
+        };
+        let approx = |expected: f64, actual: f64| (expected - actual).abs() < 1e-9;
+
+        assert!(approx(0.02, selectivity(&cmp(Operator::Eq, 10), &input)));
+        assert!(approx(0.0, selectivity(&cmp(Operator::Eq, 200), &input)));
+        assert!(approx(0.25, selectivity(&cmp(Operator::Lt, 25), &input)));
+        assert!(approx(0.75, selectivity(&cmp(Operator::Gt, 25), &input)));
+        assert!(approx(
+            0.1,
+            selectivity(&Expr::IsNull(Box::new(Expr::Column(0))), &input)
+        ));
+        let range = Expr::BinaryExpr {
+            left: Box::new(cmp(Operator::Gt, 50)),
+            op: Operator::And,
+            right: Box::new(cmp(Operator::Lt, 75)),
+        };
+        // conjunctions are assumed to be independent
+        assert!(approx(0.375, selectivity(&range, &input)));
+        // columns without statistics fall back to default selectivities
+        let unknown = Statistics::unknown(1);
+        assert!(approx(
+            DEFAULT_EQUALITY_SELECTIVITY,
+            selectivity(&cmp(Operator::Eq, 10), &unknown)
+        ));
+
+        let filtered = scale(&input, 0.02);
+        assert_eq!(Some(20), filtered.num_rows);
+        assert_eq!(Some(160), filtered.total_bytes);
+        assert_eq!(Some(20), filtered.columns[0].distinct_count);
+    }
+}
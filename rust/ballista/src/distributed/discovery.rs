@@ -0,0 +1,76 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pluggable mechanisms for discovering the executors in a cluster.
+
+use std::sync::Arc;
+
+use crate::distributed::etcd::EtcdDiscovery;
+use crate::distributed::executor::{DiscoveryMode, ExecutorConfig};
+use crate::distributed::k8s::KubernetesDiscovery;
+use crate::distributed::registry::{ExecutorRegistration, RegistryDiscovery};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::ExecutorMeta;
+
+use async_trait::async_trait;
+
+/// Backend that executors announce themselves through, and that the scheduler and clients use
+/// to find the executors in the cluster
+#[async_trait]
+pub trait DiscoveryBackend: Send + Sync {
+    /// Start announcing an executor to the cluster. The executor remains discoverable until
+    /// it is deregistered or the process exits.
+    fn register(&self, registration: ExecutorRegistration) -> Result<()>;
+
+    /// Stop announcing an executor that is shutting down and remove it from the cluster
+    async fn deregister(&self, executor_id: &str) -> Result<()>;
+
+    /// Get the executors that are currently part of the cluster
+    async fn get_executors(&self) -> Result<Vec<ExecutorMeta>>;
+}
+
+/// Create the discovery backend for the configured discovery mode
+pub fn create_discovery_backend(config: &ExecutorConfig) -> Arc<dyn DiscoveryBackend> {
+    match &config.discovery_mode {
+        DiscoveryMode::Etcd => Arc::new(EtcdDiscovery::new(&config.etcd_urls, "default")),
+        DiscoveryMode::Kubernetes(k8s_config) => {
+            Arc::new(KubernetesDiscovery::new(k8s_config.clone()))
+        }
+        DiscoveryMode::Standalone => Arc::new(StandaloneDiscovery {}),
+        DiscoveryMode::Registry { host, port } => Arc::new(RegistryDiscovery::new(
+            host,
+            *port,
+            config.auth_token.clone(),
+            config.tls.clone(),
+        )),
+    }
+}
+
+/// A single executor that does not take part in a cluster
+pub struct StandaloneDiscovery {}
+
+#[async_trait]
+impl DiscoveryBackend for StandaloneDiscovery {
+    fn register(&self, _registration: ExecutorRegistration) -> Result<()> {
+        Ok(())
+    }
+
+    async fn deregister(&self, _executor_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_executors(&self) -> Result<Vec<ExecutorMeta>> {
+        Err(ballista_error("Standalone mode not implemented yet"))
+    }
+}
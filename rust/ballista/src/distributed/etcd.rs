@@ -13,88 +13,263 @@
 // limitations under the License.
 
 //! Support for etcd discovery mechanism.
+//!
+//! Executors register a key under `/ballista/<cluster>/` that is attached to a lease and keep
+//! the lease alive while they are running, so the key is removed shortly after an executor
+//! stops. The scheduler and clients watch the key prefix to track the current executors.
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use crate::error::{ballista_error, Result};
+use crate::distributed::discovery::DiscoveryBackend;
+use crate::distributed::registry::ExecutorRegistration;
+use crate::error::{ballista_error, BallistaError, Result};
 use crate::execution::physical_plan::ExecutorMeta;
 
-use etcd_client::{Client, GetOptions, PutOptions};
-use uuid::Uuid;
+use async_trait::async_trait;
+use etcd_client::{Client, EventType, GetOptions, KeyValue, PutOptions, WatchOptions};
+use log::{debug, info, warn};
 
-/// Start a thread that will register the executor with etcd periodically
-pub fn start_etcd_thread(
-    etcd_urls: &str,
-    cluster_name: &str,
-    uuid: &Uuid,
-    host: &str,
-    port: usize,
-) {
-    let etcd_urls = etcd_urls.to_owned();
-    let cluster_name = cluster_name.to_owned();
-    let host = host.to_owned();
-    let uuid = uuid.to_owned();
-    thread::spawn(move || {
-        smol::run(async move {
-            loop {
-                match Client::connect([&etcd_urls], None).await {
-                    Ok(mut client) => {
-                        println!("Connected to etcd at {} ok", etcd_urls);
-                        let lease_time_seconds = 60;
-                        let key = format!("/ballista/{}/{}", cluster_name, &uuid);
-                        let value = format!("{}:{}", host, port);
-                        match client.lease_grant(lease_time_seconds, None).await {
-                            Ok(lease) => {
-                                let options = PutOptions::new().with_lease(lease.id());
-                                match client.put(key.clone(), value.clone(), Some(options)).await {
-                                    Ok(_) => println!("Registered with etcd as {}.", key),
-                                    Err(e) => println!("etcd put failed: {:?}", e.to_string()),
-                                }
-                            }
-                            Err(e) => println!("etcd lease grant failed: {:?}", e.to_string()),
-                        }
+/// Time after which the key of an executor that stopped keeping its lease alive is removed
+const LEASE_TTL_SECONDS: i64 = 15;
+
+/// Discovery backend that registers executors with etcd leases and watches for changes
+pub struct EtcdDiscovery {
+    etcd_urls: String,
+    cluster_name: String,
+    /// Executors keyed by etcd key, maintained by the watch once it has loaded them
+    executors: Arc<Mutex<Option<HashMap<String, ExecutorMeta>>>>,
+    watch_started: AtomicBool,
+    /// Set once the registered executor deregisters, to stop keeping its lease alive
+    deregistered: Arc<AtomicBool>,
+}
+
+impl EtcdDiscovery {
+    pub fn new(etcd_urls: &str, cluster_name: &str) -> Self {
+        Self {
+            etcd_urls: etcd_urls.to_owned(),
+            cluster_name: cluster_name.to_owned(),
+            executors: Arc::new(Mutex::new(None)),
+            watch_started: AtomicBool::new(false),
+            deregistered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Start a thread that watches the key prefix of the cluster, unless already started
+    fn start_watch(&self) {
+        if self.watch_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let etcd_urls = self.etcd_urls.clone();
+        let prefix = key_prefix(&self.cluster_name);
+        let executors = self.executors.clone();
+        thread::spawn(move || {
+            smol::run(async move {
+                loop {
+                    if let Err(e) = watch_executors(&etcd_urls, &prefix, &executors).await {
+                        warn!("etcd watch failed prefix={} error={:?}", prefix, e);
                     }
-                    Err(e) => println!("Failed to connect to etcd {:?}", e.to_string()),
+                    // changes may be missed until the watch is established again
+                    *executors.lock().expect("failed to lock mutex") = None;
+                    thread::sleep(Duration::from_secs(1));
                 }
-                thread::sleep(Duration::from_secs(15));
-            }
+            });
         });
-    });
+    }
 }
 
-pub async fn etcd_get_executors(etcd_urls: &str, cluster_name: &str) -> Result<Vec<ExecutorMeta>> {
-    match Client::connect([etcd_urls], None).await {
-        Ok(mut client) => {
-            println!("get_executor_ids got client");
-            let key = format!("/ballista/{}", cluster_name);
-            let resp = client
-                .get(key, Some(GetOptions::new().with_all_keys()))
-                .await
-                .map_err(|e| ballista_error(&format!("etcd error {:?}", e)))?;
-
-            let mut execs = vec![];
-            for kv in resp.kvs() {
-                let executor_id = kv.key_str().expect("etcd - empty string in map key");
-                let host_port = kv.value_str().expect("etcd - empty string in map value");
-                let host_port: Vec<_> = host_port.split(':').collect();
-                if host_port.len() == 2 {
-                    let host = &host_port[0];
-                    let port = &host_port[1];
-                    if let Ok(port) = port.to_string().parse::<usize>() {
-                        execs.push(ExecutorMeta {
-                            id: executor_id.to_owned(),
-                            host: host.to_string(),
-                            port,
-                        });
+#[async_trait]
+impl DiscoveryBackend for EtcdDiscovery {
+    fn register(&self, registration: ExecutorRegistration) -> Result<()> {
+        let etcd_urls = self.etcd_urls.clone();
+        let key = format!("{}{}", key_prefix(&self.cluster_name), registration.meta.id);
+        let value = format!("{}:{}", registration.meta.host, registration.meta.port);
+        let deregistered = self.deregistered.clone();
+        thread::spawn(move || {
+            smol::run(async move {
+                while !deregistered.load(Ordering::SeqCst) {
+                    if let Err(e) = keep_registered(&etcd_urls, &key, &value, &deregistered).await {
+                        warn!("etcd registration failed key={} error={:?}", key, e);
+                    }
+                    thread::sleep(Duration::from_secs(1));
+                }
+            });
+        });
+        Ok(())
+    }
+
+    async fn deregister(&self, executor_id: &str) -> Result<()> {
+        self.deregistered.store(true, Ordering::SeqCst);
+        // delete the key rather than waiting for the lease to expire
+        let mut client = Client::connect([self.etcd_urls.as_str()], None)
+            .await
+            .map_err(to_ballista_err)?;
+        let key = format!("{}{}", key_prefix(&self.cluster_name), executor_id);
+        client
+            .delete(key.as_str(), None)
+            .await
+            .map_err(to_ballista_err)?;
+        info!("Deregistered from etcd key={}", key);
+        Ok(())
+    }
+
+    async fn get_executors(&self) -> Result<Vec<ExecutorMeta>> {
+        self.start_watch();
+        let cached: Option<Vec<ExecutorMeta>> = self
+            .executors
+            .lock()
+            .expect("failed to lock mutex")
+            .as_ref()
+            .map(|executors| executors.values().cloned().collect());
+        match cached {
+            Some(executors) => Ok(executors),
+            // the watch has not loaded the executors yet
+            None => etcd_get_executors(&self.etcd_urls, &self.cluster_name).await,
+        }
+    }
+}
+
+fn key_prefix(cluster_name: &str) -> String {
+    format!("/ballista/{}/", cluster_name)
+}
+
+fn to_ballista_err(e: etcd_client::Error) -> BallistaError {
+    ballista_error(&format!("etcd error {:?}", e))
+}
+
+/// Register an executor under a lease and keep the lease alive until an error occurs or the
+/// executor deregisters
+async fn keep_registered(
+    etcd_urls: &str,
+    key: &str,
+    value: &str,
+    deregistered: &AtomicBool,
+) -> Result<()> {
+    let mut client = Client::connect([etcd_urls], None)
+        .await
+        .map_err(to_ballista_err)?;
+    debug!("Connected to etcd etcd_urls={}", etcd_urls);
+    let lease = client
+        .lease_grant(LEASE_TTL_SECONDS, None)
+        .await
+        .map_err(to_ballista_err)?;
+    let options = PutOptions::new().with_lease(lease.id());
+    client
+        .put(key, value, Some(options))
+        .await
+        .map_err(to_ballista_err)?;
+    info!("Registered with etcd key={} lease_id={}", key, lease.id());
+
+    let (mut keeper, mut stream) = client
+        .lease_keep_alive(lease.id())
+        .await
+        .map_err(to_ballista_err)?;
+    loop {
+        thread::sleep(Duration::from_secs(LEASE_TTL_SECONDS as u64 / 3));
+        if deregistered.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        keeper.keep_alive().await.map_err(to_ballista_err)?;
+        match stream.message().await.map_err(to_ballista_err)? {
+            Some(resp) if resp.ttl() > 0 => debug!("Renewed etcd lease lease_id={}", resp.id()),
+            _ => return Err(ballista_error("etcd lease expired")),
+        }
+    }
+}
+
+/// Load the executors under a key prefix and then apply changes to them as they happen
+async fn watch_executors(
+    etcd_urls: &str,
+    prefix: &str,
+    executors: &Mutex<Option<HashMap<String, ExecutorMeta>>>,
+) -> Result<()> {
+    let mut client = Client::connect([etcd_urls], None)
+        .await
+        .map_err(to_ballista_err)?;
+    let resp = client
+        .get(prefix, Some(GetOptions::new().with_prefix()))
+        .await
+        .map_err(to_ballista_err)?;
+    let mut current = HashMap::new();
+    for kv in resp.kvs() {
+        if let Some(executor) = parse_executor(kv) {
+            current.insert(executor.id.clone(), executor);
+        }
+    }
+    info!("Loaded executors from etcd count={}", current.len());
+    *executors.lock().expect("failed to lock mutex") = Some(current);
+
+    // watch from the revision after the one that was loaded so that no change is missed
+    let revision = resp.header().map(|h| h.revision() + 1).unwrap_or(0);
+    let options = WatchOptions::new()
+        .with_prefix()
+        .with_start_revision(revision);
+    let (_watcher, mut stream) = client
+        .watch(prefix, Some(options))
+        .await
+        .map_err(to_ballista_err)?;
+    while let Some(resp) = stream.message().await.map_err(to_ballista_err)? {
+        let mut executors = executors.lock().expect("failed to lock mutex");
+        let executors = executors.get_or_insert_with(HashMap::new);
+        for event in resp.events() {
+            let kv = match event.kv() {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match event.event_type() {
+                EventType::Put => {
+                    if let Some(executor) = parse_executor(kv) {
+                        info!("Executor joined executor_id={}", executor.id);
+                        executors.insert(executor.id.clone(), executor);
+                    }
+                }
+                EventType::Delete => {
+                    if let Some(executor_id) = executor_id(kv) {
+                        info!("Executor left executor_id={}", executor_id);
+                        executors.remove(executor_id);
                     }
                 }
             }
-            Ok(execs)
         }
-        Err(e) => Err(ballista_error(&format!(
-            "Failed to connect to etcd {:?}",
-            e.to_string()
-        ))),
     }
+    Err(ballista_error("etcd watch stream ended"))
+}
+
+/// The executor id is the last segment of the key
+fn executor_id(kv: &KeyValue) -> Option<&str> {
+    kv.key_str().ok().and_then(|key| key.rsplit('/').next())
+}
+
+/// Parse an executor from a key of the form `/ballista/<cluster>/<id>` and value of the form
+/// `host:port`
+fn parse_executor(kv: &KeyValue) -> Option<ExecutorMeta> {
+    let id = executor_id(kv)?;
+    let host_port: Vec<_> = kv.value_str().ok()?.split(':').collect();
+    if host_port.len() != 2 {
+        return None;
+    }
+    let port = host_port[1].parse::<usize>().ok()?;
+    Some(ExecutorMeta {
+        id: id.to_owned(),
+        host: host_port[0].to_owned(),
+        port,
+    })
+}
+
+pub async fn etcd_get_executors(etcd_urls: &str, cluster_name: &str) -> Result<Vec<ExecutorMeta>> {
+    let mut client = Client::connect([etcd_urls], None)
+        .await
+        .map_err(|e| ballista_error(&format!("Failed to connect to etcd {:?}", e.to_string())))?;
+    debug!("Connected to etcd etcd_urls={}", etcd_urls);
+    let resp = client
+        .get(
+            key_prefix(cluster_name),
+            Some(GetOptions::new().with_prefix()),
+        )
+        .await
+        .map_err(to_ballista_err)?;
+    Ok(resp.kvs().iter().filter_map(parse_executor).collect())
 }
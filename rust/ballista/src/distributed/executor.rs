@@ -12,39 +12,109 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Core executor logic for executing queries and storing results in memory.
+//! Core executor logic for executing queries and storing results in memory, spilling to disk
+//! once the shuffle memory budget is exceeded.
 
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::slice;
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::arrow::datatypes::Schema;
+use crate::arrow::datatypes::{DataType, Schema};
 use crate::arrow::record_batch::RecordBatch;
 use crate::datafusion;
 use crate::datafusion::execution::context::ExecutionContext as DFContext;
 use crate::datafusion::logicalplan::LogicalPlan;
-use crate::datafusion::logicalplan::{Expr, LogicalPlanBuilder};
+use crate::datafusion::logicalplan::{Expr, LogicalPlanBuilder, ScalarValue};
 use crate::datafusion::optimizer::optimizer::OptimizerRule;
-use crate::distributed::client::execute_action;
-use crate::distributed::etcd::{etcd_get_executors, start_etcd_thread};
-use crate::distributed::k8s::k8s_get_executors;
+use crate::distributed::catalog::{scanned_path, StatisticsCatalog};
+use crate::distributed::client::{
+    execute_action, execute_task, executor_stats, list_shuffles, push_shuffle, watch_tasks,
+};
+use crate::distributed::column_pruning::prune_columns;
+use crate::distributed::compression::ShuffleCompression;
+use crate::distributed::discovery::{create_discovery_backend, DiscoveryBackend};
+use crate::distributed::explain::{
+    describe_job, describe_job_config, describe_profile, Explanation, DISTRIBUTED_PLAN, JOB_CONFIG,
+    LOGICAL_PLAN, PHYSICAL_PLAN,
+};
+use crate::distributed::external_shuffle::ExternalShuffleStorage;
+use crate::distributed::flight_data::DEFAULT_MAX_MESSAGE_SIZE;
+use crate::distributed::job_state::JobStateStore;
+use crate::distributed::k8s::KubernetesConfig;
+use crate::distributed::placement::{LocalityFirstPlacement, PlacementPolicy};
+use crate::distributed::prepared::{PreparedQuery, PreparedStore};
+use crate::distributed::progress::ProgressTracker;
+use crate::distributed::registry::ExecutorRegistration;
+use crate::distributed::resources::TaskResources;
 use crate::distributed::scheduler::{
-    create_job, create_physical_plan, ensure_requirements, execute_job, ExecutionTask,
+    create_job, create_physical_plan, ensure_requirements, execute_job, ExecutionTask, JobConfig,
+    JobProfile, JobTimeout, QuerySettings, RetryPolicy,
 };
+use crate::distributed::scheduling::{FifoScheduling, SchedulingPolicy, DEFAULT_TENANT};
+use crate::distributed::shuffle_store::ShuffleStore;
+use crate::distributed::stage_reuse::SharedStages;
+use crate::distributed::tls::TlsConfig;
+use crate::distributed::tracing::{current_trace, in_trace, Span, TraceContext};
 use crate::error::{ballista_error, Result};
+use crate::execution::memory::{MemoryManager, TaskMemory};
+use crate::execution::operators::{hash_partitions, WriteExec, WriteFormat, WriteSummary};
 use crate::execution::physical_plan::{
-    Action, ColumnarBatch, ExecutionContext, ExecutorMeta, PhysicalPlan, ShuffleId,
+    Action, CancellationToken, ColumnarBatch, ExecutionContext, ExecutorMeta, MetricsCollector,
+    Partitioning, PhysicalPlan, ShuffleId, ShuffleLocation, TaskMetrics, TaskUpdateStream,
 };
+use crate::execution::statistics::Statistics;
+use crate::flight::FlightData;
 
 use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
+use log::{debug, info, warn};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ExecutorConfig {
     pub(crate) discovery_mode: DiscoveryMode,
     host: String,
     port: usize,
-    etcd_urls: String,
+    pub(crate) etcd_urls: String,
+    /// Token used to authenticate with other executors
+    pub(crate) auth_token: Option<String>,
+    /// TLS configuration for connections to other executors
+    pub(crate) tls: Option<TlsConfig>,
+    /// Directory that shuffle partitions are spilled to
+    work_dir: PathBuf,
+    /// Maximum number of bytes of shuffle data to hold in memory before spilling to disk
+    shuffle_memory_budget: usize,
+    /// Maximum number of bytes that the operators of all running tasks may reserve before
+    /// spilling to disk
+    operator_memory_budget: usize,
+    /// Codec that shuffle partitions are compressed with in jobs that this process schedules
+    pub(crate) shuffle_compression: ShuffleCompression,
+    /// Policy for retrying failed tasks when this process schedules jobs
+    pub(crate) retry_policy: RetryPolicy,
+    /// Policy for placing tasks on executors when this process schedules jobs
+    pub(crate) placement_policy: Arc<dyn PlacementPolicy>,
+    /// Policy for sharing the cluster between the tenants of the jobs that this process
+    /// schedules, which is shared by all of its jobs
+    pub(crate) scheduling_policy: Arc<dyn SchedulingPolicy>,
+    /// Batch size, partitioning and skew handling of jobs that this process plans
+    pub(crate) job_config: JobConfig,
+    /// Number of tasks this executor can run concurrently, announced to the registry
+    cores: usize,
+    /// Memory available to this executor in bytes, announced to the registry
+    memory_bytes: u64,
+    /// Max size of the flight data messages that shuffle partitions are sent as
+    pub(crate) max_message_size: usize,
+    /// Output of stages that the jobs this process schedules share with later jobs, when
+    /// enabled
+    pub(crate) shared_stages: Option<Arc<SharedStages>>,
+    /// Shared storage that shuffle partitions are written to and read from, if any
+    pub(crate) shuffle_storage: Option<ExternalShuffleStorage>,
 }
 
 impl ExecutorConfig {
@@ -54,39 +124,347 @@ impl ExecutorConfig {
             host: host.to_owned(),
             port,
             etcd_urls: etcd_urls.to_owned(),
+            auth_token: None,
+            tls: None,
+            work_dir: std::env::temp_dir().join("ballista"),
+            shuffle_memory_budget: usize::MAX,
+            operator_memory_budget: usize::MAX,
+            shuffle_compression: ShuffleCompression::None,
+            retry_policy: RetryPolicy::default(),
+            placement_policy: Arc::new(LocalityFirstPlacement::default()),
+            scheduling_policy: Arc::new(FifoScheduling::default()),
+            job_config: JobConfig::default(),
+            cores: 1,
+            memory_bytes: 0,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            shared_stages: None,
+            shuffle_storage: None,
         }
     }
+
+    /// Authenticate with other executors using the given token
+    pub fn with_auth_token(mut self, auth_token: &str) -> Self {
+        self.auth_token = Some(auth_token.to_owned());
+        self
+    }
+
+    /// Connect to other executors using TLS
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Spill shuffle partitions to `work_dir` once more than `memory_budget` bytes are held
+    /// in memory
+    pub fn with_shuffle_spill(mut self, work_dir: &str, memory_budget: usize) -> Self {
+        self.work_dir = PathBuf::from(work_dir);
+        self.shuffle_memory_budget = memory_budget;
+        self
+    }
+
+    /// Let the operators of running tasks reserve up to `memory_budget` bytes in total, beyond
+    /// which sorts, aggregates, and joins spill to `work_dir`
+    pub fn with_operator_memory_budget(mut self, memory_budget: usize) -> Self {
+        self.operator_memory_budget = memory_budget;
+        self
+    }
+
+    /// Compress shuffle partitions with the given codec, both when they are spilled to disk
+    /// and when they are transferred between executors, in jobs that this process schedules
+    pub fn with_shuffle_compression(mut self, shuffle_compression: ShuffleCompression) -> Self {
+        self.shuffle_compression = shuffle_compression;
+        self
+    }
+
+    /// Retry tasks that fail with a transient error according to the given policy
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Place tasks on executors according to the given policy
+    pub fn with_placement_policy(mut self, placement_policy: Arc<dyn PlacementPolicy>) -> Self {
+        self.placement_policy = placement_policy;
+        self
+    }
+
+    /// Start jobs and dispatch their tasks according to the given policy
+    pub fn with_scheduling_policy(mut self, scheduling_policy: Arc<dyn SchedulingPolicy>) -> Self {
+        self.scheduling_policy = scheduling_policy;
+        self
+    }
+
+    /// Plan and partition the jobs that this process schedules according to the given config
+    pub fn with_job_config(mut self, job_config: JobConfig) -> Self {
+        self.job_config = job_config;
+        self
+    }
+
+    /// Set the resources that this executor announces to the registry
+    pub fn with_resources(mut self, cores: usize, memory_bytes: u64) -> Self {
+        self.cores = cores;
+        self.memory_bytes = memory_bytes;
+        self
+    }
+
+    /// Split shuffle batches into flight data messages of at most `max_message_size` bytes
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Let jobs that this process schedules read the output of stages of earlier jobs that
+    /// completed within `ttl` rather than running the same stages again
+    pub fn with_stage_reuse(mut self, ttl: Duration) -> Self {
+        self.shared_stages = Some(Arc::new(SharedStages::new(ttl)));
+        self
+    }
+
+    /// Write shuffle partitions to shared storage under `root`, such as an S3 bucket or an NFS
+    /// mount, and read them from there, so that they can be read after the executor that
+    /// produced them is gone
+    pub fn with_shuffle_storage(mut self, root: &str) -> Self {
+        self.shuffle_storage = Some(ExternalShuffleStorage::new(root));
+        self
+    }
+}
+
+impl fmt::Debug for ExecutorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // avoid leaking the auth token into logs
+        f.debug_struct("ExecutorConfig")
+            .field("discovery_mode", &self.discovery_mode)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("etcd_urls", &self.etcd_urls)
+            .field(
+                "auth_token",
+                &self.auth_token.as_ref().map(|_| "<redacted>"),
+            )
+            .field("tls", &self.tls)
+            .field("work_dir", &self.work_dir)
+            .field("shuffle_memory_budget", &self.shuffle_memory_budget)
+            .field("operator_memory_budget", &self.operator_memory_budget)
+            .field("shuffle_compression", &self.shuffle_compression)
+            .field("retry_policy", &self.retry_policy)
+            .field("placement_policy", &self.placement_policy)
+            .field("scheduling_policy", &self.scheduling_policy)
+            .field("job_config", &self.job_config)
+            .field("cores", &self.cores)
+            .field("memory_bytes", &self.memory_bytes)
+            .field("max_message_size", &self.max_message_size)
+            .field("stage_reuse", &self.shared_stages.is_some())
+            .field("shuffle_storage", &self.shuffle_storage)
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum DiscoveryMode {
     Etcd,
-    Kubernetes,
+    Kubernetes(KubernetesConfig),
     Standalone,
+    /// Register with the executor at the given host and port, which tracks cluster membership
+    Registry {
+        host: String,
+        port: usize,
+    },
 }
 
 #[derive(Clone)]
 pub struct ShufflePartition {
     pub(crate) schema: Schema,
     pub(crate) data: Vec<RecordBatch>,
+    /// Codec that the partition is compressed with when spilled to disk and when fetched
+    pub(crate) compression: ShuffleCompression,
+}
+
+impl ShufflePartition {
+    pub fn new(schema: Schema, data: Vec<RecordBatch>, compression: ShuffleCompression) -> Self {
+        Self {
+            schema,
+            data,
+            compression,
+        }
+    }
+}
+
+/// Summary of a shuffle partition held by an executor
+#[derive(Debug, Clone)]
+pub struct ShufflePartitionMeta {
+    pub shuffle_id: ShuffleId,
+    pub schema: Schema,
+    pub num_rows: usize,
+    pub num_bytes: usize,
+    pub compression: ShuffleCompression,
+}
+
+/// Number of batches of query results that are fetched ahead of the consumer
+const OUTPUT_BUFFER_SIZE: usize = 8;
+
+/// Stream of record batches
+pub type RecordBatchStream = Pin<Box<dyn Stream<Item = Result<RecordBatch>> + Send + Sync>>;
+
+/// Stream of flight data messages
+pub type FlightDataStream = Pin<Box<dyn Stream<Item = Result<FlightData>> + Send + Sync>>;
+
+/// The output of a job that has been executed across the cluster
+#[derive(Debug, Clone)]
+pub struct JobOutput {
+    /// UUID of the job
+    pub(crate) job_uuid: Uuid,
+    /// Schema of the final results
+    pub(crate) schema: Arc<Schema>,
+    /// Locations of the shuffle partitions produced by the final stage
+    pub(crate) partitions: Vec<ShuffleLocation>,
+    /// Physical plan of the job, before it was divided into stages
+    pub(crate) plan: Arc<PhysicalPlan>,
+    /// Execution profile of the job
+    pub profile: JobProfile,
 }
 
 #[async_trait]
 pub trait Executor: Send + Sync {
-    /// Execute a query and store the resulting shuffle partitions in memory
-    async fn do_task(&self, task: &ExecutionTask) -> Result<ShuffleId>;
+    /// Execute a query and store the resulting shuffle partitions in memory, returning the
+    /// execution metrics of the task. The task stops early with `BallistaError::Cancelled` if
+    /// the cancellation token is cancelled. Operators may use up to `parallelism` threads.
+    async fn do_task(
+        &self,
+        task: &ExecutionTask,
+        cancellation_token: CancellationToken,
+        parallelism: usize,
+    ) -> Result<(ShuffleId, TaskMetrics)>;
 
-    /// Collect the results of a prior task that resulted in a shuffle partition
-    fn collect(&self, shuffle_id: &ShuffleId) -> Result<ShufflePartition>;
+    /// Store a shuffle partition, either produced locally or pushed by another executor
+    fn store_shuffle(&self, shuffle_id: &ShuffleId, partition: ShufflePartition) -> Result<()>;
+
+    /// List the shuffle partitions currently held by this executor
+    fn list_shuffles(&self) -> Vec<ShufflePartitionMeta>;
+
+    /// Discard all shuffle partitions held by this executor and return how many were removed
+    fn clear_shuffles(&self) -> usize;
+
+    /// Discard the shuffle partitions belonging to a job and return how many were removed
+    fn evict_job(&self, job_uuid: &Uuid) -> usize;
+
+    /// Keep shuffle partitions when they are collected, until the given job and the jobs that
+    /// produced them have been evicted
+    fn retain_shuffles(&self, job_uuid: &Uuid, shuffle_ids: &[ShuffleId]) -> Result<()>;
+
+    /// Collect the results of a prior task that resulted in a shuffle partition. The batches
+    /// are returned as a stream so that callers can consume them incrementally, along with a
+    /// summary of the partition that includes its schema and compression codec.
+    fn collect(&self, shuffle_id: &ShuffleId) -> Result<(ShufflePartitionMeta, RecordBatchStream)>;
+
+    /// Collect the results of a prior task as the flight data messages that the shuffle
+    /// partition was stored as, so that they can be served without encoding the batches again
+    fn collect_flight_data(
+        &self,
+        shuffle_id: &ShuffleId,
+    ) -> Result<(ShufflePartitionMeta, FlightDataStream)>;
+
+    /// Execute a query across the cluster and return the locations of the final partitions.
+    /// The settings of the query override the configuration of this executor for the job, and
+    /// the job is scheduled as a job of the given tenant.
+    async fn submit_query(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<JobOutput>;
 
     /// Execute a query and return results
-    async fn execute_query(&self, plan: &LogicalPlan) -> Result<ShufflePartition>;
+    async fn execute_query(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<ShufflePartition>;
+
+    /// Execute a query and return its results as a stream that fetches the final partitions
+    /// only as fast as the batches are consumed
+    async fn execute_query_stream(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<(Schema, RecordBatchStream)>;
+
+    /// Execute a query that writes each partition of its results to a file under `path` and
+    /// return a summary of the files that were written
+    async fn execute_write(
+        &self,
+        plan: &LogicalPlan,
+        path: &str,
+        format: WriteFormat,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<WriteSummary>;
+
+    /// Execute a query and collect statistics of all of its results. When the query is a scan
+    /// of all the columns of a table, the statistics are kept for planning later queries.
+    async fn analyze(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<Statistics>;
+
+    /// Describe the logical, physical, and distributed plans of a query, along with the
+    /// configuration of the job after the settings of the query are applied. With `analyze`,
+    /// the query is executed and the distributed plan shows the metrics of each operator.
+    async fn explain(
+        &self,
+        plan: &LogicalPlan,
+        analyze: bool,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<Explanation>;
+
+    /// Plan a query once and keep its physical plan, so that the tenant can execute it
+    /// repeatedly with different values bound to its parameters. Returns the handle of the
+    /// prepared query and the types of its parameters.
+    fn prepare_query(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<(String, Vec<DataType>)>;
+
+    /// Execute a query that the given tenant prepared with the given values bound to its
+    /// parameters and return its results as a stream, scheduling the job as a job of the tenant
+    async fn execute_prepared_stream(
+        &self,
+        handle: &str,
+        params: &[ScalarValue],
+        tenant: &str,
+    ) -> Result<(Schema, RecordBatchStream)>;
+
+    /// Discard a query that the given tenant prepared, returning false if the tenant prepared
+    /// no query with the handle
+    fn close_prepared(&self, handle: &str, tenant: &str) -> bool;
+
+    /// Push the shuffle partitions held by this executor to another executor, so that they are
+    /// not lost when this executor shuts down, returning how many were pushed
+    async fn hand_off_shuffles(&self, host: &str, port: usize) -> Result<usize>;
+
+    /// Leave the cluster, so that no new tasks are placed on this executor
+    async fn deregister(&self) -> Result<()>;
 }
 
 pub struct DefaultContext {
     /// map from shuffle id to executor uuid
     pub(crate) shuffle_locations: HashMap<ShuffleId, ExecutorMeta>,
     pub(crate) config: ExecutorConfig,
+    cancellation_token: CancellationToken,
+    metrics: MetricsCollector,
+    task_memory: TaskMemory,
+    parallelism: usize,
+    discovery: Arc<dyn DiscoveryBackend>,
+    job_state_store: Option<Arc<dyn JobStateStore>>,
+    job_progress: Option<ProgressTracker>,
+    tenant: String,
+    trace: Option<TraceContext>,
 }
 
 impl DefaultContext {
@@ -97,8 +475,65 @@ impl DefaultContext {
         Self {
             config: config.clone(),
             shuffle_locations,
+            cancellation_token: CancellationToken::new(),
+            metrics: MetricsCollector::new(),
+            task_memory: TaskMemory::unbounded(config.work_dir.clone()),
+            parallelism: 1,
+            discovery: create_discovery_backend(config),
+            job_state_store: None,
+            job_progress: None,
+            tenant: DEFAULT_TENANT.to_owned(),
+            trace: None,
         }
     }
+
+    /// Share a discovery backend rather than creating one for this context
+    pub fn with_discovery(mut self, discovery: Arc<dyn DiscoveryBackend>) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    /// Use a cancellation token that can be cancelled by the caller
+    pub fn with_cancellation_token(mut self, cancellation_token: CancellationToken) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Reserve the memory of operators from the memory of a task on this executor
+    pub fn with_task_memory(mut self, task_memory: TaskMemory) -> Self {
+        self.task_memory = task_memory;
+        self
+    }
+
+    /// Allow operators to use up to `parallelism` threads
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Persist the progress of jobs run with this context in a job state store
+    pub fn with_job_state_store(mut self, job_state_store: Arc<dyn JobStateStore>) -> Self {
+        self.job_state_store = Some(job_state_store);
+        self
+    }
+
+    /// Report the progress of jobs run with this context to a tracker
+    pub fn with_job_progress(mut self, job_progress: ProgressTracker) -> Self {
+        self.job_progress = Some(job_progress);
+        self
+    }
+
+    /// Schedule jobs run with this context as jobs of the given tenant
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = tenant.to_owned();
+        self
+    }
+
+    /// Record the spans of the job or task run with this context as children of the given span
+    pub fn with_trace_context(mut self, trace: Option<TraceContext>) -> Self {
+        self.trace = trace;
+        self
+    }
 }
 
 impl DefaultContext {}
@@ -106,40 +541,65 @@ impl DefaultContext {}
 #[async_trait]
 impl ExecutionContext for DefaultContext {
     async fn get_executor_ids(&self) -> Result<Vec<ExecutorMeta>> {
-        match &self.config.discovery_mode {
-            DiscoveryMode::Etcd => etcd_get_executors(&self.config.etcd_urls, "default").await,
-            DiscoveryMode::Kubernetes => k8s_get_executors("default", "ballista").await,
-            DiscoveryMode::Standalone => Err(ballista_error("Standalone mode not implemented yet")),
-        }
+        self.discovery.get_executors().await
     }
 
     async fn execute_task(
         &self,
         executor_meta: ExecutorMeta,
         task: ExecutionTask,
-    ) -> Result<ShuffleId> {
+    ) -> Result<(ShuffleId, TaskMetrics)> {
         // TODO what is the point of returning this info since it is based on input arg?
         let shuffle_id = ShuffleId::new(task.job_uuid, task.stage_id, task.partition_id);
 
-        let _ = execute_action(
+        let metrics = execute_task(
             &executor_meta.host,
             executor_meta.port,
-            &Action::Execute(task),
+            &task,
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
         )
         .await?;
 
-        Ok(shuffle_id)
+        Ok((shuffle_id, metrics))
     }
 
     async fn read_shuffle(&self, shuffle_id: &ShuffleId) -> Result<Vec<ColumnarBatch>> {
+        // partitions in shared storage are read from there, so that they can be read even if
+        // the executor that produced them is gone, falling back to the executor for
+        // partitions that it did not write to the storage
+        if let Some(storage) = &self.config.shuffle_storage {
+            match storage.read(shuffle_id) {
+                Ok(batches) => {
+                    return Ok(batches
+                        .iter()
+                        .map(|b| ColumnarBatch::from_arrow(b))
+                        .collect())
+                }
+                Err(e) => debug!(
+                    "Failed to read shuffle partition from storage job_uuid={} stage_id={} partition_id={} error={:?}",
+                    shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id, e
+                ),
+            }
+        }
         match self.shuffle_locations.get(shuffle_id) {
             Some(executor_meta) => {
-                let batches = execute_action(
+                let mut span = Span::start("fetch_shuffle", self.trace.as_ref())
+                    .with_attribute("stage_id", shuffle_id.stage_id)
+                    .with_attribute("partition_id", shuffle_id.partition_id)
+                    .with_attribute("executor_id", &executor_meta.id);
+                let fetch = execute_action(
                     &executor_meta.host,
                     executor_meta.port,
                     &Action::FetchShuffle(*shuffle_id),
-                )
-                .await?;
+                    self.config.auth_token.as_deref(),
+                    self.config.tls.as_ref(),
+                );
+                let batches = in_trace(span.context(), fetch).await.map_err(|e| {
+                    span.set_error(format!("{:?}", e));
+                    e
+                })?;
+                span.set_attribute("rows", batches.iter().map(|b| b.num_rows()).sum::<usize>());
                 Ok(batches
                     .iter()
                     .map(|b| ColumnarBatch::from_arrow(b))
@@ -152,14 +612,173 @@ impl ExecutionContext for DefaultContext {
         }
     }
 
+    async fn cancel_task(
+        &self,
+        executor_meta: ExecutorMeta,
+        job_uuid: Uuid,
+        stage_id: usize,
+        partition_id: usize,
+    ) -> Result<()> {
+        let _ = execute_action(
+            &executor_meta.host,
+            executor_meta.port,
+            &Action::CancelTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            },
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn withdraw_task(
+        &self,
+        executor_meta: ExecutorMeta,
+        job_uuid: Uuid,
+        stage_id: usize,
+        partition_id: usize,
+    ) -> Result<()> {
+        let _ = execute_action(
+            &executor_meta.host,
+            executor_meta.port,
+            &Action::WithdrawTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            },
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn release_job(&self, executor_meta: ExecutorMeta, job_uuid: Uuid) -> Result<()> {
+        let _ = execute_action(
+            &executor_meta.host,
+            executor_meta.port,
+            &Action::ReleaseJob(job_uuid),
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn retain_shuffles(
+        &self,
+        executor_meta: ExecutorMeta,
+        job_uuid: Uuid,
+        shuffle_ids: Vec<ShuffleId>,
+    ) -> Result<()> {
+        let _ = execute_action(
+            &executor_meta.host,
+            executor_meta.port,
+            &Action::RetainShuffles {
+                job_uuid,
+                shuffle_ids,
+            },
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn watch_tasks(
+        &self,
+        executor_meta: ExecutorMeta,
+        job_uuid: Uuid,
+    ) -> Result<TaskUpdateStream> {
+        watch_tasks(
+            &executor_meta.host,
+            executor_meta.port,
+            &job_uuid,
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await
+    }
+
+    async fn executor_capacity(&self, executor_meta: ExecutorMeta) -> Result<TaskResources> {
+        let stats = executor_stats(
+            &executor_meta.host,
+            executor_meta.port,
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await?;
+        if stats.cores == 0 {
+            return Err(ballista_error("Executor does not advertise its capacity"));
+        }
+        Ok(TaskResources {
+            cores: stats.cores as usize,
+            memory_bytes: stats.memory_bytes,
+        })
+    }
+
+    async fn held_shuffles(&self, executor_meta: ExecutorMeta) -> Result<Vec<ShuffleId>> {
+        list_shuffles(
+            &executor_meta.host,
+            executor_meta.port,
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await
+    }
+
     fn config(&self) -> ExecutorConfig {
         self.config.clone()
     }
+
+    fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    fn metrics(&self) -> MetricsCollector {
+        self.metrics.clone()
+    }
+
+    fn task_memory(&self) -> TaskMemory {
+        self.task_memory.clone()
+    }
+
+    fn parallelism(&self) -> usize {
+        self.parallelism
+    }
+
+    fn job_state_store(&self) -> Option<Arc<dyn JobStateStore>> {
+        self.job_state_store.clone()
+    }
+
+    fn job_progress(&self) -> Option<ProgressTracker> {
+        self.job_progress.clone()
+    }
+
+    fn tenant(&self) -> String {
+        self.tenant.clone()
+    }
+
+    fn trace_context(&self) -> Option<TraceContext> {
+        self.trace
+    }
 }
 
 pub struct BallistaExecutor {
+    /// Id that the executor registered with
+    executor_id: String,
     config: ExecutorConfig,
-    shuffle_partitions: Arc<Mutex<HashMap<String, ShufflePartition>>>,
+    shuffle_store: Arc<ShuffleStore>,
+    /// Memory that the operators of running tasks reserve from
+    memory_manager: MemoryManager,
+    discovery: Arc<dyn DiscoveryBackend>,
+    /// Statistics collected by ANALYZE
+    statistics: Arc<StatisticsCatalog>,
+    /// Queries that clients have prepared on this executor
+    prepared: PreparedStore<PreparedQuery>,
 }
 
 impl BallistaExecutor {
@@ -167,116 +786,406 @@ impl BallistaExecutor {
         let uuid = Uuid::new_v4();
 
         match &config.discovery_mode {
-            DiscoveryMode::Etcd => {
-                println!("Running in etcd mode");
-                start_etcd_thread(
-                    &config.etcd_urls,
-                    "default",
-                    &uuid,
-                    &config.host,
-                    config.port,
-                );
+            DiscoveryMode::Etcd => info!("Running in etcd mode"),
+            DiscoveryMode::Kubernetes(k8s_config) => {
+                info!("Running in k8s mode config={:?}", k8s_config)
             }
-            DiscoveryMode::Kubernetes => println!("Running in k8s mode"),
-            DiscoveryMode::Standalone => println!("Running in standalone mode"),
+            DiscoveryMode::Standalone => info!("Running in standalone mode"),
+            DiscoveryMode::Registry { host, port } => {
+                info!("Running in registry mode host={} port={}", host, port)
+            }
+        }
+
+        let discovery = create_discovery_backend(&config);
+        let registration = ExecutorRegistration {
+            meta: ExecutorMeta {
+                id: uuid.to_string(),
+                host: config.host.clone(),
+                port: config.port,
+            },
+            cores: config.cores,
+            memory_bytes: config.memory_bytes,
+        };
+        if let Err(e) = discovery.register(registration) {
+            warn!("Failed to register executor error={:?}", e);
         }
 
+        let shuffle_store =
+            ShuffleStore::new(config.work_dir.clone(), config.shuffle_memory_budget)
+                .with_max_message_size(config.max_message_size);
+        let shuffle_store = Arc::new(match &config.shuffle_storage {
+            Some(storage) => shuffle_store.with_external_storage(storage.clone()),
+            None => shuffle_store,
+        });
+        let memory_manager =
+            MemoryManager::new(config.operator_memory_budget, config.work_dir.clone());
+
         Self {
+            executor_id: uuid.to_string(),
             config,
-            shuffle_partitions: Arc::new(Mutex::new(HashMap::new())),
+            shuffle_store,
+            memory_manager,
+            discovery,
+            statistics: Arc::new(StatisticsCatalog::new()),
+            prepared: PreparedStore::default(),
         }
     }
 }
 
 #[async_trait]
 impl Executor for BallistaExecutor {
-    async fn do_task(&self, task: &ExecutionTask) -> Result<ShuffleId> {
+    async fn do_task(
+        &self,
+        task: &ExecutionTask,
+        cancellation_token: CancellationToken,
+        parallelism: usize,
+    ) -> Result<(ShuffleId, TaskMetrics)> {
+        let start = Instant::now();
+        let started_at = SystemTime::now();
+        let mut span = Span::start("task", task.trace.as_ref())
+            .with_attribute("job_uuid", task.job_uuid)
+            .with_attribute("stage_id", task.stage_id)
+            .with_attribute("partition_id", task.partition_id)
+            .with_attribute("attempt", task.attempt);
+
         // create new execution contrext specifically for this query
-        let ctx = Arc::new(DefaultContext::new(
-            &self.config,
-            task.shuffle_locations.clone(),
-        ));
+        let ctx = Arc::new(
+            DefaultContext::new(&self.config, task.shuffle_locations.clone())
+                .with_cancellation_token(cancellation_token.clone())
+                .with_discovery(self.discovery.clone())
+                .with_task_memory(self.memory_manager.task_memory(task.memory_limit))
+                .with_parallelism(parallelism)
+                .with_trace_context(Some(span.context())),
+        );
+        let metrics = ctx.metrics();
 
         let shuffle_id = ShuffleId::new(task.job_uuid, task.stage_id, task.partition_id);
 
-        let exec_plan = task.plan.as_execution_plan();
-        let stream = exec_plan.execute(ctx, task.partition_id).await?;
+        let stream = task.plan.execute(ctx, task.partition_id).await?;
+        let schema = stream.schema();
         let mut batches = vec![];
+        let mut shuffle_bytes = 0;
+        // the rows that the next stage reads in each partition, which the scheduler uses to
+        // detect skewed and small partitions
+        let mut partition_rows = match &task.output_partitioning {
+            Some(Partitioning::HashPartitioning(n, _)) => vec![0; *n],
+            _ => vec![],
+        };
         while let Some(batch) = stream.next().await? {
+            cancellation_token.check()?;
+            shuffle_bytes += batch.memory_size();
+            if let Some(Partitioning::HashPartitioning(n, exprs)) = &task.output_partitioning {
+                for partition in hash_partitions(slice::from_ref(&batch), exprs, &schema, *n)? {
+                    partition_rows[partition] += 1;
+                }
+            }
             batches.push(batch.to_arrow()?);
         }
 
-        let key = format!(
-            "{}:{}:{}",
-            shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id
-        );
-        let mut shuffle_partitions = self
-            .shuffle_partitions
-            .lock()
-            .expect("failed to lock mutex");
-        shuffle_partitions.insert(
-            key,
+        let task_metrics = TaskMetrics {
+            output_rows: batches.iter().map(|b| b.num_rows()).sum(),
+            output_batches: batches.len(),
+            shuffle_bytes,
+            duration_ms: start.elapsed().as_millis() as u64,
+            operators: metrics.operators(),
+            partition_rows,
+        };
+        span.set_attribute("rows", task_metrics.output_rows);
+        span.set_attribute("shuffle_bytes", task_metrics.shuffle_bytes);
+        // operators only report the time that they took, so their spans all start with the task
+        for op in &task_metrics.operators {
+            Span::start_at(&op.name, Some(&span.context()), started_at)
+                .with_attribute("rows", op.output_rows)
+                .with_attribute("bytes", op.output_bytes)
+                .end_at(started_at + Duration::from_millis(op.elapsed_ms));
+        }
+
+        self.shuffle_store.store_with_limit(
+            &shuffle_id,
             ShufflePartition {
                 schema: stream.schema().as_ref().clone(),
                 data: batches,
+                compression: task.shuffle_compression,
             },
-        );
+            task.memory_limit,
+        )?;
+
+        Ok((shuffle_id, task_metrics))
+    }
+
+    fn store_shuffle(&self, shuffle_id: &ShuffleId, partition: ShufflePartition) -> Result<()> {
+        self.shuffle_store.store(shuffle_id, partition)
+    }
+
+    fn list_shuffles(&self) -> Vec<ShufflePartitionMeta> {
+        self.shuffle_store.list()
+    }
+
+    fn clear_shuffles(&self) -> usize {
+        self.shuffle_store.clear()
+    }
+
+    fn evict_job(&self, job_uuid: &Uuid) -> usize {
+        self.shuffle_store.evict_job(job_uuid)
+    }
 
-        Ok(shuffle_id)
+    fn retain_shuffles(&self, job_uuid: &Uuid, shuffle_ids: &[ShuffleId]) -> Result<()> {
+        for shuffle_id in shuffle_ids {
+            self.shuffle_store.retain(shuffle_id, job_uuid)?;
+        }
+        Ok(())
+    }
+
+    fn collect(&self, shuffle_id: &ShuffleId) -> Result<(ShufflePartitionMeta, RecordBatchStream)> {
+        self.shuffle_store.take(shuffle_id)
+    }
+
+    fn collect_flight_data(
+        &self,
+        shuffle_id: &ShuffleId,
+    ) -> Result<(ShufflePartitionMeta, FlightDataStream)> {
+        self.shuffle_store.take_flight_data(shuffle_id)
+    }
+
+    async fn submit_query(
+        &self,
+        logical_plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<JobOutput> {
+        self.submit_job(logical_plan, None, settings, tenant).await
+    }
+
+    async fn execute_query(
+        &self,
+        logical_plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<ShufflePartition> {
+        let (schema, stream) = self
+            .execute_query_stream(logical_plan, settings, tenant)
+            .await?;
+        let data = stream.try_collect().await?;
+        Ok(ShufflePartition {
+            schema,
+            data,
+            compression: ShuffleCompression::None,
+        })
+    }
+
+    async fn execute_query_stream(
+        &self,
+        logical_plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<(Schema, RecordBatchStream)> {
+        let output = self.submit_query(logical_plan, settings, tenant).await?;
+        self.stream_output(output).await
+    }
+
+    async fn execute_write(
+        &self,
+        logical_plan: &LogicalPlan,
+        path: &str,
+        format: WriteFormat,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<WriteSummary> {
+        let sink = Some((path.to_owned(), format));
+        let output = self
+            .submit_job(logical_plan, sink, settings, tenant)
+            .await?;
+        let (_, stream) = self.stream_output(output).await?;
+        let batches: Vec<RecordBatch> = stream.try_collect().await?;
+        WriteSummary::try_from_batches(&batches)
+    }
+
+    async fn analyze(
+        &self,
+        logical_plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<Statistics> {
+        let results = self.execute_query(logical_plan, settings, tenant).await?;
+        let statistics = Statistics::from_batches(&results.schema, &results.data)?;
+        if let Some(path) = scanned_path(logical_plan) {
+            info!(
+                "Collected statistics path={} rows={:?}",
+                path, statistics.num_rows
+            );
+            self.statistics.register(path, statistics.clone());
+        }
+        Ok(statistics)
+    }
+
+    async fn explain(
+        &self,
+        logical_plan: &LogicalPlan,
+        analyze: bool,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<Explanation> {
+        let optimized_plan = optimize_logical_plan(logical_plan)?;
+        let job_config = self.config.job_config.with_query_settings(settings);
+        let mut explanation = Explanation::new();
+        explanation.add(JOB_CONFIG, describe_job_config(&job_config, settings));
+        explanation.add(LOGICAL_PLAN, format!("{:?}", optimized_plan));
+        if analyze {
+            let output = self.submit_query(logical_plan, settings, tenant).await?;
+            // the results are read so that the job is released on the executors
+            let (_, stream) = self.stream_output(output.clone()).await?;
+            let _: Vec<RecordBatch> = stream.try_collect().await?;
+            explanation.add(PHYSICAL_PLAN, format!("{:?}", output.plan));
+            explanation.add(DISTRIBUTED_PLAN, describe_profile(&output.profile));
+        } else {
+            let plan = plan_job(&optimized_plan, &job_config, &self.statistics, None)?;
+            explanation.add(PHYSICAL_PLAN, format!("{:?}", plan));
+            explanation.add(DISTRIBUTED_PLAN, describe_job(&create_job(plan)?));
+        }
+        Ok(explanation)
     }
 
-    fn collect(&self, shuffle_id: &ShuffleId) -> Result<ShufflePartition> {
-        let key = format!(
-            "{}:{}:{}",
-            shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id
+    fn prepare_query(
+        &self,
+        logical_plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<(String, Vec<DataType>)> {
+        let logical_plan = optimize_logical_plan(logical_plan)?;
+        let job_config = self.config.job_config.with_query_settings(settings);
+        let plan = plan_job(&logical_plan, &job_config, &self.statistics, None)?;
+        let query = PreparedQuery::try_new(plan, job_config)?;
+        let parameter_types = query.parameter_types.clone();
+        let handle = self.prepared.insert(tenant, query);
+        info!(
+            "Prepared query handle={} tenant={} parameters={}",
+            handle,
+            tenant,
+            parameter_types.len()
         );
-        let mut shuffle_partitions = self
-            .shuffle_partitions
-            .lock()
-            .expect("failed to lock mutex");
-        match shuffle_partitions.remove(&key) {
-            Some(partition) => Ok(partition),
-            _ => Err(ballista_error(&format!(
-                "invalid shuffle partition id {}",
-                key
-            ))),
+        Ok((handle, parameter_types))
+    }
+
+    async fn execute_prepared_stream(
+        &self,
+        handle: &str,
+        params: &[ScalarValue],
+        tenant: &str,
+    ) -> Result<(Schema, RecordBatchStream)> {
+        let query = self
+            .prepared
+            .get(tenant, handle)
+            .ok_or_else(|| ballista_error(&format!("No prepared query with handle {}", handle)))?;
+        let plan = query.bind(params)?;
+        let mut config = self.config.clone();
+        config.job_config = query.job_config;
+        let output = self.run_job(plan, config, tenant)?;
+        self.stream_output(output).await
+    }
+
+    fn close_prepared(&self, handle: &str, tenant: &str) -> bool {
+        self.prepared.remove(tenant, handle)
+    }
+
+    async fn hand_off_shuffles(&self, host: &str, port: usize) -> Result<usize> {
+        let shuffles = self.shuffle_store.list();
+        for meta in &shuffles {
+            let (_, stream) = self.shuffle_store.take(&meta.shuffle_id)?;
+            let batches: Vec<RecordBatch> = stream.try_collect().await?;
+            push_shuffle(
+                host,
+                port,
+                &meta.shuffle_id,
+                &meta.schema,
+                &batches,
+                meta.compression,
+                self.config.max_message_size,
+                self.config.auth_token.as_deref(),
+                self.config.tls.as_ref(),
+            )
+            .await?;
+            debug!(
+                "Handed off shuffle partition job_uuid={} stage_id={} partition_id={} host={} port={}",
+                meta.shuffle_id.job_uuid,
+                meta.shuffle_id.stage_id,
+                meta.shuffle_id.partition_id,
+                host,
+                port
+            );
         }
+        Ok(shuffles.len())
     }
 
-    async fn execute_query(&self, logical_plan: &LogicalPlan) -> Result<ShufflePartition> {
-        println!("Logical plan:\n{:?}", logical_plan);
-        let ctx = DFContext::new();
+    async fn deregister(&self) -> Result<()> {
+        self.discovery.deregister(&self.executor_id).await?;
+        info!("Deregistered executor executor_id={}", self.executor_id);
+        Ok(())
+    }
+}
 
-        // workaround for https://issues.apache.org/jira/browse/ARROW-9542
-        let mut rule = ResolveColumnsRule::new();
-        let logical_plan = rule.optimize(logical_plan)?;
+impl BallistaExecutor {
+    /// Plan and execute a job of a tenant for a query, optionally writing the results to files in
+    /// the given directory and format rather than keeping them in shuffle partitions
+    async fn submit_job(
+        &self,
+        logical_plan: &LogicalPlan,
+        sink: Option<(String, WriteFormat)>,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<JobOutput> {
+        let logical_plan = optimize_logical_plan(logical_plan)?;
 
-        let logical_plan = ctx.optimize(&logical_plan)?;
-        println!("Optimized logical plan:\n{:?}", logical_plan);
+        let mut config = self.config.clone();
+        config.job_config = config.job_config.with_query_settings(settings);
+        let plan = plan_job(&logical_plan, &config.job_config, &self.statistics, sink)?;
+        self.run_job(plan, config, tenant)
+    }
 
-        let config = self.config.clone();
+    /// Execute the physical plan of a job of a tenant across the cluster
+    fn run_job(
+        &self,
+        plan: Arc<PhysicalPlan>,
+        config: ExecutorConfig,
+        tenant: &str,
+    ) -> Result<JobOutput> {
+        let discovery = self.discovery.clone();
+        let tenant = tenant.to_owned();
+        // the job is traced as part of the request that submitted it
+        let trace = current_trace();
         let handle = thread::spawn(move || {
             smol::run(async {
-                let plan: Arc<PhysicalPlan> = create_physical_plan(&logical_plan)?;
-                println!("Physical plan:\n{:?}", plan);
+                let schema = plan.as_execution_plan().schema();
 
-                let plan = ensure_requirements(plan.as_ref())?;
-                println!("Optimized physical plan:\n{:?}", plan);
-
-                let job = create_job(plan)?;
+                let job = create_job(plan.clone())?;
                 job.explain();
+                let job_span = Span::start("job", trace.as_ref())
+                    .with_attribute("job_uuid", job.id)
+                    .with_attribute("tenant", &tenant);
 
                 // create new execution contrext specifically for this query
-                let ctx = Arc::new(DefaultContext::new(&config, HashMap::new()));
+                let cancellation_token = CancellationToken::new();
+                // progress is tracked so that a job whose timeout passes reports how far it got
+                let progress = ProgressTracker::new();
+                let timeout =
+                    JobTimeout::new(cancellation_token.clone(), config.job_config.timeout)
+                        .with_progress(progress.clone());
+                let ctx = Arc::new(
+                    DefaultContext::new(&config, HashMap::new())
+                        .with_discovery(discovery)
+                        .with_cancellation_token(cancellation_token)
+                        .with_job_progress(progress)
+                        .with_tenant(&tenant)
+                        .with_trace_context(Some(job_span.context())),
+                );
 
-                let batches = execute_job(&job, ctx.clone()).await?;
+                let (partitions, profile) = timeout.run(execute_job(&job, ctx.clone())).await?;
 
-                Ok(ShufflePartition {
-                    schema: batches[0].schema().as_ref().clone(),
-                    data: batches
-                        .iter()
-                        .map(|b| b.to_arrow())
-                        .collect::<Result<Vec<_>>>()?,
+                Ok(JobOutput {
+                    job_uuid: job.id,
+                    schema,
+                    partitions,
+                    plan,
+                    profile,
                 })
             })
         });
@@ -285,6 +1194,108 @@ impl Executor for BallistaExecutor {
             Err(e) => Err(ballista_error(&format!("Executor thread failed: {:?}", e))),
         }
     }
+
+    /// Stream the final partitions of a completed job, fetching them from the executors one
+    /// at a time as the batches are consumed, and release the job on all executors once every
+    /// partition has been read
+    async fn stream_output(&self, output: JobOutput) -> Result<(Schema, RecordBatchStream)> {
+        let shuffle_locations = output
+            .partitions
+            .iter()
+            .map(|loc| (loc.shuffle_id, loc.executor_meta.clone()))
+            .collect();
+        let ctx = DefaultContext::new(&self.config, shuffle_locations)
+            .with_discovery(self.discovery.clone());
+        let output_schema = output.schema.as_ref().clone();
+
+        // the bounded channel stops partitions from being fetched faster than they are consumed
+        let (mut tx, mut rx) = mpsc::channel(OUTPUT_BUFFER_SIZE);
+        thread::spawn(move || {
+            smol::run(async move {
+                'partitions: for loc in &output.partitions {
+                    let batches = match ctx.read_shuffle(&loc.shuffle_id).await {
+                        Ok(batches) => batches,
+                        Err(e) => {
+                            let _ = tx.send(Err(e)).await;
+                            break;
+                        }
+                    };
+                    for batch in batches {
+                        if tx.send(batch.to_arrow()).await.is_err() {
+                            // the consumer dropped the stream
+                            break 'partitions;
+                        }
+                    }
+                }
+
+                // the job is complete so the executors can discard its task statuses and any
+                // shuffle partitions that were not fetched
+                match ctx.get_executor_ids().await {
+                    Ok(executors) => {
+                        for executor in executors {
+                            if let Err(e) = ctx.release_job(executor.clone(), output.job_uuid).await
+                            {
+                                warn!(
+                                    "Failed to release job job_uuid={} executor_id={}: {:?}",
+                                    output.job_uuid, executor.id, e
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => warn!(
+                        "Failed to release job job_uuid={}: {:?}",
+                        output.job_uuid, e
+                    ),
+                }
+            })
+        });
+
+        // prefer the schema of the data that was actually produced, if any
+        let first = rx.next().await;
+        let schema = match &first {
+            Some(Ok(batch)) => batch.schema().as_ref().clone(),
+            _ => output_schema,
+        };
+        Ok((schema, Box::pin(futures::stream::iter(first).chain(rx))))
+    }
+}
+
+/// Create the physical plan of a job from an optimized logical plan, optionally writing the
+/// results to files in the given directory and format
+fn plan_job(
+    logical_plan: &LogicalPlan,
+    job_config: &JobConfig,
+    statistics: &StatisticsCatalog,
+    sink: Option<(String, WriteFormat)>,
+) -> Result<Arc<PhysicalPlan>> {
+    let plan: Arc<PhysicalPlan> = create_physical_plan(logical_plan, job_config)?;
+    debug!("Physical plan:\n{:?}", plan);
+
+    let plan = ensure_requirements(plan.as_ref(), statistics)?;
+    let plan = prune_columns(&plan)?;
+    // the sink runs in the final stage, so each task writes its own partition
+    let plan = match sink {
+        Some((path, format)) => Arc::new(PhysicalPlan::Write(Arc::new(WriteExec::new(
+            plan, &path, format,
+        )))),
+        None => plan,
+    };
+    debug!("Optimized physical plan:\n{:?}", plan);
+    Ok(plan)
+}
+
+/// Optimize a logical plan before it is turned into a job
+pub(crate) fn optimize_logical_plan(logical_plan: &LogicalPlan) -> Result<LogicalPlan> {
+    debug!("Logical plan:\n{:?}", logical_plan);
+    let ctx = DFContext::new();
+
+    // workaround for https://issues.apache.org/jira/browse/ARROW-9542
+    let mut rule = ResolveColumnsRule::new();
+    let logical_plan = rule.optimize(logical_plan)?;
+
+    let logical_plan = ctx.optimize(&logical_plan)?;
+    debug!("Optimized logical plan:\n{:?}", logical_plan);
+    Ok(logical_plan)
 }
 
 /// Replace UnresolvedColumns with Columns
@@ -332,6 +1343,21 @@ impl OptimizerRule for ResolveColumnsRule {
                     .sort(rewrite_expr_list(expr, &input.schema())?)?
                     .build()?)
             }
+            LogicalPlan::Limit { n, input, .. } => {
+                Ok(LogicalPlanBuilder::from(&self.optimize(input)?)
+                    .limit(*n)?
+                    .build()?)
+            }
+            LogicalPlan::Extension { node } => {
+                let inputs = node
+                    .inputs()
+                    .into_iter()
+                    .map(|input| self.optimize(input))
+                    .collect::<datafusion::error::Result<Vec<_>>>()?;
+                Ok(LogicalPlan::Extension {
+                    node: node.from_template(&node.expressions(), &inputs),
+                })
+            }
             _ => Ok(plan.clone()),
         }
     }
@@ -0,0 +1,218 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Descriptions of how a query is planned and executed, which clients request with EXPLAIN and
+//! EXPLAIN ANALYZE. The plans are returned to clients as a batch with one row per plan.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::arrow::array::{StringArray, StringBuilder};
+use crate::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use crate::arrow::record_batch::RecordBatch;
+use crate::distributed::scheduler::{Job, JobConfig, JobProfile, QuerySettings};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{OperatorMetrics, PhysicalPlan};
+
+/// The configuration that the job of a query runs with, after the settings of the query
+/// override the defaults of the executor
+pub const JOB_CONFIG: &str = "job_config";
+
+/// The optimized logical plan of a query
+pub const LOGICAL_PLAN: &str = "logical_plan";
+
+/// The physical plan of a query, with the shuffles that divide it into stages
+pub const PHYSICAL_PLAN: &str = "physical_plan";
+
+/// The stages of a job and the plans that their tasks execute, annotated with the metrics of
+/// each operator when the job has run
+pub const DISTRIBUTED_PLAN: &str = "distributed_plan";
+
+/// Plans of a query, each described as text
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Explanation {
+    /// Pairs of the type of plan and its description, in the order that the plans are created
+    pub plans: Vec<(String, String)>,
+}
+
+impl Explanation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, plan_type: &str, plan: String) {
+        self.plans.push((plan_type.to_owned(), plan));
+    }
+
+    /// The description of the plan of the given type, if any
+    pub fn plan(&self, plan_type: &str) -> Option<&str> {
+        self.plans
+            .iter()
+            .find(|(t, _)| t == plan_type)
+            .map(|(_, plan)| plan.as_str())
+    }
+
+    pub fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("plan_type", DataType::Utf8, false),
+            Field::new("plan", DataType::Utf8, false),
+        ]))
+    }
+
+    /// Read an explanation from the batches returned by an executor
+    pub fn try_from_batches(batches: &[RecordBatch]) -> Result<Self> {
+        let mut explanation = Self::new();
+        for batch in batches {
+            let column = |i: usize| batch.column(i).as_any().downcast_ref::<StringArray>();
+            match (column(0), column(1)) {
+                (Some(plan_types), Some(plans)) => {
+                    for i in 0..batch.num_rows() {
+                        explanation.add(plan_types.value(i), plans.value(i).to_owned());
+                    }
+                }
+                _ => return Err(ballista_error("Invalid explanation")),
+            }
+        }
+        Ok(explanation)
+    }
+
+    pub fn to_batch(&self) -> Result<RecordBatch> {
+        let mut plan_types = StringBuilder::new(self.plans.len());
+        let mut plans = StringBuilder::new(self.plans.len());
+        for (plan_type, plan) in &self.plans {
+            plan_types.append_value(plan_type)?;
+            plans.append_value(plan)?;
+        }
+        Ok(RecordBatch::try_new(
+            Self::schema(),
+            vec![Arc::new(plan_types.finish()), Arc::new(plans.finish())],
+        )?)
+    }
+}
+
+/// Describe the configuration of a job, noting which settings were overridden by the query
+pub fn describe_job_config(job_config: &JobConfig, settings: &QuerySettings) -> String {
+    let overridden = settings.names();
+    if overridden.is_empty() {
+        format!("{}", job_config)
+    } else {
+        format!("{} (set by query: {})", job_config, overridden.join(", "))
+    }
+}
+
+/// Describe the stages of a job, with the stages that each depends on, the number of tasks that
+/// it runs, and its plan
+pub fn describe_job(job: &Job) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Job {} has {} stages:", job.id, job.stages.len());
+    for stage in &job.stages {
+        let stage = stage.as_ref().borrow();
+        let _ = writeln!(out);
+        match &stage.plan {
+            Some(plan) => {
+                let tasks = plan
+                    .as_execution_plan()
+                    .output_partitioning()
+                    .partition_count();
+                let _ = writeln!(
+                    out,
+                    "Stage {}: tasks={}, depends on stages {:?}",
+                    stage.id, tasks, stage.prior_stages
+                );
+                describe_plan(&mut out, plan, 1, &[], &mut 0);
+            }
+            None => {
+                let _ = writeln!(out, "Stage {} has no plan", stage.id);
+            }
+        }
+    }
+    out
+}
+
+/// Describe the stages of a job that have run, in the order that they completed, with the plan
+/// of each annotated with the metrics of its operators summed over the tasks of the stage
+pub fn describe_profile(profile: &JobProfile) -> String {
+    let mut out = String::new();
+    for (i, stage) in profile.stages.iter().enumerate() {
+        if i > 0 {
+            let _ = writeln!(out);
+        }
+        let total = stage.total();
+        let _ = writeln!(
+            out,
+            "Stage {}: tasks={}, duration_ms={}, output_rows={}, shuffle_bytes={}",
+            stage.stage_id,
+            stage.tasks.len(),
+            stage.duration_ms,
+            total.output_rows,
+            total.shuffle_bytes
+        );
+        describe_plan(&mut out, &stage.plan, 1, &total.operators, &mut 0);
+    }
+    out
+}
+
+/// Write one line per operator of a plan, followed by the metrics of the operator. The metrics
+/// are in pre-order, like the operators, and an operator is only annotated with the next
+/// metrics if they are for an operator of the same type.
+fn describe_plan(
+    out: &mut String,
+    plan: &PhysicalPlan,
+    indent: usize,
+    operators: &[OperatorMetrics],
+    next: &mut usize,
+) {
+    let _ = write!(out, "{}{}", "  ".repeat(indent), plan.describe_operator());
+    match operators.get(*next) {
+        Some(op) if op.name == plan.name() => {
+            *next += 1;
+            let _ = write!(
+                out,
+                " [rows={}, batches={}, bytes={}, time_ms={}]",
+                op.output_rows, op.output_batches, op.output_bytes, op.elapsed_ms
+            );
+        }
+        _ => {}
+    }
+    let _ = writeln!(out);
+    for child in plan.display_children() {
+        describe_plan(out, &child, indent + 1, operators, next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_explanation() -> Result<()> {
+        let mut explanation = Explanation::new();
+        explanation.add(
+            LOGICAL_PLAN,
+            "Projection: #id\n  TableScan: employee".to_owned(),
+        );
+        explanation.add(PHYSICAL_PLAN, "Projection: expr=[#0]".to_owned());
+
+        let batch = explanation.to_batch()?;
+        assert_eq!(2, batch.num_rows());
+        let explanation2 = Explanation::try_from_batches(&[batch])?;
+        assert_eq!(explanation, explanation2);
+        assert_eq!(
+            Some("Projection: expr=[#0]"),
+            explanation2.plan(PHYSICAL_PLAN)
+        );
+        assert_eq!(None, explanation2.plan(DISTRIBUTED_PLAN));
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shuffle partitions written to storage that is shared by all executors.
+//!
+//! When executors are configured with a shuffle storage root, such as `s3://bucket/shuffle` or
+//! a directory on an NFS mount, every shuffle partition is written as an object under
+//! `{root}/{job_uuid}/{stage_id}/{partition_id}.flight`, and tasks read their input straight
+//! from there. Downstream stages can then read the output of an executor that has died or been
+//! scaled down, so shuffle data outlives the executors that produced it.
+//!
+//! An object holds the schema of the partition followed by its batches, as length-prefixed
+//! flight data messages compressed with the codec of the job. Objects are deleted when the job
+//! is released by the executor that wrote them. Objects of executors that are gone by then are
+//! left in place, and should be expired by the lifecycle rules of the store.
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::Schema;
+use crate::arrow::record_batch::RecordBatch;
+use crate::distributed::flight_data::FlightDataDecoder;
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::ShuffleId;
+use crate::flight::FlightData;
+use crate::object_store::{object_store, ObjectStore};
+
+use log::debug;
+use prost::Message;
+
+/// Shuffle partitions stored under a root path of an object store
+#[derive(Debug, Clone)]
+pub struct ExternalShuffleStorage {
+    root: String,
+}
+
+impl ExternalShuffleStorage {
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: root.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Path of the object that a shuffle partition is stored as
+    pub fn path(&self, shuffle_id: &ShuffleId) -> String {
+        format!(
+            "{}/{}/{}/{}.flight",
+            self.root, shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id
+        )
+    }
+
+    /// The object store that the root is in, configured from the environment
+    fn store(&self) -> Result<Arc<dyn ObjectStore>> {
+        object_store(&self.root)
+    }
+
+    /// Write a shuffle partition, replacing any earlier copy, and return the path it was
+    /// written to
+    pub fn write(
+        &self,
+        shuffle_id: &ShuffleId,
+        schema: &Schema,
+        flights: &[FlightData],
+    ) -> Result<String> {
+        let path = self.path(shuffle_id);
+        let mut buf = vec![];
+        encode_flight_data(&FlightData::from(schema), &mut buf)?;
+        for flight_data in flights {
+            encode_flight_data(flight_data, &mut buf)?;
+        }
+        self.store()?.write(&path, &buf)?;
+        debug!(
+            "Wrote shuffle partition job_uuid={} stage_id={} partition_id={} path={} bytes={}",
+            shuffle_id.job_uuid,
+            shuffle_id.stage_id,
+            shuffle_id.partition_id,
+            path,
+            buf.len()
+        );
+        Ok(path)
+    }
+
+    /// Read the flight data messages of a shuffle partition, without its schema
+    pub fn read_flights(&self, shuffle_id: &ShuffleId) -> Result<Vec<FlightData>> {
+        let (_, flights) = self.read_object(&self.path(shuffle_id))?;
+        Ok(flights)
+    }
+
+    /// Read the batches of a shuffle partition
+    pub fn read(&self, shuffle_id: &ShuffleId) -> Result<Vec<RecordBatch>> {
+        let (schema, flights) = self.read_object(&self.path(shuffle_id))?;
+        let mut decoder = FlightDataDecoder::new(Arc::new(schema));
+        let mut batches = vec![];
+        for flight_data in flights {
+            // messages that carry dictionaries are kept by the decoder
+            if let Some(batch) = decoder.decode(flight_data)? {
+                batches.push(batch);
+            }
+        }
+        Ok(batches)
+    }
+
+    /// Returns true if a shuffle partition has been written
+    pub fn exists(&self, shuffle_id: &ShuffleId) -> bool {
+        let path = self.path(shuffle_id);
+        self.store().and_then(|store| store.size(&path)).is_ok()
+    }
+
+    /// Delete a shuffle partition
+    pub fn delete(&self, shuffle_id: &ShuffleId) -> Result<()> {
+        self.store()?.delete(&self.path(shuffle_id))
+    }
+
+    fn read_object(&self, path: &str) -> Result<(Schema, Vec<FlightData>)> {
+        let buf = self.store()?.read(path)?;
+        let mut flights = decode_flight_data(&buf)?.into_iter();
+        let schema = flights
+            .next()
+            .ok_or_else(|| BallistaError::General(format!("Shuffle object {} is empty", path)))?;
+        Ok((Schema::try_from(&schema)?, flights.collect()))
+    }
+}
+
+/// Append a flight data message to a buffer, prefixed with its length
+fn encode_flight_data(flight_data: &FlightData, buf: &mut Vec<u8>) -> Result<()> {
+    let mut message = vec![];
+    flight_data
+        .encode(&mut message)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    buf.extend_from_slice(&(message.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&message);
+    Ok(())
+}
+
+/// Split a buffer of length-prefixed flight data messages into the messages
+fn decode_flight_data(buf: &[u8]) -> Result<Vec<FlightData>> {
+    let mut flights = vec![];
+    let mut offset = 0;
+    while offset < buf.len() {
+        let truncated = || BallistaError::General("Shuffle object is truncated".to_owned());
+        let mut len = [0u8; 8];
+        len.copy_from_slice(buf.get(offset..offset + 8).ok_or_else(truncated)?);
+        offset += 8;
+        let end = offset + u64::from_le_bytes(len) as usize;
+        let message = buf.get(offset..end).ok_or_else(truncated)?;
+        flights.push(
+            FlightData::decode(message).map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+        );
+        offset = end;
+    }
+    Ok(flights)
+}
@@ -0,0 +1,361 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Faults injected into executors to test how the scheduler retries tasks, speculates, and
+//! recomputes lost shuffle partitions. This module is only built with the `fault-injection`
+//! feature.
+//!
+//! Executors can fail tasks, delay them, drop shuffle fetches, and crash. Whether a fault is
+//! injected is decided by hashing the seed with the stage, partition, and attempt of the task,
+//! or with the number of times that the shuffle partition has been fetched, rather than by
+//! drawing random numbers. The same seed therefore injects the same faults on every run,
+//! whatever the order that tasks run in. Faults are only injected into attempts up to
+//! `max_attempt`, so that retries eventually succeed.
+//!
+//! Faults are configured with a spec of comma-separated settings, such as
+//! `seed=7,task_failure=0.2,slow_task=0.1,slow_task_delay_ms=500,dropped_fetch=0.1,stages=1|2`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::distributed::scheduler::ExecutionTask;
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::ShuffleId;
+
+/// Probabilities and targets of the faults that an executor injects
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultInjectionConfig {
+    /// Seed that the decisions to inject faults are derived from
+    pub seed: u64,
+    /// Probability that a task fails before it runs
+    pub task_failure: f64,
+    /// Probability that a task is delayed by `slow_task_delay` before it runs
+    pub slow_task: f64,
+    pub slow_task_delay: Duration,
+    /// Probability that a fetch of a shuffle partition fails
+    pub dropped_fetch: f64,
+    /// Probability that the executor crashes when it starts a task
+    pub crash: f64,
+    /// Stages whose tasks and shuffle partitions faults are injected into, or all stages when
+    /// empty
+    pub stages: Vec<usize>,
+    /// Highest attempt of a task, or fetch of a shuffle partition, that faults are injected into
+    pub max_attempt: usize,
+}
+
+impl Default for FaultInjectionConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            task_failure: 0.0,
+            slow_task: 0.0,
+            slow_task_delay: Duration::from_secs(1),
+            dropped_fetch: 0.0,
+            crash: 0.0,
+            stages: vec![],
+            max_attempt: 0,
+        }
+    }
+}
+
+impl FaultInjectionConfig {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_task_failure(mut self, probability: f64) -> Self {
+        self.task_failure = probability;
+        self
+    }
+
+    pub fn with_slow_task(mut self, probability: f64, delay: Duration) -> Self {
+        self.slow_task = probability;
+        self.slow_task_delay = delay;
+        self
+    }
+
+    pub fn with_dropped_fetch(mut self, probability: f64) -> Self {
+        self.dropped_fetch = probability;
+        self
+    }
+
+    pub fn with_crash(mut self, probability: f64) -> Self {
+        self.crash = probability;
+        self
+    }
+
+    /// Only inject faults into the tasks and shuffle partitions of the given stages
+    pub fn with_stages(mut self, stages: Vec<usize>) -> Self {
+        self.stages = stages;
+        self
+    }
+
+    pub fn with_max_attempt(mut self, max_attempt: usize) -> Self {
+        self.max_attempt = max_attempt;
+        self
+    }
+
+    /// Parse a spec of comma-separated `key=value` settings, with stages separated by `|`
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for setting in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let parts: Vec<&str> = setting.splitn(2, '=').collect();
+            if parts.len() != 2 {
+                return Err(ballista_error(&format!(
+                    "Invalid fault injection setting '{}', expected key=value",
+                    setting
+                )));
+            }
+            let (key, value) = (parts[0].trim(), parts[1].trim());
+            let invalid = |_| {
+                ballista_error(&format!(
+                    "Invalid value of fault injection setting '{}'",
+                    key
+                ))
+            };
+            match key {
+                "seed" => config.seed = value.parse().map_err(invalid)?,
+                "task_failure" => config.task_failure = parse_probability(key, value)?,
+                "slow_task" => config.slow_task = parse_probability(key, value)?,
+                "slow_task_delay_ms" => {
+                    config.slow_task_delay = Duration::from_millis(value.parse().map_err(invalid)?)
+                }
+                "dropped_fetch" => config.dropped_fetch = parse_probability(key, value)?,
+                "crash" => config.crash = parse_probability(key, value)?,
+                "stages" => {
+                    config.stages = value
+                        .split('|')
+                        .map(|stage| stage.trim().parse().map_err(invalid))
+                        .collect::<Result<_>>()?
+                }
+                "max_attempt" => config.max_attempt = value.parse().map_err(invalid)?,
+                _ => {
+                    return Err(ballista_error(&format!(
+                        "Unknown fault injection setting '{}'",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_probability(key: &str, value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(ballista_error(&format!(
+            "Fault injection setting '{}' must be a probability between 0 and 1",
+            key
+        ))),
+    }
+}
+
+/// A fault injected into a task
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskFault {
+    /// Fail the task without running it
+    Fail,
+    /// Run the task after a delay
+    Slow(Duration),
+    /// Crash the executor
+    Crash,
+}
+
+/// Number of faults of each kind that an executor has injected
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultCounts {
+    pub task_failures: usize,
+    pub slow_tasks: usize,
+    pub dropped_fetches: usize,
+    pub crashes: usize,
+}
+
+/// Decides which faults an executor injects
+#[derive(Debug)]
+pub struct FaultInjector {
+    config: FaultInjectionConfig,
+    /// Abort the process on a crash, rather than stopping the executor in this process
+    exit_process: bool,
+    /// Number of times that each shuffle partition has been fetched
+    fetches: Mutex<HashMap<ShuffleId, usize>>,
+    task_failures: AtomicUsize,
+    slow_tasks: AtomicUsize,
+    dropped_fetches: AtomicUsize,
+    crashes: AtomicUsize,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultInjectionConfig) -> Self {
+        Self {
+            config,
+            exit_process: false,
+            fetches: Mutex::new(HashMap::new()),
+            task_failures: AtomicUsize::new(0),
+            slow_tasks: AtomicUsize::new(0),
+            dropped_fetches: AtomicUsize::new(0),
+            crashes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Abort the process on a crash, for executors that run in their own process
+    pub fn with_process_exit(mut self) -> Self {
+        self.exit_process = true;
+        self
+    }
+
+    pub fn config(&self) -> &FaultInjectionConfig {
+        &self.config
+    }
+
+    pub fn exits_process(&self) -> bool {
+        self.exit_process
+    }
+
+    /// The fault to inject into a task, if any
+    pub fn task_fault(&self, task: &ExecutionTask) -> Option<TaskFault> {
+        if !self.targets(task.stage_id, task.attempt) {
+            return None;
+        }
+        let point = |kind: &str| self.point(kind, task.stage_id, task.partition_id, task.attempt);
+        if point("crash") < self.config.crash {
+            self.crashes.fetch_add(1, Ordering::SeqCst);
+            Some(TaskFault::Crash)
+        } else if point("task_failure") < self.config.task_failure {
+            self.task_failures.fetch_add(1, Ordering::SeqCst);
+            Some(TaskFault::Fail)
+        } else if point("slow_task") < self.config.slow_task {
+            self.slow_tasks.fetch_add(1, Ordering::SeqCst);
+            Some(TaskFault::Slow(self.config.slow_task_delay))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if a fetch of a shuffle partition should fail
+    pub fn drop_fetch(&self, shuffle_id: &ShuffleId) -> bool {
+        let fetch = {
+            let mut fetches = self.fetches.lock().expect("failed to lock mutex");
+            let count = fetches.entry(*shuffle_id).or_insert(0);
+            *count += 1;
+            *count - 1
+        };
+        let dropped = self.targets(shuffle_id.stage_id, fetch)
+            && self.point(
+                "dropped_fetch",
+                shuffle_id.stage_id,
+                shuffle_id.partition_id,
+                fetch,
+            ) < self.config.dropped_fetch;
+        if dropped {
+            self.dropped_fetches.fetch_add(1, Ordering::SeqCst);
+        }
+        dropped
+    }
+
+    /// Number of faults of each kind injected so far
+    pub fn counts(&self) -> FaultCounts {
+        FaultCounts {
+            task_failures: self.task_failures.load(Ordering::SeqCst),
+            slow_tasks: self.slow_tasks.load(Ordering::SeqCst),
+            dropped_fetches: self.dropped_fetches.load(Ordering::SeqCst),
+            crashes: self.crashes.load(Ordering::SeqCst),
+        }
+    }
+
+    fn targets(&self, stage_id: usize, attempt: usize) -> bool {
+        attempt <= self.config.max_attempt
+            && (self.config.stages.is_empty() || self.config.stages.contains(&stage_id))
+    }
+
+    /// A point in [0, 1) derived from the seed, the kind of fault, and where it would be
+    /// injected, which is compared against the probability of the fault
+    fn point(&self, kind: &str, stage_id: usize, partition_id: usize, attempt: usize) -> f64 {
+        let mut hasher = DefaultHasher::new();
+        (self.config.seed, kind, stage_id, partition_id, attempt).hash(&mut hasher);
+        (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::operators::InMemoryTableScanExec;
+    use crate::execution::physical_plan::PhysicalPlan;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn task(stage_id: usize, partition_id: usize, attempt: usize) -> ExecutionTask {
+        let plan = PhysicalPlan::InMemoryTableScan(Arc::new(InMemoryTableScanExec::new(vec![])));
+        ExecutionTask::new(Uuid::new_v4(), stage_id, partition_id, plan, HashMap::new())
+            .with_attempt(attempt)
+    }
+
+    #[test]
+    fn parse_spec() -> Result<()> {
+        let config = FaultInjectionConfig::from_spec(
+            "seed=7, task_failure=0.5,slow_task=0.25,slow_task_delay_ms=10,stages=1|3",
+        )?;
+        assert_eq!(
+            FaultInjectionConfig::new(7)
+                .with_task_failure(0.5)
+                .with_slow_task(0.25, Duration::from_millis(10))
+                .with_stages(vec![1, 3]),
+            config
+        );
+        assert!(FaultInjectionConfig::from_spec("crash=2").is_err());
+        assert!(FaultInjectionConfig::from_spec("unknown=1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn faults_are_deterministic() {
+        let config = FaultInjectionConfig::new(42)
+            .with_task_failure(0.5)
+            .with_dropped_fetch(0.5);
+        let a = FaultInjector::new(config.clone());
+        let b = FaultInjector::new(config);
+        for partition_id in 0..32 {
+            assert_eq!(
+                a.task_fault(&task(1, partition_id, 0)),
+                b.task_fault(&task(1, partition_id, 0))
+            );
+            let shuffle_id = ShuffleId::new(Uuid::new_v4(), 1, partition_id);
+            assert_eq!(a.drop_fetch(&shuffle_id), b.drop_fetch(&shuffle_id));
+        }
+        let counts = a.counts();
+        assert!(counts.task_failures > 0 && counts.task_failures < 32);
+        assert_eq!(counts, b.counts());
+    }
+
+    #[test]
+    fn faults_respect_targets() {
+        let faults = FaultInjector::new(
+            FaultInjectionConfig::new(1)
+                .with_task_failure(1.0)
+                .with_stages(vec![2]),
+        );
+        assert_eq!(None, faults.task_fault(&task(1, 0, 0)));
+        assert_eq!(Some(TaskFault::Fail), faults.task_fault(&task(2, 0, 0)));
+        // retries run without faults
+        assert_eq!(None, faults.task_fault(&task(2, 0, 1)));
+    }
+}
@@ -0,0 +1,402 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of record batches to and from the flight data messages that executors and clients
+//! exchange.
+//!
+//! The flight data conversions in Arrow do not support dictionary-encoded columns, so the
+//! dictionary of each such column is sent as a separate message, ahead of the first batch that
+//! uses it, and the batch itself only carries the keys. Dictionaries that are shared between
+//! batches are only sent once.
+//!
+//! Batches whose messages would be larger than the max message size are split into chunks of
+//! rows, which the decoder concatenates back into the original batch.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::arrow::array::{make_array, Array, ArrayData, ArrayDataRef, ArrayRef, UInt32Array};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use crate::arrow::flight::flight_data_to_batch;
+use crate::arrow::record_batch::RecordBatch;
+use crate::distributed::compression::{decompress_flight_data, ShuffleCompression};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::flight::FlightData;
+use crate::protobuf;
+
+use log::debug;
+use prost::Message;
+
+/// Default max size of a flight data message, which is the default message size limit of most
+/// gRPC implementations
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Read the Ballista metadata of a flight data message, which is empty unless the message is
+/// compressed or carries a dictionary
+pub fn batch_metadata(flight_data: &FlightData) -> Result<protobuf::FlightBatchMetadata> {
+    protobuf::FlightBatchMetadata::decode(flight_data.app_metadata.as_slice())
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+}
+
+/// Replace the Ballista metadata of a flight data message
+pub fn set_batch_metadata(
+    flight_data: &mut FlightData,
+    metadata: &protobuf::FlightBatchMetadata,
+) -> Result<()> {
+    let mut app_metadata = Vec::with_capacity(metadata.encoded_len());
+    metadata
+        .encode(&mut app_metadata)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    flight_data.app_metadata = app_metadata;
+    Ok(())
+}
+
+/// Converts record batches to flight data messages
+pub struct FlightDataEncoder {
+    compression: ShuffleCompression,
+    max_message_size: usize,
+    /// The dictionary most recently sent for each dictionary-encoded column
+    dictionaries: HashMap<usize, ArrayDataRef>,
+}
+
+impl FlightDataEncoder {
+    pub fn new(compression: ShuffleCompression) -> Self {
+        Self {
+            compression,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            dictionaries: HashMap::new(),
+        }
+    }
+
+    /// Split batches into chunks of rows whose messages are at most `max_message_size` bytes
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Convert a batch to flight data, preceded by messages for any dictionaries that have
+    /// not been sent yet
+    pub fn encode(&mut self, batch: &RecordBatch) -> Result<Vec<FlightData>> {
+        let mut flights = vec![];
+        let mut fields = Vec::with_capacity(batch.num_columns());
+        let mut columns = Vec::with_capacity(batch.num_columns());
+        for (i, field) in batch.schema().fields().iter().enumerate() {
+            let column = batch.column(i);
+            match field.data_type() {
+                DataType::Dictionary(key_type, value_type) => {
+                    let data = column.data();
+                    let values = data.child_data()[0].clone();
+                    let sent = self
+                        .dictionaries
+                        .get(&i)
+                        .map(|sent| Arc::ptr_eq(sent, &values))
+                        .unwrap_or(false);
+                    if !sent {
+                        let values_batch = RecordBatch::try_new(
+                            Arc::new(Schema::new(vec![dictionary_field(value_type)])),
+                            vec![make_array(values.clone())],
+                        )?;
+                        let mut flight_data = FlightData::from(&values_batch);
+                        set_batch_metadata(
+                            &mut flight_data,
+                            &protobuf::FlightBatchMetadata {
+                                compression: String::new(),
+                                dictionary: true,
+                                column_index: i as u32,
+                                chunk: false,
+                            },
+                        )?;
+                        flights.push(self.compression.compress_flight_data(flight_data)?);
+                        self.dictionaries.insert(i, values);
+                    }
+                    fields.push(Field::new(
+                        field.name(),
+                        key_type.as_ref().clone(),
+                        field.is_nullable(),
+                    ));
+                    columns.push(dictionary_keys(key_type, &data));
+                }
+                _ => {
+                    fields.push(field.clone());
+                    columns.push(column.clone());
+                }
+            }
+        }
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?;
+        self.encode_chunks(&batch, &mut flights)?;
+        Ok(flights)
+    }
+
+    /// Convert a batch to flight data, splitting it into chunks of rows while its message is
+    /// larger than the max message size, and marking all but the last chunk
+    fn encode_chunks(&self, batch: &RecordBatch, flights: &mut Vec<FlightData>) -> Result<()> {
+        let flight_data = self
+            .compression
+            .compress_flight_data(FlightData::from(batch))?;
+        let size = flight_data.encoded_len();
+        let num_rows = batch.num_rows();
+        if size <= self.max_message_size || num_rows <= 1 {
+            flights.push(flight_data);
+            return Ok(());
+        }
+
+        let num_chunks = size / self.max_message_size.max(1) + 1;
+        let chunk_rows = (num_rows + num_chunks - 1) / num_chunks;
+        let mut start = 0;
+        while start < num_rows {
+            let end = (start + chunk_rows).min(num_rows);
+            // take copies the rows, since sliced arrays are not written correctly to IPC
+            let indices = UInt32Array::from((start as u32..end as u32).collect::<Vec<_>>());
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|c| Ok(compute::take(c, &indices, None)?))
+                .collect::<Result<Vec<_>>>()?;
+            self.encode_chunks(&RecordBatch::try_new(batch.schema(), columns)?, flights)?;
+            if end < num_rows {
+                let last = flights.last_mut().expect("chunk was encoded");
+                let mut metadata = batch_metadata(last)?;
+                metadata.chunk = true;
+                set_batch_metadata(last, &metadata)?;
+            }
+            start = end;
+        }
+        debug!(
+            "Split batch into chunks rows={} bytes={} max_message_size={}",
+            num_rows, size, self.max_message_size
+        );
+        Ok(())
+    }
+}
+
+/// Converts flight data messages back to record batches, keeping track of the dictionaries
+/// that have been received
+pub struct FlightDataDecoder {
+    schema: SchemaRef,
+    /// The schema of the batches as sent, with the keys in place of dictionary-encoded columns
+    key_schema: SchemaRef,
+    dictionaries: HashMap<usize, ArrayRef>,
+    /// Chunks received so far of a batch that was split into chunks
+    chunks: Vec<RecordBatch>,
+}
+
+impl FlightDataDecoder {
+    pub fn new(schema: SchemaRef) -> Self {
+        let key_schema = Schema::new(
+            schema
+                .fields()
+                .iter()
+                .map(|field| match field.data_type() {
+                    DataType::Dictionary(key_type, _) => {
+                        Field::new(field.name(), key_type.as_ref().clone(), field.is_nullable())
+                    }
+                    _ => field.clone(),
+                })
+                .collect(),
+        );
+        Self {
+            schema,
+            key_schema: Arc::new(key_schema),
+            dictionaries: HashMap::new(),
+            chunks: vec![],
+        }
+    }
+
+    /// Decode a flight data message, which returns no batch for messages that carry a
+    /// dictionary or a chunk of a batch other than the last
+    pub fn decode(&mut self, flight_data: FlightData) -> Result<Option<RecordBatch>> {
+        let flight_data = decompress_flight_data(flight_data)?;
+        let metadata = batch_metadata(&flight_data)?;
+        if metadata.dictionary {
+            let i = metadata.column_index as usize;
+            let value_type = match self.schema.fields().get(i).map(|f| f.data_type()) {
+                Some(DataType::Dictionary(_, value_type)) => value_type,
+                _ => {
+                    return Err(ballista_error(&format!(
+                        "Received a dictionary for column {}, which is not dictionary-encoded",
+                        i
+                    )))
+                }
+            };
+            let schema = Arc::new(Schema::new(vec![dictionary_field(value_type)]));
+            let values = to_batch(&flight_data, schema)?;
+            self.dictionaries.insert(i, values.column(0).clone());
+            return Ok(None);
+        }
+
+        let batch = to_batch(&flight_data, self.key_schema.clone())?;
+        if metadata.chunk {
+            self.chunks.push(batch);
+            return Ok(None);
+        }
+        let batch = if self.chunks.is_empty() {
+            batch
+        } else {
+            self.chunks.push(batch);
+            let chunks = std::mem::take(&mut self.chunks);
+            let columns = (0..self.key_schema.fields().len())
+                .map(|i| {
+                    let arrays: Vec<ArrayRef> =
+                        chunks.iter().map(|b| b.column(i).clone()).collect();
+                    Ok(compute::concat(&arrays)?)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            RecordBatch::try_new(self.key_schema.clone(), columns)?
+        };
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match field.data_type() {
+                DataType::Dictionary(_, _) => {
+                    let values = self.dictionaries.get(&i).ok_or_else(|| {
+                        ballista_error(&format!("Missing dictionary for column {}", i))
+                    })?;
+                    Ok(dictionary_array(field.data_type(), batch.column(i), values))
+                }
+                _ => Ok(batch.column(i).clone()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+}
+
+fn dictionary_field(value_type: &DataType) -> Field {
+    Field::new("values", value_type.clone(), true)
+}
+
+fn to_batch(flight_data: &FlightData, schema: SchemaRef) -> Result<RecordBatch> {
+    flight_data_to_batch(flight_data, schema)?
+        .ok_or_else(|| ballista_error("Error converting flight data to columnar batch"))
+}
+
+/// The keys of a dictionary-encoded column, as an array of the key type
+fn dictionary_keys(key_type: &DataType, data: &ArrayDataRef) -> ArrayRef {
+    let mut builder = ArrayData::builder(key_type.clone())
+        .len(data.len())
+        .offset(data.offset())
+        .null_count(data.null_count())
+        .buffers(data.buffers().to_vec());
+    if let Some(nulls) = data.null_buffer() {
+        builder = builder.null_bit_buffer(nulls.clone());
+    }
+    make_array(builder.build())
+}
+
+/// A dictionary-encoded column from its keys and dictionary
+fn dictionary_array(data_type: &DataType, keys: &ArrayRef, values: &ArrayRef) -> ArrayRef {
+    let keys = keys.data();
+    let mut builder = ArrayData::builder(data_type.clone())
+        .len(keys.len())
+        .offset(keys.offset())
+        .null_count(keys.null_count())
+        .buffers(keys.buffers().to_vec())
+        .add_child_data(values.data());
+    if let Some(nulls) = keys.null_buffer() {
+        builder = builder.null_bit_buffer(nulls.clone());
+    }
+    make_array(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{DictionaryArray, Int32Array, StringArray};
+    use crate::arrow::datatypes::Int8Type;
+
+    #[test]
+    fn roundtrip_dictionary_batches() -> Result<()> {
+        let dictionary: DictionaryArray<Int8Type> = vec!["a", "b", "a", "c"].into_iter().collect();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new(
+                "name",
+                DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8)),
+                true,
+            ),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2, 3, 4])),
+                Arc::new(dictionary),
+            ],
+        )?;
+
+        let mut encoder = FlightDataEncoder::new(ShuffleCompression::Lz4);
+        let mut decoder = FlightDataDecoder::new(schema.clone());
+        let mut batches = vec![];
+        for _ in 0..2 {
+            for flight_data in encoder.encode(&batch)? {
+                batches.extend(decoder.decode(flight_data)?);
+            }
+        }
+        // the dictionary is only sent with the first batch
+        assert_eq!(2, batches.len());
+        assert_eq!(1, decoder.dictionaries.len());
+
+        for decoded in &batches {
+            assert_eq!(schema, decoded.schema());
+            let names = decoded
+                .column(1)
+                .as_any()
+                .downcast_ref::<DictionaryArray<Int8Type>>()
+                .expect("dictionary-encoded column");
+            let values = names
+                .values()
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .expect("string dictionary")
+                .clone();
+            let decoded_names: Vec<&str> = names
+                .keys()
+                .map(|key| values.value(key.unwrap() as usize))
+                .collect();
+            assert_eq!(vec!["a", "b", "a", "c"], decoded_names);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_chunked_batches() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int32, false)]));
+        let ids: Vec<i32> = (0..10_000).collect();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(ids.clone()))],
+        )?;
+
+        let mut encoder =
+            FlightDataEncoder::new(ShuffleCompression::None).with_max_message_size(8 * 1024);
+        let flights = encoder.encode(&batch)?;
+        assert!(flights.len() > 1);
+        assert!(flights.iter().all(|f| f.encoded_len() <= 8 * 1024));
+
+        let mut decoder = FlightDataDecoder::new(schema);
+        let mut batches = vec![];
+        for flight_data in flights {
+            batches.extend(decoder.decode(flight_data)?);
+        }
+        assert_eq!(1, batches.len());
+        let decoded = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .expect("int column");
+        assert_eq!(ids, decoded.value_slice(0, decoded.len()).to_vec());
+        Ok(())
+    }
+}
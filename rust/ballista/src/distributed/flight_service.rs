@@ -14,52 +14,186 @@
 
 //! Implementation of the Apache Arrow Flight protocol that wraps an executor.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::convert::{TryFrom, TryInto};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::audit::{AuditEvent, AuditOutcome, AuditSink, RECORD_AUDIT_EVENT};
+use crate::distributed::auth::{
+    authenticated_identity, Authenticator, Credentials, SessionManager, CLUSTER_IDENTITY,
+};
+use crate::distributed::authorization::{authorize_plan, reads_files, Authorizer};
+use crate::distributed::catalog::{infer_file_schema, TableCatalog};
+use crate::distributed::compression::ShuffleCompression;
 use crate::distributed::executor::{Executor, ShufflePartition};
-use crate::distributed::scheduler::{create_job, create_physical_plan, ensure_requirements};
+#[cfg(feature = "fault-injection")]
+use crate::distributed::fault_injection::{FaultInjector, TaskFault};
+use crate::distributed::flight_data::{
+    FlightDataDecoder, FlightDataEncoder, DEFAULT_MAX_MESSAGE_SIZE,
+};
+use crate::distributed::flight_sql::{self, FlightSqlCommand, PreparedStatements};
+use crate::distributed::metrics::ExecutorMetrics;
+use crate::distributed::plan_validation::PlanValidator;
+use crate::distributed::prepared::prepared_query_batch;
+use crate::distributed::registry::{
+    registrations_to_batch, ExecutorRegistry, DEFAULT_HEARTBEAT_TIMEOUT,
+};
+use crate::distributed::resources::{ResourcePool, TaskResources};
+use crate::distributed::scheduler::{task_key, ExecutionTask, QuerySettings};
+use crate::distributed::scheduling::tenant_of;
+use crate::distributed::status::to_status;
+use crate::distributed::tracing::{in_trace, trace_context_of, Span, TraceContext};
+use crate::error::BallistaError;
 use crate::execution::physical_plan;
-use crate::execution::physical_plan::ShuffleId;
+use crate::execution::physical_plan::{
+    CancellationToken, ColumnarBatch, ExecutorAction, ShuffleId, TaskMetrics, TaskUpdate,
+    TaskUpdateState,
+};
 use crate::flight::{
-    flight_service_server::FlightService, Action, ActionType, Criteria, Empty, FlightData,
-    FlightDescriptor, FlightInfo, HandshakeRequest, HandshakeResponse, PutResult, SchemaResult,
-    Ticket,
+    flight_descriptor, flight_service_server::FlightService, Action, ActionType, Criteria, Empty,
+    FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse,
+    Location, PutResult, SchemaResult, Ticket,
 };
-use crate::serde::decode_protobuf;
+use crate::protobuf;
+use crate::protobuf::flight_sql::{
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest,
+    ActionCreatePreparedStatementResult,
+};
+use crate::serde::{decode_protobuf, encode_protobuf};
+use crate::utils::expiring_map::ExpiringMap;
 
-use futures::{Stream, StreamExt};
+use futures::channel::{mpsc, oneshot};
+use futures::{Future, Stream, StreamExt};
+use log::{debug, error, info, warn};
+use prost::Message;
 use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+/// Default time after which the status of a finished task is discarded
+pub const DEFAULT_TASK_STATUS_TTL: Duration = Duration::from_secs(3600);
+
+/// Default maximum number of task statuses to retain
+pub const DEFAULT_MAX_TASK_STATUSES: usize = 10_000;
 
 enum TaskStatus {
+    Queued,
     Running,
-    Completed(ShuffleId),
-    Failed(String),
+    Completed(ShuffleId, TaskMetrics),
+    /// The attempt of the task that failed, and the reason that it failed
+    Failed(usize, String),
+    Cancelled,
+    /// The task was taken back by the scheduler while it was queued, and runs elsewhere
+    Withdrawn,
+}
+
+impl TaskStatus {
+    /// Queued and running tasks are still tracked by the scheduler and must not be evicted
+    fn is_finished(&self) -> bool {
+        !matches!(self, TaskStatus::Queued | TaskStatus::Running)
+    }
+}
+
+/// Scheduler that is watching the transitions of the tasks of a job
+struct TaskWatcher {
+    job_uuid: Uuid,
+    sender: mpsc::UnboundedSender<TaskUpdate>,
+}
+
+/// Outcome of submitting a task to the concurrency guard
+enum Admission {
+    /// The task can start running immediately
+    Run,
+    /// The task has been queued and will run once a slot becomes available
+    Queued,
+    /// The queue is full and the task was rejected
+    Rejected,
 }
 
 struct ConcurrencyGuard {
     concurrency_level: usize,
     max_concurrency: usize,
+    /// Tasks that have been accepted but are waiting for a free slot, in FIFO order
+    queue: VecDeque<ExecutionTask>,
+    max_queue_depth: usize,
+    /// Number of threads that running tasks may use in total, one per core
+    max_threads: usize,
+    /// Max number of threads that a single task may use
+    task_parallelism: usize,
+    /// Threads granted to running tasks in addition to the one that each task holds
+    extra_threads: usize,
+    /// Cores and memory of the executor that running tasks have reserved
+    resources: ResourcePool,
 }
 
 impl ConcurrencyGuard {
-    fn inc(&mut self) -> Result<usize, Status> {
-        if self.concurrency_level < self.max_concurrency {
+    /// Start a task if there is a free slot and its resources fit, unless earlier tasks are
+    /// still waiting for theirs
+    fn admit(&mut self, task: &ExecutionTask) -> Admission {
+        if self.queue.is_empty()
+            && self.concurrency_level < self.max_concurrency
+            && self.resources.try_reserve(&task.resources)
+        {
             self.concurrency_level += 1;
-            println!("Concurrency is {}", self.concurrency_level);
-            Ok(self.concurrency_level)
+            debug!("Concurrency changed concurrency={}", self.concurrency_level);
+            Admission::Run
+        } else if self.queue.len() < self.max_queue_depth {
+            self.queue.push_back(task.clone());
+            debug!("Queue depth changed queue_depth={}", self.queue.len());
+            Admission::Queued
         } else {
-            Err(Status::resource_exhausted("too many concurrent tasks"))
+            Admission::Rejected
         }
     }
 
-    fn dec(&mut self) {
+    /// Release the slot and resources held by a completed task, and hand them over to the
+    /// queued tasks that now fit, in FIFO order. These are returned so that they can be
+    /// started.
+    fn release(&mut self, resources: &TaskResources) -> Vec<ExecutionTask> {
         self.concurrency_level -= 1;
-        println!("Concurrency is {}", self.concurrency_level);
+        self.resources.release(resources);
+        let mut next_tasks = vec![];
+        while let Some(task) = self.queue.front() {
+            if self.concurrency_level >= self.max_concurrency
+                || !self.resources.try_reserve(&task.resources)
+            {
+                break;
+            }
+            self.concurrency_level += 1;
+            next_tasks.extend(self.queue.pop_front());
+        }
+        debug!(
+            "Concurrency changed concurrency={} queue_depth={}",
+            self.concurrency_level,
+            self.queue.len()
+        );
+        next_tasks
+    }
+
+    /// Remove a task from the queue, if it is queued
+    fn remove(&mut self, key: &str) {
+        self.queue.retain(|task| task.key() != key);
+    }
+
+    /// Grant a task that is starting the threads that it may use, which are its own plus up to
+    /// `task_parallelism - 1` threads that no other running task is using
+    fn grant_threads(&mut self) -> usize {
+        let used = self.concurrency_level + self.extra_threads;
+        let extra = (self.task_parallelism - 1).min(self.max_threads.saturating_sub(used));
+        self.extra_threads += extra;
+        debug!("Extra threads changed extra_threads={}", self.extra_threads);
+        1 + extra
+    }
+
+    /// Return the threads granted to a task once it completes
+    fn release_threads(&mut self, parallelism: usize) {
+        self.extra_threads -= parallelism - 1;
+        debug!("Extra threads changed extra_threads={}", self.extra_threads);
     }
 }
 
@@ -69,259 +203,1738 @@ pub struct BallistaFlightService {
     /// Ballista executor implementation
     executor: Arc<dyn Executor>,
     /// Results cache
-    results_cache: Arc<Mutex<HashMap<String, ShufflePartition>>>,
-    task_status_map: Arc<Mutex<HashMap<String, TaskStatus>>>,
+    results_cache: Arc<Mutex<ExpiringMap<ShufflePartition>>>,
+    /// Status of tasks, keyed by task key. Statuses of finished tasks are evicted once they
+    /// expire or the map is full, or when the scheduler releases the job.
+    task_status_map: Arc<Mutex<ExpiringMap<TaskStatus>>>,
+    /// Cancellation tokens for running tasks
+    cancellation_tokens: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    /// Schedulers that the transitions of tasks are pushed to, until their stream is closed or
+    /// the job is released
+    task_watchers: Arc<Mutex<Vec<TaskWatcher>>>,
     /// Concurrency guard to prevent executor from being overwhelmed
     concurrent_tasks: Arc<Mutex<ConcurrencyGuard>>,
+    /// When set, new tasks are rejected so that the executor can be drained
+    draining: Arc<AtomicBool>,
+    /// Set once a graceful shutdown has started, so that it only runs once
+    shutting_down: Arc<AtomicBool>,
+    /// Time that accepted tasks are given to complete when shutting down before they are
+    /// cancelled, or `None` to wait for them indefinitely
+    shutdown_grace_period: Option<Duration>,
+    /// Host and port of the executor that shuffle partitions are handed off to when shutting
+    /// down, so that jobs can still read them
+    shuffle_handoff: Option<(String, usize)>,
+    /// Signals the server to shut down once a graceful shutdown has completed
+    shutdown_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    shutdown_rx: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+    /// Sessions of authenticated clients, if authentication is enabled
+    sessions: Option<SessionManager>,
+    /// Prometheus metrics
+    metrics: Arc<ExecutorMetrics>,
+    /// Executors that have registered with this executor, when it acts as the registry
+    registry: Arc<ExecutorRegistry>,
+    /// Tables that clients have registered by name, by tenant, so that tenants cannot replace
+    /// each other's tables
+    tables: Arc<Mutex<HashMap<String, Arc<TableCatalog>>>>,
+    /// Queries that Flight SQL clients have prepared, by handle
+    prepared_statements: Arc<PreparedStatements>,
+    /// Max size of the flight data messages that query results are sent as
+    max_message_size: usize,
+    /// Limits that the plans of submitted queries and tasks must be within
+    plan_validator: Arc<PlanValidator>,
+    /// Decides which tables, rows and columns the queries of each principal may read, if
+    /// authorization is enabled
+    authorizer: Option<Arc<dyn Authorizer>>,
+    /// Where the queries, tasks, and management actions that the executor receives are
+    /// recorded, if auditing is enabled
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    /// Identities that may forward audit events to this executor to be recorded
+    audit_forwarders: Vec<String>,
+    /// Identities that may manage this executor and the tasks and shuffle partitions of jobs
+    admins: Vec<String>,
+    /// Faults injected into tasks and shuffle fetches, for testing how jobs recover from them
+    #[cfg(feature = "fault-injection")]
+    faults: Option<Arc<FaultInjector>>,
 }
 
 impl BallistaFlightService {
-    pub fn new(executor: Arc<dyn Executor>, max_concurrency: usize) -> Self {
+    pub fn new(
+        executor: Arc<dyn Executor>,
+        max_concurrency: usize,
+        max_queue_depth: usize,
+    ) -> Self {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
         Self {
             executor,
-            results_cache: Arc::new(Mutex::new(HashMap::new())),
-            task_status_map: Arc::new(Mutex::new(HashMap::new())),
+            results_cache: Arc::new(Mutex::new(ExpiringMap::new(
+                DEFAULT_TASK_STATUS_TTL,
+                DEFAULT_MAX_TASK_STATUSES,
+                |_| true,
+            ))),
+            task_status_map: Arc::new(Mutex::new(ExpiringMap::new(
+                DEFAULT_TASK_STATUS_TTL,
+                DEFAULT_MAX_TASK_STATUSES,
+                TaskStatus::is_finished,
+            ))),
+            cancellation_tokens: Arc::new(Mutex::new(HashMap::new())),
+            task_watchers: Arc::new(Mutex::new(vec![])),
             concurrent_tasks: Arc::new(Mutex::new(ConcurrencyGuard {
                 concurrency_level: 0,
                 max_concurrency,
+                queue: VecDeque::new(),
+                max_queue_depth,
+                max_threads: max_concurrency,
+                task_parallelism: 1,
+                extra_threads: 0,
+                resources: ResourcePool::new(TaskResources {
+                    cores: max_concurrency,
+                    memory_bytes: 0,
+                }),
             })),
+            draining: Arc::new(AtomicBool::new(false)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            shutdown_grace_period: None,
+            shuffle_handoff: None,
+            shutdown_tx: Arc::new(Mutex::new(Some(shutdown_tx))),
+            shutdown_rx: Arc::new(Mutex::new(Some(shutdown_rx))),
+            sessions: None,
+            metrics: Arc::new(ExecutorMetrics::try_new().expect("failed to register metrics")),
+            registry: Arc::new(ExecutorRegistry::new(DEFAULT_HEARTBEAT_TIMEOUT)),
+            tables: Arc::new(Mutex::new(HashMap::new())),
+            prepared_statements: Arc::new(PreparedStatements::default()),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            plan_validator: Arc::new(PlanValidator::new()),
+            authorizer: None,
+            audit_sink: None,
+            audit_forwarders: vec![CLUSTER_IDENTITY.to_owned()],
+            admins: vec![CLUSTER_IDENTITY.to_owned()],
+            #[cfg(feature = "fault-injection")]
+            faults: None,
         }
     }
-}
 
-type BoxedFlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+    /// Split query results into flight data messages of at most `max_message_size` bytes
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
 
-#[tonic::async_trait]
-impl FlightService for BallistaFlightService {
-    type HandshakeStream = BoxedFlightStream<HandshakeResponse>;
-    type ListFlightsStream = BoxedFlightStream<FlightInfo>;
-    type DoGetStream = BoxedFlightStream<FlightData>;
-    type DoPutStream = BoxedFlightStream<PutResult>;
-    type DoActionStream = BoxedFlightStream<flight::Result>;
-    type ListActionsStream = BoxedFlightStream<ActionType>;
-    type DoExchangeStream = BoxedFlightStream<FlightData>;
+    /// Remove registered executors that have not sent a heartbeat for longer than `timeout`
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.registry = Arc::new(ExecutorRegistry::new(timeout));
+        self
+    }
 
-    async fn do_get(
+    /// Allow each task to use up to `task_parallelism` threads, as long as the threads of all
+    /// running tasks do not exceed the number of cores of the executor
+    pub fn with_task_parallelism(self, cores: usize, task_parallelism: usize) -> Self {
+        {
+            let mut guard = self.concurrent_tasks.lock().expect("failed to lock mutex");
+            guard.max_threads = cores;
+            guard.task_parallelism = task_parallelism.max(1);
+        }
+        self
+    }
+
+    /// Run tasks only while the cores and memory that they are estimated to need fit within
+    /// the given capacity, which is advertised to schedulers. Zero memory does not limit tasks
+    /// by memory.
+    pub fn with_resources(self, cores: usize, memory_bytes: u64) -> Self {
+        self.concurrent_tasks
+            .lock()
+            .expect("failed to lock mutex")
+            .resources = ResourcePool::new(TaskResources {
+            cores: cores.max(1),
+            memory_bytes,
+        });
+        self
+    }
+
+    /// Discard the status of finished tasks and cached results after `ttl`, and retain at
+    /// most `max_entries` of each
+    pub fn with_retention(self, ttl: Duration, max_entries: usize) -> Self {
+        *self.results_cache.lock().expect("failed to lock mutex") =
+            ExpiringMap::new(ttl, max_entries, |_| true);
+        *self.task_status_map.lock().expect("failed to lock mutex") =
+            ExpiringMap::new(ttl, max_entries, TaskStatus::is_finished);
+        self
+    }
+
+    /// Cancel the tasks that are still queued or running once `grace_period` has passed since
+    /// the executor was asked to shut down, rather than waiting for them to complete
+    pub fn with_shutdown_grace_period(mut self, grace_period: Duration) -> Self {
+        self.shutdown_grace_period = Some(grace_period);
+        self
+    }
+
+    /// Push the shuffle partitions held by this executor to the executor at `host` and `port`
+    /// when shutting down, so that jobs do not need to recompute them
+    pub fn with_shuffle_handoff(mut self, host: &str, port: usize) -> Self {
+        self.shuffle_handoff = Some((host.to_owned(), port));
+        self
+    }
+
+    /// Reject queries and tasks whose plans access paths, or have a depth or number of
+    /// partitions, beyond the limits of the validator
+    pub fn with_plan_validator(mut self, validator: PlanValidator) -> Self {
+        self.plan_validator = Arc::new(validator);
+        self
+    }
+
+    /// Consult the authorizer for the scans of every query, which may deny the query or
+    /// restrict the rows and columns that it reads
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn Authorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Record the queries, tasks, and management actions that the executor receives, along
+    /// with audit events that other executors forward to this executor
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Only record the audit events that clients authenticated as one of these identities
+    /// forward, which is the cluster identity that executors share by default
+    pub fn with_audit_forwarders(mut self, identities: Vec<String>) -> Self {
+        self.audit_forwarders = identities;
+        self
+    }
+
+    /// Only let clients authenticated as one of these identities shut down, drain or clear this
+    /// executor, cancel, withdraw and release the tasks of jobs, register executors, and store
+    /// shuffle partitions, which is the cluster identity that the scheduler and executors share
+    /// by default. Executors that do not authenticate their clients see every client as the
+    /// anonymous identity, which must be listed to let the cluster manage them.
+    pub fn with_admins(mut self, identities: Vec<String>) -> Self {
+        self.admins = identities;
+        self
+    }
+
+    /// Inject faults into the tasks and shuffle fetches of this executor. A crash stops this
+    /// executor, or aborts the process when the injector is set to exit the process.
+    #[cfg(feature = "fault-injection")]
+    pub fn with_fault_injection(mut self, faults: FaultInjector) -> Self {
+        self.faults = Some(Arc::new(faults));
+        self
+    }
+
+    /// Faults injected by this executor, if fault injection is enabled
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(&self) -> Option<Arc<FaultInjector>> {
+        self.faults.clone()
+    }
+
+    /// Require clients to authenticate with the flight handshake before submitting requests
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.sessions = Some(SessionManager::new(authenticator));
+        self
+    }
+
+    /// Returns the session manager if authentication is enabled. Its interceptor must be
+    /// installed on the server so that bearer tokens are validated.
+    pub fn session_manager(&self) -> Option<SessionManager> {
+        self.sessions.clone()
+    }
+
+    /// Ensure that the request was authenticated by the interceptor, if authentication is
+    /// enabled
+    fn check_authenticated<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        match (&self.sessions, authenticated_identity(request)) {
+            (Some(_), None) => Err(Status::unauthenticated("Authentication required")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject a client that may not perform an action that manages this executor or the tasks
+    /// and shuffle partitions of jobs
+    fn check_admin_action(
         &self,
-        request: Request<Ticket>,
-    ) -> Result<Response<Self::DoGetStream>, Status> {
-        let ticket = request.into_inner();
+        action: &physical_plan::Action,
+        principal: &str,
+    ) -> Result<(), Status> {
+        let managing = match action {
+            physical_plan::Action::Manage(action) => {
+                !matches!(action, ExecutorAction::Stats | ExecutorAction::ListShuffles)
+            }
+            physical_plan::Action::CancelTask { .. }
+            | physical_plan::Action::WithdrawTask { .. }
+            | physical_plan::Action::RetainShuffles { .. }
+            | physical_plan::Action::ReleaseJob(_)
+            | physical_plan::Action::RegisterExecutor(_)
+            | physical_plan::Action::Heartbeat { .. }
+            | physical_plan::Action::DeregisterExecutor { .. } => true,
+            _ => false,
+        };
+        if managing {
+            self.check_admin(principal)
+        } else {
+            Ok(())
+        }
+    }
 
-        let action = decode_protobuf(&ticket.ticket.to_vec()).map_err(|e| to_tonic_err(&e))?;
+    fn check_admin(&self, principal: &str) -> Result<(), Status> {
+        if self.admins.iter().any(|admin| admin == principal) {
+            Ok(())
+        } else {
+            warn!("Denied management action principal={}", principal);
+            Err(Status::permission_denied(format!(
+                "{} may not manage the executor",
+                principal
+            )))
+        }
+    }
 
-        //println!("do_get: {:?}", action);
+    /// The tables that a tenant has registered
+    fn tenant_tables(&self, tenant: &str) -> Arc<TableCatalog> {
+        let mut tables = self.tables.lock().expect("failed to lock mutex");
+        tables
+            .entry(tenant.to_owned())
+            .or_insert_with(|| Arc::new(TableCatalog::new()))
+            .clone()
+    }
 
-        match &action {
+    /// Restrict a submitted plan to what the principal may read, and replace the scans of
+    /// registered tables with the plans of those tables
+    fn prepare_plan(&self, plan: &LogicalPlan, principal: &str) -> Result<LogicalPlan, Status> {
+        let plan = match &self.authorizer {
+            Some(authorizer) => {
+                authorize_plan(plan, principal, authorizer.as_ref()).map_err(|e| {
+                    warn!("Denied query principal={} error={:?}", principal, e);
+                    Status::permission_denied(e.to_string())
+                })?
+            }
+            None => plan.clone(),
+        };
+        self.tenant_tables(principal)
+            .resolve(&plan)
+            .map_err(|e| to_tonic_err(&e))
+    }
+
+    /// Start an audit event for an action, if auditing is enabled and the action is audited
+    fn audit_event(&self, action: &physical_plan::Action, principal: &str) -> Option<AuditEvent> {
+        self.audit_sink
+            .as_ref()
+            .and_then(|_| AuditEvent::for_action(action, principal))
+    }
+
+    /// Complete an audit event with the outcome of its action and record it
+    fn record_audit(&self, event: Option<AuditEvent>, outcome: AuditOutcome) {
+        if let (Some(sink), Some(event)) = (&self.audit_sink, event) {
+            let event = event.complete(outcome);
+            if let Err(e) = sink.record(&event) {
+                error!(
+                    "Failed to record audit event event={} error={:?}",
+                    event.to_json(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Record an audit event that another executor forwarded, along with the identity that it
+    /// was forwarded by. Events forwarded by other clients are rejected so that clients cannot
+    /// forge the events of other principals.
+    fn collect_audit_event(&self, body: &[u8], principal: &str) -> Result<(), Status> {
+        let sink = self
+            .audit_sink
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("executor does not collect audit events"))?;
+        // without authentication the identity of the client is not known, so any client could
+        // claim to be a forwarder
+        if self.sessions.is_none() || !self.audit_forwarders.iter().any(|p| p == principal) {
+            warn!("Rejected forwarded audit event principal={}", principal);
+            return Err(Status::permission_denied(format!(
+                "{} may not forward audit events",
+                principal
+            )));
+        }
+        let value: serde_json::Value = serde_json::from_slice(body)
+            .map_err(|e| Status::invalid_argument(format!("Invalid audit event: {}", e)))?;
+        let mut event = AuditEvent::from_json(&value).map_err(|e| to_tonic_err(&e))?;
+        event.forwarded_by = Some(principal.to_owned());
+        debug!(
+            "Collected audit event principal={} forwarded_by={}",
+            event.principal, principal
+        );
+        sink.record(&event).map_err(|e| to_tonic_err(&e))
+    }
+
+    /// Reject a query whose plan or settings are beyond the limits of this executor
+    fn validate_query(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+    ) -> Result<(), Status> {
+        self.plan_validator
+            .validate_query(plan, settings)
+            .map_err(|e| {
+                warn!("Rejected query tenant={} error={:?}", tenant, e);
+                to_tonic_err(&e)
+            })
+    }
+
+    /// Submit a query and describe the flights that its final partitions can be fetched as,
+    /// each from the executor that holds the partition
+    async fn query_flight_info(
+        &self,
+        plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+        descriptor: FlightDescriptor,
+    ) -> Result<FlightInfo, Status> {
+        self.validate_query(plan, settings, tenant)?;
+        let output = self
+            .executor
+            .submit_query(plan, settings, tenant)
+            .await
+            .map_err(|e| to_tonic_err(&e))?;
+
+        // each endpoint points at the executor holding one of the final partitions
+        let endpoint = output
+            .partitions
+            .iter()
+            .map(|loc| {
+                let ticket = encode_protobuf(&physical_plan::Action::FetchShuffle(loc.shuffle_id))
+                    .map_err(|e| to_tonic_err(&e))?;
+                Ok(FlightEndpoint {
+                    ticket: Some(Ticket { ticket }),
+                    location: vec![Location {
+                        uri: format!(
+                            "grpc+tcp://{}:{}",
+                            loc.executor_meta.host, loc.executor_meta.port
+                        ),
+                    }],
+                })
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+
+        Ok(FlightInfo {
+            schema: SchemaResult::from(output.schema.as_ref()).schema,
+            flight_descriptor: Some(descriptor),
+            endpoint,
+            total_records: -1,
+            total_bytes: -1,
+        })
+    }
+
+    /// Describe the results of a Flight SQL command. Queries are submitted at once, while the
+    /// results of metadata commands are produced when the command is sent back as the ticket.
+    async fn flight_sql_info(
+        &self,
+        command: FlightSqlCommand,
+        descriptor: FlightDescriptor,
+        tenant: &str,
+    ) -> Result<FlightInfo, Status> {
+        let plan = match &command {
+            FlightSqlCommand::StatementQuery(query) => {
+                let plan = self
+                    .tenant_tables(tenant)
+                    .parse_sql(&query.query)
+                    .map_err(|e| to_tonic_err(&e))?;
+                self.prepare_plan(&plan, tenant)?
+            }
+            FlightSqlCommand::PreparedStatementQuery(query) => {
+                let handle = String::from_utf8_lossy(&query.prepared_statement_handle);
+                self.prepared_statements
+                    .get(tenant, &handle)
+                    .ok_or_else(|| {
+                        Status::not_found(format!("unknown prepared statement {}", handle))
+                    })?
+            }
+            _ => {
+                let batch = self.flight_sql_metadata(&command, tenant)?;
+                return Ok(FlightInfo {
+                    schema: flight_sql::ipc_schema(batch.schema().as_ref()),
+                    endpoint: vec![FlightEndpoint {
+                        ticket: Some(Ticket {
+                            ticket: descriptor.cmd.clone(),
+                        }),
+                        location: vec![],
+                    }],
+                    flight_descriptor: Some(descriptor),
+                    total_records: batch.num_rows() as i64,
+                    total_bytes: -1,
+                });
+            }
+        };
+        self.query_flight_info(&plan, &QuerySettings::default(), tenant, descriptor)
+            .await
+    }
+
+    /// The results of a Flight SQL metadata command
+    fn flight_sql_metadata(
+        &self,
+        command: &FlightSqlCommand,
+        tenant: &str,
+    ) -> Result<RecordBatch, Status> {
+        match command.metadata(&self.tenant_tables(tenant).tables()) {
+            Some(batch) => batch.map_err(|e| to_tonic_err(&e)),
+            None => Err(Status::invalid_argument(
+                "Flight SQL queries must be submitted with get_flight_info",
+            )),
+        }
+    }
+
+    /// Perform a Flight SQL action, returning `None` if the action is a Ballista action
+    fn flight_sql_action(&self, action: &Action, tenant: &str) -> Result<Option<Vec<u8>>, Status> {
+        match action.r#type.as_str() {
+            flight_sql::CREATE_PREPARED_STATEMENT => {
+                let request: ActionCreatePreparedStatementRequest =
+                    flight_sql::unpack("ActionCreatePreparedStatementRequest", &action.body)
+                        .map_err(|e| to_tonic_err(&e))?;
+                let plan = self
+                    .tenant_tables(tenant)
+                    .parse_sql(&request.query)
+                    .map_err(|e| to_tonic_err(&e))?;
+                // statements are authorized for the principal that prepares them
+                let plan = self.prepare_plan(&plan, tenant)?;
+                let dataset_schema = flight_sql::ipc_schema(plan.schema());
+                let handle = self.prepared_statements.insert(tenant, plan);
+                info!("Prepared statement handle={} tenant={}", handle, tenant);
+
+                let result = ActionCreatePreparedStatementResult {
+                    prepared_statement_handle: handle.into_bytes(),
+                    dataset_schema,
+                    parameter_schema: vec![],
+                };
+                let body = flight_sql::pack("ActionCreatePreparedStatementResult", &result)
+                    .map_err(|e| to_tonic_err(&e))?;
+                Ok(Some(body))
+            }
+            flight_sql::CLOSE_PREPARED_STATEMENT => {
+                let request: ActionClosePreparedStatementRequest =
+                    flight_sql::unpack("ActionClosePreparedStatementRequest", &action.body)
+                        .map_err(|e| to_tonic_err(&e))?;
+                let handle = String::from_utf8_lossy(&request.prepared_statement_handle);
+                if !self.prepared_statements.remove(tenant, &handle) {
+                    return Err(Status::not_found(format!(
+                        "unknown prepared statement {}",
+                        handle
+                    )));
+                }
+                info!("Closed prepared statement handle={}", handle);
+                Ok(Some(vec![]))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a future that completes once a graceful shutdown of the executor has completed.
+    /// This is intended to be passed to the server's `serve_with_shutdown`, and can only be
+    /// obtained once.
+    pub fn shutdown_signal(&self) -> impl Future<Output = ()> {
+        let shutdown_rx = self
+            .shutdown_rx
+            .lock()
+            .expect("failed to lock mutex")
+            .take();
+        async move {
+            match shutdown_rx {
+                Some(shutdown_rx) => shutdown_rx.await.unwrap_or(()),
+                None => futures::future::pending().await,
+            }
+        }
+    }
+
+    /// Shut down gracefully: stop accepting new tasks, wait for accepted tasks to complete
+    /// within the grace period and cancel the rest, hand off shuffle partitions to the
+    /// configured peer, leave the cluster, and then signal the server to shut down. This is
+    /// run by the `Shutdown` action and when the process receives SIGTERM.
+    pub fn shutdown(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let service = self.clone();
+        tokio::spawn(async move {
+            let deadline = service
+                .shutdown_grace_period
+                .map(|grace_period| Instant::now() + grace_period);
+            if !service.wait_for_tasks(deadline).await {
+                let cancelled = service.cancel_accepted_tasks();
+                warn!(
+                    "Cancelled tasks that did not complete within the shutdown grace period count={}",
+                    cancelled
+                );
+                // cancelled tasks free their slots once they next check their cancellation token
+                service.wait_for_tasks(None).await;
+            }
+            info!("All tasks have completed, shutting down");
+
+            if let Some((host, port)) = &service.shuffle_handoff {
+                match service.executor.hand_off_shuffles(host, *port).await {
+                    Ok(count) => info!(
+                        "Handed off shuffle partitions host={} port={} count={}",
+                        host, port, count
+                    ),
+                    Err(e) => warn!(
+                        "Failed to hand off shuffle partitions host={} port={} error={:?}",
+                        host, port, e
+                    ),
+                }
+            }
+            if let Err(e) = service.executor.deregister().await {
+                warn!("Failed to deregister executor error={:?}", e);
+            }
+
+            if let Some(shutdown_tx) = service
+                .shutdown_tx
+                .lock()
+                .expect("failed to lock mutex")
+                .take()
+            {
+                let _ = shutdown_tx.send(());
+            }
+        });
+    }
+
+    /// Wait until no tasks are queued or running, returning false if they have not completed by
+    /// the deadline
+    async fn wait_for_tasks(&self, deadline: Option<Instant>) -> bool {
+        loop {
+            let idle = {
+                let guard = self.concurrent_tasks.lock().expect("failed to lock mutex");
+                guard.concurrency_level == 0 && guard.queue.is_empty()
+            };
+            if idle {
+                return true;
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Stop abruptly, as an injected crash: cancel all tasks without waiting for them and
+    /// stop serving, without handing off shuffle partitions. Heartbeats of an executor in this
+    /// process only stop once it deregisters, so it leaves the cluster as a crashed executor
+    /// would once its heartbeats time out.
+    #[cfg(feature = "fault-injection")]
+    fn crash(&self) {
+        if self
+            .faults
+            .as_ref()
+            .map(|f| f.exits_process())
+            .unwrap_or(false)
+        {
+            error!("Injected crash, aborting the process");
+            std::process::abort();
+        }
+        warn!("Injected crash, stopping the executor");
+        self.draining.store(true, Ordering::SeqCst);
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.cancel_accepted_tasks();
+        if let Some(shutdown_tx) = self
+            .shutdown_tx
+            .lock()
+            .expect("failed to lock mutex")
+            .take()
+        {
+            let _ = shutdown_tx.send(());
+        }
+        let executor = self.executor.clone();
+        tokio::spawn(async move {
+            if let Err(e) = executor.deregister().await {
+                warn!("Failed to deregister crashed executor error={:?}", e);
+            }
+        });
+    }
+
+    /// Run a task on the executor, after injecting any fault into it
+    async fn run_task(
+        &self,
+        task: &ExecutionTask,
+        cancellation_token: CancellationToken,
+        parallelism: usize,
+    ) -> Result<(ShuffleId, TaskMetrics), BallistaError> {
+        #[cfg(feature = "fault-injection")]
+        {
+            match self.faults.as_ref().and_then(|f| f.task_fault(task)) {
+                Some(TaskFault::Fail) => {
+                    return Err(BallistaError::General(format!(
+                        "Injected failure of task {} attempt {}",
+                        task.key(),
+                        task.attempt
+                    )))
+                }
+                Some(TaskFault::Slow(delay)) => {
+                    info!(
+                        "Injected delay of task task_key={} delay_ms={}",
+                        task.key(),
+                        delay.as_millis()
+                    );
+                    tokio::time::delay_for(delay).await;
+                }
+                Some(TaskFault::Crash) => {
+                    self.crash();
+                    return Err(BallistaError::General(format!(
+                        "Executor crashed running task {}",
+                        task.key()
+                    )));
+                }
+                None => {}
+            }
+        }
+        self.executor
+            .do_task(task, cancellation_token, parallelism)
+            .await
+    }
+
+    /// Cancel all queued and running tasks, returning how many were cancelled. The scheduler
+    /// runs the tasks again elsewhere.
+    fn cancel_accepted_tasks(&self) -> usize {
+        let keys: Vec<String> = self
+            .task_status_map
+            .lock()
+            .expect("failed to lock mutex")
+            .iter()
+            .filter(|(_, status)| !status.is_finished())
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter()
+            .filter(|key| self.cancel_task(key).is_ok())
+            .count()
+    }
+
+    /// Cancel a queued or running task and return a description of the outcome. Running tasks
+    /// stop once they next check their cancellation token, at which point their slot is freed.
+    fn cancel_task(&self, key: &str) -> Result<String, Status> {
+        let mut map = self.task_status_map.lock().expect("failed to lock mutex");
+        match map.get(key) {
+            Some(TaskStatus::Queued) => {
+                self.concurrent_tasks
+                    .lock()
+                    .expect("failed to lock mutex")
+                    .remove(key);
+                map.insert(key.to_owned(), TaskStatus::Cancelled);
+                info!("Cancelled queued task task_key={}", key);
+                Ok("cancelled queued task".to_owned())
+            }
+            Some(TaskStatus::Running) => {
+                if let Some(token) = self
+                    .cancellation_tokens
+                    .lock()
+                    .expect("failed to lock mutex")
+                    .get(key)
+                {
+                    token.cancel();
+                }
+                map.insert(key.to_owned(), TaskStatus::Cancelled);
+                info!("Cancelling running task task_key={}", key);
+                Ok("cancelling running task".to_owned())
+            }
+            Some(_) => Ok("task has already finished".to_owned()),
+            None => Err(Status::not_found(format!("unknown task {}", key))),
+        }
+    }
+
+    /// Take back a task that is still queued so that the scheduler can submit it to an idle
+    /// executor instead. Submitting the task here again queues it again. The task is marked as
+    /// withdrawn rather than forgotten, as it may already have left the queue to take a free
+    /// slot, in which case it must not start.
+    fn withdraw_task(&self, key: &str) -> Result<String, Status> {
+        let mut map = self.task_status_map.lock().expect("failed to lock mutex");
+        match map.get(key) {
+            Some(TaskStatus::Queued) => {
+                self.concurrent_tasks
+                    .lock()
+                    .expect("failed to lock mutex")
+                    .remove(key);
+                map.insert(key.to_owned(), TaskStatus::Withdrawn);
+                info!("Withdrew queued task task_key={}", key);
+                Ok("withdrew queued task".to_owned())
+            }
+            Some(_) => Err(Status::failed_precondition(format!(
+                "task {} is not queued",
+                key
+            ))),
+            None => Err(Status::not_found(format!("unknown task {}", key))),
+        }
+    }
+
+    /// Remove all state associated with a job: task statuses, cached results and shuffle
+    /// partitions. Any of its tasks that are still queued or running are cancelled first.
+    fn release_job(&self, job_uuid: &Uuid) -> String {
+        let prefix = format!("{}.", job_uuid);
+        let mut released = 0;
+        {
+            let mut map = self.task_status_map.lock().expect("failed to lock mutex");
+            let mut guard = self.concurrent_tasks.lock().expect("failed to lock mutex");
+            let cancellation_tokens = self
+                .cancellation_tokens
+                .lock()
+                .expect("failed to lock mutex");
+            map.retain(|key, status| {
+                if !key.starts_with(&prefix) {
+                    return true;
+                }
+                match status {
+                    TaskStatus::Queued => guard.remove(key),
+                    TaskStatus::Running => {
+                        if let Some(token) = cancellation_tokens.get(key) {
+                            token.cancel();
+                        }
+                    }
+                    _ => {}
+                }
+                released += 1;
+                false
+            });
+        }
+        self.results_cache
+            .lock()
+            .expect("failed to lock mutex")
+            .remove(&job_uuid.to_string());
+        // dropping the senders ends the streams of the watchers
+        self.task_watchers
+            .lock()
+            .expect("failed to lock mutex")
+            .retain(|watcher| watcher.job_uuid != *job_uuid);
+        let shuffles = self.executor.evict_job(job_uuid);
+        info!(
+            "Released job job_uuid={} tasks={} shuffle_partitions={}",
+            job_uuid, released, shuffles
+        );
+        format!(
+            "released {} tasks and {} shuffle partitions",
+            released, shuffles
+        )
+    }
+
+    /// Keep shuffle partitions held by this executor for a job that reads the output of stages
+    /// of other jobs, failing if any of them are no longer held
+    fn retain_shuffles(
+        &self,
+        job_uuid: &Uuid,
+        shuffle_ids: &[ShuffleId],
+    ) -> Result<String, Status> {
+        self.executor
+            .retain_shuffles(job_uuid, shuffle_ids)
+            .map_err(|_| Status::not_found("shuffle partitions are no longer held"))?;
+        info!(
+            "Retained shuffle partitions job_uuid={} count={}",
+            job_uuid,
+            shuffle_ids.len()
+        );
+        Ok(format!("retained {} shuffle partitions", shuffle_ids.len()))
+    }
+
+    /// Discard a query that the tenant prepared
+    fn close_prepared(&self, handle: &str, tenant: &str) -> Result<String, Status> {
+        if !self.executor.close_prepared(handle, tenant) {
+            return Err(Status::not_found(format!(
+                "no prepared query with handle {}",
+                handle
+            )));
+        }
+        info!("Closed prepared query handle={}", handle);
+        Ok(format!("closed prepared query {}", handle))
+    }
+
+    /// Subscribe to the transitions of the tasks of a job that start after this call
+    fn watch_tasks(&self, job_uuid: Uuid) -> mpsc::UnboundedReceiver<TaskUpdate> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.task_watchers
+            .lock()
+            .expect("failed to lock mutex")
+            .push(TaskWatcher { job_uuid, sender });
+        debug!("Watching tasks job_uuid={}", job_uuid);
+        receiver
+    }
+
+    /// Push the transition of a task to the schedulers watching its job, dropping the watchers
+    /// whose stream has been closed
+    fn notify_watchers(&self, task: &ExecutionTask, state: TaskUpdateState) {
+        let mut watchers = self.task_watchers.lock().expect("failed to lock mutex");
+        if watchers.is_empty() {
+            return;
+        }
+        let update = TaskUpdate {
+            job_uuid: task.job_uuid,
+            stage_id: task.stage_id,
+            partition_id: task.partition_id,
+            attempt: task.attempt,
+            state,
+        };
+        watchers.retain(|watcher| {
+            watcher.job_uuid != task.job_uuid
+                || watcher.sender.unbounded_send(update.clone()).is_ok()
+        });
+    }
+
+    /// Refresh the gauges and encode all metrics in the Prometheus text exposition format
+    pub fn metrics_text(&self) -> Result<String, BallistaError> {
+        {
+            let map = self.task_status_map.lock().expect("failed to lock mutex");
+            let queued = map
+                .values()
+                .filter(|s| matches!(s, TaskStatus::Queued))
+                .count();
+            let running = map
+                .values()
+                .filter(|s| matches!(s, TaskStatus::Running))
+                .count();
+            self.metrics.tasks_queued.set(queued as i64);
+            self.metrics.tasks_running.set(running as i64);
+            self.metrics.task_status_entries.set(map.len() as i64);
+        }
+        let concurrency = self
+            .concurrent_tasks
+            .lock()
+            .expect("failed to lock mutex")
+            .concurrency_level;
+        self.metrics.concurrency.set(concurrency as i64);
+        let results_cache_entries = self
+            .results_cache
+            .lock()
+            .expect("failed to lock mutex")
+            .len();
+        self.metrics
+            .results_cache_entries
+            .set(results_cache_entries as i64);
+        let shuffles = self.executor.list_shuffles();
+        self.metrics.shuffle_partitions.set(shuffles.len() as i64);
+        self.metrics
+            .shuffle_bytes
+            .set(shuffles.iter().map(|meta| meta.num_bytes as i64).sum());
+        self.metrics.encode()
+    }
+
+    fn stats(&self) -> protobuf::ExecutorStats {
+        let shuffles = self.executor.list_shuffles();
+        let mut stats = protobuf::ExecutorStats {
+            shuffle_partitions: shuffles.len() as u32,
+            shuffle_bytes: shuffles.iter().map(|meta| meta.num_bytes as u64).sum(),
+            queued_tasks: 0,
+            running_tasks: 0,
+            completed_tasks: 0,
+            failed_tasks: 0,
+            cancelled_tasks: 0,
+            draining: self.draining.load(Ordering::SeqCst),
+            cores: 0,
+            memory_bytes: 0,
+            reserved_cores: 0,
+            reserved_memory_bytes: 0,
+        };
+        {
+            let guard = self.concurrent_tasks.lock().expect("failed to lock mutex");
+            let capacity = guard.resources.capacity();
+            let reserved = guard.resources.reserved();
+            stats.cores = capacity.cores as u32;
+            stats.memory_bytes = capacity.memory_bytes;
+            stats.reserved_cores = reserved.cores as u32;
+            stats.reserved_memory_bytes = reserved.memory_bytes;
+        }
+        let task_status_map = self.task_status_map.lock().expect("failed to lock mutex");
+        for status in task_status_map.values() {
+            match status {
+                TaskStatus::Queued => stats.queued_tasks += 1,
+                TaskStatus::Running => stats.running_tasks += 1,
+                TaskStatus::Completed(..) => stats.completed_tasks += 1,
+                TaskStatus::Failed(..) => stats.failed_tasks += 1,
+                TaskStatus::Cancelled => stats.cancelled_tasks += 1,
+                TaskStatus::Withdrawn => {}
+            }
+        }
+        stats
+    }
+
+    /// Run a task on the shared async runtime and record its status once it completes. Tasks
+    /// do not get a dedicated thread, so the number of tasks is bounded only by the
+    /// concurrency guard. Threads on cores that no other task is using are granted to the task
+    /// for intra-task parallelism. When the task completes, the next queued task (if any) is
+    /// started.
+    fn spawn_task(&self, task: ExecutionTask) {
+        let service = self.clone();
+
+        {
+            let mut map = self.task_status_map.lock().expect("failed to lock mutex");
+            if let Some(TaskStatus::Cancelled) | Some(TaskStatus::Withdrawn) = map.get(&task.key())
+            {
+                // the task was cancelled or withdrawn after it left the queue, so hand its slot
+                // over
+                drop(map);
+                let next_tasks = self
+                    .concurrent_tasks
+                    .lock()
+                    .expect("failed to lock mutex")
+                    .release(&task.resources);
+                for next_task in next_tasks {
+                    self.spawn_task(next_task);
+                }
+                return;
+            }
+            map.insert(task.key(), TaskStatus::Running);
+        }
+        self.notify_watchers(&task, TaskUpdateState::Running);
+
+        let cancellation_token = CancellationToken::new();
+        self.cancellation_tokens
+            .lock()
+            .expect("failed to lock mutex")
+            .insert(task.key(), cancellation_token.clone());
+        let parallelism = self
+            .concurrent_tasks
+            .lock()
+            .expect("failed to lock mutex")
+            .grant_threads();
+
+        // abort the task through its cancellation token once its deadline passes
+        let deadline_passed = Arc::new(AtomicBool::new(false));
+        if let Some(time_to_deadline) = task.time_to_deadline() {
+            let cancellation_token = cancellation_token.clone();
+            let deadline_passed = deadline_passed.clone();
+            tokio::spawn(async move {
+                tokio::time::delay_for(time_to_deadline).await;
+                if !cancellation_token.is_cancelled() {
+                    deadline_passed.store(true, Ordering::SeqCst);
+                    cancellation_token.cancel();
+                }
+            });
+        }
+
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let status = match service
+                .run_task(&task, cancellation_token.clone(), parallelism)
+                .await
+            {
+                _ if deadline_passed.load(Ordering::SeqCst) => {
+                    warn!(
+                        "Task aborted at its deadline task_key={} duration_ms={}",
+                        task.key(),
+                        start.elapsed().as_millis()
+                    );
+                    let e = BallistaError::DeadlineExceeded(format!(
+                        "task {} did not complete before its deadline after running for {} ms",
+                        task.key(),
+                        start.elapsed().as_millis()
+                    ));
+                    TaskStatus::Failed(task.attempt, format!("{:?}", e))
+                }
+                _ if cancellation_token.is_cancelled() => {
+                    info!(
+                        "Task cancelled task_key={} duration_ms={}",
+                        task.key(),
+                        start.elapsed().as_millis()
+                    );
+                    TaskStatus::Cancelled
+                }
+                Ok((shuffle_id, metrics)) => {
+                    info!(
+                        "Task completed task_key={} duration_ms={} rows={} shuffle_bytes={}",
+                        task.key(),
+                        start.elapsed().as_millis(),
+                        metrics.output_rows,
+                        metrics.shuffle_bytes
+                    );
+                    TaskStatus::Completed(shuffle_id, metrics)
+                }
+                Err(e) => {
+                    error!(
+                        "Task failed task_key={} duration_ms={} error={:?}",
+                        task.key(),
+                        start.elapsed().as_millis(),
+                        e
+                    );
+                    TaskStatus::Failed(task.attempt, format!("{:?}", e))
+                }
+            };
+            service
+                .metrics
+                .task_duration
+                .observe(start.elapsed().as_secs_f64());
+            match &status {
+                TaskStatus::Completed(_, metrics) => {
+                    service.metrics.tasks_completed.inc();
+                    service
+                        .metrics
+                        .shuffle_bytes_written
+                        .inc_by(metrics.shuffle_bytes as u64);
+                }
+                TaskStatus::Failed(..) => service.metrics.tasks_failed.inc(),
+                TaskStatus::Cancelled => service.metrics.tasks_cancelled.inc(),
+                _ => {}
+            }
+            let update = match &status {
+                TaskStatus::Completed(_, metrics) => TaskUpdateState::Completed(metrics.clone()),
+                TaskStatus::Failed(_, reason) => TaskUpdateState::Failed(reason.clone()),
+                _ => TaskUpdateState::Cancelled,
+            };
+            service
+                .task_status_map
+                .lock()
+                .expect("failed to lock mutex")
+                .insert(task.key(), status);
+            // watchers are notified once the status is recorded, so that polling the task
+            // after the notification returns its outcome
+            service.notify_watchers(&task, update);
+            service
+                .cancellation_tokens
+                .lock()
+                .expect("failed to lock mutex")
+                .remove(&task.key());
+
+            let next_tasks = {
+                let mut guard = service
+                    .concurrent_tasks
+                    .lock()
+                    .expect("failed to lock mutex");
+                guard.release_threads(parallelism);
+                guard.release(&task.resources)
+            };
+            for next_task in next_tasks {
+                info!("Starting queued task task_key={}", next_task.key());
+                service.spawn_task(next_task);
+            }
+        });
+    }
+
+    /// Perform a management action sent with do_action, returning the body of its result
+    fn run_action(&self, action: physical_plan::Action, tenant: &str) -> Result<Vec<u8>, Status> {
+        self.check_admin_action(&action, tenant)?;
+        let body = match action {
+            physical_plan::Action::Manage(ExecutorAction::Shutdown) => {
+                info!("Shutting down gracefully");
+                self.shutdown();
+                b"shutting down".to_vec()
+            }
+            physical_plan::Action::Manage(ExecutorAction::Drain) => {
+                info!("Draining executor");
+                self.draining.store(true, Ordering::SeqCst);
+                b"draining".to_vec()
+            }
+            physical_plan::Action::Manage(ExecutorAction::ClearShuffleCache) => {
+                let count = self.executor.clear_shuffles();
+                info!("Cleared shuffle partitions count={}", count);
+                format!("cleared {} shuffle partitions", count).into_bytes()
+            }
+            physical_plan::Action::Manage(ExecutorAction::Stats) => {
+                let stats = self.stats();
+                let mut buf: Vec<u8> = Vec::with_capacity(stats.encoded_len());
+                stats
+                    .encode(&mut buf)
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                buf
+            }
+            physical_plan::Action::Manage(ExecutorAction::ListShuffles) => {
+                let held = protobuf::HeldShuffles {
+                    shuffle_ids: self
+                        .executor
+                        .list_shuffles()
+                        .iter()
+                        .map(|meta| (&meta.shuffle_id).try_into())
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| to_tonic_err(&e))?,
+                };
+                let mut buf: Vec<u8> = Vec::with_capacity(held.encoded_len());
+                held.encode(&mut buf)
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                buf
+            }
+            physical_plan::Action::CancelTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => self
+                .cancel_task(&task_key(&job_uuid, stage_id, partition_id))?
+                .into_bytes(),
+            physical_plan::Action::WithdrawTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => self
+                .withdraw_task(&task_key(&job_uuid, stage_id, partition_id))?
+                .into_bytes(),
+            physical_plan::Action::ReleaseJob(job_uuid) => self.release_job(&job_uuid).into_bytes(),
+            physical_plan::Action::RetainShuffles {
+                job_uuid,
+                shuffle_ids,
+            } => self.retain_shuffles(&job_uuid, &shuffle_ids)?.into_bytes(),
+            physical_plan::Action::ClosePrepared(handle) => {
+                self.close_prepared(&handle, tenant)?.into_bytes()
+            }
+            _ => return Err(Status::invalid_argument("Invalid action for do_action")),
+        };
+        Ok(body)
+    }
+
+    /// Respond to an action sent with do_get
+    async fn get_action(
+        &self,
+        action: &physical_plan::Action,
+        tenant: &str,
+        trace: Option<TraceContext>,
+    ) -> Result<Response<BoxedFlightStream<FlightData>>, Status> {
+        self.check_admin_action(action, tenant)?;
+        match action {
             physical_plan::Action::Execute(task) => {
+                if let Err(e) = self.plan_validator.validate_task(task) {
+                    warn!("Rejected task task_key={} error={:?}", task.key(), e);
+                    return Err(to_tonic_err(&e));
+                }
+                // the scans of tasks are not authorized, so clients could otherwise read the
+                // tables that the authorizer denies them by scanning the files directly
+                if self.authorizer.is_some()
+                    && tenant != CLUSTER_IDENTITY
+                    && reads_files(&task.plan)
+                {
+                    warn!("Denied task task_key={} principal={}", task.key(), tenant);
+                    return Err(Status::permission_denied(
+                        "only the cluster may run tasks that read files",
+                    ));
+                }
+                // submissions are deduplicated by task key, so a task that is submitted again
+                // while it runs, or after it completed, is not run twice
                 let key = task.key();
                 let mut map = self.task_status_map.lock().unwrap();
+                // a failed attempt is reported to every submission of that attempt, while a later
+                // attempt of the task runs again, as does a task that was withdrawn
+                let retried = match map.get(&key) {
+                    Some(TaskStatus::Failed(attempt, _)) => task.attempt > *attempt,
+                    Some(TaskStatus::Withdrawn) => true,
+                    _ => false,
+                };
+                if retried {
+                    map.remove(&key);
+                }
                 match map.get(&key) {
+                    None if self.draining.load(Ordering::SeqCst) => {
+                        // the scheduler will retry the task on another executor
+                        Err(Status::resource_exhausted("executor is draining"))
+                    }
                     None => {
-                        {
+                        // the spans of the task are children of the span of its stage, which
+                        // the scheduler sends in the metadata of the request
+                        let traced;
+                        let task = match trace {
+                            Some(trace) => {
+                                traced = task.clone().with_trace_context(trace);
+                                &traced
+                            }
+                            None => task,
+                        };
+                        let admission = {
                             let mut counter = self.concurrent_tasks.lock().unwrap();
-                            counter.inc()?;
+                            counter.admit(task)
+                        };
+                        let outcome = match admission {
+                            Admission::Rejected => {
+                                AuditOutcome::Failed("task queue is full".to_owned())
+                            }
+                            _ => AuditOutcome::Succeeded,
+                        };
+                        match admission {
+                            Admission::Run => {
+                                map.insert(key.clone(), TaskStatus::Running);
+                            }
+                            Admission::Queued => {
+                                map.insert(key.clone(), TaskStatus::Queued);
+                            }
+                            Admission::Rejected => {}
                         }
+                        // the audit sink may write to a file or forward the event to another
+                        // executor, which must not stall the readers of the task statuses
+                        drop(map);
+                        self.record_audit(self.audit_event(action, tenant), outcome);
 
-                        println!("Accepted task {}", task.key());
-
-                        map.insert(key.clone(), TaskStatus::Running);
-
-                        let task = task.clone();
-                        let map = self.task_status_map.clone();
-                        let key = key.clone();
-                        let key2 = key.clone();
-                        let concurrent_tasks = self.concurrent_tasks.clone();
-                        let executor = self.executor.clone();
-
-                        thread::spawn(move || {
-                            smol::run(async {
-                                let start = Instant::now();
-                                match executor.do_task(&task).await {
-                                    Ok(shuffle_id) => {
-                                        println!(
-                                            "Task {} completed in {} ms",
-                                            task.key(),
-                                            start.elapsed().as_millis()
-                                        );
-                                        let mut map = map.lock().unwrap();
-                                        map.insert(key, TaskStatus::Completed(shuffle_id));
-                                        let mut counter = concurrent_tasks.lock().unwrap();
-                                        counter.dec();
-                                    }
-                                    Err(e) => {
-                                        println!(
-                                            "Task {} failed after {} ms: {:?}",
-                                            task.key(),
-                                            start.elapsed().as_millis(),
-                                            e
-                                        );
-                                        let mut map = map.lock().unwrap();
-                                        map.insert(key, TaskStatus::Failed(format!("{:?}", e)));
-                                        let mut counter = concurrent_tasks.lock().unwrap();
-                                        counter.dec();
-                                    }
-                                }
-                            })
-                        });
-                        println!("Telling scheduler that task {} has started running", key2);
-                        Err(Status::already_exists("task is now running"))
+                        match admission {
+                            Admission::Run => {
+                                info!("Accepted task task_key={} attempt={}", key, task.attempt);
+                                self.spawn_task(task.clone());
+                                debug!("Task is now running task_key={}", key);
+                                Err(Status::already_exists("task is now running"))
+                            }
+                            Admission::Queued => {
+                                info!("Queued task task_key={} attempt={}", key, task.attempt);
+                                Err(Status::already_exists("task is queued"))
+                            }
+                            Admission::Rejected => {
+                                Err(Status::resource_exhausted("task queue is full"))
+                            }
+                        }
                     }
                     Some(status) => match status {
-                        TaskStatus::Failed(reason) => {
-                            println!("Telling scheduler that task {} has failed", task.key());
-                            Err(Status::aborted(reason.as_str()))
+                        TaskStatus::Failed(attempt, reason) => {
+                            debug!("Task has failed task_key={} attempt={}", key, attempt);
+                            Err(Status::aborted(reason.clone()))
+                        }
+                        TaskStatus::Queued => {
+                            debug!("Task is still queued task_key={}", key);
+                            Err(Status::already_exists("task is queued"))
                         }
                         TaskStatus::Running => {
-                            println!(
-                                "Telling scheduler that task {} is still running",
-                                task.key()
-                            );
+                            debug!("Task is still running task_key={}", key);
                             Err(Status::already_exists("task is still running"))
                         }
-                        TaskStatus::Completed(_) => {
-                            println!("Telling scheduler that task {} has completed", task.key());
-                            let results = ShufflePartition {
-                                schema: Schema::new(vec![Field::new(
-                                    "shuffle_id",
-                                    DataType::Utf8,
-                                    false,
-                                )]),
-                                data: vec![],
-                            };
-
-                            // write empty results stream to client
-                            let mut flights: Vec<Result<FlightData, Status>> =
-                                vec![Ok(FlightData::from(&results.schema))];
-
-                            let mut batches: Vec<Result<FlightData, Status>> = results
-                                .data
-                                .iter()
-                                .map(|batch| Ok(FlightData::from(batch)))
-                                .collect();
-
-                            flights.append(&mut batches);
-
-                            let output = futures::stream::iter(flights);
-                            Ok(Response::new(Box::pin(output) as Self::DoGetStream))
+                        TaskStatus::Cancelled => {
+                            debug!("Task was cancelled task_key={}", key);
+                            Err(Status::cancelled("task was cancelled"))
+                        }
+                        TaskStatus::Withdrawn => {
+                            unreachable!("withdrawn tasks are submitted again")
+                        }
+                        TaskStatus::Completed(_, metrics) => {
+                            debug!("Task has completed task_key={}", key);
+                            let schema =
+                                Schema::new(vec![Field::new("shuffle_id", DataType::Utf8, false)]);
+
+                            // write empty results stream to client, with the task metrics
+                            // attached to the schema message
+                            let metrics: protobuf::TaskMetrics =
+                                metrics.try_into().map_err(|e| to_tonic_err(&e))?;
+                            let mut app_metadata = Vec::with_capacity(metrics.encoded_len());
+                            metrics
+                                .encode(&mut app_metadata)
+                                .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                            let mut schema_flight = FlightData::from(&schema);
+                            schema_flight.app_metadata = app_metadata;
+
+                            let output = futures::stream::iter(vec![Ok(schema_flight)]);
+                            Ok(Response::new(
+                                Box::pin(output) as BoxedFlightStream<FlightData>
+                            ))
                         }
                     },
                 }
             }
             physical_plan::Action::FetchShuffle(shuffle_id) => {
-                let results = self
+                #[cfg(feature = "fault-injection")]
+                {
+                    if let Some(faults) = &self.faults {
+                        if faults.drop_fetch(shuffle_id) {
+                            warn!(
+                                "Injected dropped fetch of shuffle partition {:?}",
+                                shuffle_id
+                            );
+                            return Err(Status::unavailable("injected dropped shuffle fetch"));
+                        }
+                    }
+                }
+                let (meta, flights) = self
                     .executor
-                    .collect(shuffle_id)
+                    .collect_flight_data(shuffle_id)
                     .map_err(|e| to_tonic_err(&e))?;
+                self.metrics
+                    .shuffle_bytes_read
+                    .inc_by(meta.num_bytes as u64);
 
-                // write results stream to client
-                let mut flights: Vec<Result<FlightData, Status>> =
-                    vec![Ok(FlightData::from(&results.schema))];
+                // write the schema followed by the flight data that the partition was stored
+                // as, which is already compressed with the codec of the job that produced it
+                let schema_flight = futures::stream::iter(vec![Ok(FlightData::from(&meta.schema))]);
+                let flights = flights.map(|flight_data| flight_data.map_err(|e| to_tonic_err(&e)));
+                let output = schema_flight.chain(flights);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::InteractiveQuery { plan, settings } => {
+                let plan = self.prepare_plan(plan, tenant)?;
+                self.validate_query(&plan, settings, tenant)?;
+                let (schema, batches) = self
+                    .executor
+                    .execute_query_stream(&plan, settings, tenant)
+                    .await
+                    .map_err(|e| to_tonic_err(&e))?;
 
-                let mut batches: Vec<Result<FlightData, Status>> = results
-                    .data
-                    .iter()
-                    .map(|batch| Ok(FlightData::from(batch)))
-                    .collect();
+                // stream the results to the client as they are fetched from the executors
+                let schema_flight = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                let output = schema_flight.chain(to_flight_stream(
+                    batches,
+                    ShuffleCompression::None,
+                    self.max_message_size,
+                ));
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::Write {
+                plan,
+                path,
+                format,
+                settings,
+            } => {
+                let plan = self.prepare_plan(plan, tenant)?;
+                self.validate_query(&plan, settings, tenant)?;
+                self.plan_validator
+                    .check_path(path)
+                    .map_err(|e| to_tonic_err(&e))?;
+                let summary = self
+                    .executor
+                    .execute_write(&plan, path, *format, settings, tenant)
+                    .await
+                    .map_err(|e| to_tonic_err(&e))?;
 
-                flights.append(&mut batches);
+                // write the summary of the files rather than the results to the client
+                let batch = summary.to_batch().map_err(|e| to_tonic_err(&e))?;
+                let flights = vec![
+                    Ok(FlightData::from(batch.schema().as_ref())),
+                    Ok(FlightData::from(&batch)),
+                ];
+                let output = futures::stream::iter(flights);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::Analyze { plan, settings } => {
+                let plan = self.prepare_plan(plan, tenant)?;
+                self.validate_query(&plan, settings, tenant)?;
+                let statistics = self
+                    .executor
+                    .analyze(&plan, settings, tenant)
+                    .await
+                    .map_err(|e| to_tonic_err(&e))?;
 
+                // write the statistics of each column rather than the results to the client
+                let batch = statistics
+                    .to_batch(plan.schema())
+                    .map_err(|e| to_tonic_err(&e))?;
+                let flights = vec![
+                    Ok(FlightData::from(batch.schema().as_ref())),
+                    Ok(FlightData::from(&batch)),
+                ];
                 let output = futures::stream::iter(flights);
-                Ok(Response::new(Box::pin(output) as Self::DoGetStream))
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
             }
-            physical_plan::Action::InteractiveQuery { plan } => {
-                let results = self
+            physical_plan::Action::Explain {
+                plan,
+                analyze,
+                settings,
+            } => {
+                let plan = self.prepare_plan(plan, tenant)?;
+                self.validate_query(&plan, settings, tenant)?;
+                let explanation = self
                     .executor
-                    .execute_query(plan)
+                    .explain(&plan, *analyze, settings, tenant)
                     .await
                     .map_err(|e| to_tonic_err(&e))?;
 
-                // write results stream to client
-                let mut flights: Vec<Result<FlightData, Status>> =
-                    vec![Ok(FlightData::from(&results.schema))];
+                // write one row per plan rather than the results to the client
+                let batch = explanation.to_batch().map_err(|e| to_tonic_err(&e))?;
+                let flights = vec![
+                    Ok(FlightData::from(batch.schema().as_ref())),
+                    Ok(FlightData::from(&batch)),
+                ];
+                let output = futures::stream::iter(flights);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::CancelTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => {
+                self.cancel_task(&task_key(job_uuid, *stage_id, *partition_id))?;
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::WithdrawTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => {
+                self.withdraw_task(&task_key(job_uuid, *stage_id, *partition_id))?;
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::RetainShuffles {
+                job_uuid,
+                shuffle_ids,
+            } => {
+                self.retain_shuffles(job_uuid, shuffle_ids)?;
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::ReleaseJob(job_uuid) => {
+                self.release_job(job_uuid);
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::RegisterExecutor(registration) => {
+                self.registry.register(registration.clone());
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::Heartbeat { executor_id } => {
+                if !self.registry.heartbeat(executor_id) {
+                    return Err(Status::not_found(format!(
+                        "unknown executor {}",
+                        executor_id
+                    )));
+                }
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::DeregisterExecutor { executor_id } => {
+                if !self.registry.deregister(executor_id) {
+                    return Err(Status::not_found(format!(
+                        "unknown executor {}",
+                        executor_id
+                    )));
+                }
 
-                let mut batches: Vec<Result<FlightData, Status>> = results
-                    .data
-                    .iter()
-                    .map(|batch| Ok(FlightData::from(batch)))
-                    .collect();
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::ListExecutors => {
+                let batch = registrations_to_batch(&self.registry.live_executors())
+                    .map_err(|e| to_tonic_err(&e))?;
+                let flights = vec![
+                    Ok(FlightData::from(batch.schema().as_ref())),
+                    Ok(FlightData::from(&batch)),
+                ];
+                let output = futures::stream::iter(flights);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::RegisterTable { name, plan } => {
+                self.validate_query(plan, &QuerySettings::default(), tenant)?;
+                // the principal must be allowed to read what the table scans, while readers of
+                // the table are authorized for the table by name
+                self.prepare_plan(plan, tenant)?;
+                info!("Registered table name={} tenant={}", name, tenant);
+                self.tenant_tables(tenant).register(name, plan.clone());
 
-                flights.append(&mut batches);
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::Prepare { plan, settings } => {
+                let plan = self.prepare_plan(plan, tenant)?;
+                self.validate_query(&plan, settings, tenant)?;
+                let (handle, parameter_types) = self
+                    .executor
+                    .prepare_query(&plan, settings, tenant)
+                    .map_err(|e| to_tonic_err(&e))?;
 
+                // write the handle of the prepared query rather than results to the client
+                let batch = prepared_query_batch(&handle, &parameter_types)
+                    .map_err(|e| to_tonic_err(&e))?;
+                let flights = vec![
+                    Ok(FlightData::from(batch.schema().as_ref())),
+                    Ok(FlightData::from(&batch)),
+                ];
                 let output = futures::stream::iter(flights);
-                Ok(Response::new(Box::pin(output) as Self::DoGetStream))
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
             }
+            physical_plan::Action::ExecutePrepared { handle, params } => {
+                let (schema, batches) = self
+                    .executor
+                    .execute_prepared_stream(handle, params, tenant)
+                    .await
+                    .map_err(|e| to_tonic_err(&e))?;
+
+                let schema_flight = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                let output = schema_flight.chain(to_flight_stream(
+                    batches,
+                    ShuffleCompression::None,
+                    self.max_message_size,
+                ));
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::ClosePrepared(handle) => {
+                self.close_prepared(handle, tenant)?;
+
+                // write empty results stream to client
+                let schema = Schema::new(vec![]);
+                let output = futures::stream::iter(vec![Ok(FlightData::from(&schema))]);
+                Ok(Response::new(
+                    Box::pin(output) as BoxedFlightStream<FlightData>
+                ))
+            }
+            physical_plan::Action::Manage(_) | physical_plan::Action::WatchTasks(_) => Err(
+                Status::invalid_argument("Management actions must be sent with do_action"),
+            ),
+        }
+    }
+}
+
+type BoxedFlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+#[tonic::async_trait]
+impl FlightService for BallistaFlightService {
+    type HandshakeStream = BoxedFlightStream<HandshakeResponse>;
+    type ListFlightsStream = BoxedFlightStream<FlightInfo>;
+    type DoGetStream = BoxedFlightStream<FlightData>;
+    type DoPutStream = BoxedFlightStream<PutResult>;
+    type DoActionStream = BoxedFlightStream<flight::Result>;
+    type ListActionsStream = BoxedFlightStream<ActionType>;
+    type DoExchangeStream = BoxedFlightStream<FlightData>;
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        self.check_authenticated(&request)?;
+        let tenant = tenant_of(&request);
+        let trace = trace_context_of(&request);
+        let ticket = request.into_inner();
+
+        // the tickets of Flight SQL metadata commands are the commands themselves
+        if let Some(command) = FlightSqlCommand::decode(&ticket.ticket) {
+            let command = command.map_err(|e| to_tonic_err(&e))?;
+            let batch = self.flight_sql_metadata(&command, &tenant)?;
+            let flights = vec![
+                Ok(FlightData::from(batch.schema().as_ref())),
+                Ok(FlightData::from(&batch)),
+            ];
+            let output = futures::stream::iter(flights);
+            return Ok(Response::new(Box::pin(output) as Self::DoGetStream));
+        }
+
+        let action = decode_protobuf(&ticket.ticket.to_vec()).map_err(|e| to_tonic_err(&e))?;
+
+        debug!("do_get action={:?}", action);
+
+        // tasks are audited when they are first submitted rather than each time that the
+        // scheduler polls them
+        let event = match &action {
+            physical_plan::Action::Execute(_) => None,
+            action => self.audit_event(action, &tenant),
+        };
+        // queries start a trace unless the client propagated one, while tasks record their
+        // spans when they run rather than each time that the scheduler polls them
+        let mut span = match &action {
+            physical_plan::Action::InteractiveQuery { .. }
+            | physical_plan::Action::Write { .. }
+            | physical_plan::Action::Analyze { .. }
+            | physical_plan::Action::Explain { .. }
+            | physical_plan::Action::ExecutePrepared { .. } => {
+                Some(Span::start("query", trace.as_ref()).with_attribute("tenant", &tenant))
+            }
+            physical_plan::Action::FetchShuffle(shuffle_id) if trace.is_some() => Some(
+                Span::start("serve_shuffle", trace.as_ref())
+                    .with_attribute("stage_id", shuffle_id.stage_id)
+                    .with_attribute("partition_id", shuffle_id.partition_id),
+            ),
+            _ => None,
+        };
+        let get = self.get_action(&action, &tenant, trace);
+        let result = match &span {
+            Some(span) => in_trace(span.context(), get).await,
+            None => get.await,
+        };
+        if let (Some(span), Err(status)) = (&mut span, &result) {
+            span.set_error(status.message());
         }
+        self.record_audit(event, outcome_of(&result));
+        result
     }
 
     async fn get_schema(
         &self,
         request: Request<FlightDescriptor>,
     ) -> Result<Response<SchemaResult>, Status> {
-        println!("get_schema()");
+        self.check_authenticated(&request)?;
+        debug!("get_schema()");
 
         let request = request.into_inner();
-        let uuid = &request.path[0];
+        let path = request
+            .path
+            .first()
+            .ok_or_else(|| Status::invalid_argument("Missing path"))?;
 
-        match self
+        if let Some(results) = self
             .results_cache
             .lock()
             .expect("failed to lock mutex")
-            .get(uuid)
+            .get(path)
         {
-            Some(results) => Ok(Response::new(SchemaResult::from(&results.schema))),
-            _ => Err(Status::not_found("Invalid uuid")),
+            return Ok(Response::new(SchemaResult::from(&results.schema)));
+        }
+        if Uuid::parse_str(path).is_ok() {
+            return Err(Status::not_found("Invalid uuid"));
         }
+
+        // any other path names files on the executor, optionally followed by their format, so
+        // that clients can plan queries against data that they cannot read themselves
+        let format = request.path.get(1).map(|s| s.as_str());
+        self.plan_validator
+            .check_path(path)
+            .map_err(|e| to_tonic_err(&e))?;
+        let schema = infer_file_schema(path, format).map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(SchemaResult::from(&schema)))
     }
 
     async fn get_flight_info(
         &self,
         request: Request<FlightDescriptor>,
     ) -> Result<Response<FlightInfo>, Status> {
-        println!("get_flight_info");
+        self.check_authenticated(&request)?;
+        debug!("get_flight_info()");
+        let tenant = tenant_of(&request);
 
         let request = request.into_inner();
 
+        if let Some(command) = FlightSqlCommand::decode(&request.cmd) {
+            let command = command.map_err(|e| to_tonic_err(&e))?;
+            let info = self.flight_sql_info(command, request, &tenant).await?;
+            return Ok(Response::new(info));
+        }
+
         let action = decode_protobuf(&request.cmd.to_vec()).map_err(|e| to_tonic_err(&e))?;
 
         match &action {
-            physical_plan::Action::InteractiveQuery { plan: logical_plan } => {
-                println!("Logical plan: {:?}", logical_plan);
-
-                let plan = create_physical_plan(&logical_plan).map_err(|e| to_tonic_err(&e))?;
-                println!("Physical plan: {:?}", plan);
-
-                let plan = ensure_requirements(&plan).map_err(|e| to_tonic_err(&e))?;
-                println!("Optimized physical plan: {:?}", plan);
-
-                let job = create_job(plan).map_err(|e| to_tonic_err(&e))?;
-                job.explain();
-
-                // TODO execute the DAG by serializing stages to protobuf and allocating
-                // tasks (partitions) to executors in the cluster
-
-                Err(Status::invalid_argument("not implemented yet"))
-
-                //     let job = create_job(logical_plan).map_err(|e| to_tonic_err(&e))?;
-                //     println!("Job: {:?}", job);
-                //
-                //     //TODO execute stages
-                //
-                //     let uuid = "tbd";
-                //
-                //     match self.results.lock().expect("failed to lock mutex").get(uuid) {
-                //         Some(results) => {
-                //             let schema_bytes = schema_to_bytes(&results.schema);
-                //
-                //             Ok(Response::new(FlightInfo {
-                //                 schema: schema_bytes,
-                //                 endpoint: vec![],
-                //                 flight_descriptor: None,
-                //                 total_bytes: -1,
-                //                 total_records: -1,
-                //             }))
-                //         }
-                //         _ => Err(Status::not_found("Invalid uuid")),
-                //     }
+            physical_plan::Action::InteractiveQuery {
+                plan: logical_plan,
+                settings,
+            } => {
+                let logical_plan = self.prepare_plan(&logical_plan, &tenant)?;
+                let info = self
+                    .query_flight_info(&logical_plan, settings, &tenant, request.clone())
+                    .await?;
+                Ok(Response::new(info))
             }
             _ => Err(Status::invalid_argument("Invalid action")),
         }
@@ -329,67 +1942,287 @@ impl FlightService for BallistaFlightService {
 
     async fn handshake(
         &self,
-        _request: Request<Streaming<HandshakeRequest>>,
+        request: Request<Streaming<HandshakeRequest>>,
     ) -> Result<Response<Self::HandshakeStream>, Status> {
-        Err(Status::unimplemented("Not yet implemented"))
+        let sessions = match &self.sessions {
+            Some(sessions) => sessions,
+            None => return Err(Status::unimplemented("Authentication is not enabled")),
+        };
+
+        // the certificate presented by the client, if mutual TLS is enabled
+        let peer_cert = request
+            .peer_certs()
+            .and_then(|certs| certs.into_iter().next())
+            .map(|cert| cert.into_inner());
+
+        let mut request = request.into_inner();
+        let handshake = match request.next().await {
+            Some(handshake) => handshake?,
+            None => return Err(Status::invalid_argument("handshake received empty stream")),
+        };
+        let credentials = Credentials::from_handshake_payload(&handshake.payload, peer_cert)
+            .map_err(|e| Status::invalid_argument(format!("{:?}", e)))?
+            .ok_or_else(|| Status::unauthenticated("No credentials provided"))?;
+
+        let token = sessions
+            .login(&credentials)
+            .map_err(|e| Status::unauthenticated(format!("{:?}", e)))?;
+
+        let response = vec![Ok(HandshakeResponse {
+            protocol_version: handshake.protocol_version,
+            payload: token.into_bytes(),
+        })];
+        let output = futures::stream::iter(response);
+        Ok(Response::new(Box::pin(output) as Self::HandshakeStream))
     }
 
     async fn list_flights(
         &self,
-        _request: Request<Criteria>,
+        request: Request<Criteria>,
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
-        Err(Status::unimplemented("Not yet implemented"))
+        self.check_authenticated(&request)?;
+        // an optional job uuid can be passed as the criteria expression to filter the results
+        let job_uuid = String::from_utf8(request.into_inner().expression)
+            .map_err(|_| Status::invalid_argument("Criteria must be a job uuid"))?;
+
+        let mut flights: Vec<Result<FlightInfo, Status>> = vec![];
+
+        // shuffle partitions held by this executor, which can be fetched with do_get
+        for meta in self.executor.list_shuffles() {
+            if !job_uuid.is_empty() && meta.shuffle_id.job_uuid.to_string() != job_uuid {
+                continue;
+            }
+            let cmd = encode_protobuf(&physical_plan::Action::FetchShuffle(meta.shuffle_id))
+                .map_err(|e| to_tonic_err(&e))?;
+            flights.push(Ok(FlightInfo {
+                schema: SchemaResult::from(&meta.schema).schema,
+                flight_descriptor: Some(FlightDescriptor {
+                    r#type: flight_descriptor::DescriptorType::Cmd as i32,
+                    cmd: cmd.clone(),
+                    path: vec![],
+                }),
+                endpoint: vec![FlightEndpoint {
+                    ticket: Some(Ticket { ticket: cmd }),
+                    location: vec![],
+                }],
+                total_records: meta.num_rows as i64,
+                total_bytes: meta.num_bytes as i64,
+            }));
+        }
+
+        // tasks that this executor has accepted, described by path
+        let task_status_map = self.task_status_map.lock().expect("failed to lock mutex");
+        for (key, status) in task_status_map.iter() {
+            if !job_uuid.is_empty() && !key.starts_with(&job_uuid) {
+                continue;
+            }
+            let status = match status {
+                TaskStatus::Queued => "queued",
+                TaskStatus::Running => "running",
+                TaskStatus::Completed(..) => "completed",
+                TaskStatus::Failed(..) => "failed",
+                TaskStatus::Cancelled => "cancelled",
+                TaskStatus::Withdrawn => "withdrawn",
+            };
+            flights.push(Ok(FlightInfo {
+                schema: vec![],
+                flight_descriptor: Some(FlightDescriptor {
+                    r#type: flight_descriptor::DescriptorType::Path as i32,
+                    cmd: vec![],
+                    path: vec!["task".to_owned(), key.to_owned(), status.to_owned()],
+                }),
+                endpoint: vec![],
+                total_records: -1,
+                total_bytes: -1,
+            }));
+        }
+
+        let output = futures::stream::iter(flights);
+        Ok(Response::new(Box::pin(output) as Self::ListFlightsStream))
     }
 
     async fn do_put(
         &self,
         request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        println!("do_put()");
+        self.check_authenticated(&request)?;
+        debug!("do_put()");
+        // partitions are pushed by executors that hand them off, and clients could otherwise
+        // replace the partitions of other jobs
+        self.check_admin(&tenant_of(&request))?;
 
         let mut request = request.into_inner();
 
-        while let Some(data) = request.next().await {
-            let data = data?;
-            println!("do_put() received data: {:?}", data);
+        // the first message must describe the shuffle partition and contain the schema
+        let flight_data = match request.next().await {
+            Some(flight_data) => flight_data?,
+            None => return Err(Status::invalid_argument("do_put received empty stream")),
+        };
+        let shuffle_id = match &flight_data.flight_descriptor {
+            Some(descriptor) => {
+                match decode_protobuf(&descriptor.cmd.to_vec()).map_err(|e| to_tonic_err(&e))? {
+                    physical_plan::Action::FetchShuffle(shuffle_id) => shuffle_id,
+                    _ => return Err(Status::invalid_argument("Invalid action for do_put")),
+                }
+            }
+            None => return Err(Status::invalid_argument("Missing flight descriptor")),
+        };
+        let schema = Arc::new(
+            Schema::try_from(&flight_data)
+                .map_err(|e| to_tonic_err(&BallistaError::ArrowError(e)))?,
+        );
+
+        // all the remaining stream messages should be dictionary and record batches, and the
+        // partition is stored with the codec that the batches were pushed with
+        let mut data = vec![];
+        let mut compression = ShuffleCompression::None;
+        let mut decoder = FlightDataDecoder::new(schema.clone());
+        while let Some(flight_data) = request.next().await {
+            let flight_data = flight_data?;
+            compression =
+                ShuffleCompression::of_flight_data(&flight_data).map_err(|e| to_tonic_err(&e))?;
+            if let Some(batch) = decoder.decode(flight_data).map_err(|e| to_tonic_err(&e))? {
+                data.push(batch);
+            }
         }
 
-        Err(Status::unimplemented("Not yet implemented"))
+        info!(
+            "Received shuffle partition job_uuid={} stage_id={} partition_id={} batches={}",
+            shuffle_id.job_uuid,
+            shuffle_id.stage_id,
+            shuffle_id.partition_id,
+            data.len()
+        );
+
+        let num_bytes: usize = data
+            .iter()
+            .map(|b| ColumnarBatch::from_arrow(b).memory_size())
+            .sum();
+        self.metrics.shuffle_bytes_written.inc_by(num_bytes as u64);
+
+        self.executor
+            .store_shuffle(
+                &shuffle_id,
+                ShufflePartition {
+                    schema: schema.as_ref().clone(),
+                    data,
+                    compression,
+                },
+            )
+            .map_err(|e| to_tonic_err(&e))?;
+
+        let result = vec![Ok(PutResult {
+            app_metadata: vec![],
+        })];
+        let output = futures::stream::iter(result);
+        Ok(Response::new(Box::pin(output) as Self::DoPutStream))
     }
 
     async fn do_action(
         &self,
         request: Request<Action>,
     ) -> Result<Response<Self::DoActionStream>, Status> {
+        self.check_authenticated(&request)?;
+        let tenant = tenant_of(&request);
         let action = request.into_inner();
-        println!("do_action() type={}", action.r#type);
+        debug!("do_action() type={}", action.r#type);
 
-        let _action = decode_protobuf(&action.body.to_vec()).map_err(|e| to_tonic_err(&e))?;
+        if action.r#type == RECORD_AUDIT_EVENT {
+            self.collect_audit_event(&action.body, &tenant)?;
+            let output = futures::stream::iter(vec![Ok(flight::Result { body: vec![] })]);
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
 
-        unimplemented!()
+        if let Some(body) = self.flight_sql_action(&action, &tenant)? {
+            let output = futures::stream::iter(vec![Ok(flight::Result { body })]);
+            return Ok(Response::new(Box::pin(output) as Self::DoActionStream));
+        }
+
+        let action = decode_protobuf(&action.body.to_vec()).map_err(|e| to_tonic_err(&e))?;
+
+        // each transition of a task of the job is sent as a result until the job is released
+        if let physical_plan::Action::WatchTasks(job_uuid) = action {
+            let updates = self.watch_tasks(job_uuid).map(|update| {
+                let update: protobuf::TaskStatusUpdate =
+                    (&update).try_into().map_err(|e| to_tonic_err(&e))?;
+                let mut body = Vec::with_capacity(update.encoded_len());
+                update
+                    .encode(&mut body)
+                    .map_err(|e| Status::internal(format!("{:?}", e)))?;
+                Ok(flight::Result { body })
+            });
+            return Ok(Response::new(Box::pin(updates) as Self::DoActionStream));
+        }
+
+        let event = self.audit_event(&action, &tenant);
+        let result = self.run_action(action, &tenant);
+        self.record_audit(event, outcome_of(&result));
+        let body = result?;
 
-        // let results = execute_action(&action)?;
-        //
-        // let key = "tbd"; // generate uuid here
-        //
-        // self.results_cache
-        //     .lock()
-        //     .unwrap()
-        //     .insert(key.to_owned(), results);
-        //
-        // let result = vec![Ok(flight::Result {
-        //     body: key.as_bytes().to_vec(),
-        // })];
-        //
-        // let output = futures::stream::iter(result);
-        // Ok(Response::new(Box::pin(output) as Self::DoActionStream))
+        let result = vec![Ok(flight::Result { body })];
+        let output = futures::stream::iter(result);
+        Ok(Response::new(Box::pin(output) as Self::DoActionStream))
     }
 
     async fn list_actions(
         &self,
         _request: Request<Empty>,
     ) -> Result<Response<Self::ListActionsStream>, Status> {
-        Err(Status::unimplemented("Not yet implemented"))
+        let actions = vec![
+            (
+                ExecutorAction::Shutdown,
+                "Stop accepting new tasks, hand off shuffle partitions and leave the cluster once \
+                 accepted tasks have completed or the grace period has passed, and shut down",
+            ),
+            (
+                ExecutorAction::Drain,
+                "Stop accepting new tasks but keep serving shuffle partitions",
+            ),
+            (
+                ExecutorAction::ClearShuffleCache,
+                "Discard all shuffle partitions held by the executor",
+            ),
+            (
+                ExecutorAction::Stats,
+                "Report executor statistics as an encoded ExecutorStats protobuf message",
+            ),
+            (
+                ExecutorAction::ListShuffles,
+                "List the shuffle partitions held by the executor as an encoded HeldShuffles \
+                 protobuf message",
+            ),
+        ];
+        let named_actions = vec![
+            (
+                flight_sql::CREATE_PREPARED_STATEMENT,
+                "Plan a Flight SQL query once so that it can be executed repeatedly",
+            ),
+            (
+                flight_sql::CLOSE_PREPARED_STATEMENT,
+                "Discard a Flight SQL prepared statement",
+            ),
+            (
+                RECORD_AUDIT_EVENT,
+                "Record an audit event, as JSON, that another executor forwarded",
+            ),
+        ];
+        let actions: Vec<Result<ActionType, Status>> = actions
+            .into_iter()
+            .map(|(action, description)| (format!("{:?}", action), description))
+            .chain(
+                named_actions
+                    .into_iter()
+                    .map(|(action, description)| (action.to_owned(), description)),
+            )
+            .map(|(action, description)| {
+                Ok(ActionType {
+                    r#type: action,
+                    description: description.to_owned(),
+                })
+            })
+            .collect();
+        let output = futures::stream::iter(actions);
+        Ok(Response::new(Box::pin(output) as Self::ListActionsStream))
     }
 
     async fn do_exchange(
@@ -421,6 +2254,219 @@ impl FlightService for BallistaFlightService {
 //     data.to_vec()
 // }
 
+/// Convert a stream of batches to flight data, with the dictionaries of dictionary-encoded
+/// columns sent as separate messages ahead of the batches that use them
+fn to_flight_stream<S>(
+    batches: S,
+    compression: ShuffleCompression,
+    max_message_size: usize,
+) -> BoxedFlightStream<FlightData>
+where
+    S: Stream<Item = Result<RecordBatch, BallistaError>> + Send + Sync + 'static,
+{
+    let mut encoder = FlightDataEncoder::new(compression).with_max_message_size(max_message_size);
+    Box::pin(batches.flat_map(move |batch| {
+        let flights = match batch.and_then(|batch| encoder.encode(&batch)) {
+            Ok(flights) => flights.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(to_tonic_err(&e))],
+        };
+        futures::stream::iter(flights)
+    }))
+}
+
+/// Outcome of a request for its audit event
+fn outcome_of<T>(result: &Result<T, Status>) -> AuditOutcome {
+    match result {
+        Ok(_) => AuditOutcome::Succeeded,
+        Err(status) => AuditOutcome::Failed(status.message().to_owned()),
+    }
+}
+
 fn to_tonic_err(e: &crate::error::BallistaError) -> Status {
-    Status::internal(format!("{:?}", e))
+    to_status(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed::audit::AuditSink;
+    use crate::distributed::auth::StaticTokenAuthenticator;
+    use crate::distributed::executor::{BallistaExecutor, DiscoveryMode, ExecutorConfig};
+    use crate::execution::operators::InMemoryTableScanExec;
+    use crate::execution::physical_plan::PhysicalPlan;
+
+    #[test]
+    fn grant_threads_on_idle_cores() {
+        let mut guard = ConcurrencyGuard {
+            concurrency_level: 0,
+            max_concurrency: 2,
+            queue: VecDeque::new(),
+            max_queue_depth: 0,
+            max_threads: 4,
+            task_parallelism: 3,
+            extra_threads: 0,
+            resources: ResourcePool::new(TaskResources {
+                cores: 4,
+                memory_bytes: 0,
+            }),
+        };
+
+        // the first task takes a core of its own and two idle ones
+        guard.concurrency_level += 1;
+        assert_eq!(3, guard.grant_threads());
+        // only one core is left for the second task
+        guard.concurrency_level += 1;
+        assert_eq!(1, guard.grant_threads());
+
+        guard.release_threads(3);
+        assert_eq!(0, guard.extra_threads);
+        assert!(guard.release(&TaskResources::default()).is_empty());
+        guard.concurrency_level += 1;
+        assert_eq!(3, guard.grant_threads());
+    }
+
+    /// Keeps the events that it records in memory
+    #[derive(Default)]
+    struct MemoryAuditSink {
+        events: Mutex<Vec<AuditEvent>>,
+    }
+
+    impl AuditSink for MemoryAuditSink {
+        fn record(&self, event: &AuditEvent) -> crate::error::Result<()> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn only_collect_audit_events_from_forwarders() {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "");
+        let sink = Arc::new(MemoryAuditSink::default());
+        let service = BallistaFlightService::new(Arc::new(BallistaExecutor::new(config)), 1, 1)
+            .with_audit_sink(sink.clone());
+        let action = physical_plan::Action::Manage(ExecutorAction::Drain);
+        let event = AuditEvent::for_action(&action, "admin")
+            .unwrap()
+            .complete(AuditOutcome::Succeeded);
+        let body = event.to_json().to_string().into_bytes();
+
+        // an executor that does not authenticate its clients collects no forwarded events
+        let status = service
+            .collect_audit_event(&body, CLUSTER_IDENTITY)
+            .unwrap_err();
+        assert_eq!(tonic::Code::PermissionDenied, status.code());
+        assert!(sink.events.lock().unwrap().is_empty());
+
+        let service = service.with_authenticator(Arc::new(
+            StaticTokenAuthenticator::new().with_token("secret", CLUSTER_IDENTITY),
+        ));
+
+        let status = service.collect_audit_event(&body, "alice").unwrap_err();
+        assert_eq!(tonic::Code::PermissionDenied, status.code());
+        assert!(sink.events.lock().unwrap().is_empty());
+
+        service
+            .collect_audit_event(&body, CLUSTER_IDENTITY)
+            .unwrap();
+        let events = sink.events.lock().unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!("admin", events[0].principal);
+        assert_eq!(Some(CLUSTER_IDENTITY.to_owned()), events[0].forwarded_by);
+    }
+
+    #[test]
+    fn only_admins_manage_executor() {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "");
+        let service = BallistaFlightService::new(Arc::new(BallistaExecutor::new(config)), 1, 1);
+        let drain = || physical_plan::Action::Manage(ExecutorAction::Drain);
+
+        let status = service.run_action(drain(), "alice").unwrap_err();
+        assert_eq!(tonic::Code::PermissionDenied, status.code());
+        assert!(!service.draining.load(Ordering::SeqCst));
+        // statistics are not restricted
+        assert!(service
+            .run_action(
+                physical_plan::Action::Manage(ExecutorAction::Stats),
+                "alice"
+            )
+            .is_ok());
+
+        service.run_action(drain(), CLUSTER_IDENTITY).unwrap();
+        assert!(service.draining.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn skip_withdrawn_task_that_left_the_queue() {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "");
+        let service = BallistaFlightService::new(Arc::new(BallistaExecutor::new(config)), 1, 1);
+        let plan = PhysicalPlan::InMemoryTableScan(Arc::new(InMemoryTableScanExec::new(vec![])));
+        let task = ExecutionTask::new(Uuid::new_v4(), 1, 0, plan, HashMap::new());
+        service
+            .task_status_map
+            .lock()
+            .unwrap()
+            .insert(task.key(), TaskStatus::Queued);
+
+        // the task takes a free slot, and is withdrawn before it starts
+        service.concurrent_tasks.lock().unwrap().concurrency_level += 1;
+        service.withdraw_task(&task.key()).unwrap();
+        service.spawn_task(task.clone());
+
+        assert!(matches!(
+            service.task_status_map.lock().unwrap().get(&task.key()),
+            Some(TaskStatus::Withdrawn)
+        ));
+        assert_eq!(
+            0,
+            service.concurrent_tasks.lock().unwrap().concurrency_level
+        );
+        assert!(service.cancellation_tokens.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn push_task_transitions_to_watchers() {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "");
+        let service = BallistaFlightService::new(Arc::new(BallistaExecutor::new(config)), 1, 1);
+        let plan = PhysicalPlan::InMemoryTableScan(Arc::new(InMemoryTableScanExec::new(vec![])));
+        let task = ExecutionTask::new(Uuid::new_v4(), 1, 0, plan, HashMap::new()).with_attempt(2);
+        let other_task =
+            ExecutionTask::new(Uuid::new_v4(), 1, 0, task.plan.clone(), HashMap::new());
+
+        let mut updates = service.watch_tasks(task.job_uuid);
+        service.notify_watchers(&other_task, TaskUpdateState::Running);
+        service.notify_watchers(&task, TaskUpdateState::Running);
+        service.notify_watchers(&task, TaskUpdateState::Failed("out of memory".to_owned()));
+
+        // only the transitions of the watched job are pushed
+        let update = updates.try_next().unwrap().unwrap();
+        assert_eq!(task.job_uuid, update.job_uuid);
+        assert_eq!(2, update.attempt);
+        assert_eq!(TaskUpdateState::Running, update.state);
+        let update = updates.try_next().unwrap().unwrap();
+        assert_eq!(
+            TaskUpdateState::Failed("out of memory".to_owned()),
+            update.state
+        );
+
+        // releasing the job ends the stream
+        service.release_job(&task.job_uuid);
+        assert!(updates.try_next().unwrap().is_none());
+        assert!(service.task_watchers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn shut_down_idle_executor() {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "");
+        let service = BallistaFlightService::new(Arc::new(BallistaExecutor::new(config)), 1, 1)
+            .with_shutdown_grace_period(Duration::from_millis(10));
+        let shutdown = service.shutdown_signal();
+        smol::run(async {
+            service.shutdown();
+            // asking again does not run the shutdown sequence twice
+            service.shutdown();
+            shutdown.await;
+        });
+        assert!(service.draining.load(Ordering::SeqCst));
+        assert_eq!(0, service.cancel_accepted_tasks());
+    }
 }
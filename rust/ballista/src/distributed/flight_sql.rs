@@ -0,0 +1,340 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for the Arrow Flight SQL protocol, so that BI tools and the Flight SQL JDBC driver
+//! can query an executor directly.
+//!
+//! Flight SQL commands are protobuf messages wrapped in `google.protobuf.Any`, which are sent
+//! as the command of a flight descriptor, the ticket of a flight, or the body of an action.
+//! Ballista actions are plain protobuf messages, so the two are told apart by the type URL of
+//! the `Any` message. SQL queries are planned against the tables that clients have registered
+//! with the executor, which are listed as the tables of a single catalog and schema.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::arrow::array;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::prepared::PreparedStore;
+use crate::error::{BallistaError, Result};
+use crate::flight::SchemaResult;
+use crate::protobuf::flight_sql::{
+    Any, CommandGetDbSchemas, CommandGetTables, CommandPreparedStatementQuery,
+    CommandStatementQuery,
+};
+
+use prost::Message;
+
+/// Prefix of the type URLs of Flight SQL messages wrapped in `Any`
+pub const TYPE_URL_PREFIX: &str = "type.googleapis.com/arrow.flight.protocol.sql.";
+
+/// Name of the catalog that registered tables are listed in
+pub const CATALOG_NAME: &str = "ballista";
+
+/// Name of the schema that registered tables are listed in, which is the schema that the SQL
+/// planner scans tables from
+pub const DB_SCHEMA_NAME: &str = "default";
+
+/// Type that registered tables are listed as
+pub const TABLE_TYPE: &str = "TABLE";
+
+/// Type of the action that creates a prepared statement
+pub const CREATE_PREPARED_STATEMENT: &str = "CreatePreparedStatement";
+
+/// Type of the action that closes a prepared statement
+pub const CLOSE_PREPARED_STATEMENT: &str = "ClosePreparedStatement";
+
+/// Flight SQL commands that are sent as the command of a flight descriptor or as a ticket
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlightSqlCommand {
+    StatementQuery(CommandStatementQuery),
+    PreparedStatementQuery(CommandPreparedStatementQuery),
+    GetCatalogs,
+    GetDbSchemas(CommandGetDbSchemas),
+    GetTables(CommandGetTables),
+    GetTableTypes,
+}
+
+impl FlightSqlCommand {
+    /// Decode a Flight SQL command, returning `None` if the bytes are not a Flight SQL message,
+    /// such as when they are a Ballista action
+    pub fn decode(buf: &[u8]) -> Option<Result<Self>> {
+        let any = Any::decode(buf).ok()?;
+        let name = any.type_url.strip_prefix(TYPE_URL_PREFIX)?;
+        let value = any.value.as_slice();
+        let command = match name {
+            "CommandStatementQuery" => {
+                CommandStatementQuery::decode(value).map(FlightSqlCommand::StatementQuery)
+            }
+            "CommandPreparedStatementQuery" => CommandPreparedStatementQuery::decode(value)
+                .map(FlightSqlCommand::PreparedStatementQuery),
+            "CommandGetCatalogs" => Ok(FlightSqlCommand::GetCatalogs),
+            "CommandGetDbSchemas" => {
+                CommandGetDbSchemas::decode(value).map(FlightSqlCommand::GetDbSchemas)
+            }
+            "CommandGetTables" => CommandGetTables::decode(value).map(FlightSqlCommand::GetTables),
+            "CommandGetTableTypes" => Ok(FlightSqlCommand::GetTableTypes),
+            other => {
+                return Some(Err(BallistaError::NotImplemented(format!(
+                    "Flight SQL command {} is not supported",
+                    other
+                ))))
+            }
+        };
+        Some(command.map_err(|e| BallistaError::General(format!("{:?}", e))))
+    }
+
+    /// Describe the results of a metadata command, or `None` for queries
+    pub fn metadata(&self, tables: &HashMap<String, LogicalPlan>) -> Option<Result<RecordBatch>> {
+        match self {
+            FlightSqlCommand::GetCatalogs => Some(catalogs_batch()),
+            FlightSqlCommand::GetDbSchemas(command) => Some(db_schemas_batch(command)),
+            FlightSqlCommand::GetTables(command) => Some(tables_batch(command, tables)),
+            FlightSqlCommand::GetTableTypes => Some(table_types_batch()),
+            FlightSqlCommand::StatementQuery(_) | FlightSqlCommand::PreparedStatementQuery(_) => {
+                None
+            }
+        }
+    }
+}
+
+/// Wrap a Flight SQL message in `Any` and encode it
+pub fn pack<M: Message>(name: &str, message: &M) -> Result<Vec<u8>> {
+    let mut value = Vec::with_capacity(message.encoded_len());
+    message
+        .encode(&mut value)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    let any = Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+        value,
+    };
+    let mut buf = Vec::with_capacity(any.encoded_len());
+    any.encode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    Ok(buf)
+}
+
+/// Decode a Flight SQL message of the given name that is wrapped in `Any`, such as the body of
+/// an action
+pub fn unpack<M: Message + Default>(name: &str, buf: &[u8]) -> Result<M> {
+    let any = Any::decode(buf).map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    if any.type_url != format!("{}{}", TYPE_URL_PREFIX, name) {
+        return Err(BallistaError::General(format!(
+            "Expected Flight SQL message {} but received {}",
+            name, any.type_url
+        )));
+    }
+    M::decode(any.value.as_slice()).map_err(|e| BallistaError::General(format!("{:?}", e)))
+}
+
+/// The Arrow IPC encoding of a schema, as Flight SQL clients expect it in responses
+pub fn ipc_schema(schema: &Schema) -> Vec<u8> {
+    SchemaResult::from(schema).schema
+}
+
+fn catalogs_batch() -> Result<RecordBatch> {
+    let schema = Schema::new(vec![Field::new("catalog_name", DataType::Utf8, false)]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(array::StringArray::from(vec![CATALOG_NAME]))],
+    )?)
+}
+
+fn db_schemas_batch(command: &CommandGetDbSchemas) -> Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("catalog_name", DataType::Utf8, true),
+        Field::new("db_schema_name", DataType::Utf8, false),
+    ]);
+    let matches = matches_catalog(&command.catalog)
+        && matches_pattern(&command.db_schema_filter_pattern, DB_SCHEMA_NAME);
+    let rows = if matches { 1 } else { 0 };
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(array::StringArray::from(vec![CATALOG_NAME; rows])),
+            Arc::new(array::StringArray::from(vec![DB_SCHEMA_NAME; rows])),
+        ],
+    )?)
+}
+
+fn tables_batch(
+    command: &CommandGetTables,
+    tables: &HashMap<String, LogicalPlan>,
+) -> Result<RecordBatch> {
+    let mut fields = vec![
+        Field::new("catalog_name", DataType::Utf8, true),
+        Field::new("db_schema_name", DataType::Utf8, true),
+        Field::new("table_name", DataType::Utf8, false),
+        Field::new("table_type", DataType::Utf8, false),
+    ];
+    if command.include_schema {
+        fields.push(Field::new("table_schema", DataType::Binary, false));
+    }
+
+    let mut names: Vec<&String> = if matches_catalog(&command.catalog)
+        && matches_pattern(&command.db_schema_filter_pattern, DB_SCHEMA_NAME)
+        && (command.table_types.is_empty() || command.table_types.iter().any(|t| t == TABLE_TYPE))
+    {
+        tables
+            .keys()
+            .filter(|name| matches_pattern(&command.table_name_filter_pattern, name))
+            .collect()
+    } else {
+        vec![]
+    };
+    names.sort();
+
+    let rows = names.len();
+    let mut columns: Vec<array::ArrayRef> = vec![
+        Arc::new(array::StringArray::from(vec![CATALOG_NAME; rows])),
+        Arc::new(array::StringArray::from(vec![DB_SCHEMA_NAME; rows])),
+        Arc::new(array::StringArray::from(
+            names.iter().map(|name| name.as_str()).collect::<Vec<_>>(),
+        )),
+        Arc::new(array::StringArray::from(vec![TABLE_TYPE; rows])),
+    ];
+    if command.include_schema {
+        let schemas: Vec<Vec<u8>> = names
+            .iter()
+            .map(|name| ipc_schema(tables[*name].schema()))
+            .collect();
+        columns.push(Arc::new(array::BinaryArray::from(
+            schemas.iter().map(|s| s.as_slice()).collect::<Vec<_>>(),
+        )));
+    }
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+fn table_types_batch() -> Result<RecordBatch> {
+    let schema = Schema::new(vec![Field::new("table_type", DataType::Utf8, false)]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(array::StringArray::from(vec![TABLE_TYPE]))],
+    )?)
+}
+
+/// Whether a catalog filter, which is empty when it is not set, matches the catalog
+fn matches_catalog(catalog: &str) -> bool {
+    catalog.is_empty() || catalog == CATALOG_NAME
+}
+
+/// Whether a SQL `LIKE` pattern, which is empty when it is not set, matches a name. `%`
+/// matches any sequence of characters and `_` matches any one character.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('%', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+            Some(('_', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    pattern.is_empty() || matches(&pattern, &name)
+}
+
+/// SQL queries that clients have planned once to execute repeatedly, by handle
+pub type PreparedStatements = PreparedStore<LogicalPlan>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::logicalplan::LogicalPlanBuilder;
+
+    #[test]
+    fn decode_flight_sql_commands() -> Result<()> {
+        let query = CommandStatementQuery {
+            query: "SELECT 1".to_owned(),
+            transaction_id: vec![],
+        };
+        let buf = pack("CommandStatementQuery", &query)?;
+        assert_eq!(
+            FlightSqlCommand::StatementQuery(query),
+            FlightSqlCommand::decode(&buf).unwrap()?
+        );
+        assert!(
+            FlightSqlCommand::decode(&pack("CommandGetSqlInfo", &Any::default())?)
+                .unwrap()
+                .is_err()
+        );
+        // other messages, such as Ballista actions, are not Flight SQL commands
+        assert!(FlightSqlCommand::decode(b"\x12\x03abc").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn list_tables_matching_patterns() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let mut tables = HashMap::new();
+        for name in &["employee", "department", "employer"] {
+            let plan = LogicalPlanBuilder::scan("default", name, &schema, None)?.build()?;
+            tables.insert(name.to_string(), plan);
+        }
+
+        let command = CommandGetTables {
+            table_name_filter_pattern: "employ__".to_owned(),
+            include_schema: true,
+            ..Default::default()
+        };
+        let batch = tables_batch(&command, &tables)?;
+        assert_eq!(5, batch.num_columns());
+        let names = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<array::StringArray>()
+            .unwrap();
+        assert_eq!(2, names.len());
+        assert_eq!("employee", names.value(0));
+        assert_eq!("employer", names.value(1));
+
+        let command = CommandGetTables {
+            catalog: "other".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(0, tables_batch(&command, &tables)?.num_rows());
+        Ok(())
+    }
+
+    #[test]
+    fn match_like_patterns() {
+        assert!(matches_pattern("", "employee"));
+        assert!(matches_pattern("%", ""));
+        assert!(matches_pattern("emp%", "employee"));
+        assert!(matches_pattern("%loy%", "employee"));
+        assert!(matches_pattern("_mployee", "employee"));
+        assert!(!matches_pattern("emp", "employee"));
+        assert!(!matches_pattern("_employee", "employee"));
+    }
+
+    #[test]
+    fn close_prepared_statements() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let plan = LogicalPlanBuilder::scan("default", "employee", &schema, None)?.build()?;
+        let statements = PreparedStatements::default();
+        let handle = statements.insert("etl", plan);
+        assert!(statements.get("dashboards", &handle).is_none());
+        assert!(!statements.remove("dashboards", &handle));
+        assert!(statements.get("etl", &handle).is_some());
+        assert!(statements.remove("etl", &handle));
+        assert!(statements.get("etl", &handle).is_none());
+        assert!(!statements.remove("etl", &handle));
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! History of the jobs that the scheduler has run.
+//!
+//! The scheduler keeps the query, duration, and metrics of a bounded number of finished jobs,
+//! along with the metrics of each of their stages, so that the runs of a query can be compared
+//! long after the status of a job has been evicted. Runs of the same query share a fingerprint
+//! of their plan.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::scheduler::JobProfile;
+use crate::distributed::scheduler_server::JobState;
+
+use uuid::Uuid;
+
+/// Default maximum number of finished jobs to keep in the history
+pub const DEFAULT_MAX_JOB_HISTORY: usize = 1000;
+
+/// Metrics of a stage of a finished job
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageHistory {
+    pub stage_id: usize,
+    /// Number of tasks that the stage ran
+    pub tasks: usize,
+    /// Wall-clock time taken to run all of the tasks in the stage
+    pub duration_ms: u64,
+    pub output_rows: usize,
+    pub shuffle_bytes: usize,
+    /// The physical plan that the tasks of the stage executed
+    pub plan: String,
+}
+
+/// A job that the scheduler has run, with its query and metrics
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobHistoryEntry {
+    pub job_uuid: Uuid,
+    pub tenant: String,
+    /// SQL text that the client built the plan from, if it sent it
+    pub sql: Option<String>,
+    /// The optimized logical plan of the job
+    pub plan: String,
+    /// Fingerprint of the plan, which is the same for every run of the same query
+    pub fingerprint: u64,
+    pub state: JobState,
+    pub submitted_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    /// Number of rows in the results of the job
+    pub output_rows: usize,
+    /// Bytes of shuffle partitions written across all stages
+    pub shuffle_bytes: usize,
+    /// Metrics of the stages, in the order that they completed
+    pub stages: Vec<StageHistory>,
+}
+
+impl JobHistoryEntry {
+    /// Time from when the job was submitted until it finished, including time spent queued
+    pub fn duration(&self) -> Option<Duration> {
+        self.finished_at.map(|finished_at| {
+            finished_at
+                .duration_since(self.submitted_at)
+                .unwrap_or_else(|_| Duration::from_secs(0))
+        })
+    }
+
+    /// Whether the SQL text or plan of the job contains the given text, ignoring case
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        self.plan.to_lowercase().contains(&text)
+            || self
+                .sql
+                .as_ref()
+                .map(|sql| sql.to_lowercase().contains(&text))
+                .unwrap_or(false)
+    }
+}
+
+/// Fingerprint of a logical plan that identifies the runs of the same query, regardless of
+/// the data that they read
+pub fn query_fingerprint(plan: &LogicalPlan) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", plan).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Criteria for searching the history of jobs
+#[derive(Debug, Clone, Default)]
+pub struct JobHistoryFilter {
+    /// Tenant that jobs must belong to, or any tenant when not set
+    pub tenant: Option<String>,
+    /// Text that the SQL text or plan of jobs must contain, ignoring case
+    pub search: Option<String>,
+    /// Fingerprint that the plan of jobs must have, to list the runs of one query
+    pub fingerprint: Option<u64>,
+    /// Max number of jobs to list
+    pub limit: Option<usize>,
+}
+
+/// Bounded history of the jobs that the scheduler has run. Jobs are added when they start
+/// running and kept once they finish, until the history is full and the oldest are evicted.
+pub struct JobHistory {
+    max_entries: usize,
+    /// Jobs that are running, keyed by job UUID
+    running: Mutex<HashMap<Uuid, JobHistoryEntry>>,
+    /// Jobs that have finished, oldest first
+    finished: Mutex<VecDeque<JobHistoryEntry>>,
+}
+
+impl Default for JobHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_JOB_HISTORY)
+    }
+}
+
+impl JobHistory {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            running: Mutex::new(HashMap::new()),
+            finished: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Record that a job has started running
+    pub fn start(
+        &self,
+        job_uuid: Uuid,
+        tenant: &str,
+        sql: Option<String>,
+        plan: &LogicalPlan,
+        submitted_at: SystemTime,
+    ) {
+        let entry = JobHistoryEntry {
+            job_uuid,
+            tenant: tenant.to_owned(),
+            sql,
+            plan: format!("{:?}", plan),
+            fingerprint: query_fingerprint(plan),
+            state: JobState::Running,
+            submitted_at,
+            finished_at: None,
+            output_rows: 0,
+            shuffle_bytes: 0,
+            stages: vec![],
+        };
+        let mut running = self.running.lock().expect("failed to lock mutex");
+        running.insert(job_uuid, entry);
+    }
+
+    /// Record that a job has finished, with the profile of the stages that completed. The
+    /// rows of the results are those written by the root stage. Jobs that were not started
+    /// with this history, such as jobs resumed after a restart, are not recorded.
+    pub fn finish(
+        &self,
+        job_uuid: &Uuid,
+        state: &JobState,
+        profile: Option<&JobProfile>,
+        root_stage_id: usize,
+    ) {
+        let entry = {
+            let mut running = self.running.lock().expect("failed to lock mutex");
+            running.remove(job_uuid)
+        };
+        let mut entry = match entry {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.state = state.clone();
+        entry.finished_at = Some(SystemTime::now());
+        if let Some(profile) = profile {
+            entry.stages = profile
+                .stages
+                .iter()
+                .map(|stage| {
+                    let total = stage.total();
+                    StageHistory {
+                        stage_id: stage.stage_id,
+                        tasks: stage.tasks.len(),
+                        duration_ms: stage.duration_ms,
+                        output_rows: total.output_rows,
+                        shuffle_bytes: total.shuffle_bytes,
+                        plan: format!("{:?}", stage.plan),
+                    }
+                })
+                .collect();
+            entry.output_rows = entry
+                .stages
+                .iter()
+                .filter(|stage| stage.stage_id == root_stage_id)
+                .map(|stage| stage.output_rows)
+                .sum();
+            entry.shuffle_bytes = entry.stages.iter().map(|stage| stage.shuffle_bytes).sum();
+        }
+
+        let mut finished = self.finished.lock().expect("failed to lock mutex");
+        finished.push_back(entry);
+        while finished.len() > self.max_entries {
+            finished.pop_front();
+        }
+    }
+
+    /// The finished jobs that match the filter, most recent first
+    pub fn list(&self, filter: &JobHistoryFilter) -> Vec<JobHistoryEntry> {
+        let finished = self.finished.lock().expect("failed to lock mutex");
+        finished
+            .iter()
+            .rev()
+            .filter(|entry| match &filter.tenant {
+                Some(tenant) => entry.tenant == *tenant,
+                None => true,
+            })
+            .filter(|entry| match &filter.search {
+                Some(text) => entry.matches(text),
+                None => true,
+            })
+            .filter(|entry| match filter.fingerprint {
+                Some(fingerprint) => entry.fingerprint == fingerprint,
+                None => true,
+            })
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// A finished job, if it is still in the history
+    pub fn get(&self, job_uuid: &Uuid) -> Option<JobHistoryEntry> {
+        let finished = self.finished.lock().expect("failed to lock mutex");
+        finished
+            .iter()
+            .find(|entry| entry.job_uuid == *job_uuid)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
+    use crate::datafusion::logicalplan::{col, LogicalPlanBuilder};
+    use crate::distributed::scheduler::StageProfile;
+    use crate::error::Result;
+    use crate::execution::operators::ShuffleReaderExec;
+    use crate::execution::physical_plan::{PhysicalPlan, TaskMetrics};
+    use std::sync::Arc;
+
+    fn plan(column: &str) -> Result<LogicalPlan> {
+        let schema = Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Int32, false),
+        ]);
+        Ok(LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema),
+            None,
+        )?
+        .project(vec![col(column)])?
+        .build()?)
+    }
+
+    fn stage(stage_id: usize, rows: usize) -> StageProfile {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let reader = ShuffleReaderExec::new(Arc::new(schema), vec![]);
+        let task = TaskMetrics {
+            output_rows: rows,
+            shuffle_bytes: 100,
+            ..TaskMetrics::default()
+        };
+        StageProfile {
+            stage_id,
+            plan: Arc::new(PhysicalPlan::ShuffleReader(Arc::new(reader))),
+            duration_ms: 10,
+            tasks: vec![task.clone(), task],
+        }
+    }
+
+    #[test]
+    fn record_finished_jobs() -> Result<()> {
+        let history = JobHistory::new(10);
+        let job_uuid = Uuid::new_v4();
+        history.start(
+            job_uuid,
+            "tenant",
+            Some("SELECT a FROM employee".to_owned()),
+            &plan("a")?,
+            SystemTime::now(),
+        );
+        assert!(history.get(&job_uuid).is_none());
+
+        let profile = JobProfile {
+            stages: vec![stage(1, 1000), stage(2, 5)],
+        };
+        history.finish(&job_uuid, &JobState::Cancelled, Some(&profile), 2);
+        let entry = history.get(&job_uuid).expect("job is in the history");
+        assert_eq!(JobState::Cancelled, entry.state);
+        assert_eq!(10, entry.output_rows);
+        assert_eq!(400, entry.shuffle_bytes);
+        assert_eq!(2, entry.stages.len());
+        assert_eq!(2, entry.stages[0].tasks);
+        assert!(entry.duration().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn search_history() -> Result<()> {
+        let history = JobHistory::new(2);
+        let mut job_uuids = vec![];
+        for (column, sql) in &[("a", "SELECT a"), ("b", "SELECT b"), ("a", "select A")] {
+            let job_uuid = Uuid::new_v4();
+            history.start(
+                job_uuid,
+                "tenant",
+                Some((*sql).to_owned()),
+                &plan(column)?,
+                SystemTime::now(),
+            );
+            history.finish(&job_uuid, &JobState::Cancelled, None, 0);
+            job_uuids.push(job_uuid);
+        }
+
+        // the oldest job was evicted
+        let all = history.list(&JobHistoryFilter::default());
+        assert_eq!(vec![job_uuids[2], job_uuids[1]], uuids(&all));
+
+        let filter = JobHistoryFilter {
+            search: Some("select a".to_owned()),
+            ..JobHistoryFilter::default()
+        };
+        assert_eq!(vec![job_uuids[2]], uuids(&history.list(&filter)));
+
+        let filter = JobHistoryFilter {
+            fingerprint: Some(query_fingerprint(&plan("b")?)),
+            ..JobHistoryFilter::default()
+        };
+        assert_eq!(vec![job_uuids[1]], uuids(&history.list(&filter)));
+
+        let filter = JobHistoryFilter {
+            limit: Some(1),
+            ..JobHistoryFilter::default()
+        };
+        assert_eq!(1, history.list(&filter).len());
+        Ok(())
+    }
+
+    #[test]
+    fn list_jobs_of_tenant() -> Result<()> {
+        let history = JobHistory::new(10);
+        let mut job_uuids = vec![];
+        for tenant in &["etl", "dashboards", "etl"] {
+            let job_uuid = Uuid::new_v4();
+            history.start(job_uuid, tenant, None, &plan("a")?, SystemTime::now());
+            history.finish(&job_uuid, &JobState::Cancelled, None, 0);
+            job_uuids.push(job_uuid);
+        }
+
+        let filter = JobHistoryFilter {
+            tenant: Some("etl".to_owned()),
+            ..JobHistoryFilter::default()
+        };
+        assert_eq!(
+            vec![job_uuids[2], job_uuids[0]],
+            uuids(&history.list(&filter))
+        );
+        let filter = JobHistoryFilter {
+            tenant: Some("unknown".to_owned()),
+            ..JobHistoryFilter::default()
+        };
+        assert!(history.list(&filter).is_empty());
+        assert_eq!(3, history.list(&JobHistoryFilter::default()).len());
+        Ok(())
+    }
+
+    fn uuids(entries: &[JobHistoryEntry]) -> Vec<Uuid> {
+        entries.iter().map(|entry| entry.job_uuid).collect()
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pluggable stores for the state of the jobs running on a scheduler, so that a scheduler that
+//! restarts can resume the jobs that were in flight rather than losing them.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::distributed::scheduler::{Job, QuerySettings, Stage};
+use crate::distributed::scheduler_server::JobStatus;
+use crate::distributed::scheduling::DEFAULT_TENANT;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::physical_plan::{PhysicalPlan, ShuffleLocation};
+use crate::serde::{decode_job_record, encode_job_record};
+
+use async_trait::async_trait;
+use etcd_client::{Client, GetOptions};
+use uuid::Uuid;
+
+/// Persisted state of a stage within a job
+#[derive(Debug, Clone)]
+pub struct StageRecord {
+    pub stage_id: usize,
+    pub prior_stages: Vec<usize>,
+    pub plan: PhysicalPlan,
+    pub completed: bool,
+    /// Locations of the shuffle partitions produced by the stage, once it has completed
+    pub shuffle_locations: Vec<ShuffleLocation>,
+    /// Id of the executor that each task was placed on, keyed by partition id
+    pub task_assignments: HashMap<usize, String>,
+}
+
+/// Persisted state of a job: its DAG of stages, the progress of each stage, and the executors
+/// that its tasks were placed on
+#[derive(Debug, Clone)]
+pub struct JobRecord {
+    pub status: JobStatus,
+    pub root_stage_id: usize,
+    pub stages: Vec<StageRecord>,
+    /// Settings that the job was submitted with
+    pub settings: QuerySettings,
+    /// Tenant that submitted the job
+    pub tenant: String,
+}
+
+impl JobRecord {
+    /// Create the record of a job that has not started running yet
+    pub fn new(job: &Job, status: JobStatus) -> Result<Self> {
+        let stages = job
+            .stages
+            .iter()
+            .map(|stage| {
+                let stage = stage.borrow();
+                let plan = stage
+                    .plan
+                    .as_ref()
+                    .ok_or_else(|| ballista_error("Stages should always have a plan"))?;
+                Ok(StageRecord {
+                    stage_id: stage.id,
+                    prior_stages: stage.prior_stages.clone(),
+                    plan: plan.as_ref().clone(),
+                    completed: false,
+                    shuffle_locations: vec![],
+                    task_assignments: HashMap::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            status,
+            root_stage_id: job.root_stage_id,
+            stages,
+            settings: QuerySettings::default(),
+            tenant: DEFAULT_TENANT.to_owned(),
+        })
+    }
+
+    pub fn with_settings(mut self, settings: QuerySettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = tenant.to_owned();
+        self
+    }
+
+    /// Rebuild the DAG of stages of the job
+    pub fn to_job(&self) -> Job {
+        Job {
+            id: self.status.job_uuid,
+            stages: self
+                .stages
+                .iter()
+                .map(|stage| {
+                    Rc::new(RefCell::new(Stage {
+                        id: stage.stage_id,
+                        prior_stages: stage.prior_stages.clone(),
+                        plan: Some(Arc::new(stage.plan.clone())),
+                    }))
+                })
+                .collect(),
+            root_stage_id: self.root_stage_id,
+        }
+    }
+
+    pub fn stage(&self, stage_id: usize) -> Option<&StageRecord> {
+        self.stages.iter().find(|stage| stage.stage_id == stage_id)
+    }
+
+    pub fn stage_mut(&mut self, stage_id: usize) -> Option<&mut StageRecord> {
+        self.stages
+            .iter_mut()
+            .find(|stage| stage.stage_id == stage_id)
+    }
+}
+
+/// Store that the scheduler persists the state of its jobs in
+#[async_trait]
+pub trait JobStateStore: Send + Sync {
+    /// Create or replace the record of a job
+    async fn save_job(&self, job: &JobRecord) -> Result<()>;
+
+    async fn get_job(&self, job_uuid: &Uuid) -> Result<Option<JobRecord>>;
+
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>>;
+
+    async fn remove_job(&self, job_uuid: &Uuid) -> Result<()>;
+}
+
+/// Store that keeps job state in memory, which does not survive a restart of the scheduler
+#[derive(Default)]
+pub struct InMemoryJobStateStore {
+    jobs: Mutex<HashMap<Uuid, JobRecord>>,
+}
+
+#[async_trait]
+impl JobStateStore for InMemoryJobStateStore {
+    async fn save_job(&self, job: &JobRecord) -> Result<()> {
+        let mut jobs = self.jobs.lock().expect("failed to lock mutex");
+        jobs.insert(job.status.job_uuid, job.clone());
+        Ok(())
+    }
+
+    async fn get_job(&self, job_uuid: &Uuid) -> Result<Option<JobRecord>> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        Ok(jobs.get(job_uuid).cloned())
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        Ok(jobs.values().cloned().collect())
+    }
+
+    async fn remove_job(&self, job_uuid: &Uuid) -> Result<()> {
+        let mut jobs = self.jobs.lock().expect("failed to lock mutex");
+        jobs.remove(job_uuid);
+        Ok(())
+    }
+}
+
+/// Store that keeps job state in a sled database on the local disk of the scheduler
+pub struct SledJobStateStore {
+    db: sled::Db,
+}
+
+impl SledJobStateStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let db = sled::open(path).map_err(to_sled_err)?;
+        Ok(Self { db })
+    }
+}
+
+fn to_sled_err(e: sled::Error) -> BallistaError {
+    ballista_error(&format!("sled error {:?}", e))
+}
+
+#[async_trait]
+impl JobStateStore for SledJobStateStore {
+    async fn save_job(&self, job: &JobRecord) -> Result<()> {
+        self.db
+            .insert(job.status.job_uuid.as_bytes(), encode_job_record(job)?)
+            .map_err(to_sled_err)?;
+        self.db.flush().map_err(to_sled_err)?;
+        Ok(())
+    }
+
+    async fn get_job(&self, job_uuid: &Uuid) -> Result<Option<JobRecord>> {
+        match self.db.get(job_uuid.as_bytes()).map_err(to_sled_err)? {
+            Some(value) => Ok(Some(decode_job_record(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>> {
+        let mut jobs = vec![];
+        for entry in self.db.iter() {
+            let (_, value) = entry.map_err(to_sled_err)?;
+            jobs.push(decode_job_record(&value)?);
+        }
+        Ok(jobs)
+    }
+
+    async fn remove_job(&self, job_uuid: &Uuid) -> Result<()> {
+        self.db.remove(job_uuid.as_bytes()).map_err(to_sled_err)?;
+        self.db.flush().map_err(to_sled_err)?;
+        Ok(())
+    }
+}
+
+/// Store that keeps job state in etcd under `/ballista-jobs/<cluster>/`, so that a scheduler
+/// can be restarted on a different host
+pub struct EtcdJobStateStore {
+    etcd_urls: String,
+    prefix: String,
+}
+
+impl EtcdJobStateStore {
+    pub fn new(etcd_urls: &str, cluster_name: &str) -> Self {
+        Self {
+            etcd_urls: etcd_urls.to_owned(),
+            prefix: format!("/ballista-jobs/{}/", cluster_name),
+        }
+    }
+
+    fn key(&self, job_uuid: &Uuid) -> String {
+        format!("{}{}", self.prefix, job_uuid)
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        Client::connect([&self.etcd_urls], None)
+            .await
+            .map_err(to_etcd_err)
+    }
+}
+
+fn to_etcd_err(e: etcd_client::Error) -> BallistaError {
+    ballista_error(&format!("etcd error {:?}", e))
+}
+
+#[async_trait]
+impl JobStateStore for EtcdJobStateStore {
+    async fn save_job(&self, job: &JobRecord) -> Result<()> {
+        let mut client = self.connect().await?;
+        client
+            .put(
+                self.key(&job.status.job_uuid),
+                encode_job_record(job)?,
+                None,
+            )
+            .await
+            .map_err(to_etcd_err)?;
+        Ok(())
+    }
+
+    async fn get_job(&self, job_uuid: &Uuid) -> Result<Option<JobRecord>> {
+        let mut client = self.connect().await?;
+        let resp = client
+            .get(self.key(job_uuid), None)
+            .await
+            .map_err(to_etcd_err)?;
+        match resp.kvs().first() {
+            Some(kv) => Ok(Some(decode_job_record(kv.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<JobRecord>> {
+        let mut client = self.connect().await?;
+        let resp = client
+            .get(self.prefix.as_str(), Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(to_etcd_err)?;
+        resp.kvs()
+            .iter()
+            .map(|kv| decode_job_record(kv.value()))
+            .collect()
+    }
+
+    async fn remove_job(&self, job_uuid: &Uuid) -> Result<()> {
+        let mut client = self.connect().await?;
+        client
+            .delete(self.key(job_uuid), None)
+            .await
+            .map_err(to_etcd_err)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::distributed::progress::JobProgress;
+    use crate::distributed::scheduler_server::JobState;
+    use crate::execution::operators::ShuffleReaderExec;
+    use crate::execution::physical_plan::{ExecutorMeta, ShuffleId};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn executor_meta(id: &str) -> ExecutorMeta {
+        ExecutorMeta {
+            id: id.to_owned(),
+            host: "localhost".to_owned(),
+            port: 50051,
+        }
+    }
+
+    fn job_record(state: JobState) -> JobRecord {
+        let job_uuid = Uuid::new_v4();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let plan = |stage_id| {
+            PhysicalPlan::ShuffleReader(Arc::new(ShuffleReaderExec::new(
+                schema.clone(),
+                vec![ShuffleId::new(job_uuid, stage_id, 0)],
+            )))
+        };
+        let mut task_assignments = HashMap::new();
+        task_assignments.insert(0, "executor-1".to_owned());
+        task_assignments.insert(1, "executor-2".to_owned());
+        JobRecord {
+            status: JobStatus {
+                job_uuid,
+                state,
+                submitted_at: UNIX_EPOCH + Duration::from_millis(1_600_000_000_000),
+                progress: JobProgress::default(),
+            },
+            root_stage_id: 2,
+            stages: vec![
+                StageRecord {
+                    stage_id: 1,
+                    prior_stages: vec![],
+                    plan: plan(0),
+                    completed: true,
+                    shuffle_locations: vec![
+                        ShuffleLocation::new(
+                            ShuffleId::new(job_uuid, 1, 0),
+                            executor_meta("executor-1"),
+                        ),
+                        ShuffleLocation::new(
+                            ShuffleId::new(job_uuid, 1, 1),
+                            executor_meta("executor-2"),
+                        ),
+                    ],
+                    task_assignments,
+                },
+                StageRecord {
+                    stage_id: 2,
+                    prior_stages: vec![1],
+                    plan: plan(1),
+                    completed: false,
+                    shuffle_locations: vec![],
+                    task_assignments: HashMap::new(),
+                },
+            ],
+            settings: QuerySettings {
+                batch_size: Some(1024),
+                ..QuerySettings::default()
+            },
+            tenant: "analytics".to_owned(),
+        }
+    }
+
+    fn assert_same_record(expected: &JobRecord, actual: &JobRecord) {
+        assert_eq!(expected.status, actual.status);
+        assert_eq!(expected.root_stage_id, actual.root_stage_id);
+        assert_eq!(
+            format!("{:?}", expected.settings),
+            format!("{:?}", actual.settings)
+        );
+        assert_eq!(expected.tenant, actual.tenant);
+        assert_eq!(expected.stages.len(), actual.stages.len());
+        for (expected, actual) in expected.stages.iter().zip(&actual.stages) {
+            assert_eq!(expected.stage_id, actual.stage_id);
+            assert_eq!(expected.prior_stages, actual.prior_stages);
+            assert_eq!(format!("{:?}", expected.plan), format!("{:?}", actual.plan));
+            assert_eq!(expected.completed, actual.completed);
+            assert_eq!(
+                format!("{:?}", expected.shuffle_locations),
+                format!("{:?}", actual.shuffle_locations)
+            );
+            assert_eq!(expected.task_assignments, actual.task_assignments);
+        }
+    }
+
+    #[test]
+    fn roundtrip_job_record() -> Result<()> {
+        let record = job_record(JobState::Running);
+        let record2 = decode_job_record(&encode_job_record(&record)?)?;
+        assert_same_record(&record, &record2);
+        Ok(())
+    }
+
+    fn save_list_and_remove(store: &dyn JobStateStore) -> Result<()> {
+        smol::run(async {
+            let running = job_record(JobState::Running);
+            let failed = job_record(JobState::Failed("executor lost".to_owned()));
+            store.save_job(&running).await?;
+            store.save_job(&failed).await?;
+
+            let running_uuid = running.status.job_uuid;
+            let record = store.get_job(&running_uuid).await?.expect("job was saved");
+            assert_same_record(&running, &record);
+
+            // saving a job again replaces its record
+            let mut completed = running.clone();
+            completed.stages[1].completed = true;
+            completed.status.state = JobState::Completed(vec![]);
+            store.save_job(&completed).await?;
+            let mut jobs = store.list_jobs().await?;
+            assert_eq!(2, jobs.len());
+            jobs.sort_by_key(|job| job.status.job_uuid != running_uuid);
+            assert_same_record(&completed, &jobs[0]);
+            assert_same_record(&failed, &jobs[1]);
+
+            store.remove_job(&running_uuid).await?;
+            assert!(store.get_job(&running_uuid).await?.is_none());
+            let jobs = store.list_jobs().await?;
+            assert_eq!(1, jobs.len());
+            assert_eq!(failed.status.job_uuid, jobs[0].status.job_uuid);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn in_memory_store() -> Result<()> {
+        save_list_and_remove(&InMemoryJobStateStore::default())
+    }
+
+    #[test]
+    fn sled_store() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("ballista-job-state-{}", Uuid::new_v4()));
+        let result = save_list_and_remove(&SledJobStateStore::open(
+            path.to_str().expect("temp dir is valid UTF-8"),
+        )?);
+        std::fs::remove_dir_all(&path)?;
+        result
+    }
+}
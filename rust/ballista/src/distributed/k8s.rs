@@ -14,17 +14,65 @@
 
 //! Ballista k8s cluster management utilities
 
-use crate::error::BallistaError;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::distributed::discovery::DiscoveryBackend;
+use crate::distributed::registry::ExecutorRegistration;
+use crate::error::{ballista_error, BallistaError};
 use crate::execution::physical_plan::ExecutorMeta;
 
+use async_trait::async_trait;
 use k8s_openapi::api;
+use log::{debug, info};
+use trust_dns_resolver::TokioAsyncResolver;
+
+pub(crate) const CLUSTER_LABEL_KEY: &str = "ballista-cluster";
+
+/// Default interval at which the executors are looked up again, so that changes to the number
+/// of replicas in the stateful set are picked up
+pub const DEFAULT_K8S_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How executor pods are found
+#[derive(Debug, Clone)]
+pub enum KubernetesSource {
+    /// List the pods matching a label selector with the API server. This requires permission
+    /// to list pods.
+    ApiServer { label_selector: String },
+    /// Resolve the DNS SRV records of a named port of the headless service. This requires no
+    /// permissions but only returns pods that are ready.
+    Dns { port_name: String },
+}
 
-const CLUSTER_LABEL_KEY: &str = "ballista-cluster";
+/// Configuration for discovering executors running in Kubernetes
+#[derive(Debug, Clone)]
+pub struct KubernetesConfig {
+    pub namespace: String,
+    /// Name of the headless service that governs the stateful set
+    pub service: String,
+    pub source: KubernetesSource,
+    pub refresh_interval: Duration,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        Self {
+            namespace: "default".to_owned(),
+            service: "ballista".to_owned(),
+            source: KubernetesSource::ApiServer {
+                label_selector: format!("{}=ballista", CLUSTER_LABEL_KEY),
+            },
+            refresh_interval: DEFAULT_K8S_REFRESH_INTERVAL,
+        }
+    }
+}
 
-/// Get a list of executor nodes in a cluster by listing pods in the stateful set.
+/// Get a list of executor nodes in a cluster by listing the running pods that match a label
+/// selector.
 pub async fn k8s_get_executors(
     namespace: &str,
-    cluster_name: &str,
+    service: &str,
+    label_selector: &str,
 ) -> Result<Vec<ExecutorMeta>, BallistaError> {
     use api::core::v1::Pod;
 
@@ -34,18 +82,29 @@ pub async fn k8s_get_executors(
     let mut executors = vec![];
 
     let pods = pods
-        .list(
-            &kube::api::ListParams::default()
-                .labels(&format!("{}={}", CLUSTER_LABEL_KEY, cluster_name)),
-        )
+        .list(&kube::api::ListParams::default().labels(label_selector))
         .await?;
 
     for pod in &pods {
+        // pods that are pending or terminating cannot accept tasks. Pods that are being shut
+        // down are still running but have a deletion timestamp.
+        let running = pod
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_deref())
+            == Some("Running");
+        let terminating = pod
+            .metadata
+            .as_ref()
+            .map_or(false, |meta| meta.deletion_timestamp.is_some());
+        if !running || terminating {
+            continue;
+        }
         if let Some(pod_meta) = pod.metadata.as_ref() {
             if let Some(pod_name) = pod_meta.name.as_ref() {
                 if let Some(pod_spec) = pod.spec.as_ref() {
                     if !pod_spec.containers.is_empty() {
-                        let host = format!("{}.{}.{}", pod_name, cluster_name, namespace,);
+                        let host = format!("{}.{}.{}", pod_name, service, namespace);
 
                         if let Some(port) = pod_spec.containers[0].ports.as_ref() {
                             if !port.is_empty() {
@@ -63,3 +122,104 @@ pub async fn k8s_get_executors(
     }
     Ok(executors)
 }
+
+/// Get a list of executor nodes in a cluster by resolving the SRV records of a named port of
+/// the headless service, e.g. `_flight._tcp.ballista.default.svc.cluster.local`.
+pub async fn k8s_dns_get_executors(
+    namespace: &str,
+    service: &str,
+    port_name: &str,
+) -> Result<Vec<ExecutorMeta>, BallistaError> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()
+        .await
+        .map_err(|e| ballista_error(&format!("Failed to create DNS resolver {:?}", e)))?;
+    let name = format!(
+        "_{}._tcp.{}.{}.svc.cluster.local.",
+        port_name, service, namespace
+    );
+    let records = resolver
+        .srv_lookup(name.as_str())
+        .await
+        .map_err(|e| ballista_error(&format!("Failed to resolve {} {:?}", name, e)))?;
+
+    Ok(records
+        .iter()
+        .map(|srv| {
+            let host = srv.target().to_utf8();
+            let host = host.trim_end_matches('.');
+            // the target is the DNS name of the pod, which starts with the pod name
+            let pod_name = host.split('.').next().unwrap_or(host);
+            ExecutorMeta {
+                id: pod_name.to_owned(),
+                host: host.to_owned(),
+                port: srv.port() as usize,
+            }
+        })
+        .collect())
+}
+
+/// Discovery backend that finds the executor pods of a stateful set. Executors do not need to
+/// register because Kubernetes tracks the pods. The executors are cached for the refresh
+/// interval.
+pub struct KubernetesDiscovery {
+    config: KubernetesConfig,
+    cache: Mutex<Option<(Instant, Vec<ExecutorMeta>)>>,
+}
+
+impl KubernetesDiscovery {
+    pub fn new(config: KubernetesConfig) -> Self {
+        Self {
+            config,
+            cache: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl DiscoveryBackend for KubernetesDiscovery {
+    fn register(&self, _registration: ExecutorRegistration) -> Result<(), BallistaError> {
+        Ok(())
+    }
+
+    async fn deregister(&self, _executor_id: &str) -> Result<(), BallistaError> {
+        // pods are no longer listed once Kubernetes starts terminating them
+        Ok(())
+    }
+
+    async fn get_executors(&self) -> Result<Vec<ExecutorMeta>, BallistaError> {
+        let cached = self
+            .cache
+            .lock()
+            .expect("failed to lock mutex")
+            .as_ref()
+            .filter(|(loaded, _)| loaded.elapsed() < self.config.refresh_interval)
+            .map(|(_, executors)| executors.clone());
+        if let Some(executors) = cached {
+            return Ok(executors);
+        }
+
+        let config = &self.config;
+        let executors = match &config.source {
+            KubernetesSource::ApiServer { label_selector } => {
+                k8s_get_executors(&config.namespace, &config.service, label_selector).await?
+            }
+            KubernetesSource::Dns { port_name } => {
+                k8s_dns_get_executors(&config.namespace, &config.service, port_name).await?
+            }
+        };
+
+        let mut cache = self.cache.lock().expect("failed to lock mutex");
+        let previous = cache.as_ref().map(|(_, executors)| executors.len());
+        if previous != Some(executors.len()) {
+            info!(
+                "Discovered executors in Kubernetes count={} previous={:?}",
+                executors.len(),
+                previous
+            );
+        } else {
+            debug!("Refreshed executors count={}", executors.len());
+        }
+        *cache = Some((Instant::now(), executors.clone()));
+        Ok(executors)
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deployment of Ballista clusters to Kubernetes.
+//!
+//! A cluster named `ballista` consists of:
+//!
+//! * a ConfigMap `ballista-config` holding the settings of the cluster as `BALLISTA_*`
+//!   environment variables, and a Secret `ballista-auth` holding the auth token, if any
+//! * a ServiceAccount `ballista` with a Role that allows listing pods, which executors and the
+//!   scheduler need to discover executors
+//! * a StatefulSet `ballista-executor` of executors, governed by the headless Service
+//!   `ballista-executor`
+//! * a Deployment `ballista-scheduler` of one scheduler, exposed by the Service
+//!   `ballista-scheduler`
+//!
+//! Manifests are applied with server-side apply, so applying a cluster again updates it in
+//! place. Scaling down removes the executors with the highest ordinals, which drain their
+//! tasks when Kubernetes sends them SIGTERM.
+
+use crate::config::{env_var, BallistaConfig, AUTH_TOKEN, EXECUTOR_PORT, SCHEDULER_PORT};
+use crate::distributed::k8s::CLUSTER_LABEL_KEY;
+use crate::error::{ballista_error, Result};
+use crate::BALLISTA_VERSION;
+
+use k8s_openapi::api::apps::v1::{Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret, Service, ServiceAccount};
+use k8s_openapi::api::rbac::v1::{Role, RoleBinding};
+use kube::api::{Api, DeleteParams, Meta, PatchParams, PatchStrategy};
+use kube::client::Client;
+use log::info;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+/// Field manager that manifests are applied as
+const FIELD_MANAGER: &str = "ballista-kube";
+
+/// Default image of executors and schedulers
+pub fn default_image() -> String {
+    format!("ballistacompute/ballista-rust:{}", BALLISTA_VERSION)
+}
+
+/// Description of a Ballista cluster to deploy
+#[derive(Debug, Clone)]
+pub struct ClusterSpec {
+    pub name: String,
+    pub namespace: String,
+    pub image: String,
+    /// Number of executor replicas
+    pub executors: usize,
+    /// CPU request and limit of each executor, e.g. `2`
+    pub executor_cpu: String,
+    /// Memory request and limit of each executor, e.g. `2048Mi`
+    pub executor_memory: String,
+    /// Settings of the executors and the scheduler
+    pub config: BallistaConfig,
+}
+
+impl ClusterSpec {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            namespace: "default".to_owned(),
+            image: default_image(),
+            executors: 1,
+            executor_cpu: "1".to_owned(),
+            executor_memory: "1024Mi".to_owned(),
+            config: BallistaConfig::new(),
+        }
+    }
+
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = namespace.to_owned();
+        self
+    }
+
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.image = image.to_owned();
+        self
+    }
+
+    pub fn with_executors(mut self, executors: usize) -> Self {
+        self.executors = executors;
+        self
+    }
+
+    /// Request and limit each executor to the given CPU and memory
+    pub fn with_executor_resources(mut self, cpu: &str, memory: &str) -> Self {
+        self.executor_cpu = cpu.to_owned();
+        self.executor_memory = memory.to_owned();
+        self
+    }
+
+    /// Configure the executors and the scheduler with the given settings
+    pub fn with_config(mut self, config: BallistaConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    fn executor_name(&self) -> String {
+        format!("{}-executor", self.name)
+    }
+
+    fn scheduler_name(&self) -> String {
+        format!("{}-scheduler", self.name)
+    }
+
+    fn config_map_name(&self) -> String {
+        format!("{}-config", self.name)
+    }
+
+    fn secret_name(&self) -> String {
+        format!("{}-auth", self.name)
+    }
+
+    fn labels(&self, component: &str) -> Value {
+        json!({
+            "app.kubernetes.io/name": "ballista",
+            "app.kubernetes.io/instance": self.name,
+            "app.kubernetes.io/component": component,
+        })
+    }
+
+    fn metadata(&self, name: &str, component: &str) -> Value {
+        json!({
+            "name": name,
+            "namespace": self.namespace,
+            "labels": self.labels(component),
+        })
+    }
+
+    /// Flags that make a process discover the executors of this cluster
+    fn discovery_args(&self) -> Vec<String> {
+        vec![
+            "--mode=k8s".to_owned(),
+            format!("--k8s-namespace={}", self.namespace),
+            format!("--k8s-service={}", self.executor_name()),
+            format!("--k8s-label-selector={}={}", CLUSTER_LABEL_KEY, self.name),
+        ]
+    }
+
+    /// Environment of executors and schedulers, which read their settings from the ConfigMap
+    /// and the auth token from the Secret, if there is one
+    fn env_from(&self) -> Value {
+        json!([
+            { "configMapRef": { "name": self.config_map_name() } },
+            { "secretRef": { "name": self.secret_name(), "optional": true } },
+        ])
+    }
+
+    /// Generate the manifests of the cluster, in the order that they are applied in
+    pub fn manifests(&self) -> Result<Vec<Value>> {
+        let executor_port: u16 = self.config.require(EXECUTOR_PORT)?;
+        let scheduler_port: u16 = self.config.require(SCHEDULER_PORT)?;
+
+        // the auth token is kept out of the ConfigMap, which anyone that can read the
+        // namespace can read
+        let auth_var = env_var(AUTH_TOKEN)?;
+        let mut config_data = serde_json::Map::new();
+        let mut secret_data = serde_json::Map::new();
+        for (name, value) in self.config.env_vars() {
+            if name == auth_var {
+                secret_data.insert(name, Value::String(value));
+            } else {
+                config_data.insert(name, Value::String(value));
+            }
+        }
+
+        let mut manifests = vec![json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": self.metadata(&self.config_map_name(), "config"),
+            "data": config_data,
+        })];
+        if !secret_data.is_empty() {
+            manifests.push(json!({
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "metadata": self.metadata(&self.secret_name(), "config"),
+                "type": "Opaque",
+                "stringData": secret_data,
+            }));
+        }
+
+        manifests.push(json!({
+            "apiVersion": "v1",
+            "kind": "ServiceAccount",
+            "metadata": self.metadata(&self.name, "discovery"),
+        }));
+        manifests.push(json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "Role",
+            "metadata": self.metadata(&self.name, "discovery"),
+            "rules": [{ "apiGroups": [""], "resources": ["pods"], "verbs": ["list"] }],
+        }));
+        manifests.push(json!({
+            "apiVersion": "rbac.authorization.k8s.io/v1",
+            "kind": "RoleBinding",
+            "metadata": self.metadata(&self.name, "discovery"),
+            "roleRef": {
+                "apiGroup": "rbac.authorization.k8s.io",
+                "kind": "Role",
+                "name": self.name,
+            },
+            "subjects": [{
+                "kind": "ServiceAccount",
+                "name": self.name,
+                "namespace": self.namespace,
+            }],
+        }));
+
+        let executor_labels = self.labels("executor");
+        let mut executor_pod_labels = executor_labels.clone();
+        executor_pod_labels[CLUSTER_LABEL_KEY] = Value::String(self.name.clone());
+        let mut executor_args = self.discovery_args();
+        executor_args.push(format!("--port={}", executor_port));
+        manifests.push(json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": self.metadata(&self.executor_name(), "executor"),
+            "spec": {
+                "clusterIP": "None",
+                "selector": executor_labels,
+                "ports": [{ "name": "flight", "port": executor_port }],
+            },
+        }));
+        manifests.push(json!({
+            "apiVersion": "apps/v1",
+            "kind": "StatefulSet",
+            "metadata": self.metadata(&self.executor_name(), "executor"),
+            "spec": {
+                "serviceName": self.executor_name(),
+                "replicas": self.executors,
+                // executors start and stop independently of each other
+                "podManagementPolicy": "Parallel",
+                "selector": { "matchLabels": executor_labels },
+                "template": {
+                    "metadata": { "labels": executor_pod_labels },
+                    "spec": {
+                        "serviceAccountName": self.name,
+                        // executors drain their tasks for up to 30 seconds when they receive
+                        // SIGTERM
+                        "terminationGracePeriodSeconds": 60,
+                        "containers": [{
+                            "name": "executor",
+                            "image": self.image,
+                            "command": ["/executor"],
+                            "args": executor_args,
+                            "envFrom": self.env_from(),
+                            "resources": {
+                                "requests": {
+                                    "cpu": self.executor_cpu,
+                                    "memory": self.executor_memory,
+                                },
+                                "limits": {
+                                    "cpu": self.executor_cpu,
+                                    "memory": self.executor_memory,
+                                },
+                            },
+                            "ports": [{ "name": "flight", "containerPort": executor_port }],
+                        }],
+                    },
+                },
+            },
+        }));
+
+        let scheduler_labels = self.labels("scheduler");
+        let mut scheduler_args = self.discovery_args();
+        scheduler_args.push(format!("--port={}", scheduler_port));
+        manifests.push(json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": self.metadata(&self.scheduler_name(), "scheduler"),
+            "spec": {
+                "selector": scheduler_labels,
+                "ports": [{ "name": "grpc", "port": scheduler_port }],
+            },
+        }));
+        manifests.push(json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": self.metadata(&self.scheduler_name(), "scheduler"),
+            "spec": {
+                "replicas": 1,
+                "selector": { "matchLabels": scheduler_labels },
+                "template": {
+                    "metadata": { "labels": scheduler_labels },
+                    "spec": {
+                        "serviceAccountName": self.name,
+                        "containers": [{
+                            "name": "scheduler",
+                            "image": self.image,
+                            "command": ["/scheduler"],
+                            "args": scheduler_args,
+                            "envFrom": self.env_from(),
+                            "ports": [{ "name": "grpc", "containerPort": scheduler_port }],
+                        }],
+                    },
+                },
+            },
+        }));
+        Ok(manifests)
+    }
+
+    /// Generate the manifests of the cluster as a multi-document YAML file
+    pub fn to_yaml(&self) -> Result<String> {
+        let documents: Vec<String> = self
+            .manifests()?
+            .iter()
+            .map(|manifest| {
+                serde_yaml::to_string(manifest)
+                    .map_err(|e| ballista_error(&format!("Failed to write manifest: {}", e)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(documents
+            .iter()
+            .map(|document| format!("{}\n", document))
+            .collect())
+    }
+
+    /// Create or update the objects of the cluster
+    pub async fn apply(&self) -> Result<()> {
+        let client = Client::try_default().await?;
+        for manifest in self.manifests()? {
+            let client = client.clone();
+            match manifest["kind"].as_str() {
+                Some("ConfigMap") => apply_object::<ConfigMap>(client, &manifest).await?,
+                Some("Secret") => apply_object::<Secret>(client, &manifest).await?,
+                Some("ServiceAccount") => apply_object::<ServiceAccount>(client, &manifest).await?,
+                Some("Role") => apply_object::<Role>(client, &manifest).await?,
+                Some("RoleBinding") => apply_object::<RoleBinding>(client, &manifest).await?,
+                Some("Service") => apply_object::<Service>(client, &manifest).await?,
+                Some("StatefulSet") => apply_object::<StatefulSet>(client, &manifest).await?,
+                Some("Deployment") => apply_object::<Deployment>(client, &manifest).await?,
+                other => {
+                    return Err(ballista_error(&format!(
+                        "Unsupported manifest kind {:?}",
+                        other
+                    )))
+                }
+            }
+        }
+        info!(
+            "Applied cluster name={} namespace={} executors={}",
+            self.name, self.namespace, self.executors
+        );
+        Ok(())
+    }
+
+    /// Change the number of executors of the cluster
+    pub async fn scale(&self, executors: usize) -> Result<()> {
+        let client = Client::try_default().await?;
+        let api: Api<StatefulSet> = Api::namespaced(client, &self.namespace);
+        let patch = json!({ "spec": { "replicas": executors } });
+        api.patch(
+            &self.executor_name(),
+            &PatchParams::default(),
+            to_vec(&patch)?,
+        )
+        .await?;
+        info!(
+            "Scaled cluster name={} namespace={} executors={}",
+            self.name, self.namespace, executors
+        );
+        Ok(())
+    }
+
+    /// Delete the objects of the cluster, returning how many existed
+    pub async fn delete(&self) -> Result<usize> {
+        let client = Client::try_default().await?;
+        let mut deleted = 0;
+        // delete the workloads first so that the pods stop before their configuration goes
+        for manifest in self.manifests()?.iter().rev() {
+            let client = client.clone();
+            let found = match manifest["kind"].as_str() {
+                Some("ConfigMap") => delete_object::<ConfigMap>(client, manifest).await?,
+                Some("Secret") => delete_object::<Secret>(client, manifest).await?,
+                Some("ServiceAccount") => delete_object::<ServiceAccount>(client, manifest).await?,
+                Some("Role") => delete_object::<Role>(client, manifest).await?,
+                Some("RoleBinding") => delete_object::<RoleBinding>(client, manifest).await?,
+                Some("Service") => delete_object::<Service>(client, manifest).await?,
+                Some("StatefulSet") => delete_object::<StatefulSet>(client, manifest).await?,
+                Some("Deployment") => delete_object::<Deployment>(client, manifest).await?,
+                _ => false,
+            };
+            if found {
+                deleted += 1;
+            }
+        }
+        info!(
+            "Deleted cluster name={} namespace={} objects={}",
+            self.name, self.namespace, deleted
+        );
+        Ok(deleted)
+    }
+}
+
+fn to_vec(value: &Value) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| ballista_error(&format!("Invalid manifest: {}", e)))
+}
+
+fn name_and_namespace(manifest: &Value) -> Result<(&str, &str)> {
+    let metadata = &manifest["metadata"];
+    match (metadata["name"].as_str(), metadata["namespace"].as_str()) {
+        (Some(name), Some(namespace)) => Ok((name, namespace)),
+        _ => Err(ballista_error("Manifest is missing a name or namespace")),
+    }
+}
+
+/// Create or update an object with server-side apply
+async fn apply_object<K>(client: Client, manifest: &Value) -> Result<()>
+where
+    K: k8s_openapi::Resource + Clone + DeserializeOwned + Meta,
+{
+    let (name, namespace) = name_and_namespace(manifest)?;
+    let api: Api<K> = Api::namespaced(client, namespace);
+    let params = PatchParams {
+        patch_strategy: PatchStrategy::Apply,
+        field_manager: Some(FIELD_MANAGER.to_owned()),
+        force: true,
+        ..PatchParams::default()
+    };
+    api.patch(name, &params, to_vec(manifest)?).await?;
+    info!(
+        "Applied object kind={} name={} namespace={}",
+        K::KIND,
+        name,
+        namespace
+    );
+    Ok(())
+}
+
+/// Delete an object, returning false if it did not exist
+async fn delete_object<K>(client: Client, manifest: &Value) -> Result<bool>
+where
+    K: k8s_openapi::Resource + Clone + DeserializeOwned + Meta,
+{
+    let (name, namespace) = name_and_namespace(manifest)?;
+    let api: Api<K> = Api::namespaced(client, namespace);
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EXECUTOR_CONCURRENT_TASKS;
+
+    #[test]
+    fn generate_cluster_manifests() -> Result<()> {
+        let config = BallistaConfig::new()
+            .with_flag(EXECUTOR_CONCURRENT_TASKS, Some(8))?
+            .with_flag(AUTH_TOKEN, Some("secret"))?;
+        let spec = ClusterSpec::new("test")
+            .with_namespace("analytics")
+            .with_executors(3)
+            .with_config(config);
+        let manifests = spec.manifests()?;
+
+        let kinds: Vec<&str> = manifests
+            .iter()
+            .filter_map(|m| m["kind"].as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "ConfigMap",
+                "Secret",
+                "ServiceAccount",
+                "Role",
+                "RoleBinding",
+                "Service",
+                "StatefulSet",
+                "Service",
+                "Deployment"
+            ],
+            kinds
+        );
+        assert!(manifests
+            .iter()
+            .all(|m| m["metadata"]["namespace"] == "analytics"));
+
+        // settings that differ from the defaults are passed as environment variables, and the
+        // auth token is kept in the secret
+        assert_eq!(
+            "8",
+            manifests[0]["data"]["BALLISTA_EXECUTOR_CONCURRENT_TASKS"]
+        );
+        assert!(manifests[0]["data"]["BALLISTA_AUTH_TOKEN"].is_null());
+        assert_eq!("secret", manifests[1]["stringData"]["BALLISTA_AUTH_TOKEN"]);
+
+        let executors = &manifests[6];
+        assert_eq!(3, executors["spec"]["replicas"]);
+        assert_eq!("test-executor", executors["spec"]["serviceName"]);
+        assert_eq!(
+            "test",
+            executors["spec"]["template"]["metadata"]["labels"][CLUSTER_LABEL_KEY]
+        );
+        assert!(spec.to_yaml()?.contains("kind: StatefulSet"));
+        Ok(())
+    }
+}
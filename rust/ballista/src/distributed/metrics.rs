@@ -0,0 +1,185 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prometheus metrics for the executor, served over HTTP.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::error::{BallistaError, Result};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use log::info;
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+
+/// Metrics exported by an executor
+pub struct ExecutorMetrics {
+    registry: Registry,
+    pub(crate) tasks_queued: IntGauge,
+    pub(crate) tasks_running: IntGauge,
+    pub(crate) tasks_completed: IntCounter,
+    pub(crate) tasks_failed: IntCounter,
+    pub(crate) tasks_cancelled: IntCounter,
+    pub(crate) concurrency: IntGauge,
+    pub(crate) task_duration: Histogram,
+    pub(crate) shuffle_bytes_written: IntCounter,
+    pub(crate) shuffle_bytes_read: IntCounter,
+    pub(crate) shuffle_partitions: IntGauge,
+    pub(crate) shuffle_bytes: IntGauge,
+    pub(crate) task_status_entries: IntGauge,
+    pub(crate) results_cache_entries: IntGauge,
+}
+
+impl ExecutorMetrics {
+    pub fn try_new() -> Result<Self> {
+        let registry = Registry::new();
+        let metrics = Self {
+            tasks_queued: IntGauge::new("ballista_tasks_queued", "Tasks waiting for a slot")
+                .map_err(to_ballista_err)?,
+            tasks_running: IntGauge::new("ballista_tasks_running", "Tasks currently running")
+                .map_err(to_ballista_err)?,
+            tasks_completed: IntCounter::new(
+                "ballista_tasks_completed_total",
+                "Tasks that completed successfully",
+            )
+            .map_err(to_ballista_err)?,
+            tasks_failed: IntCounter::new("ballista_tasks_failed_total", "Tasks that failed")
+                .map_err(to_ballista_err)?,
+            tasks_cancelled: IntCounter::new(
+                "ballista_tasks_cancelled_total",
+                "Tasks that were cancelled",
+            )
+            .map_err(to_ballista_err)?,
+            concurrency: IntGauge::new(
+                "ballista_concurrency",
+                "Number of task slots currently in use",
+            )
+            .map_err(to_ballista_err)?,
+            task_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "ballista_task_duration_seconds",
+                    "Time taken to run tasks, including cancelled and failed tasks",
+                )
+                .buckets(vec![
+                    0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+                ]),
+            )
+            .map_err(to_ballista_err)?,
+            shuffle_bytes_written: IntCounter::new(
+                "ballista_shuffle_bytes_written_total",
+                "Bytes of shuffle data produced by tasks or pushed by other executors",
+            )
+            .map_err(to_ballista_err)?,
+            shuffle_bytes_read: IntCounter::new(
+                "ballista_shuffle_bytes_read_total",
+                "Bytes of shuffle data fetched from this executor",
+            )
+            .map_err(to_ballista_err)?,
+            shuffle_partitions: IntGauge::new(
+                "ballista_shuffle_partitions",
+                "Shuffle partitions held by this executor",
+            )
+            .map_err(to_ballista_err)?,
+            shuffle_bytes: IntGauge::new(
+                "ballista_shuffle_bytes",
+                "Bytes of shuffle data held by this executor",
+            )
+            .map_err(to_ballista_err)?,
+            task_status_entries: IntGauge::new(
+                "ballista_task_status_entries",
+                "Entries in the task status map",
+            )
+            .map_err(to_ballista_err)?,
+            results_cache_entries: IntGauge::new(
+                "ballista_results_cache_entries",
+                "Entries in the results cache",
+            )
+            .map_err(to_ballista_err)?,
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry
+            .register(Box::new(metrics.tasks_queued.clone()))
+            .and_then(|_| registry.register(Box::new(metrics.tasks_running.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.tasks_completed.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.tasks_failed.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.tasks_cancelled.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.concurrency.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.task_duration.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.shuffle_bytes_written.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.shuffle_bytes_read.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.shuffle_partitions.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.shuffle_bytes.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.task_status_entries.clone())))
+            .and_then(|_| registry.register(Box::new(metrics.results_cache_entries.clone())))
+            .map_err(to_ballista_err)?;
+
+        Ok(metrics)
+    }
+
+    /// Encode all metrics in the Prometheus text exposition format
+    pub fn encode(&self) -> Result<String> {
+        let mut buf = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .map_err(to_ballista_err)?;
+        String::from_utf8(buf).map_err(|e| BallistaError::General(format!("{:?}", e)))
+    }
+}
+
+fn to_ballista_err(e: prometheus::Error) -> BallistaError {
+    BallistaError::General(format!("{:?}", e))
+}
+
+/// Serve metrics over HTTP at `/metrics`. The `render` function is called on every scrape so
+/// that gauges can be refreshed before the metrics are encoded.
+pub async fn serve_metrics<F>(addr: SocketAddr, render: F) -> Result<()>
+where
+    F: Fn() -> Result<String> + Send + Sync + 'static,
+{
+    let render = Arc::new(render);
+    let make_service = make_service_fn(move |_conn| {
+        let render = render.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let render = render.clone();
+                async move {
+                    let response = match request.uri().path() {
+                        "/metrics" => match render() {
+                            Ok(text) => Response::builder()
+                                .header("Content-Type", prometheus::TEXT_FORMAT)
+                                .body(Body::from(text)),
+                            Err(e) => Response::builder()
+                                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                .body(Body::from(format!("{:?}", e))),
+                        },
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty()),
+                    };
+                    Ok::<_, Infallible>(response.expect("failed to build response"))
+                }
+            }))
+        }
+    });
+
+    info!("Serving metrics on http://{}/metrics", addr);
+    Server::bind(&addr)
+        .serve(make_service)
+        .await
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+}
@@ -14,9 +14,49 @@
 
 //! Distributed compute orchestration.
 
+pub mod adaptive;
+pub mod audit;
+pub mod auth;
+pub mod authorization;
+pub mod catalog;
 pub mod client;
+pub mod column_pruning;
+pub mod compression;
+pub mod connection_pool;
+pub mod cost;
+pub mod discovery;
 pub mod etcd;
 pub mod executor;
+pub mod explain;
+pub mod external_shuffle;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
+pub mod flight_data;
 pub mod flight_service;
+pub mod flight_sql;
+pub mod job_history;
+pub mod job_state;
 pub mod k8s;
+pub mod k8s_deploy;
+pub mod metrics;
+pub mod placement;
+pub mod plan_validation;
+pub mod prepared;
+pub mod progress;
+pub mod registry;
+pub mod resources;
+pub mod result_cache;
 pub mod scheduler;
+pub mod scheduler_server;
+pub mod scheduling;
+pub mod session;
+pub mod shuffle_store;
+pub mod skew;
+pub mod stage_reuse;
+pub mod standalone;
+pub mod status;
+pub mod stealing;
+pub mod table_store;
+pub mod tls;
+pub mod tracing;
+pub mod web_ui;
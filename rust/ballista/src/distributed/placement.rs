@@ -0,0 +1,144 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Policies for placing the tasks of a stage on executors.
+
+use std::fmt::Debug;
+use std::path::Path;
+
+use crate::distributed::scheduler::ExecutionTask;
+use crate::execution::physical_plan::{ExecutorMeta, PhysicalPlan};
+
+/// Decides which executor each task in a stage runs on
+pub trait PlacementPolicy: Send + Sync + Debug {
+    /// Return the index into `executors` of the executor chosen for each task
+    fn place(&self, tasks: &[ExecutionTask], executors: &[ExecutorMeta]) -> Vec<usize>;
+}
+
+/// Spread tasks evenly across executors, ignoring where the data is
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobinPlacement {}
+
+impl PlacementPolicy for RoundRobinPlacement {
+    fn place(&self, tasks: &[ExecutionTask], executors: &[ExecutorMeta]) -> Vec<usize> {
+        (0..tasks.len()).map(|i| i % executors.len()).collect()
+    }
+}
+
+/// Place each task on an executor that already holds its input where possible, choosing the
+/// least loaded of the candidate executors, and spread the remaining tasks across the least
+/// loaded executors.
+#[derive(Debug, Clone, Default)]
+pub struct LocalityFirstPlacement {}
+
+impl PlacementPolicy for LocalityFirstPlacement {
+    fn place(&self, tasks: &[ExecutionTask], executors: &[ExecutorMeta]) -> Vec<usize> {
+        let mut load = vec![0; executors.len()];
+        let mut placement = vec![None; tasks.len()];
+
+        // place the tasks that have a preference first so that they get their preferred
+        // executors before the load is evened out
+        for (i, task) in tasks.iter().enumerate() {
+            let preferred = preferred_executors(task, executors);
+            if let Some(executor) = least_loaded(preferred.into_iter(), &load) {
+                load[executor] += 1;
+                placement[i] = Some(executor);
+            }
+        }
+
+        placement
+            .into_iter()
+            .map(|executor| {
+                executor.unwrap_or_else(|| {
+                    let executor = least_loaded(0..executors.len(), &load)
+                        .expect("there should be at least one executor");
+                    load[executor] += 1;
+                    executor
+                })
+            })
+            .collect()
+    }
+}
+
+fn least_loaded(candidates: impl Iterator<Item = usize>, load: &[usize]) -> Option<usize> {
+    candidates.min_by_key(|i| (load[*i], *i))
+}
+
+/// Determine the executors that hold the input of a task. For tasks that read shuffle
+/// partitions, these are the executors holding the most of those partitions. For tasks that
+/// scan files, these are the executors whose host name appears as a directory in the path of
+/// the file being scanned, such as `/data/worker-1/part-0.parquet`, which is how node-local
+/// volumes are typically mounted.
+pub fn preferred_executors(task: &ExecutionTask, executors: &[ExecutorMeta]) -> Vec<usize> {
+    let mut shuffle_counts = vec![0; executors.len()];
+    let mut files = vec![];
+    collect_inputs(&task.plan, task, executors, &mut shuffle_counts, &mut files);
+
+    let max_count = shuffle_counts.iter().copied().max().unwrap_or(0);
+    if max_count > 0 {
+        return (0..executors.len())
+            .filter(|i| shuffle_counts[*i] == max_count)
+            .collect();
+    }
+
+    (0..executors.len())
+        .filter(|i| files.iter().any(|f| is_local_path(f, &executors[*i].host)))
+        .collect()
+}
+
+fn collect_inputs(
+    plan: &PhysicalPlan,
+    task: &ExecutionTask,
+    executors: &[ExecutorMeta],
+    shuffle_counts: &mut [usize],
+    files: &mut Vec<String>,
+) {
+    match plan {
+        PhysicalPlan::ShuffleReader(exec) => {
+            for shuffle_id in &exec.shuffle_id {
+                if let Some(location) = task.shuffle_locations.get(shuffle_id) {
+                    if let Some(i) = executors.iter().position(|e| e.id == location.id) {
+                        shuffle_counts[i] += 1;
+                    }
+                }
+            }
+        }
+        PhysicalPlan::CsvScan(exec) => files.extend(exec.filenames.get(task.partition_id).cloned()),
+        PhysicalPlan::ParquetScan(exec) => {
+            files.extend(exec.filenames.get(task.partition_id).cloned())
+        }
+        PhysicalPlan::JsonScan(exec) => files.extend(
+            exec.splits
+                .get(task.partition_id)
+                .map(|split| split.filename.clone()),
+        ),
+        PhysicalPlan::IpcScan(exec) => files.extend(exec.filenames.get(task.partition_id).cloned()),
+        PhysicalPlan::AvroScan(exec) => files.extend(
+            exec.splits
+                .get(task.partition_id)
+                .map(|split| split.filename.clone()),
+        ),
+        _ => {}
+    }
+    for child in plan.as_execution_plan().children() {
+        collect_inputs(&child, task, executors, shuffle_counts, files);
+    }
+}
+
+fn is_local_path(path: &str, host: &str) -> bool {
+    Path::new(path)
+        .parent()
+        .map(|dir| dir.iter().any(|component| component == host))
+        .unwrap_or(false)
+}
@@ -0,0 +1,252 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validation of the plans that executors receive before they are accepted.
+//!
+//! Plans are decoded from tickets that any client that can reach an executor may send, and
+//! would otherwise be executed as they are, reading from and writing to any path that the
+//! executor can access. The validator checks the queries and tasks that an executor receives
+//! against the paths and URIs that plans may access, the depth of plans, and the number of
+//! partitions that they may have, and rejects those that exceed these limits with a plan
+//! error. Every plan is accepted when no limits are configured.
+
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::catalog::is_table;
+use crate::distributed::scheduler::{ExecutionTask, QuerySettings};
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::PhysicalPlan;
+
+/// Limits that the plans submitted to an executor must be within
+#[derive(Debug, Clone, Default)]
+pub struct PlanValidator {
+    /// Paths and URIs under which plans may read and write files, or empty to allow any path
+    allowed_paths: Vec<String>,
+    /// Maximum number of operators from the root of a plan to any of its leaves
+    max_depth: Option<usize>,
+    /// Maximum number of partitions of any operator of a plan
+    max_partitions: Option<usize>,
+}
+
+impl PlanValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow plans to access files under these paths or URIs, such as `/data` or
+    /// `s3://bucket/tables`
+    pub fn with_allowed_paths(mut self, paths: Vec<String>) -> Self {
+        self.allowed_paths = paths
+            .iter()
+            .map(|path| path.trim().trim_end_matches('/').to_owned())
+            .filter(|path| !path.is_empty())
+            .collect();
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn with_max_partitions(mut self, max_partitions: usize) -> Self {
+        self.max_partitions = Some(max_partitions);
+        self
+    }
+
+    /// Check that a path or URI is under one of the allowed paths
+    pub fn check_path(&self, path: &str) -> Result<()> {
+        if self.allowed_paths.is_empty() {
+            return Ok(());
+        }
+        // a path that climbs out of a directory could escape the allowed path that it starts with
+        let escapes = path
+            .split(|c| c == '/' || c == '\\')
+            .any(|part| part == "..");
+        let allowed = !escapes
+            && self.allowed_paths.iter().any(|allowed| {
+                path == allowed
+                    || (path.starts_with(allowed.as_str())
+                        && path[allowed.len()..].starts_with('/'))
+            });
+        if allowed {
+            Ok(())
+        } else {
+            Err(rejected(&format!("path '{}' is not allowed", path)))
+        }
+    }
+
+    /// Check the logical plan and settings of a query that a client submitted
+    pub fn validate_query(&self, plan: &LogicalPlan, settings: &QuerySettings) -> Result<()> {
+        if let Some(partitions) = settings.target_partitions {
+            self.check_partitions(partitions)?;
+        }
+        self.check_logical(plan, 1)
+    }
+
+    /// Check the physical plan and output partitioning of a task that a scheduler submitted
+    pub fn validate_task(&self, task: &ExecutionTask) -> Result<()> {
+        if let Some(partitioning) = &task.output_partitioning {
+            self.check_partitions(partitioning.partition_count())?;
+        }
+        self.check_physical(&task.plan, 1)
+    }
+
+    fn check_logical(&self, plan: &LogicalPlan, depth: usize) -> Result<()> {
+        self.check_depth(depth)?;
+        match plan {
+            LogicalPlan::Projection { input, .. }
+            | LogicalPlan::Selection { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => self.check_logical(input, depth + 1),
+            LogicalPlan::Extension { node } => node
+                .inputs()
+                .iter()
+                .try_for_each(|input| self.check_logical(input, depth + 1)),
+            LogicalPlan::CsvScan { path, .. } | LogicalPlan::ParquetScan { path, .. } => {
+                self.check_path(path)
+            }
+            LogicalPlan::TableScan {
+                schema_name,
+                table_name,
+                ..
+            } if !is_table(schema_name) => self.check_path(table_name),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_physical(&self, plan: &PhysicalPlan, depth: usize) -> Result<()> {
+        self.check_depth(depth)?;
+        match plan {
+            PhysicalPlan::CsvScan(exec) => self.check_files(&exec.path, &exec.filenames)?,
+            PhysicalPlan::ParquetScan(exec) => self.check_files(&exec.path, &exec.filenames)?,
+            PhysicalPlan::IpcScan(exec) => self.check_files(&exec.path, &exec.filenames)?,
+            PhysicalPlan::JsonScan(exec) => self.check_path(&exec.path)?,
+            PhysicalPlan::AvroScan(exec) => self.check_path(&exec.path)?,
+            PhysicalPlan::Write(exec) => self.check_path(&exec.path)?,
+            PhysicalPlan::ShuffleReader(exec) => self.check_partitions(exec.shuffle_id.len())?,
+            _ => {}
+        }
+        let exec = plan.as_execution_plan();
+        self.check_partitions(exec.output_partitioning().partition_count())?;
+        exec.children()
+            .iter()
+            .try_for_each(|child| self.check_physical(child, depth + 1))
+    }
+
+    /// Scans carry the files that they read as well as the path that they were listed from, so
+    /// both are checked
+    fn check_files(&self, path: &str, filenames: &[String]) -> Result<()> {
+        self.check_path(path)?;
+        filenames
+            .iter()
+            .try_for_each(|filename| self.check_path(filename))
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<()> {
+        match self.max_depth {
+            Some(max_depth) if depth > max_depth => Err(rejected(&format!(
+                "plan is deeper than the maximum depth of {}",
+                max_depth
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_partitions(&self, partitions: usize) -> Result<()> {
+        match self.max_partitions {
+            Some(max_partitions) if partitions > max_partitions => Err(rejected(&format!(
+                "plan has {} partitions, more than the maximum of {}",
+                partitions, max_partitions
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn rejected(reason: &str) -> BallistaError {
+    BallistaError::PlanError(format!("Plan rejected: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
+    use crate::datafusion::logicalplan::{col, LogicalPlanBuilder};
+    use crate::execution::operators::ShuffleReaderExec;
+    use crate::execution::physical_plan::ShuffleId;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn scan(path: &str) -> LogicalPlanBuilder {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        LogicalPlanBuilder::scan_csv(path, CsvReadOptions::new().schema(&schema), None).unwrap()
+    }
+
+    #[test]
+    fn allow_paths_under_allowed_paths() {
+        let validator = PlanValidator::new()
+            .with_allowed_paths(vec!["/data/".to_owned(), "s3://bucket/tables".to_owned()]);
+        assert!(validator.check_path("/data").is_ok());
+        assert!(validator.check_path("/data/t.csv").is_ok());
+        assert!(validator
+            .check_path("s3://bucket/tables/t/part-0.parquet")
+            .is_ok());
+        assert!(validator.check_path("/data2/t.csv").is_err());
+        assert!(validator.check_path("/data/../etc/passwd").is_err());
+        assert!(validator.check_path("s3://bucket/other").is_err());
+        assert!(PlanValidator::new().check_path("/etc/passwd").is_ok());
+    }
+
+    #[test]
+    fn reject_queries_beyond_limits() -> Result<()> {
+        let validator = PlanValidator::new()
+            .with_allowed_paths(vec!["/data".to_owned()])
+            .with_max_depth(2)
+            .with_max_partitions(8);
+        let settings = QuerySettings::new();
+
+        let plan = scan("/data/t.csv").project(vec![col("a")])?.build()?;
+        validator.validate_query(&plan, &settings)?;
+        assert!(validator
+            .validate_query(&plan, &settings.with_target_partitions(16))
+            .is_err());
+
+        let plan = scan("/etc/passwd").build()?;
+        assert!(validator.validate_query(&plan, &settings).is_err());
+
+        let plan = scan("/data/t.csv")
+            .project(vec![col("a")])?
+            .limit(10)?
+            .build()?;
+        assert!(validator.validate_query(&plan, &settings).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reject_tasks_with_too_many_partitions() {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let job_uuid = Uuid::new_v4();
+        let shuffle_ids = (0..16).map(|i| ShuffleId::new(job_uuid, 1, i)).collect();
+        let reader = ShuffleReaderExec::new(Arc::new(schema), shuffle_ids);
+        let plan = PhysicalPlan::ShuffleReader(Arc::new(reader));
+        let task = ExecutionTask::new(job_uuid, 2, 0, plan, HashMap::new());
+
+        assert!(PlanValidator::new().validate_task(&task).is_ok());
+        let validator = PlanValidator::new().with_max_partitions(8);
+        assert!(validator.validate_task(&task).is_err());
+    }
+}
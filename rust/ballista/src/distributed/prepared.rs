@@ -0,0 +1,396 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prepared queries, which are planned once and then executed repeatedly with different values
+//! bound to their parameters.
+//!
+//! The executor that a query is prepared on keeps its physical plan by handle. Executing the
+//! prepared query replaces the parameters in the expressions of each operator with the bound
+//! values and runs the resulting plan as a new job, without planning the query again.
+//!
+//! Prepared queries, and the prepared statements of Flight SQL clients, belong to the tenant
+//! that prepared them, and only that tenant may execute or close them.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::arrow::array::{StringArray, UInt32Array};
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::logicalplan::{Expr, ScalarValue};
+use crate::distributed::scheduler::JobConfig;
+use crate::error::{ballista_error, Result};
+use crate::execution::expressions::{bind_parameters, collect_parameters};
+use crate::execution::operators::{
+    FilterExec, HashAggregateExec, ProjectionExec, ShuffleExchangeExec, SortExec, TopKExec,
+    WindowExec, WindowExpr,
+};
+use crate::execution::physical_plan::{ExecutionPlan, Partitioning, PhysicalPlan};
+use crate::utils::expiring_map::ExpiringMap;
+
+use uuid::Uuid;
+
+/// How long a prepared query or statement is kept after it was prepared
+pub const DEFAULT_PREPARED_QUERY_TTL: Duration = Duration::from_secs(3600);
+
+/// Max number of prepared queries or statements that an executor keeps
+pub const DEFAULT_MAX_PREPARED_QUERIES: usize = 1000;
+
+/// A query that has been planned so that it can be executed repeatedly
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    /// Physical plan of the query, with its parameters unbound
+    pub(crate) plan: Arc<PhysicalPlan>,
+    /// Configuration of the jobs that execute the query, after the settings that the query was
+    /// prepared with are applied
+    pub(crate) job_config: JobConfig,
+    /// Types of the parameters, in order
+    pub(crate) parameter_types: Vec<DataType>,
+}
+
+impl PreparedQuery {
+    pub fn try_new(plan: Arc<PhysicalPlan>, job_config: JobConfig) -> Result<Self> {
+        let parameter_types = plan_parameters(&plan)?;
+        Ok(Self {
+            plan,
+            job_config,
+            parameter_types,
+        })
+    }
+
+    /// The physical plan of the query with the given values bound to its parameters
+    pub fn bind(&self, values: &[ScalarValue]) -> Result<Arc<PhysicalPlan>> {
+        if values.len() != self.parameter_types.len() {
+            return Err(ballista_error(&format!(
+                "Prepared query has {} parameters but {} values were bound",
+                self.parameter_types.len(),
+                values.len()
+            )));
+        }
+        bind_plan_parameters(&self.plan, values)
+    }
+}
+
+/// A prepared query or statement and the tenant that prepared it
+#[derive(Debug, Clone)]
+struct Prepared<T> {
+    tenant: String,
+    value: T,
+}
+
+/// Queries or statements that clients have prepared on this executor, by handle. Each belongs
+/// to the tenant that prepared it, and the handles of other tenants are treated as unknown.
+pub struct PreparedStore<T> {
+    entries: Mutex<ExpiringMap<Prepared<T>>>,
+}
+
+impl<T: Clone> PreparedStore<T> {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(ExpiringMap::new(ttl, max_entries, |_| true)),
+        }
+    }
+
+    /// Keep a query or statement that a tenant prepared, returning its handle
+    pub fn insert(&self, tenant: &str, value: T) -> String {
+        let handle = Uuid::new_v4().to_string();
+        let mut entries = self.entries.lock().expect("failed to lock mutex");
+        entries.insert(
+            handle.clone(),
+            Prepared {
+                tenant: tenant.to_owned(),
+                value,
+            },
+        );
+        handle
+    }
+
+    /// The query or statement with the given handle, if the tenant prepared it
+    pub fn get(&self, tenant: &str, handle: &str) -> Option<T> {
+        let entries = self.entries.lock().expect("failed to lock mutex");
+        match entries.get(handle) {
+            Some(prepared) if prepared.tenant == tenant => Some(prepared.value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Discard a query or statement of a tenant, returning false if the tenant prepared none
+    /// with the handle
+    pub fn remove(&self, tenant: &str, handle: &str) -> bool {
+        let mut entries = self.entries.lock().expect("failed to lock mutex");
+        match entries.get(handle) {
+            Some(prepared) if prepared.tenant == tenant => entries.remove(handle).is_some(),
+            _ => false,
+        }
+    }
+}
+
+impl<T: Clone> Default for PreparedStore<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_PREPARED_QUERY_TTL, DEFAULT_MAX_PREPARED_QUERIES)
+    }
+}
+
+/// One-row batch with the handle of a prepared query and its number of parameters, which is
+/// returned to the client that prepared it
+pub fn prepared_query_batch(handle: &str, parameter_types: &[DataType]) -> Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("handle", DataType::Utf8, false),
+        Field::new("num_parameters", DataType::UInt32, false),
+    ]);
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(StringArray::from(vec![handle])),
+            Arc::new(UInt32Array::from(vec![parameter_types.len() as u32])),
+        ],
+    )?)
+}
+
+/// The types of the parameters that the operators of a plan refer to, in order. Parameters
+/// must be numbered from zero without gaps.
+pub fn plan_parameters(plan: &PhysicalPlan) -> Result<Vec<DataType>> {
+    let mut parameters = BTreeMap::new();
+    collect_plan_parameters(plan, &mut parameters)?;
+    parameters
+        .into_iter()
+        .enumerate()
+        .map(|(i, (index, data_type))| {
+            if i == index {
+                Ok(data_type)
+            } else {
+                Err(ballista_error(&format!(
+                    "Parameter {} is not used by the query",
+                    i
+                )))
+            }
+        })
+        .collect()
+}
+
+fn collect_plan_parameters(
+    plan: &PhysicalPlan,
+    parameters: &mut BTreeMap<usize, DataType>,
+) -> Result<()> {
+    for expr in expressions(plan) {
+        collect_parameters(&expr, parameters)?;
+    }
+    plan.as_execution_plan()
+        .children()
+        .iter()
+        .try_for_each(|child| collect_plan_parameters(child, parameters))
+}
+
+/// The expressions that an operator evaluates, excluding those of its inputs
+fn expressions(plan: &PhysicalPlan) -> Vec<Expr> {
+    match plan {
+        PhysicalPlan::Projection(exec) => exec.expr.clone(),
+        PhysicalPlan::Filter(exec) => vec![exec.filter_expr.as_ref().clone()],
+        PhysicalPlan::Sort(exec) => exec.sort_expr.clone(),
+        PhysicalPlan::TopK(exec) => exec.sort_expr.clone(),
+        PhysicalPlan::Window(exec) => {
+            let window = &exec.window_expr;
+            window
+                .args
+                .iter()
+                .chain(&window.partition_by)
+                .chain(&window.order_by)
+                .cloned()
+                .collect()
+        }
+        PhysicalPlan::HashAggregate(exec) => exec
+            .group_expr
+            .iter()
+            .chain(&exec.aggr_expr)
+            .cloned()
+            .collect(),
+        PhysicalPlan::ShuffleExchange(exec) => match exec.output_partitioning() {
+            Partitioning::HashPartitioning(_, exprs)
+            | Partitioning::RangePartitioning(_, exprs) => {
+                exprs.iter().map(|e| e.as_ref().clone()).collect()
+            }
+            Partitioning::UnknownPartitioning(_) => vec![],
+        },
+        PhysicalPlan::ParquetScan(exec) => exec.predicate.iter().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+/// Replace the parameters in the expressions of every operator of a plan with the values at
+/// their index
+pub fn bind_plan_parameters(
+    plan: &Arc<PhysicalPlan>,
+    values: &[ScalarValue],
+) -> Result<Arc<PhysicalPlan>> {
+    let bind_list = |exprs: &[Expr]| -> Result<Vec<Expr>> {
+        exprs.iter().map(|e| bind_parameters(e, values)).collect()
+    };
+    let children = plan
+        .as_execution_plan()
+        .children()
+        .iter()
+        .map(|child| bind_plan_parameters(child, values))
+        .collect::<Result<Vec<_>>>()?;
+
+    let plan = match plan.as_ref() {
+        PhysicalPlan::Projection(exec) => PhysicalPlan::Projection(Arc::new(
+            ProjectionExec::try_new(&bind_list(&exec.expr)?, children[0].clone())?,
+        )),
+        PhysicalPlan::Filter(exec) => PhysicalPlan::Filter(Arc::new(FilterExec::new(
+            &children[0],
+            &bind_parameters(&exec.filter_expr, values)?,
+        ))),
+        PhysicalPlan::Sort(exec) => PhysicalPlan::Sort(Arc::new(SortExec::try_new(
+            children[0].clone(),
+            bind_list(&exec.sort_expr)?,
+        )?)),
+        PhysicalPlan::TopK(exec) => PhysicalPlan::TopK(Arc::new(TopKExec::try_new(
+            children[0].clone(),
+            bind_list(&exec.sort_expr)?,
+            exec.k,
+            exec.partial,
+        )?)),
+        PhysicalPlan::Window(exec) => {
+            let window = &exec.window_expr;
+            let window_expr = WindowExpr {
+                args: bind_list(&window.args)?,
+                partition_by: bind_list(&window.partition_by)?,
+                order_by: bind_list(&window.order_by)?,
+                ..window.clone()
+            };
+            PhysicalPlan::Window(Arc::new(WindowExec::try_new(
+                children[0].clone(),
+                window_expr,
+            )?))
+        }
+        PhysicalPlan::HashAggregate(exec) => {
+            PhysicalPlan::HashAggregate(Arc::new(HashAggregateExec::try_new(
+                exec.mode.clone(),
+                bind_list(&exec.group_expr)?,
+                bind_list(&exec.aggr_expr)?,
+                children[0].clone(),
+            )?))
+        }
+        PhysicalPlan::ShuffleExchange(exec) => {
+            let bind_partitioning = |exprs: &[Arc<Expr>]| -> Result<Vec<Arc<Expr>>> {
+                exprs
+                    .iter()
+                    .map(|e| Ok(Arc::new(bind_parameters(e, values)?)))
+                    .collect()
+            };
+            let partitioning = match exec.output_partitioning() {
+                Partitioning::HashPartitioning(n, exprs) => {
+                    Partitioning::HashPartitioning(n, bind_partitioning(&exprs)?)
+                }
+                Partitioning::RangePartitioning(n, exprs) => {
+                    Partitioning::RangePartitioning(n, bind_partitioning(&exprs)?)
+                }
+                other => other,
+            };
+            PhysicalPlan::ShuffleExchange(Arc::new(ShuffleExchangeExec::new(
+                children[0].clone(),
+                partitioning,
+            )))
+        }
+        PhysicalPlan::ParquetScan(exec) => {
+            let mut scan = exec.as_ref().clone();
+            if let Some(predicate) = &exec.predicate {
+                scan.predicate = Some(bind_parameters(predicate, values)?);
+            }
+            PhysicalPlan::ParquetScan(Arc::new(scan))
+        }
+        other if children.is_empty() => other.clone(),
+        other => other.with_new_children(children),
+    };
+    Ok(Arc::new(plan))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int64Array;
+    use crate::datafusion::logicalplan::{col_index, Operator};
+    use crate::execution::expressions::parameter;
+    use crate::execution::operators::InMemoryTableScanExec;
+    use crate::execution::physical_plan::ColumnarBatch;
+
+    fn scan() -> Result<Arc<PhysicalPlan>> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int64, false)]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(Int64Array::from(vec![1, 5, 10]))],
+        )?;
+        Ok(Arc::new(PhysicalPlan::InMemoryTableScan(Arc::new(
+            InMemoryTableScanExec::new(vec![ColumnarBatch::from_arrow(&batch)]),
+        ))))
+    }
+
+    fn filter(child: &PhysicalPlan, value: Expr) -> Arc<PhysicalPlan> {
+        let predicate = Expr::BinaryExpr {
+            left: Box::new(col_index(0)),
+            op: Operator::Gt,
+            right: Box::new(value),
+        };
+        Arc::new(PhysicalPlan::Filter(Arc::new(FilterExec::new(
+            child, &predicate,
+        ))))
+    }
+
+    #[test]
+    fn bind_parameters_of_plan() -> Result<()> {
+        let scan = scan()?;
+        let plan = filter(&scan, parameter(0, DataType::Int64));
+        let query = PreparedQuery::try_new(plan, JobConfig::default())?;
+        assert_eq!(vec![DataType::Int64], query.parameter_types);
+
+        let bound = query.bind(&[ScalarValue::Int64(5)])?;
+        let expected = filter(&scan, Expr::Literal(ScalarValue::Int64(5)));
+        assert_eq!(format!("{:?}", expected), format!("{:?}", bound));
+        assert!(plan_parameters(&bound)?.is_empty());
+
+        assert!(query.bind(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parameters_must_be_numbered_without_gaps() -> Result<()> {
+        let plan = filter(scan()?.as_ref(), parameter(1, DataType::Int64));
+        assert!(plan_parameters(&plan).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn remove_prepared_queries() -> Result<()> {
+        let query = PreparedQuery::try_new(scan()?, JobConfig::default())?;
+        let queries = PreparedStore::default();
+        let handle = queries.insert("etl", query);
+        assert!(queries.get("etl", &handle).is_some());
+        assert!(queries.remove("etl", &handle));
+        assert!(queries.get("etl", &handle).is_none());
+        assert!(!queries.remove("etl", &handle));
+        Ok(())
+    }
+
+    #[test]
+    fn only_owner_uses_prepared_queries() -> Result<()> {
+        let query = PreparedQuery::try_new(scan()?, JobConfig::default())?;
+        let queries = PreparedStore::default();
+        let handle = queries.insert("etl", query);
+        assert!(queries.get("dashboards", &handle).is_none());
+        assert!(!queries.remove("dashboards", &handle));
+        assert!(queries.get("etl", &handle).is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Live progress of the stages and tasks of a running job, which the scheduler reports to
+//! clients so that they can show how far a job has got while it runs.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::channel::mpsc::{channel, Receiver, Sender};
+
+/// Number of tasks of a stage in each state
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TaskCounts {
+    /// Tasks waiting to be submitted to an executor, including tasks waiting to be retried
+    pub pending: usize,
+    pub queued: usize,
+    pub running: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+impl TaskCounts {
+    fn merge(&mut self, other: &TaskCounts) {
+        self.pending += other.pending;
+        self.queued += other.queued;
+        self.running += other.running;
+        self.completed += other.completed;
+        self.failed += other.failed;
+    }
+}
+
+/// Progress of a stage within a job
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StageProgress {
+    pub stage_id: usize,
+    /// Number of tasks that the stage runs, which is only known once the stage starts because
+    /// the plan of the stage is adapted to the output of the stages before it
+    pub total_tasks: usize,
+    pub tasks: TaskCounts,
+    /// Bytes of shuffle partitions written by the tasks of the stage that have completed
+    pub shuffle_bytes: usize,
+    /// Whether all tasks of the stage have completed
+    pub completed: bool,
+}
+
+/// Progress of all the stages of a job, ordered by stage id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobProgress {
+    pub stages: Vec<StageProgress>,
+}
+
+impl JobProgress {
+    /// Number of tasks that have completed, across all stages that have started
+    pub fn completed_tasks(&self) -> usize {
+        self.stages.iter().map(|s| s.tasks.completed).sum()
+    }
+
+    /// Number of tasks across all stages that have started
+    pub fn total_tasks(&self) -> usize {
+        self.stages.iter().map(|s| s.total_tasks).sum()
+    }
+
+    /// Bytes of shuffle partitions written so far, across all stages
+    pub fn shuffle_bytes(&self) -> usize {
+        self.stages.iter().map(|s| s.shuffle_bytes).sum()
+    }
+
+    /// Fraction of the stages of the job that have completed, counting a stage that is running
+    /// as the fraction of its tasks that have completed
+    pub fn fraction_complete(&self) -> f64 {
+        if self.stages.is_empty() {
+            return 0.0;
+        }
+        let done: f64 = self
+            .stages
+            .iter()
+            .map(|s| match (s.completed, s.total_tasks) {
+                (true, _) => 1.0,
+                (false, 0) => 0.0,
+                (false, total) => s.tasks.completed as f64 / total as f64,
+            })
+            .sum();
+        done / self.stages.len() as f64
+    }
+}
+
+impl fmt::Display for JobProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.0}% complete, {}/{} tasks, {} bytes shuffled",
+            self.fraction_complete() * 100.0,
+            self.completed_tasks(),
+            self.total_tasks(),
+            self.shuffle_bytes()
+        )?;
+        for stage in &self.stages {
+            write!(
+                f,
+                "\n  stage {}: {}/{} tasks completed, {} running, {} queued, {} pending, {} failed",
+                stage.stage_id,
+                stage.tasks.completed,
+                stage.total_tasks,
+                stage.tasks.running,
+                stage.tasks.queued,
+                stage.tasks.pending,
+                stage.tasks.failed
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// State of a task that has been submitted to an executor
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskState {
+    /// Waiting to be submitted again, after a failed attempt or after the executor it was placed
+    /// on left the cluster
+    Pending,
+    Queued,
+    Running,
+    Completed,
+    Failed(String),
+}
+
+impl TaskState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskState::Completed | TaskState::Failed(_))
+    }
+}
+
+/// Progress of a task that has been submitted to an executor
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskProgress {
+    pub stage_id: usize,
+    pub partition_id: usize,
+    /// Executor that the latest attempt of the task was submitted to
+    pub executor_id: String,
+    pub state: TaskState,
+    /// When the task was first submitted
+    pub started_at: SystemTime,
+    /// When the task completed or failed
+    pub finished_at: Option<SystemTime>,
+    /// Bytes of the shuffle partition that the task wrote, once it has completed
+    pub shuffle_bytes: usize,
+}
+
+#[derive(Debug, Default)]
+struct StageEntry {
+    total_tasks: usize,
+    /// Tasks that completed before the stage started running, when a stage resumes after a
+    /// scheduler restart or runs again to recompute partitions lost with an executor
+    prior_completed: usize,
+    /// Latest task counts and shuffle bytes reported for each executor running tasks of the
+    /// stage
+    executors: HashMap<String, (TaskCounts, usize)>,
+    completed: bool,
+}
+
+/// Shared record of the progress of a job, updated by the threads that run its tasks
+#[derive(Debug, Clone, Default)]
+pub struct ProgressTracker {
+    stages: Arc<Mutex<BTreeMap<usize, StageEntry>>>,
+    /// Tasks that have been submitted, keyed by stage id and partition id
+    tasks: Arc<Mutex<BTreeMap<(usize, usize), TaskProgress>>>,
+    /// Channels of the clients watching the job, which are told when its progress changes
+    watchers: Arc<Mutex<Vec<Sender<()>>>>,
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the stages of a job before any of them run
+    pub fn register_stages(&self, stage_ids: &[usize]) {
+        let mut stages = self.stages.lock().expect("failed to lock mutex");
+        for stage_id in stage_ids {
+            stages.entry(*stage_id).or_default();
+        }
+        self.notify();
+    }
+
+    /// Record that a stage is starting to run its tasks, of which `prior_completed` already
+    /// completed
+    pub fn start_stage(&self, stage_id: usize, total_tasks: usize, prior_completed: usize) {
+        let mut stages = self.stages.lock().expect("failed to lock mutex");
+        let stage = stages.entry(stage_id).or_default();
+        stage.total_tasks = total_tasks;
+        stage.prior_completed = prior_completed;
+        stage.executors.clear();
+        stage.completed = false;
+        self.notify();
+    }
+
+    /// Record the latest state of the tasks of a stage that run on an executor, along with the
+    /// bytes of shuffle partitions that the completed tasks wrote
+    pub fn update_tasks(
+        &self,
+        stage_id: usize,
+        executor_id: &str,
+        tasks: TaskCounts,
+        shuffle_bytes: usize,
+    ) {
+        let mut stages = self.stages.lock().expect("failed to lock mutex");
+        let stage = stages.entry(stage_id).or_default();
+        stage
+            .executors
+            .insert(executor_id.to_owned(), (tasks, shuffle_bytes));
+        self.notify();
+    }
+
+    /// Record the state of a task after it has been submitted to an executor
+    pub fn update_task(
+        &self,
+        stage_id: usize,
+        partition_id: usize,
+        executor_id: &str,
+        state: TaskState,
+        shuffle_bytes: usize,
+    ) {
+        let now = SystemTime::now();
+        let mut tasks = self.tasks.lock().expect("failed to lock mutex");
+        let task = tasks
+            .entry((stage_id, partition_id))
+            .or_insert_with(|| TaskProgress {
+                stage_id,
+                partition_id,
+                executor_id: executor_id.to_owned(),
+                state: TaskState::Pending,
+                started_at: now,
+                finished_at: None,
+                shuffle_bytes: 0,
+            });
+        // a task that runs again to recompute a lost partition starts a new timeline
+        if task.state.is_finished() && !state.is_finished() {
+            task.started_at = now;
+        }
+        task.finished_at = match (&task.finished_at, state.is_finished()) {
+            (Some(finished_at), true) if task.state.is_finished() => Some(*finished_at),
+            (_, true) => Some(now),
+            (_, false) => None,
+        };
+        task.executor_id = executor_id.to_owned();
+        task.state = state;
+        task.shuffle_bytes = shuffle_bytes;
+        self.notify();
+    }
+
+    /// Record that all tasks of a stage have completed, or that the stage did not need to run
+    pub fn complete_stage(&self, stage_id: usize) {
+        let mut stages = self.stages.lock().expect("failed to lock mutex");
+        stages.entry(stage_id).or_default().completed = true;
+        self.notify();
+    }
+
+    /// Subscribe to changes of the job. Changes that happen before the watcher has seen the
+    /// last one are merged into it, so a slow watcher only learns that the job changed since
+    /// it last looked.
+    pub fn subscribe(&self) -> Receiver<()> {
+        let (tx, rx) = channel(0);
+        let mut watchers = self.watchers.lock().expect("failed to lock mutex");
+        watchers.push(tx);
+        rx
+    }
+
+    /// Tell the watchers that the progress or the state of the job changed
+    pub fn notify(&self) {
+        let mut watchers = self.watchers.lock().expect("failed to lock mutex");
+        for tx in watchers.iter_mut() {
+            // a full channel already holds a change that the watcher has not seen yet
+            let _ = tx.try_send(());
+        }
+        watchers.retain(|tx| !tx.is_closed());
+    }
+
+    /// The tasks that have been submitted, ordered by stage id and partition id
+    pub fn tasks(&self) -> Vec<TaskProgress> {
+        let tasks = self.tasks.lock().expect("failed to lock mutex");
+        tasks.values().cloned().collect()
+    }
+
+    /// The current progress of the job
+    pub fn snapshot(&self) -> JobProgress {
+        let stages = self.stages.lock().expect("failed to lock mutex");
+        JobProgress {
+            stages: stages
+                .iter()
+                .map(|(stage_id, stage)| {
+                    let mut tasks = TaskCounts {
+                        completed: stage.prior_completed,
+                        ..TaskCounts::default()
+                    };
+                    let mut shuffle_bytes = 0;
+                    for (counts, bytes) in stage.executors.values() {
+                        tasks.merge(counts);
+                        shuffle_bytes += bytes;
+                    }
+                    StageProgress {
+                        stage_id: *stage_id,
+                        total_tasks: stage.total_tasks,
+                        tasks,
+                        shuffle_bytes,
+                        completed: stage.completed,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_job_progress() {
+        let tracker = ProgressTracker::new();
+        tracker.register_stages(&[1, 2]);
+        tracker.start_stage(1, 4, 0);
+        let counts = |running, completed| TaskCounts {
+            running,
+            completed,
+            ..TaskCounts::default()
+        };
+        tracker.update_tasks(1, "executor-1", counts(1, 1), 100);
+        tracker.update_tasks(1, "executor-2", counts(2, 0), 0);
+        // a later report from an executor replaces its earlier one
+        tracker.update_tasks(1, "executor-2", counts(1, 1), 200);
+
+        let progress = tracker.snapshot();
+        assert_eq!(2, progress.stages.len());
+        assert_eq!(counts(2, 2), progress.stages[0].tasks);
+        assert_eq!(300, progress.shuffle_bytes());
+        assert_eq!(2, progress.completed_tasks());
+        assert_eq!(4, progress.total_tasks());
+        assert!((progress.fraction_complete() - 0.25).abs() < f64::EPSILON);
+
+        tracker.complete_stage(1);
+        tracker.start_stage(2, 2, 1);
+        let progress = tracker.snapshot();
+        assert!(progress.stages[0].completed);
+        assert_eq!(1, progress.stages[1].tasks.completed);
+        assert!((progress.fraction_complete() - 0.75).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn track_task_timeline() {
+        let tracker = ProgressTracker::new();
+        tracker.update_task(1, 0, "executor-1", TaskState::Queued, 0);
+        tracker.update_task(1, 0, "executor-1", TaskState::Running, 0);
+        let started_at = tracker.tasks()[0].started_at;
+        assert_eq!(None, tracker.tasks()[0].finished_at);
+
+        tracker.update_task(1, 0, "executor-1", TaskState::Completed, 1024);
+        let task = &tracker.tasks()[0];
+        assert_eq!(started_at, task.started_at);
+        assert!(task.finished_at.is_some());
+        assert_eq!(1024, task.shuffle_bytes);
+
+        // a retried task keeps the executor of its latest attempt
+        tracker.update_task(1, 1, "executor-1", TaskState::Pending, 0);
+        tracker.update_task(1, 1, "executor-2", TaskState::Running, 0);
+        let tasks = tracker.tasks();
+        assert_eq!(2, tasks.len());
+        assert_eq!("executor-2", tasks[1].executor_id);
+    }
+
+    #[test]
+    fn notify_watchers_of_changes() {
+        let tracker = ProgressTracker::new();
+        let mut changes = tracker.subscribe();
+        assert!(changes.try_next().is_err());
+
+        tracker.register_stages(&[1]);
+        tracker.start_stage(1, 2, 0);
+        assert_eq!(Some(()), changes.try_next().unwrap());
+        // both changes were merged into one
+        assert!(changes.try_next().is_err());
+
+        tracker.complete_stage(1);
+        assert_eq!(Some(()), changes.try_next().unwrap());
+
+        drop(changes);
+        tracker.notify();
+        assert!(tracker.watchers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cluster membership based on executors registering with a registry and sending periodic
+//! heartbeats. Any executor can act as the registry for the cluster.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::arrow::array;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::cast_array;
+use crate::distributed::client::execute_action;
+use crate::distributed::discovery::DiscoveryBackend;
+use crate::distributed::tls::TlsConfig;
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{Action, ExecutorMeta};
+
+use async_trait::async_trait;
+use log::{debug, info, warn};
+
+/// Default interval at which executors send heartbeats to the registry
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default time after which an executor that has not sent a heartbeat is removed
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Information that an executor announces when registering
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutorRegistration {
+    pub meta: ExecutorMeta,
+    /// Number of tasks the executor can run concurrently
+    pub cores: usize,
+    /// Memory available to the executor, in bytes
+    pub memory_bytes: u64,
+}
+
+/// Executors that have registered and are still sending heartbeats
+pub struct ExecutorRegistry {
+    executors: Mutex<HashMap<String, (ExecutorRegistration, Instant)>>,
+    heartbeat_timeout: Duration,
+}
+
+impl ExecutorRegistry {
+    pub fn new(heartbeat_timeout: Duration) -> Self {
+        Self {
+            executors: Mutex::new(HashMap::new()),
+            heartbeat_timeout,
+        }
+    }
+
+    /// Add an executor, or update its details if it is already registered
+    pub fn register(&self, registration: ExecutorRegistration) {
+        let mut executors = self.executors.lock().expect("failed to lock mutex");
+        info!(
+            "Registered executor executor_id={} host={} port={} cores={} memory_bytes={}",
+            registration.meta.id,
+            registration.meta.host,
+            registration.meta.port,
+            registration.cores,
+            registration.memory_bytes
+        );
+        executors.insert(registration.meta.id.clone(), (registration, Instant::now()));
+    }
+
+    /// Record a heartbeat. Returns false if the executor is not registered, in which case it
+    /// must register again.
+    pub fn heartbeat(&self, executor_id: &str) -> bool {
+        let mut executors = self.executors.lock().expect("failed to lock mutex");
+        match executors.get_mut(executor_id) {
+            Some((_, last_heartbeat)) => {
+                *last_heartbeat = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove an executor that is shutting down. Returns false if it was not registered.
+    pub fn deregister(&self, executor_id: &str) -> bool {
+        let mut executors = self.executors.lock().expect("failed to lock mutex");
+        let removed = executors.remove(executor_id).is_some();
+        if removed {
+            info!("Deregistered executor executor_id={}", executor_id);
+        }
+        removed
+    }
+
+    /// Remove executors that have missed their heartbeats and return the remaining ones
+    pub fn live_executors(&self) -> Vec<ExecutorRegistration> {
+        let mut executors = self.executors.lock().expect("failed to lock mutex");
+        let timeout = self.heartbeat_timeout;
+        executors.retain(|executor_id, (_, last_heartbeat)| {
+            let live = last_heartbeat.elapsed() < timeout;
+            if !live {
+                warn!(
+                    "Removed executor that missed its heartbeats executor_id={}",
+                    executor_id
+                );
+            }
+            live
+        });
+        executors
+            .values()
+            .map(|(registration, _)| registration.clone())
+            .collect()
+    }
+}
+
+/// Schema of the response to the `ListExecutors` action
+pub fn registration_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("host", DataType::Utf8, false),
+        Field::new("port", DataType::UInt32, false),
+        Field::new("cores", DataType::UInt32, false),
+        Field::new("memory_bytes", DataType::UInt64, false),
+    ])
+}
+
+/// Encode registrations as a record batch
+pub fn registrations_to_batch(registrations: &[ExecutorRegistration]) -> Result<RecordBatch> {
+    let ids: Vec<&str> = registrations.iter().map(|r| r.meta.id.as_str()).collect();
+    let hosts: Vec<&str> = registrations.iter().map(|r| r.meta.host.as_str()).collect();
+    let ports: Vec<u32> = registrations.iter().map(|r| r.meta.port as u32).collect();
+    let cores: Vec<u32> = registrations.iter().map(|r| r.cores as u32).collect();
+    let memory: Vec<u64> = registrations.iter().map(|r| r.memory_bytes).collect();
+    Ok(RecordBatch::try_new(
+        Arc::new(registration_schema()),
+        vec![
+            Arc::new(array::StringArray::from(ids)),
+            Arc::new(array::StringArray::from(hosts)),
+            Arc::new(array::UInt32Array::from(ports)),
+            Arc::new(array::UInt32Array::from(cores)),
+            Arc::new(array::UInt64Array::from(memory)),
+        ],
+    )?)
+}
+
+/// Decode registrations from a record batch
+pub fn batch_to_registrations(batch: &RecordBatch) -> Result<Vec<ExecutorRegistration>> {
+    if batch.num_columns() != 5 {
+        return Err(ballista_error("Invalid executor list"));
+    }
+    let ids = batch.column(0);
+    let ids = cast_array!(ids, StringArray)?;
+    let hosts = batch.column(1);
+    let hosts = cast_array!(hosts, StringArray)?;
+    let ports = batch.column(2);
+    let ports = cast_array!(ports, UInt32Array)?;
+    let cores = batch.column(3);
+    let cores = cast_array!(cores, UInt32Array)?;
+    let memory = batch.column(4);
+    let memory = cast_array!(memory, UInt64Array)?;
+    Ok((0..batch.num_rows())
+        .map(|i| ExecutorRegistration {
+            meta: ExecutorMeta {
+                id: ids.value(i).to_owned(),
+                host: hosts.value(i).to_owned(),
+                port: ports.value(i) as usize,
+            },
+            cores: cores.value(i) as usize,
+            memory_bytes: memory.value(i),
+        })
+        .collect())
+}
+
+/// Discovery backend that registers executors with the executor acting as the registry
+pub struct RegistryDiscovery {
+    host: String,
+    port: usize,
+    auth_token: Option<String>,
+    tls: Option<TlsConfig>,
+    /// Set once the registered executor deregisters, to stop sending heartbeats
+    deregistered: Arc<AtomicBool>,
+}
+
+impl RegistryDiscovery {
+    pub fn new(
+        host: &str,
+        port: usize,
+        auth_token: Option<String>,
+        tls: Option<TlsConfig>,
+    ) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            auth_token,
+            tls,
+            deregistered: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+#[async_trait]
+impl DiscoveryBackend for RegistryDiscovery {
+    fn register(&self, registration: ExecutorRegistration) -> Result<()> {
+        start_registry_thread(
+            &self.host,
+            self.port,
+            registration,
+            self.auth_token.clone(),
+            self.tls.clone(),
+            DEFAULT_HEARTBEAT_INTERVAL,
+            self.deregistered.clone(),
+        );
+        Ok(())
+    }
+
+    async fn deregister(&self, executor_id: &str) -> Result<()> {
+        self.deregistered.store(true, Ordering::SeqCst);
+        let action = Action::DeregisterExecutor {
+            executor_id: executor_id.to_owned(),
+        };
+        execute_action(
+            &self.host,
+            self.port,
+            &action,
+            self.auth_token.as_deref(),
+            self.tls.as_ref(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn get_executors(&self) -> Result<Vec<ExecutorMeta>> {
+        registry_get_executors(
+            &self.host,
+            self.port,
+            self.auth_token.as_deref(),
+            self.tls.as_ref(),
+        )
+        .await
+    }
+}
+
+/// Get the live executors from the registry
+pub async fn registry_get_executors(
+    host: &str,
+    port: usize,
+    auth_token: Option<&str>,
+    tls: Option<&TlsConfig>,
+) -> Result<Vec<ExecutorMeta>> {
+    let batches = execute_action(host, port, &Action::ListExecutors, auth_token, tls).await?;
+    let mut executors = vec![];
+    for batch in &batches {
+        executors.extend(
+            batch_to_registrations(batch)?
+                .into_iter()
+                .map(|registration| registration.meta),
+        );
+    }
+    Ok(executors)
+}
+
+/// Start a thread that registers the executor with the registry and then sends heartbeats,
+/// registering again whenever the registry no longer knows about the executor, until the
+/// executor deregisters
+pub fn start_registry_thread(
+    registry_host: &str,
+    registry_port: usize,
+    registration: ExecutorRegistration,
+    auth_token: Option<String>,
+    tls: Option<TlsConfig>,
+    interval: Duration,
+    deregistered: Arc<AtomicBool>,
+) {
+    let registry_host = registry_host.to_owned();
+    thread::spawn(move || {
+        smol::run(async move {
+            let mut registered = false;
+            while !deregistered.load(Ordering::SeqCst) {
+                let action = if registered {
+                    Action::Heartbeat {
+                        executor_id: registration.meta.id.clone(),
+                    }
+                } else {
+                    Action::RegisterExecutor(registration.clone())
+                };
+                match execute_action(
+                    &registry_host,
+                    registry_port,
+                    &action,
+                    auth_token.as_deref(),
+                    tls.as_ref(),
+                )
+                .await
+                {
+                    Ok(_) => {
+                        if !registered {
+                            info!(
+                                "Registered with registry host={} port={}",
+                                registry_host, registry_port
+                            );
+                            registered = true;
+                        } else {
+                            debug!("Sent heartbeat executor_id={}", registration.meta.id);
+                        }
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to contact registry host={} port={} error={:?}",
+                            registry_host, registry_port, e
+                        );
+                        // the registry may have restarted or removed this executor
+                        registered = false;
+                    }
+                }
+                thread::sleep(interval);
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(id: &str) -> ExecutorRegistration {
+        ExecutorRegistration {
+            meta: ExecutorMeta {
+                id: id.to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+            },
+            cores: 4,
+            memory_bytes: 1024,
+        }
+    }
+
+    #[test]
+    fn remove_executors_that_miss_heartbeats() {
+        let registry = ExecutorRegistry::new(Duration::from_millis(0));
+        registry.register(registration("a"));
+        assert!(registry.heartbeat("a"));
+        assert!(registry.live_executors().is_empty());
+        // the executor must register again
+        assert!(!registry.heartbeat("a"));
+    }
+
+    #[test]
+    fn deregister_executors() {
+        let registry = ExecutorRegistry::new(DEFAULT_HEARTBEAT_TIMEOUT);
+        registry.register(registration("a"));
+        registry.register(registration("b"));
+        assert!(registry.deregister("a"));
+        assert!(!registry.deregister("a"));
+        assert_eq!(vec![registration("b")], registry.live_executors());
+    }
+
+    #[test]
+    fn roundtrip_registrations() -> Result<()> {
+        let registrations = vec![registration("a"), registration("b")];
+        let batch = registrations_to_batch(&registrations)?;
+        assert_eq!(registrations, batch_to_registrations(&batch)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resources that tasks need and that executors have.
+//!
+//! The scheduler estimates the cores and memory that each task needs from the operators of its
+//! plan, and executors advertise the cores and memory that they have. Tasks are packed onto
+//! executors so that the resources of the tasks running on an executor fit within its
+//! capacity, which keeps heavy tasks from running alongside as many others as light tasks do.
+
+use std::collections::HashSet;
+
+use crate::distributed::adaptive::{stage_output_bytes, visit};
+use crate::distributed::catalog::StatisticsCatalog;
+use crate::distributed::cost::estimate_statistics;
+use crate::distributed::scheduler::JobProfile;
+use crate::execution::physical_plan::PhysicalPlan;
+
+use log::debug;
+
+/// Cores and memory that a task is estimated to need, or that an executor has
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskResources {
+    pub cores: usize,
+    /// Memory in bytes, where zero means that the memory is unknown or not limited
+    pub memory_bytes: u64,
+}
+
+impl Default for TaskResources {
+    fn default() -> Self {
+        Self {
+            cores: 1,
+            memory_bytes: 0,
+        }
+    }
+}
+
+/// Estimate the resources that each task of a stage needs from the plan of the stage. Operators
+/// that buffer their input in memory need memory for their share of that input, which is the
+/// input of sorts, windows and hash aggregates and the build side of hash joins, and such tasks
+/// are estimated to need a second core for hashing or sorting every row.
+pub fn estimate_task_resources(
+    plan: &PhysicalPlan,
+    num_tasks: usize,
+    profile: &JobProfile,
+    catalog: &StatisticsCatalog,
+) -> TaskResources {
+    let mut buffered_bytes = 0u64;
+    let mut buffering = false;
+    visit(plan, &mut |plan| {
+        let input = match plan {
+            PhysicalPlan::Sort(exec) => &exec.child,
+            PhysicalPlan::Window(exec) => &exec.child,
+            PhysicalPlan::HashAggregate(exec) => &exec.child,
+            PhysicalPlan::HashJoin(exec) => &exec.left,
+            _ => return,
+        };
+        buffering = true;
+        buffered_bytes = buffered_bytes.saturating_add(input_bytes(input, profile, catalog));
+    });
+    TaskResources {
+        cores: if buffering { 2 } else { 1 },
+        memory_bytes: buffered_bytes / num_tasks.max(1) as u64,
+    }
+}
+
+/// Estimated size in bytes of the output of a plan, where the size of shuffled input is taken
+/// from the stages that produced it
+fn input_bytes(plan: &PhysicalPlan, profile: &JobProfile, catalog: &StatisticsCatalog) -> u64 {
+    if let PhysicalPlan::ShuffleReader(exec) = plan {
+        let stage_ids: HashSet<usize> = exec.shuffle_id.iter().map(|s| s.stage_id).collect();
+        return stage_ids
+            .into_iter()
+            .filter_map(|stage_id| stage_output_bytes(profile, stage_id))
+            .sum();
+    }
+    match estimate_statistics(plan, catalog).total_bytes {
+        Some(bytes) => bytes,
+        None => plan
+            .as_execution_plan()
+            .children()
+            .iter()
+            .map(|child| input_bytes(child, profile, catalog))
+            .sum(),
+    }
+}
+
+/// Resources of an executor that running tasks have reserved. A task whose resources exceed
+/// the capacity can still run, but only while no other task is running, so that no task waits
+/// forever.
+#[derive(Debug, Clone)]
+pub struct ResourcePool {
+    capacity: TaskResources,
+    reserved: TaskResources,
+    tasks: usize,
+}
+
+impl ResourcePool {
+    pub fn new(capacity: TaskResources) -> Self {
+        Self {
+            capacity,
+            reserved: TaskResources {
+                cores: 0,
+                memory_bytes: 0,
+            },
+            tasks: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> TaskResources {
+        self.capacity
+    }
+
+    pub fn reserved(&self) -> TaskResources {
+        self.reserved
+    }
+
+    /// Whether a task with the given resources could start now
+    pub fn fits(&self, resources: &TaskResources) -> bool {
+        let cores = self.reserved.cores + resources.cores <= self.capacity.cores;
+        // executors that do not advertise their memory are only limited by cores
+        let memory = self.capacity.memory_bytes == 0
+            || self.reserved.memory_bytes + resources.memory_bytes <= self.capacity.memory_bytes;
+        self.tasks == 0 || (cores && memory)
+    }
+
+    /// Reserve the resources of a task, returning false if they do not fit
+    pub fn try_reserve(&mut self, resources: &TaskResources) -> bool {
+        if !self.fits(resources) {
+            return false;
+        }
+        self.reserved.cores += resources.cores;
+        self.reserved.memory_bytes += resources.memory_bytes;
+        self.tasks += 1;
+        debug!("Reserved resources reserved={:?}", self.reserved);
+        true
+    }
+
+    /// Return the resources of a task that has finished
+    pub fn release(&mut self, resources: &TaskResources) {
+        self.reserved.cores = self.reserved.cores.saturating_sub(resources.cores);
+        self.reserved.memory_bytes = self
+            .reserved
+            .memory_bytes
+            .saturating_sub(resources.memory_bytes);
+        self.tasks = self.tasks.saturating_sub(1);
+        debug!("Released resources reserved={:?}", self.reserved);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_tasks_by_cores_and_memory() {
+        let mut pool = ResourcePool::new(TaskResources {
+            cores: 8,
+            memory_bytes: 1000,
+        });
+        let light = TaskResources {
+            cores: 1,
+            memory_bytes: 0,
+        };
+        let heavy = TaskResources {
+            cores: 2,
+            memory_bytes: 800,
+        };
+
+        // a heavy task leaves room for light tasks but not for another heavy task
+        assert!(pool.try_reserve(&heavy));
+        assert!(!pool.try_reserve(&heavy));
+        for _ in 0..6 {
+            assert!(pool.try_reserve(&light));
+        }
+        assert!(!pool.try_reserve(&light));
+
+        pool.release(&heavy);
+        assert!(pool.try_reserve(&heavy));
+        assert_eq!(
+            TaskResources {
+                cores: 8,
+                memory_bytes: 800
+            },
+            pool.reserved()
+        );
+    }
+
+    #[test]
+    fn oversized_tasks_run_alone() {
+        let mut pool = ResourcePool::new(TaskResources {
+            cores: 2,
+            memory_bytes: 100,
+        });
+        let oversized = TaskResources {
+            cores: 2,
+            memory_bytes: 500,
+        };
+        assert!(pool.try_reserve(&oversized));
+        assert!(!pool.try_reserve(&TaskResources::default()));
+        pool.release(&oversized);
+        assert!(pool.try_reserve(&TaskResources::default()));
+        assert!(!pool.try_reserve(&oversized));
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache of the results of jobs on the scheduler.
+//!
+//! Jobs are keyed by a fingerprint of their optimized logical plan and of the files that the
+//! plan scans, so that a job that repeats a recent query of unchanged files is completed at
+//! once with the shuffle partitions that hold the results of the earlier job. Files are
+//! considered unchanged while the same files with the same sizes are listed under the paths
+//! that the plan scans.
+
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::catalog::is_table;
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::ShuffleLocation;
+use crate::object_store::object_store;
+use crate::protobuf;
+use crate::utils::expiring_map::ExpiringMap;
+
+use log::debug;
+use prost::Message;
+
+/// Default maximum number of results to cache
+pub const DEFAULT_MAX_CACHED_RESULTS: usize = 1000;
+
+/// Fingerprint of the results that a tenant's query produces, from the protobuf encoding of
+/// its logical plan and the listing of the files that it scans
+pub fn plan_fingerprint(plan: &LogicalPlan, tenant: &str) -> Result<u64> {
+    let node: protobuf::LogicalPlanNode = plan.try_into()?;
+    let mut encoded = Vec::with_capacity(node.encoded_len());
+    node.encode(&mut encoded)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+
+    let mut hasher = DefaultHasher::new();
+    tenant.hash(&mut hasher);
+    encoded.hash(&mut hasher);
+    let mut paths = vec![];
+    source_paths(plan, &mut paths);
+    paths.sort();
+    paths.dedup();
+    for path in paths {
+        let mut files = object_store(&path)?.list(&path)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        path.hash(&mut hasher);
+        for file in files {
+            file.path.hash(&mut hasher);
+            file.size.hash(&mut hasher);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Paths of the files that a plan scans
+fn source_paths(plan: &LogicalPlan, paths: &mut Vec<String>) {
+    match plan {
+        LogicalPlan::Projection { input, .. }
+        | LogicalPlan::Selection { input, .. }
+        | LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. } => source_paths(input, paths),
+        LogicalPlan::Extension { node } => {
+            for input in node.inputs() {
+                source_paths(input, paths);
+            }
+        }
+        LogicalPlan::CsvScan { path, .. } | LogicalPlan::ParquetScan { path, .. } => {
+            paths.push(path.clone())
+        }
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            ..
+        } if !is_table(schema_name) => paths.push(table_name.clone()),
+        _ => {}
+    }
+}
+
+/// Results of completed jobs by fingerprint, which are served for the time-to-live after the
+/// job completed
+pub struct ResultCache {
+    ttl: Duration,
+    results: Mutex<ExpiringMap<(Vec<ShuffleLocation>, Instant)>>,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            results: Mutex::new(ExpiringMap::new(ttl, DEFAULT_MAX_CACHED_RESULTS, |_| true)),
+        }
+    }
+
+    /// The locations of the results of a recent job with the fingerprint
+    pub fn get(&self, fingerprint: u64) -> Option<Vec<ShuffleLocation>> {
+        let results = self.results.lock().expect("failed to lock mutex");
+        // expired results are only evicted when others are inserted
+        match results.get(&fingerprint.to_string()) {
+            Some((partitions, cached_at)) if cached_at.elapsed() < self.ttl => {
+                Some(partitions.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Cache the locations of the results of a job that has completed
+    pub fn insert(&self, fingerprint: u64, partitions: Vec<ShuffleLocation>) {
+        debug!("Caching results fingerprint={}", fingerprint);
+        let mut results = self.results.lock().expect("failed to lock mutex");
+        results.insert(fingerprint.to_string(), (partitions, Instant::now()));
+    }
+
+    /// Drop all cached results, returning the number that were dropped
+    pub fn invalidate(&self) -> usize {
+        let mut results = self.results.lock().expect("failed to lock mutex");
+        let count = results.len();
+        results.retain(|_, _| false);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::physical_plan::{ExecutorMeta, ShuffleId};
+
+    #[test]
+    fn serve_results_until_invalidated() {
+        let cache = ResultCache::new(Duration::from_secs(60));
+        let partitions = vec![ShuffleLocation::new(
+            ShuffleId::new(uuid::Uuid::new_v4(), 1, 0),
+            ExecutorMeta {
+                id: "e1".to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+            },
+        )];
+        assert_eq!(None, cache.get(1));
+        cache.insert(1, partitions.clone());
+        assert_eq!(Some(partitions), cache.get(1));
+        assert_eq!(None, cache.get(2));
+
+        assert_eq!(1, cache.invalidate());
+        assert_eq!(None, cache.get(1));
+    }
+
+    #[test]
+    fn expired_results_are_not_served() {
+        let cache = ResultCache::new(Duration::from_millis(0));
+        cache.insert(1, vec![]);
+        assert_eq!(None, cache.get(1));
+    }
+}
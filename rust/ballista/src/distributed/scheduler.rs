@@ -16,29 +16,59 @@
 //! and co-ordinating execution of these stages and tasks across the cluster.
 
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::dataframe::{avg, count, max, min, sum};
+use crate::dataframe::{count, count_distinct};
 use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
-use crate::datafusion::logicalplan::LogicalPlan;
 use crate::datafusion::logicalplan::{col_index, Expr};
-use crate::distributed::executor::DefaultContext;
+use crate::datafusion::logicalplan::{LogicalPlan, LogicalPlanBuilder};
+use crate::distributed::adaptive::{
+    adapt_stage_plan, broadcast_candidates, stage_output_bytes, stage_output_partitioning,
+    RuntimeStatistics,
+};
+use crate::distributed::catalog::StatisticsCatalog;
+use crate::distributed::compression::ShuffleCompression;
+use crate::distributed::cost::estimate_statistics;
+use crate::distributed::explain::describe_job;
+use crate::distributed::job_state::{JobRecord, JobStateStore};
+use crate::distributed::progress::{ProgressTracker, TaskCounts, TaskState};
+use crate::distributed::resources::{estimate_task_resources, ResourcePool, TaskResources};
+use crate::distributed::scheduling::{JobPermit, TaskSlots};
+use crate::distributed::stage_reuse::{retain_shuffles, stage_fingerprint};
+use crate::distributed::status::error_kind;
+use crate::distributed::stealing::IdleExecutors;
+use crate::distributed::tracing::{Span, TraceContext};
 use crate::error::{ballista_error, BallistaError, Result};
-use crate::execution::operators::ProjectionExec;
 use crate::execution::operators::ShuffleExchangeExec;
 use crate::execution::operators::ShuffleReaderExec;
-use crate::execution::operators::{CsvScanExec, HashAggregateExec};
-use crate::execution::operators::{FilterExec, ParquetScanExec};
+use crate::execution::operators::{AvroScanExec, AVRO_SCHEMA_NAME};
+use crate::execution::operators::{CsvFormatOptions, CsvScanExec, HashAggregateExec};
+use crate::execution::operators::{FilterExec, HashJoinExec, ParquetScanExec, SortExec};
+use crate::execution::operators::{GlobalLimitExec, LocalLimitExec, TopKExec};
+use crate::execution::operators::{IpcScanExec, ARROW_SCHEMA_NAME};
+use crate::execution::operators::{JoinNode, JsonScanExec, JSON_SCHEMA_NAME};
+use crate::execution::operators::{ProjectionExec, RepartitionExec};
+use crate::execution::operators::{UnionExec, UnionNode};
+use crate::execution::operators::{WindowExec, WindowExpr};
 use crate::execution::physical_plan::{
-    AggregateMode, ColumnarBatch, Distribution, ExecutionContext, ExecutionPlan, ExecutorMeta,
-    Partitioning, PhysicalPlan, ShuffleId,
+    compile_aggregate_expression, AggregateMode, CancellationToken, Distribution, ExecutionContext,
+    ExecutionPlan, ExecutorMeta, Partitioning, PhysicalPlan, ShuffleId, ShuffleLocation,
+    TaskMetrics, TaskUpdateState,
 };
-
-use smol::Task;
+use crate::object_store;
+use crate::protobuf::ErrorKind;
+
+use futures::channel::oneshot;
+use futures::future::{self, Either};
+use futures::StreamExt;
+use log::{debug, error, info, warn};
+use smol::{Task, Timer};
 use uuid::Uuid;
 
 /// A Job typically represents a single query and the query is executed in stages. Stages are
@@ -56,26 +86,7 @@ pub struct Job {
 
 impl Job {
     pub fn explain(&self) {
-        println!("Job {} has {} stages:\n", self.id, self.stages.len());
-        self.stages.iter().for_each(|stage| {
-            let stage = stage.as_ref().borrow();
-            println!("Stage {}:\n", stage.id);
-            if stage.prior_stages.is_empty() {
-                println!("Stage {} has no dependencies.", stage.id);
-            } else {
-                println!(
-                    "Stage {} depends on stages {:?}.",
-                    stage.id, stage.prior_stages
-                );
-            }
-            println!(
-                "\n{:?}\n",
-                stage
-                    .plan
-                    .as_ref()
-                    .expect("Stages should always have a plan")
-            );
-        })
+        println!("{}", describe_job(self));
     }
 }
 
@@ -109,6 +120,25 @@ pub struct ExecutionTask {
     pub(crate) partition_id: usize,
     pub(crate) plan: PhysicalPlan,
     pub(crate) shuffle_locations: HashMap<ShuffleId, ExecutorMeta>,
+    /// Codec that the output of the task is compressed with
+    pub(crate) shuffle_compression: ShuffleCompression,
+    /// Hash partitioning of the shuffle that the output of the task feeds, which the task
+    /// reports the number of rows in each partition of
+    pub(crate) output_partitioning: Option<Partitioning>,
+    /// Maximum number of bytes that the task holds in memory, both in its output and in the
+    /// memory that its operators reserve, above which data is spilled to disk
+    pub(crate) memory_limit: Option<usize>,
+    /// Time at which the executor aborts the task if it has not completed
+    pub(crate) deadline: Option<SystemTime>,
+    /// Number of the attempt of the task, starting from zero, which executors use to tell a
+    /// new attempt apart from a duplicate submission of an attempt they have already run
+    pub(crate) attempt: usize,
+    /// Cores and memory that the task is estimated to need, which executors reserve while
+    /// the task runs
+    pub(crate) resources: TaskResources,
+    /// Span of the stage that the task belongs to, which is sent in the metadata of the
+    /// request that submits the task rather than with the task itself
+    pub(crate) trace: Option<TraceContext>,
 }
 
 impl ExecutionTask {
@@ -125,12 +155,81 @@ impl ExecutionTask {
             partition_id,
             plan,
             shuffle_locations,
+            shuffle_compression: ShuffleCompression::None,
+            output_partitioning: None,
+            memory_limit: None,
+            deadline: None,
+            attempt: 0,
+            resources: TaskResources::default(),
+            trace: None,
         }
     }
 
+    /// Compress the output of the task with the given codec
+    pub fn with_shuffle_compression(mut self, shuffle_compression: ShuffleCompression) -> Self {
+        self.shuffle_compression = shuffle_compression;
+        self
+    }
+
+    /// Report the number of rows of the output of the task that fall in each partition of the
+    /// given hash partitioning
+    pub fn with_output_partitioning(mut self, output_partitioning: Partitioning) -> Self {
+        self.output_partitioning = Some(output_partitioning);
+        self
+    }
+
+    /// Record the spans of the task as children of the given span
+    pub fn with_trace_context(mut self, trace: TraceContext) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Spill the output and the operators of the task to disk beyond `memory_limit` bytes
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    /// Abort the task if it has not completed by `deadline`
+    pub fn with_deadline(mut self, deadline: SystemTime) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Time left until the deadline of the task, which is zero once the deadline has passed
+    pub fn time_to_deadline(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or_else(|_| Duration::from_secs(0))
+        })
+    }
+
+    pub fn with_attempt(mut self, attempt: usize) -> Self {
+        self.attempt = attempt;
+        self
+    }
+
+    /// Reserve the given cores and memory on the executor while the task runs
+    pub fn with_resources(mut self, resources: TaskResources) -> Self {
+        self.resources = resources;
+        self
+    }
+
+    /// Key of the task, which is the same for all attempts of the task
     pub fn key(&self) -> String {
-        format!("{}.{}.{}", self.job_uuid, self.stage_id, self.partition_id)
+        task_key(&self.job_uuid, self.stage_id, self.partition_id)
     }
+
+    /// Key of this attempt of the task
+    pub fn attempt_key(&self) -> String {
+        format!("{}.{}", self.key(), self.attempt)
+    }
+}
+
+/// Unique key for a task within the cluster
+pub fn task_key(job_uuid: &Uuid, stage_id: usize, partition_id: usize) -> String {
+    format!("{}.{}.{}", job_uuid, stage_id, partition_id)
 }
 
 /// Create a Job (DAG of stages) from a physical execution plan.
@@ -176,40 +275,36 @@ impl Scheduler {
         //
         match plan.as_ref() {
             PhysicalPlan::ShuffleExchange(exec) => {
-                // shuffle indicates that we need a new stage
-                let new_stage_id = self.next_stage_id;
-                self.next_stage_id += 1;
-                let new_stage = Rc::new(RefCell::new(Stage::new(new_stage_id)));
-                self.job.stages.push(new_stage.clone());
-
-                // the children need to be part of this new stage
-                let shuffle_input = self.visit_plan(exec.child.clone(), new_stage.clone())?;
-
-                new_stage.as_ref().borrow_mut().plan = Some(shuffle_input);
-
-                // the current stage depends on this new stage
-                current_stage
-                    .as_ref()
-                    .borrow_mut()
-                    .prior_stages
-                    .push(new_stage_id);
-
-                // return a shuffle reader to read the results from the stage
-                let n = exec
-                    .child
-                    .as_execution_plan()
-                    .output_partitioning()
-                    .partition_count();
-
-                let shuffle_id = (0..n)
-                    .map(|n| ShuffleId {
-                        job_uuid: self.job.id,
-                        stage_id: new_stage_id,
-                        partition_id: n,
-                    })
-                    .collect();
-                Ok(Arc::new(PhysicalPlan::ShuffleReader(Arc::new(
-                    ShuffleReaderExec::new(exec.schema(), shuffle_id),
+                self.visit_shuffle(&exec.child, exec.output_partitioning(), current_stage)
+            }
+            PhysicalPlan::Repartition(exec) => {
+                self.visit_shuffle(&exec.child, exec.output_partitioning(), current_stage)
+            }
+            PhysicalPlan::HashJoin(exec) => {
+                // each input that is shuffled becomes a separate stage that this stage depends on
+                let left = self.visit_plan(exec.left.clone(), current_stage.clone())?;
+                let right = self.visit_plan(exec.right.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(
+                    exec.with_new_children(vec![left, right]),
+                ))))
+            }
+            PhysicalPlan::SortMergeJoin(exec) => {
+                // the inputs are already partitioned and sorted so they run in the same stage
+                let left = self.visit_plan(exec.left.clone(), current_stage.clone())?;
+                let right = self.visit_plan(exec.right.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::SortMergeJoin(Arc::new(
+                    exec.with_new_children(vec![left, right]),
+                ))))
+            }
+            PhysicalPlan::Union(exec) => {
+                // the partitions of the inputs are concatenated so they run in the same stage
+                let inputs = exec
+                    .inputs
+                    .iter()
+                    .map(|input| self.visit_plan(input.clone(), current_stage.clone()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Arc::new(PhysicalPlan::Union(Arc::new(
+                    exec.with_new_children(inputs),
                 ))))
             }
             PhysicalPlan::HashAggregate(exec) => {
@@ -224,11 +319,101 @@ impl Scheduler {
                     exec.with_new_children(vec![child]),
                 ))))
             }
+            PhysicalPlan::Sort(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::Sort(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
+            PhysicalPlan::TopK(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::TopK(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
+            PhysicalPlan::GlobalLimit(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::GlobalLimit(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
+            PhysicalPlan::LocalLimit(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::LocalLimit(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
+            PhysicalPlan::Window(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::Window(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
+            PhysicalPlan::Projection(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::Projection(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
+            PhysicalPlan::Write(exec) => {
+                let child = self.visit_plan(exec.child.clone(), current_stage)?;
+                Ok(Arc::new(PhysicalPlan::Write(Arc::new(
+                    exec.with_new_children(vec![child]),
+                ))))
+            }
             PhysicalPlan::CsvScan(_) => Ok(plan.clone()),
             PhysicalPlan::ParquetScan(_) => Ok(plan.clone()),
+            PhysicalPlan::JsonScan(_) => Ok(plan.clone()),
+            PhysicalPlan::AvroScan(_) => Ok(plan.clone()),
+            PhysicalPlan::IpcScan(_) => Ok(plan.clone()),
             _ => Err(ballista_error("visit_plan unsupported operator")),
         }
     }
+
+    /// Create a new stage to run the input of a shuffle and return a shuffle reader that reads
+    /// the output of the stage with the given partitioning
+    fn visit_shuffle(
+        &mut self,
+        child: &Arc<PhysicalPlan>,
+        partitioning: Partitioning,
+        current_stage: Rc<RefCell<Stage>>,
+    ) -> Result<Arc<PhysicalPlan>> {
+        // shuffle indicates that we need a new stage
+        let new_stage_id = self.next_stage_id;
+        self.next_stage_id += 1;
+        let new_stage = Rc::new(RefCell::new(Stage::new(new_stage_id)));
+        self.job.stages.push(new_stage.clone());
+
+        // the children need to be part of this new stage
+        let shuffle_input = self.visit_plan(child.clone(), new_stage.clone())?;
+
+        new_stage.as_ref().borrow_mut().plan = Some(shuffle_input);
+
+        // the current stage depends on this new stage
+        current_stage
+            .as_ref()
+            .borrow_mut()
+            .prior_stages
+            .push(new_stage_id);
+
+        // return a shuffle reader to read the results from the stage
+        let n = child
+            .as_execution_plan()
+            .output_partitioning()
+            .partition_count();
+
+        let shuffle_id = (0..n)
+            .map(|n| ShuffleId {
+                job_uuid: self.job.id,
+                stage_id: new_stage_id,
+                partition_id: n,
+            })
+            .collect();
+        Ok(Arc::new(PhysicalPlan::ShuffleReader(Arc::new(
+            ShuffleReaderExec::new(child.as_execution_plan().schema(), shuffle_id)
+                .with_partitioning(partitioning),
+        ))))
+    }
 }
 
 enum StageStatus {
@@ -238,37 +423,664 @@ enum StageStatus {
 
 enum TaskStatus {
     Pending(ExecutionTask),
+    Queued(Instant),
     Running(Instant),
+    /// Waiting until the given time before retrying a task that failed
+    Retrying(Instant),
     Completed(ShuffleId),
-    Failed(String),
+    /// The task failed with the given error and will not be retried
+    Failed(BallistaError),
+}
+
+impl TaskStatus {
+    fn to_task_state(&self) -> TaskState {
+        match self {
+            TaskStatus::Pending(_) | TaskStatus::Retrying(_) => TaskState::Pending,
+            TaskStatus::Queued(_) => TaskState::Queued,
+            TaskStatus::Running(_) => TaskState::Running,
+            TaskStatus::Completed(_) => TaskState::Completed,
+            TaskStatus::Failed(e) => TaskState::Failed(format!("{:?}", e)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-struct ExecutorShuffleIds {
-    executor_id: String,
-    shuffle_ids: Vec<ShuffleId>,
+struct StageTaskResults {
+    /// Shuffle partitions produced by the tasks and the executors that hold them
+    shuffle_ids: Vec<(ShuffleId, ExecutorMeta)>,
+    metrics: Vec<TaskMetrics>,
+}
+
+/// Policy for retrying tasks that fail with a transient error
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of times a task is attempted, including the first attempt
+    pub max_attempts: usize,
+    /// Delay before the first retry, which doubles with each subsequent retry
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between retries
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    /// Delay before retrying a task that has failed the given number of times
+    pub fn backoff(&self, failed_attempts: usize) -> Duration {
+        let exponent = failed_attempts.saturating_sub(1).min(31) as u32;
+        self.initial_backoff
+            .checked_mul(1 << exponent)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    /// Index of the executor and delay for the next attempt of a task that failed with the
+    /// given error on the executor at the given index, or `None` if the task has used up its
+    /// attempts or would fail again. The next attempt prefers a different executor.
+    fn next_attempt(
+        &self,
+        error: &BallistaError,
+        failed_attempts: usize,
+        executor: usize,
+        num_executors: usize,
+    ) -> Option<(usize, Duration)> {
+        if failed_attempts >= self.max_attempts || !is_retryable(error) {
+            return None;
+        }
+        Some((
+            (executor + 1) % num_executors,
+            self.backoff(failed_attempts),
+        ))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3, Duration::from_millis(500), Duration::from_secs(30))
+    }
 }
 
-/// Execute a job directly against executors as starting point
-pub async fn execute_job(job: &Job, ctx: Arc<dyn ExecutionContext>) -> Result<Vec<ColumnarBatch>> {
+/// Configuration of how jobs are planned and how their stages are partitioned
+#[derive(Debug, Clone, Copy)]
+pub struct JobConfig {
+    /// Maximum number of rows in each batch that scans produce
+    pub batch_size: usize,
+    /// Number of partitions that the output of each scan is redistributed into, or `None` to
+    /// keep one partition per file or file split
+    pub target_partitions: Option<usize>,
+    /// Whether the plans of stages are adapted to the statistics of the stages they depend on
+    /// before they run, by broadcasting small join inputs, splitting skewed partitions and
+    /// coalescing small partitions
+    pub adaptive: bool,
+    /// Whether executors that run out of tasks of a stage take over tasks that are waiting to
+    /// run on other executors
+    pub work_stealing: bool,
+    /// Partitions of a join input that are larger than this many bytes are skewed if they are
+    /// also more than `skew_factor` times the median size of the partitions
+    pub skew_threshold_bytes: u64,
+    pub skew_factor: u64,
+    /// Size in bytes up to which consecutive small partitions are coalesced
+    pub target_partition_bytes: u64,
+    /// Maximum number of bytes that each task holds in memory, in its output and its operators,
+    /// above which data is spilled to disk
+    pub memory_limit: Option<usize>,
+    /// Time after which the job is cancelled if it has not completed
+    pub timeout: Option<Duration>,
+    /// Time after which each attempt of a task is aborted if it has not completed
+    pub task_timeout: Option<Duration>,
+}
+
+impl JobConfig {
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_target_partitions(mut self, target_partitions: usize) -> Self {
+        self.target_partitions = Some(target_partitions.max(1));
+        self
+    }
+
+    pub fn with_adaptive_execution(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    pub fn with_work_stealing(mut self, work_stealing: bool) -> Self {
+        self.work_stealing = work_stealing;
+        self
+    }
+
+    pub fn with_target_partition_bytes(mut self, target_partition_bytes: u64) -> Self {
+        self.target_partition_bytes = target_partition_bytes;
+        self
+    }
+
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_task_timeout(mut self, task_timeout: Duration) -> Self {
+        self.task_timeout = Some(task_timeout);
+        self
+    }
+
+    /// Override the settings of this configuration with the settings attached to a query
+    pub fn with_query_settings(self, settings: &QuerySettings) -> Self {
+        let config = match settings.batch_size {
+            Some(batch_size) => self.with_batch_size(batch_size),
+            None => self,
+        };
+        let config = match settings.target_partitions {
+            Some(target_partitions) => config.with_target_partitions(target_partitions),
+            None => config,
+        };
+        let config = match settings.memory_limit {
+            Some(memory_limit) => config.with_memory_limit(memory_limit),
+            None => config,
+        };
+        let config = match settings.timeout {
+            Some(timeout) => config.with_timeout(timeout),
+            None => config,
+        };
+        match settings.task_timeout {
+            Some(task_timeout) => config.with_task_timeout(task_timeout),
+            None => config,
+        }
+    }
+
+    /// Split partitions of join inputs that are larger than `threshold_bytes` and more than
+    /// `factor` times the median partition size
+    pub fn with_skew_threshold(mut self, threshold_bytes: u64, factor: u64) -> Self {
+        self.skew_threshold_bytes = threshold_bytes;
+        self.skew_factor = factor;
+        self
+    }
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 64 * 1024,
+            target_partitions: None,
+            adaptive: true,
+            work_stealing: true,
+            skew_threshold_bytes: 256 * 1024 * 1024,
+            skew_factor: 5,
+            target_partition_bytes: 64 * 1024 * 1024,
+            memory_limit: None,
+            timeout: None,
+            task_timeout: None,
+        }
+    }
+}
+
+impl fmt::Display for JobConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "none".to_owned());
+        write!(
+            f,
+            "batch_size={}, target_partitions={}, memory_limit={}, timeout_ms={}, \
+            task_timeout_ms={}, adaptive={}, work_stealing={}",
+            self.batch_size,
+            optional(self.target_partitions.map(|n| n.to_string())),
+            optional(self.memory_limit.map(|n| n.to_string())),
+            optional(self.timeout.map(|t| t.as_millis().to_string())),
+            optional(self.task_timeout.map(|t| t.as_millis().to_string())),
+            self.adaptive,
+            self.work_stealing
+        )
+    }
+}
+
+/// Settings that a client attaches to a query, which override the configuration of the
+/// executor for the job that runs the query only
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuerySettings {
+    /// Maximum number of rows in each batch that scans produce
+    pub batch_size: Option<usize>,
+    /// Number of partitions that the output of each scan is redistributed into
+    pub target_partitions: Option<usize>,
+    /// Maximum number of bytes that each task holds in memory
+    pub memory_limit: Option<usize>,
+    /// Time after which the query is cancelled if it has not completed
+    pub timeout: Option<Duration>,
+    /// Time after which each attempt of a task of the query is aborted if it has not completed
+    pub task_timeout: Option<Duration>,
+}
+
+impl QuerySettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    pub fn with_target_partitions(mut self, target_partitions: usize) -> Self {
+        self.target_partitions = Some(target_partitions);
+        self
+    }
+
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_task_timeout(mut self, task_timeout: Duration) -> Self {
+        self.task_timeout = Some(task_timeout);
+        self
+    }
+
+    /// Use the settings of `defaults` that are not set in these settings
+    pub fn or(self, defaults: &QuerySettings) -> Self {
+        Self {
+            batch_size: self.batch_size.or(defaults.batch_size),
+            target_partitions: self.target_partitions.or(defaults.target_partitions),
+            memory_limit: self.memory_limit.or(defaults.memory_limit),
+            timeout: self.timeout.or(defaults.timeout),
+            task_timeout: self.task_timeout.or(defaults.task_timeout),
+        }
+    }
+
+    /// Names of the settings that are set
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = vec![];
+        if self.batch_size.is_some() {
+            names.push("batch_size");
+        }
+        if self.target_partitions.is_some() {
+            names.push("target_partitions");
+        }
+        if self.memory_limit.is_some() {
+            names.push("memory_limit");
+        }
+        if self.timeout.is_some() {
+            names.push("timeout_ms");
+        }
+        if self.task_timeout.is_some() {
+            names.push("task_timeout_ms");
+        }
+        names
+    }
+}
+
+/// Determine whether a task failure may succeed if retried. Errors in the plan or in the data
+/// will fail again, whereas network errors, lost executors and executors running out of
+/// resources are transient.
+fn is_retryable(error: &BallistaError) -> bool {
+    match error_kind(error) {
+        ErrorKind::Plan
+        | ErrorKind::SchemaMismatch
+        | ErrorKind::NotImplemented
+        | ErrorKind::DeadlineExceeded
+        | ErrorKind::QueryCancelled => false,
+        ErrorKind::General
+        | ErrorKind::ResourcesExhausted
+        | ErrorKind::DatasourceIo
+        | ErrorKind::ExecutorLost => true,
+    }
+}
+
+/// Interval at which the scheduler checks whether executors running its tasks have left the
+/// cluster
+const MEMBERSHIP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval at which running tasks are polled when their executor does not push their
+/// transitions
+const TASK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Interval at which running tasks are polled when their executor pushes their transitions, in
+/// case a transition is lost
+const WATCHED_TASK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Find the next executor after `current` that is still part of the cluster
+fn next_live_executor(
+    executors: &[ExecutorMeta],
+    current: usize,
+    live: &[ExecutorMeta],
+) -> Option<usize> {
+    (1..=executors.len())
+        .map(|offset| (current + offset) % executors.len())
+        .find(|i| live.iter().any(|e| e.id == executors[*i].id))
+}
+
+/// Execution profile of a job, built from the metrics reported by its tasks
+#[derive(Debug, Clone, Default)]
+pub struct JobProfile {
+    /// Profiles of the stages, in the order that they completed
+    pub stages: Vec<StageProfile>,
+}
+
+/// Execution profile of a stage
+#[derive(Debug, Clone)]
+pub struct StageProfile {
+    pub stage_id: usize,
+    /// The plan that the tasks of the stage executed, after it was adapted to the output of
+    /// the stages that it depends on
+    pub plan: Arc<PhysicalPlan>,
+    /// Wall-clock time taken to run all of the tasks in the stage
+    pub duration_ms: u64,
+    /// Metrics of each task in the stage
+    pub tasks: Vec<TaskMetrics>,
+}
+
+impl StageProfile {
+    /// Metrics summed over all tasks in the stage
+    pub fn total(&self) -> TaskMetrics {
+        let mut total = TaskMetrics::default();
+        for task in &self.tasks {
+            total.merge(task);
+        }
+        total
+    }
+}
+
+/// Find the shuffle partitions produced by the given stages that are held by executors which
+/// are no longer part of the cluster
+fn lost_shuffle_partitions(
+    stage_ids: &[usize],
+    shuffle_locations: &HashMap<ShuffleId, ExecutorMeta>,
+    live: &[ExecutorMeta],
+) -> Vec<ShuffleId> {
+    shuffle_locations
+        .iter()
+        .filter(|(shuffle_id, executor)| {
+            stage_ids.contains(&shuffle_id.stage_id) && !live.iter().any(|e| e.id == executor.id)
+        })
+        .map(|(shuffle_id, _)| *shuffle_id)
+        .collect()
+}
+
+/// Find lost shuffle partitions that are in shared storage, or that an executor handed off to
+/// a live executor when it shut down, and read them from there from now on. Returns the
+/// partitions that are still lost.
+async fn relocate_shuffle_partitions(
+    ctx: &dyn ExecutionContext,
+    lost: Vec<ShuffleId>,
+    shuffle_locations: &mut HashMap<ShuffleId, ExecutorMeta>,
+    live: &[ExecutorMeta],
+) -> Vec<ShuffleId> {
+    let mut lost = lost;
+    if let Some(storage) = &ctx.config().shuffle_storage {
+        // tasks read partitions in shared storage directly, whatever their location
+        lost.retain(|shuffle_id| {
+            if !storage.exists(shuffle_id) {
+                return true;
+            }
+            info!(
+                "Reading shuffle partition from shared storage job_uuid={} stage_id={} partition_id={} root={}",
+                shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id, storage.root()
+            );
+            false
+        });
+    }
+    for executor in live {
+        if lost.is_empty() {
+            break;
+        }
+        let held = match ctx.held_shuffles(executor.clone()).await {
+            Ok(held) => held,
+            Err(e) => {
+                warn!(
+                    "Failed to list shuffle partitions executor_id={} error={:?}",
+                    executor.id, e
+                );
+                continue;
+            }
+        };
+        lost.retain(|shuffle_id| {
+            if !held.contains(shuffle_id) {
+                return true;
+            }
+            info!(
+                "Reading shuffle partition that was handed off job_uuid={} stage_id={} partition_id={} executor_id={}",
+                shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id, executor.id
+            );
+            shuffle_locations.insert(*shuffle_id, executor.clone());
+            false
+        });
+    }
+    lost
+}
+
+/// Locations of the shuffle partitions produced by a stage, ordered by partition
+fn stage_locations(
+    shuffle_locations: &HashMap<ShuffleId, ExecutorMeta>,
+    stage_id: usize,
+) -> Vec<ShuffleLocation> {
+    let mut locations: Vec<ShuffleLocation> = shuffle_locations
+        .iter()
+        .filter(|(shuffle_id, _)| shuffle_id.stage_id == stage_id)
+        .map(|(shuffle_id, executor)| ShuffleLocation::new(*shuffle_id, executor.clone()))
+        .collect();
+    locations.sort_by_key(|loc| loc.shuffle_id.partition_id);
+    locations
+}
+
+/// Persist the progress of a job. A failure to do so does not fail the job, but means that the
+/// job may not be resumed from this point if the scheduler restarts.
+async fn save_job_record(store: &dyn JobStateStore, record: &JobRecord) {
+    if let Err(e) = store.save_job(record).await {
+        warn!(
+            "Failed to persist job state job_uuid={} error={:?}",
+            record.status.job_uuid, e
+        );
+    }
+}
+
+/// Cancels a job that has not finished when its timeout passes
+pub(crate) struct JobTimeout {
+    timeout: Option<Duration>,
+    cancellation_token: CancellationToken,
+    progress: Option<ProgressTracker>,
+}
+
+impl JobTimeout {
+    pub fn new(cancellation_token: CancellationToken, timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            cancellation_token,
+            progress: None,
+        }
+    }
+
+    /// Report how far the job got in the error of a job whose timeout passed
+    pub fn with_progress(mut self, progress: ProgressTracker) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Run a job until it finishes, cancelling it once its timeout passes
+    pub async fn run<T>(&self, job: impl Future<Output = Result<T>>) -> Result<T> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return job.await,
+        };
+        let job = Box::pin(job);
+        match future::select(job, Timer::after(timeout)).await {
+            Either::Left((result, _)) => result,
+            Either::Right((_, job)) => {
+                self.cancellation_token.cancel();
+                // the job stops at the next point where it checks whether it was cancelled
+                job.await.map_err(|e| self.map_err(e, timeout))
+            }
+        }
+    }
+
+    /// Replace the error of a job that was cancelled because its timeout passed
+    fn map_err(&self, e: BallistaError, timeout: Duration) -> BallistaError {
+        match e {
+            BallistaError::Cancelled => {
+                let progress = match &self.progress {
+                    Some(progress) => {
+                        let progress = progress.snapshot();
+                        format!(
+                            ", having completed {} of {} stages and {} of {} tasks of the \
+                            stages that started",
+                            progress.stages.iter().filter(|s| s.completed).count(),
+                            progress.stages.len(),
+                            progress.completed_tasks(),
+                            progress.total_tasks()
+                        )
+                    }
+                    None => String::new(),
+                };
+                BallistaError::DeadlineExceeded(format!(
+                    "Job did not complete within its timeout of {} ms{}",
+                    timeout.as_millis(),
+                    progress
+                ))
+            }
+            e => e,
+        }
+    }
+}
+
+/// Time at which an attempt of a task that starts now is aborted, which is when the timeout of
+/// the task passes or when the timeout of its job passes, whichever comes first
+fn task_deadline(
+    job_deadline: Option<SystemTime>,
+    task_timeout: Option<Duration>,
+) -> Option<SystemTime> {
+    let task_deadline = task_timeout.map(|timeout| SystemTime::now() + timeout);
+    match (job_deadline, task_deadline) {
+        (Some(job), Some(task)) => Some(job.min(task)),
+        (job, task) => job.or(task),
+    }
+}
+
+/// Execute a job directly against executors, stage by stage, and return the locations of the
+/// shuffle partitions produced by the final stage along with the execution profile of the job.
+/// When the context has a job state store holding a record of the job, the progress of the job
+/// is persisted as it runs and stages that already completed are not run again. Shuffle
+/// partitions that are lost along with the executor holding them are recomputed by running the
+/// tasks that produced them again on the remaining executors.
+pub async fn execute_job(
+    job: &Job,
+    ctx: Arc<dyn ExecutionContext>,
+) -> Result<(Vec<ShuffleLocation>, JobProfile)> {
     let executors = ctx.get_executor_ids().await?;
 
-    println!("Executors: {:?}", executors);
+    debug!("Executors: {:?}", executors);
 
     if executors.is_empty() {
-        println!("no executors found");
+        error!("No executors found job_uuid={}", job.id);
         return Err(ballista_error("no executors available"));
     }
 
+    // the job waits here while its tenant is running as many jobs as it may
+    let tenant = ctx.tenant();
+    let scheduling_policy = ctx.config().scheduling_policy;
+    let _permit = JobPermit::acquire(
+        scheduling_policy.clone(),
+        &tenant,
+        job.id,
+        &ctx.cancellation_token(),
+    )
+    .await?;
+
     let mut shuffle_location_map: HashMap<ShuffleId, ExecutorMeta> = HashMap::new();
 
     let mut stage_status_map = HashMap::new();
 
+    let mut profile = JobProfile::default();
+
+    let job_config = ctx.config().job_config;
+    // executors abort the tasks of the job that are still running when its timeout passes
+    let job_deadline = job_config
+        .timeout
+        .map(|timeout| SystemTime::now() + timeout);
+
+    let plans: Vec<Arc<PhysicalPlan>> = job
+        .stages
+        .iter()
+        .filter_map(|stage| stage.borrow().plan.clone())
+        .collect();
+    // the partitioning of the output of each stage, as read by the stages that depend on it
+    let output_partitioning = stage_output_partitioning(&plans);
+    // stages that shuffle the right input of a join, which need not run if the left input of
+    // the join turns out to be small enough to broadcast
+    let broadcast_candidates = broadcast_candidates(&plans);
+
+    // plans of the stages that have started, as adapted to the output of their prior stages
+    let mut stage_plans: HashMap<usize, Arc<PhysicalPlan>> = HashMap::new();
+    // plans of the stages that run as part of the stage that reads them instead
+    let mut inlined_stages: HashMap<usize, Arc<PhysicalPlan>> = HashMap::new();
+    // output of stages of other jobs that is read instead of running the same stages again,
+    // and the stages whose reused output was lost so that they run after all
+    let shared_stages = ctx.config().shared_stages;
+    let mut reused_stages: HashMap<usize, Vec<ShuffleLocation>> = HashMap::new();
+    let mut unshared_stages: HashSet<usize> = HashSet::new();
+
     for stage in &job.stages {
         let stage = stage.borrow_mut();
         stage_status_map.insert(stage.id, StageStatus::Pending);
     }
 
+    // resume from the persisted progress of the job when the scheduler has restarted
+    let mut persisted = match ctx.job_state_store() {
+        Some(store) => store.get_job(&job.id).await?.map(|record| (store, record)),
+        None => None,
+    };
+    if let Some((_, record)) = &persisted {
+        for stage_record in &record.stages {
+            // the output of a completed stage is lost if an executor holding it has left
+            let available = stage_record
+                .shuffle_locations
+                .iter()
+                .all(|loc| executors.iter().any(|e| e.id == loc.executor_meta.id));
+            if !stage_record.completed || !available {
+                continue;
+            }
+            info!(
+                "Recovered completed stage job_uuid={} stage_id={}",
+                job.id, stage_record.stage_id
+            );
+            for loc in &stage_record.shuffle_locations {
+                shuffle_location_map.insert(loc.shuffle_id, loc.executor_meta.clone());
+            }
+            stage_status_map.insert(stage_record.stage_id, StageStatus::Completed);
+            if stage_record.stage_id == job.root_stage_id {
+                let mut final_locations = stage_record.shuffle_locations.clone();
+                final_locations.sort_by_key(|loc| loc.shuffle_id.partition_id);
+                return Ok((final_locations, profile));
+            }
+        }
+    }
+
+    let progress = ctx.job_progress();
+    if let Some(progress) = &progress {
+        let stage_ids: Vec<usize> = job.stages.iter().map(|stage| stage.borrow().id).collect();
+        progress.register_stages(&stage_ids);
+        for (stage_id, status) in &stage_status_map {
+            if let StageStatus::Completed = status {
+                progress.complete_stage(*stage_id);
+            }
+        }
+    }
+
+    // number of times that shuffle partitions lost with an executor have been recomputed
+    let mut recomputations = 0;
+
     // loop until all stages are complete
     let mut num_completed = 0;
     while num_completed < job.stages.len() {
@@ -289,40 +1101,198 @@ pub async fn execute_job(job: &Job, ctx: Arc<dyn ExecutionContext>) -> Result<Ve
                             _ => false,
                         })
                     {
-                        println!("Running stage {}", stage.id);
                         let plan = stage
                             .plan
                             .as_ref()
                             .expect("all stages should have plans at execution time");
 
+                        // rather than shuffling the right input of a join whose left input is
+                        // small, the join reads the right input directly and broadcasts the left
+                        let broadcast_bytes = broadcast_candidates
+                            .get(&stage.id)
+                            .and_then(|left_stage_id| stage_output_bytes(&profile, *left_stage_id))
+                            .filter(|bytes| {
+                                job_config.adaptive && *bytes <= BROADCAST_JOIN_THRESHOLD
+                            });
+                        if let Some(bytes) = broadcast_bytes {
+                            info!(
+                                "Skipping stage to broadcast join input job_uuid={} stage_id={} broadcast_bytes={}",
+                                job.id, stage.id, bytes
+                            );
+                            inlined_stages.insert(stage.id, plan.clone());
+                            stage_status_map.insert(stage.id, StageStatus::Completed);
+                            if let Some(progress) = &progress {
+                                progress.complete_stage(stage.id);
+                            }
+                            continue;
+                        }
+
+                        // the final stage is never shared, since its partitions are fetched by
+                        // the client of the job
+                        let fingerprint = match &shared_stages {
+                            Some(_)
+                                if stage.id != job.root_stage_id
+                                    && !unshared_stages.contains(&stage.id) =>
+                            {
+                                stage_fingerprint(plan, &tenant)
+                            }
+                            _ => None,
+                        };
+                        if let (Some(shared), Some(fingerprint)) = (&shared_stages, fingerprint) {
+                            if let Some(locations) = shared.lookup(fingerprint) {
+                                if retain_shuffles(ctx.as_ref(), job.id, &locations).await {
+                                    info!(
+                                        "Reusing output of stage of another job job_uuid={} stage_id={} source_job_uuid={} partitions={}",
+                                        job.id,
+                                        stage.id,
+                                        locations
+                                            .first()
+                                            .map(|loc| loc.shuffle_id.job_uuid.to_string())
+                                            .unwrap_or_default(),
+                                        locations.len()
+                                    );
+                                    reused_stages.insert(stage.id, locations);
+                                    stage_status_map.insert(stage.id, StageStatus::Completed);
+                                    if let Some(progress) = &progress {
+                                        progress.complete_stage(stage.id);
+                                    }
+                                    continue;
+                                }
+                                shared.remove(fingerprint);
+                            }
+                        }
+
+                        info!("Running stage job_uuid={} stage_id={}", job.id, stage.id);
+                        // the plan is only adapted once so that a stage that runs again to
+                        // recompute lost partitions keeps the same partitions
+                        let plan = match stage_plans.get(&stage.id) {
+                            Some(plan) => plan.clone(),
+                            None => {
+                                let stats = RuntimeStatistics {
+                                    shuffle_locations: &shuffle_location_map,
+                                    profile: &profile,
+                                    inlined_stages: &inlined_stages,
+                                    reused_stages: &reused_stages,
+                                };
+                                let plan = adapt_stage_plan(plan, &stats, &job_config)?;
+                                stage_plans.insert(stage.id, plan.clone());
+                                plan
+                            }
+                        };
+
                         let stage_start = Instant::now();
+                        let mut stage_span = Span::start("stage", ctx.trace_context().as_ref())
+                            .with_attribute("job_uuid", job.id)
+                            .with_attribute("stage_id", stage.id);
 
                         let exec = plan.as_execution_plan();
                         let parts = exec.output_partitioning().partition_count();
 
+                        // place tasks only on executors that are still part of the cluster
+                        let executors = match ctx.get_executor_ids().await {
+                            Ok(live) if !live.is_empty() => live,
+                            _ => executors.clone(),
+                        };
+
+                        // every task of a job compresses its output with the codec that the
+                        // scheduling process is configured with
+                        let shuffle_compression = ctx.config().shuffle_compression;
+                        // every task of a stage is estimated to need the same resources
+                        let resources = estimate_task_resources(
+                            &plan,
+                            parts,
+                            &profile,
+                            &StatisticsCatalog::new(),
+                        );
+                        debug!(
+                            "Estimated task resources stage_id={} cores={} memory_bytes={}",
+                            stage.id, resources.cores, resources.memory_bytes
+                        );
+
+                        // tasks find the partitions of stages of other jobs along with those of
+                        // this job
+                        let mut task_locations = shuffle_location_map.clone();
+                        for loc in reused_stages.values().flatten() {
+                            task_locations.insert(loc.shuffle_id, loc.executor_meta.clone());
+                        }
+
+                        // only run the tasks whose output is missing, which is all of them unless
+                        // partitions lost with an executor are being recomputed
+                        let tasks: Vec<ExecutionTask> = (0..parts)
+                            .filter(|partition| {
+                                !shuffle_location_map
+                                    .contains_key(&ShuffleId::new(job.id, stage.id, *partition))
+                            })
+                            .map(|partition| {
+                                let task = ExecutionTask::new(
+                                    job.id,
+                                    stage.id,
+                                    partition,
+                                    plan.as_ref().clone(),
+                                    task_locations.clone(),
+                                )
+                                .with_shuffle_compression(shuffle_compression)
+                                .with_resources(resources)
+                                .with_trace_context(stage_span.context());
+                                let task = match job_config.memory_limit {
+                                    Some(memory_limit) => task.with_memory_limit(memory_limit),
+                                    None => task,
+                                };
+                                match output_partitioning.get(&stage.id) {
+                                    Some(p @ Partitioning::HashPartitioning(_, _)) => {
+                                        task.with_output_partitioning(p.clone())
+                                    }
+                                    _ => task,
+                                }
+                            })
+                            .collect();
+                        if let Some(progress) = &progress {
+                            progress.start_stage(stage.id, parts, parts - tasks.len());
+                        }
+
                         // build queue of tasks per executor
-                        let mut next_executor_id = 0;
+                        let placement = ctx.config().placement_policy.place(&tasks, &executors);
+
+                        // keep tasks on the executors they were placed on before the scheduler
+                        // restarted, so that tasks which are already running are not run again
+                        let recorded = persisted
+                            .as_ref()
+                            .and_then(|(_, record)| record.stage(stage.id))
+                            .map(|s| s.task_assignments.clone())
+                            .unwrap_or_default();
+                        let placement: Vec<usize> = tasks
+                            .iter()
+                            .zip(placement)
+                            .map(|(task, executor_index)| {
+                                recorded
+                                    .get(&task.partition_id)
+                                    .and_then(|id| executors.iter().position(|e| &e.id == id))
+                                    .unwrap_or(executor_index)
+                            })
+                            .collect();
+                        if let Some((store, record)) = &mut persisted {
+                            if let Some(stage_record) = record.stage_mut(stage.id) {
+                                stage_record.task_assignments.extend(
+                                    tasks.iter().zip(&placement).map(|(task, i)| {
+                                        (task.partition_id, executors[*i].id.clone())
+                                    }),
+                                );
+                            }
+                            save_job_record(store.as_ref(), record).await;
+                        }
+
                         let mut executor_tasks = HashMap::new();
                         #[allow(clippy::needless_range_loop)]
                         for i in 0..executors.len() {
                             executor_tasks.insert(executors[i].id.clone(), vec![]);
                         }
-                        for partition in 0..parts {
-                            let task = ExecutionTask::new(
-                                job.id,
-                                stage.id,
-                                partition,
-                                plan.as_ref().clone(),
-                                shuffle_location_map.clone(),
+                        for (task, executor_index) in tasks.into_iter().zip(placement) {
+                            let executor_meta = &executors[executor_index];
+                            debug!(
+                                "Placed task task_key={} executor_id={}",
+                                task.key(),
+                                executor_meta.id
                             );
-
-                            // load balance across the executors
-                            let executor_meta = &executors[next_executor_id];
-                            next_executor_id += 1;
-                            if next_executor_id == executors.len() {
-                                next_executor_id = 0;
-                            }
-
                             let queue = executor_tasks
                                 .get_mut(&executor_meta.id)
                                 .expect("executor queue should exist");
@@ -331,6 +1301,8 @@ pub async fn execute_job(job: &Job, ctx: Arc<dyn ExecutionContext>) -> Result<Ve
                         }
 
                         let mut threads = vec![];
+                        let retry_policy = ctx.config().retry_policy;
+                        let idle_executors = Arc::new(IdleExecutors::new());
 
                         #[allow(clippy::needless_range_loop)]
                         for i in 0..executors.len() {
@@ -338,72 +1310,245 @@ pub async fn execute_job(job: &Job, ctx: Arc<dyn ExecutionContext>) -> Result<Ve
                             let queue = executor_tasks
                                 .get(&executor.id)
                                 .expect("executor queue should exist");
-                            let queue = queue.clone();
+                            let mut queue = queue.clone();
                             let ctx = ctx.clone();
-
-                            // start thread per executor
-                            let handle = thread::spawn(move || {
-                                smol::run(async {
+                            let executors = executors.clone();
+                            let stage_id = stage.id;
+                            let executor_index = i;
+                            let scheduling_policy = scheduling_policy.clone();
+                            let tenant = tenant.clone();
+                            let idle_executors = idle_executors.clone();
+
+                            // start thread per executor, whose results the job waits for without
+                            // blocking the other jobs that run on the same thread
+                            let (tx, rx) = oneshot::channel();
+                            thread::spawn(move || {
+                                let results = smol::run(async {
                                     Task::blocking(async move {
                                         let mut task_status = vec![];
                                         for task in &queue {
                                             task_status.push(TaskStatus::Pending(task.clone()));
                                         }
+                                        // the executor that each task is assigned to, which changes when a task is retried
+                                        let mut assigned_executor = vec![i; queue.len()];
+                                        let mut failed_attempts = vec![0; queue.len()];
+                                        // number of attempts of each task that have been submitted
+                                        let mut attempts = vec![0; queue.len()];
+                                        // queued and running tasks hold a slot of the tenant, so that tasks of
+                                        // other tenants are interleaved with them
+                                        let mut slots = TaskSlots::new(scheduling_policy, &tenant, queue.len());
+                                        // tasks are packed onto the executor by the resources that it advertises,
+                                        // with queued and running tasks holding their reservation
+                                        let mut capacity = match ctx.executor_capacity(executor.clone()).await {
+                                            Ok(capacity) => Some(ResourcePool::new(capacity)),
+                                            Err(e) => {
+                                                warn!("Failed to get executor capacity executor_id={} error={:?}", executor.id, e);
+                                                None
+                                            }
+                                        };
+                                        let mut reserved = vec![false; queue.len()];
+                                        // idle executors that tasks were moved to, which are offered again once the
+                                        // task finishes there
+                                        let mut stolen_by: Vec<Option<usize>> = vec![None; queue.len()];
+                                        let mut offered = false;
+                                        let mut last_membership_check = Instant::now();
+
+                                        // tasks whose executor pushed that they failed or were cancelled, which are
+                                        // polled right away so that the failure is handled as when they are submitted
+                                        let mut poll_now = vec![false; queue.len()];
 
                                         let mut shuffle_ids = vec![];
+                                        let mut metrics = vec![];
+
+                                        // the executor pushes the transitions of its tasks, so that they only need
+                                        // to be polled occasionally
+                                        let mut updates = match queue.first() {
+                                            Some(task) => match ctx.watch_tasks(executor.clone(), task.job_uuid).await {
+                                                Ok(updates) => Some(updates),
+                                                Err(e) => {
+                                                    warn!("Failed to watch tasks, polling them instead executor_id={} error={:?}", executor.id, e);
+                                                    None
+                                                }
+                                            },
+                                            None => None,
+                                        };
                                         loop {
 
                                             let mut pending = 0;
+                                            let mut queued = 0;
                                             let mut running = 0;
                                             let mut completed = 0;
                                             let mut failed = 0;
 
-                                            for status in &task_status {
+                                            for (i, status) in task_status.iter().enumerate() {
                                                 match status {
-                                                    TaskStatus::Pending(_) => pending += 1,
+                                                    TaskStatus::Pending(_) | TaskStatus::Retrying(_) => pending += 1,
+                                                    TaskStatus::Queued(_) => queued += 1,
                                                     TaskStatus::Running(_) => running += 1,
                                                     TaskStatus::Completed(_) => completed += 1,
                                                     TaskStatus::Failed(_) => failed += 1,
                                                 }
+                                                if let Some(idle) = stolen_by[i] {
+                                                    let finished = matches!(status, TaskStatus::Completed(_) | TaskStatus::Failed(_));
+                                                    if finished || assigned_executor[i] != idle {
+                                                        idle_executors.offer(idle);
+                                                        stolen_by[i] = None;
+                                                    }
+                                                }
+                                                if !matches!(status, TaskStatus::Queued(_) | TaskStatus::Running(_)) {
+                                                    slots.release(i);
+                                                    if let (true, Some(pool)) = (reserved[i], &mut capacity) {
+                                                        pool.release(&queue[i].resources);
+                                                        reserved[i] = false;
+                                                    }
+                                                }
                                             }
 
-                                            println!(
-                                                "Executor {} task stats: {} pending, {} running, {} completed, {} failed",
+                                            debug!(
+                                                "Task stats executor_id={} pending={} queued={} running={} completed={} failed={}",
                                                 executor.id,
-                                                pending,running,completed,failed
+                                                pending,queued,running,completed,failed
                                             );
 
-                                            if failed > 0  {
-                                                return Err(ballista_error("At least one task failed and there is no retry capability yet"))
+                                            if let Some(progress) = ctx.job_progress() {
+                                                let counts = TaskCounts { pending, queued, running, completed, failed };
+                                                let shuffle_bytes = metrics.iter().map(|m: &TaskMetrics| m.shuffle_bytes).sum();
+                                                progress.update_tasks(stage_id, &executor.id, counts, shuffle_bytes);
+                                            }
+
+                                            let cancelled = ctx.cancellation_token().is_cancelled();
+                                            if failed > 0 || cancelled {
+                                                // the job will fail so there is no point in letting the remaining tasks run
+                                                for i in 0..task_status.len() {
+                                                    match task_status[i] {
+                                                        TaskStatus::Queued(_) | TaskStatus::Running(_) => {
+                                                            let task = &queue[i];
+                                                            if let Err(e) = ctx
+                                                                .cancel_task(executors[assigned_executor[i]].clone(), task.job_uuid, task.stage_id, task.partition_id)
+                                                                .await
+                                                            {
+                                                                warn!("Failed to cancel task task_key={} error={:?}", task.key(), e);
+                                                            }
+                                                        }
+                                                        _ => {}
+                                                    }
+                                                }
+                                                if cancelled {
+                                                    return Err(BallistaError::Cancelled)
+                                                }
+                                                let timed_out = task_status.iter().zip(&queue).find_map(|(status, task)| match status {
+                                                    TaskStatus::Failed(e) if error_kind(e) == ErrorKind::DeadlineExceeded => Some(task.key()),
+                                                    _ => None,
+                                                });
+                                                if let Some(task_key) = timed_out {
+                                                    return Err(BallistaError::DeadlineExceeded(format!(
+                                                        "Task {} did not complete before its deadline, having completed {} of {} tasks of stage {} on executor {}",
+                                                        task_key, completed, queue.len(), stage_id, executor.id
+                                                    )))
+                                                }
+                                                return Err(ballista_error("At least one task failed and could not be retried"))
+                                            }
+
+                                            // once the executor has no tasks of its own left, it runs tasks that are
+                                            // waiting on other executors
+                                            let own_tasks = task_status.iter().zip(&assigned_executor).filter(|(status, assigned)| {
+                                                **assigned == executor_index
+                                                    && matches!(status, TaskStatus::Pending(_) | TaskStatus::Queued(_) | TaskStatus::Running(_) | TaskStatus::Retrying(_))
+                                            }).count();
+                                            if job_config.work_stealing && !offered && own_tasks == 0 {
+                                                idle_executors.offer(executor_index);
+                                                offered = true;
                                             }
 
-                                            if pending ==0 && running==0 {
+                                            if pending ==0 && queued==0 && running==0 {
                                                 break;
                                             }
 
+                                            // move in-flight tasks away from executors that have left the cluster
+                                            if last_membership_check.elapsed() >= MEMBERSHIP_CHECK_INTERVAL {
+                                                last_membership_check = Instant::now();
+                                                match ctx.get_executor_ids().await {
+                                                    Ok(live) => {
+                                                        for i in 0..task_status.len() {
+                                                            let in_flight = matches!(
+                                                                task_status[i],
+                                                                TaskStatus::Queued(_) | TaskStatus::Running(_) | TaskStatus::Retrying(_)
+                                                            );
+                                                            let lost = !live.iter().any(|e| e.id == executors[assigned_executor[i]].id);
+                                                            if in_flight && lost {
+                                                                if let Some(next) = next_live_executor(&executors, assigned_executor[i], &live) {
+                                                                    warn!(
+                                                                        "Rescheduling task from lost executor task_key={} executor_id={} new_executor_id={}",
+                                                                        queue[i].key(),
+                                                                        executors[assigned_executor[i]].id,
+                                                                        executors[next].id
+                                                                    );
+                                                                    assigned_executor[i] = next;
+                                                                    task_status[i] = TaskStatus::Pending(queue[i].clone());
+                                                                }
+                                                            }
+                                                        }
+                                                    }
+                                                    Err(e) => warn!("Failed to refresh executors error={:?}", e),
+                                                }
+                                            }
+
                                             //TODO need to send multiple tasks per network call - this is really inefficient
                                             for i in 0..task_status.len() {
+                                                // only this executor pushes the transitions of its tasks, so tasks that moved
+                                                // to other executors are polled as often as without pushes
+                                                let poll_interval = if updates.is_some() && assigned_executor[i] == executor_index {
+                                                    WATCHED_TASK_POLL_INTERVAL
+                                                } else {
+                                                    TASK_POLL_INTERVAL
+                                                };
 
                                                 let should_submit = match &task_status[i] {
                                                     TaskStatus::Pending(_) => true,
-                                                    TaskStatus::Running(last_check) => last_check.elapsed().as_millis() > 500,
+                                                    // queued tasks are not expected to make progress quickly
+                                                    TaskStatus::Queued(last_check) => poll_now[i] || last_check.elapsed() > poll_interval * 2,
+                                                    TaskStatus::Running(last_check) => poll_now[i] || last_check.elapsed() > poll_interval,
+                                                    TaskStatus::Retrying(retry_at) => Instant::now() >= *retry_at,
                                                     TaskStatus::Completed(_) => false,
-                                                    TaskStatus::Failed(_) => {
-                                                        //TODO retry logic
-                                                        false
-                                                    },
+                                                    TaskStatus::Failed(_) => false,
                                                 };
 
                                                 if should_submit {
+                                                    if matches!(task_status[i], TaskStatus::Pending(_) | TaskStatus::Retrying(_)) {
+                                                        // pending tasks wait until their resources fit on the executor, unless
+                                                        // they were moved to another executor
+                                                        if let Some(pool) = &mut capacity {
+                                                            if assigned_executor[i] == executor_index && !reserved[i] {
+                                                                if !pool.try_reserve(&queue[i].resources) {
+                                                                    continue;
+                                                                }
+                                                                reserved[i] = true;
+                                                            }
+                                                        }
+                                                        // and until the tenant has a free slot
+                                                        if !slots.acquire(i) {
+                                                            continue;
+                                                        }
+                                                    }
+                                                    poll_now[i] = false;
+                                                    // each attempt of a task has its own number and deadline, while
+                                                    // polling for the status of an attempt submits it again as it is
+                                                    if matches!(task_status[i], TaskStatus::Pending(_) | TaskStatus::Retrying(_)) {
+                                                        queue[i].attempt = attempts[i];
+                                                        queue[i].deadline = task_deadline(job_deadline, job_config.task_timeout);
+                                                        attempts[i] += 1;
+                                                    }
                                                     let task = queue[i].clone();
                                                     let task_key = task.key();
+                                                    let task_executor = &executors[assigned_executor[i]];
                                                     match ctx
-                                                        .execute_task(executor.clone(), task)
+                                                        .execute_task(task_executor.clone(), task)
                                                         .await
                                                     {
-                                                        Ok(shuffle_id) => {
-                                                            println!("Task {} completed", task_key);
-                                                            shuffle_ids.push(shuffle_id);
+                                                        Ok((shuffle_id, task_metrics)) => {
+                                                            debug!("Task completed task_key={}", task_key);
+                                                            shuffle_ids.push((shuffle_id, task_executor.clone()));
+                                                            metrics.push(task_metrics);
                                                             task_status[i] = TaskStatus::Completed(shuffle_id)
                                                         }
                                                         Err(e) => {
@@ -411,81 +1556,345 @@ pub async fn execute_job(job: &Job, ctx: Arc<dyn ExecutionContext>) -> Result<Ve
                                                             //TODO would be nice to be able to extract status code here
                                                             if msg.contains("ResourceExhausted") {
                                                                 // ignore
+                                                            } else if msg.contains("task is queued") {
+                                                                task_status[i] = TaskStatus::Queued(Instant::now())
                                                             } else if msg.contains("AlreadyExists") {
                                                                 task_status[i] = TaskStatus::Running(Instant::now())
                                                             } else {
-                                                                task_status[i] = TaskStatus::Failed(msg)
+                                                                failed_attempts[i] += 1;
+                                                                if let Some((executor, backoff)) = retry_policy.next_attempt(
+                                                                    &e,
+                                                                    failed_attempts[i],
+                                                                    assigned_executor[i],
+                                                                    executors.len(),
+                                                                ) {
+                                                                    assigned_executor[i] = executor;
+                                                                    warn!(
+                                                                        "Retrying task task_key={} attempt={} executor_id={} backoff_ms={} error={}",
+                                                                        task_key,
+                                                                        failed_attempts[i] + 1,
+                                                                        executors[assigned_executor[i]].id,
+                                                                        backoff.as_millis(),
+                                                                        msg
+                                                                    );
+                                                                    task_status[i] = TaskStatus::Retrying(Instant::now() + backoff)
+                                                                } else {
+                                                                    error!("Task failed task_key={} attempts={} error={}", task_key, failed_attempts[i], msg);
+                                                                    task_status[i] = TaskStatus::Failed(e)
+                                                                }
                                                             }
                                                         }
                                                     }
+
+                                                    if let Some(progress) = ctx.job_progress() {
+                                                        let shuffle_bytes = match task_status[i] {
+                                                            TaskStatus::Completed(_) => metrics.last().map(|m: &TaskMetrics| m.shuffle_bytes).unwrap_or(0),
+                                                            _ => 0,
+                                                        };
+                                                        progress.update_task(
+                                                            stage_id,
+                                                            queue[i].partition_id,
+                                                            &executors[assigned_executor[i]].id,
+                                                            task_status[i].to_task_state(),
+                                                            shuffle_bytes,
+                                                        );
+                                                    }
+                                                }
+                                            }
+
+                                            // move tasks that are still waiting to run on this executor to idle executors
+                                            if job_config.work_stealing {
+                                                for i in 0..task_status.len() {
+                                                    let waiting = match task_status[i] {
+                                                        TaskStatus::Pending(_) | TaskStatus::Queued(_) => {
+                                                            assigned_executor[i] == executor_index
+                                                        }
+                                                        _ => false,
+                                                    };
+                                                    if !waiting {
+                                                        continue;
+                                                    }
+                                                    let idle = match idle_executors.claim(executor_index) {
+                                                        Some(idle) => idle,
+                                                        None => break,
+                                                    };
+                                                    let task = &queue[i];
+                                                    if let TaskStatus::Queued(_) = task_status[i] {
+                                                        // the task may have started since it was last polled
+                                                        if let Err(e) = ctx
+                                                            .withdraw_task(executor.clone(), task.job_uuid, task.stage_id, task.partition_id)
+                                                            .await
+                                                        {
+                                                            debug!("Task could not be withdrawn task_key={} error={:?}", task.key(), e);
+                                                            idle_executors.offer(idle);
+                                                            continue;
+                                                        }
+                                                    }
+                                                    info!(
+                                                        "Moving task to idle executor task_key={} executor_id={} idle_executor_id={}",
+                                                        task.key(),
+                                                        executor.id,
+                                                        executors[idle].id
+                                                    );
+                                                    assigned_executor[i] = idle;
+                                                    stolen_by[i] = Some(idle);
+                                                    task_status[i] = TaskStatus::Pending(task.clone());
+                                                }
+                                            }
+
+                                            // wait for the executor to push a transition, but try not to overwhelm
+                                            // network or executors with polls
+                                            let update = match &mut updates {
+                                                Some(stream) => {
+                                                    let timeout = Timer::after(Duration::from_millis(100));
+                                                    match future::select(stream.next(), timeout).await {
+                                                        Either::Left((update, _)) => Some(update),
+                                                        Either::Right(_) => None,
+                                                    }
+                                                }
+                                                None => {
+                                                    thread::sleep(Duration::from_millis(100));
+                                                    None
                                                 }
+                                            };
+                                            let update = match update {
+                                                Some(Some(Ok(update))) => update,
+                                                Some(Some(Err(e))) => {
+                                                    warn!("Lost task updates, polling tasks instead executor_id={} error={:?}", executor.id, e);
+                                                    updates = None;
+                                                    continue;
+                                                }
+                                                Some(None) => {
+                                                    updates = None;
+                                                    continue;
+                                                }
+                                                None => continue,
+                                            };
+
+                                            // updates of superseded attempts, or of tasks that moved to another
+                                            // executor, are ignored
+                                            let i = match queue.iter().position(|task| {
+                                                task.stage_id == update.stage_id && task.partition_id == update.partition_id
+                                            }) {
+                                                Some(i) if update.attempt == queue[i].attempt
+                                                    && assigned_executor[i] == executor_index
+                                                    && matches!(task_status[i], TaskStatus::Queued(_) | TaskStatus::Running(_)) => i,
+                                                _ => continue,
+                                            };
+                                            debug!(
+                                                "Task update task_key={} attempt={} state={:?}",
+                                                queue[i].key(),
+                                                update.attempt,
+                                                update.state
+                                            );
+                                            let mut shuffle_bytes = 0;
+                                            match update.state {
+                                                TaskUpdateState::Running => task_status[i] = TaskStatus::Running(Instant::now()),
+                                                TaskUpdateState::Completed(task_metrics) => {
+                                                    let shuffle_id = ShuffleId::new(update.job_uuid, update.stage_id, update.partition_id);
+                                                    shuffle_ids.push((shuffle_id, executor.clone()));
+                                                    shuffle_bytes = task_metrics.shuffle_bytes;
+                                                    metrics.push(task_metrics);
+                                                    task_status[i] = TaskStatus::Completed(shuffle_id)
+                                                }
+                                                TaskUpdateState::Failed(_) | TaskUpdateState::Cancelled => poll_now[i] = true,
+                                            }
+                                            if let Some(progress) = ctx.job_progress() {
+                                                progress.update_task(
+                                                    stage_id,
+                                                    queue[i].partition_id,
+                                                    &executor.id,
+                                                    task_status[i].to_task_state(),
+                                                    shuffle_bytes,
+                                                );
                                             }
-                                            // try not to overwhelm network or executors
-                                            thread::sleep(Duration::from_millis(100));
                                         }
-                                        Ok(ExecutorShuffleIds {
-                                            executor_id: executor.id,
+                                        Ok(StageTaskResults {
                                             shuffle_ids,
+                                            metrics,
                                         })
                                     })
                                     .await
-                                })
+                                });
+                                // the job is gone if it was dropped before the stage finished
+                                let _ = tx.send(results);
                             });
-                            threads.push(handle);
+                            threads.push(rx);
                         }
 
-                        let mut stage_shuffle_ids: Vec<ExecutorShuffleIds> = vec![];
-                        for thread in threads {
-                            stage_shuffle_ids.push(thread.join().unwrap()?);
+                        let mut stage_results: Vec<StageTaskResults> = vec![];
+                        let mut stage_error = None;
+                        for results in future::join_all(threads).await {
+                            match results {
+                                Ok(Ok(results)) => stage_results.push(results),
+                                Ok(Err(e)) => stage_error = Some(e),
+                                Err(_) => {
+                                    stage_error = Some(ballista_error(
+                                        "Stage thread stopped before its tasks finished",
+                                    ))
+                                }
+                            }
                         }
-                        println!(
-                            "Stage {} completed in {} ms and produced {} shuffles",
-                            stage.id,
-                            stage_start.elapsed().as_millis(),
-                            stage_shuffle_ids.len()
-                        );
 
-                        for executor_shuffle_ids in &stage_shuffle_ids {
-                            for shuffle_id in &executor_shuffle_ids.shuffle_ids {
-                                let executor = executors
-                                    .iter()
-                                    .find(|e| e.id == executor_shuffle_ids.executor_id)
-                                    .unwrap();
+                        // keep the output of the tasks that completed so that they are not run again
+                        for results in &stage_results {
+                            for (shuffle_id, executor) in &results.shuffle_ids {
                                 shuffle_location_map.insert(*shuffle_id, executor.clone());
                             }
                         }
-                        stage_status_map.insert(stage.id, StageStatus::Completed);
 
-                        if stage.id == job.root_stage_id {
-                            println!("reading final results from query!");
-                            let ctx = Arc::new(DefaultContext::new(
-                                &ctx.config(),
-                                shuffle_location_map.clone(),
-                            ));
-
-                            println!("stage final shuffle ids: {:?}", stage_shuffle_ids);
-
-                            if !stage_shuffle_ids.is_empty() {
-                                let final_shuffle_ids = &stage_shuffle_ids[0]; //TODO Can't assume first one
-                                let final_shuffle_ids = &final_shuffle_ids.shuffle_ids;
-                                if final_shuffle_ids.len() == 1 {
-                                    let data = ctx.read_shuffle(&final_shuffle_ids[0]).await?;
-                                    return Ok(data);
-                                } else {
-                                    return Err(ballista_error(&format!(
-                                        "final shuffle_ids len = {}",
-                                        final_shuffle_ids.len()
-                                    )));
+                        if let Some(e) = stage_error {
+                            if let BallistaError::Cancelled = e {
+                                return Err(e);
+                            }
+                            // the stage may have failed because its input was lost with an
+                            // executor, in which case the input is recomputed from the stages
+                            // that produced it and the stage runs again
+                            let live = ctx.get_executor_ids().await?;
+                            let lost = lost_shuffle_partitions(
+                                &stage.prior_stages,
+                                &shuffle_location_map,
+                                &live,
+                            );
+                            // executors that shut down gracefully may have handed off their
+                            // partitions to other executors, which need not be recomputed
+                            let num_lost = lost.len();
+                            let lost = relocate_shuffle_partitions(
+                                ctx.as_ref(),
+                                lost,
+                                &mut shuffle_location_map,
+                                &live,
+                            )
+                            .await;
+                            let relocated = num_lost - lost.len();
+                            // the output of stages of other jobs is not recomputed by those jobs,
+                            // so when it is lost this job runs the stages itself
+                            let lost_reused: Vec<usize> = stage
+                                .prior_stages
+                                .iter()
+                                .filter(|id| {
+                                    reused_stages.get(id).map_or(false, |locations| {
+                                        locations.iter().any(|loc| {
+                                            !live.iter().any(|e| e.id == loc.executor_meta.id)
+                                        })
+                                    })
+                                })
+                                .cloned()
+                                .collect();
+                            if lost.is_empty() && lost_reused.is_empty() {
+                                if relocated > 0 {
+                                    // run the stage again, reading the partitions from the
+                                    // executors that they were handed off to
+                                    continue;
                                 }
-                            } else {
-                                return Err(ballista_error(&format!(
-                                    "stage shuffle_ids len = {}",
-                                    stage_shuffle_ids.len()
+                                return Err(e);
+                            }
+                            if recomputations >= retry_policy.max_attempts {
+                                return Err(BallistaError::ExecutorLost(format!(
+                                    "{} shuffle partitions read by stage {} were lost with their \
+                                    executors after being recomputed {} times: {:?}",
+                                    lost.len(),
+                                    stage.id,
+                                    recomputations,
+                                    e
                                 )));
                             }
+                            recomputations += 1;
+                            for stage_id in &lost_reused {
+                                warn!(
+                                    "Running stage whose reused output was lost job_uuid={} stage_id={}",
+                                    job.id, stage_id
+                                );
+                                reused_stages.remove(stage_id);
+                                unshared_stages.insert(*stage_id);
+                                stage_status_map.insert(*stage_id, StageStatus::Pending);
+                            }
+                            if !lost_reused.is_empty() {
+                                // the plan of this stage reads the lost partitions
+                                stage_plans.remove(&stage.id);
+                            }
+                            for shuffle_id in &lost {
+                                warn!(
+                                    "Recomputing shuffle partition lost with its executor job_uuid={} stage_id={} partition_id={} executor_id={}",
+                                    job.id,
+                                    shuffle_id.stage_id,
+                                    shuffle_id.partition_id,
+                                    shuffle_location_map[shuffle_id].id
+                                );
+                                shuffle_location_map.remove(shuffle_id);
+                                stage_status_map.insert(shuffle_id.stage_id, StageStatus::Pending);
+                            }
+                            continue;
+                        }
+
+                        let stage_profile = StageProfile {
+                            stage_id: stage.id,
+                            plan: plan.clone(),
+                            duration_ms: stage_start.elapsed().as_millis() as u64,
+                            tasks: stage_results
+                                .iter()
+                                .flat_map(|s| s.metrics.iter().cloned())
+                                .collect(),
+                        };
+                        let stage_metrics = stage_profile.total();
+                        info!(
+                            "Stage completed job_uuid={} stage_id={} duration_ms={} shuffles={} rows={} shuffle_bytes={}",
+                            job.id,
+                            stage.id,
+                            stage_profile.duration_ms,
+                            stage_results.iter().map(|s| s.shuffle_ids.len()).sum::<usize>(),
+                            stage_metrics.output_rows,
+                            stage_metrics.shuffle_bytes
+                        );
+                        stage_span.set_attribute("rows", stage_metrics.output_rows);
+                        stage_span.set_attribute("shuffle_bytes", stage_metrics.shuffle_bytes);
+                        for op in &stage_metrics.operators {
+                            debug!(
+                                "Operator metrics job_uuid={} stage_id={} operator={} rows={} batches={} bytes={} elapsed_ms={}",
+                                job.id,
+                                stage.id,
+                                op.name,
+                                op.output_rows,
+                                op.output_batches,
+                                op.output_bytes,
+                                op.elapsed_ms
+                            );
+                        }
+                        profile.stages.push(stage_profile);
+                        if let Some(progress) = &progress {
+                            progress.complete_stage(stage.id);
+                        }
+
+                        stage_status_map.insert(stage.id, StageStatus::Completed);
+
+                        // the partitions are held for this job so that reading them does not
+                        // remove them before later jobs have read them too
+                        if let (Some(shared), Some(fingerprint)) = (&shared_stages, fingerprint) {
+                            let locations = stage_locations(&shuffle_location_map, stage.id);
+                            if retain_shuffles(ctx.as_ref(), job.id, &locations).await {
+                                shared.publish(fingerprint, locations);
+                            }
+                        }
+
+                        if let Some((store, record)) = &mut persisted {
+                            if let Some(stage_record) = record.stage_mut(stage.id) {
+                                stage_record.completed = true;
+                                stage_record.shuffle_locations =
+                                    stage_locations(&shuffle_location_map, stage.id);
+                            }
+                            save_job_record(store.as_ref(), record).await;
+                        }
+
+                        if stage.id == job.root_stage_id {
+                            let final_locations = stage_locations(&shuffle_location_map, stage.id);
+                            debug!("Final shuffle locations: {:?}", final_locations);
+                            return Ok((final_locations, profile));
                         }
                     } else {
-                        println!("Cannot run stage {} yet", stage.id);
+                        debug!(
+                            "Cannot run stage yet job_uuid={} stage_id={}",
+                            job.id, stage.id
+                        );
                     }
                 }
                 StageStatus::Completed => {
@@ -500,14 +1909,43 @@ pub async fn execute_job(job: &Job, ctx: Arc<dyn ExecutionContext>) -> Result<Ve
 }
 
 /// Convert a logical plan into a physical plan
-pub fn create_physical_plan(plan: &LogicalPlan) -> Result<Arc<PhysicalPlan>> {
+pub fn create_physical_plan(plan: &LogicalPlan, config: &JobConfig) -> Result<Arc<PhysicalPlan>> {
     match plan {
         LogicalPlan::Projection { input, expr, .. } => {
-            let exec = ProjectionExec::try_new(expr, create_physical_plan(input)?)?;
+            // each window function is evaluated by a window operator that appends a column to
+            // its input, which the projection then refers to
+            let mut input = create_physical_plan(input, config)?;
+            let mut projection = Vec::with_capacity(expr.len());
+            for e in expr {
+                if WindowExpr::is_window_expr(e) {
+                    let exec = WindowExec::try_new(input, WindowExpr::try_from_expr(e)?)?;
+                    projection.push(col_index(exec.schema().fields().len() - 1));
+                    input = Arc::new(PhysicalPlan::Window(Arc::new(exec)));
+                } else {
+                    projection.push(e.clone());
+                }
+            }
+            let exec = ProjectionExec::try_new(&projection, input)?;
             Ok(Arc::new(PhysicalPlan::Projection(Arc::new(exec))))
         }
         LogicalPlan::Selection { input, expr, .. } => {
-            let input = create_physical_plan(input)?;
+            let input = create_physical_plan(input, config)?;
+            // let the scan skip row groups that cannot match, the filter is still applied
+            let with_predicate = |plan: &Arc<PhysicalPlan>| match plan.as_ref() {
+                PhysicalPlan::ParquetScan(scan) => Some(Arc::new(PhysicalPlan::ParquetScan(
+                    Arc::new(scan.as_ref().clone().with_predicate(expr.clone())),
+                ))),
+                _ => None,
+            };
+            let input = match input.as_ref() {
+                PhysicalPlan::Repartition(exec) => match with_predicate(&exec.child) {
+                    Some(scan) => Arc::new(PhysicalPlan::Repartition(Arc::new(
+                        exec.with_new_children(vec![scan]),
+                    ))),
+                    None => input,
+                },
+                _ => with_predicate(&input).unwrap_or(input),
+            };
             let exec = FilterExec::new(&input, expr);
             Ok(Arc::new(PhysicalPlan::Filter(Arc::new(exec))))
         }
@@ -517,7 +1955,7 @@ pub fn create_physical_plan(plan: &LogicalPlan) -> Result<Arc<PhysicalPlan>> {
             aggr_expr,
             ..
         } => {
-            let input = create_physical_plan(input)?;
+            let input = create_physical_plan(input, config)?;
             if input
                 .as_execution_plan()
                 .output_partitioning()
@@ -531,7 +1969,11 @@ pub fn create_physical_plan(plan: &LogicalPlan) -> Result<Arc<PhysicalPlan>> {
                     input,
                 )?;
                 Ok(Arc::new(PhysicalPlan::HashAggregate(Arc::new(exec))))
+            } else if let Some(distinct_arg) = count_distinct_arg(aggr_expr)? {
+                create_distinct_aggregate(input, group_expr, aggr_expr, distinct_arg)
             } else {
+                let input_schema = input.as_execution_plan().schema();
+
                 // Create partial hash aggregate to run against partitions in parallel
                 let partial_hash_exec = HashAggregateExec::try_new(
                     AggregateMode::Partial,
@@ -541,76 +1983,190 @@ pub fn create_physical_plan(plan: &LogicalPlan) -> Result<Arc<PhysicalPlan>> {
                 )?;
                 let partial = Arc::new(PhysicalPlan::HashAggregate(Arc::new(partial_hash_exec)));
 
-                // Create final hash aggregate to run on the coalesced partition of the results
-                // from the partial hash aggregate
+                // Create final hash aggregate to merge the results of the partial hash
+                // aggregate. Grouped results are hash-partitioned on the grouping columns so that
+                // each partition is merged in parallel, while aggregates without groups are
+                // merged on a single partition.
 
                 let mut final_group = vec![];
                 for i in 0..group_expr.len() {
                     final_group.push(col_index(i as usize));
                 }
 
-                //TODO this is ugly and shows that the design needs revisiting here
-
+                // each final aggregate merges the intermediate state columns that the partial
+                // aggregate produces for it
                 let mut final_aggr = vec![];
-                for (i, expr) in aggr_expr.iter().enumerate() {
-                    let j = group_expr.len() + i;
-                    match expr {
-                        Expr::AggregateFunction { name, .. } => {
-                            let expr = match name.as_str() {
-                                "SUM" => sum(col_index(j)),
-                                "MIN" => min(col_index(j)),
-                                "MAX" => max(col_index(j)),
-                                "AVG" => avg(col_index(j)),
-                                "COUNT" => count(col_index(j)),
-                                _ => panic!(),
-                            };
-                            final_aggr.push(expr);
-                        }
-                        Expr::Alias(expr, alias) => match expr.as_ref() {
-                            Expr::AggregateFunction { name, .. } => {
-                                let expr = match name.as_str() {
-                                    "SUM" => sum(col_index(j)).alias(alias),
-                                    "MIN" => min(col_index(j)).alias(alias),
-                                    "MAX" => max(col_index(j)).alias(alias),
-                                    "AVG" => avg(col_index(j)).alias(alias),
-                                    "COUNT" => count(col_index(j)).alias(alias),
-                                    _ => panic!(),
-                                };
-                                final_aggr.push(expr);
-                            }
-                            _ => panic!(),
-                        },
-                        _ => panic!(),
-                    }
+                let mut j = group_expr.len();
+                for expr in aggr_expr {
+                    let num_state_fields = compile_aggregate_expression(expr, &input_schema)?
+                        .state_fields(&input_schema)?
+                        .len();
+                    let state: Vec<Expr> = (j..j + num_state_fields).map(col_index).collect();
+                    final_aggr.push(final_aggregate_expr(expr, state)?);
+                    j += num_state_fields;
                 }
 
-                let final_hash_exec = HashAggregateExec::try_new(
-                    AggregateMode::Final,
-                    final_group,
-                    final_aggr,
-                    partial,
-                )?;
+                let final_mode = if group_expr.is_empty() {
+                    AggregateMode::Final
+                } else {
+                    AggregateMode::FinalPartitioned
+                };
+                let final_hash_exec =
+                    HashAggregateExec::try_new(final_mode, final_group, final_aggr, partial)?;
                 Ok(Arc::new(PhysicalPlan::HashAggregate(Arc::new(
                     final_hash_exec,
                 ))))
             }
         }
         LogicalPlan::CsvScan {
-            path, projection, ..
+            path,
+            schema,
+            has_header,
+            delimiter,
+            projection,
+            ..
         } => {
-            //TODO make batch size and other csv options configurable from the context
-            let batch_size = 64 * 1024;
-            let options = CsvReadOptions::new();
-            let exec = CsvScanExec::try_new(&path, options, projection.clone(), batch_size)?;
-            Ok(Arc::new(PhysicalPlan::CsvScan(Arc::new(exec))))
+            let options = CsvReadOptions::new()
+                .schema(schema)
+                .has_header(*has_header)
+                .delimiter(delimiter.unwrap_or(b','));
+            let format = CsvFormatOptions::from_metadata(schema.metadata())?;
+            let exec = CsvScanExec::try_new(
+                &path,
+                options,
+                format,
+                projection.clone(),
+                config.batch_size,
+            )?;
+            Ok(repartition(
+                Arc::new(PhysicalPlan::CsvScan(Arc::new(exec))),
+                config,
+            ))
         }
         LogicalPlan::ParquetScan {
             path, projection, ..
         } => {
-            //TODO make batch size configurable from the context
-            let batch_size = 64 * 1024;
-            let exec = ParquetScanExec::try_new(&path, projection.clone(), batch_size)?;
-            Ok(Arc::new(PhysicalPlan::ParquetScan(Arc::new(exec))))
+            let exec = ParquetScanExec::try_new(&path, projection.clone(), config.batch_size)?;
+            Ok(repartition(
+                Arc::new(PhysicalPlan::ParquetScan(Arc::new(exec))),
+                config,
+            ))
+        }
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            table_schema,
+            projection,
+            ..
+        } if schema_name == JSON_SCHEMA_NAME => {
+            let exec = JsonScanExec::try_new(
+                &table_name,
+                Arc::new(table_schema.as_ref().clone()),
+                projection.clone(),
+                config.batch_size,
+            )?;
+            Ok(repartition(
+                Arc::new(PhysicalPlan::JsonScan(Arc::new(exec))),
+                config,
+            ))
+        }
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            projection,
+            ..
+        } if schema_name == AVRO_SCHEMA_NAME => {
+            let exec = AvroScanExec::try_new(&table_name, projection.clone(), config.batch_size)?;
+            Ok(repartition(
+                Arc::new(PhysicalPlan::AvroScan(Arc::new(exec))),
+                config,
+            ))
+        }
+        LogicalPlan::TableScan {
+            schema_name,
+            table_name,
+            projection,
+            ..
+        } if schema_name == ARROW_SCHEMA_NAME => {
+            let exec = IpcScanExec::try_new(&table_name, projection.clone())?;
+            Ok(repartition(
+                Arc::new(PhysicalPlan::IpcScan(Arc::new(exec))),
+                config,
+            ))
+        }
+        LogicalPlan::Sort { input, expr, .. } => {
+            let exec = SortExec::try_new(create_physical_plan(input, config)?, expr.clone())?;
+            Ok(Arc::new(PhysicalPlan::Sort(Arc::new(exec))))
+        }
+        LogicalPlan::Limit { n, input, .. } => {
+            if let LogicalPlan::Sort { input, expr, .. } = input.as_ref() {
+                // ORDER BY .. LIMIT n only needs the first n rows of each partition, so rather
+                // than sorting the whole input each partition is reduced to its top n rows
+                // before the partitions are merged
+                let input = create_physical_plan(input, config)?;
+                let input = if input
+                    .as_execution_plan()
+                    .output_partitioning()
+                    .partition_count()
+                    > 1
+                {
+                    let exec = TopKExec::try_new(input, expr.clone(), *n, true)?;
+                    Arc::new(PhysicalPlan::TopK(Arc::new(exec)))
+                } else {
+                    input
+                };
+                let exec = TopKExec::try_new(input, expr.clone(), *n, false)?;
+                return Ok(Arc::new(PhysicalPlan::TopK(Arc::new(exec))));
+            }
+
+            let input = create_physical_plan(input, config)?;
+            let input = if input
+                .as_execution_plan()
+                .output_partitioning()
+                .partition_count()
+                > 1
+            {
+                let exec = LocalLimitExec::new(input, *n);
+                Arc::new(PhysicalPlan::LocalLimit(Arc::new(exec)))
+            } else {
+                input
+            };
+            let exec = GlobalLimitExec::new(input, *n);
+            Ok(Arc::new(PhysicalPlan::GlobalLimit(Arc::new(exec))))
+        }
+        LogicalPlan::Extension { .. } => {
+            if let Some(union) = UnionNode::from_plan(plan) {
+                return create_union(union, config);
+            }
+            let join = JoinNode::from_plan(plan).ok_or_else(|| {
+                BallistaError::General(format!(
+                    "create_physical_plan unsupported operator {:?}",
+                    plan
+                ))
+            })?;
+            let left = create_physical_plan(&join.left, config)?;
+            let right = create_physical_plan(&join.right, config)?;
+            // both inputs are shuffled into this many partitions by ensure_requirements, unless
+            // the left input is small enough to broadcast
+            let partition_count = config.target_partitions.unwrap_or_else(|| {
+                [&left, &right]
+                    .iter()
+                    .map(|p| {
+                        p.as_execution_plan()
+                            .output_partitioning()
+                            .partition_count()
+                    })
+                    .max()
+                    .unwrap_or(1)
+            });
+            let exec = HashJoinExec::try_new(
+                left,
+                right,
+                join.on.clone(),
+                join.join_type.clone(),
+                partition_count,
+            )?;
+            Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(exec))))
         }
         other => Err(BallistaError::General(format!(
             "create_physical_plan unsupported operator {:?}",
@@ -619,8 +2175,210 @@ pub fn create_physical_plan(plan: &LogicalPlan) -> Result<Arc<PhysicalPlan>> {
     }
 }
 
-/// Optimizer rule to insert shuffles as needed
-pub fn ensure_requirements(plan: &PhysicalPlan) -> Result<Arc<PhysicalPlan>> {
+/// Concatenate the partitions of the inputs of a union. Duplicate rows are removed from a
+/// union that is not UNION ALL by grouping its rows on all of their columns.
+fn create_union(union: &UnionNode, config: &JobConfig) -> Result<Arc<PhysicalPlan>> {
+    if !union.all {
+        let union_all = UnionNode::try_new(union.inputs.clone(), true)?.into_plan();
+        let group_expr = (0..union_all.schema().fields().len())
+            .map(col_index)
+            .collect();
+        let distinct = LogicalPlanBuilder::from(&union_all)
+            .aggregate(group_expr, vec![])?
+            .build()?;
+        return create_physical_plan(&distinct, config);
+    }
+    let inputs = union
+        .inputs
+        .iter()
+        .map(|input| create_physical_plan(input, config))
+        .collect::<Result<Vec<_>>>()?;
+    let exec = UnionExec::try_new(inputs)?;
+    Ok(Arc::new(PhysicalPlan::Union(Arc::new(exec))))
+}
+
+/// Redistribute the output of a scan into the target number of partitions, if one is configured
+/// and differs from the number of files or file splits that the scan reads
+fn repartition(scan: Arc<PhysicalPlan>, config: &JobConfig) -> Arc<PhysicalPlan> {
+    match config.target_partitions {
+        Some(n)
+            if n != scan
+                .as_execution_plan()
+                .output_partitioning()
+                .partition_count() =>
+        {
+            Arc::new(PhysicalPlan::Repartition(Arc::new(RepartitionExec::new(
+                scan, n,
+            ))))
+        }
+        _ => scan,
+    }
+}
+
+/// Joins whose left input is estimated to be no larger than this many bytes broadcast the left
+/// input to every task of the right input instead of repartitioning both inputs
+pub const BROADCAST_JOIN_THRESHOLD: u64 = 10 * 1024 * 1024;
+
+/// Estimate the size in bytes of the output of a plan from the sizes of the files that it scans.
+/// The estimate is an upper bound since filters and projections are assumed not to reduce the
+/// size. Returns `None` when the size cannot be estimated.
+pub fn estimated_size(plan: &PhysicalPlan) -> Option<u64> {
+    let file_size = |filenames: &[String]| {
+        filenames
+            .iter()
+            .map(|f| object_store::file_size(f).ok())
+            .sum::<Option<u64>>()
+    };
+    match plan {
+        PhysicalPlan::CsvScan(exec) => file_size(&exec.filenames),
+        PhysicalPlan::ParquetScan(exec) => file_size(&exec.filenames),
+        PhysicalPlan::JsonScan(exec) => Some(exec.splits.iter().map(|s| s.end - s.start).sum()),
+        PhysicalPlan::AvroScan(exec) => Some(exec.splits.iter().map(|s| s.end - s.start).sum()),
+        PhysicalPlan::IpcScan(exec) => file_size(&exec.filenames),
+        PhysicalPlan::Filter(exec) => estimated_size(&exec.child),
+        PhysicalPlan::Projection(exec) => estimated_size(&exec.child),
+        PhysicalPlan::Repartition(exec) => estimated_size(&exec.child),
+        PhysicalPlan::Union(exec) => exec.inputs.iter().map(|input| estimated_size(input)).sum(),
+        _ => None,
+    }
+}
+
+/// Create the final aggregate expression for `expr` that merges the given intermediate state
+/// columns. Literal arguments, such as the percentile of APPROX_PERCENTILE, are passed through.
+fn final_aggregate_expr(expr: &Expr, state: Vec<Expr>) -> Result<Expr> {
+    match expr {
+        Expr::Alias(expr, alias) => Ok(final_aggregate_expr(expr, state)?.alias(alias)),
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => {
+            let args: Vec<Expr> = state
+                .into_iter()
+                .chain(
+                    args.iter()
+                        .skip(1)
+                        .filter(|e| matches!(e, Expr::Literal(_)))
+                        .cloned(),
+                )
+                .collect();
+            if args.is_empty() {
+                return Err(ballista_error(&format!(
+                    "Aggregate function {} has no intermediate state",
+                    name
+                )));
+            }
+            Ok(Expr::AggregateFunction {
+                name: name.clone(),
+                args,
+                return_type: return_type.clone(),
+            })
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Final aggregate for {:?}",
+            other
+        ))),
+    }
+}
+
+/// Returns the argument of the COUNT(DISTINCT) aggregates in `aggr_expr`, if there are any.
+/// Distinct aggregates can currently only be planned when every aggregate in the query is a
+/// COUNT(DISTINCT) of the same expression.
+fn count_distinct_arg(aggr_expr: &[Expr]) -> Result<Option<Expr>> {
+    let args: Vec<Option<&Expr>> = aggr_expr
+        .iter()
+        .map(|e| match e {
+            Expr::Alias(e, _) => e.as_ref(),
+            other => other,
+        })
+        .map(|e| match e {
+            Expr::AggregateFunction { name, args, .. } if name == "COUNT_DISTINCT" => {
+                Some(&args[0])
+            }
+            _ => None,
+        })
+        .collect();
+    if args.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+    match args[0] {
+        Some(arg) if args.iter().all(|a| *a == Some(arg)) => Ok(Some(arg.clone())),
+        _ => Err(BallistaError::NotImplemented(
+            "COUNT(DISTINCT) combined with other aggregate expressions".to_owned(),
+        )),
+    }
+}
+
+/// Plan an aggregate query containing COUNT(DISTINCT x) against a partitioned input. Each
+/// partition is first de-duplicated on the grouping keys plus `x`, and the results are then
+/// hash-partitioned on the grouping keys so that all distinct values for a group are counted
+/// by the same task, without any single task having to hold the whole dataset.
+fn create_distinct_aggregate(
+    input: Arc<PhysicalPlan>,
+    group_expr: &[Expr],
+    aggr_expr: &[Expr],
+    distinct_arg: Expr,
+) -> Result<Arc<PhysicalPlan>> {
+    let mut dedup_group = group_expr.to_vec();
+    dedup_group.push(distinct_arg);
+    let dedup = HashAggregateExec::try_new(AggregateMode::Partial, dedup_group, vec![], input)?;
+    let dedup = Arc::new(PhysicalPlan::HashAggregate(Arc::new(dedup)));
+
+    let with_alias = |expr: &Expr, aggr: Expr| match expr {
+        Expr::Alias(_, alias) => aggr.alias(alias),
+        _ => aggr,
+    };
+
+    let num_groups = group_expr.len();
+    if num_groups > 0 {
+        let final_group: Vec<Expr> = (0..num_groups).map(col_index).collect();
+        let final_aggr: Vec<Expr> = aggr_expr
+            .iter()
+            .map(|e| with_alias(e, count_distinct(col_index(num_groups))))
+            .collect();
+        let exec = HashAggregateExec::try_new(
+            AggregateMode::FinalPartitioned,
+            final_group,
+            final_aggr,
+            dedup,
+        )?;
+        Ok(Arc::new(PhysicalPlan::HashAggregate(Arc::new(exec))))
+    } else {
+        // without grouping keys, the distinct values are de-duplicated across partitions by
+        // hash-partitioning on the values themselves, and the counts of each partition are
+        // then summed
+        let distinct = HashAggregateExec::try_new(
+            AggregateMode::FinalPartitioned,
+            vec![col_index(0)],
+            vec![],
+            dedup,
+        )?;
+        let partial = HashAggregateExec::try_new(
+            AggregateMode::Partial,
+            vec![],
+            vec![count(col_index(0))],
+            Arc::new(PhysicalPlan::HashAggregate(Arc::new(distinct))),
+        )?;
+        let final_aggr: Vec<Expr> = aggr_expr
+            .iter()
+            .map(|e| with_alias(e, count(col_index(0))))
+            .collect();
+        let exec = HashAggregateExec::try_new(
+            AggregateMode::Final,
+            vec![],
+            final_aggr,
+            Arc::new(PhysicalPlan::HashAggregate(Arc::new(partial))),
+        )?;
+        Ok(Arc::new(PhysicalPlan::HashAggregate(Arc::new(exec))))
+    }
+}
+
+/// Optimizer rule to insert shuffles as needed. Joins are planned from the estimated sizes of
+/// their inputs, which are based on the given statistics and on Parquet metadata.
+pub fn ensure_requirements(
+    plan: &PhysicalPlan,
+    statistics: &StatisticsCatalog,
+) -> Result<Arc<PhysicalPlan>> {
     let execution_plan = plan.as_execution_plan();
 
     // recurse down and replace children
@@ -630,7 +2388,7 @@ pub fn ensure_requirements(plan: &PhysicalPlan) -> Result<Arc<PhysicalPlan>> {
     let children: Vec<Arc<PhysicalPlan>> = execution_plan
         .children()
         .iter()
-        .map(|c| ensure_requirements(c.as_ref()))
+        .map(|c| ensure_requirements(c.as_ref(), statistics))
         .collect::<Result<Vec<_>>>()?;
 
     match execution_plan.required_child_distribution() {
@@ -657,10 +2415,145 @@ pub fn ensure_requirements(plan: &PhysicalPlan) -> Result<Arc<PhysicalPlan>> {
 
             Ok(Arc::new(plan.with_new_children(new_children)))
         }
-        _ => Ok(Arc::new(plan.clone())),
+        Distribution::HashClusteredDistribution {
+            required_num_partitions,
+            clustering,
+        } => match plan {
+            PhysicalPlan::HashJoin(exec) => {
+                let left_bytes = estimate_statistics(&children[0], statistics).total_bytes;
+                let right_bytes = estimate_statistics(&children[1], statistics).total_bytes;
+                match (exec.build_size_hint, left_bytes, right_bytes) {
+                    (None, Some(left_bytes), Some(right_bytes)) if right_bytes < left_bytes => {
+                        // the left input is loaded into memory, and is the one that can be
+                        // broadcast, so it should be the smaller input
+                        debug!(
+                            "Swapping join inputs left_bytes={} right_bytes={}",
+                            left_bytes, right_bytes
+                        );
+                        let left_columns = children[0].as_execution_plan().schema().fields().len();
+                        let right_columns = children[1].as_execution_plan().schema().fields().len();
+                        let join = plan_hash_join(
+                            &exec.swap_inputs()?,
+                            &[children[1].clone(), children[0].clone()],
+                            required_num_partitions,
+                            Some(right_bytes),
+                        )?;
+                        // restore the order of the columns of the join
+                        let expr: Vec<Expr> = (right_columns..right_columns + left_columns)
+                            .chain(0..right_columns)
+                            .map(col_index)
+                            .collect();
+                        let exec = ProjectionExec::try_new(&expr, join)?;
+                        Ok(Arc::new(PhysicalPlan::Projection(Arc::new(exec))))
+                    }
+                    (build_size_hint, left_bytes, _) => plan_hash_join(
+                        exec,
+                        &children,
+                        required_num_partitions,
+                        build_size_hint.or(left_bytes),
+                    ),
+                }
+            }
+            PhysicalPlan::Window(_) | PhysicalPlan::HashAggregate(_) => {
+                // the input only needs to be shuffled if it is not already hash-partitioned on
+                // the clustering keys, such as by a window with the same partition keys
+                let keys: Vec<Arc<Expr>> = clustering.into_iter().map(Arc::new).collect();
+                let child = &children[0];
+                let child = match child.as_execution_plan().output_partitioning() {
+                    Partitioning::HashPartitioning(_, child_keys) if child_keys == keys => {
+                        child.clone()
+                    }
+                    _ => Arc::new(PhysicalPlan::ShuffleExchange(Arc::new(
+                        ShuffleExchangeExec::new(
+                            child.clone(),
+                            Partitioning::HashPartitioning(required_num_partitions, keys),
+                        ),
+                    ))),
+                };
+                Ok(Arc::new(plan.with_new_children(vec![child])))
+            }
+            _ => Err(BallistaError::NotImplemented(format!(
+                "ensure_requirements hash clustered distribution for {}",
+                plan.name()
+            ))),
+        },
+        Distribution::OrderedDistribution(_) => match plan {
+            PhysicalPlan::Sort(exec) => {
+                // range-partition the input on the sort keys so that sorting each partition
+                // produces partitions that are ordered relative to each other
+                let sort_expr: Vec<Arc<Expr>> =
+                    exec.sort_expr.iter().map(|e| Arc::new(e.clone())).collect();
+                let new_children: Vec<Arc<PhysicalPlan>> = children
+                    .iter()
+                    .map(|c| {
+                        let partition_count = c
+                            .as_execution_plan()
+                            .output_partitioning()
+                            .partition_count();
+                        if partition_count > 1 {
+                            Arc::new(PhysicalPlan::ShuffleExchange(Arc::new(
+                                ShuffleExchangeExec::new(
+                                    c.clone(),
+                                    Partitioning::RangePartitioning(
+                                        partition_count,
+                                        sort_expr.clone(),
+                                    ),
+                                ),
+                            )))
+                        } else {
+                            c.clone()
+                        }
+                    })
+                    .collect();
+                Ok(Arc::new(plan.with_new_children(new_children)))
+            }
+            _ => Ok(Arc::new(plan.with_new_children(children))),
+        },
+        _ => Ok(Arc::new(plan.with_new_children(children))),
     }
 }
 
+/// Plan a hash join of two inputs that have had their requirements ensured, either by
+/// broadcasting the left input, if it is small enough, or by hash-partitioning both inputs
+fn plan_hash_join(
+    exec: &HashJoinExec,
+    children: &[Arc<PhysicalPlan>],
+    required_num_partitions: usize,
+    build_size: Option<u64>,
+) -> Result<Arc<PhysicalPlan>> {
+    match build_size {
+        Some(size) if exec.can_broadcast() && size <= BROADCAST_JOIN_THRESHOLD => {
+            debug!("Broadcasting join input estimated_bytes={}", size);
+            // the left input runs once as a separate stage and every task of the join reads
+            // all of its output, so the right input is not shuffled
+            let left = Arc::new(PhysicalPlan::ShuffleExchange(Arc::new(
+                ShuffleExchangeExec::new(children[0].clone(), Partitioning::UnknownPartitioning(1)),
+            )));
+            let exec = exec.to_broadcast(left, children[1].clone())?;
+            return Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(exec))));
+        }
+        _ => {}
+    }
+
+    // hash-partition both inputs on their own join keys
+    let keys = vec![exec.left_keys()?, exec.right_keys()?];
+    let new_children: Vec<Arc<PhysicalPlan>> = children
+        .iter()
+        .zip(keys)
+        .map(|(c, keys)| {
+            Arc::new(PhysicalPlan::ShuffleExchange(Arc::new(
+                ShuffleExchangeExec::new(
+                    c.clone(),
+                    Partitioning::HashPartitioning(required_num_partitions, keys),
+                ),
+            )))
+        })
+        .collect();
+    Ok(Arc::new(PhysicalPlan::HashJoin(Arc::new(
+        exec.with_new_children(new_children),
+    ))))
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -677,7 +2570,7 @@ pub fn ensure_requirements(plan: &PhysicalPlan) -> Result<Arc<PhysicalPlan>> {
 //
 //         let plan = df.logical_plan();
 //
-//         let plan = create_physical_plan(&plan)?;
+//         let plan = create_physical_plan(&plan, &JobConfig::default())?;
 //         let plan = ensure_requirements(&plan)?;
 //         println!("Optimized physical plan:\n{:?}", plan);
 //
@@ -688,3 +2581,317 @@ pub fn ensure_requirements(plan: &PhysicalPlan) -> Result<Arc<PhysicalPlan>> {
 //         Ok(())
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::distributed::executor::{DiscoveryMode, ExecutorConfig};
+    use crate::distributed::placement::RoundRobinPlacement;
+    use crate::distributed::scheduling::DEFAULT_TENANT;
+    use crate::execution::physical_plan::{ColumnarBatch, MetricsCollector, TaskUpdateStream};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(3, Duration::from_millis(100), Duration::from_millis(350))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = policy();
+        assert_eq!(Duration::from_millis(100), policy.backoff(1));
+        assert_eq!(Duration::from_millis(200), policy.backoff(2));
+        assert_eq!(Duration::from_millis(350), policy.backoff(3));
+        assert_eq!(Duration::from_millis(350), policy.backoff(100));
+    }
+
+    #[test]
+    fn transient_errors_are_retryable() {
+        assert!(is_retryable(&BallistaError::General(
+            "transport error".to_owned()
+        )));
+        assert!(is_retryable(&BallistaError::ExecutorLost(
+            "executor left".to_owned()
+        )));
+        assert!(is_retryable(&BallistaError::ResourcesExhausted(
+            "SortExec could not reserve 10 bytes".to_owned()
+        )));
+        assert!(!is_retryable(&BallistaError::PlanError(
+            "unknown table t".to_owned()
+        )));
+        assert!(!is_retryable(&BallistaError::SchemaMismatch(
+            "expected 2 columns".to_owned()
+        )));
+        assert!(!is_retryable(&BallistaError::NotImplemented(
+            "ROLLUP".to_owned()
+        )));
+        assert!(!is_retryable(&BallistaError::Cancelled));
+    }
+
+    #[test]
+    fn retries_on_another_executor() {
+        let policy = policy();
+        let error = BallistaError::General("transport error".to_owned());
+        assert_eq!(
+            Some((2, Duration::from_millis(100))),
+            policy.next_attempt(&error, 1, 1, 3)
+        );
+        assert_eq!(
+            Some((0, Duration::from_millis(200))),
+            policy.next_attempt(&error, 2, 2, 3)
+        );
+        // a single executor is retried
+        assert_eq!(
+            Some((0, Duration::from_millis(100))),
+            policy.next_attempt(&error, 1, 0, 1)
+        );
+    }
+
+    #[test]
+    fn stops_retrying_after_max_attempts() {
+        let policy = policy();
+        let error = BallistaError::General("transport error".to_owned());
+        assert!(policy.next_attempt(&error, 2, 0, 2).is_some());
+        assert_eq!(None, policy.next_attempt(&error, 3, 0, 2));
+        let error = BallistaError::PlanError("unknown table t".to_owned());
+        assert_eq!(None, policy.next_attempt(&error, 1, 0, 2));
+    }
+
+    #[test]
+    fn cancel_job_when_timeout_passes() {
+        let cancellation_token = CancellationToken::new();
+        let timeout = JobTimeout::new(cancellation_token.clone(), Some(Duration::from_millis(10)));
+        let result: Result<()> = smol::run(timeout.run(async {
+            while !cancellation_token.is_cancelled() {
+                Timer::after(Duration::from_millis(1)).await;
+            }
+            Err(BallistaError::Cancelled)
+        }));
+        match result {
+            Err(BallistaError::DeadlineExceeded(msg)) => {
+                assert_eq!("Job did not complete within its timeout of 10 ms", msg)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn executor_meta(id: &str) -> ExecutorMeta {
+        ExecutorMeta {
+            id: id.to_owned(),
+            host: "localhost".to_owned(),
+            port: 50051,
+        }
+    }
+
+    /// Context whose executors complete tasks without running them, except that the second
+    /// executor leaves the cluster as soon as the tasks of the given stage start, and the tasks
+    /// whose input was held by an executor that has left fail to fetch it
+    struct LosingExecutorContext {
+        config: ExecutorConfig,
+        losing_stage_id: usize,
+        live: Mutex<Vec<ExecutorMeta>>,
+        /// Stage id, partition id and executor id of each task that ran
+        tasks: Mutex<Vec<(usize, usize, String)>>,
+    }
+
+    impl LosingExecutorContext {
+        fn new(losing_stage_id: usize) -> Self {
+            let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "")
+                .with_retry_policy(RetryPolicy::new(
+                    1,
+                    Duration::from_millis(1),
+                    Duration::from_millis(1),
+                ))
+                .with_placement_policy(Arc::new(RoundRobinPlacement::default()))
+                .with_job_config(
+                    JobConfig::default()
+                        .with_adaptive_execution(false)
+                        .with_work_stealing(false),
+                );
+            Self {
+                config,
+                losing_stage_id,
+                live: Mutex::new(vec![
+                    executor_meta("executor-1"),
+                    executor_meta("executor-2"),
+                ]),
+                tasks: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ExecutionContext for LosingExecutorContext {
+        async fn get_executor_ids(&self) -> Result<Vec<ExecutorMeta>> {
+            Ok(self.live.lock().unwrap().clone())
+        }
+
+        async fn execute_task(
+            &self,
+            executor_id: ExecutorMeta,
+            task: ExecutionTask,
+        ) -> Result<(ShuffleId, TaskMetrics)> {
+            let mut live = self.live.lock().unwrap();
+            if task.stage_id == self.losing_stage_id {
+                live.retain(|e| e.id != "executor-2");
+            }
+            let lost_input = task
+                .shuffle_locations
+                .values()
+                .any(|executor| !live.iter().any(|e| e.id == executor.id));
+            if lost_input || !live.iter().any(|e| e.id == executor_id.id) {
+                return Err(ballista_error("Failed to fetch shuffle partition"));
+            }
+            self.tasks.lock().unwrap().push((
+                task.stage_id,
+                task.partition_id,
+                executor_id.id.clone(),
+            ));
+            Ok((
+                ShuffleId::new(task.job_uuid, task.stage_id, task.partition_id),
+                TaskMetrics::default(),
+            ))
+        }
+
+        async fn read_shuffle(&self, _shuffle_id: &ShuffleId) -> Result<Vec<ColumnarBatch>> {
+            Ok(vec![])
+        }
+
+        async fn cancel_task(
+            &self,
+            _executor_id: ExecutorMeta,
+            _job_uuid: Uuid,
+            _stage_id: usize,
+            _partition_id: usize,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn withdraw_task(
+            &self,
+            _executor_id: ExecutorMeta,
+            _job_uuid: Uuid,
+            _stage_id: usize,
+            _partition_id: usize,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn release_job(&self, _executor_id: ExecutorMeta, _job_uuid: Uuid) -> Result<()> {
+            Ok(())
+        }
+
+        async fn retain_shuffles(
+            &self,
+            _executor_id: ExecutorMeta,
+            _job_uuid: Uuid,
+            _shuffle_ids: Vec<ShuffleId>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn watch_tasks(
+            &self,
+            _executor_id: ExecutorMeta,
+            _job_uuid: Uuid,
+        ) -> Result<TaskUpdateStream> {
+            Err(ballista_error("Task updates are not pushed"))
+        }
+
+        async fn executor_capacity(&self, _executor_id: ExecutorMeta) -> Result<TaskResources> {
+            Err(ballista_error("Executor capacity is not advertised"))
+        }
+
+        async fn held_shuffles(&self, _executor_id: ExecutorMeta) -> Result<Vec<ShuffleId>> {
+            Ok(vec![])
+        }
+
+        fn config(&self) -> ExecutorConfig {
+            self.config.clone()
+        }
+
+        fn cancellation_token(&self) -> CancellationToken {
+            CancellationToken::new()
+        }
+
+        fn metrics(&self) -> MetricsCollector {
+            MetricsCollector::new()
+        }
+
+        fn task_memory(&self) -> TaskMemory {
+            TaskMemory::unbounded(std::env::temp_dir())
+        }
+
+        fn parallelism(&self) -> usize {
+            1
+        }
+
+        fn job_state_store(&self) -> Option<Arc<dyn JobStateStore>> {
+            None
+        }
+
+        fn job_progress(&self) -> Option<ProgressTracker> {
+            None
+        }
+
+        fn tenant(&self) -> String {
+            DEFAULT_TENANT.to_owned()
+        }
+
+        fn trace_context(&self) -> Option<TraceContext> {
+            None
+        }
+    }
+
+    #[test]
+    fn recompute_partitions_lost_after_stage_completes() -> Result<()> {
+        let job_uuid = Uuid::new_v4();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let stage = |id, prior_stages: Vec<usize>| {
+            let reader = ShuffleReaderExec::new(
+                schema.clone(),
+                vec![
+                    ShuffleId::new(job_uuid, id - 1, 0),
+                    ShuffleId::new(job_uuid, id - 1, 1),
+                ],
+            )
+            .with_partitioning(Partitioning::UnknownPartitioning(2));
+            Rc::new(RefCell::new(Stage {
+                id,
+                prior_stages,
+                plan: Some(Arc::new(PhysicalPlan::ShuffleReader(Arc::new(reader)))),
+            }))
+        };
+        let job = Job {
+            id: job_uuid,
+            stages: vec![stage(1, vec![]), stage(2, vec![1])],
+            root_stage_id: 2,
+        };
+        let ctx = Arc::new(LosingExecutorContext::new(2));
+
+        let (locations, _) = smol::run(execute_job(&job, ctx.clone()))?;
+
+        // the partition of the first stage that was held by the lost executor is computed
+        // again on the remaining executor before the second stage runs again
+        let tasks = ctx.tasks.lock().unwrap().clone();
+        let mut stage_1_tasks: Vec<(usize, String)> = tasks
+            .iter()
+            .filter(|(stage_id, _, _)| *stage_id == 1)
+            .map(|(_, partition_id, executor_id)| (*partition_id, executor_id.clone()))
+            .collect();
+        assert_eq!(3, stage_1_tasks.len());
+        assert_eq!((1, "executor-1".to_owned()), stage_1_tasks[2]);
+        stage_1_tasks.truncate(2);
+        stage_1_tasks.sort();
+        assert_eq!(
+            vec![(0, "executor-1".to_owned()), (1, "executor-2".to_owned())],
+            stage_1_tasks
+        );
+        assert_eq!(2, locations.len());
+        assert!(locations
+            .iter()
+            .all(|loc| loc.executor_meta.id == "executor-1" && loc.shuffle_id.stage_id == 2));
+        Ok(())
+    }
+}
@@ -0,0 +1,1346 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! gRPC service for a standalone scheduler that owns the state of the jobs running in a
+//! cluster, so that executors only need to run tasks.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::arrow::datatypes::Schema;
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::auth::CLUSTER_IDENTITY;
+use crate::distributed::authorization::{authorize_plan, Authorizer};
+use crate::distributed::catalog::{file_scan_plan, StatisticsCatalog, TableCatalog};
+use crate::distributed::client::executor_stats;
+use crate::distributed::column_pruning::prune_columns;
+use crate::distributed::discovery::{create_discovery_backend, DiscoveryBackend};
+use crate::distributed::executor::{optimize_logical_plan, DefaultContext, ExecutorConfig};
+use crate::distributed::job_history::{JobHistory, JobHistoryEntry, JobHistoryFilter};
+use crate::distributed::job_state::{InMemoryJobStateStore, JobRecord, JobStateStore};
+use crate::distributed::plan_validation::PlanValidator;
+use crate::distributed::progress::{JobProgress, ProgressTracker, TaskProgress};
+use crate::distributed::result_cache::{plan_fingerprint, ResultCache};
+use crate::distributed::scheduler::{
+    create_job, create_physical_plan, ensure_requirements, execute_job, Job, JobConfig, JobTimeout,
+    QuerySettings,
+};
+use crate::distributed::scheduling::tenant_of;
+use crate::distributed::session::{session_id_of, Session, Sessions, DEFAULT_MAX_SESSIONS};
+use crate::distributed::stage_reuse::retain_shuffles;
+use crate::distributed::status::to_status;
+use crate::distributed::table_store::{InMemoryTableStore, TableDefinition, TableStore};
+use crate::distributed::tracing::{trace_context_of, Span, TraceContext};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::physical_plan::{CancellationToken, ExecutorMeta, ShuffleLocation};
+use crate::protobuf;
+use crate::protobuf::scheduler_grpc_server::SchedulerGrpc;
+use crate::utils::expiring_map::ExpiringMap;
+
+use async_trait::async_trait;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::{SinkExt, Stream, StreamExt};
+use log::{error, info, warn};
+use smol::Task;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+/// Default time after which the status of a finished job is discarded
+pub const DEFAULT_JOB_STATUS_TTL: Duration = Duration::from_secs(3600);
+
+/// Default maximum number of finished jobs to retain
+pub const DEFAULT_MAX_JOB_STATUSES: usize = 1000;
+
+/// Default time between the statuses streamed to a client watching a job
+pub const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Stream of the statuses of a job, sent to a client watching the job
+pub type JobStatusStream =
+    Pin<Box<dyn Stream<Item = Result<protobuf::JobStatus, Status>> + Send + Sync + 'static>>;
+
+/// State of a job in the scheduler
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobState {
+    Queued,
+    Running,
+    /// The job completed and its results are in the given partitions
+    Completed(Vec<ShuffleLocation>),
+    Failed(String),
+    Cancelled,
+}
+
+impl JobState {
+    fn is_finished(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    pub job_uuid: Uuid,
+    pub state: JobState,
+    pub submitted_at: SystemTime,
+    /// Progress of the stages of the job, as of when the status was read
+    pub progress: JobProgress,
+}
+
+struct JobEntry {
+    status: JobStatus,
+    /// Tenant that submitted the job, which is the only tenant that may see and cancel it
+    tenant: String,
+    cancellation_token: CancellationToken,
+    progress: ProgressTracker,
+}
+
+impl JobEntry {
+    fn status(&self) -> JobStatus {
+        JobStatus {
+            progress: self.progress.snapshot(),
+            ..self.status.clone()
+        }
+    }
+}
+
+/// Starts a job on the thread that runs it
+type StartJob = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()>>> + Send>;
+
+/// Thread that runs the jobs of a scheduler as tasks, which wait on executors without blocking
+/// each other. Jobs cannot be sent between threads, so each job is created on this thread by
+/// the closure that starts it.
+#[derive(Clone)]
+struct JobRunner {
+    jobs: UnboundedSender<StartJob>,
+}
+
+impl JobRunner {
+    fn new() -> Self {
+        let (tx, mut rx) = unbounded::<StartJob>();
+        thread::spawn(move || {
+            smol::run(async move {
+                // the channel closes once the scheduler and all of its jobs are dropped
+                while let Some(start) = rx.next().await {
+                    Task::local(start()).detach();
+                }
+            })
+        });
+        Self { jobs: tx }
+    }
+
+    fn spawn<F, J>(&self, start: F)
+    where
+        F: FnOnce() -> J + Send + 'static,
+        J: Future<Output = ()> + 'static,
+    {
+        let start: StartJob = Box::new(move || Box::pin(start()));
+        if self.jobs.unbounded_send(start).is_err() {
+            error!("Failed to start job because the job thread stopped");
+        }
+    }
+}
+
+/// Scheduler that plans jobs and runs them against the executors in the cluster
+#[derive(Clone)]
+pub struct SchedulerServer {
+    config: ExecutorConfig,
+    discovery: Arc<dyn DiscoveryBackend>,
+    /// Jobs keyed by job UUID. Finished jobs are evicted once they expire or the map is full.
+    jobs: Arc<Mutex<ExpiringMap<JobEntry>>>,
+    job_state_store: Arc<dyn JobStateStore>,
+    /// Sessions of clients, with the tables that they have registered
+    sessions: Arc<Sessions>,
+    /// External tables that all clients can scan
+    table_store: Arc<dyn TableStore>,
+    /// Results of recent jobs that are served to repeated queries, when enabled
+    result_cache: Option<Arc<ResultCache>>,
+    /// Queries and metrics of the jobs that have finished
+    history: Arc<JobHistory>,
+    /// Authorizer of the scans of jobs and views, or None if authorization is disabled
+    authorizer: Option<Arc<dyn Authorizer>>,
+    /// Validator of the paths that tables are registered and created with
+    plan_validator: Arc<PlanValidator>,
+    /// Identities that may create and drop the external tables that all clients can scan
+    admins: Vec<String>,
+    runner: JobRunner,
+}
+
+impl SchedulerServer {
+    pub fn new(config: ExecutorConfig) -> Self {
+        let discovery = create_discovery_backend(&config);
+        Self {
+            config,
+            discovery,
+            jobs: Arc::new(Mutex::new(ExpiringMap::new(
+                DEFAULT_JOB_STATUS_TTL,
+                DEFAULT_MAX_JOB_STATUSES,
+                |entry: &JobEntry| entry.status.state.is_finished(),
+            ))),
+            job_state_store: Arc::new(InMemoryJobStateStore::default()),
+            sessions: Arc::new(Sessions::default()),
+            table_store: Arc::new(InMemoryTableStore::default()),
+            result_cache: None,
+            history: Arc::new(JobHistory::default()),
+            authorizer: None,
+            plan_validator: Arc::new(PlanValidator::new()),
+            admins: vec![CLUSTER_IDENTITY.to_owned()],
+            runner: JobRunner::new(),
+        }
+    }
+
+    /// Persist the state of jobs in the given store rather than in memory
+    pub fn with_job_state_store(mut self, job_state_store: Arc<dyn JobStateStore>) -> Self {
+        self.job_state_store = job_state_store;
+        self
+    }
+
+    /// Close sessions once they have not been used for the given time
+    pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+        self.sessions = Arc::new(Sessions::new(ttl, DEFAULT_MAX_SESSIONS));
+        self
+    }
+
+    /// Persist the definitions of external tables in the given store rather than in memory
+    pub fn with_table_store(mut self, table_store: Arc<dyn TableStore>) -> Self {
+        self.table_store = table_store;
+        self
+    }
+
+    /// Complete jobs that repeat a query of unchanged files within the given time of an
+    /// earlier job with the results of that job
+    pub fn with_result_cache(mut self, ttl: Duration) -> Self {
+        self.result_cache = Some(Arc::new(ResultCache::new(ttl)));
+        self
+    }
+
+    /// Keep the queries and metrics of up to the given number of finished jobs
+    pub fn with_job_history(mut self, max_entries: usize) -> Self {
+        self.history = Arc::new(JobHistory::new(max_entries));
+        self
+    }
+
+    /// Consult the authorizer for the scans of every job and view, which may deny them or
+    /// restrict the rows and columns that they read
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn Authorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Only allow tables to be registered and created with paths that the validator allows,
+    /// which should be the paths that executors allow plans to read
+    pub fn with_plan_validator(mut self, validator: PlanValidator) -> Self {
+        self.plan_validator = Arc::new(validator);
+        self
+    }
+
+    /// Only let clients authenticated as one of these identities create and drop external
+    /// tables, which is the cluster identity by default. Schedulers that do not authenticate
+    /// their clients see every client as the anonymous identity.
+    pub fn with_admins(mut self, identities: Vec<String>) -> Self {
+        self.admins = identities;
+        self
+    }
+
+    /// Apply the restrictions of the authorizer to a plan whose tables have been resolved, so
+    /// that the scans of the tables and views of sessions are authorized by the paths that they
+    /// read
+    fn authorize(&self, plan: &LogicalPlan, tenant: &str) -> Result<LogicalPlan> {
+        match &self.authorizer {
+            Some(authorizer) => authorize_plan(plan, tenant, authorizer.as_ref()).map_err(|e| {
+                warn!("Denied query tenant={} error={:?}", tenant, e);
+                e
+            }),
+            None => Ok(plan.clone()),
+        }
+    }
+
+    /// Load the jobs persisted by a previous run of the scheduler and resume the ones that were
+    /// queued or running. Finished jobs that were submitted longer ago than the job status TTL
+    /// are removed from the store. Returns the number of jobs that were resumed.
+    pub async fn recover(&self) -> Result<usize> {
+        let mut resumed = 0;
+        for record in self.job_state_store.list_jobs().await? {
+            let status = record.status.clone();
+            if status.state.is_finished() {
+                let expired = status
+                    .submitted_at
+                    .elapsed()
+                    .map(|age| age > DEFAULT_JOB_STATUS_TTL)
+                    .unwrap_or(false);
+                if expired {
+                    self.job_state_store.remove_job(&status.job_uuid).await?;
+                } else {
+                    self.update(
+                        status,
+                        &record.tenant,
+                        CancellationToken::new(),
+                        ProgressTracker::new(),
+                    );
+                }
+                continue;
+            }
+            info!("Resuming job job_uuid={}", status.job_uuid);
+            let cancellation_token = CancellationToken::new();
+            let progress = ProgressTracker::new();
+            self.update(
+                status,
+                &record.tenant,
+                cancellation_token.clone(),
+                progress.clone(),
+            );
+            let server = self.clone();
+            self.runner.spawn(move || async move {
+                let job = record.to_job();
+                server
+                    .run_job(
+                        &job,
+                        &record.settings,
+                        &record.tenant,
+                        None,
+                        None,
+                        cancellation_token,
+                        progress,
+                    )
+                    .await;
+            });
+            resumed += 1;
+        }
+        Ok(resumed)
+    }
+
+    /// Create a session of a tenant, whose jobs run with the given settings unless they
+    /// override them
+    pub fn create_session(&self, tenant: &str, settings: QuerySettings) -> Arc<Session> {
+        self.sessions.create(tenant, settings)
+    }
+
+    /// The session of a tenant with the given id, which keeps the session alive. Sessions of
+    /// different tenants are kept apart so that clients cannot scan the tables of other
+    /// tenants by guessing their session.
+    pub fn session(&self, tenant: &str, session_id: &str) -> Result<Arc<Session>> {
+        self.sessions.get(tenant, session_id)
+    }
+
+    /// Close a session of a tenant, returning false if there was no such session
+    pub fn close_session(&self, tenant: &str, session_id: &str) -> bool {
+        self.sessions.close(tenant, session_id)
+    }
+
+    /// Close the sessions that have not been used for the session TTL, returning the number
+    /// that were closed
+    pub fn expire_sessions(&self) -> usize {
+        let expired = self.sessions.expire_idle();
+        if expired > 0 {
+            info!("Closed idle sessions count={}", expired);
+        }
+        expired
+    }
+
+    /// Time after which a session that has not been used is closed
+    pub fn session_ttl(&self) -> Duration {
+        self.sessions.ttl()
+    }
+
+    /// Register the files at a path as a table of a session, returning the schema of the
+    /// table, which is inferred from the files when it is not given
+    pub fn register_session_table(
+        &self,
+        session: &Session,
+        name: &str,
+        path: &str,
+        format: Option<&str>,
+        schema: Option<Schema>,
+    ) -> Result<Schema> {
+        // the schema of the files is inferred here, so the path is checked before it is read
+        self.plan_validator.check_path(path)?;
+        let plan = file_scan_plan(path, format, schema)?;
+        let schema = plan.schema().as_ref().clone();
+        session.catalog().register(name, plan);
+        info!(
+            "Registered table tenant={} session_id={} name={} path={}",
+            session.tenant(),
+            session.id(),
+            name,
+            path
+        );
+        Ok(schema)
+    }
+
+    /// Register a logical plan as a view of a session, returning the schema of the view. The
+    /// tables that the plan scans by name must already be registered with the session or be
+    /// external tables.
+    pub async fn register_session_view(
+        &self,
+        session: &Session,
+        name: &str,
+        plan: LogicalPlan,
+    ) -> Result<Schema> {
+        // views are authorized again whenever a job scans them, but a view of tables that the
+        // tenant may not read is rejected up front
+        let resolved = self.resolve_tables(Some(session), &plan).await?;
+        self.authorize(&resolved, session.tenant())?;
+        let schema = plan.schema().as_ref().clone();
+        session.catalog().register(name, plan);
+        info!(
+            "Registered view tenant={} session_id={} name={}",
+            session.tenant(),
+            session.id(),
+            name
+        );
+        Ok(schema)
+    }
+
+    /// Define an external table, replacing any existing table with the same name
+    pub async fn create_table(&self, table: &TableDefinition) -> Result<()> {
+        self.plan_validator.check_path(&table.location)?;
+        self.table_store.save_table(table).await?;
+        info!(
+            "Created external table name={} location={} format={}",
+            table.name, table.location, table.format
+        );
+        Ok(())
+    }
+
+    /// Drop an external table, returning false if there was no such table
+    pub async fn remove_table(&self, name: &str) -> Result<bool> {
+        let dropped = self.table_store.remove_table(name).await?;
+        info!("Dropped external table name={} dropped={}", name, dropped);
+        Ok(dropped)
+    }
+
+    pub async fn tables(&self) -> Result<Vec<TableDefinition>> {
+        self.table_store.list_tables().await
+    }
+
+    /// Replace the scans of named tables in a plan with the plans of those tables, where the
+    /// tables of the session hide external tables with the same name
+    async fn resolve_tables(
+        &self,
+        session: Option<&Session>,
+        plan: &LogicalPlan,
+    ) -> Result<LogicalPlan> {
+        let catalog = TableCatalog::new();
+        for table in self.table_store.list_tables().await? {
+            catalog.register(&table.name, table.to_plan()?);
+        }
+        if let Some(session) = session {
+            for (name, table) in session.catalog().tables() {
+                catalog.register(&name, table);
+            }
+        }
+        catalog.resolve(plan)
+    }
+
+    /// Reject a request to change the external tables that all tenants share, unless it comes
+    /// from an admin, as tenants could otherwise replace or drop each other's tables
+    fn check_admin<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let principal = tenant_of(request);
+        if self.admins.iter().any(|admin| *admin == principal) {
+            Ok(())
+        } else {
+            warn!("Denied change of external tables principal={}", principal);
+            Err(Status::permission_denied(format!(
+                "{} may not change external tables",
+                principal
+            )))
+        }
+    }
+
+    /// Reject a request for a job of another tenant, which is reported as unknown so that
+    /// tenants cannot learn which jobs other tenants run
+    fn check_job_owner<T>(&self, request: &Request<T>, job_uuid: &Uuid) -> Result<(), Status> {
+        match (job_owner_of(request), self.job_tenant(job_uuid)) {
+            (Some(owner), Some(tenant)) if owner != tenant => {
+                Err(Status::not_found(format!("unknown job {}", job_uuid)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The session that a request belongs to, if the request names one
+    fn request_session<T>(&self, request: &Request<T>) -> Result<Option<Arc<Session>>, Status> {
+        match session_id_of(request) {
+            Some(session_id) => self
+                .session(&tenant_of(request), &session_id)
+                .map(Some)
+                .map_err(|e| Status::not_found(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Plan a job and start running it in the background on behalf of a tenant, with the given
+    /// settings overriding the configuration of the scheduler, returning the job UUID once the
+    /// job has been planned. The spans of the job are children of the given span, if any, and
+    /// the SQL text that the plan was built from is kept in the job history.
+    pub fn submit(
+        &self,
+        logical_plan: &LogicalPlan,
+        settings: &QuerySettings,
+        tenant: &str,
+        sql: Option<String>,
+        trace: Option<TraceContext>,
+    ) -> Result<Uuid> {
+        let logical_plan = optimize_logical_plan(logical_plan)?;
+        let fingerprint = match &self.result_cache {
+            Some(cache) => match plan_fingerprint(&logical_plan, tenant) {
+                Ok(fingerprint) => Some((cache.clone(), fingerprint)),
+                Err(e) => {
+                    warn!("Failed to fingerprint plan error={:?}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        if let Some((cache, fingerprint)) = &fingerprint {
+            if let Some(partitions) = cache.get(*fingerprint) {
+                let job_uuid = Uuid::new_v4();
+                let status = JobStatus {
+                    job_uuid,
+                    state: JobState::Completed(partitions),
+                    submitted_at: SystemTime::now(),
+                    progress: JobProgress::default(),
+                };
+                self.update(
+                    status,
+                    tenant,
+                    CancellationToken::new(),
+                    ProgressTracker::new(),
+                );
+                info!(
+                    "Served job from result cache job_uuid={} fingerprint={}",
+                    job_uuid, fingerprint
+                );
+                return Ok(job_uuid);
+            }
+        }
+        let fingerprint = fingerprint.map(|(_, fingerprint)| fingerprint);
+        let (tx, rx) = mpsc::channel();
+        let server = self.clone();
+        let settings = *settings;
+        let tenant = tenant.to_owned();
+        self.runner.spawn(move || async move {
+            // jobs cannot be sent between threads so are planned on the thread that runs them
+            let job_config = server.config.job_config.with_query_settings(&settings);
+            let job = match plan_job(&logical_plan, &job_config) {
+                Ok(job) => job,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            let status = JobStatus {
+                job_uuid: job.id,
+                state: JobState::Queued,
+                submitted_at: SystemTime::now(),
+                progress: JobProgress::default(),
+            };
+            // persist the job before acknowledging it so that it survives a restart
+            let persisted = match JobRecord::new(&job, status.clone()) {
+                Ok(record) => {
+                    let record = record.with_settings(settings).with_tenant(&tenant);
+                    server.job_state_store.save_job(&record).await
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = persisted {
+                let _ = tx.send(Err(e));
+                return;
+            }
+            server
+                .history
+                .start(job.id, &tenant, sql, &logical_plan, status.submitted_at);
+            let cancellation_token = CancellationToken::new();
+            let progress = ProgressTracker::new();
+            server.update(
+                status,
+                &tenant,
+                cancellation_token.clone(),
+                progress.clone(),
+            );
+            let _ = tx.send(Ok(job.id));
+
+            server
+                .run_job(
+                    &job,
+                    &settings,
+                    &tenant,
+                    fingerprint,
+                    trace,
+                    cancellation_token,
+                    progress,
+                )
+                .await;
+        });
+        rx.recv()
+            .map_err(|e| ballista_error(&format!("Scheduler thread failed: {:?}", e)))?
+    }
+
+    /// Run a job that has been planned and persisted, until it finishes. The results of the
+    /// job are cached under the fingerprint of its plan if it completes, and its metrics are
+    /// recorded in the job history.
+    #[allow(clippy::too_many_arguments)]
+    async fn run_job(
+        &self,
+        job: &Job,
+        settings: &QuerySettings,
+        tenant: &str,
+        fingerprint: Option<u64>,
+        trace: Option<TraceContext>,
+        cancellation_token: CancellationToken,
+        progress: ProgressTracker,
+    ) {
+        let mut span = Span::start("job", trace.as_ref())
+            .with_attribute("job_uuid", job.id)
+            .with_attribute("tenant", tenant);
+        let mut config = self.config.clone();
+        config.job_config = config.job_config.with_query_settings(settings);
+        let timeout = JobTimeout::new(cancellation_token.clone(), config.job_config.timeout)
+            .with_progress(progress.clone());
+        let ctx = Arc::new(
+            DefaultContext::new(&config, HashMap::new())
+                .with_discovery(self.discovery.clone())
+                .with_cancellation_token(cancellation_token)
+                .with_job_state_store(self.job_state_store.clone())
+                .with_job_progress(progress)
+                .with_tenant(tenant)
+                .with_trace_context(Some(span.context())),
+        );
+        self.set_state(&job.id, JobState::Running).await;
+        let result = timeout.run(execute_job(job, ctx.clone())).await;
+        let (state, profile) = match result {
+            Ok((partitions, profile)) => {
+                info!("Job completed job_uuid={}", job.id);
+                // the partitions are held so that fetching them does not remove them before
+                // the jobs that are served from the cache have fetched them too
+                if let (Some(cache), Some(fingerprint)) = (&self.result_cache, fingerprint) {
+                    if retain_shuffles(ctx.as_ref(), job.id, &partitions).await {
+                        cache.insert(fingerprint, partitions.clone());
+                    }
+                }
+                (JobState::Completed(partitions), Some(profile))
+            }
+            Err(BallistaError::Cancelled) => {
+                info!("Job cancelled job_uuid={}", job.id);
+                (JobState::Cancelled, None)
+            }
+            Err(e) => {
+                error!("Job failed job_uuid={} error={:?}", job.id, e);
+                span.set_error(format!("{:?}", e));
+                (JobState::Failed(format!("{:?}", e)), None)
+            }
+        };
+        self.history
+            .finish(&job.id, &state, profile.as_ref(), job.root_stage_id);
+        self.set_state(&job.id, state).await;
+    }
+
+    pub fn job_status(&self, job_uuid: &Uuid) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        jobs.get(&job_uuid.to_string()).map(|entry| entry.status())
+    }
+
+    /// The jobs of a tenant, or of every tenant if no tenant is given, in the order that they
+    /// were submitted
+    pub fn jobs(&self, tenant: Option<&str>) -> Vec<JobStatus> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        let mut statuses: Vec<JobStatus> = jobs
+            .values()
+            .filter(|entry| tenant.map_or(true, |tenant| entry.tenant == tenant))
+            .map(|entry| entry.status())
+            .collect();
+        statuses.sort_by_key(|status| status.submitted_at);
+        statuses
+    }
+
+    /// The finished jobs in the history that match the filter, most recent first
+    pub fn job_history(&self, filter: &JobHistoryFilter) -> Vec<JobHistoryEntry> {
+        self.history.list(filter)
+    }
+
+    /// A finished job, if it is still in the history
+    pub fn job_history_entry(&self, job_uuid: &Uuid) -> Option<JobHistoryEntry> {
+        self.history.get(job_uuid)
+    }
+
+    /// The tasks of a job that have been submitted to executors
+    pub fn job_tasks(&self, job_uuid: &Uuid) -> Vec<TaskProgress> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        jobs.get(&job_uuid.to_string())
+            .map(|entry| entry.progress.tasks())
+            .unwrap_or_default()
+    }
+
+    /// The persisted record of a job, with the plans of its stages
+    pub async fn job_record(&self, job_uuid: &Uuid) -> Result<Option<JobRecord>> {
+        self.job_state_store.get_job(job_uuid).await
+    }
+
+    /// The executors in the cluster that jobs run on
+    pub async fn executors(&self) -> Result<Vec<ExecutorMeta>> {
+        self.discovery.get_executors().await
+    }
+
+    /// Ask an executor for statistics of the tasks that it is running and the shuffle
+    /// partitions that it holds
+    pub async fn executor_stats(&self, executor: &ExecutorMeta) -> Result<protobuf::ExecutorStats> {
+        executor_stats(
+            &executor.host,
+            executor.port,
+            self.config.auth_token.as_deref(),
+            self.config.tls.as_ref(),
+        )
+        .await
+    }
+
+    /// Cancel a job. Returns false if the job has already finished.
+    pub fn cancel(&self, job_uuid: &Uuid) -> Result<bool> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        match jobs.get(&job_uuid.to_string()) {
+            Some(entry) if entry.status.state.is_finished() => Ok(false),
+            Some(entry) => {
+                info!("Cancelling job job_uuid={}", job_uuid);
+                entry.cancellation_token.cancel();
+                Ok(true)
+            }
+            None => Err(ballista_error(&format!("unknown job {}", job_uuid))),
+        }
+    }
+
+    /// Drop the cached results of earlier jobs, returning the number that were dropped
+    pub fn invalidate_results(&self) -> usize {
+        let invalidated = match &self.result_cache {
+            Some(cache) => cache.invalidate(),
+            None => 0,
+        };
+        info!("Invalidated result cache invalidated={}", invalidated);
+        invalidated
+    }
+
+    /// The tenant that submitted a job, if the job is known
+    pub fn job_tenant(&self, job_uuid: &Uuid) -> Option<String> {
+        let jobs = self.jobs.lock().expect("failed to lock mutex");
+        jobs.get(&job_uuid.to_string())
+            .map(|entry| entry.tenant.clone())
+    }
+
+    fn update(
+        &self,
+        status: JobStatus,
+        tenant: &str,
+        cancellation_token: CancellationToken,
+        progress: ProgressTracker,
+    ) {
+        let mut jobs = self.jobs.lock().expect("failed to lock mutex");
+        jobs.insert(
+            status.job_uuid.to_string(),
+            JobEntry {
+                status,
+                tenant: tenant.to_owned(),
+                cancellation_token,
+                progress: progress.clone(),
+            },
+        );
+        progress.notify();
+    }
+
+    /// Stream the status of a job each time that the job makes progress, at most once per
+    /// interval, ending with the status of the job once it has finished
+    pub fn watch(&self, job_uuid: &Uuid, interval: Duration) -> Result<JobStatusStream> {
+        let mut changes = {
+            let jobs = self.jobs.lock().expect("failed to lock mutex");
+            match jobs.get(&job_uuid.to_string()) {
+                Some(entry) => entry.progress.subscribe(),
+                None => return Err(ballista_error(&format!("unknown job {}", job_uuid))),
+            }
+        };
+        let job_uuid = *job_uuid;
+        let (mut tx, rx) = futures::channel::mpsc::channel(1);
+        let server = self.clone();
+        tokio::spawn(async move {
+            let mut last_sent = None;
+            // the status is gone if the job finished long enough ago to be evicted
+            while let Some(status) = server.job_status(&job_uuid) {
+                let finished = status.state.is_finished();
+                if last_sent.as_ref() != Some(&status) {
+                    let message: Result<protobuf::JobStatus, Status> =
+                        (&status).try_into().map_err(|e| to_tonic_err(&e));
+                    if tx.send(message).await.is_err() {
+                        // the client stopped watching
+                        break;
+                    }
+                    last_sent = Some(status);
+                }
+                // wait for the job to change, then for the rest of the interval so that the
+                // changes made in the meantime are sent together
+                if finished || changes.next().await.is_none() {
+                    break;
+                }
+                tokio::time::delay_for(interval).await;
+            }
+        });
+        Ok(Box::pin(rx))
+    }
+
+    async fn set_state(&self, job_uuid: &Uuid, state: JobState) {
+        let persisted = match self.job_state_store.get_job(job_uuid).await {
+            Ok(Some(mut record)) => {
+                record.status.state = state.clone();
+                self.job_state_store.save_job(&record).await
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = persisted {
+            warn!(
+                "Failed to persist job state job_uuid={} error={:?}",
+                job_uuid, e
+            );
+        }
+
+        let entry = {
+            let jobs = self.jobs.lock().expect("failed to lock mutex");
+            jobs.get(&job_uuid.to_string()).map(|entry| {
+                (
+                    JobStatus {
+                        state,
+                        ..entry.status.clone()
+                    },
+                    entry.tenant.clone(),
+                    entry.cancellation_token.clone(),
+                    entry.progress.clone(),
+                )
+            })
+        };
+        if let Some((status, tenant, cancellation_token, progress)) = entry {
+            self.update(status, &tenant, cancellation_token, progress);
+        }
+    }
+}
+
+fn plan_job(logical_plan: &LogicalPlan, config: &JobConfig) -> Result<Job> {
+    let plan = create_physical_plan(logical_plan, config)?;
+    // the scheduler has no statistics collected by ANALYZE, which are kept by executors
+    let plan = ensure_requirements(plan.as_ref(), &StatisticsCatalog::new())?;
+    let plan = prune_columns(&plan)?;
+    let job = create_job(plan)?;
+    job.explain();
+    Ok(job)
+}
+
+/// The session that a call which only makes sense within a session belongs to
+fn require_session(session: Option<Arc<Session>>) -> Result<Arc<Session>, Status> {
+    session.ok_or_else(|| {
+        Status::failed_precondition("A session must be created with the handshake first")
+    })
+}
+
+/// The tenant whose jobs a request may see and cancel, or None if the request comes from the
+/// processes of the cluster, which may see and cancel every job
+fn job_owner_of<T>(request: &Request<T>) -> Option<String> {
+    Some(tenant_of(request)).filter(|tenant| tenant != CLUSTER_IDENTITY)
+}
+
+fn parse_job_uuid(job_uuid: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(job_uuid).map_err(|e| Status::invalid_argument(format!("{:?}", e)))
+}
+
+fn to_tonic_err(e: &BallistaError) -> Status {
+    to_status(e)
+}
+
+#[async_trait]
+impl SchedulerGrpc for SchedulerServer {
+    async fn handshake(
+        &self,
+        request: Request<protobuf::HandshakeParams>,
+    ) -> Result<Response<protobuf::HandshakeResult>, Status> {
+        let tenant = tenant_of(&request);
+        let settings: QuerySettings = match &request.get_ref().settings {
+            Some(settings) => settings.try_into().map_err(|e| to_tonic_err(&e))?,
+            None => QuerySettings::default(),
+        };
+        let session = self.create_session(&tenant, settings);
+        Ok(Response::new(protobuf::HandshakeResult {
+            session_id: session.id().to_owned(),
+            idle_timeout_ms: self.session_ttl().as_millis() as u64,
+        }))
+    }
+
+    async fn set_session_config(
+        &self,
+        request: Request<protobuf::SetSessionConfigParams>,
+    ) -> Result<Response<protobuf::SetSessionConfigResult>, Status> {
+        let session = require_session(self.request_session(&request)?)?;
+        let settings: QuerySettings = match &request.get_ref().settings {
+            Some(settings) => settings.try_into().map_err(|e| to_tonic_err(&e))?,
+            None => QuerySettings::default(),
+        };
+        let settings = session.update_settings(&settings);
+        info!(
+            "Updated session settings session_id={} settings={:?}",
+            session.id(),
+            settings.names()
+        );
+        Ok(Response::new(protobuf::SetSessionConfigResult {
+            settings: Some((&settings).try_into().map_err(|e| to_tonic_err(&e))?),
+        }))
+    }
+
+    async fn close_session(
+        &self,
+        request: Request<protobuf::CloseSessionParams>,
+    ) -> Result<Response<protobuf::CloseSessionResult>, Status> {
+        let closed = match session_id_of(&request) {
+            Some(session_id) => self.close_session(&tenant_of(&request), &session_id),
+            None => false,
+        };
+        Ok(Response::new(protobuf::CloseSessionResult { closed }))
+    }
+
+    async fn submit_job(
+        &self,
+        request: Request<protobuf::SubmitJobParams>,
+    ) -> Result<Response<protobuf::SubmitJobResult>, Status> {
+        let tenant = tenant_of(&request);
+        // clients that trace their requests propagate their trace to the job
+        let trace = trace_context_of(&request);
+        let session = self.request_session(&request)?;
+        let params = request.into_inner();
+        let plan: LogicalPlan = params
+            .logical_plan
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("missing logical plan"))?
+            .try_into()
+            .map_err(|e| to_tonic_err(&e))?;
+        let plan = self
+            .resolve_tables(session.as_deref(), &plan)
+            .await
+            .map_err(|e| to_tonic_err(&e))?;
+        let plan = self
+            .authorize(&plan, &tenant)
+            .map_err(|e| Status::permission_denied(e.to_string()))?;
+        let settings: QuerySettings = match &params.settings {
+            Some(settings) => settings.try_into().map_err(|e| to_tonic_err(&e))?,
+            None => QuerySettings::default(),
+        };
+        let settings = match &session {
+            Some(session) => session.job_settings(&settings),
+            None => settings,
+        };
+        let sql = Some(params.sql).filter(|sql| !sql.is_empty());
+        let job_uuid = self
+            .submit(&plan, &settings, &tenant, sql, trace)
+            .map_err(|e| to_tonic_err(&e))?;
+        info!("Submitted job job_uuid={} tenant={}", job_uuid, tenant);
+        Ok(Response::new(protobuf::SubmitJobResult {
+            job_uuid: job_uuid.to_string(),
+        }))
+    }
+
+    async fn get_job_status(
+        &self,
+        request: Request<protobuf::GetJobStatusParams>,
+    ) -> Result<Response<protobuf::JobStatus>, Status> {
+        let job_uuid = parse_job_uuid(&request.get_ref().job_uuid)?;
+        self.check_job_owner(&request, &job_uuid)?;
+        match self.job_status(&job_uuid) {
+            Some(status) => Ok(Response::new(
+                (&status).try_into().map_err(|e| to_tonic_err(&e))?,
+            )),
+            None => Err(Status::not_found(format!("unknown job {}", job_uuid))),
+        }
+    }
+
+    async fn list_jobs(
+        &self,
+        request: Request<protobuf::ListJobsParams>,
+    ) -> Result<Response<protobuf::ListJobsResult>, Status> {
+        // tenants only see their own jobs, while the processes of the cluster see every job
+        let jobs = self
+            .jobs(job_owner_of(&request).as_deref())
+            .iter()
+            .map(|status| status.try_into())
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(protobuf::ListJobsResult { jobs }))
+    }
+
+    async fn list_job_history(
+        &self,
+        request: Request<protobuf::ListJobHistoryParams>,
+    ) -> Result<Response<protobuf::ListJobHistoryResult>, Status> {
+        // tenants only see their own jobs, while the processes of the cluster see every job
+        let tenant = job_owner_of(&request);
+        let params = request.into_inner();
+        let filter = JobHistoryFilter {
+            tenant,
+            search: Some(params.search).filter(|search| !search.is_empty()),
+            fingerprint: Some(params.fingerprint).filter(|fingerprint| *fingerprint != 0),
+            limit: Some(params.limit as usize).filter(|limit| *limit != 0),
+        };
+        let jobs = self
+            .job_history(&filter)
+            .iter()
+            .map(|entry| entry.try_into())
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(protobuf::ListJobHistoryResult { jobs }))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<protobuf::CancelJobParams>,
+    ) -> Result<Response<protobuf::CancelJobResult>, Status> {
+        let job_uuid = parse_job_uuid(&request.get_ref().job_uuid)?;
+        self.check_job_owner(&request, &job_uuid)?;
+        let cancelled = self
+            .cancel(&job_uuid)
+            .map_err(|_| Status::not_found(format!("unknown job {}", job_uuid)))?;
+        Ok(Response::new(protobuf::CancelJobResult { cancelled }))
+    }
+
+    type WatchJobStream = JobStatusStream;
+
+    async fn watch_job(
+        &self,
+        request: Request<protobuf::WatchJobParams>,
+    ) -> Result<Response<Self::WatchJobStream>, Status> {
+        let job_uuid = parse_job_uuid(&request.get_ref().job_uuid)?;
+        self.check_job_owner(&request, &job_uuid)?;
+        let params = request.into_inner();
+        let interval = match params.interval_ms {
+            0 => DEFAULT_WATCH_INTERVAL,
+            ms => Duration::from_millis(ms),
+        };
+        let statuses = self
+            .watch(&job_uuid, interval)
+            .map_err(|_| Status::not_found(format!("unknown job {}", job_uuid)))?;
+        Ok(Response::new(statuses))
+    }
+
+    async fn register_table(
+        &self,
+        request: Request<protobuf::RegisterTableParams>,
+    ) -> Result<Response<protobuf::RegisterTableResult>, Status> {
+        let session = require_session(self.request_session(&request)?)?;
+        let params = request.into_inner();
+        let format = Some(params.file_format.as_str()).filter(|f| !f.is_empty());
+        let schema: Option<Schema> = match &params.schema {
+            Some(schema) => Some(schema.try_into().map_err(|e| to_tonic_err(&e))?),
+            None => None,
+        };
+        let schema = self
+            .register_session_table(&session, &params.name, &params.path, format, schema)
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(protobuf::RegisterTableResult {
+            schema: Some((&schema).try_into().map_err(|e| to_tonic_err(&e))?),
+        }))
+    }
+
+    async fn register_view(
+        &self,
+        request: Request<protobuf::RegisterViewParams>,
+    ) -> Result<Response<protobuf::RegisterTableResult>, Status> {
+        let session = require_session(self.request_session(&request)?)?;
+        let params = request.into_inner();
+        let plan: LogicalPlan = params
+            .plan
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("missing logical plan"))?
+            .try_into()
+            .map_err(|e| to_tonic_err(&e))?;
+        let schema = self
+            .register_session_view(&session, &params.name, plan)
+            .await
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(protobuf::RegisterTableResult {
+            schema: Some((&schema).try_into().map_err(|e| to_tonic_err(&e))?),
+        }))
+    }
+
+    async fn create_external_table(
+        &self,
+        request: Request<protobuf::TableDefinition>,
+    ) -> Result<Response<protobuf::TableDefinition>, Status> {
+        self.check_admin(&request)?;
+        let params = request.into_inner();
+        // the schema of the table may be inferred from its files, so the location is checked
+        // before they are read
+        self.plan_validator
+            .check_path(&params.location)
+            .map_err(|e| to_tonic_err(&e))?;
+        let format = Some(params.file_format.as_str()).filter(|f| !f.is_empty());
+        let schema: Option<Schema> = match &params.schema {
+            Some(schema) => Some(schema.try_into().map_err(|e| to_tonic_err(&e))?),
+            None => None,
+        };
+        let table = TableDefinition::try_new(
+            &params.name,
+            &params.location,
+            format,
+            schema,
+            params.partition_columns,
+        )
+        .map_err(|e| to_tonic_err(&e))?;
+        self.create_table(&table)
+            .await
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(
+            (&table).try_into().map_err(|e| to_tonic_err(&e))?,
+        ))
+    }
+
+    async fn drop_table(
+        &self,
+        request: Request<protobuf::DropTableParams>,
+    ) -> Result<Response<protobuf::DropTableResult>, Status> {
+        self.check_admin(&request)?;
+        let dropped = self
+            .remove_table(&request.into_inner().name)
+            .await
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(protobuf::DropTableResult { dropped }))
+    }
+
+    async fn list_tables(
+        &self,
+        _request: Request<protobuf::ListTablesParams>,
+    ) -> Result<Response<protobuf::ListTablesResult>, Status> {
+        let tables = self
+            .tables()
+            .await
+            .map_err(|e| to_tonic_err(&e))?
+            .iter()
+            .map(|table| table.try_into())
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| to_tonic_err(&e))?;
+        Ok(Response::new(protobuf::ListTablesResult { tables }))
+    }
+
+    async fn invalidate_result_cache(
+        &self,
+        _request: Request<protobuf::InvalidateResultCacheParams>,
+    ) -> Result<Response<protobuf::InvalidateResultCacheResult>, Status> {
+        let invalidated = self.invalidate_results() as u64;
+        Ok(Response::new(protobuf::InvalidateResultCacheResult {
+            invalidated,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field};
+    use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
+    use crate::datafusion::logicalplan::LogicalPlanBuilder;
+    use crate::distributed::auth::anonymous_interceptor;
+    use crate::distributed::executor::DiscoveryMode;
+    use crate::distributed::job_state::StageRecord;
+    use crate::distributed::scheduling::DEFAULT_TENANT;
+    use crate::execution::operators::ShuffleReaderExec;
+    use crate::execution::physical_plan::{PhysicalPlan, ShuffleId};
+    use std::time::Instant;
+
+    fn scheduler() -> SchedulerServer {
+        SchedulerServer::new(ExecutorConfig::new(
+            DiscoveryMode::Standalone,
+            "localhost",
+            50050,
+            "",
+        ))
+    }
+
+    fn job_record(state: JobState, submitted_at: SystemTime) -> JobRecord {
+        let job_uuid = Uuid::new_v4();
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let plan = PhysicalPlan::ShuffleReader(Arc::new(ShuffleReaderExec::new(
+            Arc::new(schema),
+            vec![ShuffleId::new(job_uuid, 0, 0)],
+        )));
+        JobRecord {
+            status: JobStatus {
+                job_uuid,
+                state,
+                submitted_at,
+                progress: JobProgress::default(),
+            },
+            root_stage_id: 1,
+            stages: vec![StageRecord {
+                stage_id: 1,
+                prior_stages: vec![],
+                plan,
+                completed: false,
+                shuffle_locations: vec![],
+                task_assignments: HashMap::new(),
+            }],
+            settings: QuerySettings::default(),
+            tenant: DEFAULT_TENANT.to_owned(),
+        }
+    }
+
+    #[test]
+    fn list_job_history_of_anonymous_clients() -> Result<()> {
+        let scheduler = scheduler();
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let plan =
+            LogicalPlanBuilder::scan_csv("a.csv", CsvReadOptions::new().schema(&schema), None)?
+                .build()?;
+        let job_uuid = Uuid::new_v4();
+        scheduler
+            .history
+            .start(job_uuid, DEFAULT_TENANT, None, &plan, SystemTime::now());
+        scheduler
+            .history
+            .finish(&job_uuid, &JobState::Cancelled, None, 0);
+        let other_job_uuid = Uuid::new_v4();
+        scheduler
+            .history
+            .start(other_job_uuid, "etl", None, &plan, SystemTime::now());
+        scheduler
+            .history
+            .finish(&other_job_uuid, &JobState::Cancelled, None, 0);
+
+        // a client that claims to be the cluster is still an anonymous client, which only sees
+        // the jobs of the default tenant
+        let mut intercepted = Request::new(());
+        intercepted.metadata_mut().insert(
+            "x-ballista-identity",
+            tonic::metadata::MetadataValue::from_static(CLUSTER_IDENTITY),
+        );
+        let intercepted = anonymous_interceptor()(intercepted).unwrap();
+        let mut request = Request::new(protobuf::ListJobHistoryParams::default());
+        *request.metadata_mut() = intercepted.metadata().clone();
+        let result = smol::run(scheduler.list_job_history(request)).unwrap();
+        let jobs = result.into_inner().jobs;
+        assert_eq!(1, jobs.len());
+        assert_eq!(job_uuid.to_string(), jobs[0].job_uuid);
+        Ok(())
+    }
+
+    fn request_of<T>(message: T, identity: &'static str) -> Request<T> {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert(
+            "x-ballista-identity",
+            tonic::metadata::MetadataValue::from_static(identity),
+        );
+        request
+    }
+
+    #[test]
+    fn only_show_jobs_to_their_tenant() {
+        let scheduler = scheduler();
+        let status = job_record(JobState::Running, SystemTime::now()).status;
+        let job_uuid = status.job_uuid;
+        scheduler.update(
+            status,
+            "etl",
+            CancellationToken::new(),
+            ProgressTracker::new(),
+        );
+
+        smol::run(async {
+            let params = || protobuf::GetJobStatusParams {
+                job_uuid: job_uuid.to_string(),
+            };
+            let status = scheduler
+                .get_job_status(request_of(params(), "dashboards"))
+                .await
+                .unwrap_err();
+            assert_eq!(tonic::Code::NotFound, status.code());
+            let status = scheduler
+                .cancel_job(request_of(
+                    protobuf::CancelJobParams {
+                        job_uuid: job_uuid.to_string(),
+                    },
+                    "dashboards",
+                ))
+                .await
+                .unwrap_err();
+            assert_eq!(tonic::Code::NotFound, status.code());
+            assert!(scheduler
+                .get_job_status(request_of(params(), "etl"))
+                .await
+                .is_ok());
+
+            let jobs = |identity| {
+                scheduler.list_jobs(request_of(protobuf::ListJobsParams::default(), identity))
+            };
+            assert!(jobs("dashboards")
+                .await
+                .unwrap()
+                .into_inner()
+                .jobs
+                .is_empty());
+            assert_eq!(1, jobs("etl").await.unwrap().into_inner().jobs.len());
+            assert_eq!(
+                1,
+                jobs(CLUSTER_IDENTITY)
+                    .await
+                    .unwrap()
+                    .into_inner()
+                    .jobs
+                    .len()
+            );
+        });
+    }
+
+    #[test]
+    fn recover_resumes_in_flight_jobs() -> Result<()> {
+        smol::run(async {
+            let store = Arc::new(InMemoryJobStateStore::default());
+            let now = SystemTime::now();
+            let expired = job_record(
+                JobState::Completed(vec![]),
+                now - DEFAULT_JOB_STATUS_TTL - Duration::from_secs(60),
+            );
+            let finished = job_record(JobState::Failed("executor lost".to_owned()), now);
+            let in_flight = job_record(JobState::Running, now);
+            for record in &[&expired, &finished, &in_flight] {
+                store.save_job(record).await?;
+            }
+
+            let scheduler = scheduler().with_job_state_store(store.clone());
+            assert_eq!(1, scheduler.recover().await?);
+
+            // finished jobs are not run again, and the expired ones are forgotten
+            assert!(store.get_job(&expired.status.job_uuid).await?.is_none());
+            assert!(scheduler.job_status(&expired.status.job_uuid).is_none());
+            assert_eq!(
+                Some(finished.status.state.clone()),
+                scheduler
+                    .job_status(&finished.status.job_uuid)
+                    .map(|status| status.state)
+            );
+
+            // the job that was in flight runs again, and fails as the cluster has no executors
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let status = scheduler
+                    .job_status(&in_flight.status.job_uuid)
+                    .expect("resumed job has a status");
+                if status.state.is_finished() {
+                    assert!(matches!(status.state, JobState::Failed(_)));
+                    break;
+                }
+                assert!(Instant::now() < deadline, "resumed job did not finish");
+                smol::Timer::after(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,412 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Policies for sharing the cluster between the tenants that submit jobs.
+//!
+//! A tenant is the identity that a client authenticated as with the flight handshake, and
+//! clients that did not authenticate share the default tenant. The policy decides when a job
+//! may start and when each task of a running job may be dispatched to an executor.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::distributed::auth::{authenticated_identity, ANONYMOUS_IDENTITY};
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::CancellationToken;
+
+use log::{debug, info};
+use smol::Timer;
+use tonic::Request;
+use uuid::Uuid;
+
+/// Tenant of the jobs of clients that did not authenticate
+pub const DEFAULT_TENANT: &str = ANONYMOUS_IDENTITY;
+
+/// Tenant that the jobs of a request are scheduled as, which is the identity that the client
+/// authenticated as
+pub fn tenant_of<T>(request: &Request<T>) -> String {
+    authenticated_identity(request).unwrap_or_else(|| DEFAULT_TENANT.to_owned())
+}
+
+/// Interval at which a job that is waiting to start checks whether it may start
+const JOB_ADMISSION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Decides when the jobs of each tenant start and when their tasks are dispatched
+pub trait SchedulingPolicy: Send + Sync + Debug {
+    /// Start a job of a tenant if the tenant may run another job, otherwise queue the job
+    /// behind the other jobs of the tenant. Returns whether the job has started.
+    fn try_start_job(&self, tenant: &str, job_uuid: &Uuid) -> bool;
+
+    /// Remove a job that has finished, or that was queued and will not start
+    fn finish_job(&self, tenant: &str, job_uuid: &Uuid);
+
+    /// Take a task slot for a task of a tenant, returning false if the task must wait while
+    /// the tasks of other tenants are dispatched
+    fn try_dispatch_task(&self, tenant: &str) -> bool;
+
+    /// Return the task slot of a task that has completed, failed or been moved
+    fn finish_task(&self, tenant: &str);
+}
+
+/// Start every job immediately and dispatch tasks in the order that jobs submit them
+#[derive(Debug, Clone, Default)]
+pub struct FifoScheduling {}
+
+impl SchedulingPolicy for FifoScheduling {
+    fn try_start_job(&self, _tenant: &str, _job_uuid: &Uuid) -> bool {
+        true
+    }
+
+    fn finish_job(&self, _tenant: &str, _job_uuid: &Uuid) {}
+
+    fn try_dispatch_task(&self, _tenant: &str) -> bool {
+        true
+    }
+
+    fn finish_task(&self, _tenant: &str) {}
+}
+
+/// Weight and limits of a tenant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TenantPolicy {
+    /// Share of the task slots of the tenant relative to the other tenants
+    pub weight: usize,
+    /// Max number of jobs of the tenant that run at a time, beyond which jobs are queued
+    pub max_concurrent_jobs: usize,
+}
+
+impl Default for TenantPolicy {
+    fn default() -> Self {
+        Self {
+            weight: 1,
+            max_concurrent_jobs: 4,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TenantState {
+    running_jobs: Vec<Uuid>,
+    /// Jobs waiting to start, in the order that they were submitted
+    queued_jobs: VecDeque<Uuid>,
+    running_tasks: usize,
+    /// Whether a task of the tenant was refused a slot and has not been dispatched since
+    waiting: bool,
+}
+
+/// Queue the jobs of each tenant beyond its max number of concurrent jobs, and share the task
+/// slots of the cluster between the tenants with running jobs in proportion to their weights.
+/// A tenant may use the slots that other tenants leave idle, but only until a task of a tenant
+/// that is below its share is waiting, so that a large query of one tenant cannot starve the
+/// queries of the others.
+#[derive(Debug)]
+pub struct FairScheduling {
+    /// Number of tasks that the executors of the cluster run at a time
+    task_slots: usize,
+    /// Policies of tenants by name, with the default policy applying to the other tenants
+    tenants: HashMap<String, TenantPolicy>,
+    default_policy: TenantPolicy,
+    state: Mutex<HashMap<String, TenantState>>,
+}
+
+impl FairScheduling {
+    pub fn new(task_slots: usize) -> Self {
+        Self {
+            task_slots: task_slots.max(1),
+            tenants: HashMap::new(),
+            default_policy: TenantPolicy::default(),
+            state: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Apply the given policy to tenants that do not have a policy of their own
+    pub fn with_default_policy(mut self, policy: TenantPolicy) -> Self {
+        self.default_policy = policy;
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: &str, policy: TenantPolicy) -> Self {
+        self.tenants.insert(tenant.to_owned(), policy);
+        self
+    }
+
+    /// Parse the policies of tenants from a list of `tenant=weight/max_concurrent_jobs` pairs
+    /// separated by commas, such as `etl=1/2,dashboards=3/8`
+    pub fn with_tenants(mut self, spec: &str) -> Result<Self> {
+        for entry in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let invalid = || BallistaError::General(format!("Invalid tenant policy '{}'", entry));
+            let mut parts = entry.splitn(2, '=');
+            let tenant = parts.next().ok_or_else(invalid)?.trim();
+            let mut limits = parts.next().ok_or_else(invalid)?.splitn(2, '/');
+            let weight = limits
+                .next()
+                .and_then(|s| s.trim().parse().ok())
+                .ok_or_else(invalid)?;
+            let max_concurrent_jobs = match limits.next() {
+                Some(s) => s.trim().parse().map_err(|_| invalid())?,
+                None => self.default_policy.max_concurrent_jobs,
+            };
+            if tenant.is_empty() || weight == 0 || max_concurrent_jobs == 0 {
+                return Err(invalid());
+            }
+            self.tenants.insert(
+                tenant.to_owned(),
+                TenantPolicy {
+                    weight,
+                    max_concurrent_jobs,
+                },
+            );
+        }
+        Ok(self)
+    }
+
+    fn policy(&self, tenant: &str) -> TenantPolicy {
+        self.tenants
+            .get(tenant)
+            .copied()
+            .unwrap_or(self.default_policy)
+    }
+
+    /// Task slots that a tenant is entitled to while the given tenants have running jobs,
+    /// which is at least one so that every tenant makes progress
+    fn share(&self, tenant: &str, state: &HashMap<String, TenantState>) -> usize {
+        let total_weight: usize = state
+            .iter()
+            .filter(|(_, s)| !s.running_jobs.is_empty())
+            .map(|(name, _)| self.policy(name).weight)
+            .sum();
+        let weight = self.policy(tenant).weight;
+        (self.task_slots * weight / total_weight.max(weight)).max(1)
+    }
+}
+
+impl SchedulingPolicy for FairScheduling {
+    fn try_start_job(&self, tenant: &str, job_uuid: &Uuid) -> bool {
+        let max_concurrent_jobs = self.policy(tenant).max_concurrent_jobs;
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        let tenant_state = state.entry(tenant.to_owned()).or_default();
+        if tenant_state.running_jobs.contains(job_uuid) {
+            return true;
+        }
+        if !tenant_state.queued_jobs.contains(job_uuid) {
+            tenant_state.queued_jobs.push_back(*job_uuid);
+        }
+        let next = tenant_state.queued_jobs.front() == Some(job_uuid);
+        if next && tenant_state.running_jobs.len() < max_concurrent_jobs {
+            tenant_state.queued_jobs.pop_front();
+            tenant_state.running_jobs.push(*job_uuid);
+            info!(
+                "Started job tenant={} job_uuid={} running_jobs={} queued_jobs={}",
+                tenant,
+                job_uuid,
+                tenant_state.running_jobs.len(),
+                tenant_state.queued_jobs.len()
+            );
+            true
+        } else {
+            false
+        }
+    }
+
+    fn finish_job(&self, tenant: &str, job_uuid: &Uuid) {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        if let Some(tenant_state) = state.get_mut(tenant) {
+            tenant_state.running_jobs.retain(|id| id != job_uuid);
+            tenant_state.queued_jobs.retain(|id| id != job_uuid);
+            if tenant_state.running_jobs.is_empty() {
+                tenant_state.waiting = false;
+            }
+            if tenant_state.running_jobs.is_empty()
+                && tenant_state.queued_jobs.is_empty()
+                && tenant_state.running_tasks == 0
+            {
+                state.remove(tenant);
+            }
+        }
+    }
+
+    fn try_dispatch_task(&self, tenant: &str) -> bool {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        let running_tasks = state.get(tenant).map_or(0, |s| s.running_tasks);
+        let dispatch = if running_tasks < self.share(tenant, &state) {
+            true
+        } else {
+            // idle slots are lent to this tenant unless another tenant is waiting for its share
+            let busy_slots: usize = state.values().map(|s| s.running_tasks).sum();
+            busy_slots < self.task_slots
+                && !state.iter().any(|(name, s)| {
+                    name != tenant && s.waiting && s.running_tasks < self.share(name, &state)
+                })
+        };
+        let tenant_state = state.entry(tenant.to_owned()).or_default();
+        if dispatch {
+            tenant_state.running_tasks += 1;
+            tenant_state.waiting = false;
+        } else if !tenant_state.waiting {
+            debug!(
+                "Task waits for a slot tenant={} running_tasks={}",
+                tenant, running_tasks
+            );
+            tenant_state.waiting = true;
+        }
+        dispatch
+    }
+
+    fn finish_task(&self, tenant: &str) {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        if let Some(tenant_state) = state.get_mut(tenant) {
+            tenant_state.running_tasks = tenant_state.running_tasks.saturating_sub(1);
+        }
+    }
+}
+
+/// A job that has started under a scheduling policy, which finishes when this is dropped
+pub struct JobPermit {
+    policy: Arc<dyn SchedulingPolicy>,
+    tenant: String,
+    job_uuid: Uuid,
+}
+
+impl JobPermit {
+    /// Wait until the policy lets a job of the tenant start, or until the job is cancelled
+    pub async fn acquire(
+        policy: Arc<dyn SchedulingPolicy>,
+        tenant: &str,
+        job_uuid: Uuid,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Self> {
+        let permit = Self {
+            policy,
+            tenant: tenant.to_owned(),
+            job_uuid,
+        };
+        let mut queued = false;
+        while !permit.policy.try_start_job(tenant, &job_uuid) {
+            if !queued {
+                info!(
+                    "Job is queued behind other jobs of its tenant tenant={} job_uuid={}",
+                    tenant, job_uuid
+                );
+                queued = true;
+            }
+            // dropping the permit removes the job from the queue
+            cancellation_token.check()?;
+            Timer::after(JOB_ADMISSION_INTERVAL).await;
+        }
+        Ok(permit)
+    }
+}
+
+impl Drop for JobPermit {
+    fn drop(&mut self) {
+        self.policy.finish_job(&self.tenant, &self.job_uuid);
+    }
+}
+
+/// Task slots held by the tasks of a job, which are returned to the policy when they are
+/// released or when this is dropped
+pub struct TaskSlots {
+    policy: Arc<dyn SchedulingPolicy>,
+    tenant: String,
+    /// Whether the task at each index holds a slot
+    held: Vec<bool>,
+}
+
+impl TaskSlots {
+    pub fn new(policy: Arc<dyn SchedulingPolicy>, tenant: &str, num_tasks: usize) -> Self {
+        Self {
+            policy,
+            tenant: tenant.to_owned(),
+            held: vec![false; num_tasks],
+        }
+    }
+
+    /// Take a slot for the task at the given index, returning false if it must wait
+    pub fn acquire(&mut self, index: usize) -> bool {
+        if !self.held[index] {
+            self.held[index] = self.policy.try_dispatch_task(&self.tenant);
+        }
+        self.held[index]
+    }
+
+    pub fn release(&mut self, index: usize) {
+        if self.held[index] {
+            self.held[index] = false;
+            self.policy.finish_task(&self.tenant);
+        }
+    }
+}
+
+impl Drop for TaskSlots {
+    fn drop(&mut self) {
+        for index in 0..self.held.len() {
+            self.release(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_jobs_beyond_max_concurrent_jobs() -> Result<()> {
+        let policy = FairScheduling::new(4).with_tenants("etl=1/1")?;
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(policy.try_start_job("etl", &first));
+        assert!(!policy.try_start_job("etl", &second));
+        // other tenants are not affected by the limit of this tenant
+        assert!(policy.try_start_job("dashboards", &Uuid::new_v4()));
+
+        policy.finish_job("etl", &first);
+        assert!(policy.try_start_job("etl", &second));
+        Ok(())
+    }
+
+    #[test]
+    fn share_task_slots_by_weight() -> Result<()> {
+        let policy = FairScheduling::new(8).with_tenants("etl=1/4,dashboards=3/4")?;
+        assert!(policy.try_start_job("etl", &Uuid::new_v4()));
+
+        // a tenant alone may use all the slots
+        for _ in 0..8 {
+            assert!(policy.try_dispatch_task("etl"));
+        }
+        assert!(!policy.try_dispatch_task("etl"));
+
+        // once another tenant has work, the first tenant only gets its share of the slots that
+        // are freed, while the other tenant gets its share even while the cluster is busy
+        assert!(policy.try_start_job("dashboards", &Uuid::new_v4()));
+        for _ in 0..6 {
+            assert!(policy.try_dispatch_task("dashboards"));
+        }
+        assert!(!policy.try_dispatch_task("dashboards"));
+        for _ in 0..7 {
+            policy.finish_task("etl");
+        }
+        assert!(policy.try_dispatch_task("etl"));
+        assert!(!policy.try_dispatch_task("etl"));
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_tenant_policies() {
+        assert!(FairScheduling::new(1).with_tenants("etl").is_err());
+        assert!(FairScheduling::new(1).with_tenants("etl=0/1").is_err());
+        assert!(FairScheduling::new(1).with_tenants("etl=1/x").is_err());
+        assert!(FairScheduling::new(1).with_tenants("").is_ok());
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sessions of clients of the scheduler.
+//!
+//! A client creates a session with the scheduler handshake and sends the session id in the
+//! `x-ballista-session-id` metadata of every later call. The tables that the client registers
+//! and the settings that it overrides belong to the session, and are dropped once the session
+//! is closed or has not been used for the idle timeout.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::distributed::catalog::TableCatalog;
+use crate::distributed::scheduler::QuerySettings;
+use crate::error::{ballista_error, Result};
+use crate::utils::expiring_map::ExpiringMap;
+
+use log::info;
+use tonic::metadata::MetadataValue;
+use tonic::Request;
+use uuid::Uuid;
+
+/// Header containing the id of the session that a call belongs to
+pub const SESSION_ID_HEADER: &str = "x-ballista-session-id";
+
+/// Default time after which a session that has not been used is closed
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(3600);
+
+/// Default maximum number of sessions to keep
+pub const DEFAULT_MAX_SESSIONS: usize = 1000;
+
+/// State of a session of a client
+pub struct Session {
+    id: String,
+    /// Tenant that created the session, which is the only tenant that may use it
+    tenant: String,
+    /// Tables registered with the session
+    catalog: TableCatalog,
+    /// Settings that the jobs of the session run with unless a job overrides them
+    settings: RwLock<QuerySettings>,
+}
+
+impl Session {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    pub fn catalog(&self) -> &TableCatalog {
+        &self.catalog
+    }
+
+    /// The settings of the session
+    pub fn settings(&self) -> QuerySettings {
+        *self.settings.read().expect("failed to lock")
+    }
+
+    /// Override settings of the session, keeping the ones that are not set, and return the
+    /// resulting settings
+    pub fn update_settings(&self, settings: &QuerySettings) -> QuerySettings {
+        let mut current = self.settings.write().expect("failed to lock");
+        *current = settings.or(&*current);
+        *current
+    }
+
+    /// The settings of a job of the session, which override the settings of the session
+    pub fn job_settings(&self, settings: &QuerySettings) -> QuerySettings {
+        settings.or(&self.settings())
+    }
+}
+
+/// Sessions of clients, which are closed once they have not been used for the time-to-live
+pub struct Sessions {
+    sessions: Mutex<ExpiringMap<Arc<Session>>>,
+    ttl: Duration,
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration, max_sessions: usize) -> Self {
+        Self {
+            sessions: Mutex::new(ExpiringMap::new(ttl, max_sessions, |_| true)),
+            ttl,
+        }
+    }
+
+    /// Time after which a session that has not been used is closed
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Create a session of a tenant with the given settings
+    pub fn create(&self, tenant: &str, settings: QuerySettings) -> Arc<Session> {
+        let session = Arc::new(Session {
+            id: Uuid::new_v4().to_string(),
+            tenant: tenant.to_owned(),
+            catalog: TableCatalog::new(),
+            settings: RwLock::new(settings),
+        });
+        let mut sessions = self.sessions.lock().expect("failed to lock mutex");
+        sessions.insert(session.id.clone(), session.clone());
+        info!(
+            "Created session session_id={} tenant={}",
+            session.id, tenant
+        );
+        session
+    }
+
+    /// The session with the given id, which keeps the session alive. Fails if the session does
+    /// not exist, has expired, or belongs to another tenant.
+    pub fn get(&self, tenant: &str, session_id: &str) -> Result<Arc<Session>> {
+        let mut sessions = self.sessions.lock().expect("failed to lock mutex");
+        match sessions.touch(session_id) {
+            Some(session) if session.tenant == tenant => Ok(session.clone()),
+            _ => Err(ballista_error(&format!(
+                "Unknown or expired session {}",
+                session_id
+            ))),
+        }
+    }
+
+    /// Close a session of a tenant, returning false if there was no such session
+    pub fn close(&self, tenant: &str, session_id: &str) -> bool {
+        let mut sessions = self.sessions.lock().expect("failed to lock mutex");
+        match sessions.get(session_id) {
+            Some(session) if session.tenant == tenant => {
+                sessions.remove(session_id);
+                info!("Closed session session_id={} tenant={}", session_id, tenant);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Close the sessions that have not been used for the time-to-live, returning the number
+    /// that were closed
+    pub fn expire_idle(&self) -> usize {
+        let mut sessions = self.sessions.lock().expect("failed to lock mutex");
+        sessions.evict_expired()
+    }
+
+    /// Number of open sessions
+    pub fn len(&self) -> usize {
+        self.sessions.lock().expect("failed to lock mutex").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new(DEFAULT_SESSION_TTL, DEFAULT_MAX_SESSIONS)
+    }
+}
+
+/// The id of the session that a request belongs to, if any
+pub fn session_id_of<T>(request: &Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get(SESSION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_owned())
+}
+
+/// Attach the id of a session to a request
+pub fn with_session_id<T>(message: T, session_id: Option<&str>) -> Result<Request<T>> {
+    let mut request = Request::new(message);
+    if let Some(session_id) = session_id {
+        let value = MetadataValue::from_str(session_id)
+            .map_err(|e| ballista_error(&format!("Invalid session id: {:?}", e)))?;
+        request.metadata_mut().insert(SESSION_ID_HEADER, value);
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::datafusion::logicalplan::LogicalPlanBuilder;
+    use crate::distributed::catalog::file_scan_plan;
+
+    #[test]
+    fn sessions_have_separate_tables() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        let table = file_scan_plan("employee.parquet", None, Some(schema.clone()))?;
+        let sessions = Sessions::default();
+        let a = sessions.create("tenant", QuerySettings::default());
+        let b = sessions.create("tenant", QuerySettings::default());
+        a.catalog().register("employee", table.clone());
+
+        let query = LogicalPlanBuilder::scan("default", "employee", &schema, None)?.build()?;
+        assert_eq!(
+            format!("{:?}", table),
+            format!(
+                "{:?}",
+                sessions.get("tenant", a.id())?.catalog().resolve(&query)?
+            )
+        );
+        assert!(b.catalog().resolve(&query).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sessions_belong_to_their_tenant() -> Result<()> {
+        let sessions = Sessions::default();
+        let session = sessions.create("tenant", QuerySettings::default());
+        assert!(sessions.get("other", session.id()).is_err());
+        assert!(!sessions.close("other", session.id()));
+        assert!(sessions.close("tenant", session.id()));
+        assert!(sessions.get("tenant", session.id()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn expire_idle_sessions() {
+        let sessions = Sessions::new(Duration::from_millis(20), DEFAULT_MAX_SESSIONS);
+        let session = sessions.create("tenant", QuerySettings::default());
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(1, sessions.expire_idle());
+        assert!(sessions.get("tenant", session.id()).is_err());
+    }
+
+    #[test]
+    fn job_settings_override_session_settings() {
+        let sessions = Sessions::default();
+        let session = sessions.create("tenant", QuerySettings::new().with_batch_size(100));
+        let settings = session.update_settings(&QuerySettings::new().with_target_partitions(4));
+        assert_eq!(Some(100), settings.batch_size);
+        assert_eq!(Some(4), settings.target_partitions);
+
+        let job_settings = session.job_settings(&QuerySettings::new().with_batch_size(10));
+        assert_eq!(Some(10), job_settings.batch_size);
+        assert_eq!(Some(4), job_settings.target_partitions);
+    }
+}
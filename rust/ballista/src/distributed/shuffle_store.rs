@@ -0,0 +1,779 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage for the shuffle partitions held by an executor.
+//!
+//! Partitions are encoded as flight data messages, compressed with the codec of the job that
+//! produced them, when they are stored, so that they can be served to other executors without
+//! encoding them again. The messages are held in memory until a memory budget is exceeded, after
+//! which new partitions are written to local disk as a sequence of length-prefixed messages and
+//! streamed back from disk when fetched.
+//!
+//! Storing a partition is atomic: spill files are written under a temporary name and renamed
+//! once complete, and a partition replaces an earlier copy with the same id in a single step,
+//! so that duplicate attempts of a task cannot corrupt a partition that is being read.
+//!
+//! A partition is removed once it has been fetched, unless jobs hold it so that more than one
+//! job can read it, in which case it is kept until every job holding it has been released.
+//!
+//! When external storage is configured, every partition is written there instead of being
+//! held by the executor, and is left in place when the store is dropped so that it can still
+//! be read once the executor is gone.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::record_batch::RecordBatch;
+use crate::distributed::compression::ShuffleCompression;
+use crate::distributed::executor::{
+    FlightDataStream, RecordBatchStream, ShufflePartition, ShufflePartitionMeta,
+};
+use crate::distributed::external_shuffle::ExternalShuffleStorage;
+use crate::distributed::flight_data::{
+    FlightDataDecoder, FlightDataEncoder, DEFAULT_MAX_MESSAGE_SIZE,
+};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::physical_plan::{ColumnarBatch, ShuffleId};
+use crate::flight::FlightData;
+
+use log::{debug, info, warn};
+use prost::Message;
+use uuid::Uuid;
+
+enum StoredPartition {
+    /// The flight data messages of the partition
+    InMemory(Vec<FlightData>),
+    /// File of length-prefixed flight data messages
+    OnDisk(PathBuf),
+    /// Object in external storage, at the given path
+    External(String),
+}
+
+struct StoredShuffle {
+    meta: ShufflePartitionMeta,
+    partition: StoredPartition,
+    /// Jobs that hold the partition, which is read without being removed while any do
+    holders: HashSet<Uuid>,
+}
+
+struct ShuffleStoreState {
+    shuffles: HashMap<ShuffleId, StoredShuffle>,
+    /// Number of bytes held in memory
+    memory_used: usize,
+}
+
+/// Shuffle partition storage with a memory budget
+pub struct ShuffleStore {
+    /// Directory that spilled partitions are written to
+    work_dir: PathBuf,
+    /// Maximum number of bytes of shuffle data to hold in memory
+    memory_budget: usize,
+    /// Max size of the flight data messages that partitions are encoded as
+    max_message_size: usize,
+    /// Shared storage that partitions are written to instead of memory or local disk, if any
+    external: Option<ExternalShuffleStorage>,
+    state: Mutex<ShuffleStoreState>,
+}
+
+impl ShuffleStore {
+    pub fn new(work_dir: PathBuf, memory_budget: usize) -> Self {
+        Self {
+            work_dir,
+            memory_budget,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            external: None,
+            state: Mutex::new(ShuffleStoreState {
+                shuffles: HashMap::new(),
+                memory_used: 0,
+            }),
+        }
+    }
+
+    /// Split batches into flight data messages of at most `max_message_size` bytes
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Write every partition to shared storage, so that it outlives this executor
+    pub fn with_external_storage(mut self, storage: ExternalShuffleStorage) -> Self {
+        self.external = Some(storage);
+        self
+    }
+
+    /// Store a shuffle partition, spilling it to disk if it does not fit in the memory budget
+    pub fn store(&self, shuffle_id: &ShuffleId, partition: ShufflePartition) -> Result<()> {
+        self.store_with_limit(shuffle_id, partition, None)
+    }
+
+    /// Store a shuffle partition, spilling it to disk if it does not fit in the memory budget
+    /// or is larger than `memory_limit` bytes
+    pub fn store_with_limit(
+        &self,
+        shuffle_id: &ShuffleId,
+        partition: ShufflePartition,
+        memory_limit: Option<usize>,
+    ) -> Result<()> {
+        let meta = ShufflePartitionMeta {
+            shuffle_id: *shuffle_id,
+            schema: partition.schema.clone(),
+            num_rows: partition.data.iter().map(|b| b.num_rows()).sum(),
+            num_bytes: partition
+                .data
+                .iter()
+                .map(|b| ColumnarBatch::from_arrow(b).memory_size())
+                .sum(),
+            compression: partition.compression,
+        };
+
+        let mut encoder = FlightDataEncoder::new(partition.compression)
+            .with_max_message_size(self.max_message_size);
+        let mut flights = vec![];
+        for batch in &partition.data {
+            flights.extend(encoder.encode(batch)?);
+        }
+
+        // the object of a partition is written before the lock is taken, since writing to
+        // remote storage is slow
+        let external = match &self.external {
+            Some(storage) => Some(storage.write(shuffle_id, &meta.schema, &flights)?),
+            None => None,
+        };
+
+        // the memory of a partition that fits is reserved under the lock, but a partition that
+        // does not fit is spilled after the lock is released, so that other stores and fetches
+        // do not wait for the file to be written
+        let within_limit = memory_limit.map_or(true, |limit| meta.num_bytes <= limit);
+        let partition = if let Some(path) = external {
+            StoredPartition::External(path)
+        } else if within_limit && self.reserve_memory(meta.num_bytes) {
+            StoredPartition::InMemory(flights)
+        } else {
+            let path = self.spill(shuffle_id, partition.compression, &flights)?;
+            info!(
+                "Spilled shuffle partition job_uuid={} stage_id={} partition_id={} bytes={} compression={}",
+                shuffle_id.job_uuid,
+                shuffle_id.stage_id,
+                shuffle_id.partition_id,
+                meta.num_bytes,
+                meta.compression
+            );
+            StoredPartition::OnDisk(path)
+        };
+
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        // replace any existing partition with the same id, such as the output of an earlier
+        // attempt of the task that produced it, which is still held by the same jobs
+        let holders = state
+            .shuffles
+            .get(shuffle_id)
+            .map(|s| s.holders.clone())
+            .unwrap_or_default();
+        let previous = state.shuffles.insert(
+            *shuffle_id,
+            StoredShuffle {
+                meta,
+                partition,
+                holders,
+            },
+        );
+        if let Some(StoredShuffle {
+            partition: StoredPartition::InMemory(_),
+            meta,
+            ..
+        }) = &previous
+        {
+            state.memory_used -= meta.num_bytes;
+        }
+        drop(state);
+        if let Some(previous) = previous {
+            debug!(
+                "Replaced shuffle partition job_uuid={} stage_id={} partition_id={}",
+                shuffle_id.job_uuid, shuffle_id.stage_id, shuffle_id.partition_id
+            );
+            // the object of an earlier copy in external storage has been overwritten, so
+            // only a spill file is deleted
+            delete_spill_file(previous);
+        }
+        Ok(())
+    }
+
+    /// Reserve memory for a partition if it fits in the memory budget
+    fn reserve_memory(&self, num_bytes: usize) -> bool {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        if state.memory_used.saturating_add(num_bytes) <= self.memory_budget {
+            state.memory_used += num_bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// List the shuffle partitions held by this store
+    pub fn list(&self) -> Vec<ShufflePartitionMeta> {
+        let state = self.state.lock().expect("failed to lock mutex");
+        state.shuffles.values().map(|s| s.meta.clone()).collect()
+    }
+
+    /// Hold a shuffle partition for a job, so that it is kept when it is fetched until the job
+    /// and the job that produced it have both been released
+    pub fn retain(&self, shuffle_id: &ShuffleId, job_uuid: &Uuid) -> Result<()> {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        let shuffle = state.shuffles.get_mut(shuffle_id).ok_or_else(|| {
+            ballista_error(&format!("invalid shuffle partition id {:?}", shuffle_id))
+        })?;
+        shuffle.holders.insert(shuffle_id.job_uuid);
+        shuffle.holders.insert(*job_uuid);
+        debug!(
+            "Retained shuffle partition job_uuid={} stage_id={} partition_id={} holders={}",
+            shuffle_id.job_uuid,
+            shuffle_id.stage_id,
+            shuffle_id.partition_id,
+            shuffle.holders.len()
+        );
+        Ok(())
+    }
+
+    /// Remove a shuffle partition and return its batches as a stream, along with its summary.
+    /// Spilled partitions are read back from disk incrementally and the file is deleted once
+    /// the stream is dropped. Partitions that jobs hold are read without being removed.
+    pub fn take(
+        &self,
+        shuffle_id: &ShuffleId,
+    ) -> Result<(ShufflePartitionMeta, RecordBatchStream)> {
+        let (meta, flights) = self.take_flights(shuffle_id)?;
+        let batches = DecodedBatches {
+            flights,
+            decoder: FlightDataDecoder::new(Arc::new(meta.schema.clone())),
+        };
+        Ok((meta, Box::pin(futures::stream::iter(batches))))
+    }
+
+    /// Remove a shuffle partition and return the flight data messages that it was stored as,
+    /// which can be sent to clients as they are
+    pub fn take_flight_data(
+        &self,
+        shuffle_id: &ShuffleId,
+    ) -> Result<(ShufflePartitionMeta, FlightDataStream)> {
+        let (meta, flights) = self.take_flights(shuffle_id)?;
+        Ok((meta, Box::pin(futures::stream::iter(flights))))
+    }
+
+    fn take_flights(&self, shuffle_id: &ShuffleId) -> Result<(ShufflePartitionMeta, Flights)> {
+        if let Some(held) = self.read_held(shuffle_id) {
+            return held;
+        }
+        match self.remove_entry(shuffle_id) {
+            Some(StoredShuffle {
+                partition: StoredPartition::InMemory(flights),
+                meta,
+                ..
+            }) => Ok((meta, Box::new(flights.into_iter().map(Ok)))),
+            Some(StoredShuffle {
+                partition: StoredPartition::OnDisk(path),
+                meta,
+                ..
+            }) => Ok((meta, Box::new(SpillFileReader::try_new(path)?))),
+            Some(StoredShuffle {
+                partition: StoredPartition::External(_),
+                meta,
+                ..
+            }) => {
+                let flights = self.read_external(&meta.shuffle_id)?;
+                self.delete_external(&meta.shuffle_id);
+                Ok((meta, Box::new(flights.into_iter().map(Ok))))
+            }
+            None => Err(ballista_error(&format!(
+                "invalid shuffle partition id {:?}",
+                shuffle_id
+            ))),
+        }
+    }
+
+    /// Read a copy of a shuffle partition that jobs hold, leaving the partition in place
+    fn read_held(&self, shuffle_id: &ShuffleId) -> Option<Result<(ShufflePartitionMeta, Flights)>> {
+        let state = self.state.lock().expect("failed to lock mutex");
+        let shuffle = state.shuffles.get(shuffle_id)?;
+        if shuffle.holders.is_empty() {
+            return None;
+        }
+        let meta = shuffle.meta.clone();
+        let flights: Result<Flights> = match &shuffle.partition {
+            StoredPartition::InMemory(flights) => Ok(Box::new(flights.clone().into_iter().map(Ok))),
+            StoredPartition::OnDisk(path) => {
+                SpillFileReader::try_new_shared(path.clone()).map(|r| Box::new(r) as Flights)
+            }
+            StoredPartition::External(_) => {
+                // reading from remote storage is slow, so the object is read after the lock is
+                // released
+                drop(state);
+                self.read_external(shuffle_id)
+                    .map(|flights| Box::new(flights.into_iter().map(Ok)) as Flights)
+            }
+        };
+        Some(flights.map(|flights| (meta, flights)))
+    }
+
+    /// Release the shuffle partitions that a job produced or holds, removing the ones that no
+    /// other job holds, and return how many were removed
+    pub fn evict_job(&self, job_uuid: &Uuid) -> usize {
+        let shuffle_ids: Vec<ShuffleId> = {
+            let mut state = self.state.lock().expect("failed to lock mutex");
+            state
+                .shuffles
+                .iter_mut()
+                .filter_map(|(id, shuffle)| {
+                    let evicted = if shuffle.holders.is_empty() {
+                        &id.job_uuid == job_uuid
+                    } else {
+                        shuffle.holders.remove(job_uuid) && shuffle.holders.is_empty()
+                    };
+                    Some(*id).filter(|_| evicted)
+                })
+                .collect()
+        };
+        for shuffle_id in &shuffle_ids {
+            self.remove(shuffle_id);
+        }
+        debug!(
+            "Evicted shuffle partitions job_uuid={} count={}",
+            job_uuid,
+            shuffle_ids.len()
+        );
+        shuffle_ids.len()
+    }
+
+    /// Remove all shuffle partitions and return how many were removed
+    pub fn clear(&self) -> usize {
+        let shuffles = self.drain();
+        let count = shuffles.len();
+        for shuffle in shuffles {
+            self.delete(shuffle);
+        }
+        count
+    }
+
+    /// Remove all shuffle partitions from the index and release their share of the memory
+    /// budget. Memory that is reserved for partitions that are still being stored stays
+    /// reserved, as it is released when those partitions are removed.
+    fn drain(&self) -> Vec<StoredShuffle> {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        let shuffles: Vec<StoredShuffle> = state.shuffles.drain().map(|(_, s)| s).collect();
+        for shuffle in &shuffles {
+            if let StoredPartition::InMemory(_) = shuffle.partition {
+                state.memory_used -= shuffle.meta.num_bytes;
+            }
+        }
+        shuffles
+    }
+
+    /// Remove a shuffle partition and delete its spill file or object, if any
+    fn remove(&self, shuffle_id: &ShuffleId) {
+        if let Some(shuffle) = self.remove_entry(shuffle_id) {
+            self.delete(shuffle);
+        }
+    }
+
+    /// Delete the spill file or the object in external storage of a removed partition
+    fn delete(&self, shuffle: StoredShuffle) {
+        if let StoredPartition::External(_) = shuffle.partition {
+            self.delete_external(&shuffle.meta.shuffle_id);
+        } else {
+            delete_spill_file(shuffle);
+        }
+    }
+
+    fn read_external(&self, shuffle_id: &ShuffleId) -> Result<Vec<FlightData>> {
+        match &self.external {
+            Some(storage) => storage.read_flights(shuffle_id),
+            None => Err(ballista_error("No external shuffle storage is configured")),
+        }
+    }
+
+    fn delete_external(&self, shuffle_id: &ShuffleId) {
+        if let Some(storage) = &self.external {
+            if let Err(e) = storage.delete(shuffle_id) {
+                warn!(
+                    "Failed to delete shuffle object path={} error={:?}",
+                    storage.path(shuffle_id),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Remove a shuffle partition from the index and release its share of the memory budget
+    fn remove_entry(&self, shuffle_id: &ShuffleId) -> Option<StoredShuffle> {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        let shuffle = state.shuffles.remove(shuffle_id)?;
+        if let StoredPartition::InMemory(_) = shuffle.partition {
+            state.memory_used -= shuffle.meta.num_bytes;
+        }
+        Some(shuffle)
+    }
+
+    /// Write the flight data messages of a partition to a new file, each prefixed with its
+    /// length. The file is written under a temporary name and renamed once it is complete.
+    fn spill(
+        &self,
+        shuffle_id: &ShuffleId,
+        compression: ShuffleCompression,
+        flights: &[FlightData],
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(&self.work_dir)?;
+        let extension = match compression {
+            ShuffleCompression::None => "flight".to_owned(),
+            compression => format!("flight.{}", compression.name()),
+        };
+        // every copy of a partition has its own file, so that storing a partition again does
+        // not overwrite a file that is being read
+        let name = format!(
+            "{}-{}-{}-{}",
+            shuffle_id.job_uuid,
+            shuffle_id.stage_id,
+            shuffle_id.partition_id,
+            Uuid::new_v4()
+        );
+        let tmp_path = self.work_dir.join(format!("{}.tmp", name));
+        let path = self.work_dir.join(format!("{}.{}", name, extension));
+        let written = write_flights(&tmp_path, flights).and_then(|_| {
+            fs::rename(&tmp_path, &path)?;
+            Ok(())
+        });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        Ok(path)
+    }
+}
+
+impl Drop for ShuffleStore {
+    fn drop(&mut self) {
+        // objects in external storage are kept so that they can be read once this executor
+        // is gone
+        self.drain().into_iter().for_each(delete_spill_file);
+    }
+}
+
+/// Write length-prefixed flight data messages to a file, syncing it to disk
+fn write_flights(path: &Path, flights: &[FlightData]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut buf = vec![];
+    for flight_data in flights {
+        buf.clear();
+        flight_data
+            .encode(&mut buf)
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        writer.write_all(&(buf.len() as u64).to_le_bytes())?;
+        writer.write_all(&buf)?;
+    }
+    let file = writer
+        .into_inner()
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn delete_spill_file(shuffle: StoredShuffle) {
+    if let StoredPartition::OnDisk(path) = shuffle.partition {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Failed to delete spill file {:?}: {:?}", path, e);
+        }
+    }
+}
+
+/// The flight data messages of a partition
+type Flights = Box<dyn Iterator<Item = Result<FlightData>> + Send + Sync>;
+
+/// Reads the flight data messages of a spilled partition and deletes the file when dropped,
+/// unless the partition is still held by jobs
+struct SpillFileReader {
+    reader: BufReader<File>,
+    path: PathBuf,
+    shared: bool,
+}
+
+impl SpillFileReader {
+    fn try_new(path: PathBuf) -> Result<Self> {
+        let reader = BufReader::new(File::open(&path)?);
+        Ok(Self {
+            reader,
+            path,
+            shared: false,
+        })
+    }
+
+    /// Read a spill file that is left in place when the reader is dropped
+    fn try_new_shared(path: PathBuf) -> Result<Self> {
+        let reader = BufReader::new(File::open(&path)?);
+        Ok(Self {
+            reader,
+            path,
+            shared: true,
+        })
+    }
+
+    fn read_flight_data(&mut self) -> Result<Option<FlightData>> {
+        let mut len = [0u8; 8];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut buf = vec![0u8; u64::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut buf)?;
+        let flight_data = FlightData::decode(buf.as_slice())
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+        Ok(Some(flight_data))
+    }
+}
+
+impl Iterator for SpillFileReader {
+    type Item = Result<FlightData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_flight_data().transpose()
+    }
+}
+
+impl Drop for SpillFileReader {
+    fn drop(&mut self) {
+        if self.shared {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to delete spill file {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+/// Decodes the flight data messages of a partition into batches
+struct DecodedBatches {
+    flights: Flights,
+    decoder: FlightDataDecoder,
+}
+
+impl Iterator for DecodedBatches {
+    type Item = Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for flight_data in &mut self.flights {
+            // messages that carry dictionaries are kept by the decoder
+            match flight_data.and_then(|flight_data| self.decoder.decode(flight_data)) {
+                Ok(Some(batch)) => return Some(Ok(batch)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use futures::TryStreamExt;
+
+    #[test]
+    fn serve_spilled_partition_as_stored_flight_data() -> Result<()> {
+        smol::run(async {
+            let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+            )?;
+            let partition = || ShufflePartition {
+                schema: schema.clone(),
+                data: vec![batch.clone(), batch.clone()],
+                compression: ShuffleCompression::Lz4,
+            };
+            let dir = std::env::temp_dir().join("ballista-shuffle-store-test");
+            // a budget of zero bytes spills every partition
+            let store = ShuffleStore::new(dir, 0);
+            let shuffle_id = ShuffleId::new(Uuid::new_v4(), 1, 0);
+
+            store.store(&shuffle_id, partition())?;
+            let (meta, flights) = store.take_flight_data(&shuffle_id)?;
+            assert_eq!(6, meta.num_rows);
+            let flights: Vec<FlightData> = flights.try_collect().await?;
+            assert_eq!(2, flights.len());
+            assert_eq!(
+                ShuffleCompression::Lz4,
+                ShuffleCompression::of_flight_data(&flights[0])?
+            );
+
+            store.store(&shuffle_id, partition())?;
+            let (_, batches) = store.take(&shuffle_id)?;
+            let batches: Vec<RecordBatch> = batches.try_collect().await?;
+            assert_eq!(2, batches.len());
+            assert_eq!(3, batches[1].num_rows());
+            assert!(store.list().is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn clear_keeps_memory_reserved_for_partitions_being_stored() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let dir = std::env::temp_dir().join("ballista-shuffle-store-clear-test");
+        let store = ShuffleStore::new(dir, 1 << 20);
+        let shuffle_id = ShuffleId::new(Uuid::new_v4(), 1, 0);
+        store.store(
+            &shuffle_id,
+            ShufflePartition {
+                schema,
+                data: vec![batch],
+                compression: ShuffleCompression::None,
+            },
+        )?;
+
+        // a partition that is being stored has reserved its memory but is not yet in the index
+        assert!(store.reserve_memory(100));
+        assert_eq!(1, store.clear());
+        assert_eq!(100, store.state.lock().unwrap().memory_used);
+        Ok(())
+    }
+
+    #[test]
+    fn store_duplicate_partition_while_it_is_read() -> Result<()> {
+        smol::run(async {
+            let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+            let partition = |values: Vec<i32>| -> Result<ShufflePartition> {
+                let batch = RecordBatch::try_new(
+                    Arc::new(schema.clone()),
+                    vec![Arc::new(Int32Array::from(values))],
+                )?;
+                Ok(ShufflePartition {
+                    schema: schema.clone(),
+                    data: vec![batch],
+                    compression: ShuffleCompression::None,
+                })
+            };
+            let dir = std::env::temp_dir().join("ballista-shuffle-store-test");
+            let store = ShuffleStore::new(dir, 0);
+            let shuffle_id = ShuffleId::new(Uuid::new_v4(), 1, 0);
+
+            store.store(&shuffle_id, partition(vec![1, 2, 3])?)?;
+            let (_, first) = store.take(&shuffle_id)?;
+            // a duplicate attempt stores the partition again before the first copy is read
+            store.store(&shuffle_id, partition(vec![4, 5])?)?;
+            store.store(&shuffle_id, partition(vec![6])?)?;
+            assert_eq!(1, store.list().len());
+
+            let first: Vec<RecordBatch> = first.try_collect().await?;
+            assert_eq!(3, first[0].num_rows());
+            let (_, last) = store.take(&shuffle_id)?;
+            let last: Vec<RecordBatch> = last.try_collect().await?;
+            assert_eq!(1, last[0].num_rows());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn held_partition_is_kept_until_every_holder_is_released() -> Result<()> {
+        smol::run(async {
+            let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+            )?;
+            let dir = std::env::temp_dir().join("ballista-shuffle-store-test");
+            for memory_budget in &[usize::MAX, 0] {
+                let store = ShuffleStore::new(dir.clone(), *memory_budget);
+                let producer = Uuid::new_v4();
+                let reader = Uuid::new_v4();
+                let shuffle_id = ShuffleId::new(producer, 1, 0);
+                store.store(
+                    &shuffle_id,
+                    ShufflePartition {
+                        schema: schema.clone(),
+                        data: vec![batch.clone()],
+                        compression: ShuffleCompression::None,
+                    },
+                )?;
+                assert!(store
+                    .retain(&ShuffleId::new(reader, 1, 0), &reader)
+                    .is_err());
+                store.retain(&shuffle_id, &reader)?;
+
+                // held partitions are read without being removed
+                for _ in 0..2 {
+                    let (_, batches) = store.take(&shuffle_id)?;
+                    let batches: Vec<RecordBatch> = batches.try_collect().await?;
+                    assert_eq!(3, batches[0].num_rows());
+                }
+                assert_eq!(0, store.evict_job(&producer));
+                assert_eq!(1, store.list().len());
+                assert_eq!(1, store.evict_job(&reader));
+                assert!(store.list().is_empty());
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn external_partition_outlives_store() -> Result<()> {
+        smol::run(async {
+            let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+            let batch = RecordBatch::try_new(
+                Arc::new(schema.clone()),
+                vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+            )?;
+            let root = std::env::temp_dir().join("ballista-external-shuffle-test");
+            let storage = ExternalShuffleStorage::new(root.to_str().unwrap());
+            let shuffle_id = ShuffleId::new(Uuid::new_v4(), 1, 0);
+            let store = ShuffleStore::new(std::env::temp_dir(), usize::MAX)
+                .with_external_storage(storage.clone());
+            store.store(
+                &shuffle_id,
+                ShufflePartition {
+                    schema: schema.clone(),
+                    data: vec![batch.clone(), batch],
+                    compression: ShuffleCompression::Lz4,
+                },
+            )?;
+            assert!(storage.exists(&shuffle_id));
+
+            // the partition can be read by other executors once this one is gone
+            drop(store);
+            let batches = storage.read(&shuffle_id)?;
+            assert_eq!(2, batches.len());
+            assert_eq!(3, batches[1].num_rows());
+
+            let store = ShuffleStore::new(std::env::temp_dir(), usize::MAX)
+                .with_external_storage(storage.clone());
+            store.store(
+                &shuffle_id,
+                ShufflePartition {
+                    schema: schema.clone(),
+                    data: vec![],
+                    compression: ShuffleCompression::None,
+                },
+            )?;
+            assert_eq!(1, store.evict_job(&shuffle_id.job_uuid));
+            assert!(!storage.exists(&shuffle_id));
+            Ok(())
+        })
+    }
+}
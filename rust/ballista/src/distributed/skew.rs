@@ -0,0 +1,231 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splitting of skewed join partitions.
+//!
+//! Each task of a stage whose output is hash-partitioned reports the number of rows in each
+//! partition. Before a stage with a partitioned hash join runs, partitions of the join inputs
+//! that are much larger than the others are split into sub-partitions, so that a single hot
+//! key does not leave one task doing most of the work of the stage. Each sub-partition reads a
+//! share of the rows of the skewed input and all of the matching rows of the other input.
+
+use std::sync::Arc;
+
+use crate::distributed::scheduler::{JobConfig, JobProfile};
+use crate::execution::operators::{HashJoinExec, ShuffleReaderExec};
+use crate::execution::physical_plan::{JoinType, Partitioning, PhysicalPlan};
+
+use log::info;
+
+/// Maximum number of sub-partitions that a skewed partition is split into, which bounds the
+/// number of times the matching rows of the other join input are read
+pub const MAX_SKEW_SPLITS: usize = 32;
+
+/// Split the skewed partitions of the inputs of a partitioned hash join whose inputs are both
+/// read from hash-partitioned shuffles, or return `None` if neither input is skewed
+pub fn split_skewed_join(
+    exec: &HashJoinExec,
+    left: &ShuffleReaderExec,
+    right: &ShuffleReaderExec,
+    profile: &JobProfile,
+    config: &JobConfig,
+) -> Option<HashJoinExec> {
+    let left_sizes = partition_sizes(left, profile)?;
+    let right_sizes = partition_sizes(right, profile)?;
+    if left_sizes.len() != right_sizes.len() {
+        return None;
+    }
+    let split = split_join_partitions(&left_sizes, &right_sizes, &exec.join_type, config)?;
+    info!(
+        "Splitting skewed join partitions join_type={:?} splits={:?}",
+        exec.join_type, split.splits
+    );
+    let left = left
+        .clone()
+        .with_skew_splits(split.splits.clone(), split.left_replicated);
+    let right = right
+        .clone()
+        .with_skew_splits(split.splits.clone(), split.right_replicated);
+    Some(
+        exec.with_new_children(vec![
+            Arc::new(PhysicalPlan::ShuffleReader(Arc::new(left))),
+            Arc::new(PhysicalPlan::ShuffleReader(Arc::new(right))),
+        ])
+        .with_partition_count(split.splits.iter().sum()),
+    )
+}
+
+/// Estimate the size in bytes of each partition of a hash-partitioned shuffle from the metrics
+/// of the stage that produced it
+pub fn partition_sizes(exec: &ShuffleReaderExec, profile: &JobProfile) -> Option<Vec<u64>> {
+    let n = match &exec.partitioning {
+        Partitioning::HashPartitioning(n, _) => *n,
+        _ => return None,
+    };
+    let stage_id = exec.shuffle_id.first()?.stage_id;
+    // the first run of a stage covers all of its tasks, whereas later runs only recompute
+    // partitions that were lost
+    let total = profile
+        .stages
+        .iter()
+        .find(|stage| stage.stage_id == stage_id)?
+        .total();
+    if total.partition_rows.len() != n {
+        return None;
+    }
+    let bytes_per_row = if total.output_rows == 0 {
+        0.0
+    } else {
+        total.shuffle_bytes as f64 / total.output_rows as f64
+    };
+    Some(
+        total
+            .partition_rows
+            .iter()
+            .map(|rows| (*rows as f64 * bytes_per_row) as u64)
+            .collect(),
+    )
+}
+
+/// How the partitions of the inputs of a join are split
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinSplit {
+    /// Number of sub-partitions that each partition is split into
+    pub splits: Vec<usize>,
+    /// Whether every sub-partition of each partition reads all of its rows from the left input
+    pub left_replicated: Vec<bool>,
+    /// Whether every sub-partition of each partition reads all of its rows from the right input
+    pub right_replicated: Vec<bool>,
+}
+
+/// Decide how to split the skewed partitions of the inputs of a join, given the estimated size
+/// of each partition of the left and right inputs, or return `None` if neither is skewed.
+/// Only an input whose rows each appear in the output independently of the other rows of that
+/// input can be split: the left input of inner and left joins, and the right input of inner
+/// and right joins.
+pub fn split_join_partitions(
+    left: &[u64],
+    right: &[u64],
+    join_type: &JoinType,
+    config: &JobConfig,
+) -> Option<JoinSplit> {
+    let no_splits = vec![1; left.len()];
+    let left_splits = match join_type {
+        JoinType::Inner | JoinType::Left => skew_splits(left, config),
+        _ => no_splits.clone(),
+    };
+    let right_splits = match join_type {
+        JoinType::Inner | JoinType::Right => skew_splits(right, config),
+        _ => no_splits,
+    };
+    let mut split = JoinSplit {
+        splits: vec![1; left.len()],
+        left_replicated: vec![false; left.len()],
+        right_replicated: vec![false; left.len()],
+    };
+    for i in 0..left.len() {
+        if left_splits[i] > 1 && left_splits[i] >= right_splits[i] {
+            split.splits[i] = left_splits[i];
+            split.right_replicated[i] = true;
+        } else if right_splits[i] > 1 {
+            split.splits[i] = right_splits[i];
+            split.left_replicated[i] = true;
+        }
+    }
+    if split.splits.iter().all(|n| *n == 1) {
+        None
+    } else {
+        Some(split)
+    }
+}
+
+/// Number of sub-partitions to split each partition into, given its estimated size in bytes.
+/// A partition is skewed when it is larger than the skew threshold and more than the skew
+/// factor times the median partition size, and is split into sub-partitions of roughly the
+/// median size.
+pub fn skew_splits(sizes: &[u64], config: &JobConfig) -> Vec<usize> {
+    let mut sorted = sizes.to_vec();
+    sorted.sort();
+    let median = sorted.get(sorted.len() / 2).cloned().unwrap_or(0).max(1);
+    sizes
+        .iter()
+        .map(|size| {
+            if *size > config.skew_threshold_bytes
+                && *size > median.saturating_mul(config.skew_factor)
+            {
+                (((size + median - 1) / median) as usize).min(MAX_SKEW_SPLITS)
+            } else {
+                1
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn split_skewed_partitions() {
+        let config = JobConfig::default().with_skew_threshold(256 * MB, 5);
+        let sizes = vec![100 * MB, 110 * MB, 90 * MB, 1000 * MB];
+        assert_eq!(vec![1, 1, 1, 10], skew_splits(&sizes, &config));
+
+        // large partitions are not skewed when they are all of a similar size
+        let sizes = vec![500 * MB, 510 * MB, 490 * MB, 1000 * MB];
+        assert_eq!(vec![1, 1, 1, 1], skew_splits(&sizes, &config));
+
+        // small partitions are not worth splitting however uneven they are
+        let sizes = vec![MB, MB, MB, 100 * MB];
+        assert_eq!(vec![1, 1, 1, 1], skew_splits(&sizes, &config));
+
+        // the number of sub-partitions is bounded
+        let sizes = vec![MB, MB, MB, 1000 * MB];
+        assert_eq!(MAX_SKEW_SPLITS, skew_splits(&sizes, &config)[3]);
+    }
+
+    #[test]
+    fn split_join_inputs() {
+        let config = JobConfig::default().with_skew_threshold(256 * MB, 5);
+        let skewed = vec![100 * MB, 100 * MB, 1000 * MB];
+        let even = vec![100 * MB, 100 * MB, 100 * MB];
+
+        // the skewed right input of an inner join is split and the left input is replicated
+        let split = split_join_partitions(&even, &skewed, &JoinType::Inner, &config);
+        assert_eq!(
+            Some(JoinSplit {
+                splits: vec![1, 1, 10],
+                left_replicated: vec![false, false, true],
+                right_replicated: vec![false, false, false],
+            }),
+            split
+        );
+
+        // unmatched rows of the left input of a left join would be repeated in each
+        // sub-partition if the left input were replicated
+        assert_eq!(
+            None,
+            split_join_partitions(&even, &skewed, &JoinType::Left, &config)
+        );
+        let split = split_join_partitions(&skewed, &even, &JoinType::Left, &config).unwrap();
+        assert_eq!(vec![false, false, true], split.right_replicated);
+
+        assert_eq!(
+            None,
+            split_join_partitions(&skewed, &skewed, &JoinType::Full, &config)
+        );
+    }
+}
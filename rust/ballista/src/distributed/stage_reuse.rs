@@ -0,0 +1,174 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reuse of the output of stages across jobs.
+//!
+//! Stages that do not read the output of other stages, such as a scan followed by a filter and
+//! a partial aggregate, are fingerprinted by their plan and the tenant of their job. Once such
+//! a stage of one job completes, the executors hold its shuffle partitions so that reading them
+//! does not remove them, and the partitions are published under the fingerprint of the stage.
+//! A later job with a stage of the same fingerprint holds the published partitions as well and
+//! reads them rather than running the stage again. Executors only discard the partitions once
+//! every job holding them has been released, so that releasing one job does not break the
+//! others.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::distributed::adaptive::visit;
+use crate::execution::physical_plan::{
+    ExecutionContext, ExecutorMeta, PhysicalPlan, ShuffleId, ShuffleLocation,
+};
+use crate::protobuf;
+use crate::utils::expiring_map::ExpiringMap;
+
+use log::{debug, warn};
+use prost::Message;
+use uuid::Uuid;
+
+/// Default maximum number of stages to publish for reuse
+pub const DEFAULT_MAX_SHARED_STAGES: usize = 1000;
+
+/// Fingerprint of the output of a stage of a tenant's job, or `None` if the stage reads the
+/// output of other stages and so cannot be shared
+pub fn stage_fingerprint(plan: &PhysicalPlan, tenant: &str) -> Option<u64> {
+    let mut reads_shuffles = false;
+    visit(plan, &mut |plan| {
+        if let PhysicalPlan::ShuffleReader(_) = plan {
+            reads_shuffles = true;
+        }
+    });
+    if reads_shuffles {
+        return None;
+    }
+    let node: protobuf::PhysicalPlanNode = match plan.try_into() {
+        Ok(node) => node,
+        Err(e) => {
+            debug!("Failed to fingerprint stage error={:?}", e);
+            return None;
+        }
+    };
+    let mut encoded = Vec::with_capacity(node.encoded_len());
+    node.encode(&mut encoded).ok()?;
+    let mut hasher = DefaultHasher::new();
+    tenant.hash(&mut hasher);
+    encoded.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Shuffle partitions of completed stages that later jobs may read, by the fingerprint of the
+/// stage. Stages are published for the time-to-live after they completed.
+pub struct SharedStages {
+    ttl: Duration,
+    stages: Mutex<ExpiringMap<(Vec<ShuffleLocation>, Instant)>>,
+}
+
+impl SharedStages {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            stages: Mutex::new(ExpiringMap::new(ttl, DEFAULT_MAX_SHARED_STAGES, |_| true)),
+        }
+    }
+
+    /// Publish the shuffle partitions of a stage that has completed, which the executors hold
+    pub fn publish(&self, fingerprint: u64, locations: Vec<ShuffleLocation>) {
+        debug!(
+            "Published stage fingerprint={} partitions={}",
+            fingerprint,
+            locations.len()
+        );
+        let mut stages = self.stages.lock().expect("failed to lock mutex");
+        stages.insert(fingerprint.to_string(), (locations, Instant::now()));
+    }
+
+    /// The shuffle partitions of a recently completed stage with the fingerprint
+    pub fn lookup(&self, fingerprint: u64) -> Option<Vec<ShuffleLocation>> {
+        let stages = self.stages.lock().expect("failed to lock mutex");
+        // expired stages are only evicted when others are published
+        match stages.get(&fingerprint.to_string()) {
+            Some((locations, published_at)) if published_at.elapsed() < self.ttl => {
+                Some(locations.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Stop publishing a stage whose shuffle partitions are no longer held
+    pub fn remove(&self, fingerprint: u64) {
+        let mut stages = self.stages.lock().expect("failed to lock mutex");
+        stages.remove(&fingerprint.to_string());
+    }
+}
+
+/// Ask the executors holding shuffle partitions to keep them for a job, returning false if
+/// any executor no longer holds them
+pub async fn retain_shuffles(
+    ctx: &dyn ExecutionContext,
+    job_uuid: Uuid,
+    locations: &[ShuffleLocation],
+) -> bool {
+    let mut by_executor: HashMap<String, (ExecutorMeta, Vec<ShuffleId>)> = HashMap::new();
+    for loc in locations {
+        by_executor
+            .entry(loc.executor_meta.id.clone())
+            .or_insert_with(|| (loc.executor_meta.clone(), vec![]))
+            .1
+            .push(loc.shuffle_id);
+    }
+    for (executor, shuffle_ids) in by_executor.into_iter().map(|(_, v)| v) {
+        if let Err(e) = ctx
+            .retain_shuffles(executor.clone(), job_uuid, shuffle_ids)
+            .await
+        {
+            warn!(
+                "Failed to retain shuffle partitions job_uuid={} executor_id={} error={:?}",
+                job_uuid, executor.id, e
+            );
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_stages_until_expired() {
+        let locations = vec![ShuffleLocation::new(
+            ShuffleId::new(Uuid::new_v4(), 0, 0),
+            ExecutorMeta {
+                id: "e1".to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+            },
+        )];
+        let shared = SharedStages::new(Duration::from_secs(60));
+        assert_eq!(None, shared.lookup(1));
+        shared.publish(1, locations.clone());
+        assert_eq!(Some(locations.clone()), shared.lookup(1));
+        shared.remove(1);
+        assert_eq!(None, shared.lookup(1));
+
+        let expired = SharedStages::new(Duration::from_millis(0));
+        expired.publish(1, locations);
+        assert_eq!(None, expired.lookup(1));
+    }
+}
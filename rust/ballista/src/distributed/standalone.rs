@@ -0,0 +1,361 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Clusters of a scheduler and executors on localhost, for local development and for tests
+//! that exercise the full distributed path.
+//!
+//! The first executor acts as the registry that the other executors and the scheduler discover
+//! the cluster through, so no etcd or Kubernetes is needed. The processes either run in this
+//! process, as servers on the tokio runtime, or as child processes started from the `executor`
+//! and `scheduler` binaries.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::distributed::auth::{anonymous_interceptor, ANONYMOUS_IDENTITY};
+use crate::distributed::executor::{BallistaExecutor, DiscoveryMode, Executor, ExecutorConfig};
+#[cfg(feature = "fault-injection")]
+use crate::distributed::fault_injection::{FaultInjectionConfig, FaultInjector};
+use crate::distributed::flight_service::BallistaFlightService;
+use crate::distributed::registry::registry_get_executors;
+use crate::distributed::scheduler::JobConfig;
+use crate::distributed::scheduler_server::SchedulerServer;
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::ExecutorMeta;
+use crate::flight::flight_service_server::FlightServiceServer;
+use crate::protobuf::scheduler_grpc_server::SchedulerGrpcServer;
+
+use futures::channel::oneshot;
+use log::{error, info, warn};
+use tonic::transport::Server;
+
+/// Host that the processes of a standalone cluster listen on
+pub const STANDALONE_HOST: &str = "localhost";
+
+/// Default port of the scheduler of a standalone cluster
+pub const DEFAULT_SCHEDULER_PORT: usize = 50050;
+
+/// Default port of the first executor of a standalone cluster, with the other executors on
+/// the ports that follow it
+pub const DEFAULT_EXECUTOR_PORT: usize = 50051;
+
+/// Layout of a standalone cluster
+#[derive(Debug, Clone)]
+pub struct StandaloneConfig {
+    /// Number of executors
+    pub executors: usize,
+    pub scheduler_port: usize,
+    /// Port of the first executor, with the other executors on the ports that follow it
+    pub executor_port: usize,
+    /// Number of tasks that each executor runs concurrently
+    pub concurrent_tasks: usize,
+    /// Max number of tasks that each executor queues
+    pub queue_depth: usize,
+    /// Batch size, partitioning and skew handling of the jobs of the cluster
+    pub job_config: JobConfig,
+    /// Faults injected by the executors other than the first, when they run in this process
+    #[cfg(feature = "fault-injection")]
+    pub faults: Option<FaultInjectionConfig>,
+}
+
+impl StandaloneConfig {
+    pub fn new(executors: usize) -> Self {
+        Self {
+            executors,
+            scheduler_port: DEFAULT_SCHEDULER_PORT,
+            executor_port: DEFAULT_EXECUTOR_PORT,
+            concurrent_tasks: 2,
+            queue_depth: 1024,
+            job_config: JobConfig::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: None,
+        }
+    }
+
+    /// Listen on the given port for the scheduler and on the ports from `executor_port` for
+    /// the executors
+    pub fn with_ports(mut self, scheduler_port: usize, executor_port: usize) -> Self {
+        self.scheduler_port = scheduler_port;
+        self.executor_port = executor_port;
+        self
+    }
+
+    pub fn with_concurrent_tasks(mut self, concurrent_tasks: usize) -> Self {
+        self.concurrent_tasks = concurrent_tasks;
+        self
+    }
+
+    pub fn with_job_config(mut self, job_config: JobConfig) -> Self {
+        self.job_config = job_config;
+        self
+    }
+
+    /// Inject faults into the executors other than the first, which serves the registry and
+    /// the clients of the cluster
+    #[cfg(feature = "fault-injection")]
+    pub fn with_fault_injection(mut self, faults: FaultInjectionConfig) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Ports of the executors, the first of which is the registry
+    pub fn executor_ports(&self) -> Vec<usize> {
+        (0..self.executors)
+            .map(|i| self.executor_port + i)
+            .collect()
+    }
+
+    fn discovery_mode(&self) -> DiscoveryMode {
+        DiscoveryMode::Registry {
+            host: STANDALONE_HOST.to_owned(),
+            port: self.executor_port,
+        }
+    }
+
+    /// Configuration of the executor, or of the scheduler, listening on the given port
+    pub fn executor_config(&self, port: usize) -> ExecutorConfig {
+        ExecutorConfig::new(self.discovery_mode(), STANDALONE_HOST, port, "")
+            .with_resources(self.concurrent_tasks, 0)
+            .with_job_config(self.job_config)
+    }
+}
+
+/// A scheduler and executors running on localhost
+pub struct StandaloneCluster {
+    config: StandaloneConfig,
+    /// Flight services of the executors, when they run in this process
+    executors: Vec<BallistaFlightService>,
+    /// The scheduler, when it runs in this process
+    scheduler: Option<SchedulerServer>,
+    scheduler_shutdown: Mutex<Option<oneshot::Sender<()>>>,
+    /// Executors and the scheduler, when they run as child processes
+    processes: Mutex<Vec<Child>>,
+}
+
+impl StandaloneCluster {
+    /// Start the executors and the scheduler in this process, which must be running in a
+    /// tokio runtime, and wait for the executors to join the cluster
+    pub async fn start(config: StandaloneConfig) -> Result<Self> {
+        if config.executors == 0 {
+            return Err(ballista_error("A cluster needs at least one executor"));
+        }
+        let mut executors = vec![];
+        for port in config.executor_ports() {
+            let executor: Arc<dyn Executor> =
+                Arc::new(BallistaExecutor::new(config.executor_config(port)));
+            // the processes of a standalone cluster do not authenticate, so the scheduler and
+            // the other executors manage this executor as anonymous clients
+            let service =
+                BallistaFlightService::new(executor, config.concurrent_tasks, config.queue_depth)
+                    .with_resources(config.concurrent_tasks, 0)
+                    .with_admins(vec![ANONYMOUS_IDENTITY.to_owned()]);
+            #[cfg(feature = "fault-injection")]
+            let service = match &config.faults {
+                Some(faults) if port != config.executor_port => {
+                    service.with_fault_injection(FaultInjector::new(faults.clone()))
+                }
+                _ => service,
+            };
+            serve_executor(service.clone(), port)?;
+            executors.push(service);
+        }
+
+        let scheduler = SchedulerServer::new(config.executor_config(config.scheduler_port))
+            .with_admins(vec![ANONYMOUS_IDENTITY.to_owned()]);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let addr = listen_addr(config.scheduler_port)?;
+        let server =
+            SchedulerGrpcServer::with_interceptor(scheduler.clone(), anonymous_interceptor());
+        tokio::spawn(async move {
+            let shutdown = async move {
+                let _ = shutdown_rx.await;
+            };
+            if let Err(e) = Server::builder()
+                .add_service(server)
+                .serve_with_shutdown(addr, shutdown)
+                .await
+            {
+                error!("Scheduler failed: {:?}", e);
+            }
+        });
+
+        let cluster = Self {
+            config,
+            executors,
+            scheduler: Some(scheduler),
+            scheduler_shutdown: Mutex::new(Some(shutdown_tx)),
+            processes: Mutex::new(vec![]),
+        };
+        cluster.wait_for_executors(Duration::from_secs(30)).await?;
+        info!(
+            "Started standalone cluster executors={} scheduler_port={}",
+            cluster.config.executors, cluster.config.scheduler_port
+        );
+        Ok(cluster)
+    }
+
+    /// Start the executors and the scheduler as child processes, from the `executor` and
+    /// `scheduler` binaries in `bin_dir`, and wait for the executors to join the cluster. The
+    /// processes are killed when the cluster is dropped.
+    pub async fn spawn(config: StandaloneConfig, bin_dir: &Path) -> Result<Self> {
+        if config.executors == 0 {
+            return Err(ballista_error("A cluster needs at least one executor"));
+        }
+        let registry = format!("{}:{}", STANDALONE_HOST, config.executor_port);
+        let mut processes = vec![];
+        for port in config.executor_ports() {
+            let child = Command::new(bin_dir.join("executor"))
+                .args(&["--mode", "registry", "--registry", &registry])
+                .args(&["--external-host", STANDALONE_HOST])
+                .args(&["--port", &port.to_string()])
+                .args(&["--concurrent-tasks", &config.concurrent_tasks.to_string()])
+                .args(&["--admins", ANONYMOUS_IDENTITY])
+                .spawn()?;
+            processes.push(child);
+        }
+        let child = Command::new(bin_dir.join("scheduler"))
+            .args(&["--mode", "registry", "--registry", &registry])
+            .args(&["--port", &config.scheduler_port.to_string()])
+            .args(&["--admins", ANONYMOUS_IDENTITY])
+            .spawn()?;
+        processes.push(child);
+
+        let cluster = Self {
+            config,
+            executors: vec![],
+            scheduler: None,
+            scheduler_shutdown: Mutex::new(None),
+            processes: Mutex::new(processes),
+        };
+        cluster.wait_for_executors(Duration::from_secs(30)).await?;
+        info!(
+            "Started standalone cluster of child processes executors={} scheduler_port={}",
+            cluster.config.executors, cluster.config.scheduler_port
+        );
+        Ok(cluster)
+    }
+
+    pub fn config(&self) -> &StandaloneConfig {
+        &self.config
+    }
+
+    pub fn scheduler_port(&self) -> usize {
+        self.config.scheduler_port
+    }
+
+    /// The scheduler, when it runs in this process
+    pub fn scheduler(&self) -> Option<&SchedulerServer> {
+        self.scheduler.as_ref()
+    }
+
+    /// Flight services of the executors, when they run in this process
+    pub fn executors(&self) -> &[BallistaFlightService] {
+        &self.executors
+    }
+
+    /// The executors that have joined the cluster
+    pub async fn live_executors(&self) -> Result<Vec<ExecutorMeta>> {
+        registry_get_executors(STANDALONE_HOST, self.config.executor_port, None, None).await
+    }
+
+    /// Wait until every executor has registered with the registry
+    pub async fn wait_for_executors(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let live = self.live_executors().await.map(|e| e.len()).unwrap_or(0);
+            if live >= self.config.executors {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(ballista_error(&format!(
+                    "Only {} of {} executors joined the cluster within {:?}",
+                    live, self.config.executors, timeout
+                )));
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Stop the scheduler and shut the executors down gracefully, or kill the child processes
+    pub fn shutdown(&self) {
+        if let Some(shutdown_tx) = self
+            .scheduler_shutdown
+            .lock()
+            .expect("failed to lock mutex")
+            .take()
+        {
+            let _ = shutdown_tx.send(());
+        }
+        for executor in &self.executors {
+            executor.shutdown();
+        }
+        let mut processes = self.processes.lock().expect("failed to lock mutex");
+        for mut child in processes.drain(..) {
+            if let Err(e) = child.kill().and_then(|_| child.wait()) {
+                warn!("Failed to stop process pid={} error={:?}", child.id(), e);
+            }
+        }
+    }
+}
+
+impl Drop for StandaloneCluster {
+    fn drop(&mut self) {
+        // executors shut down on the tokio runtime, which may be gone by the time that the
+        // cluster is dropped
+        if tokio::runtime::Handle::try_current().is_ok() {
+            self.shutdown();
+        } else {
+            self.executors.clear();
+            self.shutdown();
+        }
+    }
+}
+
+fn listen_addr(port: usize) -> Result<SocketAddr> {
+    format!("0.0.0.0:{}", port)
+        .parse()
+        .map_err(|e| ballista_error(&format!("Invalid port {}: {:?}", port, e)))
+}
+
+/// Serve the flight service of an executor on the tokio runtime until it shuts down
+fn serve_executor(service: BallistaFlightService, port: usize) -> Result<()> {
+    let addr = listen_addr(port)?;
+    let shutdown = service.shutdown_signal();
+    let server = match service.session_manager() {
+        Some(sessions) => FlightServiceServer::with_interceptor(service, sessions.interceptor()),
+        None => FlightServiceServer::with_interceptor(service, anonymous_interceptor()),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = Server::builder()
+            .add_service(server)
+            .serve_with_shutdown(addr, shutdown)
+            .await
+        {
+            error!("Executor failed port={}: {:?}", port, e);
+        }
+    });
+    Ok(())
+}
+
+/// Directory of the binaries of the running program, where the `executor` and `scheduler`
+/// binaries are built next to it
+pub fn bin_dir() -> Result<PathBuf> {
+    let exe = std::env::current_exe()?;
+    exe.parent()
+        .map(|dir| dir.to_path_buf())
+        .ok_or_else(|| ballista_error("The running program has no directory"))
+}
@@ -0,0 +1,185 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mapping of errors to gRPC statuses and back.
+//!
+//! Each error is classified into a kind that determines the code of the status, and the kind
+//! and message of the error are attached to the status as an `ErrorDetail` message so that
+//! clients can reconstruct the error. Statuses without a detail, such as those of transport
+//! failures, are reported as general errors.
+
+use std::io::{self, Cursor};
+
+use crate::arrow::error::ArrowError;
+use crate::datafusion::error::ExecutionError;
+use crate::error::BallistaError;
+use crate::protobuf::{ErrorDetail, ErrorKind};
+
+use bytes::Bytes;
+use prost::Message;
+use tonic::{Code, Status};
+
+/// Classify an error
+pub fn error_kind(e: &BallistaError) -> ErrorKind {
+    match e {
+        BallistaError::NotImplemented(_) => ErrorKind::NotImplemented,
+        BallistaError::SqlError(_) | BallistaError::PlanError(_) => ErrorKind::Plan,
+        BallistaError::SchemaMismatch(_) => ErrorKind::SchemaMismatch,
+        BallistaError::ResourcesExhausted(_) => ErrorKind::ResourcesExhausted,
+        BallistaError::IoError(_) => ErrorKind::DatasourceIo,
+        BallistaError::ExecutorLost(_) => ErrorKind::ExecutorLost,
+        BallistaError::DeadlineExceeded(_) => ErrorKind::DeadlineExceeded,
+        BallistaError::Cancelled => ErrorKind::QueryCancelled,
+        BallistaError::ArrowError(e) => arrow_error_kind(e),
+        BallistaError::DataFusionError(e) => match e {
+            ExecutionError::IoError(_) => ErrorKind::DatasourceIo,
+            ExecutionError::ParserError(_) => ErrorKind::Plan,
+            ExecutionError::InvalidColumn(_) => ErrorKind::SchemaMismatch,
+            ExecutionError::NotImplemented(_) => ErrorKind::NotImplemented,
+            ExecutionError::ArrowError(e) => arrow_error_kind(e),
+            _ => ErrorKind::General,
+        },
+        _ => ErrorKind::General,
+    }
+}
+
+fn arrow_error_kind(e: &ArrowError) -> ErrorKind {
+    match e {
+        ArrowError::IoError(_) | ArrowError::CsvError(_) | ArrowError::JsonError(_) => {
+            ErrorKind::DatasourceIo
+        }
+        // batches are rejected with this error when their columns do not match their schema
+        ArrowError::InvalidArgumentError(_) => ErrorKind::SchemaMismatch,
+        _ => ErrorKind::General,
+    }
+}
+
+/// The gRPC code of the status that an error of the given kind is reported with
+pub fn status_code(kind: ErrorKind) -> Code {
+    match kind {
+        ErrorKind::General => Code::Internal,
+        ErrorKind::Plan => Code::InvalidArgument,
+        ErrorKind::SchemaMismatch => Code::FailedPrecondition,
+        ErrorKind::ResourcesExhausted => Code::ResourceExhausted,
+        ErrorKind::DatasourceIo => Code::DataLoss,
+        ErrorKind::ExecutorLost => Code::Unavailable,
+        ErrorKind::NotImplemented => Code::Unimplemented,
+        ErrorKind::DeadlineExceeded => Code::DeadlineExceeded,
+        ErrorKind::QueryCancelled => Code::Cancelled,
+    }
+}
+
+/// Report an error as a status with the kind and message of the error attached
+pub fn to_status(e: &BallistaError) -> Status {
+    let kind = error_kind(e);
+    let detail = ErrorDetail {
+        kind: kind as i32,
+        message: error_message(e),
+    };
+    let mut details = Vec::with_capacity(detail.encoded_len());
+    match detail.encode(&mut details) {
+        Ok(()) => Status::with_details(status_code(kind), e.to_string(), Bytes::from(details)),
+        Err(_) => Status::new(status_code(kind), e.to_string()),
+    }
+}
+
+/// Reconstruct the error that a status reports. Statuses that have no error detail are
+/// reported as general errors that include the code of the status, unless a deadline passed
+/// or the request was cancelled.
+pub fn from_status(status: &Status) -> BallistaError {
+    let detail: Option<ErrorDetail> = if status.details().is_empty() {
+        None
+    } else {
+        ErrorDetail::decode(&mut Cursor::new(status.details())).ok()
+    };
+    let detail = match detail {
+        Some(detail) => detail,
+        None if status.code() == Code::DeadlineExceeded => {
+            return BallistaError::DeadlineExceeded(status.message().to_owned())
+        }
+        None if status.code() == Code::Cancelled => return BallistaError::Cancelled,
+        None => return BallistaError::General(format!("{:?}", status)),
+    };
+    let message = detail.message;
+    match ErrorKind::from_i32(detail.kind).unwrap_or(ErrorKind::General) {
+        ErrorKind::General => BallistaError::General(message),
+        ErrorKind::Plan => BallistaError::PlanError(message),
+        ErrorKind::SchemaMismatch => BallistaError::SchemaMismatch(message),
+        ErrorKind::ResourcesExhausted => BallistaError::ResourcesExhausted(message),
+        ErrorKind::DatasourceIo => {
+            BallistaError::IoError(io::Error::new(io::ErrorKind::Other, message))
+        }
+        ErrorKind::ExecutorLost => BallistaError::ExecutorLost(message),
+        ErrorKind::NotImplemented => BallistaError::NotImplemented(message),
+        ErrorKind::DeadlineExceeded => BallistaError::DeadlineExceeded(message),
+        ErrorKind::QueryCancelled => BallistaError::Cancelled,
+    }
+}
+
+/// Message of an error without the prefix that describes its kind, for the variants that the
+/// error is reconstructed as
+fn error_message(e: &BallistaError) -> String {
+    match e {
+        BallistaError::General(message)
+        | BallistaError::NotImplemented(message)
+        | BallistaError::PlanError(message)
+        | BallistaError::SchemaMismatch(message)
+        | BallistaError::ResourcesExhausted(message)
+        | BallistaError::ExecutorLost(message)
+        | BallistaError::DeadlineExceeded(message) => message.clone(),
+        _ => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_errors_through_status() {
+        let errors = vec![
+            BallistaError::PlanError("unknown table t".to_owned()),
+            BallistaError::SchemaMismatch("expected 2 columns".to_owned()),
+            BallistaError::ResourcesExhausted("SortExec could not reserve 10 bytes".to_owned()),
+            BallistaError::ExecutorLost("2 shuffle partitions were lost".to_owned()),
+            BallistaError::DeadlineExceeded("job did not complete".to_owned()),
+            BallistaError::Cancelled,
+        ];
+        for e in errors {
+            let status = to_status(&e);
+            assert_eq!(status_code(error_kind(&e)), status.code());
+            assert_eq!(format!("{:?}", e), format!("{:?}", from_status(&status)));
+        }
+
+        let e = BallistaError::IoError(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        let status = to_status(&e);
+        assert_eq!(Code::DataLoss, status.code());
+        match from_status(&status) {
+            BallistaError::IoError(e) => assert_eq!("IO error: no such file", e.to_string()),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn statuses_without_detail_are_general_errors() {
+        let status = Status::resource_exhausted("executor is at capacity");
+        match from_status(&status) {
+            BallistaError::General(message) => assert!(message.contains("ResourceExhausted")),
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        let status = Status::cancelled("task was cancelled");
+        assert!(matches!(from_status(&status), BallistaError::Cancelled));
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Work stealing between the executors that run the tasks of a stage.
+//!
+//! Executors that run out of tasks of a stage offer themselves as idle. The scheduler threads
+//! of the executors that still have tasks waiting, either not yet submitted or queued on their
+//! executor, claim an idle executor and move one of those tasks to it, so that skewed stages
+//! do not wait on a single overloaded executor while others sit idle.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::debug;
+
+/// Executors, by index, that have no tasks of a stage left to run
+#[derive(Debug, Default)]
+pub struct IdleExecutors {
+    idle: Mutex<VecDeque<usize>>,
+}
+
+impl IdleExecutors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Offer an executor to run tasks of other executors
+    pub fn offer(&self, executor_index: usize) {
+        let mut idle = self.idle.lock().expect("failed to lock mutex");
+        if !idle.contains(&executor_index) {
+            idle.push_back(executor_index);
+            debug!("Executor is idle executor_index={}", executor_index);
+        }
+    }
+
+    /// Claim an idle executor other than the given one, which runs one task for the claimer
+    /// until it is offered again
+    pub fn claim(&self, executor_index: usize) -> Option<usize> {
+        let mut idle = self.idle.lock().expect("failed to lock mutex");
+        let position = idle.iter().position(|i| *i != executor_index)?;
+        idle.remove(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claim_idle_executors_once() {
+        let idle = IdleExecutors::new();
+        idle.offer(0);
+        idle.offer(2);
+        idle.offer(2);
+
+        // executors do not steal from themselves
+        assert_eq!(Some(2), idle.claim(0));
+        assert_eq!(None, idle.claim(0));
+        assert_eq!(Some(0), idle.claim(1));
+        assert_eq!(None, idle.claim(1));
+
+        idle.offer(2);
+        assert_eq!(Some(2), idle.claim(1));
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pluggable stores for the definitions of external tables, which are shared by all the clients
+//! of a scheduler and survive a restart of the scheduler, unlike the tables that are registered
+//! with a session.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::arrow::datatypes::Schema;
+use crate::datafusion::logicalplan::LogicalPlan;
+use crate::distributed::catalog::{file_scan_plan, infer_file_schema, resolve_file_format};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::serde::{decode_table_definition, encode_table_definition};
+
+use async_trait::async_trait;
+use etcd_client::{Client, GetOptions};
+
+/// Definition of an external table, which is a directory of files in one format
+#[derive(Debug, Clone)]
+pub struct TableDefinition {
+    pub name: String,
+    /// Path of the files of the table, which may be in an object store
+    pub location: String,
+    /// One of `parquet`, `csv`, `json`, `avro`, or `arrow`
+    pub format: String,
+    pub schema: Schema,
+    /// Columns of the schema whose values are taken from hive-style `key=value` directories
+    /// rather than from the files
+    pub partition_columns: Vec<String>,
+}
+
+impl TableDefinition {
+    /// Define a table, taking its format from the file extension and inferring its schema from
+    /// its files when they are not given
+    pub fn try_new(
+        name: &str,
+        location: &str,
+        format: Option<&str>,
+        schema: Option<Schema>,
+        partition_columns: Vec<String>,
+    ) -> Result<Self> {
+        let format = resolve_file_format(location, format)?;
+        let schema = match schema {
+            Some(schema) => schema,
+            None => infer_file_schema(location, Some(format))?,
+        };
+        for column in &partition_columns {
+            schema.index_of(column).map_err(|_| {
+                ballista_error(&format!(
+                    "Partition column '{}' of table '{}' is not in its schema",
+                    column, name
+                ))
+            })?;
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            location: location.to_owned(),
+            format: format.to_owned(),
+            schema,
+            partition_columns,
+        })
+    }
+
+    /// Create a logical plan that scans the table
+    pub fn to_plan(&self) -> Result<LogicalPlan> {
+        file_scan_plan(
+            &self.location,
+            Some(&self.format),
+            Some(self.schema.clone()),
+        )
+    }
+}
+
+/// Store that the scheduler persists the definitions of external tables in
+#[async_trait]
+pub trait TableStore: Send + Sync {
+    /// Create or replace the definition of a table
+    async fn save_table(&self, table: &TableDefinition) -> Result<()>;
+
+    async fn get_table(&self, name: &str) -> Result<Option<TableDefinition>>;
+
+    async fn list_tables(&self) -> Result<Vec<TableDefinition>>;
+
+    /// Remove the definition of a table, returning false if there was no such table
+    async fn remove_table(&self, name: &str) -> Result<bool>;
+}
+
+/// Store that keeps table definitions in memory, which do not survive a restart of the
+/// scheduler
+#[derive(Default)]
+pub struct InMemoryTableStore {
+    tables: Mutex<HashMap<String, TableDefinition>>,
+}
+
+#[async_trait]
+impl TableStore for InMemoryTableStore {
+    async fn save_table(&self, table: &TableDefinition) -> Result<()> {
+        let mut tables = self.tables.lock().expect("failed to lock mutex");
+        tables.insert(table.name.clone(), table.clone());
+        Ok(())
+    }
+
+    async fn get_table(&self, name: &str) -> Result<Option<TableDefinition>> {
+        let tables = self.tables.lock().expect("failed to lock mutex");
+        Ok(tables.get(name).cloned())
+    }
+
+    async fn list_tables(&self) -> Result<Vec<TableDefinition>> {
+        let tables = self.tables.lock().expect("failed to lock mutex");
+        Ok(tables.values().cloned().collect())
+    }
+
+    async fn remove_table(&self, name: &str) -> Result<bool> {
+        let mut tables = self.tables.lock().expect("failed to lock mutex");
+        Ok(tables.remove(name).is_some())
+    }
+}
+
+/// Store that keeps table definitions in a sled database on the local disk of the scheduler
+pub struct SledTableStore {
+    db: sled::Db,
+}
+
+impl SledTableStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let db = sled::open(path).map_err(to_sled_err)?;
+        Ok(Self { db })
+    }
+}
+
+fn to_sled_err(e: sled::Error) -> BallistaError {
+    ballista_error(&format!("sled error {:?}", e))
+}
+
+#[async_trait]
+impl TableStore for SledTableStore {
+    async fn save_table(&self, table: &TableDefinition) -> Result<()> {
+        self.db
+            .insert(table.name.as_bytes(), encode_table_definition(table)?)
+            .map_err(to_sled_err)?;
+        self.db.flush().map_err(to_sled_err)?;
+        Ok(())
+    }
+
+    async fn get_table(&self, name: &str) -> Result<Option<TableDefinition>> {
+        match self.db.get(name.as_bytes()).map_err(to_sled_err)? {
+            Some(value) => Ok(Some(decode_table_definition(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_tables(&self) -> Result<Vec<TableDefinition>> {
+        let mut tables = vec![];
+        for entry in self.db.iter() {
+            let (_, value) = entry.map_err(to_sled_err)?;
+            tables.push(decode_table_definition(&value)?);
+        }
+        Ok(tables)
+    }
+
+    async fn remove_table(&self, name: &str) -> Result<bool> {
+        let removed = self.db.remove(name.as_bytes()).map_err(to_sled_err)?;
+        self.db.flush().map_err(to_sled_err)?;
+        Ok(removed.is_some())
+    }
+}
+
+/// Store that keeps table definitions in etcd under `/ballista-tables/<cluster>/`, so that
+/// schedulers on different hosts share them
+pub struct EtcdTableStore {
+    etcd_urls: String,
+    prefix: String,
+}
+
+impl EtcdTableStore {
+    pub fn new(etcd_urls: &str, cluster_name: &str) -> Self {
+        Self {
+            etcd_urls: etcd_urls.to_owned(),
+            prefix: format!("/ballista-tables/{}/", cluster_name),
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        Client::connect([&self.etcd_urls], None)
+            .await
+            .map_err(to_etcd_err)
+    }
+}
+
+fn to_etcd_err(e: etcd_client::Error) -> BallistaError {
+    ballista_error(&format!("etcd error {:?}", e))
+}
+
+#[async_trait]
+impl TableStore for EtcdTableStore {
+    async fn save_table(&self, table: &TableDefinition) -> Result<()> {
+        let mut client = self.connect().await?;
+        client
+            .put(self.key(&table.name), encode_table_definition(table)?, None)
+            .await
+            .map_err(to_etcd_err)?;
+        Ok(())
+    }
+
+    async fn get_table(&self, name: &str) -> Result<Option<TableDefinition>> {
+        let mut client = self.connect().await?;
+        let resp = client
+            .get(self.key(name), None)
+            .await
+            .map_err(to_etcd_err)?;
+        match resp.kvs().first() {
+            Some(kv) => Ok(Some(decode_table_definition(kv.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_tables(&self) -> Result<Vec<TableDefinition>> {
+        let mut client = self.connect().await?;
+        let resp = client
+            .get(self.prefix.as_str(), Some(GetOptions::new().with_prefix()))
+            .await
+            .map_err(to_etcd_err)?;
+        resp.kvs()
+            .iter()
+            .map(|kv| decode_table_definition(kv.value()))
+            .collect()
+    }
+
+    async fn remove_table(&self, name: &str) -> Result<bool> {
+        let mut client = self.connect().await?;
+        let resp = client
+            .delete(self.key(name), None)
+            .await
+            .map_err(to_etcd_err)?;
+        Ok(resp.deleted() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::{DataType, Field};
+
+    #[test]
+    fn save_and_remove_tables() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("fare_amount", DataType::Float64, true),
+            Field::new("year", DataType::Utf8, false),
+        ]);
+        let table = TableDefinition::try_new(
+            "trips",
+            "/data/trips",
+            Some("parquet"),
+            Some(schema.clone()),
+            vec!["year".to_owned()],
+        )?;
+        assert!(TableDefinition::try_new(
+            "trips",
+            "/data/trips",
+            Some("parquet"),
+            Some(schema),
+            vec!["month".to_owned()],
+        )
+        .is_err());
+
+        // definitions are persisted in their protobuf encoding
+        let decoded = decode_table_definition(&encode_table_definition(&table)?)?;
+        assert_eq!(format!("{:?}", table), format!("{:?}", decoded));
+
+        smol::run(async {
+            let store = InMemoryTableStore::default();
+            store.save_table(&table).await?;
+            assert_eq!(1, store.list_tables().await?.len());
+            assert!(store.get_table("trips").await?.is_some());
+            assert!(store.remove_table("trips").await?);
+            assert!(!store.remove_table("trips").await?);
+            assert!(store.list_tables().await?.is_empty());
+            Ok(())
+        })
+    }
+}
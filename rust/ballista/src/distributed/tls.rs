@@ -0,0 +1,107 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS configuration for the Flight service and for connections between executors.
+
+use std::fmt;
+use std::fs;
+
+use crate::error::Result;
+
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+/// TLS configuration shared by the Flight server and by clients connecting to other executors
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// PEM-encoded certificate and private key presented to peers
+    identity: Option<(Vec<u8>, Vec<u8>)>,
+    /// PEM-encoded CA certificate used to verify peers
+    ca_cert: Option<Vec<u8>>,
+    /// Require clients to present a certificate signed by the CA (mutual TLS)
+    client_auth: bool,
+    /// Domain name expected in the server certificate, if different from the host name
+    domain_name: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the certificate and private key that this process presents to peers
+    pub fn with_identity(mut self, cert_path: &str, key_path: &str) -> Result<Self> {
+        self.identity = Some((fs::read(cert_path)?, fs::read(key_path)?));
+        Ok(self)
+    }
+
+    /// Load the CA certificate used to verify peers
+    pub fn with_ca_cert(mut self, ca_cert_path: &str) -> Result<Self> {
+        self.ca_cert = Some(fs::read(ca_cert_path)?);
+        Ok(self)
+    }
+
+    /// Require clients to present a certificate signed by the CA certificate
+    pub fn with_client_auth(mut self, client_auth: bool) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Override the domain name used to verify the server certificate
+    pub fn with_domain_name(mut self, domain_name: &str) -> Self {
+        self.domain_name = Some(domain_name.to_owned());
+        self
+    }
+
+    /// Create the configuration for the Flight server
+    pub fn server_config(&self) -> ServerTlsConfig {
+        let mut config = ServerTlsConfig::new();
+        if let Some((cert, key)) = &self.identity {
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        if self.client_auth {
+            if let Some(ca_cert) = &self.ca_cert {
+                config = config.client_ca_root(Certificate::from_pem(ca_cert));
+            }
+        }
+        config
+    }
+
+    /// Create the configuration for a client connecting to an executor. The identity is
+    /// presented to the server so that mutual TLS can be used for intra-cluster traffic.
+    pub fn client_config(&self) -> ClientTlsConfig {
+        let mut config = ClientTlsConfig::new();
+        if let Some(ca_cert) = &self.ca_cert {
+            config = config.ca_certificate(Certificate::from_pem(ca_cert));
+        }
+        if let Some((cert, key)) = &self.identity {
+            config = config.identity(Identity::from_pem(cert, key));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name.as_str());
+        }
+        config
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // avoid leaking the private key into logs
+        f.debug_struct("TlsConfig")
+            .field("identity", &self.identity.is_some())
+            .field("ca_cert", &self.ca_cert.is_some())
+            .field("client_auth", &self.client_auth)
+            .field("domain_name", &self.domain_name)
+            .finish()
+    }
+}
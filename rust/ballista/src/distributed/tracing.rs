@@ -0,0 +1,378 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Distributed tracing of jobs across clients, schedulers, and executors.
+//!
+//! The trace context of a request is propagated in the W3C `traceparent` gRPC metadata
+//! header, which OpenTelemetry uses by default, so traces started by instrumented clients
+//! continue through the scheduler, the tasks that executors run, and the shuffle fetches of
+//! those tasks. Spans are recorded for jobs, stages, tasks, the operators of tasks, and shuffle
+//! fetches, and are exported in the Zipkin JSON format, which Jaeger and most OpenTelemetry
+//! collectors accept, so that the critical path of a slow query can be seen in one trace.
+
+use std::future::Future;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+
+use lazy_static::lazy_static;
+use log::warn;
+use serde_json::{json, Map, Value};
+use tonic::metadata::MetadataValue;
+use tonic::Request;
+use uuid::Uuid;
+
+/// Metadata header that the trace context of a request is sent in
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Max number of spans that are sent to the collector in one request
+const EXPORT_BATCH_SIZE: usize = 512;
+
+/// Max time that a finished span waits before it is sent to the collector
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref SPAN_EXPORTER: RwLock<Option<Arc<dyn SpanExporter>>> = RwLock::new(None);
+}
+
+tokio::task_local! {
+    static CURRENT_TRACE: TraceContext;
+}
+
+/// Identity of a span within a trace, which the spans that it causes are children of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    /// Whether the spans of the trace are recorded
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Start a new trace
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: Uuid::new_v4().as_u128(),
+            span_id: new_span_id(),
+            sampled: true,
+        }
+    }
+
+    /// A new span in the same trace
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: new_span_id(),
+            ..*self
+        }
+    }
+
+    /// Format as the value of a `traceparent` header
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.span_id, self.sampled as u8
+        )
+    }
+
+    /// Parse the value of a `traceparent` header, returning `None` if it is not valid
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.trim().split('-').collect();
+        match parts.as_slice() {
+            [version, trace_id, span_id, flags]
+                if *version != "ff" && trace_id.len() == 32 && span_id.len() == 16 =>
+            {
+                let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+                let span_id = u64::from_str_radix(span_id, 16).ok()?;
+                let flags = u8::from_str_radix(flags, 16).ok()?;
+                // all-zero identifiers are invalid
+                if trace_id == 0 || span_id == 0 {
+                    return None;
+                }
+                Some(Self {
+                    trace_id,
+                    span_id,
+                    sampled: flags & 1 == 1,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn new_span_id() -> u64 {
+    // span ids must not be zero
+    (Uuid::new_v4().as_u128() as u64).max(1)
+}
+
+/// The trace context that the sender of a request propagated, if any
+pub fn trace_context_of<T>(request: &Request<T>) -> Option<TraceContext> {
+    request
+        .metadata()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceContext::from_traceparent)
+}
+
+/// Propagate the current trace context, if any, with a request
+pub fn inject_trace_context<T>(request: &mut Request<T>) {
+    if let Some(trace) = current_trace() {
+        if let Ok(value) = MetadataValue::from_str(&trace.to_traceparent()) {
+            request.metadata_mut().insert(TRACEPARENT_HEADER, value);
+        }
+    }
+}
+
+/// Run a future with a trace context as the current trace context, which the requests that it
+/// sends propagate
+pub async fn in_trace<F: Future>(trace: TraceContext, f: F) -> F::Output {
+    CURRENT_TRACE.scope(trace, f).await
+}
+
+/// The trace context of the future that is running, if it runs within `in_trace`
+pub fn current_trace() -> Option<TraceContext> {
+    CURRENT_TRACE.try_with(|trace| *trace).ok()
+}
+
+/// A unit of work within a trace, which is exported when it is dropped
+pub struct Span {
+    name: String,
+    context: TraceContext,
+    parent_span_id: Option<u64>,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    attributes: Vec<(String, String)>,
+}
+
+impl Span {
+    /// Start a span as a child of the given span, or as the root of a new trace
+    pub fn start(name: &str, parent: Option<&TraceContext>) -> Self {
+        Self {
+            name: name.to_owned(),
+            context: parent
+                .map(|p| p.child())
+                .unwrap_or_else(TraceContext::new_root),
+            parent_span_id: parent.map(|p| p.span_id),
+            start: SystemTime::now(),
+            end: None,
+            attributes: vec![],
+        }
+    }
+
+    /// Start a span that began in the past, such as an operator whose time is only known
+    /// once its task has completed
+    pub fn start_at(name: &str, parent: Option<&TraceContext>, start: SystemTime) -> Self {
+        let mut span = Self::start(name, parent);
+        span.start = start;
+        span
+    }
+
+    /// The context that children of this span are started from
+    pub fn context(&self) -> TraceContext {
+        self.context
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: impl ToString) -> Self {
+        self.set_attribute(key, value);
+        self
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: impl ToString) {
+        self.attributes.push((key.to_owned(), value.to_string()));
+    }
+
+    /// Mark the span as failed
+    pub fn set_error(&mut self, error: impl ToString) {
+        self.set_attribute("error", error);
+    }
+
+    /// End the span at a time other than when it is dropped
+    pub fn end_at(mut self, end: SystemTime) {
+        self.end = Some(end);
+    }
+
+    /// Describe the span in the Zipkin v2 JSON format
+    pub fn to_zipkin(&self, service_name: &str) -> Value {
+        let end = self.end.unwrap_or_else(SystemTime::now);
+        let micros = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or_default()
+        };
+        let tags: Map<String, Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+            .collect();
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.context.trace_id),
+            "id": format!("{:016x}", self.context.span_id),
+            "name": self.name,
+            "timestamp": micros(self.start),
+            // zipkin drops spans with a duration of zero
+            "duration": micros(end).saturating_sub(micros(self.start)).max(1),
+            "localEndpoint": { "serviceName": service_name },
+            "tags": tags,
+        });
+        if let Some(parent_span_id) = self.parent_span_id {
+            span["parentId"] = Value::String(format!("{:016x}", parent_span_id));
+        }
+        span
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if !self.context.sampled {
+            return;
+        }
+        if self.end.is_none() {
+            self.end = Some(SystemTime::now());
+        }
+        let exporter = SPAN_EXPORTER.read().expect("failed to lock span exporter");
+        if let Some(exporter) = exporter.as_ref() {
+            exporter.export(self);
+        }
+    }
+}
+
+/// Destination of the spans that this process records
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, span: &Span);
+}
+
+/// Export the spans that this process records, which are discarded until an exporter is set
+pub fn set_span_exporter(exporter: Arc<dyn SpanExporter>) {
+    *SPAN_EXPORTER.write().expect("failed to lock span exporter") = Some(exporter);
+}
+
+/// Sends spans in batches to a collector that accepts the Zipkin v2 JSON format, such as
+/// `http://jaeger:9411/api/v2/spans` when Jaeger is run with its Zipkin endpoint enabled. Spans
+/// are sent from a background thread, and are dropped with a warning if the collector fails.
+pub struct ZipkinExporter {
+    service_name: String,
+    tx: Mutex<mpsc::Sender<Value>>,
+}
+
+impl ZipkinExporter {
+    pub fn new(endpoint: &str, service_name: &str) -> Self {
+        let (tx, rx) = mpsc::channel::<Value>();
+        let endpoint = endpoint.to_owned();
+        thread::spawn(move || {
+            let client = reqwest::Client::new();
+            let mut batch = vec![];
+            loop {
+                let closed = match rx.recv_timeout(EXPORT_INTERVAL) {
+                    Ok(span) => {
+                        batch.push(span);
+                        if batch.len() < EXPORT_BATCH_SIZE {
+                            continue;
+                        }
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+                if !batch.is_empty() {
+                    let spans = Value::Array(batch.split_off(0));
+                    if let Err(e) = send_spans(&client, &endpoint, &spans) {
+                        warn!("Failed to export spans endpoint={} error={:?}", endpoint, e);
+                    }
+                }
+                if closed {
+                    break;
+                }
+            }
+        });
+        Self {
+            service_name: service_name.to_owned(),
+            tx: Mutex::new(tx),
+        }
+    }
+}
+
+fn send_spans(client: &reqwest::Client, endpoint: &str, spans: &Value) -> Result<()> {
+    client
+        .post(endpoint)
+        .json(spans)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+impl SpanExporter for ZipkinExporter {
+    fn export(&self, span: &Span) {
+        let _ = self
+            .tx
+            .lock()
+            .expect("failed to lock mutex")
+            .send(span.to_zipkin(&self.service_name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_traceparent() {
+        let trace = TraceContext::new_root();
+        assert_eq!(
+            Some(trace),
+            TraceContext::from_traceparent(&trace.to_traceparent())
+        );
+        let parsed = TraceContext::from_traceparent(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+        )
+        .unwrap();
+        assert_eq!(0x4bf92f3577b34da6a3ce929d0e0e4736, parsed.trace_id);
+        assert_eq!(0x00f067aa0ba902b7, parsed.span_id);
+        assert!(!parsed.sampled);
+
+        assert!(TraceContext::from_traceparent("00-0-0-01").is_none());
+        assert!(TraceContext::from_traceparent(
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn propagate_current_trace() {
+        let trace = TraceContext::new_root();
+        let mut request = Request::new(());
+        smol::run(in_trace(trace, async {
+            assert_eq!(Some(trace), current_trace());
+            inject_trace_context(&mut request);
+        }));
+        assert_eq!(Some(trace), trace_context_of(&request));
+        assert_eq!(None, current_trace());
+    }
+
+    #[test]
+    fn describe_spans_as_zipkin() {
+        let job = Span::start("job", None);
+        let trace = job.context();
+        let stage = Span::start("stage", Some(&trace)).with_attribute("stage_id", 1);
+        assert_eq!(trace.trace_id, stage.context().trace_id);
+        assert_ne!(trace.span_id, stage.context().span_id);
+
+        let span = stage.to_zipkin("ballista-scheduler");
+        assert_eq!(format!("{:016x}", trace.span_id), span["parentId"]);
+        assert_eq!("1", span["tags"]["stage_id"]);
+        assert_eq!("ballista-scheduler", span["localEndpoint"]["serviceName"]);
+        assert!(job.to_zipkin("ballista-scheduler")["parentId"].is_null());
+    }
+}
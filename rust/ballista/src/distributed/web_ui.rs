@@ -0,0 +1,828 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Web UI for the scheduler, served over HTTP. It shows the jobs that the scheduler is running
+//! or has recently run, with the stages, tasks, and failures of each job, the history of
+//! finished jobs with their queries and metrics, and the executors in the cluster with the
+//! tasks that they ran.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::distributed::job_history::{JobHistoryEntry, JobHistoryFilter};
+use crate::distributed::job_state::JobRecord;
+use crate::distributed::progress::{TaskProgress, TaskState};
+use crate::distributed::scheduler_server::{JobState, JobStatus, SchedulerServer};
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::ExecutorMeta;
+use crate::protobuf;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use log::{info, warn};
+use uuid::Uuid;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 1em 2em; color: #222; }
+nav a { margin-right: 1em; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }
+pre { background: #f4f4f4; padding: 8px; overflow-x: auto; }
+.completed { color: #2a7d2a; } .failed { color: #b22; } .running { color: #1a5fb4; }
+.lane { position: relative; background: #f4f4f4; margin-bottom: 4px; }
+.task { position: absolute; height: 12px; min-width: 2px; background: #1a5fb4; }
+.task.completed { background: #2a7d2a; } .task.failed { background: #b22; }
+.task.queued, .task.pending { background: #999; }
+";
+
+/// Serve the web UI of a scheduler over HTTP
+pub async fn serve_ui(addr: SocketAddr, scheduler: SchedulerServer) -> Result<()> {
+    let make_service = make_service_fn(move |_conn| {
+        let scheduler = scheduler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let scheduler = scheduler.clone();
+                async move {
+                    let uri = request.uri();
+                    let response = match render_path(&scheduler, uri.path(), uri.query()).await {
+                        Ok(Some(html)) => Response::builder()
+                            .header("Content-Type", "text/html; charset=utf-8")
+                            .body(Body::from(html)),
+                        Ok(None) => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::from(page("Not found", "<p>No such page</p>\n"))),
+                        Err(e) => Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::from(format!("{:?}", e))),
+                    };
+                    Ok::<_, Infallible>(response.expect("failed to build response"))
+                }
+            }))
+        }
+    });
+
+    info!("Serving web UI on http://{}/", addr);
+    Server::bind(&addr)
+        .serve(make_service)
+        .await
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+}
+
+/// Render the page at the given path, or None if there is no such page
+async fn render_path(
+    scheduler: &SchedulerServer,
+    path: &str,
+    query: Option<&str>,
+) -> Result<Option<String>> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        [] | ["jobs"] => Ok(Some(render_jobs(&scheduler.jobs(None)))),
+        ["jobs", job_uuid] => {
+            let job_uuid = match Uuid::parse_str(job_uuid) {
+                Ok(job_uuid) => job_uuid,
+                Err(_) => return Ok(None),
+            };
+            match scheduler.job_status(&job_uuid) {
+                Some(status) => {
+                    let record = scheduler.job_record(&job_uuid).await?;
+                    let tasks = scheduler.job_tasks(&job_uuid);
+                    Ok(Some(render_job(&status, record.as_ref(), &tasks)))
+                }
+                None => Ok(None),
+            }
+        }
+        ["history"] => {
+            let search = query_param(query, "q").filter(|q| !q.is_empty());
+            let filter = JobHistoryFilter {
+                tenant: None,
+                search: search.clone(),
+                fingerprint: query_param(query, "fingerprint").and_then(|f| f.parse().ok()),
+                limit: None,
+            };
+            Ok(Some(render_history(
+                &scheduler.job_history(&filter),
+                search.as_deref(),
+            )))
+        }
+        ["history", job_uuid] => match Uuid::parse_str(job_uuid) {
+            Ok(job_uuid) => Ok(scheduler
+                .job_history_entry(&job_uuid)
+                .map(|entry| render_history_entry(&entry))),
+            Err(_) => Ok(None),
+        },
+        ["executors"] => {
+            let mut executors = vec![];
+            for executor in scheduler.executors().await? {
+                let stats = fetch_stats(scheduler, &executor).await;
+                executors.push((executor, stats));
+            }
+            Ok(Some(render_executors(&executors)))
+        }
+        ["executors", executor_id] => {
+            let executor = scheduler
+                .executors()
+                .await?
+                .into_iter()
+                .find(|executor| executor.id == *executor_id);
+            match executor {
+                Some(executor) => {
+                    let stats = fetch_stats(scheduler, &executor).await;
+                    let mut tasks = vec![];
+                    for status in scheduler.jobs(None) {
+                        for task in scheduler.job_tasks(&status.job_uuid) {
+                            if task.executor_id == executor.id {
+                                tasks.push((status.job_uuid, task));
+                            }
+                        }
+                    }
+                    Ok(Some(render_executor(&executor, stats.as_ref(), &tasks)))
+                }
+                None => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Statistics of an executor, or None if the executor could not be reached
+async fn fetch_stats(
+    scheduler: &SchedulerServer,
+    executor: &ExecutorMeta,
+) -> Option<protobuf::ExecutorStats> {
+    match scheduler.executor_stats(executor).await {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            warn!(
+                "Failed to fetch executor statistics executor_id={} error={:?}",
+                executor.id, e
+            );
+            None
+        }
+    }
+}
+
+/// Page listing the jobs of the scheduler, oldest first
+pub fn render_jobs(jobs: &[JobStatus]) -> String {
+    let mut body = String::new();
+    if jobs.is_empty() {
+        body.push_str("<p>No jobs</p>\n");
+        return page("Jobs", &body);
+    }
+    body.push_str(
+        "<table>\n<tr><th>Job</th><th>State</th><th>Submitted</th><th>Progress</th>\
+         <th>Tasks</th><th>Shuffled</th></tr>\n",
+    );
+    for job in jobs {
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"/jobs/{}\">{}</a></td><td class=\"{}\">{}</td><td>{}</td>\
+             <td>{:.0}%</td><td>{}/{}</td><td>{}</td></tr>",
+            job.job_uuid,
+            job.job_uuid,
+            state_name(&job.state),
+            state_name(&job.state),
+            format_age(job.submitted_at),
+            job.progress.fraction_complete() * 100.0,
+            job.progress.completed_tasks(),
+            job.progress.total_tasks(),
+            format_bytes(job.progress.shuffle_bytes())
+        );
+    }
+    body.push_str("</table>\n");
+    page("Jobs", &body)
+}
+
+/// Page showing a job with its stages, a timeline of its tasks on each executor, and the tasks
+/// that failed. The plans of the stages are only shown if the job has been persisted.
+pub fn render_job(
+    status: &JobStatus,
+    record: Option<&JobRecord>,
+    tasks: &[TaskProgress],
+) -> String {
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "<p>State: <span class=\"{}\">{}</span>, submitted {}, {}</p>",
+        state_name(&status.state),
+        state_name(&status.state),
+        format_age(status.submitted_at),
+        escape(
+            status
+                .progress
+                .to_string()
+                .lines()
+                .next()
+                .unwrap_or_default()
+        )
+    );
+    if let JobState::Failed(error) = &status.state {
+        let _ = writeln!(body, "<pre class=\"failed\">{}</pre>", escape(error));
+    }
+
+    body.push_str("<h2>Stages</h2>\n");
+    body.push_str(
+        "<table>\n<tr><th>Stage</th><th>Depends on</th><th>Tasks</th><th>Running</th>\
+         <th>Queued</th><th>Pending</th><th>Failed</th><th>Shuffled</th><th>Status</th></tr>\n",
+    );
+    for stage in &status.progress.stages {
+        let prior_stages = record
+            .and_then(|record| record.stage(stage.stage_id))
+            .map(|s| {
+                s.prior_stages
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        let state = if stage.completed {
+            "completed"
+        } else if stage.total_tasks > 0 {
+            "running"
+        } else {
+            "waiting"
+        };
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}/{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td><td class=\"{}\">{}</td></tr>",
+            stage.stage_id,
+            prior_stages,
+            stage.tasks.completed,
+            stage.total_tasks,
+            stage.tasks.running,
+            stage.tasks.queued,
+            stage.tasks.pending,
+            stage.tasks.failed,
+            format_bytes(stage.shuffle_bytes),
+            state,
+            state
+        );
+    }
+    body.push_str("</table>\n");
+
+    body.push_str("<h2>Task timeline</h2>\n");
+    body.push_str(&render_timeline(tasks, SystemTime::now()));
+
+    let failures: Vec<&TaskProgress> = tasks
+        .iter()
+        .filter(|task| matches!(task.state, TaskState::Failed(_)))
+        .collect();
+    if !failures.is_empty() {
+        body.push_str("<h2>Failures</h2>\n");
+        body.push_str(
+            "<table>\n<tr><th>Stage</th><th>Partition</th><th>Executor</th><th>Error</th></tr>\n",
+        );
+        for task in failures {
+            if let TaskState::Failed(error) = &task.state {
+                let _ = writeln!(
+                    body,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td><pre>{}</pre></td></tr>",
+                    task.stage_id,
+                    task.partition_id,
+                    executor_link(&task.executor_id),
+                    escape(error)
+                );
+            }
+        }
+        body.push_str("</table>\n");
+    }
+
+    if let Some(record) = record {
+        body.push_str("<h2>Stage plans</h2>\n");
+        for stage in &record.stages {
+            let _ = writeln!(
+                body,
+                "<h3>Stage {}</h3>\n<pre>{}</pre>",
+                stage.stage_id,
+                escape(&format!("{:?}", stage.plan))
+            );
+        }
+    }
+
+    page(&format!("Job {}", status.job_uuid), &body)
+}
+
+/// Timeline with a lane per executor, in which each task is a bar from when it was submitted
+/// until it finished. Tasks that overlap on an executor are stacked.
+fn render_timeline(tasks: &[TaskProgress], now: SystemTime) -> String {
+    let start = match tasks.iter().map(|task| task.started_at).min() {
+        Some(start) => start,
+        None => return "<p>No tasks have been submitted</p>\n".to_owned(),
+    };
+    let end = tasks
+        .iter()
+        .map(|task| task.finished_at.unwrap_or(now))
+        .max()
+        .unwrap_or(now);
+    let span = millis_between(start, end).max(1) as f64;
+
+    let mut executors: BTreeMap<&str, Vec<&TaskProgress>> = BTreeMap::new();
+    for task in tasks {
+        executors
+            .entry(task.executor_id.as_str())
+            .or_default()
+            .push(task);
+    }
+
+    let mut html = String::new();
+    let _ = writeln!(html, "<p>{} from the first task</p>", format_duration(span));
+    html.push_str("<table>\n");
+    for (executor_id, mut tasks) in executors {
+        tasks.sort_by_key(|task| task.started_at);
+        // end time of the last task in each row of the lane
+        let mut rows: Vec<SystemTime> = vec![];
+        let mut bars = String::new();
+        for task in tasks {
+            let finished_at = task.finished_at.unwrap_or(now);
+            let row = match rows.iter().position(|end| *end <= task.started_at) {
+                Some(row) => row,
+                None => {
+                    rows.push(finished_at);
+                    rows.len() - 1
+                }
+            };
+            rows[row] = finished_at;
+            let offset = millis_between(start, task.started_at) as f64;
+            let duration = millis_between(task.started_at, finished_at) as f64;
+            let _ = writeln!(
+                bars,
+                "<div class=\"task {}\" style=\"left:{:.2}%;width:{:.2}%;top:{}px\" \
+                 title=\"stage {} partition {}: {}, {}\"></div>",
+                task_state_name(&task.state),
+                offset * 100.0 / span,
+                duration * 100.0 / span,
+                row * 14,
+                task.stage_id,
+                task.partition_id,
+                task_state_name(&task.state),
+                format_duration(duration)
+            );
+        }
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td style=\"width:800px\"><div class=\"lane\" style=\"height:{}px\">\n{}</div></td></tr>",
+            executor_link(executor_id),
+            rows.len() * 14,
+            bars
+        );
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Page listing the finished jobs in the history, most recent first, with a form to search
+/// their queries
+pub fn render_history(entries: &[JobHistoryEntry], search: Option<&str>) -> String {
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "<form action=\"/history\"><input name=\"q\" size=\"60\" value=\"{}\"> \
+         <button>Search</button></form>",
+        escape(search.unwrap_or_default())
+    );
+    if entries.is_empty() {
+        body.push_str("<p>No finished jobs</p>\n");
+        return page("History", &body);
+    }
+    body.push_str(
+        "<table>\n<tr><th>Job</th><th>State</th><th>Submitted</th><th>Duration</th>\
+         <th>Rows</th><th>Shuffled</th><th>Query</th><th>Runs</th></tr>\n",
+    );
+    for entry in entries {
+        let query = entry.sql.as_deref().unwrap_or(&entry.plan);
+        let query = query.lines().next().unwrap_or_default();
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"/history/{}\">{}</a></td><td class=\"{}\">{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td>\
+             <td><a href=\"/history?fingerprint={}\">all runs</a></td></tr>",
+            entry.job_uuid,
+            entry.job_uuid,
+            state_name(&entry.state),
+            state_name(&entry.state),
+            format_age(entry.submitted_at),
+            format_entry_duration(entry),
+            entry.output_rows,
+            format_bytes(entry.shuffle_bytes),
+            escape(query),
+            entry.fingerprint
+        );
+    }
+    body.push_str("</table>\n");
+    page("History", &body)
+}
+
+/// Page showing the query, plan, and metrics of a finished job and of each of its stages
+pub fn render_history_entry(entry: &JobHistoryEntry) -> String {
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "<p>State: <span class=\"{}\">{}</span>, submitted {} by {}, ran for {}, {} rows, \
+         {} shuffled. <a href=\"/history?fingerprint={}\">Other runs of this query</a></p>",
+        state_name(&entry.state),
+        state_name(&entry.state),
+        format_age(entry.submitted_at),
+        escape(&entry.tenant),
+        format_entry_duration(entry),
+        entry.output_rows,
+        format_bytes(entry.shuffle_bytes),
+        entry.fingerprint
+    );
+    if let JobState::Failed(error) = &entry.state {
+        let _ = writeln!(body, "<pre class=\"failed\">{}</pre>", escape(error));
+    }
+    if let Some(sql) = &entry.sql {
+        let _ = writeln!(body, "<h2>SQL</h2>\n<pre>{}</pre>", escape(sql));
+    }
+    let _ = writeln!(body, "<h2>Plan</h2>\n<pre>{}</pre>", escape(&entry.plan));
+
+    if !entry.stages.is_empty() {
+        body.push_str("<h2>Stages</h2>\n");
+        body.push_str(
+            "<table>\n<tr><th>Stage</th><th>Tasks</th><th>Duration</th><th>Rows</th>\
+             <th>Shuffled</th></tr>\n",
+        );
+        for stage in &entry.stages {
+            let _ = writeln!(
+                body,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                stage.stage_id,
+                stage.tasks,
+                format_duration(stage.duration_ms as f64),
+                stage.output_rows,
+                format_bytes(stage.shuffle_bytes)
+            );
+        }
+        body.push_str("</table>\n");
+
+        body.push_str("<h2>Stage plans</h2>\n");
+        for stage in &entry.stages {
+            let _ = writeln!(
+                body,
+                "<h3>Stage {}</h3>\n<pre>{}</pre>",
+                stage.stage_id,
+                escape(&stage.plan)
+            );
+        }
+    }
+
+    page(&format!("Job {}", entry.job_uuid), &body)
+}
+
+/// Page listing the executors in the cluster with their statistics
+pub fn render_executors(executors: &[(ExecutorMeta, Option<protobuf::ExecutorStats>)]) -> String {
+    let mut body = String::new();
+    if executors.is_empty() {
+        body.push_str("<p>No executors</p>\n");
+        return page("Executors", &body);
+    }
+    body.push_str(
+        "<table>\n<tr><th>Executor</th><th>Address</th><th>Running</th><th>Queued</th>\
+         <th>Completed</th><th>Failed</th><th>Cancelled</th><th>Shuffle partitions</th>\
+         <th>Shuffle data</th><th>Draining</th></tr>\n",
+    );
+    for (executor, stats) in executors {
+        let _ = write!(
+            body,
+            "<tr><td>{}</td><td>{}:{}</td>",
+            executor_link(&executor.id),
+            escape(&executor.host),
+            executor.port
+        );
+        match stats {
+            Some(stats) => {
+                let _ = writeln!(
+                    body,
+                    "<td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                     <td>{}</td><td>{}</td></tr>",
+                    stats.running_tasks,
+                    stats.queued_tasks,
+                    stats.completed_tasks,
+                    stats.failed_tasks,
+                    stats.cancelled_tasks,
+                    stats.shuffle_partitions,
+                    format_bytes(stats.shuffle_bytes as usize),
+                    stats.draining
+                );
+            }
+            None => body.push_str("<td colspan=\"8\" class=\"failed\">unreachable</td></tr>\n"),
+        }
+    }
+    body.push_str("</table>\n");
+    page("Executors", &body)
+}
+
+/// Page showing the statistics of an executor and the tasks of the scheduler's jobs that ran
+/// on it
+pub fn render_executor(
+    executor: &ExecutorMeta,
+    stats: Option<&protobuf::ExecutorStats>,
+    tasks: &[(Uuid, TaskProgress)],
+) -> String {
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "<p>Address: {}:{}</p>",
+        escape(&executor.host),
+        executor.port
+    );
+    match stats {
+        Some(stats) => {
+            let _ = writeln!(
+                body,
+                "<table>\n<tr><th>Running tasks</th><td>{}</td></tr>\n\
+                 <tr><th>Queued tasks</th><td>{}</td></tr>\n\
+                 <tr><th>Completed tasks</th><td>{}</td></tr>\n\
+                 <tr><th>Failed tasks</th><td>{}</td></tr>\n\
+                 <tr><th>Cancelled tasks</th><td>{}</td></tr>\n\
+                 <tr><th>Shuffle partitions held</th><td>{}</td></tr>\n\
+                 <tr><th>Shuffle data held</th><td>{}</td></tr>\n\
+                 <tr><th>Reserved cores</th><td>{} of {}</td></tr>\n\
+                 <tr><th>Reserved memory</th><td>{} of {}</td></tr>\n\
+                 <tr><th>Draining</th><td>{}</td></tr>\n</table>",
+                stats.running_tasks,
+                stats.queued_tasks,
+                stats.completed_tasks,
+                stats.failed_tasks,
+                stats.cancelled_tasks,
+                stats.shuffle_partitions,
+                format_bytes(stats.shuffle_bytes as usize),
+                stats.reserved_cores,
+                stats.cores,
+                format_bytes(stats.reserved_memory_bytes as usize),
+                format_bytes(stats.memory_bytes as usize),
+                stats.draining
+            );
+        }
+        None => body.push_str("<p class=\"failed\">The executor could not be reached</p>\n"),
+    }
+
+    body.push_str("<h2>Tasks</h2>\n");
+    if tasks.is_empty() {
+        body.push_str("<p>No tasks of the scheduler's jobs have run on this executor</p>\n");
+    } else {
+        body.push_str(
+            "<table>\n<tr><th>Job</th><th>Stage</th><th>Partition</th><th>State</th>\
+             <th>Duration</th><th>Shuffled</th></tr>\n",
+        );
+        let now = SystemTime::now();
+        for (job_uuid, task) in tasks {
+            let duration = millis_between(task.started_at, task.finished_at.unwrap_or(now)) as f64;
+            let _ = writeln!(
+                body,
+                "<tr><td><a href=\"/jobs/{}\">{}</a></td><td>{}</td><td>{}</td>\
+                 <td class=\"{}\">{}</td><td>{}</td><td>{}</td></tr>",
+                job_uuid,
+                job_uuid,
+                task.stage_id,
+                task.partition_id,
+                task_state_name(&task.state),
+                task_state_name(&task.state),
+                format_duration(duration),
+                format_bytes(task.shuffle_bytes)
+            );
+        }
+        body.push_str("</table>\n");
+    }
+
+    page(&format!("Executor {}", escape(&executor.id)), &body)
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{} - Ballista</title>\n\
+         <style>{}</style>\n</head>\n<body>\n<nav><a href=\"/\">Jobs</a><a href=\"/history\">History</a><a href=\"/executors\">Executors</a></nav>\n\
+         <h1>{}</h1>\n{}</body>\n</html>\n",
+        title, STYLE, title, body
+    )
+}
+
+fn executor_link(executor_id: &str) -> String {
+    format!(
+        "<a href=\"/executors/{}\">{}</a>",
+        escape(executor_id),
+        escape(executor_id)
+    )
+}
+
+fn state_name(state: &JobState) -> &'static str {
+    match state {
+        JobState::Queued => "queued",
+        JobState::Running => "running",
+        JobState::Completed(_) => "completed",
+        JobState::Failed(_) => "failed",
+        JobState::Cancelled => "cancelled",
+    }
+}
+
+fn task_state_name(state: &TaskState) -> &'static str {
+    match state {
+        TaskState::Pending => "pending",
+        TaskState::Queued => "queued",
+        TaskState::Running => "running",
+        TaskState::Completed => "completed",
+        TaskState::Failed(_) => "failed",
+    }
+}
+
+/// Escape text for inclusion in HTML
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The decoded value of a parameter of a URL query string
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            Some((parts.next()?, parts.next().unwrap_or_default()))
+        })
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode_query_value(value))
+}
+
+/// Decode `+` and percent-encoded bytes of a value in a URL query string
+fn decode_query_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i + 1..i + 3) {
+            Some(hex) if bytes[i] == b'%' => std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn millis_between(from: SystemTime, to: SystemTime) -> u64 {
+    to.duration_since(from)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_millis() as u64
+}
+
+fn format_age(time: SystemTime) -> String {
+    match time.elapsed() {
+        Ok(age) => format!("{} ago", format_duration(age.as_millis() as f64)),
+        Err(_) => "just now".to_owned(),
+    }
+}
+
+fn format_duration(millis: f64) -> String {
+    if millis < 1000.0 {
+        format!("{:.0} ms", millis)
+    } else if millis < 60_000.0 {
+        format!("{:.1} s", millis / 1000.0)
+    } else {
+        format!("{:.1} min", millis / 60_000.0)
+    }
+}
+
+fn format_entry_duration(entry: &JobHistoryEntry) -> String {
+    match entry.duration() {
+        Some(duration) => format_duration(duration.as_millis() as f64),
+        None => "-".to_owned(),
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed::progress::JobProgress;
+
+    #[test]
+    fn render_job_page() {
+        let job_uuid = Uuid::new_v4();
+        let submitted_at = SystemTime::now() - Duration::from_secs(10);
+        let status = JobStatus {
+            job_uuid,
+            state: JobState::Failed("<error>".to_owned()),
+            submitted_at,
+            progress: JobProgress::default(),
+        };
+        let task = |partition_id, executor_id: &str, state| TaskProgress {
+            stage_id: 1,
+            partition_id,
+            executor_id: executor_id.to_owned(),
+            state,
+            started_at: submitted_at,
+            finished_at: Some(submitted_at + Duration::from_secs(2)),
+            shuffle_bytes: 2048,
+        };
+        let tasks = vec![
+            task(0, "executor-1", TaskState::Completed),
+            task(1, "executor-1", TaskState::Completed),
+            task(2, "executor-2", TaskState::Failed("disk <full>".to_owned())),
+        ];
+
+        let html = render_job(&status, None, &tasks);
+        assert!(html.contains(&format!("<h1>Job {}</h1>", job_uuid)));
+        assert!(html.contains("&lt;error&gt;"));
+        assert!(html.contains("disk &lt;full&gt;"));
+        assert!(!html.contains("<error>"));
+        // the overlapping tasks on the first executor are stacked in two rows
+        assert!(html.contains("<div class=\"lane\" style=\"height:28px\">"));
+        assert!(html.contains("<a href=\"/executors/executor-2\">executor-2</a>"));
+    }
+
+    #[test]
+    fn render_history_page() {
+        let submitted_at = SystemTime::now() - Duration::from_secs(10);
+        let entry = JobHistoryEntry {
+            job_uuid: Uuid::new_v4(),
+            tenant: "tenant".to_owned(),
+            sql: Some("SELECT a FROM t WHERE a < 5".to_owned()),
+            plan: "Projection: #a".to_owned(),
+            fingerprint: 42,
+            state: JobState::Cancelled,
+            submitted_at,
+            finished_at: Some(submitted_at + Duration::from_millis(1500)),
+            output_rows: 10,
+            shuffle_bytes: 2048,
+            stages: vec![],
+        };
+
+        let html = render_history(&[entry.clone()], Some("a < 5"));
+        assert!(html.contains("value=\"a &lt; 5\""));
+        assert!(html.contains("<code>SELECT a FROM t WHERE a &lt; 5</code>"));
+        assert!(html.contains("<td>1.5 s</td>"));
+        assert!(html.contains("href=\"/history?fingerprint=42\""));
+
+        let html = render_history_entry(&entry);
+        assert!(html.contains(&format!("<h1>Job {}</h1>", entry.job_uuid)));
+        assert!(html.contains("<pre>Projection: #a</pre>"));
+    }
+
+    #[test]
+    fn decode_query_params() {
+        let query = Some("q=select+a%3C5%25&fingerprint=42&empty");
+        assert_eq!(Some("select a<5%".to_owned()), query_param(query, "q"));
+        assert_eq!(Some("42".to_owned()), query_param(query, "fingerprint"));
+        assert_eq!(Some("".to_owned()), query_param(query, "empty"));
+        assert_eq!(None, query_param(query, "other"));
+        assert_eq!(None, query_param(None, "q"));
+        assert_eq!("100%", decode_query_value("100%"));
+    }
+
+    #[test]
+    fn format_sizes() {
+        assert_eq!("512 B", format_bytes(512));
+        assert_eq!("1.5 KB", format_bytes(1536));
+        assert_eq!("2.0 GB", format_bytes(2 * 1024 * 1024 * 1024));
+        assert_eq!("250 ms", format_duration(250.0));
+        assert_eq!("1.5 s", format_duration(1500.0));
+    }
+}
@@ -39,6 +39,18 @@ pub enum BallistaError {
     KubeAPIError(kube::error::Error),
     KubeAPIRequestError(k8s_openapi::RequestError),
     KubeAPIResponseError(k8s_openapi::ResponseError),
+    Cancelled,
+    /// An operator could not reserve the memory that it needed and could not spill
+    ResourcesExhausted(String),
+    /// A job or task did not complete before its deadline
+    DeadlineExceeded(String),
+    /// The query could not be planned
+    PlanError(String),
+    /// Data did not have the schema that the plan expected
+    SchemaMismatch(String),
+    /// Shuffle output was lost with an executor that left the cluster and could not be
+    /// recomputed
+    ExecutorLost(String),
     // TonicError(tonic::status::Status)
 }
 
@@ -130,6 +142,14 @@ impl Display for BallistaError {
             BallistaError::KubeAPIResponseError(ref desc) => {
                 write!(f, "KubeAPI response error: {}", desc)
             }
+            BallistaError::Cancelled => write!(f, "Task cancelled"),
+            BallistaError::ResourcesExhausted(ref desc) => {
+                write!(f, "Resources exhausted: {}", desc)
+            }
+            BallistaError::DeadlineExceeded(ref desc) => write!(f, "Deadline exceeded: {}", desc),
+            BallistaError::PlanError(ref desc) => write!(f, "Plan error: {}", desc),
+            BallistaError::SchemaMismatch(ref desc) => write!(f, "Schema mismatch: {}", desc),
+            BallistaError::ExecutorLost(ref desc) => write!(f, "Executor lost: {}", desc),
         }
     }
 }
@@ -14,7 +14,7 @@
 
 use std::sync::Arc;
 
-use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::datatypes::{DataType, Field, Schema};
 use crate::error::Result;
 use crate::execution::physical_plan::{
     Accumulator, AggregateExpr, AggregateMode, ColumnarBatch, ColumnarValue, Expression,
@@ -92,6 +92,18 @@ impl AggregateExpr for AliasedAggregate {
     fn create_accumulator(&self, mode: &AggregateMode) -> Box<dyn Accumulator> {
         self.expr.create_accumulator(mode)
     }
+
+    fn state_fields(&self, input_schema: &Schema) -> Result<Vec<Field>> {
+        self.expr.state_fields(input_schema)
+    }
+
+    fn evaluate_state(&self, batch: &ColumnarBatch) -> Result<Vec<ColumnarValue>> {
+        self.expr.evaluate_state(batch)
+    }
+
+    fn mergeable(&self) -> bool {
+        self.expr.mergeable()
+    }
 }
 
 pub fn aliased_aggr(expr: Arc<dyn AggregateExpr>, alias: &str) -> Arc<dyn AggregateExpr> {
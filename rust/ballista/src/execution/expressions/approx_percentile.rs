@@ -0,0 +1,313 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Approximate percentile aggregate expression based on the t-digest data structure, which
+//! summarizes a distribution in a bounded number of centroids that are small near the tails,
+//! so that digests built from each partition can be merged into an accurate final digest.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::cast_array;
+use crate::datafusion::logicalplan::ScalarValue;
+use crate::error::{ballista_error, Result};
+use crate::execution::expressions::variance::scalar_to_f64;
+use crate::execution::physical_plan::{
+    Accumulator, AggregateExpr, AggregateMode, ColumnarBatch, ColumnarValue, Expression,
+};
+
+/// Controls the trade-off between the size of a digest and its accuracy
+const COMPRESSION: f64 = 100.0;
+
+#[derive(Debug)]
+pub struct ApproxPercentile {
+    /// The expression being aggregated, or the serialized digest when merging the intermediate
+    /// state of a partial aggregation
+    input: Arc<dyn Expression>,
+    /// The percentile to compute, between 0 and 1
+    percentile: f64,
+}
+
+impl ApproxPercentile {
+    pub fn try_new(input: Arc<dyn Expression>, percentile: f64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&percentile) {
+            return Err(ballista_error(&format!(
+                "Percentile must be between 0 and 1 but was {}",
+                percentile
+            )));
+        }
+        Ok(Self { input, percentile })
+    }
+}
+
+impl AggregateExpr for ApproxPercentile {
+    fn name(&self) -> String {
+        format!("APPROX_PERCENTILE({:?}, {})", self.input, self.percentile)
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Float64)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate_input(&self, batch: &ColumnarBatch) -> Result<ColumnarValue> {
+        self.input.evaluate(batch)
+    }
+
+    fn create_accumulator(&self, _mode: &AggregateMode) -> Box<dyn Accumulator> {
+        Box::new(ApproxPercentileAccumulator {
+            digest: TDigest::new(),
+            percentile: self.percentile,
+        })
+    }
+
+    fn state_fields(&self, _input_schema: &Schema) -> Result<Vec<Field>> {
+        Ok(vec![Field::new(
+            &format!("{}[digest]", self.name()),
+            DataType::Utf8,
+            true,
+        )])
+    }
+}
+
+struct ApproxPercentileAccumulator {
+    digest: TDigest,
+    percentile: f64,
+}
+
+impl Accumulator for ApproxPercentileAccumulator {
+    fn accumulate(&mut self, value: &ColumnarValue) -> Result<()> {
+        match value {
+            ColumnarValue::Columnar(array) => {
+                let array = compute::cast(array, &DataType::Float64)?;
+                let array = cast_array!(array, Float64Array)?;
+                for i in 0..array.len() {
+                    if array.is_valid(i) {
+                        self.digest.add(array.value(i));
+                    }
+                }
+            }
+            ColumnarValue::Scalar(Some(value), _) => self.digest.add(scalar_to_f64(value)?),
+            ColumnarValue::Scalar(None, _) => {}
+        }
+        Ok(())
+    }
+
+    fn get_value(&self) -> Result<Option<ScalarValue>> {
+        Ok(self
+            .digest
+            .clone()
+            .quantile(self.percentile)
+            .map(ScalarValue::Float64))
+    }
+
+    fn state(&self) -> Result<Vec<Option<ScalarValue>>> {
+        Ok(vec![Some(ScalarValue::Utf8(
+            self.digest.clone().serialize(),
+        ))])
+    }
+
+    fn merge(&mut self, state: &[ColumnarValue]) -> Result<()> {
+        match &state[0] {
+            ColumnarValue::Scalar(Some(ScalarValue::Utf8(s)), _) => {
+                self.digest.merge(&TDigest::deserialize(s)?);
+                Ok(())
+            }
+            ColumnarValue::Scalar(None, _) => Ok(()),
+            other => Err(ballista_error(&format!(
+                "Unexpected intermediate state for APPROX_PERCENTILE: {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// A merging t-digest. Values are buffered and periodically merged into the centroids, which
+/// are kept sorted by mean.
+#[derive(Debug, Clone)]
+struct TDigest {
+    /// Centroids as (mean, weight) pairs
+    centroids: Vec<(f64, f64)>,
+    /// Values that have not been merged into the centroids yet
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    fn new() -> Self {
+        Self {
+            centroids: vec![],
+            buffer: vec![],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.buffer.push(value);
+        if self.buffer.len() >= 10 * COMPRESSION as usize {
+            self.compress();
+        }
+    }
+
+    fn merge(&mut self, other: &TDigest) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.centroids.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        self.compress();
+    }
+
+    /// Merge the buffered values and any adjacent centroids that fit within the size limit for
+    /// their position in the distribution
+    fn compress(&mut self) {
+        let mut all: Vec<(f64, f64)> = self.centroids.drain(..).collect();
+        all.extend(self.buffer.drain(..).map(|v| (v, 1.0)));
+        if all.is_empty() {
+            return;
+        }
+        all.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let total: f64 = all.iter().map(|c| c.1).sum();
+        let mut so_far = 0.0;
+        let mut current = all[0];
+        for &(mean, weight) in &all[1..] {
+            let q = (so_far + (current.1 + weight) / 2.0) / total;
+            let limit = (4.0 * total * q * (1.0 - q) / COMPRESSION).max(1.0);
+            if current.1 + weight <= limit {
+                let merged = current.1 + weight;
+                current.0 += (mean - current.0) * weight / merged;
+                current.1 = merged;
+            } else {
+                so_far += current.1;
+                self.centroids.push(current);
+                current = (mean, weight);
+            }
+        }
+        self.centroids.push(current);
+    }
+
+    /// Estimate the value at quantile `q` by interpolating between centroid centers
+    fn quantile(mut self, q: f64) -> Option<f64> {
+        self.compress();
+        if self.centroids.is_empty() {
+            return None;
+        }
+        let total: f64 = self.centroids.iter().map(|c| c.1).sum();
+        let target = q * total;
+
+        let mut cumulative = 0.0;
+        let mut prev_center = 0.0;
+        let mut prev_mean = self.min;
+        for &(mean, weight) in &self.centroids {
+            let center = cumulative + weight / 2.0;
+            if target < center {
+                let fraction = if center > prev_center {
+                    (target - prev_center) / (center - prev_center)
+                } else {
+                    0.0
+                };
+                return Some(prev_mean + fraction * (mean - prev_mean));
+            }
+            cumulative += weight;
+            prev_center = center;
+            prev_mean = mean;
+        }
+        let fraction = if total > prev_center {
+            (target - prev_center) / (total - prev_center)
+        } else {
+            1.0
+        };
+        Some(prev_mean + fraction * (self.max - prev_mean))
+    }
+
+    /// Serialize the digest as `min,max;mean:weight,mean:weight,...`
+    fn serialize(mut self) -> String {
+        self.compress();
+        let centroids: Vec<String> = self
+            .centroids
+            .iter()
+            .map(|(mean, weight)| format!("{}:{}", mean, weight))
+            .collect();
+        format!("{},{};{}", self.min, self.max, centroids.join(","))
+    }
+
+    fn deserialize(s: &str) -> Result<Self> {
+        let parse = |s: &str| {
+            s.parse::<f64>()
+                .map_err(|e| ballista_error(&format!("Invalid t-digest value '{}': {:?}", s, e)))
+        };
+        let mut parts = s.splitn(2, ';');
+        let bounds = parts.next().unwrap_or_default();
+        let centroids = parts.next().unwrap_or_default();
+        let mut bounds = bounds.splitn(2, ',');
+        let mut digest = TDigest::new();
+        digest.min = parse(bounds.next().unwrap_or_default())?;
+        digest.max = parse(bounds.next().unwrap_or_default())?;
+        for centroid in centroids.split(',').filter(|c| !c.is_empty()) {
+            let mut parts = centroid.splitn(2, ':');
+            let mean = parse(parts.next().unwrap_or_default())?;
+            let weight = parse(parts.next().unwrap_or_default())?;
+            digest.centroids.push((mean, weight));
+        }
+        Ok(digest)
+    }
+}
+
+/// Create an approximate percentile expression
+pub fn approx_percentile(
+    input: Arc<dyn Expression>,
+    percentile: f64,
+) -> Result<Arc<dyn AggregateExpr>> {
+    Ok(Arc::new(ApproxPercentile::try_new(input, percentile)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_digests() {
+        let mut a = TDigest::new();
+        let mut b = TDigest::new();
+        for i in 0..10_000 {
+            if i % 3 == 0 {
+                a.add(i as f64);
+            } else {
+                b.add(i as f64);
+            }
+        }
+        let mut digest = TDigest::deserialize(&a.serialize()).unwrap();
+        digest.merge(&TDigest::deserialize(&b.serialize()).unwrap());
+        assert_eq!(Some(0.0), digest.clone().quantile(0.0));
+        assert_eq!(Some(9999.0), digest.clone().quantile(1.0));
+        for q in &[0.01, 0.25, 0.5, 0.75, 0.99] {
+            let estimate = digest.clone().quantile(*q).unwrap();
+            assert!(
+                (estimate - q * 10_000.0).abs() < 50.0,
+                "q={} estimate={}",
+                q,
+                estimate
+            );
+        }
+    }
+}
@@ -16,7 +16,7 @@ use std::sync::Arc;
 
 use crate::arrow::array;
 use crate::arrow::compute;
-use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::datatypes::{DataType, Field, Schema};
 use crate::cast_array;
 use crate::datafusion::logicalplan::ScalarValue;
 use crate::error::{ballista_error, Result};
@@ -24,24 +24,28 @@ use crate::execution::physical_plan::{
     Accumulator, AggregateExpr, AggregateMode, ColumnarBatch, ColumnarValue, Expression,
 };
 
+/// AVG aggregate expression. The intermediate state is the sum and count of the values so
+/// that partial results can be merged exactly.
 #[derive(Debug)]
 pub struct Avg {
-    input: Arc<dyn Expression>,
+    /// The expression being aggregated, or the sum and count columns when merging the
+    /// intermediate state of a partial aggregation
+    args: Vec<Arc<dyn Expression>>,
 }
 
 impl Avg {
-    pub fn new(input: Arc<dyn Expression>) -> Self {
-        Self { input }
+    pub fn new(args: Vec<Arc<dyn Expression>>) -> Self {
+        Self { args }
     }
 }
 
 impl AggregateExpr for Avg {
     fn name(&self) -> String {
-        format!("AVG({:?})", self.input)
+        format!("AVG({:?})", self.args[0])
     }
 
     fn data_type(&self, input_schema: &Schema) -> Result<DataType> {
-        match self.input.data_type(input_schema)? {
+        match self.args[0].data_type(input_schema)? {
             DataType::Int8
             | DataType::Int16
             | DataType::Int32
@@ -61,7 +65,7 @@ impl AggregateExpr for Avg {
     }
 
     fn evaluate_input(&self, batch: &ColumnarBatch) -> Result<ColumnarValue> {
-        self.input.evaluate(batch)
+        self.args[0].evaluate(batch)
     }
 
     fn create_accumulator(&self, _mode: &AggregateMode) -> Box<dyn Accumulator> {
@@ -70,6 +74,20 @@ impl AggregateExpr for Avg {
             count: None,
         })
     }
+
+    fn state_fields(&self, _input_schema: &Schema) -> Result<Vec<Field>> {
+        Ok(vec![
+            Field::new(&format!("{}[sum]", self.name()), DataType::Float64, true),
+            Field::new(&format!("{}[count]", self.name()), DataType::UInt64, true),
+        ])
+    }
+
+    fn evaluate_state(&self, batch: &ColumnarBatch) -> Result<Vec<ColumnarValue>> {
+        if self.args.len() != 2 {
+            return Err(ballista_error("AVG requires sum and count state columns"));
+        }
+        self.args.iter().map(|e| e.evaluate(batch)).collect()
+    }
 }
 
 macro_rules! avg_accumulate {
@@ -208,9 +226,34 @@ impl Accumulator for AvgAccumulator {
             _ => Ok(None),
         }
     }
+
+    fn state(&self) -> Result<Vec<Option<ScalarValue>>> {
+        Ok(vec![
+            self.sum.map(ScalarValue::Float64),
+            self.count.map(|n| ScalarValue::UInt64(n as u64)),
+        ])
+    }
+
+    fn merge(&mut self, state: &[ColumnarValue]) -> Result<()> {
+        match (&state[0], &state[1]) {
+            (
+                ColumnarValue::Scalar(Some(ScalarValue::Float64(sum)), _),
+                ColumnarValue::Scalar(Some(ScalarValue::UInt64(count)), _),
+            ) => {
+                self.sum = Some(self.sum.unwrap_or(0.0) + sum);
+                self.count = Some(self.count.unwrap_or(0) + *count as i64);
+                Ok(())
+            }
+            (ColumnarValue::Scalar(None, _), ColumnarValue::Scalar(None, _)) => Ok(()),
+            other => Err(ballista_error(&format!(
+                "Unexpected intermediate state for AVG: {:?}",
+                other
+            ))),
+        }
+    }
 }
 
 /// Create an avg expression
-pub fn avg(expr: Arc<dyn Expression>) -> Arc<dyn AggregateExpr> {
-    Arc::new(Avg::new(expr))
+pub fn avg(args: Vec<Arc<dyn Expression>>) -> Arc<dyn AggregateExpr> {
+    Arc::new(Avg::new(args))
 }
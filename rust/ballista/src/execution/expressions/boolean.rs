@@ -0,0 +1,223 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Boolean AND, OR, and NOT expressions, which follow the three-valued logic of SQL.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, BooleanArray};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::cast_array;
+use crate::datafusion::logicalplan::{Operator, ScalarValue};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// AND or OR of two boolean expressions
+#[derive(Debug)]
+pub struct BooleanExpr {
+    l: Arc<dyn Expression>,
+    op: Operator,
+    r: Arc<dyn Expression>,
+}
+
+impl BooleanExpr {
+    pub fn new(l: Arc<dyn Expression>, op: Operator, r: Arc<dyn Expression>) -> Self {
+        Self { l, op, r }
+    }
+}
+
+impl Expression for BooleanExpr {
+    fn name(&self) -> String {
+        format!("{} {:?} {}", self.l.name(), self.op, self.r.name())
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn nullable(&self, input_schema: &Schema) -> Result<bool> {
+        Ok(self.l.nullable(input_schema)? || self.r.nullable(input_schema)?)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let l = self.l.evaluate(input)?;
+        // a literal on the left side either decides the result or leaves it to the right side
+        if let ColumnarValue::Scalar(Some(ScalarValue::Boolean(b)), _) = &l {
+            return match (&self.op, b) {
+                (Operator::And, false) | (Operator::Or, true) => Ok(l),
+                _ => self.r.evaluate(input),
+            };
+        }
+        let l = l.to_arrow()?;
+        let r = self.r.evaluate(input)?.to_arrow()?;
+        let l = cast_array!(l, BooleanArray)?;
+        let r = cast_array!(r, BooleanArray)?;
+        let result = match self.op {
+            Operator::And => and_kleene(l, r)?,
+            Operator::Or => or_kleene(l, r)?,
+            ref other => {
+                return Err(ballista_error(&format!(
+                    "Invalid boolean operator '{:?}'",
+                    other
+                )))
+            }
+        };
+        Ok(ColumnarValue::Columnar(Arc::new(result)))
+    }
+}
+
+/// Create an AND or OR expression
+pub fn boolean(
+    l: Arc<dyn Expression>,
+    op: &Operator,
+    r: Arc<dyn Expression>,
+) -> Arc<dyn Expression> {
+    Arc::new(BooleanExpr::new(l, op.to_owned(), r))
+}
+
+/// NOT expression
+#[derive(Debug)]
+pub struct NotExpr {
+    expr: Arc<dyn Expression>,
+}
+
+impl NotExpr {
+    pub fn new(expr: Arc<dyn Expression>) -> Self {
+        Self { expr }
+    }
+}
+
+impl Expression for NotExpr {
+    fn name(&self) -> String {
+        format!("NOT {}", self.expr.name())
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn nullable(&self, input_schema: &Schema) -> Result<bool> {
+        self.expr.nullable(input_schema)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        match self.expr.evaluate(input)? {
+            ColumnarValue::Scalar(Some(ScalarValue::Boolean(b)), n) => {
+                Ok(ColumnarValue::Scalar(Some(ScalarValue::Boolean(!b)), n))
+            }
+            ColumnarValue::Scalar(None, n) => Ok(ColumnarValue::Scalar(None, n)),
+            value => {
+                let array = value.to_arrow()?;
+                let array = cast_array!(array, BooleanArray)?;
+                Ok(ColumnarValue::Columnar(Arc::new(compute::not(array)?)))
+            }
+        }
+    }
+}
+
+/// Create a NOT expression
+pub fn not(expr: Arc<dyn Expression>) -> Arc<dyn Expression> {
+    Arc::new(NotExpr::new(expr))
+}
+
+/// AND of two boolean arrays, in which false AND null is false. Arrays without nulls are
+/// combined with the Arrow kernel, which works on whole words of the bitmaps at a time.
+pub(crate) fn and_kleene(l: &BooleanArray, r: &BooleanArray) -> Result<BooleanArray> {
+    if l.null_count() == 0 && r.null_count() == 0 {
+        return Ok(compute::and(l, r)?);
+    }
+    Ok(kleene(l, r, |l, r| match (l, r) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+    }))
+}
+
+/// OR of two boolean arrays, in which true OR null is true. Arrays without nulls are combined
+/// with the Arrow kernel, which works on whole words of the bitmaps at a time.
+pub(crate) fn or_kleene(l: &BooleanArray, r: &BooleanArray) -> Result<BooleanArray> {
+    if l.null_count() == 0 && r.null_count() == 0 {
+        return Ok(compute::or(l, r)?);
+    }
+    Ok(kleene(l, r, |l, r| match (l, r) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+    }))
+}
+
+/// Combine two boolean arrays that have nulls one row at a time, because the Arrow kernels
+/// return null whenever either input is null
+fn kleene<F>(l: &BooleanArray, r: &BooleanArray, f: F) -> BooleanArray
+where
+    F: Fn(Option<bool>, Option<bool>) -> Option<bool>,
+{
+    let value = |array: &BooleanArray, i: usize| {
+        if array.is_valid(i) {
+            Some(array.value(i))
+        } else {
+            None
+        }
+    };
+    let values: Vec<Option<bool>> = (0..l.len()).map(|i| f(value(l, i), value(r, i))).collect();
+    BooleanArray::from(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::ArrayRef;
+    use crate::arrow::datatypes::Field;
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::execution::expressions::col;
+
+    #[test]
+    fn three_valued_logic() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Boolean, true),
+            Field::new("b", DataType::Boolean, true),
+        ]));
+        let a: ArrayRef = Arc::new(BooleanArray::from(vec![
+            Some(true),
+            Some(false),
+            None,
+            None,
+        ]));
+        let b: ArrayRef = Arc::new(BooleanArray::from(vec![
+            None,
+            None,
+            Some(true),
+            Some(false),
+        ]));
+        let batch = ColumnarBatch::from_arrow(&RecordBatch::try_new(schema, vec![a, b])?);
+
+        let and = boolean(col(0, "a"), &Operator::And, col(1, "b"));
+        let result = and.evaluate(&batch)?.to_arrow()?;
+        let result = cast_array!(result, BooleanArray)?;
+        assert!(result.is_null(0));
+        assert!(!result.value(1) && result.is_valid(1));
+        assert!(result.is_null(2));
+        assert!(!result.value(3) && result.is_valid(3));
+
+        let or = boolean(col(0, "a"), &Operator::Or, col(1, "b"));
+        let result = not(or).evaluate(&batch)?.to_arrow()?;
+        let result = cast_array!(result, BooleanArray)?;
+        assert!(!result.value(0) && result.is_valid(0));
+        assert!(result.is_null(1));
+        assert!(!result.value(2) && result.is_valid(2));
+        assert!(result.is_null(3));
+        Ok(())
+    }
+}
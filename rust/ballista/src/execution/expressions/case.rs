@@ -0,0 +1,340 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CASE expressions. The version of DataFusion that Ballista uses does not have a logical CASE
+//! expression, so CASE is encoded as a scalar function named `CASE` whose arguments are a
+//! boolean literal indicating whether there is a base expression, the base expression if there
+//! is one, pairs of WHEN and THEN expressions, and finally the ELSE expression if there is one.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::cast_array;
+use crate::datafusion::logicalplan::{Expr, Operator, ScalarValue};
+use crate::error::{ballista_error, Result};
+use crate::execution::expressions::comparison::compare_arrays;
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// Name of the scalar function that CASE expressions are encoded as
+pub const CASE_FUNCTION_NAME: &str = "CASE";
+
+/// The parts of a logical CASE expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseParts {
+    pub expr: Option<Expr>,
+    pub when_then_expr: Vec<(Expr, Expr)>,
+    pub else_expr: Option<Expr>,
+}
+
+impl CaseParts {
+    /// Encode the CASE expression as a logical expression
+    pub fn to_expr(&self, return_type: DataType) -> Expr {
+        let mut args = vec![Expr::Literal(ScalarValue::Boolean(self.expr.is_some()))];
+        args.extend(self.expr.iter().cloned());
+        for (when, then) in &self.when_then_expr {
+            args.push(when.clone());
+            args.push(then.clone());
+        }
+        args.extend(self.else_expr.iter().cloned());
+        Expr::ScalarFunction {
+            name: CASE_FUNCTION_NAME.to_owned(),
+            args,
+            return_type,
+        }
+    }
+
+    /// Decode a CASE expression from the arguments of the scalar function it is encoded as
+    pub fn try_from_args(args: &[Expr]) -> Result<Self> {
+        let (expr, rest) = match args.split_first() {
+            Some((Expr::Literal(ScalarValue::Boolean(true)), rest)) if !rest.is_empty() => {
+                (Some(rest[0].clone()), &rest[1..])
+            }
+            Some((Expr::Literal(ScalarValue::Boolean(false)), rest)) => (None, rest),
+            _ => return Err(ballista_error("Invalid CASE expression")),
+        };
+        if rest.len() < 2 {
+            return Err(ballista_error(
+                "CASE expression must have at least one WHEN",
+            ));
+        }
+        let when_then_expr = rest
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+        let else_expr = if rest.len() % 2 == 1 {
+            rest.last().cloned()
+        } else {
+            None
+        };
+        Ok(Self {
+            expr,
+            when_then_expr,
+            else_expr,
+        })
+    }
+
+    /// The expressions that produce the result of the CASE expression
+    pub fn result_exprs(&self) -> Vec<&Expr> {
+        self.when_then_expr
+            .iter()
+            .map(|(_, then)| then)
+            .chain(self.else_expr.iter())
+            .collect()
+    }
+}
+
+/// CASE expression, which produces the THEN value of the first WHEN expression that is true,
+/// or equal to the base expression when there is one, and otherwise the ELSE value or null.
+#[derive(Debug)]
+pub struct CaseExpr {
+    expr: Option<Arc<dyn Expression>>,
+    when_then_expr: Vec<(Arc<dyn Expression>, Arc<dyn Expression>)>,
+    else_expr: Option<Arc<dyn Expression>>,
+}
+
+impl CaseExpr {
+    pub fn try_new(
+        expr: Option<Arc<dyn Expression>>,
+        when_then_expr: Vec<(Arc<dyn Expression>, Arc<dyn Expression>)>,
+        else_expr: Option<Arc<dyn Expression>>,
+    ) -> Result<Self> {
+        if when_then_expr.is_empty() {
+            return Err(ballista_error(
+                "CASE expression must have at least one WHEN",
+            ));
+        }
+        Ok(Self {
+            expr,
+            when_then_expr,
+            else_expr,
+        })
+    }
+
+    fn result_exprs(&self) -> impl Iterator<Item = &Arc<dyn Expression>> {
+        self.when_then_expr
+            .iter()
+            .map(|(_, then)| then)
+            .chain(self.else_expr.iter())
+    }
+}
+
+impl Expression for CaseExpr {
+    fn name(&self) -> String {
+        let mut name = "CASE".to_owned();
+        if let Some(expr) = &self.expr {
+            name.push_str(&format!(" {}", expr.name()));
+        }
+        for (when, then) in &self.when_then_expr {
+            name.push_str(&format!(" WHEN {} THEN {}", when.name(), then.name()));
+        }
+        if let Some(else_expr) = &self.else_expr {
+            name.push_str(&format!(" ELSE {}", else_expr.name()));
+        }
+        name.push_str(" END");
+        name
+    }
+
+    fn data_type(&self, input_schema: &Schema) -> Result<DataType> {
+        common_type(self.result_exprs(), input_schema)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let data_type = self.data_type(&input.schema())?;
+        let base = match &self.expr {
+            Some(expr) => Some(expr.evaluate(input)?.to_arrow()?),
+            None => None,
+        };
+
+        let num_rows = input.num_rows();
+        let mut choices: Vec<Option<usize>> = vec![None; num_rows];
+        let mut values = Vec::with_capacity(self.when_then_expr.len() + 1);
+        for (i, (when, then)) in self.when_then_expr.iter().enumerate() {
+            let when = match &base {
+                Some(base) => {
+                    let when = when.evaluate(input)?.to_arrow()?;
+                    let when = if when.data_type() == base.data_type() {
+                        when
+                    } else {
+                        compute::cast(&when, base.data_type())?
+                    };
+                    Some(compare_arrays(base, &Operator::Eq, &when)?)
+                }
+                None => to_optional_array(when.evaluate(input)?, &DataType::Boolean)?,
+            };
+            if let Some(when) = when {
+                let when = cast_array!(when, BooleanArray)?;
+                for (row, choice) in choices.iter_mut().enumerate() {
+                    if choice.is_none() && when.is_valid(row) && when.value(row) {
+                        *choice = Some(i);
+                    }
+                }
+            }
+            values.push(to_optional_array(then.evaluate(input)?, &data_type)?);
+        }
+        if let Some(else_expr) = &self.else_expr {
+            let i = values.len();
+            values.push(to_optional_array(else_expr.evaluate(input)?, &data_type)?);
+            for choice in choices.iter_mut().filter(|c| c.is_none()) {
+                *choice = Some(i);
+            }
+        }
+
+        Ok(ColumnarValue::Columnar(select_rows(
+            &data_type, &values, &choices,
+        )?))
+    }
+}
+
+/// Create a CASE expression
+pub fn case(
+    expr: Option<Arc<dyn Expression>>,
+    when_then_expr: Vec<(Arc<dyn Expression>, Arc<dyn Expression>)>,
+    else_expr: Option<Arc<dyn Expression>>,
+) -> Result<Arc<dyn Expression>> {
+    Ok(Arc::new(CaseExpr::try_new(
+        expr,
+        when_then_expr,
+        else_expr,
+    )?))
+}
+
+/// Determine the type of a conditional expression from the first of its possible results that
+/// is not a null literal
+pub(crate) fn common_type<'a>(
+    exprs: impl Iterator<Item = &'a Arc<dyn Expression>>,
+    input_schema: &Schema,
+) -> Result<DataType> {
+    for expr in exprs {
+        match expr.data_type(input_schema)? {
+            DataType::Null => continue,
+            data_type => return Ok(data_type),
+        }
+    }
+    Err(ballista_error(
+        "Cannot determine the type of a conditional expression where all results are null",
+    ))
+}
+
+/// Convert a value to an array of the given type, or `None` when the value is a null scalar
+pub(crate) fn to_optional_array(
+    value: ColumnarValue,
+    data_type: &DataType,
+) -> Result<Option<ArrayRef>> {
+    match value {
+        ColumnarValue::Scalar(None, _) | ColumnarValue::Scalar(Some(ScalarValue::Null), _) => {
+            Ok(None)
+        }
+        value => {
+            let array = value.to_arrow()?;
+            if array.data_type() == data_type {
+                Ok(Some(array))
+            } else {
+                Ok(Some(compute::cast(&array, data_type)?))
+            }
+        }
+    }
+}
+
+macro_rules! select_rows {
+    ($VALUES:expr, $CHOICES:expr, $ARRAY_TYPE:ident, $BUILDER:ident) => {{
+        let values = $VALUES
+            .iter()
+            .map(|v| match v {
+                Some(v) => Ok(Some(cast_array!(v, $ARRAY_TYPE)?)),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut builder = array::$BUILDER::new($CHOICES.len());
+        for (row, choice) in $CHOICES.iter().enumerate() {
+            match choice.and_then(|i| values[i]) {
+                Some(v) if v.is_valid(row) => builder.append_value(v.value(row))?,
+                _ => builder.append_null()?,
+            }
+        }
+        Ok(Arc::new(builder.finish()))
+    }};
+}
+
+/// Build an array where each row is taken from the array chosen for that row, or is null when
+/// no array is chosen or the chosen array is a null scalar
+pub(crate) fn select_rows(
+    data_type: &DataType,
+    values: &[Option<ArrayRef>],
+    choices: &[Option<usize>],
+) -> Result<ArrayRef> {
+    match data_type {
+        DataType::Boolean => select_rows!(values, choices, BooleanArray, BooleanBuilder),
+        DataType::Int8 => select_rows!(values, choices, Int8Array, Int8Builder),
+        DataType::Int16 => select_rows!(values, choices, Int16Array, Int16Builder),
+        DataType::Int32 => select_rows!(values, choices, Int32Array, Int32Builder),
+        DataType::Int64 => select_rows!(values, choices, Int64Array, Int64Builder),
+        DataType::UInt8 => select_rows!(values, choices, UInt8Array, UInt8Builder),
+        DataType::UInt16 => select_rows!(values, choices, UInt16Array, UInt16Builder),
+        DataType::UInt32 => select_rows!(values, choices, UInt32Array, UInt32Builder),
+        DataType::UInt64 => select_rows!(values, choices, UInt64Array, UInt64Builder),
+        DataType::Float32 => select_rows!(values, choices, Float32Array, Float32Builder),
+        DataType::Float64 => select_rows!(values, choices, Float64Array, Float64Builder),
+        DataType::Utf8 => select_rows!(values, choices, StringArray, StringBuilder),
+        other => Err(ballista_error(&format!(
+            "Unsupported type for conditional expression {:?}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_case() -> Result<()> {
+        let parts = CaseParts {
+            expr: Some(Expr::Column(0)),
+            when_then_expr: vec![(
+                Expr::Literal(ScalarValue::Int64(1)),
+                Expr::Literal(ScalarValue::Utf8("one".to_owned())),
+            )],
+            else_expr: Some(Expr::Literal(ScalarValue::Utf8("other".to_owned()))),
+        };
+        match parts.to_expr(DataType::Utf8) {
+            Expr::ScalarFunction { args, .. } => {
+                assert_eq!(parts, CaseParts::try_from_args(&args)?);
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn select() -> Result<()> {
+        let a: ArrayRef = Arc::new(array::Int64Array::from(vec![Some(1), Some(2), None]));
+        let b: ArrayRef = Arc::new(array::Int64Array::from(vec![10, 20, 30]));
+        let result = select_rows(
+            &DataType::Int64,
+            &[Some(a), None, Some(b)],
+            &[Some(2), Some(1), Some(0)],
+        )?;
+        let result = cast_array!(result, Int64Array)?;
+        assert_eq!(10, result.value(0));
+        assert!(result.is_null(1));
+        assert!(result.is_null(2));
+        Ok(())
+    }
+}
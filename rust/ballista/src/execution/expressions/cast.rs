@@ -0,0 +1,227 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CAST expression. Casts are delegated to the Arrow cast kernel, except for conversions
+//! between strings and dates or timestamps, which the kernel does not support and which are
+//! performed with millisecond precision.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef, StringBuilder};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::cast_array;
+use crate::error::{ballista_error, Result};
+use crate::execution::expressions::scalar_function::{
+    civil_from_days, days_from_civil, from_millis, to_millis, MILLIS_PER_DAY, MILLIS_PER_HOUR,
+    MILLIS_PER_MINUTE, MILLIS_PER_SECOND,
+};
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// CAST expression
+#[derive(Debug)]
+pub struct CastExpr {
+    expr: Arc<dyn Expression>,
+    data_type: DataType,
+}
+
+impl CastExpr {
+    pub fn new(expr: Arc<dyn Expression>, data_type: DataType) -> Self {
+        Self { expr, data_type }
+    }
+}
+
+impl Expression for CastExpr {
+    fn name(&self) -> String {
+        format!("CAST({} AS {:?})", self.expr.name(), self.data_type)
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(self.data_type.clone())
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        // values that cannot be converted become null
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let array = self.expr.evaluate(input)?.to_arrow()?;
+        Ok(ColumnarValue::Columnar(cast_column(
+            &array,
+            &self.data_type,
+        )?))
+    }
+}
+
+/// Create a CAST expression
+pub fn cast(expr: Arc<dyn Expression>, data_type: DataType) -> Arc<dyn Expression> {
+    Arc::new(CastExpr::new(expr, data_type))
+}
+
+fn is_temporal(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Date32(_) | DataType::Date64(_) | DataType::Timestamp(..) => true,
+        _ => false,
+    }
+}
+
+/// Cast an array to the given type
+fn cast_column(array: &ArrayRef, data_type: &DataType) -> Result<ArrayRef> {
+    match (array.data_type(), data_type) {
+        (from, to) if from == to => Ok(array.clone()),
+        (DataType::Utf8, to) if is_temporal(to) => {
+            let strings = cast_array!(array, StringArray)?;
+            let values = (0..strings.len())
+                .map(|i| {
+                    if strings.is_valid(i) {
+                        parse_millis(strings.value(i))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            from_millis(values, to)
+        }
+        (from, DataType::Utf8) if is_temporal(from) => {
+            let date_only = match from {
+                DataType::Date32(_) | DataType::Date64(_) => true,
+                _ => false,
+            };
+            let values = to_millis(array)?;
+            let mut builder = StringBuilder::new(values.len());
+            for value in values {
+                match value {
+                    Some(millis) => builder.append_value(&format_millis(millis, date_only))?,
+                    None => builder.append_null()?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        (from, to) if is_temporal(from) && is_temporal(to) => {
+            // the cast kernel does not convert between all units of dates and timestamps
+            match compute::cast(array, to) {
+                Ok(array) => Ok(array),
+                Err(_) => from_millis(to_millis(array)?, to),
+            }
+        }
+        (_, to) => Ok(compute::cast(array, to)?),
+    }
+}
+
+/// Parse a date in the form `YYYY-MM-DD` or a timestamp in the form
+/// `YYYY-MM-DD HH:MM:SS[.fff]`, where the separator may also be `T`, into milliseconds since
+/// the UNIX epoch. Returns `None` if the string cannot be parsed.
+fn parse_millis(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, time) = match s.find(|c| c == ' ' || c == 'T') {
+        Some(i) => (&s[..i], Some(s[i + 1..].trim_end_matches('Z'))),
+        None => (s, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if month < 1 || month > 12 || day < 1 || day > 31 {
+        return None;
+    }
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+
+    if let Some(time) = time {
+        let (time, fraction) = match time.find('.') {
+            Some(i) => (&time[..i], Some(&time[i + 1..])),
+            None => (time, None),
+        };
+        let mut parts = time.splitn(3, ':');
+        let hour: i64 = parts.next()?.parse().ok()?;
+        let minute: i64 = parts.next()?.parse().ok()?;
+        let second: i64 = match parts.next() {
+            Some(second) => second.parse().ok()?,
+            None => 0,
+        };
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        millis += hour * MILLIS_PER_HOUR + minute * MILLIS_PER_MINUTE + second * MILLIS_PER_SECOND;
+        if let Some(fraction) = fraction {
+            if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let digits: String = fraction.chars().chain("000".chars()).take(3).collect();
+            millis += digits.parse::<i64>().ok()?;
+        }
+    }
+    Some(millis)
+}
+
+/// Format milliseconds since the UNIX epoch as a date or timestamp
+fn format_millis(millis: i64, date_only: bool) -> String {
+    let (year, month, day) = civil_from_days(millis.div_euclid(MILLIS_PER_DAY));
+    if date_only {
+        return format!("{:04}-{:02}-{:02}", year, month, day);
+    }
+    let time = millis.rem_euclid(MILLIS_PER_DAY);
+    let formatted = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / MILLIS_PER_HOUR,
+        time % MILLIS_PER_HOUR / MILLIS_PER_MINUTE,
+        time % MILLIS_PER_MINUTE / MILLIS_PER_SECOND
+    );
+    match time % MILLIS_PER_SECOND {
+        0 => formatted,
+        fraction => format!("{}.{:03}", formatted, fraction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::TimeUnit;
+
+    #[test]
+    fn string_to_timestamp() -> Result<()> {
+        let input: ArrayRef = Arc::new(array::StringArray::from(vec![
+            "2020-02-29 12:30:15.25",
+            "1969-12-31T23:59:59",
+            "not a timestamp",
+        ]));
+        let result = cast_column(&input, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+        let timestamps = cast_array!(result, TimestampMillisecondArray)?;
+        assert_eq!(1_582_979_415_250, timestamps.value(0));
+        assert_eq!(-1_000, timestamps.value(1));
+        assert!(timestamps.is_null(2));
+
+        let result = cast_column(&result, &DataType::Utf8)?;
+        let strings = cast_array!(result, StringArray)?;
+        assert_eq!("2020-02-29 12:30:15.250", strings.value(0));
+        assert_eq!("1969-12-31 23:59:59", strings.value(1));
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_casts() -> Result<()> {
+        let input: ArrayRef = Arc::new(array::StringArray::from(vec!["1", "2.5", "x"]));
+        let result = cast_column(&input, &DataType::Float64)?;
+        let result = cast_column(&result, &DataType::Int32)?;
+        let ints = cast_array!(result, Int32Array)?;
+        assert_eq!(1, ints.value(0));
+        assert_eq!(2, ints.value(1));
+        assert!(ints.is_null(2));
+        Ok(())
+    }
+}
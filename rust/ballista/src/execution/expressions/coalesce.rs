@@ -0,0 +1,79 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use crate::arrow::array::Array;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::error::{ballista_error, Result};
+use crate::execution::expressions::case::{common_type, select_rows, to_optional_array};
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// Name of the scalar function that COALESCE expressions are encoded as
+pub const COALESCE_FUNCTION_NAME: &str = "COALESCE";
+
+/// COALESCE expression, which produces the first of its arguments that is not null
+#[derive(Debug)]
+pub struct CoalesceExpr {
+    args: Vec<Arc<dyn Expression>>,
+}
+
+impl CoalesceExpr {
+    pub fn try_new(args: Vec<Arc<dyn Expression>>) -> Result<Self> {
+        if args.is_empty() {
+            return Err(ballista_error("COALESCE requires at least one argument"));
+        }
+        Ok(Self { args })
+    }
+}
+
+impl Expression for CoalesceExpr {
+    fn name(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(|e| e.name()).collect();
+        format!("COALESCE({})", args.join(", "))
+    }
+
+    fn data_type(&self, input_schema: &Schema) -> Result<DataType> {
+        common_type(self.args.iter(), input_schema)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let data_type = self.data_type(&input.schema())?;
+        let values = self
+            .args
+            .iter()
+            .map(|e| to_optional_array(e.evaluate(input)?, &data_type))
+            .collect::<Result<Vec<_>>>()?;
+        let choices: Vec<Option<usize>> = (0..input.num_rows())
+            .map(|row| {
+                values.iter().position(|v| match v {
+                    Some(v) => v.is_valid(row),
+                    None => false,
+                })
+            })
+            .collect();
+        Ok(ColumnarValue::Columnar(select_rows(
+            &data_type, &values, &choices,
+        )?))
+    }
+}
+
+/// Create a COALESCE expression
+pub fn coalesce(args: Vec<Arc<dyn Expression>>) -> Result<Arc<dyn Expression>> {
+    Ok(Arc::new(CoalesceExpr::try_new(args)?))
+}
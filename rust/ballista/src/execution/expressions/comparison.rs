@@ -14,11 +14,11 @@
 
 use std::sync::Arc;
 
-use crate::arrow::array;
+use crate::arrow::array::{self, ArrayRef};
 use crate::arrow::compute;
 use crate::arrow::datatypes::{DataType, Schema};
 use crate::cast_array;
-use crate::datafusion::logicalplan::Operator;
+use crate::datafusion::logicalplan::{Operator, ScalarValue};
 use crate::error::{ballista_error, Result};
 use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
 
@@ -59,7 +59,7 @@ macro_rules! compare_op {
                 other
             ))),
         }?;
-        Ok(ColumnarValue::Columnar(Arc::new(bools)))
+        Ok(Arc::new(bools))
     }};
 }
 
@@ -78,44 +78,131 @@ impl Expression for Comparison {
     }
 
     fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
-        let l = self.l.evaluate(input)?.to_arrow()?;
-        let r = self.r.evaluate(input)?.to_arrow()?;
-        if l.data_type() != r.data_type() {
-            return Err(ballista_error(
-                "Both inputs to Comparison expression must have same type",
-            ));
-        }
-        match l.data_type() {
-            DataType::Int8 => compare_op!(l, r, Int8Array, &self.op),
-            DataType::Int16 => compare_op!(l, r, Int16Array, &self.op),
-            DataType::Int32 => compare_op!(l, r, Int32Array, &self.op),
-            DataType::Int64 => compare_op!(l, r, Int64Array, &self.op),
-            DataType::UInt8 => compare_op!(l, r, UInt8Array, &self.op),
-            DataType::UInt16 => compare_op!(l, r, UInt16Array, &self.op),
-            DataType::UInt32 => compare_op!(l, r, UInt32Array, &self.op),
-            DataType::UInt64 => compare_op!(l, r, UInt64Array, &self.op),
-            DataType::Float32 => compare_op!(l, r, Float32Array, &self.op),
-            DataType::Float64 => compare_op!(l, r, Float64Array, &self.op),
-            DataType::Utf8 => {
-                let l = cast_array!(l, StringArray)?;
-                let r = cast_array!(r, StringArray)?;
-                let bools = match &self.op {
-                    Operator::Lt => Ok(compute::lt_utf8(l, r)?),
-                    Operator::LtEq => Ok(compute::lt_eq_utf8(l, r)?),
-                    Operator::Gt => Ok(compute::gt_utf8(l, r)?),
-                    Operator::GtEq => Ok(compute::gt_eq_utf8(l, r)?),
-                    Operator::Eq => Ok(compute::eq_utf8(l, r)?),
-                    Operator::NotEq => Ok(compute::neq_utf8(l, r)?),
-                    other => Err(ballista_error(&format!(
-                        "Invalid comparison operator '{:?}'",
-                        other
-                    ))),
-                }?;
-                Ok(ColumnarValue::Columnar(Arc::new(bools)))
+        // comparisons with a literal use the scalar kernels rather than expanding the literal
+        // into an array first
+        let result = match (self.l.evaluate(input)?, self.r.evaluate(input)?) {
+            (ColumnarValue::Columnar(l), ColumnarValue::Scalar(Some(r), _)) => {
+                compare_array_scalar(&l, &self.op, &r)?
+            }
+            (ColumnarValue::Scalar(Some(l), _), ColumnarValue::Columnar(r)) => {
+                compare_array_scalar(&r, &swap(&self.op), &l)?
             }
-            _ => Err(ballista_error(
-                "Unsupported datatype for Comparison expression",
-            )),
+            (l, r) => compare_arrays(&l.to_arrow()?, &self.op, &r.to_arrow()?)?,
+        };
+        Ok(ColumnarValue::Columnar(result))
+    }
+}
+
+/// The operator that gives the same result when the operands are swapped
+fn swap(op: &Operator) -> Operator {
+    match op {
+        Operator::Lt => Operator::Gt,
+        Operator::LtEq => Operator::GtEq,
+        Operator::Gt => Operator::Lt,
+        Operator::GtEq => Operator::LtEq,
+        other => other.clone(),
+    }
+}
+
+macro_rules! compare_scalar_op {
+    ($ARRAY:ident, $TY:ident, $VALUE:expr, $OP:expr) => {{
+        let array = cast_array!($ARRAY, $TY)?;
+        let bools = match $OP {
+            Operator::Lt => Ok(compute::lt_scalar(array, $VALUE)?),
+            Operator::LtEq => Ok(compute::lt_eq_scalar(array, $VALUE)?),
+            Operator::Gt => Ok(compute::gt_scalar(array, $VALUE)?),
+            Operator::GtEq => Ok(compute::gt_eq_scalar(array, $VALUE)?),
+            Operator::Eq => Ok(compute::eq_scalar(array, $VALUE)?),
+            Operator::NotEq => Ok(compute::neq_scalar(array, $VALUE)?),
+            other => Err(ballista_error(&format!(
+                "Invalid comparison operator '{:?}'",
+                other
+            ))),
+        }?;
+        Ok(Arc::new(bools))
+    }};
+}
+
+/// Compare an array with a scalar value of the same type, producing a boolean array
+pub(crate) fn compare_array_scalar(
+    l: &ArrayRef,
+    op: &Operator,
+    r: &ScalarValue,
+) -> Result<ArrayRef> {
+    match (l.data_type(), r) {
+        (DataType::Int8, ScalarValue::Int8(v)) => compare_scalar_op!(l, Int8Array, *v, op),
+        (DataType::Int16, ScalarValue::Int16(v)) => compare_scalar_op!(l, Int16Array, *v, op),
+        (DataType::Int32, ScalarValue::Int32(v)) => compare_scalar_op!(l, Int32Array, *v, op),
+        (DataType::Int64, ScalarValue::Int64(v)) => compare_scalar_op!(l, Int64Array, *v, op),
+        (DataType::UInt8, ScalarValue::UInt8(v)) => compare_scalar_op!(l, UInt8Array, *v, op),
+        (DataType::UInt16, ScalarValue::UInt16(v)) => compare_scalar_op!(l, UInt16Array, *v, op),
+        (DataType::UInt32, ScalarValue::UInt32(v)) => compare_scalar_op!(l, UInt32Array, *v, op),
+        (DataType::UInt64, ScalarValue::UInt64(v)) => compare_scalar_op!(l, UInt64Array, *v, op),
+        (DataType::Float32, ScalarValue::Float32(v)) => {
+            compare_scalar_op!(l, Float32Array, *v, op)
+        }
+        (DataType::Float64, ScalarValue::Float64(v)) => {
+            compare_scalar_op!(l, Float64Array, *v, op)
+        }
+        (DataType::Utf8, ScalarValue::Utf8(v)) => {
+            let l = cast_array!(l, StringArray)?;
+            let bools = match op {
+                Operator::Lt => Ok(compute::lt_utf8_scalar(l, v)?),
+                Operator::LtEq => Ok(compute::lt_eq_utf8_scalar(l, v)?),
+                Operator::Gt => Ok(compute::gt_utf8_scalar(l, v)?),
+                Operator::GtEq => Ok(compute::gt_eq_utf8_scalar(l, v)?),
+                Operator::Eq => Ok(compute::eq_utf8_scalar(l, v)?),
+                Operator::NotEq => Ok(compute::neq_utf8_scalar(l, v)?),
+                other => Err(ballista_error(&format!(
+                    "Invalid comparison operator '{:?}'",
+                    other
+                ))),
+            }?;
+            Ok(Arc::new(bools))
+        }
+        _ => Err(ballista_error(
+            "Both inputs to Comparison expression must have same type",
+        )),
+    }
+}
+
+/// Compare two arrays of the same type, producing a boolean array
+pub(crate) fn compare_arrays(l: &ArrayRef, op: &Operator, r: &ArrayRef) -> Result<ArrayRef> {
+    if l.data_type() != r.data_type() {
+        return Err(ballista_error(
+            "Both inputs to Comparison expression must have same type",
+        ));
+    }
+    match l.data_type() {
+        DataType::Int8 => compare_op!(l, r, Int8Array, op),
+        DataType::Int16 => compare_op!(l, r, Int16Array, op),
+        DataType::Int32 => compare_op!(l, r, Int32Array, op),
+        DataType::Int64 => compare_op!(l, r, Int64Array, op),
+        DataType::UInt8 => compare_op!(l, r, UInt8Array, op),
+        DataType::UInt16 => compare_op!(l, r, UInt16Array, op),
+        DataType::UInt32 => compare_op!(l, r, UInt32Array, op),
+        DataType::UInt64 => compare_op!(l, r, UInt64Array, op),
+        DataType::Float32 => compare_op!(l, r, Float32Array, op),
+        DataType::Float64 => compare_op!(l, r, Float64Array, op),
+        DataType::Utf8 => {
+            let l = cast_array!(l, StringArray)?;
+            let r = cast_array!(r, StringArray)?;
+            let bools = match op {
+                Operator::Lt => Ok(compute::lt_utf8(l, r)?),
+                Operator::LtEq => Ok(compute::lt_eq_utf8(l, r)?),
+                Operator::Gt => Ok(compute::gt_utf8(l, r)?),
+                Operator::GtEq => Ok(compute::gt_eq_utf8(l, r)?),
+                Operator::Eq => Ok(compute::eq_utf8(l, r)?),
+                Operator::NotEq => Ok(compute::neq_utf8(l, r)?),
+                other => Err(ballista_error(&format!(
+                    "Invalid comparison operator '{:?}'",
+                    other
+                ))),
+            }?;
+            Ok(Arc::new(bools))
         }
+        _ => Err(ballista_error(
+            "Unsupported datatype for Comparison expression",
+        )),
     }
 }
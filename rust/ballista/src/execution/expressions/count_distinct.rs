@@ -0,0 +1,128 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::datafusion::logicalplan::ScalarValue;
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::{
+    Accumulator, AggregateExpr, AggregateMode, ColumnarBatch, ColumnarValue, Expression,
+};
+
+#[derive(Debug)]
+pub struct CountDistinct {
+    input: Arc<dyn Expression>,
+}
+
+impl CountDistinct {
+    pub fn new(input: Arc<dyn Expression>) -> Self {
+        Self { input }
+    }
+}
+
+impl AggregateExpr for CountDistinct {
+    fn name(&self) -> String {
+        format!("COUNT_DISTINCT({:?})", self.input)
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::UInt64)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate_input(&self, batch: &ColumnarBatch) -> Result<ColumnarValue> {
+        self.input.evaluate(batch)
+    }
+
+    fn create_accumulator(&self, _mode: &AggregateMode) -> Box<dyn Accumulator> {
+        // the set of distinct values cannot be merged from partial results, so the query
+        // planner must make sure that all values for a group are seen by a single accumulator
+        Box::new(CountDistinctAccumulator {
+            values: HashSet::new(),
+        })
+    }
+
+    fn mergeable(&self) -> bool {
+        false
+    }
+}
+
+/// Hashable representation of the values that COUNT(DISTINCT) can be applied to. Floating
+/// point values are compared by their bit pattern.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum DistinctValue {
+    Boolean(bool),
+    Int(i64),
+    UInt(u64),
+    Float(u64),
+    Utf8(String),
+}
+
+impl DistinctValue {
+    fn try_from_scalar(value: &ScalarValue) -> Result<Self> {
+        match value {
+            ScalarValue::Boolean(v) => Ok(DistinctValue::Boolean(*v)),
+            ScalarValue::Int8(v) => Ok(DistinctValue::Int(*v as i64)),
+            ScalarValue::Int16(v) => Ok(DistinctValue::Int(*v as i64)),
+            ScalarValue::Int32(v) => Ok(DistinctValue::Int(*v as i64)),
+            ScalarValue::Int64(v) => Ok(DistinctValue::Int(*v)),
+            ScalarValue::UInt8(v) => Ok(DistinctValue::UInt(*v as u64)),
+            ScalarValue::UInt16(v) => Ok(DistinctValue::UInt(*v as u64)),
+            ScalarValue::UInt32(v) => Ok(DistinctValue::UInt(*v as u64)),
+            ScalarValue::UInt64(v) => Ok(DistinctValue::UInt(*v)),
+            ScalarValue::Float32(v) => Ok(DistinctValue::Float((*v as f64).to_bits())),
+            ScalarValue::Float64(v) => Ok(DistinctValue::Float(v.to_bits())),
+            ScalarValue::Utf8(v) => Ok(DistinctValue::Utf8(v.clone())),
+            other => Err(BallistaError::NotImplemented(format!(
+                "COUNT(DISTINCT) of {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+struct CountDistinctAccumulator {
+    values: HashSet<DistinctValue>,
+}
+
+impl Accumulator for CountDistinctAccumulator {
+    fn accumulate(&mut self, value: &ColumnarValue) -> Result<()> {
+        match value {
+            ColumnarValue::Scalar(Some(value), _) => {
+                self.values.insert(DistinctValue::try_from_scalar(value)?);
+            }
+            ColumnarValue::Scalar(None, _) => {}
+            ColumnarValue::Columnar(_) => {
+                return Err(BallistaError::NotImplemented(
+                    "COUNT(DISTINCT) of columnar values".to_owned(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn get_value(&self) -> Result<Option<ScalarValue>> {
+        Ok(Some(ScalarValue::UInt64(self.values.len() as u64)))
+    }
+}
+
+/// Create a count distinct expression
+pub fn count_distinct(expr: Arc<dyn Expression>) -> Arc<dyn AggregateExpr> {
+    Arc::new(CountDistinct::new(expr))
+}
@@ -0,0 +1,259 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! IN list and BETWEEN predicates. The version of DataFusion that Ballista uses does not have
+//! logical expressions for these predicates, so they are encoded as scalar functions named
+//! `IN_LIST` and `BETWEEN` whose first argument is a boolean literal indicating whether the
+//! predicate is negated, followed by the expression being tested and then either the list of
+//! values or the low and high bounds.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef, BooleanArray};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::cast_array;
+use crate::datafusion::logicalplan::{Expr, Operator, ScalarValue};
+use crate::error::{ballista_error, Result};
+use crate::execution::expressions::boolean::{and_kleene, or_kleene};
+use crate::execution::expressions::comparison::compare_arrays;
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// Name of the scalar function that IN list predicates are encoded as
+pub const IN_LIST_FUNCTION_NAME: &str = "IN_LIST";
+
+/// Name of the scalar function that BETWEEN predicates are encoded as
+pub const BETWEEN_FUNCTION_NAME: &str = "BETWEEN";
+
+/// Encode a predicate as a logical expression
+pub fn encode_predicate(name: &str, negated: bool, args: Vec<Expr>) -> Expr {
+    let mut encoded = vec![Expr::Literal(ScalarValue::Boolean(negated))];
+    encoded.extend(args);
+    Expr::ScalarFunction {
+        name: name.to_owned(),
+        args: encoded,
+        return_type: DataType::Boolean,
+    }
+}
+
+/// Decode the arguments of an encoded predicate into the negated flag, the expression being
+/// tested, and the remaining arguments
+pub fn decode_predicate(args: &[Expr]) -> Result<(bool, &Expr, &[Expr])> {
+    match args {
+        [Expr::Literal(ScalarValue::Boolean(negated)), expr, rest @ ..] => {
+            Ok((*negated, expr, rest))
+        }
+        _ => Err(ballista_error(&format!("Invalid predicate {:?}", args))),
+    }
+}
+
+/// IN list predicate, which is true when the expression is equal to any of the values in the
+/// list, null when there is no match and either the expression or a value in the list is null,
+/// and false otherwise
+#[derive(Debug)]
+pub struct InListExpr {
+    expr: Arc<dyn Expression>,
+    list: Vec<Arc<dyn Expression>>,
+    negated: bool,
+}
+
+impl InListExpr {
+    pub fn new(expr: Arc<dyn Expression>, list: Vec<Arc<dyn Expression>>, negated: bool) -> Self {
+        Self {
+            expr,
+            list,
+            negated,
+        }
+    }
+}
+
+impl Expression for InListExpr {
+    fn name(&self) -> String {
+        let list: Vec<String> = self.list.iter().map(|e| e.name()).collect();
+        format!(
+            "{} {}IN ({})",
+            self.expr.name(),
+            if self.negated { "NOT " } else { "" },
+            list.join(", ")
+        )
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let array = self.expr.evaluate(input)?.to_arrow()?;
+        // the comparisons with each value are combined with OR, so that a row is null when
+        // there is no match and any of the comparisons is null
+        let mut result: Option<BooleanArray> = None;
+        for value in &self.list {
+            let value = evaluate_as(value.as_ref(), input, array.data_type())?;
+            let eq = compare_arrays(&array, &Operator::Eq, &value)?;
+            let eq = cast_array!(eq, BooleanArray)?;
+            result = Some(match result {
+                Some(result) => or_kleene(&result, eq)?,
+                None => BooleanArray::from(eq.data()),
+            });
+        }
+        match result {
+            Some(result) => Ok(ColumnarValue::Columnar(negate(result, self.negated)?)),
+            None => Err(ballista_error("IN list requires at least one value")),
+        }
+    }
+}
+
+/// Create an IN list predicate
+pub fn in_list(
+    expr: Arc<dyn Expression>,
+    list: Vec<Arc<dyn Expression>>,
+    negated: bool,
+) -> Arc<dyn Expression> {
+    Arc::new(InListExpr::new(expr, list, negated))
+}
+
+/// BETWEEN predicate, which is true when the expression is greater than or equal to the low
+/// bound and less than or equal to the high bound
+#[derive(Debug)]
+pub struct BetweenExpr {
+    expr: Arc<dyn Expression>,
+    low: Arc<dyn Expression>,
+    high: Arc<dyn Expression>,
+    negated: bool,
+}
+
+impl BetweenExpr {
+    pub fn new(
+        expr: Arc<dyn Expression>,
+        low: Arc<dyn Expression>,
+        high: Arc<dyn Expression>,
+        negated: bool,
+    ) -> Self {
+        Self {
+            expr,
+            low,
+            high,
+            negated,
+        }
+    }
+}
+
+impl Expression for BetweenExpr {
+    fn name(&self) -> String {
+        format!(
+            "{} {}BETWEEN {} AND {}",
+            self.expr.name(),
+            if self.negated { "NOT " } else { "" },
+            self.low.name(),
+            self.high.name()
+        )
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let array = self.expr.evaluate(input)?.to_arrow()?;
+        let low = evaluate_as(self.low.as_ref(), input, array.data_type())?;
+        let high = evaluate_as(self.high.as_ref(), input, array.data_type())?;
+        let ge = compare_arrays(&array, &Operator::GtEq, &low)?;
+        let ge = cast_array!(ge, BooleanArray)?;
+        let le = compare_arrays(&array, &Operator::LtEq, &high)?;
+        let le = cast_array!(le, BooleanArray)?;
+        let result = and_kleene(ge, le)?;
+        Ok(ColumnarValue::Columnar(negate(result, self.negated)?))
+    }
+}
+
+/// Create a BETWEEN predicate
+pub fn between(
+    expr: Arc<dyn Expression>,
+    low: Arc<dyn Expression>,
+    high: Arc<dyn Expression>,
+    negated: bool,
+) -> Arc<dyn Expression> {
+    Arc::new(BetweenExpr::new(expr, low, high, negated))
+}
+
+/// Evaluate an expression and cast the result to the type of the expression it is compared to
+fn evaluate_as(
+    expr: &dyn Expression,
+    input: &ColumnarBatch,
+    data_type: &DataType,
+) -> Result<ArrayRef> {
+    let array = expr.evaluate(input)?.to_arrow()?;
+    if array.data_type() == data_type {
+        Ok(array)
+    } else {
+        Ok(compute::cast(&array, data_type)?)
+    }
+}
+
+/// Negate the result of a predicate if the predicate is negated
+fn negate(result: BooleanArray, negated: bool) -> Result<ArrayRef> {
+    let result = if negated {
+        compute::not(&result)?
+    } else {
+        result
+    };
+    Ok(Arc::new(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::datatypes::Field;
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::execution::expressions::{col, lit};
+
+    #[test]
+    fn in_list_and_between() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, true)]));
+        let a: ArrayRef = Arc::new(array::Int64Array::from(vec![Some(1), Some(5), None]));
+        let batch = ColumnarBatch::from_arrow(&RecordBatch::try_new(schema, vec![a])?);
+
+        let expr = in_list(
+            col(0, "a"),
+            vec![lit(ScalarValue::Int32(1)), lit(ScalarValue::Int64(2))],
+            false,
+        );
+        let result = expr.evaluate(&batch)?.to_arrow()?;
+        let result = cast_array!(result, BooleanArray)?;
+        assert!(result.value(0));
+        assert!(!result.value(1));
+        assert!(result.is_null(2));
+
+        let expr = between(
+            col(0, "a"),
+            lit(ScalarValue::Int64(2)),
+            lit(ScalarValue::Int64(5)),
+            true,
+        );
+        let result = expr.evaluate(&batch)?.to_arrow()?;
+        let result = cast_array!(result, BooleanArray)?;
+        assert!(result.value(0));
+        assert!(!result.value(1));
+        assert!(result.is_null(2));
+        Ok(())
+    }
+}
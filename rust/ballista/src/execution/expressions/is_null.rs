@@ -0,0 +1,85 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::datafusion::logicalplan::ScalarValue;
+use crate::error::Result;
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// IS NULL or IS NOT NULL expression
+#[derive(Debug)]
+pub struct IsNullExpr {
+    expr: Arc<dyn Expression>,
+    negated: bool,
+}
+
+impl IsNullExpr {
+    pub fn new(expr: Arc<dyn Expression>, negated: bool) -> Self {
+        Self { expr, negated }
+    }
+}
+
+impl Expression for IsNullExpr {
+    fn name(&self) -> String {
+        if self.negated {
+            format!("{} IS NOT NULL", self.expr.name())
+        } else {
+            format!("{} IS NULL", self.expr.name())
+        }
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        match self.expr.evaluate(input)? {
+            ColumnarValue::Scalar(value, n) => {
+                let is_null = match value {
+                    None | Some(ScalarValue::Null) => true,
+                    Some(_) => false,
+                };
+                Ok(ColumnarValue::Scalar(
+                    Some(ScalarValue::Boolean(is_null != self.negated)),
+                    n,
+                ))
+            }
+            ColumnarValue::Columnar(array) => {
+                let bools = if self.negated {
+                    compute::is_not_null(&array)?
+                } else {
+                    compute::is_null(&array)?
+                };
+                Ok(ColumnarValue::Columnar(Arc::new(bools)))
+            }
+        }
+    }
+}
+
+/// Create an IS NULL expression
+pub fn is_null(expr: Arc<dyn Expression>) -> Arc<dyn Expression> {
+    Arc::new(IsNullExpr::new(expr, false))
+}
+
+/// Create an IS NOT NULL expression
+pub fn is_not_null(expr: Arc<dyn Expression>) -> Arc<dyn Expression> {
+    Arc::new(IsNullExpr::new(expr, true))
+}
@@ -47,6 +47,9 @@ impl Expression for Literal {
             ScalarValue::Int64(_) => Ok(DataType::Int64),
             ScalarValue::Float32(_) => Ok(DataType::Float32),
             ScalarValue::Float64(_) => Ok(DataType::Float64),
+            ScalarValue::Utf8(_) => Ok(DataType::Utf8),
+            ScalarValue::Boolean(_) => Ok(DataType::Boolean),
+            ScalarValue::Null => Ok(DataType::Null),
             _ => unimplemented!(),
         }
     }
@@ -15,23 +15,54 @@
 //! Relational expressions that can be used in query plans.
 
 pub use self::alias::{alias, aliased_aggr};
+pub use self::approx_percentile::approx_percentile;
 pub use self::arithmetic::{add, div, mult, subtract};
 pub use self::avg::avg;
+pub use self::boolean::{boolean, not};
+pub use self::case::{case, CaseParts, CASE_FUNCTION_NAME};
+pub use self::cast::cast;
+pub use self::coalesce::{coalesce, COALESCE_FUNCTION_NAME};
 pub use self::column::col;
 pub use self::comparison::compare;
 pub use self::count::count;
+pub use self::count_distinct::count_distinct;
+pub use self::in_list::{
+    between, decode_predicate, encode_predicate, in_list, BETWEEN_FUNCTION_NAME,
+    IN_LIST_FUNCTION_NAME,
+};
+pub use self::is_null::{is_not_null, is_null};
 pub use self::literal::lit;
 pub use self::max::max;
 pub use self::min::min;
+pub use self::parameter::{
+    bind_parameters, collect_parameters, parameter, parameter_index, unbound_parameter,
+    PARAMETER_FUNCTION_NAME,
+};
+pub(crate) use self::scalar_function::civil_from_days;
+pub use self::scalar_function::{scalar_function, ScalarFunction};
+pub use self::scalar_udf::scalar_udf;
 pub use self::sum::sum;
+pub use self::variance::{stddev, stddev_pop, variance, variance_pop};
 
 mod alias;
+mod approx_percentile;
 mod arithmetic;
 mod avg;
+mod boolean;
+mod case;
+mod cast;
+mod coalesce;
 mod column;
 mod comparison;
 mod count;
+mod count_distinct;
+mod in_list;
+mod is_null;
 mod literal;
 mod max;
 mod min;
+mod parameter;
+mod scalar_function;
+mod scalar_udf;
 mod sum;
+mod variance;
@@ -0,0 +1,224 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parameters of prepared queries, which are placeholders for values that are bound each time
+//! the query is executed.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::datafusion::logicalplan::{Expr, ScalarValue};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+/// Name of the scalar function that parameters are encoded as
+pub const PARAMETER_FUNCTION_NAME: &str = "PARAMETER";
+
+/// Create a parameter, which refers to the value at `index` of the values that a prepared
+/// query is executed with, and which has the given type
+pub fn parameter(index: usize, data_type: DataType) -> Expr {
+    Expr::ScalarFunction {
+        name: PARAMETER_FUNCTION_NAME.to_owned(),
+        args: vec![Expr::Literal(ScalarValue::UInt32(index as u32))],
+        return_type: data_type,
+    }
+}
+
+/// The index and type of a parameter, or `None` if the expression is not a parameter
+pub fn parameter_index(expr: &Expr) -> Option<(usize, &DataType)> {
+    match expr {
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } if name == PARAMETER_FUNCTION_NAME => match args.as_slice() {
+            [Expr::Literal(ScalarValue::UInt32(index))] => Some((*index as usize, return_type)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Parameter that has not been bound to a value, which has the type of the parameter so that
+/// a prepared query can be planned, but which fails if it is evaluated
+#[derive(Debug)]
+pub struct ParameterExpr {
+    index: usize,
+    data_type: DataType,
+}
+
+impl Expression for ParameterExpr {
+    fn name(&self) -> String {
+        format!("${}", self.index + 1)
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(self.data_type.clone())
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, _input: &ColumnarBatch) -> Result<ColumnarValue> {
+        Err(ballista_error(&format!(
+            "Parameter {} must be bound to a value before the query is executed",
+            self.index
+        )))
+    }
+}
+
+/// Create a placeholder for a parameter that has not been bound to a value
+pub fn unbound_parameter(index: usize, data_type: DataType) -> Arc<dyn Expression> {
+    Arc::new(ParameterExpr { index, data_type })
+}
+
+/// Add the parameters that an expression refers to, by index, failing if the same parameter
+/// is used with different types
+pub fn collect_parameters(expr: &Expr, parameters: &mut BTreeMap<usize, DataType>) -> Result<()> {
+    if let Some((index, data_type)) = parameter_index(expr) {
+        return match parameters.insert(index, data_type.clone()) {
+            Some(existing) if existing != *data_type => Err(ballista_error(&format!(
+                "Parameter {} is used as both {:?} and {:?}",
+                index, existing, data_type
+            ))),
+            _ => Ok(()),
+        };
+    }
+    match expr {
+        Expr::Alias(expr, _)
+        | Expr::Not(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::Sort { expr, .. } => collect_parameters(expr, parameters),
+        Expr::BinaryExpr { left, right, .. } => {
+            collect_parameters(left, parameters)?;
+            collect_parameters(right, parameters)
+        }
+        Expr::ScalarFunction { args, .. } | Expr::AggregateFunction { args, .. } => args
+            .iter()
+            .try_for_each(|arg| collect_parameters(arg, parameters)),
+        _ => Ok(()),
+    }
+}
+
+/// Replace the parameters of an expression with the values at their index. Values of a
+/// different type than the parameter are cast to the type of the parameter.
+pub fn bind_parameters(expr: &Expr, values: &[ScalarValue]) -> Result<Expr> {
+    if let Some((index, data_type)) = parameter_index(expr) {
+        let value = values.get(index).ok_or_else(|| {
+            ballista_error(&format!(
+                "No value was bound to parameter {} of {} values",
+                index,
+                values.len()
+            ))
+        })?;
+        let literal = Expr::Literal(value.clone());
+        return if literal.get_type(&Schema::empty())? == *data_type {
+            Ok(literal)
+        } else {
+            Ok(Expr::Cast {
+                expr: Box::new(literal),
+                data_type: data_type.clone(),
+            })
+        };
+    }
+    let bind_box =
+        |expr: &Expr| -> Result<Box<Expr>> { Ok(Box::new(bind_parameters(expr, values)?)) };
+    let bind_list = |args: &[Expr]| -> Result<Vec<Expr>> {
+        args.iter().map(|e| bind_parameters(e, values)).collect()
+    };
+    match expr {
+        Expr::Alias(expr, alias) => Ok(Expr::Alias(bind_box(expr)?, alias.clone())),
+        Expr::Not(expr) => Ok(Expr::Not(bind_box(expr)?)),
+        Expr::IsNull(expr) => Ok(Expr::IsNull(bind_box(expr)?)),
+        Expr::IsNotNull(expr) => Ok(Expr::IsNotNull(bind_box(expr)?)),
+        Expr::Cast { expr, data_type } => Ok(Expr::Cast {
+            expr: bind_box(expr)?,
+            data_type: data_type.clone(),
+        }),
+        Expr::Sort {
+            expr,
+            asc,
+            nulls_first,
+        } => Ok(Expr::Sort {
+            expr: bind_box(expr)?,
+            asc: *asc,
+            nulls_first: *nulls_first,
+        }),
+        Expr::BinaryExpr { left, op, right } => Ok(Expr::BinaryExpr {
+            left: bind_box(left)?,
+            op: op.clone(),
+            right: bind_box(right)?,
+        }),
+        Expr::ScalarFunction {
+            name,
+            args,
+            return_type,
+        } => Ok(Expr::ScalarFunction {
+            name: name.clone(),
+            args: bind_list(args)?,
+            return_type: return_type.clone(),
+        }),
+        Expr::AggregateFunction {
+            name,
+            args,
+            return_type,
+        } => Ok(Expr::AggregateFunction {
+            name: name.clone(),
+            args: bind_list(args)?,
+            return_type: return_type.clone(),
+        }),
+        other => Ok(other.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::logicalplan::{col_index, Operator};
+
+    #[test]
+    fn bind_values_to_parameters() -> Result<()> {
+        let predicate = |value: Expr| Expr::BinaryExpr {
+            left: Box::new(col_index(0)),
+            op: Operator::Gt,
+            right: Box::new(value),
+        };
+        let expr = predicate(parameter(0, DataType::Int64));
+
+        let mut parameters = BTreeMap::new();
+        collect_parameters(&expr, &mut parameters)?;
+        assert_eq!(
+            vec![(0, DataType::Int64)],
+            parameters.into_iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            predicate(Expr::Literal(ScalarValue::Int64(5))),
+            bind_parameters(&expr, &[ScalarValue::Int64(5)])?
+        );
+        assert_eq!(
+            predicate(Expr::Cast {
+                expr: Box::new(Expr::Literal(ScalarValue::Int32(5))),
+                data_type: DataType::Int64,
+            }),
+            bind_parameters(&expr, &[ScalarValue::Int32(5)])?
+        );
+        assert!(bind_parameters(&expr, &[]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,595 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Built-in scalar functions that can be evaluated in the physical expression layer. Functions
+//! are referenced by name from `Expr::ScalarFunction` in logical plans and resolved against
+//! this registry when the plan is compiled on the executor.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef, StringBuilder};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, DateUnit, Schema, TimeUnit};
+use crate::cast_array;
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+
+pub(crate) const MILLIS_PER_SECOND: i64 = 1_000;
+pub(crate) const MILLIS_PER_MINUTE: i64 = 60 * MILLIS_PER_SECOND;
+pub(crate) const MILLIS_PER_HOUR: i64 = 60 * MILLIS_PER_MINUTE;
+pub(crate) const MILLIS_PER_DAY: i64 = 24 * MILLIS_PER_HOUR;
+
+/// Registry of the built-in scalar functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalarFunction {
+    Abs,
+    Ceil,
+    Floor,
+    Round,
+    Sqrt,
+    Ln,
+    Exp,
+    Upper,
+    Lower,
+    Trim,
+    Ltrim,
+    Rtrim,
+    Length,
+    Substring,
+    Concat,
+    DateTrunc,
+    Extract,
+}
+
+impl ScalarFunction {
+    /// All of the built-in scalar functions
+    pub const ALL: [ScalarFunction; 17] = [
+        ScalarFunction::Abs,
+        ScalarFunction::Ceil,
+        ScalarFunction::Floor,
+        ScalarFunction::Round,
+        ScalarFunction::Sqrt,
+        ScalarFunction::Ln,
+        ScalarFunction::Exp,
+        ScalarFunction::Upper,
+        ScalarFunction::Lower,
+        ScalarFunction::Trim,
+        ScalarFunction::Ltrim,
+        ScalarFunction::Rtrim,
+        ScalarFunction::Length,
+        ScalarFunction::Substring,
+        ScalarFunction::Concat,
+        ScalarFunction::DateTrunc,
+        ScalarFunction::Extract,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScalarFunction::Abs => "ABS",
+            ScalarFunction::Ceil => "CEIL",
+            ScalarFunction::Floor => "FLOOR",
+            ScalarFunction::Round => "ROUND",
+            ScalarFunction::Sqrt => "SQRT",
+            ScalarFunction::Ln => "LN",
+            ScalarFunction::Exp => "EXP",
+            ScalarFunction::Upper => "UPPER",
+            ScalarFunction::Lower => "LOWER",
+            ScalarFunction::Trim => "TRIM",
+            ScalarFunction::Ltrim => "LTRIM",
+            ScalarFunction::Rtrim => "RTRIM",
+            ScalarFunction::Length => "LENGTH",
+            ScalarFunction::Substring => "SUBSTRING",
+            ScalarFunction::Concat => "CONCAT",
+            ScalarFunction::DateTrunc => "DATE_TRUNC",
+            ScalarFunction::Extract => "EXTRACT",
+        }
+    }
+
+    /// Look up a function by name, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_uppercase();
+        match name.as_str() {
+            "SUBSTR" => Some(ScalarFunction::Substring),
+            "DATE_PART" => Some(ScalarFunction::Extract),
+            _ => Self::ALL.iter().cloned().find(|f| f.name() == name),
+        }
+    }
+
+    /// Get the data type to use for this function in a logical plan, where the types of the
+    /// arguments are not known. DATE_TRUNC preserves the type of its argument when executed.
+    pub fn logical_return_type(self) -> DataType {
+        match self {
+            ScalarFunction::DateTrunc => DataType::Timestamp(TimeUnit::Millisecond, None),
+            ScalarFunction::Length | ScalarFunction::Extract => DataType::Int64,
+            ScalarFunction::Upper
+            | ScalarFunction::Lower
+            | ScalarFunction::Trim
+            | ScalarFunction::Ltrim
+            | ScalarFunction::Rtrim
+            | ScalarFunction::Substring
+            | ScalarFunction::Concat => DataType::Utf8,
+            _ => DataType::Float64,
+        }
+    }
+
+    /// Get the data type of the result of this function, given the types of its arguments
+    pub fn return_type(self, arg_types: &[DataType]) -> Result<DataType> {
+        let (min_args, max_args) = match self {
+            ScalarFunction::Round => (1, Some(2)),
+            ScalarFunction::Substring => (2, Some(3)),
+            ScalarFunction::Concat => (1, None),
+            ScalarFunction::DateTrunc | ScalarFunction::Extract => (2, Some(2)),
+            _ => (1, Some(1)),
+        };
+        if arg_types.len() < min_args || max_args.map_or(false, |n| arg_types.len() > n) {
+            return Err(ballista_error(&format!(
+                "Function {} does not accept {} arguments",
+                self.name(),
+                arg_types.len()
+            )));
+        }
+        match self {
+            ScalarFunction::Abs
+            | ScalarFunction::Ceil
+            | ScalarFunction::Floor
+            | ScalarFunction::Round
+            | ScalarFunction::Sqrt
+            | ScalarFunction::Ln
+            | ScalarFunction::Exp => Ok(DataType::Float64),
+            ScalarFunction::Upper
+            | ScalarFunction::Lower
+            | ScalarFunction::Trim
+            | ScalarFunction::Ltrim
+            | ScalarFunction::Rtrim
+            | ScalarFunction::Substring
+            | ScalarFunction::Concat => Ok(DataType::Utf8),
+            ScalarFunction::Length | ScalarFunction::Extract => Ok(DataType::Int64),
+            ScalarFunction::DateTrunc => match &arg_types[1] {
+                t @ DataType::Date32(_) | t @ DataType::Date64(_) | t @ DataType::Timestamp(..) => {
+                    Ok(t.clone())
+                }
+                other => Err(ballista_error(&format!(
+                    "DATE_TRUNC does not support {:?}",
+                    other
+                ))),
+            },
+        }
+    }
+}
+
+/// Physical expression that invokes a built-in scalar function
+#[derive(Debug)]
+pub struct ScalarFunctionExpr {
+    fun: ScalarFunction,
+    args: Vec<Arc<dyn Expression>>,
+}
+
+impl ScalarFunctionExpr {
+    pub fn new(fun: ScalarFunction, args: Vec<Arc<dyn Expression>>) -> Self {
+        Self { fun, args }
+    }
+}
+
+impl Expression for ScalarFunctionExpr {
+    fn name(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(|e| e.name()).collect();
+        format!("{}({})", self.fun.name(), args.join(", "))
+    }
+
+    fn data_type(&self, input_schema: &Schema) -> Result<DataType> {
+        let arg_types = self
+            .args
+            .iter()
+            .map(|e| e.data_type(input_schema))
+            .collect::<Result<Vec<_>>>()?;
+        self.fun.return_type(&arg_types)
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let args = self
+            .args
+            .iter()
+            .map(|e| e.evaluate(input)?.to_arrow())
+            .collect::<Result<Vec<_>>>()?;
+        let result = match self.fun {
+            ScalarFunction::Abs => math(&args[0], f64::abs),
+            ScalarFunction::Ceil => math(&args[0], f64::ceil),
+            ScalarFunction::Floor => math(&args[0], f64::floor),
+            ScalarFunction::Sqrt => math(&args[0], f64::sqrt),
+            ScalarFunction::Ln => math(&args[0], f64::ln),
+            ScalarFunction::Exp => math(&args[0], f64::exp),
+            ScalarFunction::Round => round(&args),
+            ScalarFunction::Upper => strings(&args[0], |s| s.to_uppercase()),
+            ScalarFunction::Lower => strings(&args[0], |s| s.to_lowercase()),
+            ScalarFunction::Trim => strings(&args[0], |s| s.trim().to_owned()),
+            ScalarFunction::Ltrim => strings(&args[0], |s| s.trim_start().to_owned()),
+            ScalarFunction::Rtrim => strings(&args[0], |s| s.trim_end().to_owned()),
+            ScalarFunction::Length => length(&args[0]),
+            ScalarFunction::Substring => substring(&args),
+            ScalarFunction::Concat => concat(&args),
+            ScalarFunction::DateTrunc => date_trunc(&args[0], &args[1]),
+            ScalarFunction::Extract => extract(&args[0], &args[1]),
+        }?;
+        Ok(ColumnarValue::Columnar(result))
+    }
+}
+
+/// Create a scalar function expression
+pub fn scalar_function(fun: ScalarFunction, args: Vec<Arc<dyn Expression>>) -> Arc<dyn Expression> {
+    Arc::new(ScalarFunctionExpr::new(fun, args))
+}
+
+/// Apply a unary math function to a numeric array, producing doubles
+fn math(array: &ArrayRef, f: fn(f64) -> f64) -> Result<ArrayRef> {
+    let array = compute::cast(array, &DataType::Float64)?;
+    let array = cast_array!(array, Float64Array)?;
+    let values: Vec<Option<f64>> = (0..array.len())
+        .map(|i| {
+            if array.is_valid(i) {
+                Some(f(array.value(i)))
+            } else {
+                None
+            }
+        })
+        .collect();
+    Ok(Arc::new(array::Float64Array::from(values)))
+}
+
+/// ROUND(x) or ROUND(x, digits)
+fn round(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let value = compute::cast(&args[0], &DataType::Float64)?;
+    let value = cast_array!(value, Float64Array)?;
+    let digits = match args.get(1) {
+        Some(digits) => Some(compute::cast(digits, &DataType::Int64)?),
+        None => None,
+    };
+    let digits = match &digits {
+        Some(digits) => Some(cast_array!(digits, Int64Array)?),
+        None => None,
+    };
+    let values: Vec<Option<f64>> = (0..value.len())
+        .map(|i| {
+            if value.is_null(i) {
+                return None;
+            }
+            match digits {
+                Some(digits) if digits.is_null(i) => None,
+                Some(digits) => {
+                    let factor = 10f64.powi(digits.value(i) as i32);
+                    Some((value.value(i) * factor).round() / factor)
+                }
+                None => Some(value.value(i).round()),
+            }
+        })
+        .collect();
+    Ok(Arc::new(array::Float64Array::from(values)))
+}
+
+/// Apply a function to each string in an array
+fn strings(array: &ArrayRef, f: impl Fn(&str) -> String) -> Result<ArrayRef> {
+    let array = cast_array!(array, StringArray)?;
+    let mut builder = StringBuilder::new(array.len());
+    for i in 0..array.len() {
+        if array.is_valid(i) {
+            builder.append_value(&f(array.value(i)))?;
+        } else {
+            builder.append_null()?;
+        }
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// Number of characters in each string
+fn length(array: &ArrayRef) -> Result<ArrayRef> {
+    let array = cast_array!(array, StringArray)?;
+    let values: Vec<Option<i64>> = (0..array.len())
+        .map(|i| {
+            if array.is_valid(i) {
+                Some(array.value(i).chars().count() as i64)
+            } else {
+                None
+            }
+        })
+        .collect();
+    Ok(Arc::new(array::Int64Array::from(values)))
+}
+
+/// SUBSTRING(s, start) or SUBSTRING(s, start, length), where `start` is one-based
+fn substring(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let strings = &args[0];
+    let strings = cast_array!(strings, StringArray)?;
+    let start = compute::cast(&args[1], &DataType::Int64)?;
+    let start = cast_array!(start, Int64Array)?;
+    let count = match args.get(2) {
+        Some(count) => Some(compute::cast(count, &DataType::Int64)?),
+        None => None,
+    };
+    let count = match &count {
+        Some(count) => Some(cast_array!(count, Int64Array)?),
+        None => None,
+    };
+    let mut builder = StringBuilder::new(strings.len());
+    for i in 0..strings.len() {
+        let count = match count {
+            Some(count) if count.is_null(i) => {
+                builder.append_null()?;
+                continue;
+            }
+            Some(count) if count.value(i) < 0 => {
+                return Err(ballista_error("Negative substring length not allowed"))
+            }
+            Some(count) => Some(count.value(i)),
+            None => None,
+        };
+        if strings.is_null(i) || start.is_null(i) {
+            builder.append_null()?;
+            continue;
+        }
+        // positions before the start of the string count towards the length
+        let begin = start.value(i) - 1;
+        let end = count.map(|n| begin + n);
+        let s: String = strings
+            .value(i)
+            .chars()
+            .enumerate()
+            .filter(|(j, _)| {
+                let j = *j as i64;
+                j >= begin && end.map_or(true, |end| j < end)
+            })
+            .map(|(_, c)| c)
+            .collect();
+        builder.append_value(&s)?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// Concatenate the string representations of the arguments, ignoring nulls
+fn concat(args: &[ArrayRef]) -> Result<ArrayRef> {
+    let args = args
+        .iter()
+        .map(|a| compute::cast(a, &DataType::Utf8))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let args = args
+        .iter()
+        .map(|a| cast_array!(a, StringArray))
+        .collect::<Result<Vec<_>>>()?;
+    let num_rows = args[0].len();
+    let mut builder = StringBuilder::new(num_rows);
+    for i in 0..num_rows {
+        let mut s = String::new();
+        for arg in &args {
+            if arg.is_valid(i) {
+                s.push_str(arg.value(i));
+            }
+        }
+        builder.append_value(&s)?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// Read a date or timestamp array as milliseconds since the UNIX epoch
+pub(crate) fn to_millis(array: &ArrayRef) -> Result<Vec<Option<i64>>> {
+    macro_rules! read {
+        ($ARRAY_TYPE:ident, $TO_MILLIS:expr) => {{
+            let array = cast_array!(array, $ARRAY_TYPE)?;
+            Ok((0..array.len())
+                .map(|i| {
+                    if array.is_valid(i) {
+                        Some($TO_MILLIS(array.value(i) as i64))
+                    } else {
+                        None
+                    }
+                })
+                .collect())
+        }};
+    }
+    match array.data_type() {
+        DataType::Date32(DateUnit::Day) => read!(Date32Array, |v: i64| v * MILLIS_PER_DAY),
+        DataType::Date64(DateUnit::Millisecond) => read!(Date64Array, |v: i64| v),
+        DataType::Timestamp(TimeUnit::Second, _) => {
+            read!(TimestampSecondArray, |v: i64| v * MILLIS_PER_SECOND)
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            read!(TimestampMillisecondArray, |v: i64| v)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            read!(TimestampMicrosecondArray, |v: i64| v.div_euclid(1_000))
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            read!(TimestampNanosecondArray, |v: i64| v.div_euclid(1_000_000))
+        }
+        other => Err(ballista_error(&format!(
+            "Expected a date or timestamp but found {:?}",
+            other
+        ))),
+    }
+}
+
+/// Create a date or timestamp array from milliseconds since the UNIX epoch
+pub(crate) fn from_millis(values: Vec<Option<i64>>, data_type: &DataType) -> Result<ArrayRef> {
+    let convert = |f: &dyn Fn(i64) -> i64| values.iter().map(|v| v.map(f)).collect::<Vec<_>>();
+    match data_type {
+        DataType::Date32(DateUnit::Day) => Ok(Arc::new(array::Date32Array::from(
+            values
+                .iter()
+                .map(|v| v.map(|v| v.div_euclid(MILLIS_PER_DAY) as i32))
+                .collect::<Vec<_>>(),
+        ))),
+        DataType::Date64(DateUnit::Millisecond) => Ok(Arc::new(array::Date64Array::from(values))),
+        DataType::Timestamp(TimeUnit::Second, tz) => {
+            Ok(Arc::new(array::TimestampSecondArray::from_opt_vec(
+                convert(&|v| v.div_euclid(MILLIS_PER_SECOND)),
+                tz.clone(),
+            )))
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, tz) => Ok(Arc::new(
+            array::TimestampMillisecondArray::from_opt_vec(values, tz.clone()),
+        )),
+        DataType::Timestamp(TimeUnit::Microsecond, tz) => Ok(Arc::new(
+            array::TimestampMicrosecondArray::from_opt_vec(convert(&|v| v * 1_000), tz.clone()),
+        )),
+        DataType::Timestamp(TimeUnit::Nanosecond, tz) => Ok(Arc::new(
+            array::TimestampNanosecondArray::from_opt_vec(convert(&|v| v * 1_000_000), tz.clone()),
+        )),
+        other => Err(ballista_error(&format!(
+            "Expected a date or timestamp but found {:?}",
+            other
+        ))),
+    }
+}
+
+/// Convert days since the UNIX epoch to a (year, month, day) civil date
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Convert a (year, month, day) civil date to days since the UNIX epoch
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Read the string value of a granularity or date part argument at the given row
+fn string_at(array: &ArrayRef, i: usize) -> Result<Option<String>> {
+    let array = cast_array!(array, StringArray)?;
+    if array.is_valid(i) {
+        Ok(Some(array.value(i).to_lowercase()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// DATE_TRUNC(granularity, t) truncates a date or timestamp to the given granularity
+fn date_trunc(granularity: &ArrayRef, array: &ArrayRef) -> Result<ArrayRef> {
+    let millis = to_millis(array)?;
+    let mut values = Vec::with_capacity(millis.len());
+    for (i, value) in millis.iter().enumerate() {
+        let (value, granularity) = match (value, string_at(granularity, i)?) {
+            (Some(value), Some(granularity)) => (*value, granularity),
+            _ => {
+                values.push(None);
+                continue;
+            }
+        };
+        let days = value.div_euclid(MILLIS_PER_DAY);
+        let (year, month, _) = civil_from_days(days);
+        let truncated = match granularity.as_str() {
+            "year" => days_from_civil(year, 1, 1) * MILLIS_PER_DAY,
+            "quarter" => days_from_civil(year, (month - 1) / 3 * 3 + 1, 1) * MILLIS_PER_DAY,
+            "month" => days_from_civil(year, month, 1) * MILLIS_PER_DAY,
+            // weeks start on Monday, and the UNIX epoch was a Thursday
+            "week" => (days - (days + 3).rem_euclid(7)) * MILLIS_PER_DAY,
+            "day" => days * MILLIS_PER_DAY,
+            "hour" => value - value.rem_euclid(MILLIS_PER_HOUR),
+            "minute" => value - value.rem_euclid(MILLIS_PER_MINUTE),
+            "second" => value - value.rem_euclid(MILLIS_PER_SECOND),
+            other => {
+                return Err(ballista_error(&format!(
+                    "Unsupported DATE_TRUNC granularity '{}'",
+                    other
+                )))
+            }
+        };
+        values.push(Some(truncated));
+    }
+    from_millis(values, array.data_type())
+}
+
+/// EXTRACT(part, t) extracts a date part from a date or timestamp
+fn extract(part: &ArrayRef, array: &ArrayRef) -> Result<ArrayRef> {
+    let millis = to_millis(array)?;
+    let mut values = Vec::with_capacity(millis.len());
+    for (i, value) in millis.iter().enumerate() {
+        let (value, part) = match (value, string_at(part, i)?) {
+            (Some(value), Some(part)) => (*value, part),
+            _ => {
+                values.push(None);
+                continue;
+            }
+        };
+        let days = value.div_euclid(MILLIS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        let extracted = match part.as_str() {
+            "year" => year,
+            "quarter" => (month - 1) / 3 + 1,
+            "month" => month,
+            "day" => day,
+            // day of the week from Sunday (0) to Saturday (6)
+            "dow" => (days + 4).rem_euclid(7),
+            "doy" => days - days_from_civil(year, 1, 1) + 1,
+            "hour" => value.rem_euclid(MILLIS_PER_DAY) / MILLIS_PER_HOUR,
+            "minute" => value.rem_euclid(MILLIS_PER_HOUR) / MILLIS_PER_MINUTE,
+            "second" => value.rem_euclid(MILLIS_PER_MINUTE) / MILLIS_PER_SECOND,
+            other => {
+                return Err(ballista_error(&format!(
+                    "Unsupported EXTRACT date part '{}'",
+                    other
+                )))
+            }
+        };
+        values.push(Some(extracted));
+    }
+    Ok(Arc::new(array::Int64Array::from(values)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_dates() {
+        for days in &[-719_468, -1, 0, 59, 10_957, 18_321, 2_932_896] {
+            let (year, month, day) = civil_from_days(*days);
+            assert_eq!(*days, days_from_civil(year, month, day));
+        }
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2020, 2, 29), civil_from_days(18_321));
+    }
+
+    #[test]
+    fn string_functions() -> Result<()> {
+        let input: ArrayRef = Arc::new(array::StringArray::from(vec!["  Hello ", "world"]));
+        let start: ArrayRef = Arc::new(array::Int64Array::from(vec![3, 0]));
+        let count: ArrayRef = Arc::new(array::Int64Array::from(vec![2, 3]));
+
+        let result = substring(&[input.clone(), start, count])?;
+        let result = cast_array!(result, StringArray)?;
+        assert_eq!("He", result.value(0));
+        assert_eq!("wo", result.value(1));
+
+        let result = strings(&input, |s| s.trim().to_uppercase())?;
+        let result = cast_array!(result, StringArray)?;
+        assert_eq!("HELLO", result.value(0));
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{ColumnarBatch, ColumnarValue, Expression};
+use crate::execution::udf::{ScalarUdfFn, ScalarUdfSignature};
+
+/// Physical expression that invokes a user-defined scalar function. Arguments are cast to the
+/// types declared in the signature before the function is invoked.
+pub struct ScalarUdfExpr {
+    signature: ScalarUdfSignature,
+    fun: ScalarUdfFn,
+    args: Vec<Arc<dyn Expression>>,
+}
+
+impl ScalarUdfExpr {
+    pub fn new(
+        signature: ScalarUdfSignature,
+        fun: ScalarUdfFn,
+        args: Vec<Arc<dyn Expression>>,
+    ) -> Self {
+        Self {
+            signature,
+            fun,
+            args,
+        }
+    }
+}
+
+impl fmt::Debug for ScalarUdfExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScalarUdfExpr")
+            .field("signature", &self.signature)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl Expression for ScalarUdfExpr {
+    fn name(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(|e| e.name()).collect();
+        format!("{}({})", self.signature.name, args.join(", "))
+    }
+
+    fn data_type(&self, _input_schema: &Schema) -> Result<DataType> {
+        Ok(self.signature.return_type.clone())
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate(&self, input: &ColumnarBatch) -> Result<ColumnarValue> {
+        let args = self
+            .args
+            .iter()
+            .zip(&self.signature.arg_types)
+            .map(|(e, data_type)| {
+                let array = e.evaluate(input)?.to_arrow()?;
+                if array.data_type() == data_type {
+                    Ok(array)
+                } else {
+                    Ok(compute::cast(&array, data_type)?)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let result = (self.fun)(&args)?;
+        if result.len() != input.num_rows() || result.data_type() != &self.signature.return_type {
+            return Err(ballista_error(&format!(
+                "UDF {} returned {} values of type {:?} but expected {} values of type {:?}",
+                self.signature.name,
+                result.len(),
+                result.data_type(),
+                input.num_rows(),
+                self.signature.return_type
+            )));
+        }
+        Ok(ColumnarValue::Columnar(result))
+    }
+}
+
+/// Create an expression that invokes a user-defined scalar function
+pub fn scalar_udf(
+    signature: ScalarUdfSignature,
+    fun: ScalarUdfFn,
+    args: Vec<Arc<dyn Expression>>,
+) -> Result<Arc<dyn Expression>> {
+    if args.len() != signature.arg_types.len() {
+        return Err(ballista_error(&format!(
+            "UDF {} expects {} arguments but was given {}",
+            signature.name,
+            signature.arg_types.len(),
+            args.len()
+        )));
+    }
+    Ok(Arc::new(ScalarUdfExpr::new(signature, fun, args)))
+}
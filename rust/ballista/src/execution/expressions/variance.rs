@@ -0,0 +1,303 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Variance and standard deviation aggregate expressions. Values are accumulated with Welford's
+//! online algorithm, and the intermediate states of partial aggregations are combined with the
+//! parallel algorithm of Chan et al. so that results are numerically stable.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::cast_array;
+use crate::datafusion::logicalplan::ScalarValue;
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{
+    Accumulator, AggregateExpr, AggregateMode, ColumnarBatch, ColumnarValue, Expression,
+};
+
+/// The statistic computed from the accumulated variance state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarianceKind {
+    /// Sample variance
+    Variance,
+    /// Population variance
+    VariancePop,
+    /// Sample standard deviation
+    Stddev,
+    /// Population standard deviation
+    StddevPop,
+}
+
+impl VarianceKind {
+    fn name(self) -> &'static str {
+        match self {
+            VarianceKind::Variance => "VAR",
+            VarianceKind::VariancePop => "VAR_POP",
+            VarianceKind::Stddev => "STDDEV",
+            VarianceKind::StddevPop => "STDDEV_POP",
+        }
+    }
+
+    fn is_population(self) -> bool {
+        self == VarianceKind::VariancePop || self == VarianceKind::StddevPop
+    }
+
+    fn is_stddev(self) -> bool {
+        self == VarianceKind::Stddev || self == VarianceKind::StddevPop
+    }
+}
+
+#[derive(Debug)]
+pub struct Variance {
+    kind: VarianceKind,
+    /// The expression being aggregated, or the count, mean, and m2 columns when merging the
+    /// intermediate state of a partial aggregation
+    args: Vec<Arc<dyn Expression>>,
+}
+
+impl Variance {
+    pub fn new(kind: VarianceKind, args: Vec<Arc<dyn Expression>>) -> Self {
+        Self { kind, args }
+    }
+}
+
+impl AggregateExpr for Variance {
+    fn name(&self) -> String {
+        format!("{}({:?})", self.kind.name(), self.args[0])
+    }
+
+    fn data_type(&self, input_schema: &Schema) -> Result<DataType> {
+        match self.args[0].data_type(input_schema)? {
+            DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float32
+            | DataType::Float64 => Ok(DataType::Float64),
+            other => Err(ballista_error(&format!(
+                "{} does not support {:?}",
+                self.kind.name(),
+                other
+            ))),
+        }
+    }
+
+    fn nullable(&self, _input_schema: &Schema) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn evaluate_input(&self, batch: &ColumnarBatch) -> Result<ColumnarValue> {
+        self.args[0].evaluate(batch)
+    }
+
+    fn create_accumulator(&self, _mode: &AggregateMode) -> Box<dyn Accumulator> {
+        Box::new(VarianceAccumulator {
+            kind: self.kind,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        })
+    }
+
+    fn state_fields(&self, _input_schema: &Schema) -> Result<Vec<Field>> {
+        Ok(vec![
+            Field::new(&format!("{}[count]", self.name()), DataType::UInt64, true),
+            Field::new(&format!("{}[mean]", self.name()), DataType::Float64, true),
+            Field::new(&format!("{}[m2]", self.name()), DataType::Float64, true),
+        ])
+    }
+
+    fn evaluate_state(&self, batch: &ColumnarBatch) -> Result<Vec<ColumnarValue>> {
+        if self.args.len() != 3 {
+            return Err(ballista_error(&format!(
+                "{} requires count, mean, and m2 state columns",
+                self.kind.name()
+            )));
+        }
+        self.args.iter().map(|e| e.evaluate(batch)).collect()
+    }
+}
+
+struct VarianceAccumulator {
+    kind: VarianceKind,
+    count: u64,
+    mean: f64,
+    /// Sum of squared differences from the mean
+    m2: f64,
+}
+
+impl VarianceAccumulator {
+    fn update(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+}
+
+impl Accumulator for VarianceAccumulator {
+    fn accumulate(&mut self, value: &ColumnarValue) -> Result<()> {
+        match value {
+            ColumnarValue::Columnar(array) => {
+                let array = compute::cast(array, &DataType::Float64)?;
+                let array = cast_array!(array, Float64Array)?;
+                for i in 0..array.len() {
+                    if array.is_valid(i) {
+                        self.update(array.value(i));
+                    }
+                }
+            }
+            ColumnarValue::Scalar(Some(value), _) => self.update(scalar_to_f64(value)?),
+            ColumnarValue::Scalar(None, _) => {}
+        }
+        Ok(())
+    }
+
+    fn get_value(&self) -> Result<Option<ScalarValue>> {
+        let variance = if self.kind.is_population() {
+            if self.count == 0 {
+                return Ok(None);
+            }
+            self.m2 / self.count as f64
+        } else {
+            if self.count < 2 {
+                return Ok(None);
+            }
+            self.m2 / (self.count - 1) as f64
+        };
+        if self.kind.is_stddev() {
+            Ok(Some(ScalarValue::Float64(variance.sqrt())))
+        } else {
+            Ok(Some(ScalarValue::Float64(variance)))
+        }
+    }
+
+    fn state(&self) -> Result<Vec<Option<ScalarValue>>> {
+        Ok(vec![
+            Some(ScalarValue::UInt64(self.count)),
+            Some(ScalarValue::Float64(self.mean)),
+            Some(ScalarValue::Float64(self.m2)),
+        ])
+    }
+
+    fn merge(&mut self, state: &[ColumnarValue]) -> Result<()> {
+        match (&state[0], &state[1], &state[2]) {
+            (
+                ColumnarValue::Scalar(Some(ScalarValue::UInt64(count)), _),
+                ColumnarValue::Scalar(Some(ScalarValue::Float64(mean)), _),
+                ColumnarValue::Scalar(Some(ScalarValue::Float64(m2)), _),
+            ) => {
+                if *count == 0 {
+                    return Ok(());
+                }
+                let total = self.count + count;
+                let delta = mean - self.mean;
+                self.mean += delta * *count as f64 / total as f64;
+                self.m2 += m2 + delta * delta * self.count as f64 * *count as f64 / total as f64;
+                self.count = total;
+                Ok(())
+            }
+            other => Err(ballista_error(&format!(
+                "Unexpected intermediate state for {}: {:?}",
+                self.kind.name(),
+                other
+            ))),
+        }
+    }
+}
+
+/// Convert a numeric scalar value to a double
+pub(crate) fn scalar_to_f64(value: &ScalarValue) -> Result<f64> {
+    match value {
+        ScalarValue::Int8(n) => Ok(*n as f64),
+        ScalarValue::Int16(n) => Ok(*n as f64),
+        ScalarValue::Int32(n) => Ok(*n as f64),
+        ScalarValue::Int64(n) => Ok(*n as f64),
+        ScalarValue::UInt8(n) => Ok(*n as f64),
+        ScalarValue::UInt16(n) => Ok(*n as f64),
+        ScalarValue::UInt32(n) => Ok(*n as f64),
+        ScalarValue::UInt64(n) => Ok(*n as f64),
+        ScalarValue::Float32(n) => Ok(*n as f64),
+        ScalarValue::Float64(n) => Ok(*n),
+        other => Err(ballista_error(&format!(
+            "Expected a numeric value but found {:?}",
+            other
+        ))),
+    }
+}
+
+/// Create a sample variance expression
+pub fn variance(args: Vec<Arc<dyn Expression>>) -> Arc<dyn AggregateExpr> {
+    Arc::new(Variance::new(VarianceKind::Variance, args))
+}
+
+/// Create a population variance expression
+pub fn variance_pop(args: Vec<Arc<dyn Expression>>) -> Arc<dyn AggregateExpr> {
+    Arc::new(Variance::new(VarianceKind::VariancePop, args))
+}
+
+/// Create a sample standard deviation expression
+pub fn stddev(args: Vec<Arc<dyn Expression>>) -> Arc<dyn AggregateExpr> {
+    Arc::new(Variance::new(VarianceKind::Stddev, args))
+}
+
+/// Create a population standard deviation expression
+pub fn stddev_pop(args: Vec<Arc<dyn Expression>>) -> Arc<dyn AggregateExpr> {
+    Arc::new(Variance::new(VarianceKind::StddevPop, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_partial_state() -> Result<()> {
+        let values: Vec<f64> = (0..100).map(|i| (i * i) as f64 / 7.0).collect();
+        let accumulator = |values: &[f64]| -> Result<VarianceAccumulator> {
+            let mut acc = VarianceAccumulator {
+                kind: VarianceKind::Variance,
+                count: 0,
+                mean: 0.0,
+                m2: 0.0,
+            };
+            for v in values {
+                acc.accumulate(&ColumnarValue::Scalar(Some(ScalarValue::Float64(*v)), 1))?;
+            }
+            Ok(acc)
+        };
+
+        let single = accumulator(&values)?;
+        let mut merged = accumulator(&values[..30])?;
+        let state: Vec<ColumnarValue> = accumulator(&values[30..])?
+            .state()?
+            .into_iter()
+            .map(|v| ColumnarValue::Scalar(v, 1))
+            .collect();
+        merged.merge(&state)?;
+
+        match (single.get_value()?, merged.get_value()?) {
+            (Some(ScalarValue::Float64(a)), Some(ScalarValue::Float64(b))) => {
+                assert!((a - b).abs() < 1e-6 * a.abs())
+            }
+            other => panic!("unexpected values {:?}", other),
+        }
+        Ok(())
+    }
+}
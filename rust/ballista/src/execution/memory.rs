@@ -0,0 +1,243 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Memory accounting for the operators of the tasks running on an executor.
+//!
+//! Operators that buffer data, such as sorts, hash aggregates, and hash joins, reserve memory
+//! for it before buffering it. Reservations are drawn from a budget shared by all tasks on the
+//! executor, and are also bounded by the memory limit of the task when its query sets one.
+//! Operators that can spill write the data they have buffered to disk when their reservation
+//! cannot grow, while other operators fail with `BallistaError::ResourcesExhausted` rather than
+//! exhausting the memory of the executor.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::error::{BallistaError, Result};
+
+use log::debug;
+
+/// Memory reserved so far, and the maximum that may be reserved
+#[derive(Debug)]
+struct MemoryPool {
+    limit: usize,
+    used: Mutex<usize>,
+}
+
+impl MemoryPool {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+        }
+    }
+}
+
+/// Executor-wide budget of memory that operators reserve from
+#[derive(Debug, Clone)]
+pub struct MemoryManager {
+    pool: Arc<MemoryPool>,
+    /// Directory that operators spill to
+    spill_dir: PathBuf,
+}
+
+impl MemoryManager {
+    pub fn new(limit: usize, spill_dir: PathBuf) -> Self {
+        Self {
+            pool: Arc::new(MemoryPool::new(limit)),
+            spill_dir,
+        }
+    }
+
+    /// Memory for a task, which may reserve at most `limit` bytes when given
+    pub fn task_memory(&self, limit: Option<usize>) -> TaskMemory {
+        TaskMemory {
+            executor: self.pool.clone(),
+            task: Arc::new(MemoryPool::new(limit.unwrap_or(usize::MAX))),
+            spill_dir: self.spill_dir.clone(),
+        }
+    }
+
+    /// Bytes reserved by all tasks
+    pub fn used(&self) -> usize {
+        *self.pool.used.lock().expect("failed to lock mutex")
+    }
+
+    pub fn limit(&self) -> usize {
+        self.pool.limit
+    }
+}
+
+/// Memory that the operators of a task reserve from, bounded by both the memory left on the
+/// executor and the memory limit of the task
+#[derive(Debug, Clone)]
+pub struct TaskMemory {
+    executor: Arc<MemoryPool>,
+    task: Arc<MemoryPool>,
+    spill_dir: PathBuf,
+}
+
+impl TaskMemory {
+    /// Memory that is not shared with other tasks and that has no limit, for running operators
+    /// outside of an executor
+    pub fn unbounded(spill_dir: PathBuf) -> Self {
+        MemoryManager::new(usize::MAX, spill_dir).task_memory(None)
+    }
+
+    /// Create an empty reservation for an operator, named in logs and errors
+    pub fn reservation(&self, consumer: &str) -> MemoryReservation {
+        MemoryReservation {
+            memory: self.clone(),
+            consumer: consumer.to_owned(),
+            size: 0,
+        }
+    }
+
+    /// Bytes reserved by the operators of this task
+    pub fn used(&self) -> usize {
+        *self.task.used.lock().expect("failed to lock mutex")
+    }
+
+    /// Directory that operators of this task spill to
+    pub fn spill_dir(&self) -> &Path {
+        &self.spill_dir
+    }
+
+    fn try_reserve(&self, bytes: usize) -> bool {
+        let mut executor_used = self.executor.used.lock().expect("failed to lock mutex");
+        let mut task_used = self.task.used.lock().expect("failed to lock mutex");
+        let fits = |used: usize, limit: usize| used.saturating_add(bytes) <= limit;
+        if fits(*executor_used, self.executor.limit) && fits(*task_used, self.task.limit) {
+            *executor_used += bytes;
+            *task_used += bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn release(&self, bytes: usize) {
+        let mut executor_used = self.executor.used.lock().expect("failed to lock mutex");
+        let mut task_used = self.task.used.lock().expect("failed to lock mutex");
+        *executor_used -= bytes;
+        *task_used -= bytes;
+    }
+}
+
+/// Memory reserved by an operator, which is released when the reservation is dropped
+pub struct MemoryReservation {
+    memory: TaskMemory,
+    consumer: String,
+    size: usize,
+}
+
+impl MemoryReservation {
+    /// Bytes currently reserved
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reserve `bytes` more, returning false without reserving anything when there is not
+    /// enough memory, in which case the operator is expected to spill
+    pub fn try_grow(&mut self, bytes: usize) -> bool {
+        if self.memory.try_reserve(bytes) {
+            self.size += bytes;
+            true
+        } else {
+            debug!(
+                "Failed to grow memory reservation consumer={} size={} additional={}",
+                self.consumer, self.size, bytes
+            );
+            false
+        }
+    }
+
+    /// Reserve `bytes` more, failing when there is not enough memory
+    pub fn grow(&mut self, bytes: usize) -> Result<()> {
+        if self.try_grow(bytes) {
+            Ok(())
+        } else {
+            Err(BallistaError::ResourcesExhausted(format!(
+                "{} could not reserve {} bytes in addition to the {} bytes it holds, with {} bytes \
+                reserved by its task",
+                self.consumer,
+                bytes,
+                self.size,
+                self.memory.used()
+            )))
+        }
+    }
+
+    /// Release up to `bytes` of the reservation
+    pub fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.size);
+        self.memory.release(bytes);
+        self.size -= bytes;
+    }
+
+    /// Release the whole reservation, returning the number of bytes released
+    pub fn free(&mut self) -> usize {
+        let size = self.size;
+        self.shrink(size);
+        size
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
+
+impl fmt::Debug for MemoryReservation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryReservation")
+            .field("consumer", &self.consumer)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservations_are_bounded_by_executor_and_task() {
+        let manager = MemoryManager::new(100, std::env::temp_dir());
+        let task1 = manager.task_memory(Some(60));
+        let task2 = manager.task_memory(None);
+
+        let mut sort = task1.reservation("SortExec");
+        assert!(sort.try_grow(50));
+        // the task limit is reached before the executor budget
+        assert!(!sort.try_grow(20));
+        assert_eq!(50, sort.size());
+
+        let mut join = task2.reservation("HashJoinExec");
+        assert!(join.try_grow(40));
+        // the executor budget is shared by both tasks
+        assert!(!join.try_grow(20));
+        assert!(join.grow(20).is_err());
+        assert_eq!(90, manager.used());
+
+        sort.shrink(30);
+        assert!(join.try_grow(20));
+        drop(join);
+        assert_eq!(20, manager.used());
+        assert_eq!(20, task1.used());
+        assert_eq!(0, task2.used());
+    }
+}
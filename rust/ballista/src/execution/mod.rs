@@ -15,5 +15,9 @@
 //! Query plan representation and execution logic.
 
 pub mod expressions;
+pub mod memory;
 pub mod operators;
 pub mod physical_plan;
+pub mod spill;
+pub mod statistics;
+pub mod udf;
@@ -0,0 +1,597 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Avro scan operator. An Avro object container file is a header followed by blocks of records
+//! that each end with the sync marker of the file, so files are divided into byte ranges that
+//! are read as separate partitions, where each range reads the blocks that start within it.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Date32Builder, Float32Builder, Float64Builder,
+    Int32Builder, Int64Builder, StringBuilder, TimestampMillisecondBuilder,
+};
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, SchemaRef, TimeUnit};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::operators::file_splits::{
+    create_splits, next_record_start, FileSplit, DEFAULT_SPLIT_SIZE,
+};
+use crate::execution::physical_plan::{
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext,
+    ExecutionPlan, Partitioning,
+};
+use crate::object_store::{self, ObjectStore};
+
+use async_trait::async_trait;
+use avro_rs::types::Value as AvroValue;
+use avro_rs::Schema as AvroSchema;
+use flate2::read::DeflateDecoder;
+
+/// Schema name of logical table scans that read Avro files, where the table name is the path
+/// of the files
+pub const AVRO_SCHEMA_NAME: &str = "avro";
+
+/// Extension of the files that are read from a directory
+pub const AVRO_FILE_EXTENSION: &str = ".avro";
+
+/// Bytes at the start of every Avro object container file
+const MAGIC: &[u8] = b"Obj\x01";
+
+/// Size of the sync marker that follows every block
+const SYNC_SIZE: usize = 16;
+
+/// Maximum size of an encoded long
+const MAX_LONG_SIZE: usize = 10;
+
+/// Number of bytes read when reading the header of a file, which is read again with a larger
+/// size when the header does not fit
+const HEADER_READ_SIZE: u64 = 16 * 1024;
+
+/// Execution plan for scanning Avro files
+pub struct AvroScanExec {
+    /// Path to a file or to a directory containing files with the same schema
+    pub(crate) path: String,
+    /// Byte ranges of the files, one per partition
+    pub(crate) splits: Vec<FileSplit>,
+    /// Schema of the files
+    schema: SchemaRef,
+    /// Optional projection for which columns to load
+    pub(crate) projection: Option<Vec<usize>>,
+    /// Schema after the projection has been applied
+    projected_schema: SchemaRef,
+    /// Batch size
+    pub(crate) batch_size: usize,
+}
+
+impl AvroScanExec {
+    /// Create a new execution plan for reading a set of Avro files
+    pub fn try_new(path: &str, projection: Option<Vec<usize>>, batch_size: usize) -> Result<Self> {
+        let splits = create_splits(path, AVRO_FILE_EXTENSION, DEFAULT_SPLIT_SIZE)?;
+        let schema = Arc::new(split_schema(&splits[0])?);
+        let projected_schema = match &projection {
+            None => schema.clone(),
+            Some(p) => Arc::new(Schema::new(
+                p.iter().map(|i| schema.field(*i).clone()).collect(),
+            )),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            splits,
+            schema,
+            projection,
+            projected_schema,
+            batch_size,
+        })
+    }
+
+    /// Read a different set of columns, given as indices into the file schema
+    pub fn with_projection(&self, projection: Vec<usize>) -> Self {
+        let projected_schema = Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        );
+        Self {
+            path: self.path.clone(),
+            splits: self.splits.clone(),
+            schema: self.schema.clone(),
+            projection: Some(projection),
+            projected_schema: Arc::new(projected_schema),
+            batch_size: self.batch_size,
+        }
+    }
+}
+
+/// Get the Arrow schema of an Avro data source from the header of its first file
+pub fn avro_table_schema(path: &str) -> Result<Schema> {
+    let splits = create_splits(path, AVRO_FILE_EXTENSION, DEFAULT_SPLIT_SIZE)?;
+    split_schema(&splits[0])
+}
+
+fn split_schema(split: &FileSplit) -> Result<Schema> {
+    let store = object_store::object_store(&split.filename)?;
+    let header = read_header(store.as_ref(), split)?;
+    to_arrow_schema(&header.schema)
+}
+
+#[async_trait]
+impl ExecutionPlan for AvroScanExec {
+    fn schema(&self) -> SchemaRef {
+        self.projected_schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.splits.len())
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let reader = AvroSplitReader::try_new(&self.splits[partition_index])?;
+        Ok(Arc::new(AvroBatchIter {
+            reader: Arc::new(Mutex::new(reader)),
+            schema: self.projected_schema.clone(),
+            batch_size: self.batch_size,
+            cancellation_token: ctx.cancellation_token(),
+        }))
+    }
+}
+
+struct AvroBatchIter {
+    reader: Arc<Mutex<AvroSplitReader>>,
+    /// Schema after the projection has been applied
+    schema: SchemaRef,
+    batch_size: usize,
+    /// Stop reading when the task is cancelled
+    cancellation_token: CancellationToken,
+}
+
+#[async_trait]
+impl ColumnarBatchIter for AvroBatchIter {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        self.cancellation_token.check()?;
+        let mut reader = self.reader.lock().expect("failed to lock mutex");
+        match reader.next_records(self.batch_size)? {
+            Some(records) => {
+                let batch = to_record_batch(&records, &self.schema)?;
+                Ok(Some(ColumnarBatch::from_arrow(&batch)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Compression codecs of blocks
+#[derive(Debug, Clone, PartialEq)]
+enum Codec {
+    Null,
+    Deflate,
+}
+
+/// The metadata at the start of an Avro object container file
+#[derive(Debug)]
+struct AvroHeader {
+    /// Schema that the records were written with
+    schema: AvroSchema,
+    codec: Codec,
+    /// Marker that follows every block
+    sync: Vec<u8>,
+    /// Size of the header in bytes
+    size: u64,
+}
+
+/// Reads the records of the blocks that start within a split
+struct AvroSplitReader {
+    store: Arc<dyn ObjectStore>,
+    split: FileSplit,
+    header: AvroHeader,
+    /// Position of the next block
+    pos: u64,
+    /// Records of the current block that have not been returned yet
+    records: VecDeque<AvroValue>,
+}
+
+impl AvroSplitReader {
+    fn try_new(split: &FileSplit) -> Result<Self> {
+        let store = object_store::object_store(&split.filename)?;
+        let header = read_header(store.as_ref(), split)?;
+        let pos = if split.start <= header.size {
+            header.size
+        } else {
+            next_record_start(store.as_ref(), split, split.start, &header.sync)?
+        };
+        Ok(Self {
+            store,
+            split: split.clone(),
+            header,
+            pos,
+            records: VecDeque::new(),
+        })
+    }
+
+    /// Read up to `max_records` records, returning `None` when the split has been read
+    fn next_records(&mut self, max_records: usize) -> Result<Option<Vec<AvroValue>>> {
+        while self.records.len() < max_records && self.read_block()? {}
+        if self.records.is_empty() {
+            return Ok(None);
+        }
+        let n = max_records.min(self.records.len());
+        Ok(Some(self.records.drain(..n).collect()))
+    }
+
+    /// Decode the next block of the split, returning false when there are no more blocks
+    fn read_block(&mut self) -> Result<bool> {
+        let filename = &self.split.filename;
+        if self.pos >= self.split.end || self.pos >= self.split.file_size {
+            return Ok(false);
+        }
+
+        // a block starts with the number of records and the size of the records in bytes
+        let prefix_size = (2 * MAX_LONG_SIZE).min((self.split.file_size - self.pos) as usize);
+        let prefix = self.store.read_range(filename, self.pos, prefix_size)?;
+        let mut i = 0;
+        let (count, size) = match (read_long(&prefix, &mut i), read_long(&prefix, &mut i)) {
+            (Some(count), Some(size)) if count >= 0 && size >= 0 => (count, size as usize),
+            _ => {
+                return Err(ballista_error(&format!(
+                    "Invalid Avro block at byte {} of {}",
+                    self.pos, filename
+                )))
+            }
+        };
+        let data_start = self.pos + i as u64;
+        let block = self
+            .store
+            .read_range(filename, data_start, size + SYNC_SIZE)?;
+        if block[size..] != self.header.sync[..] {
+            return Err(ballista_error(&format!(
+                "Invalid sync marker at byte {} of {}",
+                data_start + size as u64,
+                filename
+            )));
+        }
+        self.pos = data_start + block.len() as u64;
+
+        let data = match self.header.codec {
+            Codec::Null => block[..size].to_vec(),
+            Codec::Deflate => {
+                let mut data = vec![];
+                DeflateDecoder::new(&block[..size]).read_to_end(&mut data)?;
+                data
+            }
+        };
+        let mut reader = &data[..];
+        for _ in 0..count {
+            let record = avro_rs::from_avro_datum(&self.header.schema, &mut reader, None)
+                .map_err(|e| avro_error(filename, e))?;
+            self.records.push_back(record);
+        }
+        Ok(true)
+    }
+}
+
+/// Read the header of the file of a split, reading more of the file until the header fits
+fn read_header(store: &dyn ObjectStore, split: &FileSplit) -> Result<AvroHeader> {
+    let mut size = HEADER_READ_SIZE.min(split.file_size);
+    loop {
+        let bytes = store.read_range(&split.filename, 0, size as usize)?;
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(ballista_error(&format!(
+                "{} is not an Avro file",
+                split.filename
+            )));
+        }
+        if let Some((metadata, sync, header_size)) = parse_header(&bytes) {
+            let schema = metadata
+                .get("avro.schema")
+                .ok_or_else(|| ballista_error(&format!("{} has no schema", split.filename)))?;
+            let schema = AvroSchema::parse_str(&String::from_utf8_lossy(schema))
+                .map_err(|e| avro_error(&split.filename, e))?;
+            let codec = match metadata.get("avro.codec").map(|c| c.as_slice()) {
+                None | Some(b"null") => Codec::Null,
+                Some(b"deflate") => Codec::Deflate,
+                Some(other) => {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Avro codec {}",
+                        String::from_utf8_lossy(other)
+                    )))
+                }
+            };
+            return Ok(AvroHeader {
+                schema,
+                codec,
+                sync,
+                size: header_size as u64,
+            });
+        }
+        if size >= split.file_size {
+            return Err(ballista_error(&format!(
+                "Invalid Avro header in {}",
+                split.filename
+            )));
+        }
+        size = (size * 4).min(split.file_size);
+    }
+}
+
+/// Parse the metadata and sync marker of a header, returning `None` when the bytes end before
+/// the header does
+fn parse_header(bytes: &[u8]) -> Option<(HashMap<String, Vec<u8>>, Vec<u8>, usize)> {
+    let mut pos = MAGIC.len();
+    let mut metadata = HashMap::new();
+    loop {
+        let count = read_long(bytes, &mut pos)?;
+        if count == 0 {
+            break;
+        }
+        if count < 0 {
+            // a negative count is followed by the size of the block in bytes
+            read_long(bytes, &mut pos)?;
+        }
+        for _ in 0..count.abs() {
+            let key = read_bytes(bytes, &mut pos)?;
+            let value = read_bytes(bytes, &mut pos)?;
+            metadata.insert(String::from_utf8_lossy(key).into_owned(), value.to_vec());
+        }
+    }
+    let sync = bytes.get(pos..pos + SYNC_SIZE)?.to_vec();
+    Some((metadata, sync, pos + SYNC_SIZE))
+}
+
+/// Read a zig-zag encoded variable-length long
+fn read_long(bytes: &[u8], pos: &mut usize) -> Option<i64> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let b = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Some((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+/// Read bytes that are prefixed by their length
+fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = read_long(bytes, pos)? as usize;
+    let value = bytes.get(*pos..*pos + len)?;
+    *pos += len;
+    Some(value)
+}
+
+fn avro_error(filename: &str, e: impl Display) -> BallistaError {
+    ballista_error(&format!("Error reading Avro file {}: {}", filename, e))
+}
+
+/// Convert the schema of the records of a file to an Arrow schema
+fn to_arrow_schema(schema: &AvroSchema) -> Result<Schema> {
+    match schema {
+        AvroSchema::Record { fields, .. } => {
+            let fields = fields
+                .iter()
+                .map(|f| {
+                    let (data_type, nullable) = to_arrow_type(&f.schema)?;
+                    Ok(Field::new(&f.name, data_type, nullable))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Schema::new(fields))
+        }
+        other => Err(BallistaError::NotImplemented(format!(
+            "Avro files of {:?} rather than records",
+            other
+        ))),
+    }
+}
+
+/// Convert an Avro type to an Arrow type and whether it is nullable
+fn to_arrow_type(schema: &AvroSchema) -> Result<(DataType, bool)> {
+    let data_type = match schema {
+        AvroSchema::Union(union) => {
+            // unions of null and one other type are how Avro represents nullable values
+            let variants: Vec<&AvroSchema> = union
+                .variants()
+                .iter()
+                .filter(|s| **s != AvroSchema::Null)
+                .collect();
+            return match variants.as_slice() {
+                [variant] => Ok((to_arrow_type(variant)?.0, true)),
+                _ => Err(BallistaError::NotImplemented(format!(
+                    "Avro union {:?}",
+                    schema
+                ))),
+            };
+        }
+        AvroSchema::Boolean => DataType::Boolean,
+        AvroSchema::Int => DataType::Int32,
+        AvroSchema::Long => DataType::Int64,
+        AvroSchema::Float => DataType::Float32,
+        AvroSchema::Double => DataType::Float64,
+        AvroSchema::String | AvroSchema::Enum { .. } => DataType::Utf8,
+        AvroSchema::Bytes | AvroSchema::Fixed { .. } => DataType::Binary,
+        AvroSchema::Date => DataType::Date32(DateUnit::Day),
+        // timestamps are read with millisecond precision, which is the precision that plans
+        // can serialize
+        AvroSchema::TimestampMillis | AvroSchema::TimestampMicros => {
+            DataType::Timestamp(TimeUnit::Millisecond, None)
+        }
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Avro type {:?}",
+                other
+            )))
+        }
+    };
+    Ok((data_type, false))
+}
+
+/// Build an array from the values of a column, with nulls for missing values
+macro_rules! build_array {
+    ($BUILDER:ident, $VALUES:expr, $($PATTERN:pat => $VALUE:expr),+) => {{
+        let mut builder = $BUILDER::new($VALUES.len());
+        for value in $VALUES {
+            match value {
+                $(Some($PATTERN) => builder.append_value($VALUE)?,)+
+                _ => builder.append_null()?,
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+/// Convert records to a batch with the columns of the schema, which are looked up by name
+fn to_record_batch(records: &[AvroValue], schema: &SchemaRef) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let values: Vec<Option<&AvroValue>> = records
+                .iter()
+                .map(|record| field_value(record, field.name()))
+                .collect();
+            Ok(match field.data_type() {
+                DataType::Boolean => {
+                    build_array!(BooleanBuilder, values, AvroValue::Boolean(v) => *v)
+                }
+                DataType::Int32 => build_array!(Int32Builder, values, AvroValue::Int(v) => *v),
+                DataType::Int64 => build_array!(Int64Builder, values, AvroValue::Long(v) => *v),
+                DataType::Float32 => {
+                    build_array!(Float32Builder, values, AvroValue::Float(v) => *v)
+                }
+                DataType::Float64 => {
+                    build_array!(Float64Builder, values, AvroValue::Double(v) => *v)
+                }
+                DataType::Utf8 => build_array!(
+                    StringBuilder,
+                    values,
+                    AvroValue::String(v) => v,
+                    AvroValue::Enum(_, v) => v
+                ),
+                DataType::Binary => build_array!(
+                    BinaryBuilder,
+                    values,
+                    AvroValue::Bytes(v) => v,
+                    AvroValue::Fixed(_, v) => v
+                ),
+                DataType::Date32(_) => {
+                    build_array!(Date32Builder, values, AvroValue::Date(v) => *v)
+                }
+                DataType::Timestamp(_, _) => build_array!(
+                    TimestampMillisecondBuilder,
+                    values,
+                    AvroValue::TimestampMillis(v) => *v,
+                    AvroValue::TimestampMicros(v) => *v / 1000
+                ),
+                other => {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Reading Avro values as {:?}",
+                        other
+                    )))
+                }
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// Get the value of a field of a record, unwrapping values of nullable types
+fn field_value<'a>(record: &'a AvroValue, name: &str) -> Option<&'a AvroValue> {
+    let value = match record {
+        AvroValue::Record(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+        _ => None,
+    };
+    match value {
+        Some(AvroValue::Union(inner)) => Some(inner.as_ref()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use avro_rs::types::Record;
+    use avro_rs::Writer;
+    use std::fs;
+
+    #[test]
+    fn read_splits() -> Result<()> {
+        let schema = AvroSchema::parse_str(
+            r#"{"type": "record", "name": "t", "fields": [
+                {"name": "a", "type": "long"},
+                {"name": "b", "type": ["null", "string"]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+        for i in 0..3 {
+            let mut record = Record::new(writer.schema()).unwrap();
+            record.put("a", i as i64);
+            record.put("b", AvroValue::Union(Box::new(AvroValue::Null)));
+            writer.append(record).unwrap();
+            // write each record as a separate block
+            writer.flush().unwrap();
+        }
+
+        let dir = std::env::temp_dir().join(format!("ballista-avro-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("data.avro"), writer.into_inner())?;
+        let path = dir.to_str().unwrap();
+
+        let table_schema = Arc::new(avro_table_schema(path)?);
+        assert_eq!(
+            "a: Int64, b: Utf8",
+            table_schema
+                .fields()
+                .iter()
+                .map(|f| format!("{}: {:?}", f.name(), f.data_type()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        // splits smaller than a block read each block exactly once
+        let mut values = vec![];
+        for split in create_splits(path, AVRO_FILE_EXTENSION, 8)? {
+            let mut reader = AvroSplitReader::try_new(&split)?;
+            while let Some(records) = reader.next_records(2)? {
+                let batch = to_record_batch(&records, &table_schema)?;
+                let a = batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<crate::arrow::array::Int64Array>()
+                    .unwrap();
+                values.extend((0..a.len()).map(|i| a.value(i)));
+                assert_eq!(batch.num_rows(), batch.column(1).null_count());
+            }
+        }
+        assert_eq!(vec![0, 1, 2], values);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -15,22 +15,232 @@
 // specific language governing permissions and limitations
 // under the License.
 
-//! CSV scan operator. Forked from DataFusion.
+//! CSV scan operator. Forked from DataFusion, and extended to read files with other quoting
+//! and null conventions, compressed files, and files with malformed rows.
 
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::{Cursor, Read};
 use std::sync::{Arc, Mutex};
 
-use crate::arrow::csv;
-use crate::arrow::datatypes::{Schema, SchemaRef};
-use crate::datafusion::execution::physical_plan::common::build_file_list;
+use crate::arrow::array::{
+    ArrayRef, BooleanBuilder, Float32Builder, Float64Builder, Int16Builder, Int32Builder,
+    Int64Builder, Int8Builder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder,
+    UInt8Builder,
+};
+use crate::arrow::csv::reader::infer_file_schema;
+use crate::arrow::datatypes::{DataType, Schema, SchemaRef};
+use crate::arrow::record_batch::RecordBatch;
 use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
-use crate::error::{ballista_error, Result};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::object_store;
 
 use crate::execution::physical_plan::{
-    ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext, ExecutionPlan,
-    Partitioning,
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext,
+    ExecutionPlan, Partitioning,
 };
 use async_trait::async_trait;
+use bzip2::read::BzDecoder;
+use csv::StringRecord;
+use flate2::read::MultiGzDecoder;
+
+/// Prefix of the schema metadata keys that carry the format options of logical CSV scans
+const METADATA_PREFIX: &str = "ballista.csv.";
+
+/// Compression of CSV files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsvCompression {
+    Uncompressed,
+    Gzip,
+    Bzip2,
+}
+
+impl CsvCompression {
+    /// Name of the compression, as used in serialized plans
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvCompression::Uncompressed => "",
+            CsvCompression::Gzip => "gzip",
+            CsvCompression::Bzip2 => "bzip2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "" => Ok(CsvCompression::Uncompressed),
+            "gzip" => Ok(CsvCompression::Gzip),
+            "bzip2" => Ok(CsvCompression::Bzip2),
+            other => Err(ballista_error(&format!(
+                "Unsupported CSV compression '{}'",
+                other
+            ))),
+        }
+    }
+
+    /// Extension of compressed files, which follows the `.csv` extension
+    fn extension(&self) -> &'static str {
+        match self {
+            CsvCompression::Uncompressed => "",
+            CsvCompression::Gzip => ".gz",
+            CsvCompression::Bzip2 => ".bz2",
+        }
+    }
+}
+
+/// What to do with rows that have the wrong number of fields or values that cannot be parsed
+/// as the type of their column
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MalformedRowPolicy {
+    /// Fail the scan
+    Error,
+    /// Leave the row out
+    Skip,
+    /// Read missing fields and invalid values as nulls, ignoring any extra fields
+    NullFill,
+}
+
+impl MalformedRowPolicy {
+    /// Name of the policy, as used in serialized plans
+    pub fn name(&self) -> &'static str {
+        match self {
+            MalformedRowPolicy::Error => "error",
+            MalformedRowPolicy::Skip => "skip",
+            MalformedRowPolicy::NullFill => "null_fill",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "error" => Ok(MalformedRowPolicy::Error),
+            "skip" => Ok(MalformedRowPolicy::Skip),
+            "null_fill" => Ok(MalformedRowPolicy::NullFill),
+            other => Err(ballista_error(&format!(
+                "Unsupported malformed row policy '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Options for reading CSV files beyond the header, delimiter, and schema of `CsvReadOptions`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFormatOptions {
+    /// Character that quotes fields. Defaults to `b'"'`
+    pub quote: u8,
+    /// Character that escapes quotes within quoted fields. When this is not provided, quotes
+    /// are escaped by doubling them.
+    pub escape: Option<u8>,
+    /// Values that are read as nulls, in addition to empty values in non-string columns
+    pub null_values: Vec<String>,
+    /// Compression of the files
+    pub compression: CsvCompression,
+    /// What to do with malformed rows
+    pub malformed_rows: MalformedRowPolicy,
+}
+
+impl CsvFormatOptions {
+    pub fn new() -> Self {
+        Self {
+            quote: b'"',
+            escape: None,
+            null_values: vec![],
+            compression: CsvCompression::Uncompressed,
+            malformed_rows: MalformedRowPolicy::Error,
+        }
+    }
+
+    /// Specify the quote character
+    pub fn quote(mut self, quote: u8) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    /// Specify the escape character
+    pub fn escape(mut self, escape: u8) -> Self {
+        self.escape = Some(escape);
+        self
+    }
+
+    /// Add a value that is read as null, such as `NULL` or `\N`
+    pub fn null_value(mut self, value: &str) -> Self {
+        self.null_values.push(value.to_owned());
+        self
+    }
+
+    /// Specify the compression of the files
+    pub fn compression(mut self, compression: CsvCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Specify what to do with malformed rows
+    pub fn malformed_rows(mut self, policy: MalformedRowPolicy) -> Self {
+        self.malformed_rows = policy;
+        self
+    }
+
+    /// Extension of the files that are read from a directory
+    pub fn file_extension(&self) -> String {
+        format!(".csv{}", self.compression.extension())
+    }
+
+    /// Encode the options as schema metadata, since logical CSV scans have no other place for
+    /// them. Default options are not encoded.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        if *self == Self::new() {
+            return metadata;
+        }
+        let mut insert = |key: &str, value: String| {
+            metadata.insert(format!("{}{}", METADATA_PREFIX, key), value);
+        };
+        insert("quote", self.quote.to_string());
+        if let Some(escape) = self.escape {
+            insert("escape", escape.to_string());
+        }
+        insert(
+            "null_values",
+            serde_json::to_string(&self.null_values).unwrap_or_default(),
+        );
+        insert("compression", self.compression.name().to_owned());
+        insert("malformed_rows", self.malformed_rows.name().to_owned());
+        metadata
+    }
+
+    /// Decode the options from schema metadata, using the defaults for any that are missing
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Result<Self> {
+        let get = |key: &str| metadata.get(&format!("{}{}", METADATA_PREFIX, key));
+        let parse_char = |value: &String| {
+            value
+                .parse::<u8>()
+                .map_err(|_| ballista_error(&format!("Invalid CSV character '{}'", value)))
+        };
+        let mut options = Self::new();
+        if let Some(quote) = get("quote") {
+            options.quote = parse_char(quote)?;
+        }
+        if let Some(escape) = get("escape") {
+            options.escape = Some(parse_char(escape)?);
+        }
+        if let Some(null_values) = get("null_values") {
+            options.null_values = serde_json::from_str(null_values)
+                .map_err(|e| ballista_error(&format!("Invalid CSV null values: {:?}", e)))?;
+        }
+        if let Some(compression) = get("compression") {
+            options.compression = CsvCompression::from_name(compression)?;
+        }
+        if let Some(malformed_rows) = get("malformed_rows") {
+            options.malformed_rows = MalformedRowPolicy::from_name(malformed_rows)?;
+        }
+        Ok(options)
+    }
+}
+
+impl Default for CsvFormatOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// Execution plan for scanning a CSV file
 pub struct CsvScanExec {
@@ -41,9 +251,11 @@ pub struct CsvScanExec {
     /// Schema representing the CSV file
     schema: SchemaRef,
     /// Does the CSV file have a header?
-    has_header: bool,
-    /// An optional column delimiter. Defaults to `b','`
-    delimiter: Option<u8>,
+    pub(crate) has_header: bool,
+    /// The column delimiter. Defaults to `b','`
+    pub(crate) delimiter: u8,
+    /// Quoting, null, compression, and malformed row options
+    pub(crate) format: CsvFormatOptions,
     /// Optional projection for which columns to load
     pub(crate) projection: Option<Vec<usize>>,
     /// Schema after the projection has been applied
@@ -57,19 +269,19 @@ impl CsvScanExec {
     pub fn try_new(
         path: &str,
         options: CsvReadOptions,
+        format: CsvFormatOptions,
         projection: Option<Vec<usize>>,
         batch_size: usize,
     ) -> Result<Self> {
         // build list of partition files
-        let mut filenames: Vec<String> = vec![];
-        build_file_list(path, &mut filenames, ".csv")?;
+        let filenames = object_store::list_files(path, &format.file_extension())?;
         if filenames.is_empty() {
             return Err(ballista_error("No files found"));
         }
 
         let schema = match options.schema {
             Some(s) => s.clone(),
-            None => CsvScanExec::try_infer_schema(&filenames, &options)?,
+            None => CsvScanExec::try_infer_schema(&filenames, &options, &format)?,
         };
 
         let projected_schema = match &projection {
@@ -82,19 +294,87 @@ impl CsvScanExec {
             filenames,
             schema: Arc::new(schema),
             has_header: options.has_header,
-            delimiter: Some(options.delimiter),
+            delimiter: options.delimiter,
+            format,
             projection,
             projected_schema: Arc::new(projected_schema),
             batch_size,
         })
     }
 
-    /// Infer schema for given CSV dataset
-    pub fn try_infer_schema(filenames: &[String], options: &CsvReadOptions) -> Result<Schema> {
-        Ok(csv::infer_schema_from_files(
-            &filenames,
-            options.delimiter,
-            Some(options.schema_infer_max_records),
+    /// Read a different set of columns, given as indices into the CSV schema
+    pub fn with_projection(&self, projection: Vec<usize>) -> Self {
+        let projected_schema = Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        );
+        Self {
+            path: self.path.clone(),
+            filenames: self.filenames.clone(),
+            schema: self.schema.clone(),
+            has_header: self.has_header,
+            delimiter: self.delimiter,
+            format: self.format.clone(),
+            projection: Some(projection),
+            projected_schema: Arc::new(projected_schema),
+            batch_size: self.batch_size,
+        }
+    }
+
+    /// The schema of the files, including the columns that the projection leaves out
+    pub fn file_schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Infer schema for given CSV dataset from its first records. The records are rewritten
+    /// with the default quoting and with null values left empty before the types of the
+    /// columns are inferred.
+    pub fn try_infer_schema(
+        filenames: &[String],
+        options: &CsvReadOptions,
+        format: &CsvFormatOptions,
+    ) -> Result<Schema> {
+        let max_records = options.schema_infer_max_records;
+        let mut sample = csv::Writer::from_writer(vec![]);
+        let mut num_records = 0;
+        let mut num_fields = None;
+        for (i, filename) in filenames.iter().enumerate() {
+            if num_records >= max_records {
+                break;
+            }
+            let mut reader =
+                open_csv_file(filename, options.has_header, options.delimiter, format)?;
+            if options.has_header && i == 0 {
+                let headers = reader.headers().map_err(csv_error)?.clone();
+                num_fields = Some(headers.len());
+                sample.write_record(&headers).map_err(csv_error)?;
+            }
+            let mut record = StringRecord::new();
+            while num_records < max_records && reader.read_record(&mut record).map_err(csv_error)? {
+                // malformed rows are left out of the sample
+                if *num_fields.get_or_insert(record.len()) != record.len() {
+                    continue;
+                }
+                let values = record.iter().map(|value| {
+                    if format.null_values.iter().any(|v| v == value) {
+                        ""
+                    } else {
+                        value
+                    }
+                });
+                sample.write_record(values).map_err(csv_error)?;
+                num_records += 1;
+            }
+        }
+        let sample = sample
+            .into_inner()
+            .map_err(|e| ballista_error(&format!("Error writing CSV sample: {:?}", e)))?;
+        Ok(infer_file_schema(
+            &mut Cursor::new(sample),
+            b',',
+            Some(max_records),
             options.has_header,
         )?)
     }
@@ -113,53 +393,158 @@ impl ExecutionPlan for CsvScanExec {
 
     async fn execute(
         &self,
-        _ctx: Arc<dyn ExecutionContext>,
+        ctx: Arc<dyn ExecutionContext>,
         partition_index: usize,
     ) -> Result<ColumnarBatchStream> {
-        Ok(Arc::new(CsvBatchIter::try_new(
-            &self.filenames[partition_index],
-            self.schema.clone(),
-            self.has_header,
-            self.delimiter,
-            &self.projection,
-            self.projected_schema.clone(),
-            self.batch_size,
-        )?))
+        let filename = &self.filenames[partition_index];
+        let reader = open_csv_file(filename, self.has_header, self.delimiter, &self.format)?;
+        let projection = match &self.projection {
+            Some(p) => p.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        Ok(Arc::new(CsvBatchIter {
+            filename: filename.clone(),
+            reader: Arc::new(Mutex::new(reader)),
+            num_fields: self.schema.fields().len(),
+            projection,
+            schema: self.projected_schema.clone(),
+            format: self.format.clone(),
+            batch_size: self.batch_size,
+            cancellation_token: ctx.cancellation_token(),
+        }))
     }
 }
 
 struct CsvBatchIter {
-    /// Arrow CSV reader
-    reader: Arc<Mutex<csv::Reader<File>>>,
+    filename: String,
+    /// CSV record reader
+    reader: Arc<Mutex<csv::Reader<Box<dyn Read + Send>>>>,
+    /// Number of fields in well-formed rows
+    num_fields: usize,
+    /// Indices of the fields to read
+    projection: Vec<usize>,
     /// Schema after the projection has been applied
     schema: SchemaRef,
+    format: CsvFormatOptions,
+    batch_size: usize,
+    /// Stop reading when the task is cancelled
+    cancellation_token: CancellationToken,
+}
+
+/// Build an array from optional values, where values that cannot be parsed are null
+macro_rules! build_array {
+    ($BUILDER:ident, $VALUES:expr, $PARSE:expr) => {{
+        let values: Vec<Option<&str>> = $VALUES.collect();
+        let mut builder = $BUILDER::new(values.len());
+        for value in values {
+            match value.map($PARSE) {
+                Some(Ok(value)) => builder.append_value(value)?,
+                _ => builder.append_null()?,
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
 }
 
 impl CsvBatchIter {
-    /// Create an iterator for a CSV file
-    pub fn try_new(
-        filename: &str,
-        schema: SchemaRef,
-        has_header: bool,
-        delimiter: Option<u8>,
-        projection: &Option<Vec<usize>>,
-        projected_schema: SchemaRef,
-        batch_size: usize,
-    ) -> Result<Self> {
-        let file = File::open(filename)?;
-        let reader = csv::Reader::new(
-            file,
-            schema,
-            has_header,
-            delimiter,
-            batch_size,
-            projection.clone(),
-        );
+    /// The value of a field, or `None` if the field is missing or null
+    fn value<'a>(
+        &self,
+        record: &'a StringRecord,
+        i: usize,
+        data_type: &DataType,
+    ) -> Option<&'a str> {
+        let value = record.get(i)?;
+        if self.format.null_values.iter().any(|v| v == value)
+            || (value.is_empty() && *data_type != DataType::Utf8)
+        {
+            None
+        } else {
+            Some(value)
+        }
+    }
 
-        Ok(Self {
-            reader: Arc::new(Mutex::new(reader)),
-            schema: projected_schema,
-        })
+    /// Check a row against the malformed row policy, returning whether to keep it
+    fn keep(&self, record: &StringRecord) -> Result<bool> {
+        if self.format.malformed_rows == MalformedRowPolicy::NullFill {
+            return Ok(true);
+        }
+        let problem = if record.len() != self.num_fields {
+            Some(format!(
+                "expected {} fields but found {}",
+                self.num_fields,
+                record.len()
+            ))
+        } else {
+            self.projection
+                .iter()
+                .zip(self.schema.fields())
+                .find_map(
+                    |(i, field)| match self.value(record, *i, field.data_type()) {
+                        Some(value) if !is_valid(value, field.data_type()) => Some(format!(
+                            "invalid value '{}' for column {} of type {:?}",
+                            value,
+                            field.name(),
+                            field.data_type()
+                        )),
+                        _ => None,
+                    },
+                )
+        };
+        match (problem, self.format.malformed_rows) {
+            (None, _) => Ok(true),
+            (Some(_), MalformedRowPolicy::Skip) => Ok(false),
+            (Some(problem), _) => Err(ballista_error(&format!(
+                "Malformed CSV row in {} at line {}: {}",
+                self.filename,
+                record.position().map(|p| p.line()).unwrap_or_default(),
+                problem
+            ))),
+        }
+    }
+
+    fn to_record_batch(&self, records: &[StringRecord]) -> Result<RecordBatch> {
+        let columns = self
+            .projection
+            .iter()
+            .zip(self.schema.fields())
+            .map(|(i, field)| {
+                let data_type = field.data_type();
+                let values = records
+                    .iter()
+                    .map(|record| self.value(record, *i, data_type));
+                Ok(match data_type {
+                    DataType::Boolean => build_array!(BooleanBuilder, values, parse_bool),
+                    DataType::Int8 => build_array!(Int8Builder, values, str::parse),
+                    DataType::Int16 => build_array!(Int16Builder, values, str::parse),
+                    DataType::Int32 => build_array!(Int32Builder, values, str::parse),
+                    DataType::Int64 => build_array!(Int64Builder, values, str::parse),
+                    DataType::UInt8 => build_array!(UInt8Builder, values, str::parse),
+                    DataType::UInt16 => build_array!(UInt16Builder, values, str::parse),
+                    DataType::UInt32 => build_array!(UInt32Builder, values, str::parse),
+                    DataType::UInt64 => build_array!(UInt64Builder, values, str::parse),
+                    DataType::Float32 => build_array!(Float32Builder, values, str::parse),
+                    DataType::Float64 => build_array!(Float64Builder, values, str::parse),
+                    DataType::Utf8 => {
+                        let mut builder = StringBuilder::new(records.len());
+                        for value in values {
+                            match value {
+                                Some(value) => builder.append_value(value)?,
+                                None => builder.append_null()?,
+                            }
+                        }
+                        Arc::new(builder.finish()) as ArrayRef
+                    }
+                    other => {
+                        return Err(BallistaError::NotImplemented(format!(
+                            "Reading CSV columns of type {:?}",
+                            other
+                        )))
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
     }
 }
 
@@ -170,14 +555,182 @@ impl ColumnarBatchIter for CsvBatchIter {
     }
 
     async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        self.cancellation_token.check()?;
         let mut reader = self.reader.lock().expect("failed to lock mutex");
-        match reader.next() {
-            Ok(Some(batch)) => Ok(Some(ColumnarBatch::from_arrow(&batch))),
-            Ok(None) => Ok(None),
-            Err(e) => Err(ballista_error(&format!(
-                "Error reading CSV: {}",
-                e.to_string()
-            ))),
+        let mut records = vec![];
+        let mut record = StringRecord::new();
+        while records.len() < self.batch_size
+            && reader.read_record(&mut record).map_err(csv_error)?
+        {
+            if self.keep(&record)? {
+                records.push(record.clone());
+            }
+        }
+        if records.is_empty() {
+            return Ok(None);
         }
+        let batch = self.to_record_batch(&records)?;
+        Ok(Some(ColumnarBatch::from_arrow(&batch)))
+    }
+}
+
+/// Open a CSV file for reading records, decompressing it if needed
+fn open_csv_file(
+    filename: &str,
+    has_header: bool,
+    delimiter: u8,
+    format: &CsvFormatOptions,
+) -> Result<csv::Reader<Box<dyn Read + Send>>> {
+    // remote files are fetched in parallel parts before they are parsed
+    let input: Box<dyn Read + Send> = if object_store::is_remote(filename) {
+        Box::new(Cursor::new(
+            object_store::object_store(filename)?.read(filename)?,
+        ))
+    } else {
+        Box::new(File::open(filename)?)
+    };
+    let input: Box<dyn Read + Send> = match format.compression {
+        CsvCompression::Uncompressed => input,
+        CsvCompression::Gzip => Box::new(MultiGzDecoder::new(input)),
+        CsvCompression::Bzip2 => Box::new(BzDecoder::new(input)),
+    };
+    Ok(csv::ReaderBuilder::new()
+        .has_headers(has_header)
+        .delimiter(delimiter)
+        .quote(format.quote)
+        .escape(format.escape)
+        .flexible(true)
+        .from_reader(input))
+}
+
+fn is_valid(value: &str, data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Boolean => parse_bool(value).is_ok(),
+        DataType::Int8 => value.parse::<i8>().is_ok(),
+        DataType::Int16 => value.parse::<i16>().is_ok(),
+        DataType::Int32 => value.parse::<i32>().is_ok(),
+        DataType::Int64 => value.parse::<i64>().is_ok(),
+        DataType::UInt8 => value.parse::<u8>().is_ok(),
+        DataType::UInt16 => value.parse::<u16>().is_ok(),
+        DataType::UInt32 => value.parse::<u32>().is_ok(),
+        DataType::UInt64 => value.parse::<u64>().is_ok(),
+        DataType::Float32 => value.parse::<f32>().is_ok(),
+        DataType::Float64 => value.parse::<f64>().is_ok(),
+        _ => true,
+    }
+}
+
+fn parse_bool(value: &str) -> std::result::Result<bool, ()> {
+    if value.eq_ignore_ascii_case("true") {
+        Ok(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Ok(false)
+    } else {
+        Err(())
+    }
+}
+
+fn csv_error(e: csv::Error) -> BallistaError {
+    ballista_error(&format!("Error reading CSV: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Array, Int32Array, StringArray};
+    use crate::arrow::datatypes::Field;
+    use std::fs;
+    use std::io::Write;
+
+    fn read(path: &str, schema: &Schema, format: CsvFormatOptions) -> Result<Vec<RecordBatch>> {
+        let options = CsvReadOptions::new()
+            .schema(schema)
+            .has_header(true)
+            .delimiter(b'|');
+        let exec = CsvScanExec::try_new(path, options, format, None, 1024)?;
+        let reader = open_csv_file(&exec.filenames[0], true, b'|', &exec.format)?;
+        let iter = CsvBatchIter {
+            filename: exec.filenames[0].clone(),
+            reader: Arc::new(Mutex::new(reader)),
+            num_fields: schema.fields().len(),
+            projection: (0..schema.fields().len()).collect(),
+            schema: exec.schema(),
+            format: exec.format.clone(),
+            batch_size: 1024,
+            cancellation_token: CancellationToken::new(),
+        };
+        let mut batches = vec![];
+        while let Some(batch) = smol::run(iter.next())? {
+            batches.push(batch.to_arrow()?);
+        }
+        Ok(batches)
+    }
+
+    #[test]
+    fn read_compressed_file_with_malformed_rows() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ballista-csv-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(dir.join("data.csv.gz"))?,
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"id|name\n1|'a|b'\nNULL|c\nx|d\n4\n")?;
+        encoder.finish()?;
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, true),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let format = CsvFormatOptions::new()
+            .quote(b'\'')
+            .null_value("NULL")
+            .compression(CsvCompression::Gzip);
+        let path = dir.to_str().unwrap();
+
+        assert!(read(path, &schema, format.clone()).is_err());
+
+        let batches = read(
+            path,
+            &schema,
+            format.clone().malformed_rows(MalformedRowPolicy::Skip),
+        )?;
+        assert_eq!(2, batches[0].num_rows());
+        let names = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("a|b", names.value(0));
+
+        let batches = read(
+            path,
+            &schema,
+            format.malformed_rows(MalformedRowPolicy::NullFill),
+        )?;
+        let ids = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(4, ids.len());
+        assert_eq!(2, ids.null_count());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_metadata() -> Result<()> {
+        let format = CsvFormatOptions::new()
+            .escape(b'\\')
+            .null_value("\\N")
+            .compression(CsvCompression::Bzip2)
+            .malformed_rows(MalformedRowPolicy::Skip);
+        assert_eq!(
+            format,
+            CsvFormatOptions::from_metadata(&format.to_metadata())?
+        );
+        assert!(CsvFormatOptions::new().to_metadata().is_empty());
+        Ok(())
     }
 }
@@ -0,0 +1,220 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Discovery of partition columns from hive-style directory layouts, such as
+//! `/data/date=2020-01-01/region=eu/part-0.parquet`, where every file in a directory named
+//! `key=value` has the value `value` for the partition column `key`.
+
+use std::sync::Arc;
+
+use crate::arrow::array::{ArrayRef, Int64Builder, StringBuilder};
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::datafusion::logicalplan::ScalarValue;
+use crate::error::{ballista_error, Result};
+use crate::object_store;
+
+/// Directory name value that hive uses for null partition values
+pub const DEFAULT_PARTITION_NAME: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// The files of a data source together with the values of their partition columns
+#[derive(Debug, Clone)]
+pub struct PartitionedFiles {
+    pub filenames: Vec<String>,
+    /// Partition columns, in the order in which they appear in the path
+    pub fields: Vec<Field>,
+    /// Values of the partition columns for each file, in the same order as the filenames
+    pub values: Vec<Vec<ScalarValue>>,
+}
+
+impl PartitionedFiles {
+    /// Find the files with the given extension under a local path or object store prefix and
+    /// parse the values of their partition columns. Partition columns are integers when every
+    /// value is an integer and are strings otherwise.
+    pub fn try_new(path: &str, extension: &str) -> Result<Self> {
+        let filenames = object_store::list_files(path, extension)?;
+        if filenames.is_empty() {
+            return Err(ballista_error(&format!(
+                "No {} files found in {}",
+                extension, path
+            )));
+        }
+
+        let parsed: Vec<Vec<(String, Option<String>)>> = filenames
+            .iter()
+            .map(|f| parse_partition_path(path, f))
+            .collect();
+        let names: Vec<String> = parsed[0].iter().map(|(k, _)| k.clone()).collect();
+        for (filename, values) in filenames.iter().zip(&parsed) {
+            if values.iter().map(|(k, _)| k).ne(names.iter()) {
+                return Err(ballista_error(&format!(
+                    "File {} does not have the partition columns {:?}",
+                    filename, names
+                )));
+            }
+        }
+
+        let fields: Vec<Field> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let is_integer = parsed.iter().all(|values| match &values[i].1 {
+                    Some(v) => v.parse::<i64>().is_ok(),
+                    None => true,
+                });
+                let data_type = if is_integer {
+                    DataType::Int64
+                } else {
+                    DataType::Utf8
+                };
+                Field::new(name, data_type, true)
+            })
+            .collect();
+
+        let values = parsed
+            .into_iter()
+            .map(|values| {
+                values
+                    .into_iter()
+                    .zip(&fields)
+                    .map(|((_, value), field)| match (value, field.data_type()) {
+                        (None, _) => ScalarValue::Null,
+                        (Some(v), DataType::Int64) => ScalarValue::Int64(v.parse().unwrap()),
+                        (Some(v), _) => ScalarValue::Utf8(v),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Ok(Self {
+            filenames,
+            fields,
+            values,
+        })
+    }
+
+    /// The schema of the data source, which is the schema of the files followed by the
+    /// partition columns
+    pub fn schema(&self, file_schema: &Schema) -> Schema {
+        let mut fields = file_schema.fields().clone();
+        fields.extend(self.fields.iter().cloned());
+        Schema::new(fields)
+    }
+}
+
+/// Parse the `key=value` directory names between the root path and the file
+fn parse_partition_path(root: &str, filename: &str) -> Vec<(String, Option<String>)> {
+    let relative = if filename.starts_with(root) {
+        &filename[root.len()..]
+    } else {
+        filename
+    };
+    let mut segments: Vec<&str> = relative.split('/').filter(|s| !s.is_empty()).collect();
+    // the last segment is the file name
+    segments.pop();
+    segments
+        .into_iter()
+        .filter_map(|segment| {
+            let i = segment.find('=')?;
+            let value = unescape(&segment[i + 1..]);
+            let value = if value == DEFAULT_PARTITION_NAME {
+                None
+            } else {
+                Some(value)
+            };
+            Some((unescape(&segment[..i]), value))
+        })
+        .collect()
+}
+
+/// Decode the `%XX` escape sequences that hive uses for special characters in paths
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Create an array containing the value of a partition column for every row of a batch
+pub(crate) fn partition_array(
+    value: &ScalarValue,
+    data_type: &DataType,
+    num_rows: usize,
+) -> Result<ArrayRef> {
+    match data_type {
+        DataType::Int64 => {
+            let mut builder = Int64Builder::new(num_rows);
+            for _ in 0..num_rows {
+                match value {
+                    ScalarValue::Int64(v) => builder.append_value(*v)?,
+                    _ => builder.append_null()?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new(num_rows);
+            for _ in 0..num_rows {
+                match value {
+                    ScalarValue::Utf8(v) => builder.append_value(v)?,
+                    _ => builder.append_null()?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        other => Err(ballista_error(&format!(
+            "Unsupported partition column type {:?}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_path() {
+        assert_eq!(
+            vec![
+                ("date".to_owned(), Some("2020-01-01".to_owned())),
+                ("region".to_owned(), Some("eu/west".to_owned())),
+                ("year".to_owned(), None),
+            ],
+            parse_partition_path(
+                "/data",
+                "/data/date=2020-01-01/region=eu%2Fwest/year=__HIVE_DEFAULT_PARTITION__/part-0.parquet"
+            )
+        );
+        assert!(parse_partition_path("/data", "/data/part-0.parquet").is_empty());
+    }
+}
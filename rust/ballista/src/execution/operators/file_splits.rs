@@ -0,0 +1,97 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Division of files into byte ranges that are read by separate partitions of a scan. Formats
+//! that can be split have markers between records, and each range reads the records that
+//! start within it.
+
+use crate::error::{ballista_error, Result};
+use crate::object_store::{self, ObjectStore};
+
+/// Files are read in ranges of at most this many bytes
+pub const DEFAULT_SPLIT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Number of bytes read at a time when searching for a marker
+const MARKER_SEARCH_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A byte range of a file that is read by one partition of a scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSplit {
+    pub filename: String,
+    pub file_size: u64,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Divide the files with the given extension under a path into byte ranges of at most
+/// `split_size` bytes. Every file has at least one split, even when it is empty.
+pub fn create_splits(path: &str, extension: &str, split_size: u64) -> Result<Vec<FileSplit>> {
+    let mut objects = object_store::object_store(path)?.list(path)?;
+    objects.retain(|o| o.path.ends_with(extension));
+    objects.sort_by(|a, b| a.path.cmp(&b.path));
+    if objects.is_empty() {
+        return Err(ballista_error(&format!(
+            "No {} files found in {}",
+            extension, path
+        )));
+    }
+
+    let mut splits = vec![];
+    for object in objects {
+        let mut start = 0;
+        loop {
+            let end = (start + split_size).min(object.size);
+            splits.push(FileSplit {
+                filename: object.path.clone(),
+                file_size: object.size,
+                start,
+                end,
+            });
+            if end >= object.size {
+                break;
+            }
+            start = end;
+        }
+    }
+    Ok(splits)
+}
+
+/// Find the position just after the first occurrence of `marker` that ends at or after `pos`,
+/// which is where the next record starts, or the end of the file when there is no such marker
+pub fn next_record_start(
+    store: &dyn ObjectStore,
+    split: &FileSplit,
+    pos: u64,
+    marker: &[u8],
+) -> Result<u64> {
+    if pos >= split.file_size {
+        return Ok(split.file_size);
+    }
+    let mut offset = pos.saturating_sub(marker.len() as u64);
+    while offset < split.file_size {
+        let length = MARKER_SEARCH_CHUNK_SIZE
+            .max(2 * marker.len())
+            .min((split.file_size - offset) as usize);
+        let chunk = store.read_range(&split.filename, offset, length)?;
+        if let Some(i) = chunk.windows(marker.len()).position(|w| w == marker) {
+            return Ok(offset + (i + marker.len()) as u64);
+        }
+        if offset + length as u64 >= split.file_size {
+            break;
+        }
+        // the next chunk overlaps this one in case the marker spans both
+        offset += (length - marker.len() + 1) as u64;
+    }
+    Ok(split.file_size)
+}
@@ -17,9 +17,9 @@
 use std::sync::Arc;
 
 use crate::arrow;
-use crate::arrow::array;
+use crate::arrow::array::{self, Array};
 use crate::arrow::datatypes::Schema;
-use crate::datafusion::logicalplan::Expr;
+use crate::datafusion::logicalplan::{Expr, ScalarValue};
 use crate::error::{ballista_error, Result};
 use crate::{
     cast_array,
@@ -31,6 +31,7 @@ use crate::{
 
 use crate::execution::physical_plan::Partitioning;
 use async_trait::async_trait;
+use log::debug;
 use std::time::Instant;
 
 /// FilterExec evaluates a boolean expression against each row of input to determine which rows
@@ -82,11 +83,7 @@ impl ExecutionPlan for FilterExec {
     ) -> Result<ColumnarBatchStream> {
         let expr = compile_expression(&self.filter_expr, &self.schema())?;
         Ok(Arc::new(FilterIter {
-            input: self
-                .child
-                .as_execution_plan()
-                .execute(ctx.clone(), partition_index)
-                .await?,
+            input: self.child.execute(ctx.clone(), partition_index).await?,
             filter_expr: expr,
         }))
     }
@@ -111,8 +108,8 @@ impl ColumnarBatchIter for FilterIter {
                 let bools = self.filter_expr.evaluate(&input)?;
                 let batch = apply_filter(&input, &bools)?;
                 let elapsed = start.elapsed().as_millis();
-                println!(
-                    "Filtered batch of {} rows in {} ms.",
+                debug!(
+                    "Filtered batch rows={} duration_ms={}",
                     input.num_rows(),
                     elapsed
                 );
@@ -123,17 +120,34 @@ impl ColumnarBatchIter for FilterIter {
     }
 }
 
-/// Filter the provided batch based on the bitmask
+/// Filter the provided batch based on the bitmask. Each column is filtered with the Arrow
+/// kernel, while a literal bitmask keeps either all of the rows or none of them.
 fn apply_filter(batch: &ColumnarBatch, bitmask: &ColumnarValue) -> Result<ColumnarBatch> {
-    let predicate = bitmask.to_arrow()?;
+    let predicate = match bitmask {
+        ColumnarValue::Scalar(Some(ScalarValue::Boolean(true)), _) => return Ok(batch.clone()),
+        ColumnarValue::Scalar(_, _) => {
+            let empty_arrays = (0..batch.num_columns())
+                .map(|i| {
+                    Ok(ColumnarValue::Columnar(
+                        batch.column(i).to_arrow()?.slice(0, 0),
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(ColumnarBatch::from_values(&empty_arrays));
+        }
+        ColumnarValue::Columnar(predicate) => predicate,
+    };
     let predicate = cast_array!(predicate, BooleanArray)?;
 
-    let mut filtered_arrays = Vec::with_capacity(batch.num_columns());
-    for i in 0..batch.num_columns() {
-        let array = batch.column(i);
-        let filtered_array = arrow::compute::filter(array.to_arrow()?.as_ref(), predicate)?;
-        filtered_arrays.push(ColumnarValue::Columnar(filtered_array));
-    }
+    let filtered_arrays = (0..batch.num_columns())
+        .map(|i| {
+            let array = batch.column(i).to_arrow()?;
+            Ok(ColumnarValue::Columnar(arrow::compute::filter(
+                array.as_ref(),
+                predicate,
+            )?))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
     Ok(ColumnarBatch::from_values(&filtered_arrays))
 }
@@ -17,24 +17,38 @@
 
 //! Ballista Hash Aggregate operator. This is based on the implementation from DataFusion in the
 //! Apache Arrow project.
-
+//!
+//! Aggregates over several partitions run in two phases: a partial aggregate before the shuffle
+//! produces the intermediate state of each group, and a final aggregate after the shuffle merges
+//! it. When the hash table outgrows its memory reservation, the partial aggregate sends on the
+//! groups it holds and starts afresh, while the other modes spill the intermediate state to
+//! files partitioned by grouping key and merge one file at a time once the input is consumed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
 
 use crate::arrow::array::StringBuilder;
 use crate::arrow::array::{self, ArrayRef};
-use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::datatypes::{DataType, Schema};
 use crate::cast_array;
 use crate::datafusion::logicalplan::{Expr, ScalarValue};
 use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::memory::TaskMemory;
 use crate::execution::physical_plan::{
     compile_aggregate_expressions, compile_expressions, Accumulator, AggregateExpr, AggregateMode,
-    ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ColumnarValue, Distribution,
-    ExecutionContext, ExecutionPlan, Expression, MaybeColumnarBatch, Partitioning, PhysicalPlan,
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ColumnarValue,
+    Distribution, ExecutionContext, ExecutionPlan, Expression, MaybeColumnarBatch, Partitioning,
+    PhysicalPlan,
 };
+use crate::execution::spill::{SpillFile, SpillWriter};
 
 use async_trait::async_trait;
 use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::{debug, error};
 use smol::Task;
 use std::collections::HashMap;
 
@@ -62,18 +76,12 @@ impl HashAggregateExec {
         let compiled_group_expr = compile_expressions(&group_expr, &input_schema)?;
         let compiled_aggr_expr = compile_aggregate_expressions(&aggr_expr, &input_schema)?;
 
-        let mut fields = compiled_group_expr
-            .iter()
-            .map(|e| e.to_schema_field(&input_schema))
-            .collect::<Result<Vec<_>>>()?;
-        fields.extend(
-            compiled_aggr_expr
-                .iter()
-                .map(|e| e.to_schema_field(&input_schema))
-                .collect::<Result<Vec<_>>>()?,
-        );
-
-        let schema = Arc::new(Schema::new(fields));
+        let schema = Arc::new(output_schema(
+            &mode,
+            &compiled_group_expr,
+            &compiled_aggr_expr,
+            &input_schema,
+        )?);
 
         Ok(Self {
             mode,
@@ -104,7 +112,9 @@ impl ExecutionPlan for HashAggregateExec {
 
     fn output_partitioning(&self) -> Partitioning {
         match self.mode {
-            AggregateMode::Partial => self.child.as_execution_plan().output_partitioning(),
+            AggregateMode::Partial | AggregateMode::FinalPartitioned => {
+                self.child.as_execution_plan().output_partitioning()
+            }
             _ => Partitioning::UnknownPartitioning(1),
         }
     }
@@ -112,6 +122,16 @@ impl ExecutionPlan for HashAggregateExec {
     fn required_child_distribution(&self) -> Distribution {
         match self.mode {
             AggregateMode::Partial => Distribution::UnspecifiedDistribution,
+            AggregateMode::FinalPartitioned if !self.group_expr.is_empty() => {
+                Distribution::HashClusteredDistribution {
+                    required_num_partitions: self
+                        .child
+                        .as_execution_plan()
+                        .output_partitioning()
+                        .partition_count(),
+                    clustering: self.group_expr.clone(),
+                }
+            }
             _ => Distribution::SinglePartition,
         }
     }
@@ -127,41 +147,49 @@ impl ExecutionPlan for HashAggregateExec {
     ) -> Result<ColumnarBatchStream> {
         let child_exec = self.child.as_execution_plan();
         let input_schema = child_exec.schema();
-        let input = child_exec.execute(ctx.clone(), partition_index).await?;
+        let input = self.child.execute(ctx.clone(), partition_index).await?;
         let group_expr = compile_expressions(&self.group_expr, &input_schema)?;
         let aggr_expr = compile_aggregate_expressions(&self.aggr_expr, &input_schema)?;
-        let a: Vec<Field> = group_expr
-            .iter()
-            .map(|e| e.to_schema_field(&input_schema))
-            .collect::<Result<_>>()?;
-        let b: Vec<Field> = aggr_expr
-            .iter()
-            .map(|e| e.to_schema_field(&input_schema))
-            .collect::<Result<_>>()?;
-        let mut fields = vec![];
-        for field in &a {
-            fields.push(field.clone());
-        }
-        for field in &b {
-            fields.push(field.clone());
-        }
         Ok(Arc::new(HashAggregateIter::new(
             &self.mode,
             input,
             group_expr,
             aggr_expr,
-            Arc::new(Schema::new(fields)),
+            self.schema(),
+            ctx.cancellation_token(),
+            ctx.task_memory(),
         )))
     }
 }
 
-/// Create array from `value` attribute in map entry (representing an aggregate scalar
-/// value)
+/// Partial aggregation outputs the intermediate state of each aggregate expression, which can
+/// span multiple columns, whereas the other modes output the final values
+fn output_schema(
+    mode: &AggregateMode,
+    group_expr: &[Arc<dyn Expression>],
+    aggr_expr: &[Arc<dyn AggregateExpr>],
+    input_schema: &Schema,
+) -> Result<Schema> {
+    let mut fields = group_expr
+        .iter()
+        .map(|e| e.to_schema_field(&input_schema))
+        .collect::<Result<Vec<_>>>()?;
+    for expr in aggr_expr {
+        match mode {
+            AggregateMode::Partial => fields.extend(expr.state_fields(input_schema)?),
+            _ => fields.push(expr.to_schema_field(input_schema)?),
+        }
+    }
+    Ok(Schema::new(fields))
+}
+
+/// Create array from the aggregate scalar values (or intermediate state values) of each map
+/// entry
 macro_rules! extract_aggr_value {
-    ($BUILDER:ident, $TY:ident, $TY2:ty, $MAP:expr, $COL_INDEX:expr) => {{
-        let mut builder = array::$BUILDER::new($MAP.len());
-        for v in $MAP.values() {
-            match v[$COL_INDEX].as_ref().get_value()? {
+    ($BUILDER:ident, $TY:ident, $TY2:ty, $VALUES:expr, $COL_INDEX:expr) => {{
+        let mut builder = array::$BUILDER::new($VALUES.len());
+        for v in $VALUES {
+            match v {
                 None => builder.append_null()?,
                 Some(ScalarValue::$TY(n)) => builder.append_value(n as $TY2)?,
                 Some(other) => {
@@ -216,12 +244,13 @@ macro_rules! extract_group_val {
 //     }};
 // }
 
-macro_rules! update_accumulators {
-    ($ARRAY:ident, $ARRAY_TY:ident, $SCALAR_TY:expr, $ROW:expr, $COL:expr, $ACCUM:expr) => {{
+macro_rules! scalar_value {
+    ($ARRAY:ident, $ARRAY_TY:ident, $SCALAR_TY:expr, $ROW:expr) => {{
         let primitive_array = cast_array!($ARRAY, $ARRAY_TY)?;
         if $ARRAY.is_valid($ROW) {
-            let value = $SCALAR_TY(primitive_array.value($ROW));
-            $ACCUM[$COL].accumulate(&ColumnarValue::Scalar(Some(value), 1))?;
+            Some($SCALAR_TY(primitive_array.value($ROW)))
+        } else {
+            None
         }
     }};
 }
@@ -241,6 +270,8 @@ fn run(
     input: ColumnarBatchStream,
     group_expr: Vec<Arc<dyn Expression>>,
     aggr_expr: Vec<Arc<dyn AggregateExpr>>,
+    cancellation_token: CancellationToken,
+    memory: TaskMemory,
 ) -> Result<()> {
     smol::run(async {
         // metrics
@@ -250,6 +281,19 @@ fn run(
         let mut batch_count = 0;
         let mut row_count = 0;
 
+        let input_schema = input.as_ref().schema();
+        let mut reservation = memory.reservation("HashAggregateExec");
+
+        // final aggregation merges the intermediate state produced by partial aggregation
+        let merge = match mode {
+            AggregateMode::Final | AggregateMode::FinalPartitioned => true,
+            AggregateMode::Partial | AggregateMode::Complete => false,
+        };
+
+        // groups can only be spilled when the state of every aggregate can be merged again
+        let spillable = aggr_expr.iter().all(|e| e.mergeable());
+        let mut spill: Option<AggregateSpill> = None;
+
         // hash map of grouping values to accumulators
         let mut map: HashMap<Vec<GroupByScalar>, AccumulatorSet> = HashMap::new();
 
@@ -260,9 +304,10 @@ fn run(
             key.push(GroupByScalar::UInt32(0));
         }
 
-        // iterate over all the input batches .. note that in partial mode it would be valid
-        // to emit batches periodically and reset the accumulator map to reduce memory pressure
+        // iterate over all the input batches
         loop {
+            cancellation_token.check()?;
+
             let read_batch_start = Instant::now();
             let maybe_batch = input.next().await?;
             read_batch_time += read_batch_start.elapsed().as_millis();
@@ -280,36 +325,60 @@ fn run(
                         .map(|e| e.evaluate(&batch))
                         .collect::<Result<Vec<_>>>()?;
 
-                    // evaluate the input expressions to the aggregate functions
+                    // evaluate the input expressions to the aggregate functions, or the
+                    // intermediate state columns when merging the results of partial
+                    // aggregation
                     let aggr_input_values = aggr_expr
                         .iter()
-                        .map(|e| e.evaluate_input(&batch))
+                        .map(|e| {
+                            if merge {
+                                e.evaluate_state(&batch)
+                            } else {
+                                Ok(vec![e.evaluate_input(&batch)?])
+                            }
+                        })
                         .collect::<Result<Vec<_>>>()?;
 
-                    // we now need to switch to row-based processing :-(
-                    for row in 0..batch.num_rows() {
-                        // create grouping key for this row
-                        create_key(&group_values, row, &mut key)?;
-
-                        // lookup the accumulators for this grouping key
-                        let updated = match map.get_mut(&key) {
-                            Some(mut accumulators) => {
-                                accumulate(&aggr_input_values, &mut accumulators, row)?;
-                                true
+                    let new_group_bytes = update_groups(
+                        &mut map,
+                        &mut key,
+                        &group_values,
+                        &aggr_input_values,
+                        batch.num_rows(),
+                        &aggr_expr,
+                        mode,
+                        merge,
+                    )?;
+
+                    // memory for the groups first seen in this batch is reserved once the batch
+                    // has been processed, and the groups are flushed when it cannot be reserved
+                    if !reservation.try_grow(new_group_bytes) {
+                        match mode {
+                            // partial results are merged after the shuffle, so the groups seen
+                            // so far can be sent on early and the hash table started afresh
+                            AggregateMode::Partial => {
+                                debug!("HashAggregate emitting partial groups={}", map.len());
+                                let batch = create_batch_from_accum_map(
+                                    &map,
+                                    mode,
+                                    &input_schema,
+                                    &group_expr,
+                                    &aggr_expr,
+                                )?;
+                                send(&tx, batch)?;
+                                map.clear();
+                                reservation.free();
                             }
-                            None => false,
-                        };
-
-                        // create the accumulators for this grouping key if they weren't found
-                        if !updated {
-                            let mut accumulators: AccumulatorSet = aggr_expr
-                                .iter()
-                                .map(|expr| expr.create_accumulator(&mode))
-                                .collect();
-
-                            accumulate(&aggr_input_values, &mut accumulators, row)?;
-
-                            map.insert(key.clone(), accumulators);
+                            _ if spillable => {
+                                let spill = spill
+                                    .get_or_insert_with(|| AggregateSpill::new(memory.spill_dir()));
+                                spill.write(&mut map, &input_schema, &group_expr, &aggr_expr)?;
+                                reservation.free();
+                            }
+                            // the groups of aggregates that cannot be merged are held in memory,
+                            // so the task fails rather than exhausting the memory of the executor
+                            // when they do not fit
+                            _ => reservation.grow(new_group_bytes)?,
                         }
                     }
                     accum_batch_time += accum_start.elapsed().as_millis();
@@ -320,19 +389,69 @@ fn run(
 
         // prepare results
         let prepare_final_batch_start = Instant::now();
-        let batch = create_batch_from_accum_map(
-            &map,
-            input.as_ref().schema().as_ref(),
-            &group_expr,
-            &aggr_expr,
-        )?;
+        match spill {
+            Some(mut spill) => {
+                // the groups still in memory are spilled too, so that each partition of the
+                // groups can be merged on its own
+                spill.write(&mut map, &input_schema, &group_expr, &aggr_expr)?;
+                reservation.free();
+                debug!("HashAggregate merging spilled groups {:?}", spill);
+
+                let state_columns = state_columns(&group_expr, &aggr_expr, &input_schema)?;
+                for file in spill.finish()? {
+                    for batch in file.read()? {
+                        cancellation_token.check()?;
+                        let batch = ColumnarBatch::from_arrow(&batch?);
+                        let group_values: Vec<ColumnarValue> = (0..group_expr.len())
+                            .map(|i| batch.column(i).clone())
+                            .collect();
+                        let state_values: Vec<Vec<ColumnarValue>> = state_columns
+                            .iter()
+                            .map(|columns| {
+                                columns.iter().map(|i| batch.column(*i).clone()).collect()
+                            })
+                            .collect();
+                        let new_group_bytes = update_groups(
+                            &mut map,
+                            &mut key,
+                            &group_values,
+                            &state_values,
+                            batch.num_rows(),
+                            &aggr_expr,
+                            mode,
+                            true,
+                        )?;
+                        // a partition holds a fraction of the groups, so it is expected to fit
+                        reservation.grow(new_group_bytes)?;
+                    }
+                    if map.is_empty() {
+                        continue;
+                    }
+                    let batch = create_batch_from_accum_map(
+                        &map,
+                        mode,
+                        &input_schema,
+                        &group_expr,
+                        &aggr_expr,
+                    )?;
+                    send(&tx, batch)?;
+                    map.clear();
+                    reservation.free();
+                }
+            }
+            None => {
+                let batch = create_batch_from_accum_map(
+                    &map,
+                    mode,
+                    &input_schema,
+                    &group_expr,
+                    &aggr_expr,
+                )?;
+                send(&tx, batch)?;
+            }
+        }
         let create_final_batch_time = prepare_final_batch_start.elapsed().as_millis();
 
-        // send the result batch over the channel
-        tx.send(Ok(Some(batch))).map_err(|e| {
-            ballista_error(&format!("Error sending hash aggregate result: {:?}", e))
-        })?;
-
         // send EOF marker
         tx.send(Ok(None)).map_err(|e| {
             ballista_error(&format!(
@@ -341,10 +460,9 @@ fn run(
             ))
         })?;
 
-        println!(
-            "HashAggregate processed {} batches and {} rows. \
-            Reading: {} ms; Accumulating: {} ms; Create result: {} ms. \
-            Total duration {} ms.",
+        debug!(
+            "HashAggregate completed batches={} rows={} read_ms={} accumulate_ms={} \
+            create_result_ms={} duration_ms={}",
             batch_count,
             row_count,
             read_batch_time,
@@ -357,109 +475,227 @@ fn run(
     })
 }
 
+/// Send a result batch over the channel
+fn send(tx: &Sender<MaybeColumnarBatch>, batch: ColumnarBatch) -> Result<()> {
+    tx.send(Ok(Some(batch)))
+        .map_err(|e| ballista_error(&format!("Error sending hash aggregate result: {:?}", e)))
+}
+
+/// Update the accumulators of the groups of each row, creating the groups that are not yet in
+/// the hash table. Returns the estimated bytes of the new groups.
+#[allow(clippy::too_many_arguments)]
+fn update_groups(
+    map: &mut HashMap<Vec<GroupByScalar>, AccumulatorSet>,
+    key: &mut Vec<GroupByScalar>,
+    group_values: &[ColumnarValue],
+    aggr_input_values: &[Vec<ColumnarValue>],
+    num_rows: usize,
+    aggr_expr: &[Arc<dyn AggregateExpr>],
+    mode: &AggregateMode,
+    merge: bool,
+) -> Result<usize> {
+    let mut new_group_bytes = 0;
+
+    // we now need to switch to row-based processing :-(
+    for row in 0..num_rows {
+        // create grouping key for this row
+        create_key(group_values, row, key)?;
+
+        // lookup the accumulators for this grouping key
+        let updated = match map.get_mut(&*key) {
+            Some(mut accumulators) => {
+                accumulate(aggr_input_values, &mut accumulators, row, merge)?;
+                true
+            }
+            None => false,
+        };
+
+        // create the accumulators for this grouping key if they weren't found
+        if !updated {
+            let mut accumulators: AccumulatorSet = aggr_expr
+                .iter()
+                .map(|expr| expr.create_accumulator(mode))
+                .collect();
+
+            accumulate(aggr_input_values, &mut accumulators, row, merge)?;
+
+            new_group_bytes += group_size(key, accumulators.len());
+            map.insert(key.clone(), accumulators);
+        }
+    }
+    Ok(new_group_bytes)
+}
+
+/// Number of files that the groups are partitioned into when they are spilled
+const SPILL_PARTITIONS: usize = 16;
+
+/// Intermediate state of groups that did not fit in memory, partitioned by grouping key so that
+/// all the state of a group ends up in the same file and each file can be merged on its own
+struct AggregateSpill {
+    dir: PathBuf,
+    writers: Vec<SpillWriter>,
+    num_spills: usize,
+}
+
+impl AggregateSpill {
+    fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            writers: vec![],
+            num_spills: 0,
+        }
+    }
+
+    /// Move the state of the groups in the hash table to disk, in the layout of partial
+    /// aggregation
+    fn write(
+        &mut self,
+        map: &mut HashMap<Vec<GroupByScalar>, AccumulatorSet>,
+        input_schema: &Schema,
+        group_expr: &[Arc<dyn Expression>],
+        aggr_expr: &[Arc<dyn AggregateExpr>],
+    ) -> Result<()> {
+        let mut partitions: Vec<HashMap<Vec<GroupByScalar>, AccumulatorSet>> =
+            (0..SPILL_PARTITIONS).map(|_| HashMap::new()).collect();
+        for (key, accumulators) in map.drain() {
+            partitions[spill_partition(&key)].insert(key, accumulators);
+        }
+        for (i, partition) in partitions.iter().enumerate() {
+            let batch = create_batch_from_accum_map(
+                partition,
+                &AggregateMode::Partial,
+                input_schema,
+                group_expr,
+                aggr_expr,
+            )?
+            .to_arrow()?;
+            if self.writers.len() <= i {
+                self.writers.push(SpillWriter::try_new(
+                    &self.dir,
+                    "hash_aggregate",
+                    &batch.schema(),
+                )?);
+            }
+            if batch.num_rows() > 0 {
+                self.writers[i].write(&batch)?;
+            }
+        }
+        self.num_spills += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<SpillFile>> {
+        self.writers.into_iter().map(|w| w.finish()).collect()
+    }
+}
+
+impl fmt::Debug for AggregateSpill {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AggregateSpill")
+            .field("partitions", &self.writers.len())
+            .field("spills", &self.num_spills)
+            .finish()
+    }
+}
+
+/// Partition of the spill files that a group goes to. The hash is salted so that it is
+/// independent of the hash partitioning that brought the rows to this partition of the
+/// aggregate, which would otherwise put all of the groups in the same few files.
+fn spill_partition(key: &[GroupByScalar]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    SPILL_PARTITIONS.hash(&mut hasher);
+    key.hash(&mut hasher);
+    (hasher.finish() % SPILL_PARTITIONS as u64) as usize
+}
+
+/// Indices of the intermediate state columns of each aggregate expression in the batches
+/// produced by partial aggregation, which follow the grouping columns
+fn state_columns(
+    group_expr: &[Arc<dyn Expression>],
+    aggr_expr: &[Arc<dyn AggregateExpr>],
+    input_schema: &Schema,
+) -> Result<Vec<Vec<usize>>> {
+    let mut next = group_expr.len();
+    aggr_expr
+        .iter()
+        .map(|e| {
+            let num_fields = e.state_fields(input_schema)?.len();
+            let columns = (next..next + num_fields).collect();
+            next += num_fields;
+            Ok(columns)
+        })
+        .collect()
+}
+
+/// Estimated bytes of the accumulator of an aggregate expression for one group
+const ACCUMULATOR_BYTES: usize = 48;
+
+/// Estimated bytes of the entry in the hash table for a group
+fn group_size(key: &[GroupByScalar], accumulators: usize) -> usize {
+    let strings: usize = key
+        .iter()
+        .map(|k| match k {
+            GroupByScalar::Utf8(s) => s.len(),
+            _ => 0,
+        })
+        .sum();
+    std::mem::size_of::<GroupByScalar>() * key.len() + strings + ACCUMULATOR_BYTES * accumulators
+}
+
 #[inline]
 fn accumulate(
-    aggr_input_values: &[ColumnarValue],
+    aggr_input_values: &[Vec<ColumnarValue>],
     accumulators: &mut AccumulatorSet,
     row: usize,
+    merge: bool,
 ) -> Result<()> {
-    for (col, value) in aggr_input_values.iter().enumerate() {
-        match &value {
-            ColumnarValue::Columnar(array) => match array.data_type() {
-                DataType::Int8 => update_accumulators!(
-                    array,
-                    Int8Array,
-                    ScalarValue::Int8,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::Int16 => update_accumulators!(
-                    array,
-                    Int16Array,
-                    ScalarValue::Int16,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::Int32 => update_accumulators!(
-                    array,
-                    Int32Array,
-                    ScalarValue::Int32,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::Int64 => update_accumulators!(
-                    array,
-                    Int64Array,
-                    ScalarValue::Int64,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::UInt8 => update_accumulators!(
-                    array,
-                    UInt8Array,
-                    ScalarValue::UInt8,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::UInt16 => update_accumulators!(
-                    array,
-                    UInt16Array,
-                    ScalarValue::UInt16,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::UInt32 => update_accumulators!(
-                    array,
-                    UInt32Array,
-                    ScalarValue::UInt32,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::UInt64 => update_accumulators!(
-                    array,
-                    UInt64Array,
-                    ScalarValue::UInt64,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::Float32 => update_accumulators!(
-                    array,
-                    Float32Array,
-                    ScalarValue::Float32,
-                    row,
-                    col,
-                    accumulators
-                ),
-                DataType::Float64 => update_accumulators!(
-                    array,
-                    Float64Array,
-                    ScalarValue::Float64,
-                    row,
-                    col,
-                    accumulators
-                ),
-                _other => {
-                    unimplemented!()
-                    // return Err(BallistaError::General(format!(
-                    //     "Unsupported data type {:?} for result of aggregate expression",
-                    //     other
-                    // )));
-                }
-            },
-            _ => unimplemented!(),
-        };
+    for (col, values) in aggr_input_values.iter().enumerate() {
+        let values = values
+            .iter()
+            .map(|v| Ok(ColumnarValue::Scalar(scalar_at(v, row)?, 1)))
+            .collect::<Result<Vec<_>>>()?;
+        if merge {
+            accumulators[col].merge(&values)?;
+        } else if let ColumnarValue::Scalar(Some(_), _) = &values[0] {
+            accumulators[col].accumulate(&values[0])?;
+        }
     }
     Ok(())
 }
 
+/// Get the value of a column at the given row, or `None` if the value is null
+fn scalar_at(value: &ColumnarValue, row: usize) -> Result<Option<ScalarValue>> {
+    match value {
+        ColumnarValue::Columnar(array) => Ok(match array.data_type() {
+            DataType::Int8 => scalar_value!(array, Int8Array, ScalarValue::Int8, row),
+            DataType::Int16 => scalar_value!(array, Int16Array, ScalarValue::Int16, row),
+            DataType::Int32 => scalar_value!(array, Int32Array, ScalarValue::Int32, row),
+            DataType::Int64 => scalar_value!(array, Int64Array, ScalarValue::Int64, row),
+            DataType::UInt8 => scalar_value!(array, UInt8Array, ScalarValue::UInt8, row),
+            DataType::UInt16 => scalar_value!(array, UInt16Array, ScalarValue::UInt16, row),
+            DataType::UInt32 => scalar_value!(array, UInt32Array, ScalarValue::UInt32, row),
+            DataType::UInt64 => scalar_value!(array, UInt64Array, ScalarValue::UInt64, row),
+            DataType::Float32 => scalar_value!(array, Float32Array, ScalarValue::Float32, row),
+            DataType::Float64 => scalar_value!(array, Float64Array, ScalarValue::Float64, row),
+            DataType::Utf8 => {
+                let string_array = cast_array!(array, StringArray)?;
+                if array.is_valid(row) {
+                    Some(ScalarValue::Utf8(string_array.value(row).to_owned()))
+                } else {
+                    None
+                }
+            }
+            other => {
+                return Err(BallistaError::General(format!(
+                    "Unsupported data type {:?} for input of aggregate expression",
+                    other
+                )))
+            }
+        }),
+        ColumnarValue::Scalar(value, _) => Ok(value.clone()),
+    }
+}
+
 impl HashAggregateIter {
     fn new(
         mode: &AggregateMode,
@@ -467,13 +703,27 @@ impl HashAggregateIter {
         group_expr: Vec<Arc<dyn Expression>>,
         aggr_expr: Vec<Arc<dyn AggregateExpr>>,
         output_schema: Arc<Schema>,
+        cancellation_token: CancellationToken,
+        memory: TaskMemory,
     ) -> Self {
         let (tx, rx): (Sender<MaybeColumnarBatch>, Receiver<MaybeColumnarBatch>) = unbounded();
 
         let mode = mode.clone();
+        let error_tx = tx.clone();
         let _ = std::thread::spawn(move || {
-            if let Err(e) = run(tx, &mode, input, group_expr, aggr_expr) {
-                println!("HashAggregateExec thread terminated with error: {:?}", e);
+            if let Err(e) = run(
+                tx,
+                &mode,
+                input,
+                group_expr,
+                aggr_expr,
+                cancellation_token,
+                memory,
+            ) {
+                error!("HashAggregateExec thread terminated with error: {:?}", e);
+                // forward the error so that the consumer sees the cause rather than a closed
+                // channel
+                let _ = error_tx.send(Err(e));
             }
         });
 
@@ -545,6 +795,7 @@ fn create_key(
 /// Create a columnar batch from the hash map
 fn create_batch_from_accum_map(
     map: &HashMap<Vec<GroupByScalar>, AccumulatorSet>,
+    mode: &AggregateMode,
     input_schema: &Schema,
     group_expr: &[Arc<dyn Expression>],
     aggr_expr: &[Arc<dyn AggregateExpr>],
@@ -585,25 +836,29 @@ fn create_batch_from_accum_map(
         arrays.push(array?);
     }
 
-    // aggregate values
+    // aggregate values, or the intermediate state of each aggregate for partial aggregation
     for i in 0..aggr_expr.len() {
-        let data_type = aggr_expr[i].data_type(&input_schema)?;
-        let array = match data_type {
-            DataType::UInt8 => extract_aggr_value!(UInt64Builder, UInt8, u64, map, i),
-            DataType::UInt16 => extract_aggr_value!(UInt64Builder, UInt16, u64, map, i),
-            DataType::UInt32 => extract_aggr_value!(UInt64Builder, UInt32, u64, map, i),
-            DataType::UInt64 => extract_aggr_value!(UInt64Builder, UInt64, u64, map, i),
-            DataType::Int8 => extract_aggr_value!(Int64Builder, Int8, i64, map, i),
-            DataType::Int16 => extract_aggr_value!(Int64Builder, Int16, i64, map, i),
-            DataType::Int32 => extract_aggr_value!(Int64Builder, Int32, i64, map, i),
-            DataType::Int64 => extract_aggr_value!(Int64Builder, Int64, i64, map, i),
-            DataType::Float32 => extract_aggr_value!(Float32Builder, Float32, f32, map, i),
-            DataType::Float64 => extract_aggr_value!(Float64Builder, Float64, f64, map, i),
-            _ => Err(BallistaError::General(
-                "Unsupported aggregate expr".to_string(),
-            )),
-        };
-        arrays.push(array?);
+        match mode {
+            AggregateMode::Partial => {
+                let fields = aggr_expr[i].state_fields(&input_schema)?;
+                let states = map
+                    .values()
+                    .map(|v| v[i].state())
+                    .collect::<Result<Vec<_>>>()?;
+                for (j, field) in fields.iter().enumerate() {
+                    let values = states.iter().map(|s| s[j].clone()).collect();
+                    arrays.push(create_aggr_array(field.data_type(), values, i)?);
+                }
+            }
+            _ => {
+                let data_type = aggr_expr[i].data_type(&input_schema)?;
+                let values = map
+                    .values()
+                    .map(|v| v[i].get_value())
+                    .collect::<Result<Vec<_>>>()?;
+                arrays.push(create_aggr_array(&data_type, values, i)?);
+            }
+        }
     }
 
     let values: Vec<ColumnarValue> = arrays
@@ -614,6 +869,45 @@ fn create_batch_from_accum_map(
     Ok(ColumnarBatch::from_values(&values))
 }
 
+/// Create an array from the values of aggregate expression `i`
+fn create_aggr_array(
+    data_type: &DataType,
+    values: Vec<Option<ScalarValue>>,
+    i: usize,
+) -> Result<ArrayRef> {
+    match data_type {
+        DataType::UInt8 => extract_aggr_value!(UInt64Builder, UInt8, u64, values, i),
+        DataType::UInt16 => extract_aggr_value!(UInt64Builder, UInt16, u64, values, i),
+        DataType::UInt32 => extract_aggr_value!(UInt64Builder, UInt32, u64, values, i),
+        DataType::UInt64 => extract_aggr_value!(UInt64Builder, UInt64, u64, values, i),
+        DataType::Int8 => extract_aggr_value!(Int64Builder, Int8, i64, values, i),
+        DataType::Int16 => extract_aggr_value!(Int64Builder, Int16, i64, values, i),
+        DataType::Int32 => extract_aggr_value!(Int64Builder, Int32, i64, values, i),
+        DataType::Int64 => extract_aggr_value!(Int64Builder, Int64, i64, values, i),
+        DataType::Float32 => extract_aggr_value!(Float32Builder, Float32, f32, values, i),
+        DataType::Float64 => extract_aggr_value!(Float64Builder, Float64, f64, values, i),
+        DataType::Utf8 => {
+            let mut builder = StringBuilder::new(values.len());
+            for v in values {
+                match v {
+                    None => builder.append_null()?,
+                    Some(ScalarValue::Utf8(s)) => builder.append_value(&s)?,
+                    Some(other) => {
+                        return Err(ballista_error(&format!(
+                            "Unexpected value {:?} for aggregate expr #{}",
+                            other, i
+                        )))
+                    }
+                }
+            }
+            Ok(Arc::new(builder.finish()) as ArrayRef)
+        }
+        _ => Err(BallistaError::General(
+            "Unsupported aggregate expr".to_string(),
+        )),
+    }
+}
+
 #[async_trait]
 impl ColumnarBatchIter for HashAggregateIter {
     fn schema(&self) -> Arc<Schema> {
@@ -0,0 +1,864 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Partitioned hash join operator. Both inputs are expected to be hash-partitioned on the join
+//! keys so that each partition can be joined independently of the others.
+//!
+//! DataFusion has no logical plan for joins, so joins are represented in logical plans by an
+//! extension node that the scheduler plans as a hash join.
+
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef, UInt32Array};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::cast_array;
+use crate::datafusion::logicalplan::{col_index, Expr, LogicalPlan, UserDefinedLogicalNode};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::operators::in_memory::InMemoryTableScanIter;
+use crate::execution::physical_plan::{
+    ColumnarBatch, ColumnarBatchStream, Distribution, ExecutionContext, ExecutionPlan, JoinType,
+    Partitioning, PhysicalPlan,
+};
+use crate::execution::spill::{SpillFile, SpillWriter};
+
+use async_trait::async_trait;
+use log::info;
+
+/// HashJoinExec joins two inputs on equality of one or more pairs of columns. The left input is
+/// loaded into a hash table (the build side) and the right input is streamed through it (the
+/// probe side), one partition at a time.
+///
+/// In a broadcast join every partition of the join reads the whole of the left input rather
+/// than a hash partition of it, so the right input does not need to be repartitioned.
+#[derive(Debug)]
+pub struct HashJoinExec {
+    pub(crate) left: Arc<PhysicalPlan>,
+    pub(crate) right: Arc<PhysicalPlan>,
+    /// Pairs of left and right column names that must be equal for rows to join
+    pub(crate) on: Vec<(String, String)>,
+    pub(crate) join_type: JoinType,
+    /// Number of partitions that both inputs are hash-partitioned into, or the number of
+    /// partitions of the right input for a broadcast join
+    pub(crate) partition_count: usize,
+    pub(crate) broadcast: bool,
+    /// Size in bytes of the left input, if known, used to decide whether to broadcast it
+    pub(crate) build_size_hint: Option<u64>,
+    schema: Arc<Schema>,
+}
+
+impl HashJoinExec {
+    pub fn try_new(
+        left: Arc<PhysicalPlan>,
+        right: Arc<PhysicalPlan>,
+        on: Vec<(String, String)>,
+        join_type: JoinType,
+        partition_count: usize,
+    ) -> Result<Self> {
+        let schema = join_schema(&left, &right, &on, &join_type)?;
+        Ok(Self {
+            left,
+            right,
+            on,
+            join_type,
+            partition_count: partition_count.max(1),
+            broadcast: false,
+            build_size_hint: None,
+            schema,
+        })
+    }
+
+    /// Change the number of partitions of the join, such as when skewed partitions of its inputs
+    /// have been split into sub-partitions
+    pub fn with_partition_count(mut self, partition_count: usize) -> Self {
+        self.partition_count = partition_count.max(1);
+        self
+    }
+
+    pub fn with_build_size_hint(mut self, bytes: u64) -> Self {
+        self.build_size_hint = Some(bytes);
+        self
+    }
+
+    /// Whether the left input can be broadcast. Rows from the left that match nothing cannot
+    /// be detected by any single partition of a broadcast join, so left and full outer joins
+    /// cannot be broadcast.
+    pub fn can_broadcast(&self) -> bool {
+        matches!(self.join_type, JoinType::Inner | JoinType::Right)
+    }
+
+    /// Create a broadcast join that reads the whole of `left` in each of the partitions of
+    /// `right`
+    pub fn to_broadcast(&self, left: Arc<PhysicalPlan>, right: Arc<PhysicalPlan>) -> Result<Self> {
+        if !self.can_broadcast() {
+            return Err(ballista_error(&format!(
+                "Cannot broadcast the left input of a {:?} join",
+                self.join_type
+            )));
+        }
+        let partition_count = right
+            .as_execution_plan()
+            .output_partitioning()
+            .partition_count();
+        Ok(HashJoinExec {
+            left,
+            right,
+            on: self.on.clone(),
+            join_type: self.join_type.clone(),
+            partition_count: partition_count.max(1),
+            broadcast: true,
+            build_size_hint: self.build_size_hint,
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Create the equivalent join with the inputs swapped, so that the right input is loaded
+    /// into the hash table instead. The columns of the right input come first in the output.
+    pub fn swap_inputs(&self) -> Result<Self> {
+        let join_type = match self.join_type {
+            JoinType::Left => JoinType::Right,
+            JoinType::Right => JoinType::Left,
+            ref other => other.clone(),
+        };
+        let on = self
+            .on
+            .iter()
+            .map(|(l, r)| (r.clone(), l.clone()))
+            .collect();
+        HashJoinExec::try_new(
+            self.right.clone(),
+            self.left.clone(),
+            on,
+            join_type,
+            self.partition_count,
+        )
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> HashJoinExec {
+        assert!(new_children.len() == 2);
+        HashJoinExec {
+            left: new_children[0].clone(),
+            right: new_children[1].clone(),
+            on: self.on.clone(),
+            join_type: self.join_type.clone(),
+            partition_count: self.partition_count,
+            broadcast: self.broadcast,
+            build_size_hint: self.build_size_hint,
+            schema: self.schema.clone(),
+        }
+    }
+
+    /// Expressions for the join keys of the left input
+    pub fn left_keys(&self) -> Result<Vec<Arc<Expr>>> {
+        let schema = self.left.as_execution_plan().schema();
+        key_exprs(&schema, self.on.iter().map(|(l, _)| l.as_str()))
+    }
+
+    /// Expressions for the join keys of the right input
+    pub fn right_keys(&self) -> Result<Vec<Arc<Expr>>> {
+        let schema = self.right.as_execution_plan().schema();
+        key_exprs(&schema, self.on.iter().map(|(_, r)| r.as_str()))
+    }
+}
+
+/// Logical join of two plans on equality of pairs of named columns, with the columns of the
+/// left plan followed by the columns of the right plan
+#[derive(Debug, Clone)]
+pub struct JoinNode {
+    pub left: LogicalPlan,
+    pub right: LogicalPlan,
+    pub on: Vec<(String, String)>,
+    pub join_type: JoinType,
+    schema: Box<Schema>,
+}
+
+impl JoinNode {
+    pub fn try_new(
+        left: LogicalPlan,
+        right: LogicalPlan,
+        on: Vec<(String, String)>,
+        join_type: JoinType,
+    ) -> Result<Self> {
+        let schema = join_schema_of(left.schema(), right.schema(), &on, &join_type)?;
+        Ok(Self {
+            left,
+            right,
+            on,
+            join_type,
+            schema: Box::new(schema),
+        })
+    }
+
+    /// Create a logical plan that joins the two plans
+    pub fn into_plan(self) -> LogicalPlan {
+        LogicalPlan::Extension {
+            node: Arc::new(self),
+        }
+    }
+
+    /// The join that a logical plan consists of, if it is a join
+    pub fn from_plan(plan: &LogicalPlan) -> Option<&JoinNode> {
+        match plan {
+            LogicalPlan::Extension { node } => node.as_any().downcast_ref::<JoinNode>(),
+            _ => None,
+        }
+    }
+}
+
+impl UserDefinedLogicalNode for JoinNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![&self.left, &self.right]
+    }
+
+    #[allow(clippy::borrowed_box)]
+    fn schema(&self) -> &Box<Schema> {
+        &self.schema
+    }
+
+    fn expressions(&self) -> Vec<Expr> {
+        // the join keys are referred to by name, which does not change when inputs are rewritten
+        vec![]
+    }
+
+    fn fmt_for_explain(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Join: type={:?}, on={:?}", self.join_type, self.on)
+    }
+
+    fn from_template(
+        &self,
+        _exprs: &Vec<Expr>,
+        inputs: &Vec<LogicalPlan>,
+    ) -> Arc<dyn UserDefinedLogicalNode + Send + Sync> {
+        assert!(inputs.len() == 2);
+        // the inputs can lose columns that nothing refers to, which changes the schema, but
+        // the join keys were checked when the join was created
+        let schema = join_schema_of(
+            inputs[0].schema(),
+            inputs[1].schema(),
+            &self.on,
+            &self.join_type,
+        )
+        .map(Box::new)
+        .unwrap_or_else(|_| self.schema.clone());
+        Arc::new(JoinNode {
+            left: inputs[0].clone(),
+            right: inputs[1].clone(),
+            on: self.on.clone(),
+            join_type: self.join_type.clone(),
+            schema,
+        })
+    }
+}
+
+/// Check the join keys of two inputs and determine the schema of the result of joining them
+pub(crate) fn join_schema(
+    left: &PhysicalPlan,
+    right: &PhysicalPlan,
+    on: &[(String, String)],
+    join_type: &JoinType,
+) -> Result<Arc<Schema>> {
+    let left_schema = left.as_execution_plan().schema();
+    let right_schema = right.as_execution_plan().schema();
+    Ok(Arc::new(join_schema_of(
+        &left_schema,
+        &right_schema,
+        on,
+        join_type,
+    )?))
+}
+
+/// Check the join keys of two schemas and determine the schema of the result of joining them
+fn join_schema_of(
+    left_schema: &Schema,
+    right_schema: &Schema,
+    on: &[(String, String)],
+    join_type: &JoinType,
+) -> Result<Schema> {
+    if on.is_empty() {
+        return Err(ballista_error("Join requires at least one join key"));
+    }
+    for (l, r) in on {
+        let l_type = left_schema.field_with_name(l)?.data_type();
+        let r_type = right_schema.field_with_name(r)?.data_type();
+        if l_type != r_type {
+            return Err(ballista_error(&format!(
+                "Join key types do not match: {} is {:?} but {} is {:?}",
+                l, l_type, r, r_type
+            )));
+        }
+    }
+
+    // rows from the side that is not preserved by an outer join can be null
+    let (left_nullable, right_nullable) = match join_type {
+        JoinType::Inner => (false, false),
+        JoinType::Left => (false, true),
+        JoinType::Right => (true, false),
+        JoinType::Full => (true, true),
+    };
+    let mut fields = join_fields(left_schema, left_nullable);
+    fields.extend(join_fields(right_schema, right_nullable));
+    Ok(Schema::new(fields))
+}
+
+fn join_fields(schema: &Schema, force_nullable: bool) -> Vec<Field> {
+    schema
+        .fields()
+        .iter()
+        .map(|f| {
+            Field::new(
+                f.name(),
+                f.data_type().clone(),
+                f.is_nullable() || force_nullable,
+            )
+        })
+        .collect()
+}
+
+fn key_exprs<'a>(schema: &Schema, names: impl Iterator<Item = &'a str>) -> Result<Vec<Arc<Expr>>> {
+    names
+        .map(|name| Ok(Arc::new(col_index(schema.index_of(name)?))))
+        .collect()
+}
+
+#[async_trait]
+impl ExecutionPlan for HashJoinExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.partition_count)
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        if self.broadcast {
+            return Distribution::BroadcastDistribution;
+        }
+        Distribution::HashClusteredDistribution {
+            required_num_partitions: self.partition_count,
+            clustering: self
+                .on
+                .iter()
+                .map(|(l, _)| Expr::UnresolvedColumn(l.clone()))
+                .collect(),
+        }
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.left.clone(), self.right.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let cancellation_token = ctx.cancellation_token();
+        let mut reservation = ctx.task_memory().reservation("HashJoinExec");
+
+        // load the left partition for as long as the memory for it can be reserved
+        let left = self.left.execute(ctx.clone(), partition_index).await?;
+        let mut left_batches = vec![];
+        let mut fits_in_memory = true;
+        while let Some(batch) = left.next().await? {
+            cancellation_token.check()?;
+            let batch = batch.to_arrow()?;
+            fits_in_memory = reservation.try_grow(build_size(&batch));
+            left_batches.push(batch);
+            if !fits_in_memory {
+                break;
+            }
+        }
+
+        let right = self.right.execute(ctx.clone(), partition_index).await?;
+        let output = if fits_in_memory {
+            let mut table = JoinTable::try_new(self, &left_batches)?;
+            let mut output = vec![];
+            while let Some(batch) = right.next().await? {
+                cancellation_token.check()?;
+                output.extend(table.probe(&batch.to_arrow()?)?);
+            }
+            output.extend(table.unmatched()?);
+            output
+        } else {
+            // partition both inputs to disk so that each part of the left input fits in memory
+            reservation.free();
+            let spill_dir = ctx.task_memory().spill_dir().to_owned();
+            let left_schema = self.left.as_execution_plan().schema();
+            let right_schema = self.right.as_execution_plan().schema();
+            let (left_keys, right_keys) = self.key_indices()?;
+            let mut left_parts = SpillPartitions::try_new(&spill_dir, &left_schema, left_keys)?;
+            for batch in &left_batches {
+                left_parts.write(batch)?;
+            }
+            drop(left_batches);
+            while let Some(batch) = left.next().await? {
+                cancellation_token.check()?;
+                left_parts.write(&batch.to_arrow()?)?;
+            }
+            let mut right_parts = SpillPartitions::try_new(&spill_dir, &right_schema, right_keys)?;
+            while let Some(batch) = right.next().await? {
+                cancellation_token.check()?;
+                right_parts.write(&batch.to_arrow()?)?;
+            }
+            let left_parts = left_parts.finish()?;
+            let right_parts = right_parts.finish()?;
+            info!(
+                "HashJoinExec spilled partition_index={} left_bytes={} right_bytes={}",
+                partition_index,
+                left_parts.iter().map(|f| f.num_bytes()).sum::<usize>(),
+                right_parts.iter().map(|f| f.num_bytes()).sum::<usize>()
+            );
+
+            // each part of the left input only joins with the same part of the right input,
+            // and a part that still does not fit in memory fails the task
+            let mut output = vec![];
+            for (left_part, right_part) in left_parts.iter().zip(right_parts.iter()) {
+                cancellation_token.check()?;
+                let batches = left_part.read()?.collect::<Result<Vec<_>>>()?;
+                reservation.grow(batches.iter().map(build_size).sum())?;
+                let mut table = JoinTable::try_new(self, &batches)?;
+                for batch in right_part.read()? {
+                    output.extend(table.probe(&batch?)?);
+                }
+                output.extend(table.unmatched()?);
+                reservation.free();
+            }
+            output
+        };
+
+        Ok(Arc::new(InMemoryTableScanIter::with_schema(
+            self.schema.clone(),
+            output.iter().map(ColumnarBatch::from_arrow).collect(),
+        )))
+    }
+}
+
+impl HashJoinExec {
+    /// Indices of the join keys in the left and right inputs
+    fn key_indices(&self) -> Result<(Vec<usize>, Vec<usize>)> {
+        let left_schema = self.left.as_execution_plan().schema();
+        let right_schema = self.right.as_execution_plan().schema();
+        let left = self
+            .on
+            .iter()
+            .map(|(l, _)| left_schema.index_of(l))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let right = self
+            .on
+            .iter()
+            .map(|(_, r)| right_schema.index_of(r))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok((left, right))
+    }
+}
+
+/// Number of parts that the inputs of a join are divided into when the left input does not fit
+/// in memory
+const SPILL_PARTITIONS: usize = 16;
+
+/// Estimated bytes of the hash table entry for each row of the left input
+const HASH_TABLE_ROW_BYTES: usize = 64;
+
+/// Bytes to reserve for a batch of the left input and its entries in the hash table
+fn build_size(batch: &RecordBatch) -> usize {
+    ColumnarBatch::from_arrow(batch).memory_size() + batch.num_rows() * HASH_TABLE_ROW_BYTES
+}
+
+/// Hash table over the rows of the left input of a join, which the batches of the right input
+/// are probed against
+struct JoinTable {
+    left_schema: Arc<Schema>,
+    right_schema: Arc<Schema>,
+    schema: Arc<Schema>,
+    right_key_indices: Vec<usize>,
+    preserve_left: bool,
+    preserve_right: bool,
+    /// The left input concatenated into a single batch so that rows can be addressed by a
+    /// single index
+    left_columns: Vec<ArrayRef>,
+    map: HashMap<Vec<JoinKeyValue>, Vec<u32>>,
+    /// Whether each row of the left input has matched a row of the right input
+    left_matched: Vec<bool>,
+}
+
+impl JoinTable {
+    fn try_new(join: &HashJoinExec, left_batches: &[RecordBatch]) -> Result<Self> {
+        let left_schema = join.left.as_execution_plan().schema();
+        let (left_key_indices, right_key_indices) = join.key_indices()?;
+        let left_columns = concat_batches(&left_schema, left_batches)?;
+        let left_rows = left_columns.first().map(|c| c.len()).unwrap_or(0);
+
+        let mut map: HashMap<Vec<JoinKeyValue>, Vec<u32>> = HashMap::new();
+        let left_keys: Vec<ArrayRef> = left_key_indices
+            .iter()
+            .filter_map(|i| left_columns.get(*i).cloned())
+            .collect();
+        for row in 0..left_rows {
+            if let Some(key) = join_key(&left_keys, row)? {
+                map.entry(key).or_insert_with(Vec::new).push(row as u32);
+            }
+        }
+
+        Ok(Self {
+            left_schema,
+            right_schema: join.right.as_execution_plan().schema(),
+            schema: join.schema.clone(),
+            right_key_indices,
+            preserve_left: matches!(join.join_type, JoinType::Left | JoinType::Full),
+            preserve_right: matches!(join.join_type, JoinType::Right | JoinType::Full),
+            left_columns,
+            map,
+            left_matched: vec![false; left_rows],
+        })
+    }
+
+    /// Join a batch of the right input with the left input
+    fn probe(&mut self, batch: &RecordBatch) -> Result<Option<RecordBatch>> {
+        let right_keys: Vec<ArrayRef> = self
+            .right_key_indices
+            .iter()
+            .map(|i| batch.column(*i).clone())
+            .collect();
+        let mut left_indices: Vec<Option<u32>> = vec![];
+        let mut right_indices: Vec<Option<u32>> = vec![];
+        for row in 0..batch.num_rows() {
+            let matches = match join_key(&right_keys, row)? {
+                Some(key) => self.map.get(&key),
+                None => None,
+            };
+            match matches {
+                Some(rows) => {
+                    for left_row in rows {
+                        self.left_matched[*left_row as usize] = true;
+                        left_indices.push(Some(*left_row));
+                        right_indices.push(Some(row as u32));
+                    }
+                }
+                None if self.preserve_right => {
+                    left_indices.push(None);
+                    right_indices.push(Some(row as u32));
+                }
+                None => {}
+            }
+        }
+        if right_indices.is_empty() {
+            return Ok(None);
+        }
+        let mut columns = take_columns(&self.left_schema, &self.left_columns, left_indices)?;
+        columns.extend(take_columns(
+            &self.right_schema,
+            batch.columns(),
+            right_indices,
+        )?);
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+
+    /// Rows from the left that did not match any row from the right, for joins that preserve
+    /// the left input
+    fn unmatched(&self) -> Result<Option<RecordBatch>> {
+        if !self.preserve_left {
+            return Ok(None);
+        }
+        let unmatched: Vec<Option<u32>> = self
+            .left_matched
+            .iter()
+            .enumerate()
+            .filter(|(_, matched)| !**matched)
+            .map(|(row, _)| Some(row as u32))
+            .collect();
+        if unmatched.is_empty() {
+            return Ok(None);
+        }
+        let nulls = vec![None; unmatched.len()];
+        let mut columns = take_columns(&self.left_schema, &self.left_columns, unmatched)?;
+        columns.extend(take_columns(&self.right_schema, &[], nulls)?);
+        Ok(Some(RecordBatch::try_new(self.schema.clone(), columns)?))
+    }
+}
+
+/// Spill files that the rows of one input of a join are divided between by the hash of their
+/// join keys
+struct SpillPartitions {
+    schema: Arc<Schema>,
+    key_indices: Vec<usize>,
+    writers: Vec<SpillWriter>,
+}
+
+impl SpillPartitions {
+    fn try_new(dir: &Path, schema: &Arc<Schema>, key_indices: Vec<usize>) -> Result<Self> {
+        let writers = (0..SPILL_PARTITIONS)
+            .map(|_| SpillWriter::try_new(dir, "hash-join", schema))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            schema: schema.clone(),
+            key_indices,
+            writers,
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let keys: Vec<ArrayRef> = self
+            .key_indices
+            .iter()
+            .map(|i| batch.column(*i).clone())
+            .collect();
+        let mut indices: Vec<Vec<Option<u32>>> = vec![vec![]; SPILL_PARTITIONS];
+        for row in 0..batch.num_rows() {
+            indices[spill_partition(&keys, row)?].push(Some(row as u32));
+        }
+        for (writer, indices) in self.writers.iter_mut().zip(indices) {
+            if !indices.is_empty() {
+                let columns = take_columns(&self.schema, batch.columns(), indices)?;
+                writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<SpillFile>> {
+        self.writers.into_iter().map(|w| w.finish()).collect()
+    }
+}
+
+/// Determine the part of a spilled join input that a row belongs to. The hash is salted so that
+/// it is independent of the hash partitioning that brought the rows to this partition of the
+/// join, which would otherwise put all of them in the same few parts. Rows with null keys
+/// never match and all go to the first part.
+fn spill_partition(keys: &[ArrayRef], row: usize) -> Result<usize> {
+    match join_key(keys, row)? {
+        Some(key) => {
+            let mut hasher = DefaultHasher::new();
+            SPILL_PARTITIONS.hash(&mut hasher);
+            key.hash(&mut hasher);
+            Ok((hasher.finish() % SPILL_PARTITIONS as u64) as usize)
+        }
+        None => Ok(0),
+    }
+}
+
+/// Concatenate batches into one array per column. Returns no columns when there are no rows.
+pub(crate) fn concat_batches(schema: &Schema, batches: &[RecordBatch]) -> Result<Vec<ArrayRef>> {
+    let batches: Vec<&RecordBatch> = batches.iter().filter(|b| b.num_rows() > 0).collect();
+    if batches.is_empty() {
+        return Ok(vec![]);
+    }
+    (0..schema.fields().len())
+        .map(|i| {
+            let arrays: Vec<ArrayRef> = batches.iter().map(|b| b.column(i).clone()).collect();
+            Ok(compute::concat(&arrays)?)
+        })
+        .collect()
+}
+
+/// Select rows from columns by index, producing nulls for indices that are `None`. Columns may
+/// be empty when there are no rows to select from, in which case all indices must be `None`.
+pub(crate) fn take_columns(
+    schema: &Schema,
+    columns: &[ArrayRef],
+    indices: Vec<Option<u32>>,
+) -> Result<Vec<ArrayRef>> {
+    if columns.is_empty() {
+        return schema
+            .fields()
+            .iter()
+            .map(|f| null_array(f.data_type(), indices.len()))
+            .collect();
+    }
+    let indices = UInt32Array::from(indices);
+    columns
+        .iter()
+        .map(|c| Ok(compute::take(c, &indices, None)?))
+        .collect()
+}
+
+macro_rules! build_null_array {
+    ($LEN:expr, $BUILDER:ident) => {{
+        let mut builder = array::$BUILDER::new($LEN);
+        for _ in 0..$LEN {
+            builder.append_null()?;
+        }
+        Ok(Arc::new(builder.finish()) as ArrayRef)
+    }};
+}
+
+/// Create an array of nulls of the given type
+fn null_array(data_type: &DataType, len: usize) -> Result<ArrayRef> {
+    match data_type {
+        DataType::Boolean => build_null_array!(len, BooleanBuilder),
+        DataType::UInt8 => build_null_array!(len, UInt8Builder),
+        DataType::UInt16 => build_null_array!(len, UInt16Builder),
+        DataType::UInt32 => build_null_array!(len, UInt32Builder),
+        DataType::UInt64 => build_null_array!(len, UInt64Builder),
+        DataType::Int8 => build_null_array!(len, Int8Builder),
+        DataType::Int16 => build_null_array!(len, Int16Builder),
+        DataType::Int32 => build_null_array!(len, Int32Builder),
+        DataType::Int64 => build_null_array!(len, Int64Builder),
+        DataType::Float32 => build_null_array!(len, Float32Builder),
+        DataType::Float64 => build_null_array!(len, Float64Builder),
+        DataType::Utf8 => build_null_array!(len, StringBuilder),
+        other => Err(BallistaError::NotImplemented(format!(
+            "Outer join with column of type {:?}",
+            other
+        ))),
+    }
+}
+
+/// Types that can be used as join keys (all primitives except for floating point numerics)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+pub(crate) enum JoinKeyValue {
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Utf8(String),
+}
+
+/// Create the join key for a row, or `None` if any of the key columns is null since null never
+/// equals anything
+pub(crate) fn join_key(keys: &[ArrayRef], row: usize) -> Result<Option<Vec<JoinKeyValue>>> {
+    let mut key = Vec::with_capacity(keys.len());
+    for col in keys {
+        if col.is_null(row) {
+            return Ok(None);
+        }
+        let value = match col.data_type() {
+            DataType::UInt8 => JoinKeyValue::UInt8(cast_array!(col, UInt8Array)?.value(row)),
+            DataType::UInt16 => JoinKeyValue::UInt16(cast_array!(col, UInt16Array)?.value(row)),
+            DataType::UInt32 => JoinKeyValue::UInt32(cast_array!(col, UInt32Array)?.value(row)),
+            DataType::UInt64 => JoinKeyValue::UInt64(cast_array!(col, UInt64Array)?.value(row)),
+            DataType::Int8 => JoinKeyValue::Int8(cast_array!(col, Int8Array)?.value(row)),
+            DataType::Int16 => JoinKeyValue::Int16(cast_array!(col, Int16Array)?.value(row)),
+            DataType::Int32 => JoinKeyValue::Int32(cast_array!(col, Int32Array)?.value(row)),
+            DataType::Int64 => JoinKeyValue::Int64(cast_array!(col, Int64Array)?.value(row)),
+            DataType::Utf8 => {
+                JoinKeyValue::Utf8(cast_array!(col, StringArray)?.value(row).to_owned())
+            }
+            other => {
+                return Err(BallistaError::NotImplemented(format!(
+                    "Join key of type {:?}",
+                    other
+                )))
+            }
+        };
+        key.push(value);
+    }
+    Ok(Some(key))
+}
+
+/// Determine the partition that a row belongs to when hash-partitioning on the given keys. Rows
+/// with null keys all go to the first partition.
+pub(crate) fn hash_partition(
+    keys: &[ArrayRef],
+    row: usize,
+    partition_count: usize,
+) -> Result<usize> {
+    match join_key(keys, row)? {
+        Some(key) => {
+            // the default hasher uses fixed keys so every executor agrees on the partition
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            Ok((hasher.finish() % partition_count as u64) as usize)
+        }
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::operators::test_utils::{collect, format_rows, int_batch, scan};
+
+    fn join(join_type: JoinType) -> Result<Vec<String>> {
+        let left = scan(&[
+            int_batch(
+                &["lk", "lv"],
+                vec![
+                    vec![Some(1), Some(2), None],
+                    vec![Some(1), Some(2), Some(3)],
+                ],
+            )?,
+            int_batch(&["lk", "lv"], vec![vec![Some(2)], vec![Some(4)]])?,
+        ]);
+        let right = scan(&[int_batch(
+            &["rk", "rv"],
+            vec![
+                vec![Some(2), Some(3), Some(2), None],
+                vec![Some(10), Some(20), Some(30), Some(40)],
+            ],
+        )?]);
+        let exec = HashJoinExec::try_new(
+            left,
+            right,
+            vec![("lk".to_owned(), "rk".to_owned())],
+            join_type,
+            1,
+        )?;
+        // the order of the joined rows is not defined
+        let mut rows = format_rows(&collect(&PhysicalPlan::HashJoin(Arc::new(exec)), 0)?)?;
+        rows.sort();
+        Ok(rows)
+    }
+
+    #[test]
+    fn inner_join_with_duplicate_keys() -> Result<()> {
+        assert_eq!(
+            vec!["2,2,2,10", "2,2,2,30", "2,4,2,10", "2,4,2,30"],
+            join(JoinType::Inner)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn outer_joins_keep_unmatched_rows() -> Result<()> {
+        assert_eq!(
+            vec![
+                "1,1,NULL,NULL",
+                "2,2,2,10",
+                "2,2,2,30",
+                "2,4,2,10",
+                "2,4,2,30",
+                "NULL,3,NULL,NULL",
+            ],
+            join(JoinType::Left)?
+        );
+        assert_eq!(
+            vec![
+                "2,2,2,10",
+                "2,2,2,30",
+                "2,4,2,10",
+                "2,4,2,30",
+                "NULL,NULL,3,20",
+                "NULL,NULL,NULL,40",
+            ],
+            join(JoinType::Right)?
+        );
+        Ok(())
+    }
+}
@@ -58,13 +58,20 @@ impl ExecutionPlan for InMemoryTableScanExec {
 
 pub struct InMemoryTableScanIter {
     index: Arc<AtomicUsize>,
+    schema: Arc<Schema>,
     data: Vec<ColumnarBatch>,
 }
 
 impl InMemoryTableScanIter {
     fn new(data: Vec<ColumnarBatch>) -> Self {
+        Self::with_schema(data[0].schema(), data)
+    }
+
+    /// Create an iterator with an explicit schema, which allows the data to be empty
+    pub(crate) fn with_schema(schema: Arc<Schema>, data: Vec<ColumnarBatch>) -> Self {
         Self {
             index: Arc::new(AtomicUsize::new(0)),
+            schema,
             data,
         }
     }
@@ -73,7 +80,7 @@ impl InMemoryTableScanIter {
 #[async_trait]
 impl ColumnarBatchIter for InMemoryTableScanIter {
     fn schema(&self) -> Arc<Schema> {
-        self.data[0].schema()
+        self.schema.clone()
     }
 
     async fn next(&self) -> Result<Option<ColumnarBatch>> {
@@ -0,0 +1,251 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Arrow IPC scan operator, for files in the IPC file format (also known as Feather version 2)
+//! and in the IPC stream format. Files already contain Arrow record batches and their schema,
+//! so scans only need to read them, and local files are memory-mapped rather than read.
+
+use std::fs::File;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::{Schema, SchemaRef};
+use crate::arrow::ipc::reader::{FileReader, StreamReader};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::{
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext,
+    ExecutionPlan, Partitioning,
+};
+use crate::object_store;
+
+use async_trait::async_trait;
+use memmap::Mmap;
+
+/// Schema name of logical table scans that read Arrow IPC files, where the table name is the
+/// path of the files
+pub const ARROW_SCHEMA_NAME: &str = "arrow";
+
+/// Extensions of the files that are read from a directory
+pub const ARROW_FILE_EXTENSIONS: &[&str] = &[".arrow", ".feather", ".arrows"];
+
+/// Bytes at the start of files in the IPC file format, which files in the stream format lack
+const FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// Execution plan for scanning Arrow IPC files, with one partition per file
+pub struct IpcScanExec {
+    /// Path to a file or to a directory containing files with the same schema
+    pub(crate) path: String,
+    /// Individual files
+    pub(crate) filenames: Vec<String>,
+    /// Schema of the files
+    schema: SchemaRef,
+    /// Optional projection for which columns to load
+    pub(crate) projection: Option<Vec<usize>>,
+    /// Schema after the projection has been applied
+    projected_schema: SchemaRef,
+}
+
+impl IpcScanExec {
+    /// Create a new execution plan for reading a set of Arrow IPC files
+    pub fn try_new(path: &str, projection: Option<Vec<usize>>) -> Result<Self> {
+        let filenames = list_ipc_files(path)?;
+        let schema = open_ipc_file(&filenames[0])?.schema();
+        let projected_schema = match &projection {
+            None => schema.clone(),
+            Some(p) => Arc::new(Schema::new(
+                p.iter().map(|i| schema.field(*i).clone()).collect(),
+            )),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            filenames,
+            schema,
+            projection,
+            projected_schema,
+        })
+    }
+
+    /// Read a different set of columns, given as indices into the file schema
+    pub fn with_projection(&self, projection: Vec<usize>) -> Self {
+        let projected_schema = Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        );
+        Self {
+            path: self.path.clone(),
+            filenames: self.filenames.clone(),
+            schema: self.schema.clone(),
+            projection: Some(projection),
+            projected_schema: Arc::new(projected_schema),
+        }
+    }
+}
+
+/// Get the schema of an Arrow IPC data source from its first file
+pub fn ipc_table_schema(path: &str) -> Result<Schema> {
+    let filenames = list_ipc_files(path)?;
+    Ok(open_ipc_file(&filenames[0])?.schema().as_ref().clone())
+}
+
+#[async_trait]
+impl ExecutionPlan for IpcScanExec {
+    fn schema(&self) -> SchemaRef {
+        self.projected_schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.filenames.len())
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let reader = open_ipc_file(&self.filenames[partition_index])?;
+        Ok(Arc::new(IpcBatchIter {
+            reader: Arc::new(Mutex::new(reader)),
+            projection: self.projection.clone(),
+            schema: self.projected_schema.clone(),
+            cancellation_token: ctx.cancellation_token(),
+        }))
+    }
+}
+
+struct IpcBatchIter {
+    /// Arrow IPC file or stream reader
+    reader: Arc<Mutex<Box<dyn RecordBatchReader + Send>>>,
+    projection: Option<Vec<usize>>,
+    /// Schema after the projection has been applied
+    schema: SchemaRef,
+    /// Stop reading when the task is cancelled
+    cancellation_token: CancellationToken,
+}
+
+#[async_trait]
+impl ColumnarBatchIter for IpcBatchIter {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        self.cancellation_token.check()?;
+        let mut reader = self.reader.lock().expect("failed to lock mutex");
+        match reader.next() {
+            Some(batch) => {
+                let batch = batch?;
+                let batch = match &self.projection {
+                    Some(p) => RecordBatch::try_new(
+                        self.schema.clone(),
+                        p.iter().map(|i| batch.column(*i).clone()).collect(),
+                    )?,
+                    None => batch,
+                };
+                Ok(Some(ColumnarBatch::from_arrow(&batch)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// The contents of a file, which are memory-mapped for local files
+enum IpcData {
+    Mapped(Mmap),
+    Fetched(Vec<u8>),
+}
+
+impl AsRef<[u8]> for IpcData {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            IpcData::Mapped(mmap) => mmap.as_ref(),
+            IpcData::Fetched(data) => data.as_ref(),
+        }
+    }
+}
+
+fn list_ipc_files(path: &str) -> Result<Vec<String>> {
+    let filenames: Vec<String> = object_store::list_files(path, "")?
+        .into_iter()
+        .filter(|f| ARROW_FILE_EXTENSIONS.iter().any(|ext| f.ends_with(ext)))
+        .collect();
+    if filenames.is_empty() {
+        return Err(ballista_error(&format!(
+            "No Arrow IPC files found in {}",
+            path
+        )));
+    }
+    Ok(filenames)
+}
+
+/// Open a file in either the IPC file format or the IPC stream format
+fn open_ipc_file(filename: &str) -> Result<Box<dyn RecordBatchReader + Send>> {
+    let data = if object_store::is_remote(filename) {
+        IpcData::Fetched(object_store::object_store(filename)?.read(filename)?)
+    } else {
+        let file = File::open(filename)?;
+        // the file must not be modified while it is mapped, which holds for the immutable
+        // datasets that scans read
+        IpcData::Mapped(unsafe { Mmap::map(&file)? })
+    };
+    let is_file_format = data.as_ref().starts_with(FILE_MAGIC);
+    let cursor = Cursor::new(data);
+    if is_file_format {
+        Ok(Box::new(FileReader::try_new(cursor)?))
+    } else {
+        Ok(Box::new(StreamReader::try_new(cursor)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field};
+    use crate::arrow::ipc::writer::{FileWriter, StreamWriter};
+    use std::fs;
+
+    #[test]
+    fn read_file_and_stream_formats() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("a", DataType::Int32, false)]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema.clone()),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+
+        let dir = std::env::temp_dir().join(format!("ballista-ipc-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        let mut writer = FileWriter::try_new(File::create(dir.join("a.arrow"))?, &schema)?;
+        writer.write(&batch)?;
+        writer.finish()?;
+        let mut writer = StreamWriter::try_new(File::create(dir.join("b.arrows"))?, &schema)?;
+        writer.write(&batch)?;
+        writer.finish()?;
+
+        let filenames = list_ipc_files(dir.to_str().unwrap())?;
+        assert_eq!(2, filenames.len());
+        for filename in &filenames {
+            let mut reader = open_ipc_file(filename)?;
+            assert_eq!(schema, *reader.schema());
+            let batch = reader.next().unwrap()?;
+            assert_eq!(3, batch.num_rows());
+            assert!(reader.next().is_none());
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
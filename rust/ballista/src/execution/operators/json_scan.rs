@@ -0,0 +1,279 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Newline-delimited JSON scan operator. Files are divided into byte ranges that are read as
+//! separate partitions, where each range reads the lines that start within it.
+
+use std::io::{BufReader, Cursor};
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::datatypes::{Schema, SchemaRef};
+use crate::arrow::json;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, Result};
+use crate::execution::operators::file_splits::{
+    create_splits, next_record_start, FileSplit, DEFAULT_SPLIT_SIZE,
+};
+use crate::execution::physical_plan::{
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext,
+    ExecutionPlan, Partitioning,
+};
+use crate::object_store;
+
+use async_trait::async_trait;
+
+/// Schema name of logical table scans that read newline-delimited JSON files, where the table
+/// name is the path of the files
+pub const JSON_SCHEMA_NAME: &str = "ndjson";
+
+/// Extension of the files that are read from a directory
+pub const JSON_FILE_EXTENSION: &str = ".json";
+
+/// Options for reading newline-delimited JSON files, mirroring `CsvReadOptions`
+#[derive(Debug, Clone)]
+pub struct JsonReadOptions<'a> {
+    /// The schema of the files. When this is not provided, the schema is inferred from the
+    /// first records of the first file.
+    pub schema: Option<&'a Schema>,
+    /// Maximum number of records to read when inferring the schema
+    pub schema_infer_max_records: usize,
+}
+
+impl<'a> JsonReadOptions<'a> {
+    pub fn new() -> Self {
+        Self {
+            schema: None,
+            schema_infer_max_records: 1000,
+        }
+    }
+
+    /// Specify the schema rather than inferring it
+    pub fn schema(mut self, schema: &'a Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Specify the number of records to read when inferring the schema
+    pub fn schema_infer_max_records(mut self, max_records: usize) -> Self {
+        self.schema_infer_max_records = max_records;
+        self
+    }
+}
+
+impl<'a> Default for JsonReadOptions<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Execution plan for scanning newline-delimited JSON files
+pub struct JsonScanExec {
+    /// Path to a file or to a directory containing files with the same schema
+    pub(crate) path: String,
+    /// Byte ranges of the files, one per partition
+    pub(crate) splits: Vec<FileSplit>,
+    /// Schema of the files
+    pub(crate) schema: SchemaRef,
+    /// Optional projection for which columns to load
+    pub(crate) projection: Option<Vec<usize>>,
+    /// Schema after the projection has been applied
+    projected_schema: SchemaRef,
+    /// Batch size
+    pub(crate) batch_size: usize,
+}
+
+impl JsonScanExec {
+    /// Create a new execution plan for reading a set of JSON files with a known schema
+    pub fn try_new(
+        path: &str,
+        schema: SchemaRef,
+        projection: Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> Result<Self> {
+        let splits = create_splits(path, JSON_FILE_EXTENSION, DEFAULT_SPLIT_SIZE)?;
+        let projected_schema = match &projection {
+            None => schema.clone(),
+            Some(p) => Arc::new(Schema::new(
+                p.iter().map(|i| schema.field(*i).clone()).collect(),
+            )),
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            splits,
+            schema,
+            projection,
+            projected_schema,
+            batch_size,
+        })
+    }
+
+    /// Read a different set of columns, given as indices into the file schema
+    pub fn with_projection(&self, projection: Vec<usize>) -> Self {
+        let projected_schema = Schema::new(
+            projection
+                .iter()
+                .map(|i| self.schema.field(*i).clone())
+                .collect(),
+        );
+        Self {
+            path: self.path.clone(),
+            splits: self.splits.clone(),
+            schema: self.schema.clone(),
+            projection: Some(projection),
+            projected_schema: Arc::new(projected_schema),
+            batch_size: self.batch_size,
+        }
+    }
+
+    /// Infer the schema of a JSON data source from the first records of its first file
+    pub fn try_infer_schema(path: &str, options: &JsonReadOptions) -> Result<Schema> {
+        let split = create_splits(path, JSON_FILE_EXTENSION, DEFAULT_SPLIT_SIZE)?.remove(0);
+        let mut reader = BufReader::new(Cursor::new(read_split(&split)?));
+        let schema =
+            json::reader::infer_json_schema(&mut reader, Some(options.schema_infer_max_records))?;
+        Ok(schema.as_ref().clone())
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for JsonScanExec {
+    fn schema(&self) -> SchemaRef {
+        self.projected_schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.splits.len())
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        Ok(Arc::new(JsonBatchIter::try_new(
+            &self.splits[partition_index],
+            self.schema.clone(),
+            self.projected_schema.clone(),
+            self.batch_size,
+            ctx.cancellation_token(),
+        )?))
+    }
+}
+
+struct JsonBatchIter {
+    /// Arrow JSON reader
+    reader: Arc<Mutex<json::Reader<Cursor<Vec<u8>>>>>,
+    /// Schema after the projection has been applied
+    schema: SchemaRef,
+    /// Stop reading when the task is cancelled
+    cancellation_token: CancellationToken,
+}
+
+impl JsonBatchIter {
+    fn try_new(
+        split: &FileSplit,
+        schema: SchemaRef,
+        projected_schema: SchemaRef,
+        batch_size: usize,
+        cancellation_token: CancellationToken,
+    ) -> Result<Self> {
+        let data = read_split(split)?;
+        let projection = projected_schema
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        let reader = json::Reader::new(
+            BufReader::new(Cursor::new(data)),
+            schema,
+            batch_size,
+            Some(projection),
+        );
+        Ok(Self {
+            reader: Arc::new(Mutex::new(reader)),
+            schema: projected_schema,
+            cancellation_token,
+        })
+    }
+}
+
+#[async_trait]
+impl ColumnarBatchIter for JsonBatchIter {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        self.cancellation_token.check()?;
+        let mut reader = self.reader.lock().expect("failed to lock mutex");
+        match reader.next() {
+            Ok(Some(batch)) => {
+                // the reader returns the projected columns in the order of the file schema
+                let columns = self
+                    .schema
+                    .fields()
+                    .iter()
+                    .map(|f| Ok(batch.column(batch.schema().index_of(f.name())?).clone()))
+                    .collect::<Result<Vec<_>>>()?;
+                let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+                Ok(Some(ColumnarBatch::from_arrow(&batch)))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(ballista_error(&format!(
+                "Error reading JSON: {}",
+                e.to_string()
+            ))),
+        }
+    }
+}
+
+/// Read the lines that start within a split
+fn read_split(split: &FileSplit) -> Result<Vec<u8>> {
+    let store = object_store::object_store(&split.filename)?;
+    let line_start = |pos: u64| match pos {
+        0 => Ok(0),
+        _ => next_record_start(store.as_ref(), split, pos, b"\n"),
+    };
+    let begin = line_start(split.start)?;
+    let finish = line_start(split.end)?;
+    if finish <= begin {
+        return Ok(vec![]);
+    }
+    store.read_range(&split.filename, begin, (finish - begin) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn split_lines() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("ballista-json-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        let filename = dir.join("data.json");
+        fs::write(&filename, "{\"a\":1}\n{\"a\":22}\n{\"a\":333}\n")?;
+
+        let splits = create_splits(dir.to_str().unwrap(), JSON_FILE_EXTENSION, 10)?;
+        assert_eq!(3, splits.len());
+        let lines = splits
+            .iter()
+            .map(|split| Ok(String::from_utf8(read_split(split)?).unwrap()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(vec!["{\"a\":1}\n{\"a\":22}\n", "{\"a\":333}\n", ""], lines);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
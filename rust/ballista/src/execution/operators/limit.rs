@@ -0,0 +1,224 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limit operators. A local limit runs against each partition in parallel so that no partition
+//! produces more rows than are needed, and a global limit applies the limit to the single
+//! partition that the results of the local limits are merged into.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::arrow::array::Array;
+use crate::arrow::datatypes::Schema;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::Result;
+use crate::execution::physical_plan::{
+    ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, Distribution, ExecutionContext,
+    ExecutionPlan, Partitioning, PhysicalPlan,
+};
+
+use async_trait::async_trait;
+
+/// GlobalLimitExec produces at most `limit` rows from the single partition of its input.
+#[derive(Debug, Clone)]
+pub struct GlobalLimitExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    pub(crate) limit: usize,
+}
+
+impl GlobalLimitExec {
+    pub fn new(child: Arc<PhysicalPlan>, limit: usize) -> Self {
+        Self { child, limit }
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> GlobalLimitExec {
+        assert!(new_children.len() == 1);
+        GlobalLimitExec {
+            child: new_children[0].clone(),
+            limit: self.limit,
+        }
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for GlobalLimitExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.child.as_execution_plan().schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        Distribution::SinglePartition
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        Ok(Arc::new(LimitIter::new(
+            self.child.execute(ctx, partition_index).await?,
+            self.limit,
+        )))
+    }
+}
+
+/// LocalLimitExec produces at most `limit` rows from each partition of its input.
+#[derive(Debug, Clone)]
+pub struct LocalLimitExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    pub(crate) limit: usize,
+}
+
+impl LocalLimitExec {
+    pub fn new(child: Arc<PhysicalPlan>, limit: usize) -> Self {
+        Self { child, limit }
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> LocalLimitExec {
+        assert!(new_children.len() == 1);
+        LocalLimitExec {
+            child: new_children[0].clone(),
+            limit: self.limit,
+        }
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for LocalLimitExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.child.as_execution_plan().schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.child.as_execution_plan().output_partitioning()
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        Ok(Arc::new(LimitIter::new(
+            self.child.execute(ctx, partition_index).await?,
+            self.limit,
+        )))
+    }
+}
+
+/// Iterator that stops reading its input once the limit has been reached
+struct LimitIter {
+    input: ColumnarBatchStream,
+    remaining: AtomicUsize,
+}
+
+impl LimitIter {
+    fn new(input: ColumnarBatchStream, limit: usize) -> Self {
+        Self {
+            input,
+            remaining: AtomicUsize::new(limit),
+        }
+    }
+}
+
+#[async_trait]
+impl ColumnarBatchIter for LimitIter {
+    fn schema(&self) -> Arc<Schema> {
+        self.input.schema()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        let remaining = self.remaining.load(Ordering::SeqCst);
+        if remaining == 0 {
+            return Ok(None);
+        }
+        match self.input.next().await? {
+            Some(batch) if batch.num_rows() <= remaining => {
+                self.remaining
+                    .store(remaining - batch.num_rows(), Ordering::SeqCst);
+                Ok(Some(batch))
+            }
+            Some(batch) => {
+                self.remaining.store(0, Ordering::SeqCst);
+                let batch = batch.to_arrow()?;
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|c| c.slice(0, remaining))
+                    .collect();
+                let batch = RecordBatch::try_new(batch.schema(), columns)?;
+                Ok(Some(ColumnarBatch::from_arrow(&batch)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::operators::test_utils::{collect, format_rows, int_batch, scan};
+
+    fn input() -> Result<Arc<PhysicalPlan>> {
+        Ok(scan(&[
+            int_batch(&["a"], vec![vec![Some(1), Some(2), Some(3)]])?,
+            int_batch(&["a"], vec![vec![Some(4), Some(5), Some(6)]])?,
+        ]))
+    }
+
+    #[test]
+    fn batch_straddling_limit_is_sliced() -> Result<()> {
+        let plans = vec![
+            PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(input()?, 4))),
+            PhysicalPlan::LocalLimit(Arc::new(LocalLimitExec::new(input()?, 4))),
+        ];
+        for plan in plans {
+            let batches = collect(&plan, 0)?;
+            let num_rows: Vec<usize> = batches.iter().map(|b| b.num_rows()).collect();
+            assert_eq!(vec![3, 1], num_rows);
+            assert_eq!(vec!["1", "2", "3", "4"], format_rows(&batches)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn limit_at_batch_boundary() -> Result<()> {
+        let plan = PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(input()?, 3)));
+        assert_eq!(vec!["1", "2", "3"], format_rows(&collect(&plan, 0)?)?);
+
+        let plan = PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(input()?, 10)));
+        assert_eq!(6, format_rows(&collect(&plan, 0)?)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn limit_zero() -> Result<()> {
+        let plan = PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(input()?, 0)));
+        assert!(collect(&plan, 0)?.is_empty());
+        let plan = PhysicalPlan::LocalLimit(Arc::new(LocalLimitExec::new(input()?, 0)));
+        assert!(collect(&plan, 0)?.is_empty());
+        Ok(())
+    }
+}
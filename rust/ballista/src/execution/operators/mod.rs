@@ -15,20 +15,47 @@
 //! Relational operators that can be used in query plans. Relational operators represent concepts
 //! such as projection, selection, aggregate, and join, and transform streams of data.
 
-pub use csv_scan::CsvScanExec;
+pub use avro_scan::{avro_table_schema, AvroScanExec, AVRO_SCHEMA_NAME};
+pub use csv_scan::{CsvCompression, CsvFormatOptions, CsvScanExec, MalformedRowPolicy};
+pub use file_partitions::PartitionedFiles;
 pub use filter::FilterExec;
 pub use hash_aggregate::HashAggregateExec;
+pub use hash_join::{HashJoinExec, JoinNode};
 pub use in_memory::InMemoryTableScanExec;
-pub use parquet_scan::ParquetScanExec;
+pub use ipc_scan::{ipc_table_schema, IpcScanExec, ARROW_SCHEMA_NAME};
+pub use json_scan::{JsonReadOptions, JsonScanExec, JSON_SCHEMA_NAME};
+pub use limit::{GlobalLimitExec, LocalLimitExec};
+pub use parquet_scan::{parquet_table_schema, ParquetScanExec};
 pub use projection::ProjectionExec;
+pub use repartition::RepartitionExec;
 pub use shuffle_exchange::ShuffleExchangeExec;
-pub use shuffle_reader::ShuffleReaderExec;
+pub use shuffle_reader::{hash_partitions, ShuffleReaderExec};
+pub use sort::{SortExec, TopKExec};
+pub use sort_merge_join::SortMergeJoinExec;
+pub use union::{UnionExec, UnionNode};
+pub use window::{WindowExec, WindowExpr, WindowFrame, WindowFunction};
+pub use write::{WriteExec, WriteFormat, WriteSummary, WrittenFile};
 
+mod avro_scan;
 mod csv_scan;
+mod file_partitions;
+mod file_splits;
 mod filter;
 mod hash_aggregate;
+mod hash_join;
 mod in_memory;
+mod ipc_scan;
+mod json_scan;
+mod limit;
 mod parquet_scan;
 mod projection;
+mod repartition;
 mod shuffle_exchange;
 mod shuffle_reader;
+mod sort;
+mod sort_merge_join;
+#[cfg(test)]
+mod test_utils;
+mod union;
+mod window;
+mod write;
@@ -14,25 +14,43 @@
 
 //! Parquet scan operator.
 
+use std::cmp::Ordering;
 use std::fs::File;
+use std::io::Cursor;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
 
 use crate::error::{BallistaError, Result};
+use crate::execution::expressions::{
+    decode_predicate, BETWEEN_FUNCTION_NAME, IN_LIST_FUNCTION_NAME,
+};
+use crate::execution::operators::file_partitions::{partition_array, PartitionedFiles};
 use crate::execution::physical_plan::{
-    ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext, ExecutionPlan,
-    MaybeColumnarBatch, Partitioning,
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext,
+    ExecutionPlan, MaybeColumnarBatch, Partitioning,
 };
+use crate::execution::statistics::{compare_scalars, ColumnStatistics, Statistics};
 
-use crate::arrow::datatypes::Schema;
-use crate::arrow::record_batch::RecordBatchReader;
-use crate::datafusion::execution::physical_plan::common;
+use crate::arrow::array::ArrayRef;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::record_batch::{RecordBatch, RecordBatchReader};
+use crate::datafusion::logicalplan::{Expr, Operator, ScalarValue};
+use crate::object_store::{self, ObjectStore};
 use crate::parquet::arrow::arrow_reader::ArrowReader;
 use crate::parquet::arrow::ParquetFileArrowReader;
-use crate::parquet::file::reader::SerializedFileReader;
+use crate::parquet::errors::{ParquetError, Result as ParquetResult};
+use crate::parquet::file::metadata::{ParquetMetaData, RowGroupMetaData};
+use crate::parquet::file::reader::{
+    ChunkReader, FileReader, Length, RowGroupReader, SerializedFileReader,
+};
+use crate::parquet::file::statistics::Statistics as ParquetStatistics;
+use crate::parquet::record::reader::RowIter;
+use crate::parquet::schema::types::Type as SchemaType;
 
 use async_trait::async_trait;
 use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::debug;
 use smol::Task;
 use std::time::Instant;
 
@@ -40,25 +58,33 @@ use std::time::Instant;
 /// columns are loaded into memory. The partitioning scheme is currently rather simplistic with a
 /// one to one mapping of filename to partition. Also, there is currently no support for schema
 /// merging, so all partitions must have the same schema.
+///
+/// An optional predicate, referring to the columns of the output schema, is evaluated against
+/// the min/max statistics of each row group so that row groups that cannot contain matching rows
+/// are not read. The predicate is not applied to individual rows, so the scan is still followed
+/// by a filter.
+///
+/// Files in hive-style `key=value` directories have partition columns, which follow the columns
+/// of the files in the schema. Files whose partition values cannot match the predicate are not
+/// scanned at all.
 #[derive(Debug, Clone)]
 pub struct ParquetScanExec {
     pub(crate) path: String,
     pub(crate) filenames: Vec<String>,
+    /// Values of the partition columns of each file
+    pub(crate) partition_values: Vec<Vec<ScalarValue>>,
     pub(crate) projection: Option<Vec<usize>>,
+    /// Schema of the files followed by the partition columns
     pub(crate) parquet_schema: Arc<Schema>,
     pub(crate) output_schema: Arc<Schema>,
     pub(crate) batch_size: usize,
+    pub(crate) predicate: Option<Expr>,
 }
 
 impl ParquetScanExec {
     pub fn try_new(path: &str, projection: Option<Vec<usize>>, batch_size: usize) -> Result<Self> {
-        let mut filenames: Vec<String> = vec![];
-        common::build_file_list(path, &mut filenames, ".parquet")?;
-        let filename = &filenames[0];
-        let file = File::open(filename)?;
-        let file_reader = Rc::new(SerializedFileReader::new(file).unwrap()); //TODO error handling
-        let mut arrow_reader = ParquetFileArrowReader::new(file_reader);
-        let schema = arrow_reader.get_schema().unwrap(); //TODO error handling
+        let files = PartitionedFiles::try_new(path, ".parquet")?;
+        let schema = files.schema(&parquet_file_schema(&files.filenames[0])?);
 
         let projected_fields = match &projection {
             Some(p) => p.clone(),
@@ -74,13 +100,147 @@ impl ParquetScanExec {
 
         Ok(Self {
             path: path.to_owned(),
-            filenames,
+            filenames: files.filenames,
+            partition_values: files.values,
             projection,
             parquet_schema: Arc::new(schema),
             output_schema: Arc::new(projected_schema),
             batch_size,
+            predicate: None,
         })
     }
+
+    /// Skip files whose partition values, and row groups whose statistics, show that they
+    /// contain no rows matching the predicate
+    pub fn with_predicate(mut self, predicate: Expr) -> Self {
+        let num_file_columns = self.parquet_schema.fields().len() - self.num_partition_columns();
+        let projection: Vec<usize> = match &self.projection {
+            Some(p) => p.clone(),
+            None => (0..self.parquet_schema.fields().len()).collect(),
+        };
+        let (filenames, partition_values): (Vec<_>, Vec<_>) = self
+            .filenames
+            .iter()
+            .cloned()
+            .zip(self.partition_values.iter().cloned())
+            .filter(|(_, values)| {
+                let stats = |column: &Expr| {
+                    let index = match column {
+                        Expr::Column(i) => projection.get(*i).cloned(),
+                        Expr::UnresolvedColumn(name) => self.parquet_schema.index_of(name).ok(),
+                        _ => None,
+                    }?;
+                    match values.get(index.checked_sub(num_file_columns)?)? {
+                        ScalarValue::Null => None,
+                        value => Some((value.clone(), value.clone())),
+                    }
+                };
+                may_match(&predicate, &stats)
+            })
+            .unzip();
+        debug!(
+            "ParquetScan reading {} of {} files in {}",
+            filenames.len(),
+            self.filenames.len(),
+            self.path
+        );
+        // keep one file when every file is pruned so that the scan still has a schema and a
+        // partition to execute
+        if !filenames.is_empty() {
+            self.filenames = filenames;
+            self.partition_values = partition_values;
+        } else {
+            self.filenames.truncate(1);
+            self.partition_values.truncate(1);
+        }
+        self.predicate = Some(predicate);
+        self
+    }
+
+    /// Statistics of the projected columns of the files that the scan reads, from the row
+    /// counts and column statistics in the Parquet metadata. The metadata has no distinct
+    /// counts, and min and max values are only available for columns of primitive types.
+    pub fn statistics(&self) -> Result<Statistics> {
+        let num_file_columns = self.parquet_schema.fields().len() - self.num_partition_columns();
+        let mut stats: Option<Statistics> = None;
+        for (filename, values) in self.filenames.iter().zip(&self.partition_values) {
+            let reader = open_parquet_file(filename)?;
+            let metadata = reader.metadata();
+            let flat = metadata.file_metadata().schema_descr().num_columns() == num_file_columns;
+            for row_group in metadata.row_groups() {
+                let num_rows = row_group.num_rows() as u64;
+                let mut columns = Vec::with_capacity(self.parquet_schema.fields().len());
+                for i in 0..num_file_columns {
+                    if !flat {
+                        columns.push(ColumnStatistics::default());
+                        continue;
+                    }
+                    let data_type = self.parquet_schema.field(i).data_type();
+                    let (min_value, max_value) = match min_max(row_group, i, data_type) {
+                        Some((min, max)) => (Some(min), Some(max)),
+                        None => (None, None),
+                    };
+                    columns.push(ColumnStatistics {
+                        null_count: row_group.column(i).statistics().map(|s| s.null_count()),
+                        min_value,
+                        max_value,
+                        distinct_count: None,
+                    });
+                }
+                // every row of a file has the same value in each partition column
+                columns.extend(values.iter().map(|value| match value {
+                    ScalarValue::Null => ColumnStatistics {
+                        null_count: Some(num_rows),
+                        ..ColumnStatistics::default()
+                    },
+                    value => ColumnStatistics {
+                        null_count: Some(0),
+                        min_value: Some(value.clone()),
+                        max_value: Some(value.clone()),
+                        distinct_count: None,
+                    },
+                }));
+                let row_group_stats = Statistics {
+                    num_rows: Some(num_rows),
+                    total_bytes: Some(row_group.total_byte_size() as u64),
+                    columns,
+                };
+                stats = Some(match stats {
+                    Some(stats) => stats.merge(&row_group_stats),
+                    None => row_group_stats,
+                });
+            }
+        }
+        let stats = stats.unwrap_or_else(|| Statistics {
+            num_rows: Some(0),
+            total_bytes: Some(0),
+            ..Statistics::unknown(self.parquet_schema.fields().len())
+        });
+        Ok(match &self.projection {
+            Some(projection) => stats.project(projection),
+            None => stats,
+        })
+    }
+
+    fn num_partition_columns(&self) -> usize {
+        self.partition_values.first().map(|v| v.len()).unwrap_or(0)
+    }
+
+    /// Read a different set of columns, given as indices into the Parquet schema. Any predicate
+    /// must already refer to the columns of the new projection.
+    pub fn with_projection(&self, projection: Vec<usize>) -> Self {
+        let output_schema = Schema::new(
+            projection
+                .iter()
+                .map(|i| self.parquet_schema.field(*i).clone())
+                .collect(),
+        );
+        Self {
+            projection: Some(projection),
+            output_schema: Arc::new(output_schema),
+            ..self.clone()
+        }
+    }
 }
 
 #[async_trait]
@@ -97,13 +257,18 @@ impl ExecutionPlan for ParquetScanExec {
 
     async fn execute(
         &self,
-        _ctx: Arc<dyn ExecutionContext>,
+        ctx: Arc<dyn ExecutionContext>,
         partition_index: usize,
     ) -> Result<ColumnarBatchStream> {
         Ok(Arc::new(ParquetBatchIter::try_new(
             &self.filenames[partition_index],
+            self.parquet_schema.clone(),
+            self.partition_values[partition_index].clone(),
             self.projection.clone(),
+            self.predicate.clone(),
             self.batch_size,
+            ctx.parallelism(),
+            ctx.cancellation_token(),
         )?))
     }
 }
@@ -115,109 +280,239 @@ pub struct ParquetBatchIter {
 
 #[allow(dead_code)]
 impl ParquetBatchIter {
+    /// Read a file with the given partition values, where the projection refers to the columns
+    /// of the table schema, which is the schema of the file followed by the partition columns.
+    /// The row groups of the file are split between up to `parallelism` threads.
+    #[allow(clippy::too_many_arguments)]
     pub fn try_new(
         filename: &str,
+        table_schema: Arc<Schema>,
+        partition_values: Vec<ScalarValue>,
         projection: Option<Vec<usize>>,
+        predicate: Option<Expr>,
         batch_size: usize,
+        parallelism: usize,
+        cancellation_token: CancellationToken,
     ) -> Result<Self> {
-        let file = File::open(filename)?;
-        let file_reader = Rc::new(SerializedFileReader::new(file).unwrap()); //TODO error handling
-        let mut arrow_reader = ParquetFileArrowReader::new(file_reader);
-        let schema = arrow_reader.get_schema().unwrap(); //TODO error handling
+        let schema = parquet_file_schema(filename)?;
+        let num_file_columns = schema.fields().len();
 
         let projection = match projection {
             Some(p) => p,
-            None => (0..schema.fields().len()).collect(),
+            None => (0..table_schema.fields().len()).collect(),
         };
 
-        let projected_schema = Schema::new(
+        let projected_schema = Arc::new(Schema::new(
             projection
                 .iter()
-                .map(|i| schema.field(*i).clone())
+                .map(|i| table_schema.field(*i).clone())
                 .collect(),
-        );
+        ));
+
+        // the columns to read from the file, which must include at least one column to know the
+        // number of rows when only partition columns are projected
+        let mut file_projection: Vec<usize> = projection
+            .iter()
+            .cloned()
+            .filter(|i| *i < num_file_columns)
+            .collect();
+        if file_projection.is_empty() {
+            file_projection.push(0);
+        }
+
+        // there is no point in starting more threads than there are row groups
+        let num_threads = if parallelism > 1 {
+            parallelism
+                .min(open_parquet_file(filename)?.num_row_groups())
+                .max(1)
+        } else {
+            1
+        };
 
         let (response_tx, response_rx): (Sender<MaybeColumnarBatch>, Receiver<MaybeColumnarBatch>) =
             unbounded();
 
-        let filename = filename.to_string();
-
-        std::thread::spawn(move || {
-            let start = Instant::now();
-            let mut batch_read_time = 0;
-            let mut total_bytes_read = 0;
-            let mut output_batches = 0;
-            let mut output_rows = 0;
-
-            //TODO error handling, remove unwraps
-            let file = File::open(&filename).unwrap();
-            match SerializedFileReader::new(file) {
-                Ok(file_reader) => {
-                    let file_reader = Rc::new(file_reader);
-                    let mut arrow_reader = ParquetFileArrowReader::new(file_reader);
-                    match arrow_reader.get_record_reader_by_columns(projection, batch_size) {
-                        Ok(mut batch_reader) => loop {
-                            // read the next batch
-                            let start_batch = Instant::now();
-                            let maybe_batch = batch_reader.next_batch();
-                            batch_read_time += start_batch.elapsed().as_millis();
-
-                            match maybe_batch {
-                                Ok(Some(batch)) => {
-                                    output_batches += 1;
-                                    output_rows += batch.num_rows();
-
-                                    let columnar_batch = ColumnarBatch::from_arrow(&batch);
-                                    println!(
-                                        "ParquetScanExec read batch containing {} bytes",
-                                        columnar_batch.memory_size()
-                                    );
-                                    total_bytes_read += columnar_batch.memory_size();
-
-                                    response_tx.send(Ok(Some(columnar_batch))).unwrap();
-                                }
-                                Ok(None) => {
-                                    response_tx.send(Ok(None)).unwrap();
-                                    break;
-                                }
-                                Err(e) => {
-                                    response_tx
-                                        .send(Err(BallistaError::General(format!("{:?}", e))))
-                                        .unwrap();
+        let reader = RowGroupsReader {
+            filename: filename.to_string(),
+            schema,
+            projection,
+            file_projection,
+            output_schema: projected_schema.clone(),
+            partition_values,
+            num_file_columns,
+            predicate,
+            batch_size,
+            cancellation_token,
+            response_tx,
+            remaining: Arc::new(AtomicUsize::new(num_threads)),
+            thread_index: 0,
+            num_threads,
+        };
+        for thread_index in 0..num_threads {
+            let reader = RowGroupsReader {
+                thread_index,
+                ..reader.clone()
+            };
+            std::thread::spawn(move || reader.run());
+        }
+
+        Ok(Self {
+            schema: projected_schema,
+            response_rx,
+        })
+    }
+}
+
+/// Reads every n-th row group of a file, on a thread of its own, into a channel that is shared
+/// with the other threads reading the file. The last thread to finish ends the stream.
+#[derive(Clone)]
+struct RowGroupsReader {
+    filename: String,
+    /// Schema of the file
+    schema: Schema,
+    projection: Vec<usize>,
+    file_projection: Vec<usize>,
+    output_schema: Arc<Schema>,
+    partition_values: Vec<ScalarValue>,
+    num_file_columns: usize,
+    predicate: Option<Expr>,
+    batch_size: usize,
+    cancellation_token: CancellationToken,
+    response_tx: Sender<MaybeColumnarBatch>,
+    /// Number of threads that have not finished reading yet
+    remaining: Arc<AtomicUsize>,
+    thread_index: usize,
+    num_threads: usize,
+}
+
+impl RowGroupsReader {
+    fn run(self) {
+        let start = Instant::now();
+        let mut batch_read_time = 0;
+        let mut total_bytes_read = 0;
+        let mut output_batches = 0;
+        let mut output_rows = 0;
+
+        match open_parquet_file(&self.filename) {
+            Ok(file_reader) => {
+                let file_reader: Rc<dyn FileReader> = match self.row_groups(file_reader.as_ref()) {
+                    Some(row_groups) => Rc::new(RowGroupFilter::new(file_reader, row_groups)),
+                    None => Rc::from(file_reader),
+                };
+                let mut arrow_reader = ParquetFileArrowReader::new(file_reader);
+                match arrow_reader
+                    .get_record_reader_by_columns(self.file_projection.clone(), self.batch_size)
+                {
+                    Ok(mut batch_reader) => loop {
+                        // stop reading if the task has been cancelled, in which case the
+                        // receiver may already have been dropped
+                        if self.cancellation_token.is_cancelled() {
+                            let _ = self.response_tx.send(Err(BallistaError::Cancelled));
+                            break;
+                        }
+
+                        // read the next batch
+                        let start_batch = Instant::now();
+                        let maybe_batch = batch_reader.next_batch();
+                        batch_read_time += start_batch.elapsed().as_millis();
+
+                        let maybe_batch = match maybe_batch {
+                            Ok(Some(batch)) => add_partition_columns(
+                                &batch,
+                                &self.output_schema,
+                                &self.projection,
+                                &self.file_projection,
+                                &self.partition_values,
+                                self.num_file_columns,
+                            )
+                            .map(Some),
+                            Ok(None) => Ok(None),
+                            Err(e) => Err(BallistaError::General(format!("{:?}", e))),
+                        };
+
+                        match maybe_batch {
+                            Ok(Some(batch)) => {
+                                output_batches += 1;
+                                output_rows += batch.num_rows();
+
+                                let columnar_batch = ColumnarBatch::from_arrow(&batch);
+                                debug!(
+                                    "ParquetScanExec read batch bytes={}",
+                                    columnar_batch.memory_size()
+                                );
+                                total_bytes_read += columnar_batch.memory_size();
+
+                                // the receiver is dropped when the rest of the stream is not
+                                // needed, such as under a limit
+                                if self.response_tx.send(Ok(Some(columnar_batch))).is_err() {
                                     break;
                                 }
                             }
-                        },
-
-                        Err(e) => {
-                            response_tx
-                                .send(Err(BallistaError::General(format!("{:?}", e))))
-                                .unwrap();
+                            Ok(None) => break,
+                            Err(e) => {
+                                let _ = self.response_tx.send(Err(e));
+                                break;
+                            }
                         }
+                    },
+
+                    Err(e) => {
+                        let _ = self
+                            .response_tx
+                            .send(Err(BallistaError::General(format!("{:?}", e))));
                     }
                 }
+            }
 
-                Err(e) => {
-                    response_tx
-                        .send(Err(BallistaError::General(format!("{:?}", e))))
-                        .unwrap();
-                }
+            Err(e) => {
+                let _ = self.response_tx.send(Err(e));
             }
+        }
 
-            println!(
-                "ParquetScan scanned {} batches and {} rows containing {} bytes in {} ms. Total duration {} ms.",
-                output_batches,
-                output_rows,
-                total_bytes_read,
-                batch_read_time,
-                start.elapsed().as_millis()
-            );
-        });
+        if self.remaining.fetch_sub(1, AtomicOrdering::SeqCst) == 1 {
+            let _ = self.response_tx.send(Ok(None));
+        }
 
-        Ok(Self {
-            schema: Arc::new(projected_schema),
-            response_rx,
-        })
+        debug!(
+            "ParquetScan completed thread={}/{} batches={} rows={} bytes={} read_ms={} duration_ms={}",
+            self.thread_index,
+            self.num_threads,
+            output_batches,
+            output_rows,
+            total_bytes_read,
+            batch_read_time,
+            start.elapsed().as_millis()
+        );
+    }
+
+    /// The row groups that this thread reads, or None when it reads the whole file
+    fn row_groups(&self, file_reader: &dyn FileReader) -> Option<Vec<usize>> {
+        let row_groups = match &self.predicate {
+            Some(predicate) => {
+                let row_groups = prune_row_groups(
+                    file_reader.metadata(),
+                    &self.schema,
+                    &self.projection,
+                    predicate,
+                );
+                debug!(
+                    "ParquetScan reading {} of {} row groups in {}",
+                    row_groups.len(),
+                    file_reader.num_row_groups(),
+                    self.filename
+                );
+                row_groups
+            }
+            None if self.num_threads == 1 => return None,
+            None => (0..file_reader.num_row_groups()).collect(),
+        };
+        Some(
+            row_groups
+                .into_iter()
+                .skip(self.thread_index)
+                .step_by(self.num_threads)
+                .collect(),
+        )
     }
 }
 
@@ -232,3 +527,312 @@ impl ColumnarBatchIter for ParquetBatchIter {
         Task::blocking(async move { channel.recv().unwrap() }).await
     }
 }
+
+/// Open a local or remote Parquet file
+fn open_parquet_file(filename: &str) -> Result<Box<dyn FileReader>> {
+    let to_error = |e: ParquetError| {
+        BallistaError::General(format!("Failed to open Parquet file {}: {:?}", filename, e))
+    };
+    if object_store::is_remote(filename) {
+        let store = object_store::object_store(filename)?;
+        let length = store.size(filename)?;
+        let reader = ObjectChunkReader {
+            store,
+            path: filename.to_owned(),
+            length,
+        };
+        Ok(Box::new(
+            SerializedFileReader::new(reader).map_err(to_error)?,
+        ))
+    } else {
+        let file = File::open(filename)?;
+        Ok(Box::new(SerializedFileReader::new(file).map_err(to_error)?))
+    }
+}
+
+/// Read the schema of a Parquet file
+fn parquet_file_schema(filename: &str) -> Result<Schema> {
+    let file_reader: Rc<dyn FileReader> = Rc::from(open_parquet_file(filename)?);
+    let mut arrow_reader = ParquetFileArrowReader::new(file_reader);
+    Ok(arrow_reader.get_schema().unwrap()) //TODO error handling
+}
+
+/// Read the schema of a Parquet file, or directory of Parquet files, including any partition
+/// columns
+pub fn parquet_table_schema(path: &str) -> Result<Schema> {
+    let files = PartitionedFiles::try_new(path, ".parquet")?;
+    Ok(files.schema(&parquet_file_schema(&files.filenames[0])?))
+}
+
+/// Reads ranges of a Parquet file in an object store, so that only the footer and the column
+/// chunks that are needed are fetched
+struct ObjectChunkReader {
+    store: Arc<dyn ObjectStore>,
+    path: String,
+    length: u64,
+}
+
+impl Length for ObjectChunkReader {
+    fn len(&self) -> u64 {
+        self.length
+    }
+}
+
+impl ChunkReader for ObjectChunkReader {
+    type T = Cursor<Vec<u8>>;
+
+    fn get_read(&self, start: u64, length: usize) -> ParquetResult<Self::T> {
+        self.store
+            .read_range(&self.path, start, length)
+            .map(Cursor::new)
+            .map_err(|e| ParquetError::General(format!("{:?}", e)))
+    }
+}
+
+/// Build the projected batch from a batch of the projected file columns and the partition values
+/// of the file
+fn add_partition_columns(
+    batch: &RecordBatch,
+    schema: &Arc<Schema>,
+    projection: &[usize],
+    file_projection: &[usize],
+    partition_values: &[ScalarValue],
+    num_file_columns: usize,
+) -> Result<RecordBatch> {
+    if projection == file_projection {
+        return Ok(batch.clone());
+    }
+    let columns = projection
+        .iter()
+        .zip(schema.fields())
+        .map(|(i, field)| {
+            if *i < num_file_columns {
+                let j = file_projection.iter().position(|c| c == i).unwrap();
+                Ok(batch.column(j).clone())
+            } else {
+                partition_array(
+                    &partition_values[*i - num_file_columns],
+                    field.data_type(),
+                    batch.num_rows(),
+                )
+            }
+        })
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+/// File reader that only exposes a subset of the row groups of a Parquet file
+struct RowGroupFilter {
+    reader: Box<dyn FileReader>,
+    row_groups: Vec<usize>,
+    metadata: ParquetMetaData,
+}
+
+impl RowGroupFilter {
+    fn new(reader: Box<dyn FileReader>, row_groups: Vec<usize>) -> Self {
+        let metadata = ParquetMetaData::new(
+            reader.metadata().file_metadata().clone(),
+            row_groups
+                .iter()
+                .map(|i| reader.metadata().row_group(*i).clone())
+                .collect(),
+        );
+        Self {
+            reader,
+            row_groups,
+            metadata,
+        }
+    }
+}
+
+impl FileReader for RowGroupFilter {
+    fn metadata(&self) -> &ParquetMetaData {
+        &self.metadata
+    }
+
+    fn num_row_groups(&self) -> usize {
+        self.row_groups.len()
+    }
+
+    fn get_row_group(&self, i: usize) -> ParquetResult<Box<dyn RowGroupReader + '_>> {
+        self.reader.get_row_group(self.row_groups[i])
+    }
+
+    fn get_row_iter(&self, projection: Option<SchemaType>) -> ParquetResult<RowIter> {
+        RowIter::from_file(projection, self)
+    }
+}
+
+/// Determine which row groups may contain rows that match the predicate, where the predicate
+/// refers to the columns of the projected schema. Columns are mapped to Parquet columns by
+/// position, so statistics are only used when the schema has no nested fields.
+fn prune_row_groups(
+    metadata: &ParquetMetaData,
+    schema: &Schema,
+    projection: &[usize],
+    predicate: &Expr,
+) -> Vec<usize> {
+    let flat = metadata.file_metadata().schema_descr().num_columns() == schema.fields().len();
+    (0..metadata.num_row_groups())
+        .filter(|i| {
+            if !flat {
+                return true;
+            }
+            let row_group = metadata.row_group(*i);
+            let stats = |column: &Expr| {
+                let index = match column {
+                    Expr::Column(i) => projection.get(*i).cloned(),
+                    Expr::UnresolvedColumn(name) => schema.index_of(name).ok(),
+                    _ => None,
+                }
+                .filter(|index| *index < schema.fields().len())?;
+                min_max(row_group, index, schema.field(index).data_type())
+            };
+            may_match(predicate, &stats)
+        })
+        .collect()
+}
+
+/// Get the min and max values of a column in a row group, if the statistics are available
+fn min_max(
+    row_group: &RowGroupMetaData,
+    index: usize,
+    data_type: &DataType,
+) -> Option<(ScalarValue, ScalarValue)> {
+    let stats = row_group.column(index).statistics()?;
+    if !stats.has_min_max_set() {
+        return None;
+    }
+    match stats {
+        ParquetStatistics::Boolean(s) => Some((
+            ScalarValue::Boolean(*s.min()),
+            ScalarValue::Boolean(*s.max()),
+        )),
+        ParquetStatistics::Int32(s) => Some((
+            ScalarValue::Int64(*s.min() as i64),
+            ScalarValue::Int64(*s.max() as i64),
+        )),
+        ParquetStatistics::Int64(s) => {
+            Some((ScalarValue::Int64(*s.min()), ScalarValue::Int64(*s.max())))
+        }
+        ParquetStatistics::Float(s) => Some((
+            ScalarValue::Float64(*s.min() as f64),
+            ScalarValue::Float64(*s.max() as f64),
+        )),
+        ParquetStatistics::Double(s) => Some((
+            ScalarValue::Float64(*s.min()),
+            ScalarValue::Float64(*s.max()),
+        )),
+        ParquetStatistics::ByteArray(s) if *data_type == DataType::Utf8 => Some((
+            ScalarValue::Utf8(String::from_utf8(s.min().data().to_vec()).ok()?),
+            ScalarValue::Utf8(String::from_utf8(s.max().data().to_vec()).ok()?),
+        )),
+        _ => None,
+    }
+}
+
+/// Decide whether a predicate may be true for any row, given a function that returns the min
+/// and max values of a column. Returns true when this cannot be determined.
+fn may_match(
+    predicate: &Expr,
+    stats: &dyn Fn(&Expr) -> Option<(ScalarValue, ScalarValue)>,
+) -> bool {
+    // compare the values of a column to a literal, returning None when the answer is unknown
+    let compare = |column: &Expr, op: &Operator, value: &Expr| -> Option<bool> {
+        let value = match value {
+            Expr::Literal(value) => value,
+            _ => return None,
+        };
+        let (min, max) = stats(column)?;
+        let min = compare_scalars(&min, value)?;
+        let max = compare_scalars(&max, value)?;
+        match op {
+            Operator::Eq => Some(min != Ordering::Greater && max != Ordering::Less),
+            Operator::NotEq => Some(min != Ordering::Equal || max != Ordering::Equal),
+            Operator::Lt => Some(min == Ordering::Less),
+            Operator::LtEq => Some(min != Ordering::Greater),
+            Operator::Gt => Some(max == Ordering::Greater),
+            Operator::GtEq => Some(max != Ordering::Less),
+            _ => None,
+        }
+    };
+    let flip = |op: &Operator| match op {
+        Operator::Lt => Operator::Gt,
+        Operator::LtEq => Operator::GtEq,
+        Operator::Gt => Operator::Lt,
+        Operator::GtEq => Operator::LtEq,
+        other => other.clone(),
+    };
+
+    match predicate {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => may_match(left, stats) && may_match(right, stats),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => may_match(left, stats) || may_match(right, stats),
+        Expr::BinaryExpr { left, op, right } => match (left.as_ref(), right.as_ref()) {
+            (_, Expr::Literal(_)) => compare(left, op, right),
+            (Expr::Literal(_), _) => compare(right, &flip(op), left),
+            _ => None,
+        }
+        .unwrap_or(true),
+        Expr::ScalarFunction { name, args, .. } if name == BETWEEN_FUNCTION_NAME => {
+            match decode_predicate(args) {
+                Ok((false, expr, [low, high])) => {
+                    compare(expr, &Operator::GtEq, low).unwrap_or(true)
+                        && compare(expr, &Operator::LtEq, high).unwrap_or(true)
+                }
+                _ => true,
+            }
+        }
+        Expr::ScalarFunction { name, args, .. } if name == IN_LIST_FUNCTION_NAME => {
+            match decode_predicate(args) {
+                Ok((false, expr, list)) => list
+                    .iter()
+                    .any(|value| compare(expr, &Operator::Eq, value).unwrap_or(true)),
+                _ => true,
+            }
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_with_statistics() {
+        let stats = |column: &Expr| match column {
+            Expr::Column(0) => Some((ScalarValue::Int64(10), ScalarValue::Int64(20))),
+            _ => None,
+        };
+        let lit = |n: i64| Expr::Literal(ScalarValue::Int64(n));
+        let cmp = |op: Operator, n: i64| Expr::BinaryExpr {
+            left: Box::new(Expr::Column(0)),
+            op,
+            right: Box::new(lit(n)),
+        };
+
+        assert!(may_match(&cmp(Operator::Eq, 15), &stats));
+        assert!(!may_match(&cmp(Operator::Eq, 25), &stats));
+        assert!(!may_match(&cmp(Operator::Lt, 10), &stats));
+        assert!(may_match(&cmp(Operator::LtEq, 10), &stats));
+        assert!(!may_match(&cmp(Operator::Gt, 20), &stats));
+        assert!(!may_match(
+            &Expr::BinaryExpr {
+                left: Box::new(cmp(Operator::Gt, 12)),
+                op: Operator::And,
+                right: Box::new(cmp(Operator::Lt, 11)),
+            },
+            &stats
+        ));
+        // unknown columns and unsupported expressions never prune
+        assert!(may_match(&Expr::Column(1), &stats));
+    }
+}
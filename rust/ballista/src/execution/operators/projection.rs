@@ -30,6 +30,8 @@ use async_trait::async_trait;
 #[derive(Debug, Clone)]
 pub struct ProjectionExec {
     /// Logical expressions for the projection.
+    pub(crate) expr: Vec<Expr>,
+    /// Compiled expressions for the projection.
     exprs: Vec<Arc<dyn Expression>>,
     /// The input operator to apply the projection to.
     pub(crate) child: Arc<PhysicalPlan>,
@@ -51,11 +53,22 @@ impl ProjectionExec {
         let schema = Arc::new(Schema::new(fields?));
 
         Ok(Self {
+            expr: expr.to_vec(),
             exprs,
             child,
             schema,
         })
     }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> ProjectionExec {
+        assert!(new_children.len() == 1);
+        ProjectionExec {
+            expr: self.expr.clone(),
+            exprs: self.exprs.clone(),
+            child: new_children[0].clone(),
+            schema: self.schema.clone(),
+        }
+    }
 }
 
 #[async_trait]
@@ -78,11 +91,7 @@ impl ExecutionPlan for ProjectionExec {
         partition_index: usize,
     ) -> Result<ColumnarBatchStream> {
         Ok(Arc::new(ProjectionIter {
-            input: self
-                .child
-                .as_execution_plan()
-                .execute(ctx.clone(), partition_index)
-                .await?,
+            input: self.child.execute(ctx.clone(), partition_index).await?,
             projection: self.exprs.clone(),
         }))
     }
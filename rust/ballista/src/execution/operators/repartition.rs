@@ -0,0 +1,74 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Repartition operator.
+
+use std::sync::Arc;
+
+use crate::arrow::datatypes::Schema;
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::{
+    ColumnarBatchStream, ExecutionContext, ExecutionPlan, Partitioning, PhysicalPlan,
+};
+
+use async_trait::async_trait;
+
+/// RepartitionExec redistributes the rows of its input evenly across a different number of
+/// partitions, e.g. to spread a scan of a few large files over more tasks. Like a shuffle
+/// exchange, it marks the boundary between two stages and is replaced by a shuffle reader when
+/// the plan is broken down into stages.
+#[derive(Debug, Clone)]
+pub struct RepartitionExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    pub(crate) partition_count: usize,
+}
+
+impl RepartitionExec {
+    pub fn new(child: Arc<PhysicalPlan>, partition_count: usize) -> Self {
+        Self {
+            child,
+            partition_count: partition_count.max(1),
+        }
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> RepartitionExec {
+        assert!(new_children.len() == 1);
+        RepartitionExec::new(new_children[0].clone(), self.partition_count)
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for RepartitionExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.child.as_execution_plan().schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.partition_count)
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    async fn execute(
+        &self,
+        _ctx: Arc<dyn ExecutionContext>,
+        _partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        Err(BallistaError::NotImplemented(
+            "RepartitionExec is replaced by a shuffle when the plan is scheduled".to_owned(),
+        ))
+    }
+}
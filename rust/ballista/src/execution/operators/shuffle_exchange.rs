@@ -43,6 +43,10 @@ impl ExecutionPlan for ShuffleExchangeExec {
         self.child.as_execution_plan().schema()
     }
 
+    fn output_partitioning(&self) -> Partitioning {
+        self.output_partitioning.clone()
+    }
+
     async fn execute(
         &self,
         _ctx: Arc<dyn ExecutionContext>,
@@ -16,12 +16,19 @@
 
 use std::sync::Arc;
 
+use crate::arrow::array::{ArrayRef, UInt32Array};
+use crate::arrow::compute;
 use crate::arrow::datatypes::Schema;
-use crate::error::Result;
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::logicalplan::Expr;
+use crate::error::{ballista_error, Result};
 use crate::execution::physical_plan::{
-    ColumnarBatchStream, ExecutionContext, ExecutionPlan, ShuffleId,
+    compile_expressions, ColumnarBatch, ColumnarBatchStream, ExecutionContext, ExecutionPlan,
+    Partitioning, ShuffleId,
 };
 
+use crate::execution::operators::hash_join::hash_partition;
+use crate::execution::operators::sort::range_partitions;
 use crate::execution::operators::InMemoryTableScanExec;
 use async_trait::async_trait;
 
@@ -30,11 +37,80 @@ use async_trait::async_trait;
 pub struct ShuffleReaderExec {
     schema: Arc<Schema>,
     pub(crate) shuffle_id: Vec<ShuffleId>,
+    /// Partitioning of the shuffle. Each shuffle partition holds the whole output of one task
+    /// of the stage that produced it, so when the shuffle is partitioned by value the reader of
+    /// each partition reads every shuffle partition and keeps the rows that belong to it. Rows
+    /// of a shuffle with an unknown partitioning into more than one partition are distributed
+    /// evenly across the partitions.
+    pub(crate) partitioning: Partitioning,
+    /// Number of sub-partitions that each partition of the shuffle is split into when the
+    /// shuffle is skewed, or empty if no partition is split
+    pub(crate) skew_splits: Vec<usize>,
+    /// Whether the rows of each split partition are read by all of its sub-partitions rather
+    /// than divided between them, for the input of a join that is not being split
+    pub(crate) skew_replicated: Vec<bool>,
+    /// Number of consecutive partitions of the shuffle that each partition reads when small
+    /// partitions are coalesced, or empty if each partition reads one partition of the shuffle
+    pub(crate) coalesced_partitions: Vec<usize>,
 }
 
 impl ShuffleReaderExec {
     pub fn new(schema: Arc<Schema>, shuffle_id: Vec<ShuffleId>) -> Self {
-        Self { schema, shuffle_id }
+        Self {
+            schema,
+            shuffle_id,
+            partitioning: Partitioning::UnknownPartitioning(1),
+            skew_splits: vec![],
+            skew_replicated: vec![],
+            coalesced_partitions: vec![],
+        }
+    }
+
+    pub fn with_partitioning(mut self, partitioning: Partitioning) -> Self {
+        self.partitioning = partitioning;
+        self
+    }
+
+    /// Split each partition of the shuffle into the given number of sub-partitions, either
+    /// dividing its rows between them or, when `replicated`, having each read all of its rows
+    pub fn with_skew_splits(mut self, splits: Vec<usize>, replicated: Vec<bool>) -> Self {
+        self.skew_splits = splits;
+        self.skew_replicated = replicated;
+        self
+    }
+
+    /// Have each partition read the given number of consecutive partitions of the shuffle
+    pub fn with_coalesced_partitions(mut self, coalesced_partitions: Vec<usize>) -> Self {
+        self.coalesced_partitions = coalesced_partitions;
+        self
+    }
+
+    /// The range of partitions of the shuffle that a coalesced partition reads
+    fn coalesced_range(&self, partition_index: usize) -> Result<(usize, usize)> {
+        let start: usize = self.coalesced_partitions.iter().take(partition_index).sum();
+        match self.coalesced_partitions.get(partition_index) {
+            Some(n) => Ok((start, start + n)),
+            None => Err(ballista_error(&format!(
+                "Partition {} is out of range for {} coalesced partitions",
+                partition_index,
+                self.coalesced_partitions.len()
+            ))),
+        }
+    }
+
+    /// The partition of the shuffle and the sub-partition of it that an output partition reads
+    fn skew_split(&self, partition_index: usize) -> Result<(usize, usize)> {
+        let mut start = 0;
+        for (partition, splits) in self.skew_splits.iter().enumerate() {
+            if partition_index < start + splits {
+                return Ok((partition, partition_index - start));
+            }
+            start += splits;
+        }
+        Err(ballista_error(&format!(
+            "Partition {} is out of range for {} skew splits",
+            partition_index, start
+        )))
     }
 }
 
@@ -44,6 +120,16 @@ impl ExecutionPlan for ShuffleReaderExec {
         self.schema.clone()
     }
 
+    fn output_partitioning(&self) -> Partitioning {
+        if !self.skew_splits.is_empty() {
+            Partitioning::UnknownPartitioning(self.skew_splits.iter().sum())
+        } else if !self.coalesced_partitions.is_empty() {
+            Partitioning::UnknownPartitioning(self.coalesced_partitions.len())
+        } else {
+            self.partitioning.clone()
+        }
+    }
+
     async fn execute(
         &self,
         ctx: Arc<dyn ExecutionContext>,
@@ -52,9 +138,119 @@ impl ExecutionPlan for ShuffleReaderExec {
         //TODO read shuffles in parallel
         let mut batches = vec![];
         for shuffle_id in &self.shuffle_id {
+            ctx.cancellation_token().check()?;
             batches.extend(ctx.read_shuffle(&shuffle_id).await?);
         }
+        // the partition of each row of the batches, unless every partition reads all rows
+        let row_partitions = match &self.partitioning {
+            Partitioning::HashPartitioning(n, exprs) => {
+                Some(hash_partitions(&batches, exprs, &self.schema, *n)?)
+            }
+            Partitioning::RangePartitioning(n, exprs) => {
+                let exprs: Vec<Expr> = exprs.iter().map(|e| e.as_ref().clone()).collect();
+                Some(range_partitions(&batches, &exprs, &self.schema, *n)?)
+            }
+            Partitioning::UnknownPartitioning(n) if *n > 1 => {
+                let num_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+                Some((0..num_rows).map(|row| row % n).collect())
+            }
+            Partitioning::UnknownPartitioning(_) => None,
+        };
+        // whether each row belongs to this partition
+        let selected: Option<Vec<bool>> = match row_partitions {
+            Some(row_partitions) if !self.coalesced_partitions.is_empty() => {
+                let (start, end) = self.coalesced_range(partition_index)?;
+                Some(
+                    row_partitions
+                        .iter()
+                        .map(|p| *p >= start && *p < end)
+                        .collect(),
+                )
+            }
+            Some(row_partitions) if self.skew_splits.is_empty() => Some(
+                row_partitions
+                    .iter()
+                    .map(|p| *p == partition_index)
+                    .collect(),
+            ),
+            Some(row_partitions) => {
+                // a sub-partition of a skewed partition takes every nth row of the partition
+                let (partition, split) = self.skew_split(partition_index)?;
+                let splits = self.skew_splits[partition];
+                let replicated = self.skew_replicated.get(partition) == Some(&true);
+                let mut ordinal = 0;
+                Some(
+                    row_partitions
+                        .iter()
+                        .map(|p| {
+                            if *p != partition {
+                                return false;
+                            }
+                            let keep = replicated || ordinal % splits == split;
+                            ordinal += 1;
+                            keep
+                        })
+                        .collect(),
+                )
+            }
+            None => None,
+        };
+        if let Some(selected) = selected {
+            let mut partition = vec![];
+            let mut offset = 0;
+            for batch in &batches {
+                let indices: Vec<u32> = (0..batch.num_rows())
+                    .filter(|row| selected[offset + row])
+                    .map(|row| row as u32)
+                    .collect();
+                offset += batch.num_rows();
+                if !indices.is_empty() {
+                    partition.push(take_rows(batch, indices)?);
+                }
+            }
+            if partition.is_empty() && !batches.is_empty() {
+                // the in-memory scan takes its schema from the first batch
+                partition.push(take_rows(&batches[0], vec![])?);
+            }
+            batches = partition;
+        }
         let exec = InMemoryTableScanExec::new(batches);
         exec.execute(ctx.clone(), partition_index).await
     }
 }
+
+/// The hash partition of each row of the batches
+pub fn hash_partitions(
+    batches: &[ColumnarBatch],
+    exprs: &[Arc<Expr>],
+    schema: &Schema,
+    n: usize,
+) -> Result<Vec<usize>> {
+    let exprs: Vec<Expr> = exprs.iter().map(|e| e.as_ref().clone()).collect();
+    let keys = compile_expressions(&exprs, schema)?;
+    let mut row_partitions = vec![];
+    for batch in batches {
+        let key_columns = keys
+            .iter()
+            .map(|k| k.evaluate(batch)?.to_arrow())
+            .collect::<Result<Vec<_>>>()?;
+        for row in 0..batch.num_rows() {
+            row_partitions.push(hash_partition(&key_columns, row, n)?);
+        }
+    }
+    Ok(row_partitions)
+}
+
+fn take_rows(batch: &ColumnarBatch, indices: Vec<u32>) -> Result<ColumnarBatch> {
+    let batch = batch.to_arrow()?;
+    let indices = UInt32Array::from(indices);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|c| Ok(compute::take(c, &indices, None)?))
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    Ok(ColumnarBatch::from_arrow(&RecordBatch::try_new(
+        batch.schema(),
+        columns,
+    )?))
+}
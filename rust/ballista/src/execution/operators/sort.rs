@@ -0,0 +1,738 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sort operator. Each partition is sorted independently, so a global sort relies on the input
+//! being range-partitioned on the sort keys, in which case reading the sorted partitions in
+//! order produces globally ordered results.
+//!
+//! Partitions that do not fit in the memory of the task are sorted externally: the input is
+//! sorted in runs that fit in memory, each run is spilled to disk, and the runs are merged as
+//! the output is read.
+
+use std::cmp::Ordering;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::array::{self, Array, ArrayRef};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::cast_array;
+use crate::datafusion::logicalplan::Expr;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::memory::MemoryReservation;
+use crate::execution::operators::hash_join::{concat_batches, take_columns};
+use crate::execution::operators::in_memory::InMemoryTableScanIter;
+use crate::execution::physical_plan::{
+    compile_expression, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, Distribution,
+    ExecutionContext, ExecutionPlan, Expression, NullOrdering, Partitioning, PhysicalPlan,
+    SortDirection, SortOrder,
+};
+use crate::execution::spill::{SpillFile, SpillWriter};
+
+use async_trait::async_trait;
+use log::info;
+
+/// Number of rows sampled per output partition when computing the boundaries of a range
+/// partitioning
+const RANGE_SAMPLES_PER_PARTITION: usize = 100;
+
+/// Number of rows in each batch of a spilled run, and in each batch produced by merging runs
+const MERGE_BATCH_ROWS: usize = 8192;
+
+/// SortExec sorts each partition of its input on one or more sort expressions.
+#[derive(Debug)]
+pub struct SortExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    /// Sort expressions, each an `Expr::Sort`
+    pub(crate) sort_expr: Vec<Expr>,
+}
+
+impl SortExec {
+    pub fn try_new(child: Arc<PhysicalPlan>, sort_expr: Vec<Expr>) -> Result<Self> {
+        // fail early on expressions that cannot be sorted on
+        compile_sort_keys(&sort_expr, &child.as_execution_plan().schema())?;
+        Ok(Self { child, sort_expr })
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> SortExec {
+        assert!(new_children.len() == 1);
+        SortExec {
+            child: new_children[0].clone(),
+            sort_expr: self.sort_expr.clone(),
+        }
+    }
+
+    fn sort_order(&self) -> Vec<SortOrder> {
+        self.sort_expr
+            .iter()
+            .filter_map(|e| match e {
+                Expr::Sort {
+                    expr,
+                    asc,
+                    nulls_first,
+                } => Some(SortOrder::new(
+                    Arc::new(expr.as_ref().clone()),
+                    if *asc {
+                        SortDirection::Ascending
+                    } else {
+                        SortDirection::Descending
+                    },
+                    if *nulls_first {
+                        NullOrdering::NullsFirst
+                    } else {
+                        NullOrdering::NullsLast
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for SortExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.child.as_execution_plan().schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.child.as_execution_plan().output_partitioning()
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        Distribution::OrderedDistribution(self.sort_order())
+    }
+
+    fn output_ordering(&self) -> Option<Vec<SortOrder>> {
+        Some(self.sort_order())
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let schema = self.schema();
+        let keys = compile_sort_keys(&self.sort_expr, &schema)?;
+
+        let task_memory = ctx.task_memory();
+        let mut reservation = task_memory.reservation("SortExec");
+        let input = self.child.execute(ctx.clone(), partition_index).await?;
+        let mut batches = vec![];
+        let mut spilled_runs = vec![];
+        while let Some(batch) = input.next().await? {
+            ctx.cancellation_token().check()?;
+            if !reservation.try_grow(batch.memory_size()) {
+                // sort the buffered batches into a run on disk to make room for the batch
+                if let Some(run) = sort_batches(&schema, &keys, &batches, None)? {
+                    let file = spill_run(task_memory.spill_dir(), &schema, &run)?;
+                    info!(
+                        "SortExec spilled run partition_index={} rows={} bytes={}",
+                        partition_index,
+                        file.num_rows(),
+                        file.num_bytes()
+                    );
+                    spilled_runs.push(file);
+                }
+                batches.clear();
+                reservation.free();
+                reservation.grow(batch.memory_size())?;
+            }
+            batches.push(batch.to_arrow()?);
+        }
+        let sorted = sort_batches(&schema, &keys, &batches, None)?;
+        if spilled_runs.is_empty() {
+            return Ok(Arc::new(InMemoryTableScanIter::with_schema(
+                schema,
+                sorted.iter().map(ColumnarBatch::from_arrow).collect(),
+            )));
+        }
+
+        // the last run stays in memory and is merged with the runs read back from disk
+        let mut runs = spilled_runs
+            .into_iter()
+            .map(|file| SortedRun::try_new(&keys, Box::new(file.read()?), Some(file)))
+            .collect::<Result<Vec<_>>>()?;
+        runs.push(SortedRun::try_new(
+            &keys,
+            Box::new(sorted.into_iter().map(Ok)),
+            None,
+        )?);
+        Ok(Arc::new(SortMergeIter {
+            schema,
+            keys,
+            runs: Mutex::new(runs),
+            _reservation: reservation,
+        }))
+    }
+}
+
+/// Write a sorted run to a spill file, in batches small enough to merge one at a time
+fn spill_run(dir: &Path, schema: &Arc<Schema>, run: &RecordBatch) -> Result<SpillFile> {
+    let mut writer = SpillWriter::try_new(dir, "sort", schema)?;
+    for start in (0..run.num_rows()).step_by(MERGE_BATCH_ROWS) {
+        let end = (start + MERGE_BATCH_ROWS).min(run.num_rows());
+        let indices = (start..end).map(|i| Some(i as u32)).collect();
+        let columns = take_columns(schema, run.columns(), indices)?;
+        writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+    }
+    writer.finish()
+}
+
+/// Batches of a sorted run
+type SortedBatches = Box<dyn Iterator<Item = Result<RecordBatch>> + Send>;
+
+/// Position in a sorted run that is being merged
+struct SortedRun {
+    batches: SortedBatches,
+    /// The batch being merged, or `None` once the run is exhausted
+    batch: Option<RecordBatch>,
+    /// Sort key columns of the batch being merged
+    key_columns: Vec<ArrayRef>,
+    /// Next row of the batch to merge
+    row: usize,
+    /// The file that the run is read from, which is deleted once the run has been merged
+    _file: Option<SpillFile>,
+}
+
+impl SortedRun {
+    fn try_new(keys: &[SortKey], batches: SortedBatches, file: Option<SpillFile>) -> Result<Self> {
+        let mut run = Self {
+            batches,
+            batch: None,
+            key_columns: vec![],
+            row: 0,
+            _file: file,
+        };
+        run.advance(keys)?;
+        Ok(run)
+    }
+
+    /// Move on to the next non-empty batch of the run
+    fn advance(&mut self, keys: &[SortKey]) -> Result<()> {
+        self.batch = None;
+        self.row = 0;
+        for batch in &mut self.batches {
+            let batch = batch?;
+            if batch.num_rows() > 0 {
+                self.key_columns = evaluate_sort_keys(keys, &ColumnarBatch::from_arrow(&batch))?;
+                self.batch = Some(batch);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges sorted runs, producing one batch of the merged rows at a time
+struct SortMergeIter {
+    schema: Arc<Schema>,
+    keys: Vec<SortKey>,
+    runs: Mutex<Vec<SortedRun>>,
+    /// Memory of the run that was kept in memory, released once the output has been read
+    _reservation: MemoryReservation,
+}
+
+#[async_trait]
+impl ColumnarBatchIter for SortMergeIter {
+    fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        let mut runs = self.runs.lock().expect("failed to lock mutex");
+        merge_runs(&self.schema, &self.keys, &mut runs)
+    }
+}
+
+/// Merge the next rows of sorted runs into a batch, or return `None` when all runs have been
+/// merged. Rows that compare equal are taken from the earlier run first.
+fn merge_runs(
+    schema: &Arc<Schema>,
+    keys: &[SortKey],
+    runs: &mut [SortedRun],
+) -> Result<Option<ColumnarBatch>> {
+    // the batches that rows are taken from, which are concatenated to take the rows in one go
+    let mut batches: Vec<RecordBatch> = vec![];
+    let mut offsets: Vec<Option<usize>> = vec![None; runs.len()];
+    let mut num_rows = 0;
+    let mut indices: Vec<Option<u32>> = vec![];
+    while indices.len() < MERGE_BATCH_ROWS {
+        // the run whose next row comes first
+        let mut next: Option<usize> = None;
+        for (i, run) in runs.iter().enumerate() {
+            if run.batch.is_none() {
+                continue;
+            }
+            next = match next {
+                Some(j) => {
+                    let other = &runs[j];
+                    let ordering = compare_rows(
+                        &run.key_columns,
+                        run.row,
+                        &other.key_columns,
+                        other.row,
+                        keys,
+                    )?;
+                    Some(if ordering == Ordering::Less { i } else { j })
+                }
+                None => Some(i),
+            };
+        }
+        let i = match next {
+            Some(i) => i,
+            None => break,
+        };
+
+        let run = &mut runs[i];
+        let batch_rows = run.batch.as_ref().map(|b| b.num_rows()).unwrap_or(0);
+        let offset = match offsets[i] {
+            Some(offset) => offset,
+            None => {
+                batches.extend(run.batch.clone());
+                offsets[i] = Some(num_rows);
+                num_rows += batch_rows;
+                num_rows - batch_rows
+            }
+        };
+        indices.push(Some((offset + run.row) as u32));
+        run.row += 1;
+        if run.row == batch_rows {
+            run.advance(keys)?;
+            offsets[i] = None;
+        }
+    }
+    if indices.is_empty() {
+        return Ok(None);
+    }
+
+    let columns = concat_batches(schema, &batches)?;
+    let columns = take_columns(schema, &columns, indices)?;
+    Ok(Some(ColumnarBatch::from_arrow(&RecordBatch::try_new(
+        schema.clone(),
+        columns,
+    )?)))
+}
+
+/// TopKExec keeps the first `k` rows of each partition of its input in the order of one or more
+/// sort expressions. A partial top-k runs against each partition in parallel so that only `k`
+/// rows per partition are shuffled, and a final top-k merges those rows into a single partition.
+#[derive(Debug)]
+pub struct TopKExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    /// Sort expressions, each an `Expr::Sort`
+    pub(crate) sort_expr: Vec<Expr>,
+    pub(crate) k: usize,
+    pub(crate) partial: bool,
+}
+
+impl TopKExec {
+    pub fn try_new(
+        child: Arc<PhysicalPlan>,
+        sort_expr: Vec<Expr>,
+        k: usize,
+        partial: bool,
+    ) -> Result<Self> {
+        compile_sort_keys(&sort_expr, &child.as_execution_plan().schema())?;
+        Ok(Self {
+            child,
+            sort_expr,
+            k,
+            partial,
+        })
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> TopKExec {
+        assert!(new_children.len() == 1);
+        TopKExec {
+            child: new_children[0].clone(),
+            sort_expr: self.sort_expr.clone(),
+            k: self.k,
+            partial: self.partial,
+        }
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for TopKExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.child.as_execution_plan().schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        if self.partial {
+            self.child.as_execution_plan().output_partitioning()
+        } else {
+            Partitioning::UnknownPartitioning(1)
+        }
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        if self.partial {
+            Distribution::UnspecifiedDistribution
+        } else {
+            Distribution::SinglePartition
+        }
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let schema = self.schema();
+        let keys = compile_sort_keys(&self.sort_expr, &schema)?;
+
+        // buffer up to twice the limit before discarding the rows that are not in the top k, so
+        // that memory use does not depend on the size of the partition
+        let input = self.child.execute(ctx.clone(), partition_index).await?;
+        let mut batches = vec![];
+        let mut buffered_rows = 0;
+        while let Some(batch) = input.next().await? {
+            ctx.cancellation_token().check()?;
+            buffered_rows += batch.num_rows();
+            batches.push(batch.to_arrow()?);
+            if buffered_rows > 2 * self.k {
+                batches = sort_batches(&schema, &keys, &batches, Some(self.k))?
+                    .into_iter()
+                    .collect();
+                buffered_rows = self.k;
+            }
+        }
+        let sorted = sort_batches(&schema, &keys, &batches, Some(self.k))?;
+        Ok(Arc::new(InMemoryTableScanIter::with_schema(
+            schema,
+            sorted.iter().map(ColumnarBatch::from_arrow).collect(),
+        )))
+    }
+}
+
+/// Sort the rows of the batches into a single batch, keeping only the first `limit` rows when
+/// a limit is given. Returns `None` when there are no batches.
+fn sort_batches(
+    schema: &Arc<Schema>,
+    keys: &[SortKey],
+    batches: &[RecordBatch],
+    limit: Option<usize>,
+) -> Result<Option<RecordBatch>> {
+    let columns = concat_batches(schema, batches)?;
+    if columns.is_empty() {
+        return Ok(None);
+    }
+
+    let batch = ColumnarBatch::from_arrow(&RecordBatch::try_new(schema.clone(), columns)?);
+    let key_columns = evaluate_sort_keys(keys, &batch)?;
+    let mut indices: Vec<u32> = (0..batch.num_rows() as u32).collect();
+    sort_indices(&mut indices, &key_columns, keys)?;
+    if let Some(limit) = limit {
+        indices.truncate(limit);
+    }
+
+    let batch = batch.to_arrow()?;
+    let indices = indices.into_iter().map(Some).collect();
+    let columns = take_columns(schema, batch.columns(), indices)?;
+    Ok(Some(RecordBatch::try_new(schema.clone(), columns)?))
+}
+
+/// A compiled sort expression
+pub(crate) struct SortKey {
+    expr: Arc<dyn Expression>,
+    asc: bool,
+    nulls_first: bool,
+}
+
+/// Compile sort expressions, each of which must be an `Expr::Sort`
+pub(crate) fn compile_sort_keys(sort_expr: &[Expr], schema: &Schema) -> Result<Vec<SortKey>> {
+    sort_expr
+        .iter()
+        .map(|e| match e {
+            Expr::Sort {
+                expr,
+                asc,
+                nulls_first,
+            } => Ok(SortKey {
+                expr: compile_expression(expr, schema)?,
+                asc: *asc,
+                nulls_first: *nulls_first,
+            }),
+            other => Err(ballista_error(&format!(
+                "Expected sort expression but found {:?}",
+                other
+            ))),
+        })
+        .collect()
+}
+
+pub(crate) fn evaluate_sort_keys(keys: &[SortKey], batch: &ColumnarBatch) -> Result<Vec<ArrayRef>> {
+    keys.iter()
+        .map(|k| k.expr.evaluate(batch)?.to_arrow())
+        .collect()
+}
+
+/// Sort row indices by the values of the sort keys in those rows
+pub(crate) fn sort_indices(
+    indices: &mut Vec<u32>,
+    key_columns: &[ArrayRef],
+    keys: &[SortKey],
+) -> Result<()> {
+    let mut error = None;
+    indices.sort_by(|a, b| {
+        match compare_rows(key_columns, *a as usize, key_columns, *b as usize, keys) {
+            Ok(ordering) => ordering,
+            Err(e) => {
+                error.get_or_insert(e);
+                Ordering::Equal
+            }
+        }
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Compare row `i` of one set of sort key columns with row `j` of another
+pub(crate) fn compare_rows(
+    a: &[ArrayRef],
+    i: usize,
+    b: &[ArrayRef],
+    j: usize,
+    keys: &[SortKey],
+) -> Result<Ordering> {
+    for (k, key) in keys.iter().enumerate() {
+        let ordering = match (a[k].is_null(i), b[k].is_null(j)) {
+            (true, true) => Ordering::Equal,
+            // nulls are placed first or last regardless of the sort direction
+            (true, false) if key.nulls_first => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, true) if key.nulls_first => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) if key.asc => compare_values(&a[k], i, &b[k], j)?,
+            (false, false) => compare_values(&a[k], i, &b[k], j)?.reverse(),
+        };
+        if ordering != Ordering::Equal {
+            return Ok(ordering);
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+macro_rules! compare_array_values {
+    ($A:ident, $I:expr, $B:ident, $J:expr, $ARRAY_TYPE:ident) => {{
+        let a = cast_array!($A, $ARRAY_TYPE)?;
+        let b = cast_array!($B, $ARRAY_TYPE)?;
+        a.value($I)
+            .partial_cmp(&b.value($J))
+            .unwrap_or(Ordering::Equal)
+    }};
+}
+
+/// Compare two non-null values of the same type
+fn compare_values(a: &ArrayRef, i: usize, b: &ArrayRef, j: usize) -> Result<Ordering> {
+    Ok(match a.data_type() {
+        DataType::UInt8 => compare_array_values!(a, i, b, j, UInt8Array),
+        DataType::UInt16 => compare_array_values!(a, i, b, j, UInt16Array),
+        DataType::UInt32 => compare_array_values!(a, i, b, j, UInt32Array),
+        DataType::UInt64 => compare_array_values!(a, i, b, j, UInt64Array),
+        DataType::Int8 => compare_array_values!(a, i, b, j, Int8Array),
+        DataType::Int16 => compare_array_values!(a, i, b, j, Int16Array),
+        DataType::Int32 => compare_array_values!(a, i, b, j, Int32Array),
+        DataType::Int64 => compare_array_values!(a, i, b, j, Int64Array),
+        DataType::Float32 => compare_array_values!(a, i, b, j, Float32Array),
+        DataType::Float64 => compare_array_values!(a, i, b, j, Float64Array),
+        DataType::Utf8 => compare_array_values!(a, i, b, j, StringArray),
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Sort on column of type {:?}",
+                other
+            )))
+        }
+    })
+}
+
+/// Determine the partition of each row of the batches when range-partitioning them into
+/// `partition_count` partitions. The boundaries of the ranges are chosen from an evenly spaced
+/// sample of the rows, so every reader of the same batches computes the same boundaries.
+pub(crate) fn range_partitions(
+    batches: &[ColumnarBatch],
+    sort_expr: &[Expr],
+    schema: &Schema,
+    partition_count: usize,
+) -> Result<Vec<usize>> {
+    if batches.is_empty() {
+        return Ok(vec![]);
+    }
+    let keys = compile_sort_keys(sort_expr, schema)?;
+    let mut key_columns: Vec<Vec<ArrayRef>> = vec![vec![]; keys.len()];
+    for batch in batches {
+        for (k, column) in evaluate_sort_keys(&keys, batch)?.into_iter().enumerate() {
+            key_columns[k].push(column);
+        }
+    }
+    let key_columns = key_columns
+        .iter()
+        .map(|arrays| Ok(compute::concat(arrays)?))
+        .collect::<Result<Vec<ArrayRef>>>()?;
+    let num_rows = key_columns.first().map(|c| c.len()).unwrap_or(0);
+    if num_rows == 0 || partition_count <= 1 {
+        return Ok(vec![0; num_rows]);
+    }
+
+    let step = (num_rows / (partition_count * RANGE_SAMPLES_PER_PARTITION)).max(1);
+    let mut sample: Vec<u32> = (0..num_rows).step_by(step).map(|i| i as u32).collect();
+    sort_indices(&mut sample, &key_columns, &keys)?;
+    let boundaries: Vec<u32> = (1..partition_count)
+        .map(|p| sample[p * sample.len() / partition_count])
+        .collect();
+
+    (0..num_rows)
+        .map(|row| {
+            // rows equal to a boundary belong to the partition below it
+            let mut partition = 0;
+            for boundary in &boundaries {
+                let ordering =
+                    compare_rows(&key_columns, row, &key_columns, *boundary as usize, &keys)?;
+                if ordering != Ordering::Greater {
+                    break;
+                }
+                partition += 1;
+            }
+            Ok(partition)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::logicalplan::col;
+    use crate::execution::operators::test_utils::{collect, format_rows, int_batch, scan};
+    use crate::execution::operators::GlobalLimitExec;
+
+    /// Values of columns `a` and `b` of the input
+    fn columns() -> (Vec<Option<i32>>, Vec<Option<i32>>) {
+        let a = vec![
+            Some(5),
+            Some(1),
+            Some(9),
+            Some(3),
+            Some(9),
+            None,
+            Some(7),
+            Some(5),
+            Some(2),
+        ];
+        (a, (1..=9).map(Some).collect())
+    }
+
+    /// The input as three batches of three rows
+    fn batches() -> Result<Vec<RecordBatch>> {
+        let (a, b) = columns();
+        a.chunks(3)
+            .zip(b.chunks(3))
+            .map(|(a, b)| int_batch(&["a", "b"], vec![a.to_vec(), b.to_vec()]))
+            .collect()
+    }
+
+    fn input() -> Result<Arc<PhysicalPlan>> {
+        Ok(scan(&batches()?))
+    }
+
+    fn sort_expr() -> Vec<Expr> {
+        vec![
+            Expr::Sort {
+                expr: Box::new(col("a")),
+                asc: false,
+                nulls_first: false,
+            },
+            Expr::Sort {
+                expr: Box::new(col("b")),
+                asc: true,
+                nulls_first: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn top_k_matches_sort_then_limit() -> Result<()> {
+        for k in &[0, 1, 3, 8, 20] {
+            let top_k = TopKExec::try_new(input()?, sort_expr(), *k, false)?;
+            let top_k = format_rows(&collect(&PhysicalPlan::TopK(Arc::new(top_k)), 0)?)?;
+
+            let sort = SortExec::try_new(input()?, sort_expr())?;
+            let limit = GlobalLimitExec::new(Arc::new(PhysicalPlan::Sort(Arc::new(sort))), *k);
+            let sorted = format_rows(&collect(&PhysicalPlan::GlobalLimit(Arc::new(limit)), 0)?)?;
+
+            assert_eq!(sorted, top_k, "k={}", k);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn top_k_orders_rows() -> Result<()> {
+        let top_k = TopKExec::try_new(input()?, sort_expr(), 4, false)?;
+        assert_eq!(
+            vec!["9,3", "9,5", "7,7", "5,1"],
+            format_rows(&collect(&PhysicalPlan::TopK(Arc::new(top_k)), 0)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn range_partitions_sorted_separately_are_globally_ordered() -> Result<()> {
+        let batches: Vec<ColumnarBatch> =
+            batches()?.iter().map(ColumnarBatch::from_arrow).collect();
+        let schema = input()?.as_execution_plan().schema();
+        let partitions = range_partitions(&batches, &sort_expr(), &schema, 3)?;
+
+        // sort each partition on its own and read the partitions in order, as the final stage
+        // of a range-partitioned sort does
+        let (a, b) = columns();
+        let mut merged = vec![];
+        for p in 0..3 {
+            let rows: Vec<usize> = (0..a.len()).filter(|i| partitions[*i] == p).collect();
+            assert!(!rows.is_empty(), "partition {} is empty", p);
+            let batch = int_batch(
+                &["a", "b"],
+                vec![
+                    rows.iter().map(|i| a[*i]).collect(),
+                    rows.iter().map(|i| b[*i]).collect(),
+                ],
+            )?;
+            let sort = SortExec::try_new(scan(&[batch]), sort_expr())?;
+            merged.extend(format_rows(&collect(
+                &PhysicalPlan::Sort(Arc::new(sort)),
+                0,
+            )?)?);
+        }
+
+        let sort = SortExec::try_new(input()?, sort_expr())?;
+        let sorted = format_rows(&collect(&PhysicalPlan::Sort(Arc::new(sort)), 0)?)?;
+        assert_eq!(
+            vec!["9,3", "9,5", "7,7", "5,1", "5,8", "3,4", "2,9", "1,2", "NULL,6"],
+            sorted
+        );
+        assert_eq!(sorted, merged);
+        Ok(())
+    }
+}
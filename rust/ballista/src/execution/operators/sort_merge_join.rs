@@ -0,0 +1,537 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sort-merge join operator. Both inputs must already be sorted in ascending order on the join
+//! keys within each partition, and partition `i` of the left input is joined with partition
+//! `i` of the right input. Only the rows of the current key on each side are held in memory,
+//! so inputs that are too large for a hash table can be joined.
+
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+
+use crate::arrow::array::ArrayRef;
+use crate::arrow::datatypes::Schema;
+use crate::arrow::record_batch::RecordBatch;
+use crate::datafusion::logicalplan::Expr;
+use crate::error::{ballista_error, Result};
+use crate::execution::operators::hash_join::{
+    concat_batches, join_key, join_schema, take_columns, JoinKeyValue,
+};
+use crate::execution::physical_plan::{
+    CancellationToken, ColumnarBatch, ColumnarBatchIter, ColumnarBatchStream, ExecutionContext,
+    ExecutionPlan, JoinType, NullOrdering, Partitioning, PhysicalPlan, SortDirection, SortOrder,
+};
+
+use async_trait::async_trait;
+
+/// SortMergeJoinExec joins two inputs that are sorted on the join keys by advancing through
+/// both of them in step.
+#[derive(Debug)]
+pub struct SortMergeJoinExec {
+    pub(crate) left: Arc<PhysicalPlan>,
+    pub(crate) right: Arc<PhysicalPlan>,
+    /// Pairs of left and right column names that must be equal for rows to join
+    pub(crate) on: Vec<(String, String)>,
+    pub(crate) join_type: JoinType,
+    /// Maximum number of rows in each output batch
+    pub(crate) batch_size: usize,
+    schema: Arc<Schema>,
+}
+
+impl SortMergeJoinExec {
+    pub fn try_new(
+        left: Arc<PhysicalPlan>,
+        right: Arc<PhysicalPlan>,
+        on: Vec<(String, String)>,
+        join_type: JoinType,
+        batch_size: usize,
+    ) -> Result<Self> {
+        let left_partitions = left
+            .as_execution_plan()
+            .output_partitioning()
+            .partition_count();
+        let right_partitions = right
+            .as_execution_plan()
+            .output_partitioning()
+            .partition_count();
+        if left_partitions != right_partitions {
+            return Err(ballista_error(&format!(
+                "Sort-merge join inputs must have the same number of partitions but left has {} \
+                 and right has {}",
+                left_partitions, right_partitions
+            )));
+        }
+        let schema = join_schema(&left, &right, &on, &join_type)?;
+        Ok(Self {
+            left,
+            right,
+            on,
+            join_type,
+            batch_size: batch_size.max(1),
+            schema,
+        })
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> SortMergeJoinExec {
+        assert!(new_children.len() == 2);
+        SortMergeJoinExec {
+            left: new_children[0].clone(),
+            right: new_children[1].clone(),
+            on: self.on.clone(),
+            join_type: self.join_type.clone(),
+            batch_size: self.batch_size,
+            schema: self.schema.clone(),
+        }
+    }
+}
+
+fn ascending(columns: impl Iterator<Item = String>) -> Vec<SortOrder> {
+    columns
+        .map(|name| {
+            SortOrder::new(
+                Arc::new(Expr::UnresolvedColumn(name)),
+                SortDirection::Ascending,
+                NullOrdering::NullsFirst,
+            )
+        })
+        .collect()
+}
+
+#[async_trait]
+impl ExecutionPlan for SortMergeJoinExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.left.as_execution_plan().output_partitioning()
+    }
+
+    fn output_ordering(&self) -> Option<Vec<SortOrder>> {
+        match self.join_type {
+            JoinType::Inner | JoinType::Left => {
+                Some(ascending(self.on.iter().map(|(l, _)| l.clone())))
+            }
+            JoinType::Right => Some(ascending(self.on.iter().map(|(_, r)| r.clone()))),
+            // rows from either side can have null keys on the other
+            JoinType::Full => None,
+        }
+    }
+
+    fn required_child_ordering(&self) -> Option<Vec<Vec<SortOrder>>> {
+        Some(vec![
+            ascending(self.on.iter().map(|(l, _)| l.clone())),
+            ascending(self.on.iter().map(|(_, r)| r.clone())),
+        ])
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.left.clone(), self.right.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let left_schema = self.left.as_execution_plan().schema();
+        let right_schema = self.right.as_execution_plan().schema();
+        let left_keys = self
+            .on
+            .iter()
+            .map(|(l, _)| left_schema.index_of(l))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let right_keys = self
+            .on
+            .iter()
+            .map(|(_, r)| right_schema.index_of(r))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let left = self.left.execute(ctx.clone(), partition_index).await?;
+        let right = self.right.execute(ctx.clone(), partition_index).await?;
+        let state = MergeState {
+            left: Cursor::try_new(left, left_keys).await?,
+            right: Cursor::try_new(right, right_keys).await?,
+            left_schema,
+            right_schema,
+            preserve_left: matches!(self.join_type, JoinType::Left | JoinType::Full),
+            preserve_right: matches!(self.join_type, JoinType::Right | JoinType::Full),
+        };
+        Ok(Arc::new(SortMergeJoinIter {
+            schema: self.schema.clone(),
+            batch_size: self.batch_size,
+            cancellation_token: ctx.cancellation_token(),
+            state: Mutex::new(Some(state)),
+        }))
+    }
+}
+
+/// Position within one of the sorted inputs
+struct Cursor {
+    input: ColumnarBatchStream,
+    key_indices: Vec<usize>,
+    batch: Option<RecordBatch>,
+    /// Incremented for every batch read from the input, to identify the current batch
+    batch_seq: usize,
+    row: usize,
+    key: Option<Vec<JoinKeyValue>>,
+    /// Last non-null key seen, to check that the input is sorted
+    last_key: Option<Vec<JoinKeyValue>>,
+}
+
+impl Cursor {
+    async fn try_new(input: ColumnarBatchStream, key_indices: Vec<usize>) -> Result<Self> {
+        let mut cursor = Self {
+            input,
+            key_indices,
+            batch: None,
+            batch_seq: 0,
+            row: 0,
+            key: None,
+            last_key: None,
+        };
+        cursor.next_batch().await?;
+        Ok(cursor)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.batch.is_none()
+    }
+
+    async fn next_batch(&mut self) -> Result<()> {
+        self.row = 0;
+        loop {
+            match self.input.next().await? {
+                Some(batch) if batch.num_rows() == 0 => continue,
+                Some(batch) => {
+                    self.batch = Some(batch.to_arrow()?);
+                    self.batch_seq += 1;
+                    break;
+                }
+                None => {
+                    self.batch = None;
+                    break;
+                }
+            }
+        }
+        self.read_key()
+    }
+
+    async fn advance(&mut self) -> Result<()> {
+        self.row += 1;
+        match &self.batch {
+            Some(batch) if self.row < batch.num_rows() => self.read_key(),
+            _ => self.next_batch().await,
+        }
+    }
+
+    fn read_key(&mut self) -> Result<()> {
+        self.key = match &self.batch {
+            Some(batch) => {
+                let keys: Vec<ArrayRef> = self
+                    .key_indices
+                    .iter()
+                    .map(|i| batch.column(*i).clone())
+                    .collect();
+                join_key(&keys, self.row)?
+            }
+            None => None,
+        };
+        if let Some(key) = &self.key {
+            if let Some(last_key) = &self.last_key {
+                if key < last_key {
+                    return Err(ballista_error(
+                        "Input to sort-merge join is not sorted on the join keys",
+                    ));
+                }
+            }
+            self.last_key = Some(key.clone());
+        }
+        Ok(())
+    }
+}
+
+/// Batches that rows of an output batch are taken from, addressed as if they were concatenated
+#[derive(Default)]
+struct Rows {
+    batches: Vec<(usize, RecordBatch)>,
+    num_rows: usize,
+}
+
+impl Rows {
+    /// Index of the current row of a cursor within the concatenated batches
+    fn index(&mut self, cursor: &Cursor) -> Option<u32> {
+        let batch = cursor.batch.as_ref()?;
+        let offset = match self.batches.last() {
+            Some((seq, b)) if *seq == cursor.batch_seq => self.num_rows - b.num_rows(),
+            _ => {
+                self.batches.push((cursor.batch_seq, batch.clone()));
+                self.num_rows += batch.num_rows();
+                self.num_rows - batch.num_rows()
+            }
+        };
+        Some((offset + cursor.row) as u32)
+    }
+
+    fn take(&self, schema: &Schema, indices: Vec<Option<u32>>) -> Result<Vec<ArrayRef>> {
+        let batches: Vec<RecordBatch> = self.batches.iter().map(|(_, b)| b.clone()).collect();
+        let columns = concat_batches(schema, &batches)?;
+        take_columns(schema, &columns, indices)
+    }
+}
+
+struct MergeState {
+    left: Cursor,
+    right: Cursor,
+    left_schema: Arc<Schema>,
+    right_schema: Arc<Schema>,
+    preserve_left: bool,
+    preserve_right: bool,
+}
+
+impl MergeState {
+    /// Produce the next output batch of roughly `batch_size` rows, or `None` when both inputs
+    /// are exhausted
+    async fn next_batch(
+        &mut self,
+        schema: &Arc<Schema>,
+        batch_size: usize,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Option<ColumnarBatch>> {
+        let mut left_rows = Rows::default();
+        let mut right_rows = Rows::default();
+        let mut left_indices: Vec<Option<u32>> = vec![];
+        let mut right_indices: Vec<Option<u32>> = vec![];
+
+        while left_indices.len() < batch_size
+            && !(self.left.is_exhausted() && self.right.is_exhausted())
+        {
+            cancellation_token.check()?;
+            // once one side is exhausted the rest of the other side only matters if its
+            // unmatched rows are preserved
+            if (self.right.is_exhausted() && !self.preserve_left)
+                || (self.left.is_exhausted() && !self.preserve_right)
+            {
+                break;
+            }
+            let order = match (&self.left.key, &self.right.key) {
+                _ if self.right.is_exhausted() => Ordering::Less,
+                _ if self.left.is_exhausted() => Ordering::Greater,
+                // null keys never match so they are emitted as unmatched rows
+                (None, _) => Ordering::Less,
+                (_, None) => Ordering::Greater,
+                (Some(l), Some(r)) => l.cmp(r),
+            };
+            match order {
+                Ordering::Less => {
+                    if self.preserve_left {
+                        left_indices.push(left_rows.index(&self.left));
+                        right_indices.push(None);
+                    }
+                    self.left.advance().await?;
+                }
+                Ordering::Greater => {
+                    if self.preserve_right {
+                        left_indices.push(None);
+                        right_indices.push(right_rows.index(&self.right));
+                    }
+                    self.right.advance().await?;
+                }
+                Ordering::Equal => {
+                    // every row with this key on the left joins every row with it on the right
+                    let key = self.left.key.clone();
+                    let mut left_group = vec![];
+                    while !self.left.is_exhausted() && self.left.key == key {
+                        left_group.push(left_rows.index(&self.left));
+                        self.left.advance().await?;
+                    }
+                    let mut right_group = vec![];
+                    while !self.right.is_exhausted() && self.right.key == key {
+                        right_group.push(right_rows.index(&self.right));
+                        self.right.advance().await?;
+                    }
+                    for l in &left_group {
+                        for r in &right_group {
+                            left_indices.push(*l);
+                            right_indices.push(*r);
+                        }
+                    }
+                }
+            }
+        }
+
+        if left_indices.is_empty() {
+            return Ok(None);
+        }
+        let mut columns = left_rows.take(&self.left_schema, left_indices)?;
+        columns.extend(right_rows.take(&self.right_schema, right_indices)?);
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        Ok(Some(ColumnarBatch::from_arrow(&batch)))
+    }
+}
+
+struct SortMergeJoinIter {
+    schema: Arc<Schema>,
+    batch_size: usize,
+    cancellation_token: CancellationToken,
+    /// Taken out while a batch is being produced since the lock cannot be held across awaits
+    state: Mutex<Option<MergeState>>,
+}
+
+#[async_trait]
+impl ColumnarBatchIter for SortMergeJoinIter {
+    fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        let state = self.state.lock().expect("failed to lock mutex").take();
+        let mut state =
+            state.ok_or_else(|| ballista_error("Sort-merge join polled concurrently"))?;
+        let batch = state
+            .next_batch(&self.schema, self.batch_size, &self.cancellation_token)
+            .await;
+        *self.state.lock().expect("failed to lock mutex") = Some(state);
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::operators::test_utils::{collect, format_rows, int_batch, scan};
+
+    /// Left input with a null key, an unmatched key and a group of key 2 that spans batches
+    fn left() -> Result<Arc<PhysicalPlan>> {
+        Ok(scan(&[
+            int_batch(
+                &["lk", "lv"],
+                vec![
+                    vec![None, Some(1), Some(2), Some(2)],
+                    vec![Some(1), Some(2), Some(3), Some(4)],
+                ],
+            )?,
+            int_batch(
+                &["lk", "lv"],
+                vec![vec![Some(2), Some(4)], vec![Some(5), Some(6)]],
+            )?,
+        ]))
+    }
+
+    /// Right input with a null key, an unmatched key and a group of key 2 that spans batches
+    fn right() -> Result<Arc<PhysicalPlan>> {
+        Ok(scan(&[
+            int_batch(
+                &["rk", "rv"],
+                vec![vec![None, Some(2)], vec![Some(10), Some(20)]],
+            )?,
+            int_batch(
+                &["rk", "rv"],
+                vec![
+                    vec![Some(2), Some(3), Some(4)],
+                    vec![Some(30), Some(40), Some(50)],
+                ],
+            )?,
+        ]))
+    }
+
+    fn join(join_type: JoinType, batch_size: usize) -> Result<Vec<String>> {
+        let exec = SortMergeJoinExec::try_new(
+            left()?,
+            right()?,
+            vec![("lk".to_owned(), "rk".to_owned())],
+            join_type,
+            batch_size,
+        )?;
+        format_rows(&collect(&PhysicalPlan::SortMergeJoin(Arc::new(exec)), 0)?)
+    }
+
+    /// Rows of key 2, which every row of the group on the left joins with every row of the
+    /// group on the right
+    const MATCHED: &[&str] = &[
+        "2,3,2,20", "2,3,2,30", "2,4,2,20", "2,4,2,30", "2,5,2,20", "2,5,2,30",
+    ];
+
+    fn expected(before: &[&str], between: &[&str], after: &[&str]) -> Vec<String> {
+        before
+            .iter()
+            .chain(MATCHED)
+            .chain(between)
+            .chain(after)
+            .map(|row| row.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn inner_join() -> Result<()> {
+        assert_eq!(
+            expected(&[], &[], &["4,6,4,50"]),
+            join(JoinType::Inner, 1024)?
+        );
+        // smaller output batches hold the same rows
+        assert_eq!(join(JoinType::Inner, 1024)?, join(JoinType::Inner, 2)?);
+        Ok(())
+    }
+
+    #[test]
+    fn left_join() -> Result<()> {
+        assert_eq!(
+            expected(&["NULL,1,NULL,NULL", "1,2,NULL,NULL"], &[], &["4,6,4,50"]),
+            join(JoinType::Left, 1024)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn right_join() -> Result<()> {
+        assert_eq!(
+            expected(&["NULL,NULL,NULL,10"], &["NULL,NULL,3,40"], &["4,6,4,50"]),
+            join(JoinType::Right, 1024)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn full_join() -> Result<()> {
+        assert_eq!(
+            expected(
+                &["NULL,1,NULL,NULL", "NULL,NULL,NULL,10", "1,2,NULL,NULL"],
+                &["NULL,NULL,3,40"],
+                &["4,6,4,50"]
+            ),
+            join(JoinType::Full, 1024)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unsorted_input_fails() -> Result<()> {
+        let left = scan(&[int_batch(
+            &["lk", "lv"],
+            vec![vec![Some(3), Some(1)], vec![Some(1), Some(2)]],
+        )?]);
+        let exec = SortMergeJoinExec::try_new(
+            left,
+            right()?,
+            vec![("lk".to_owned(), "rk".to_owned())],
+            JoinType::Inner,
+            1024,
+        )?;
+        match collect(&PhysicalPlan::SortMergeJoin(Arc::new(exec)), 0) {
+            Err(e) => assert!(e.to_string().contains("not sorted on the join keys")),
+            Ok(_) => panic!("joining unsorted input should fail"),
+        }
+        Ok(())
+    }
+}
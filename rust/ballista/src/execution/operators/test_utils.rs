@@ -0,0 +1,102 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for tests that execute operators on in-memory data.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef, Int32Array};
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::cast_array;
+use crate::distributed::executor::{DefaultContext, DiscoveryMode, ExecutorConfig};
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::operators::InMemoryTableScanExec;
+use crate::execution::physical_plan::{ColumnarBatch, ExecutionContext, PhysicalPlan};
+
+/// Context for running operators in the test process, outside of a cluster
+pub(crate) fn test_context() -> Arc<dyn ExecutionContext> {
+    let config = ExecutorConfig::new(DiscoveryMode::Standalone, "localhost", 50051, "");
+    Arc::new(DefaultContext::new(&config, HashMap::new()))
+}
+
+/// Batch of nullable Int32 columns with the given names and values
+pub(crate) fn int_batch(names: &[&str], columns: Vec<Vec<Option<i32>>>) -> Result<RecordBatch> {
+    let schema = Schema::new(
+        names
+            .iter()
+            .map(|name| Field::new(name, DataType::Int32, true))
+            .collect(),
+    );
+    let columns: Vec<ArrayRef> = columns
+        .into_iter()
+        .map(|values| Arc::new(Int32Array::from(values)) as ArrayRef)
+        .collect();
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// Plan that scans the given batches as a single partition
+pub(crate) fn scan(batches: &[RecordBatch]) -> Arc<PhysicalPlan> {
+    Arc::new(PhysicalPlan::InMemoryTableScan(Arc::new(
+        InMemoryTableScanExec::new(batches.iter().map(ColumnarBatch::from_arrow).collect()),
+    )))
+}
+
+/// Execute one partition of a plan and collect the batches that it produces
+pub(crate) fn collect(plan: &PhysicalPlan, partition_index: usize) -> Result<Vec<RecordBatch>> {
+    smol::run(async {
+        let stream = plan.execute(test_context(), partition_index).await?;
+        let mut batches = vec![];
+        while let Some(batch) = stream.next().await? {
+            batches.push(batch.to_arrow()?);
+        }
+        Ok(batches)
+    })
+}
+
+/// Format each row of the batches as comma-separated values, with `NULL` for null values
+pub(crate) fn format_rows(batches: &[RecordBatch]) -> Result<Vec<String>> {
+    let mut rows = vec![];
+    for batch in batches {
+        for row in 0..batch.num_rows() {
+            let values = batch
+                .columns()
+                .iter()
+                .map(|column| format_value(column, row))
+                .collect::<Result<Vec<_>>>()?;
+            rows.push(values.join(","));
+        }
+    }
+    Ok(rows)
+}
+
+fn format_value(column: &ArrayRef, row: usize) -> Result<String> {
+    if column.is_null(row) {
+        return Ok("NULL".to_owned());
+    }
+    Ok(match column.data_type() {
+        DataType::Int32 => cast_array!(column, Int32Array)?.value(row).to_string(),
+        DataType::Int64 => cast_array!(column, Int64Array)?.value(row).to_string(),
+        DataType::UInt64 => cast_array!(column, UInt64Array)?.value(row).to_string(),
+        DataType::Float64 => cast_array!(column, Float64Array)?.value(row).to_string(),
+        DataType::Utf8 => cast_array!(column, StringArray)?.value(row).to_owned(),
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Formatting values of type {:?}",
+                other
+            )))
+        }
+    })
+}
@@ -0,0 +1,432 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Union operator. The partitions of the inputs are concatenated, so the union runs in the
+//! same stage as its inputs and needs no shuffle.
+//!
+//! DataFusion has no logical plan for unions, so unions are represented in logical plans by an
+//! extension node that the scheduler plans as a union. Columns are matched by position, and
+//! inputs whose columns have different numeric types are cast to a common type.
+
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::datafusion::logicalplan::{col, col_index, Expr, LogicalPlan, UserDefinedLogicalNode};
+use crate::error::{ballista_error, Result};
+use crate::execution::operators::ProjectionExec;
+use crate::execution::physical_plan::{
+    ColumnarBatchStream, ExecutionContext, ExecutionPlan, Partitioning, PhysicalPlan,
+};
+
+use async_trait::async_trait;
+
+/// UnionExec concatenates the partitions of its inputs, which all produce the same columns.
+/// The partitions of the union are those of the first input, followed by those of the second
+/// input, and so on.
+#[derive(Debug, Clone)]
+pub struct UnionExec {
+    pub(crate) inputs: Vec<Arc<PhysicalPlan>>,
+    schema: Arc<Schema>,
+}
+
+impl UnionExec {
+    /// Create a union of the inputs, casting the columns of inputs whose types differ from the
+    /// common types of the union
+    pub fn try_new(inputs: Vec<Arc<PhysicalPlan>>) -> Result<Self> {
+        let schemas: Vec<Arc<Schema>> = inputs
+            .iter()
+            .map(|input| input.as_execution_plan().schema())
+            .collect();
+        let schema = union_schema(&schemas.iter().map(|s| s.as_ref()).collect::<Vec<&Schema>>())?;
+        let inputs = inputs
+            .into_iter()
+            .zip(&schemas)
+            .map(|(input, input_schema)| coerce_input(input, input_schema, &schema))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            inputs,
+            schema: Arc::new(schema),
+        })
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> UnionExec {
+        assert!(new_children.len() == self.inputs.len());
+        UnionExec {
+            inputs: new_children,
+            schema: self.schema.clone(),
+        }
+    }
+
+    /// The input and its partition that a partition of the union reads
+    fn input_partition(&self, partition_index: usize) -> Option<(&Arc<PhysicalPlan>, usize)> {
+        let mut index = partition_index;
+        for input in &self.inputs {
+            let count = input
+                .as_execution_plan()
+                .output_partitioning()
+                .partition_count();
+            if index < count {
+                return Some((input, index));
+            }
+            index -= count;
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for UnionExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        let partition_count = self
+            .inputs
+            .iter()
+            .map(|input| {
+                input
+                    .as_execution_plan()
+                    .output_partitioning()
+                    .partition_count()
+            })
+            .sum();
+        Partitioning::UnknownPartitioning(partition_count)
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        self.inputs.clone()
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        match self.input_partition(partition_index) {
+            Some((input, index)) => input.execute(ctx, index).await,
+            None => Err(ballista_error(&format!(
+                "Union has no partition {}",
+                partition_index
+            ))),
+        }
+    }
+}
+
+/// Logical plan node that concatenates the rows of its inputs. Unless `all` is set, duplicate
+/// rows are removed.
+#[derive(Debug, Clone)]
+pub struct UnionNode {
+    pub inputs: Vec<LogicalPlan>,
+    /// Whether duplicate rows are kept, as in UNION ALL
+    pub all: bool,
+    schema: Box<Schema>,
+}
+
+impl UnionNode {
+    pub fn try_new(inputs: Vec<LogicalPlan>, all: bool) -> Result<Self> {
+        if inputs.len() < 2 {
+            return Err(ballista_error("Union requires at least two inputs"));
+        }
+        let schema = union_schema(
+            &inputs
+                .iter()
+                .map(|input| input.schema().as_ref())
+                .collect::<Vec<&Schema>>(),
+        )?;
+        Ok(Self {
+            inputs,
+            all,
+            schema: Box::new(schema),
+        })
+    }
+
+    /// Create a logical plan that unions the plans
+    pub fn into_plan(self) -> LogicalPlan {
+        LogicalPlan::Extension {
+            node: Arc::new(self),
+        }
+    }
+
+    /// The union that a logical plan consists of, if it is a union
+    pub fn from_plan(plan: &LogicalPlan) -> Option<&UnionNode> {
+        match plan {
+            LogicalPlan::Extension { node } => node.as_any().downcast_ref::<UnionNode>(),
+            _ => None,
+        }
+    }
+}
+
+impl UserDefinedLogicalNode for UnionNode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        self.inputs.iter().collect()
+    }
+
+    #[allow(clippy::borrowed_box)]
+    fn schema(&self) -> &Box<Schema> {
+        &self.schema
+    }
+
+    fn expressions(&self) -> Vec<Expr> {
+        // columns are matched by position, so every column of every input is referred to in
+        // order that the optimizer does not remove columns from some inputs but not others
+        self.inputs
+            .iter()
+            .flat_map(|input| {
+                input
+                    .schema()
+                    .fields()
+                    .iter()
+                    .map(|field| col(field.name()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn fmt_for_explain(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Union: all={}, inputs={}", self.all, self.inputs.len())
+    }
+
+    fn from_template(
+        &self,
+        _exprs: &Vec<Expr>,
+        inputs: &Vec<LogicalPlan>,
+    ) -> Arc<dyn UserDefinedLogicalNode + Send + Sync> {
+        assert!(inputs.len() == self.inputs.len());
+        let schema = union_schema(
+            &inputs
+                .iter()
+                .map(|input| input.schema().as_ref())
+                .collect::<Vec<&Schema>>(),
+        )
+        .map(Box::new)
+        .unwrap_or_else(|_| self.schema.clone());
+        Arc::new(UnionNode {
+            inputs: inputs.clone(),
+            all: self.all,
+            schema,
+        })
+    }
+}
+
+/// Determine the schema of the union of inputs with the given schemas. Columns are matched by
+/// position and take their names from the first input. A column is nullable if it is nullable
+/// in any input, and has the narrowest type that the types of the column in every input can be
+/// cast to.
+pub(crate) fn union_schema(schemas: &[&Schema]) -> Result<Schema> {
+    let first = match schemas.first() {
+        Some(first) => first,
+        None => return Err(ballista_error("Union requires at least one input")),
+    };
+    let mut fields = first.fields().clone();
+    for schema in &schemas[1..] {
+        if schema.fields().len() != fields.len() {
+            return Err(ballista_error(&format!(
+                "Union inputs have different numbers of columns: {} and {}",
+                fields.len(),
+                schema.fields().len()
+            )));
+        }
+        for (field, other) in fields.iter_mut().zip(schema.fields()) {
+            let data_type =
+                coerce_types(field.data_type(), other.data_type()).ok_or_else(|| {
+                    ballista_error(&format!(
+                        "Union column {} has incompatible types {:?} and {:?}",
+                        field.name(),
+                        field.data_type(),
+                        other.data_type()
+                    ))
+                })?;
+            *field = Field::new(
+                field.name(),
+                data_type,
+                field.is_nullable() || other.is_nullable(),
+            );
+        }
+    }
+    Ok(Schema::new(fields))
+}
+
+/// The narrowest type that values of both types can be cast to. Integers are widened to an
+/// integer type that can hold both, and integers and floats are cast to Float64.
+fn coerce_types(a: &DataType, b: &DataType) -> Option<DataType> {
+    if a == b {
+        return Some(a.clone());
+    }
+    match (integer_width(a), integer_width(b)) {
+        (Some((a_signed, a_bits)), Some((b_signed, b_bits))) => {
+            let bits = if a_signed == b_signed {
+                a_bits.max(b_bits)
+            } else {
+                // a signed type needs twice the bits to hold every value of an unsigned type
+                let (signed_bits, unsigned_bits) = if a_signed {
+                    (a_bits, b_bits)
+                } else {
+                    (b_bits, a_bits)
+                };
+                signed_bits.max(unsigned_bits * 2)
+            };
+            Some(integer_type(a_signed || b_signed, bits).unwrap_or(DataType::Float64))
+        }
+        _ if is_numeric(a) && is_numeric(b) => Some(DataType::Float64),
+        _ => None,
+    }
+}
+
+/// Whether an integer type is signed and its number of bits
+fn integer_width(data_type: &DataType) -> Option<(bool, usize)> {
+    match data_type {
+        DataType::Int8 => Some((true, 8)),
+        DataType::Int16 => Some((true, 16)),
+        DataType::Int32 => Some((true, 32)),
+        DataType::Int64 => Some((true, 64)),
+        DataType::UInt8 => Some((false, 8)),
+        DataType::UInt16 => Some((false, 16)),
+        DataType::UInt32 => Some((false, 32)),
+        DataType::UInt64 => Some((false, 64)),
+        _ => None,
+    }
+}
+
+fn integer_type(signed: bool, bits: usize) -> Option<DataType> {
+    match (signed, bits) {
+        (true, 8) => Some(DataType::Int8),
+        (true, 16) => Some(DataType::Int16),
+        (true, 32) => Some(DataType::Int32),
+        (true, 64) => Some(DataType::Int64),
+        (false, 8) => Some(DataType::UInt8),
+        (false, 16) => Some(DataType::UInt16),
+        (false, 32) => Some(DataType::UInt32),
+        (false, 64) => Some(DataType::UInt64),
+        _ => None,
+    }
+}
+
+fn is_numeric(data_type: &DataType) -> bool {
+    integer_width(data_type).is_some() || matches!(data_type, DataType::Float32 | DataType::Float64)
+}
+
+/// Cast the columns of an input to the types of the union and rename them to the names of the
+/// union, unless the input already produces them
+fn coerce_input(
+    input: Arc<PhysicalPlan>,
+    input_schema: &Schema,
+    schema: &Schema,
+) -> Result<Arc<PhysicalPlan>> {
+    let matches = input_schema
+        .fields()
+        .iter()
+        .zip(schema.fields())
+        .all(|(a, b)| a.name() == b.name() && a.data_type() == b.data_type());
+    if matches {
+        return Ok(input);
+    }
+    let expr: Vec<Expr> = input_schema
+        .fields()
+        .iter()
+        .zip(schema.fields())
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let column = if a.data_type() == b.data_type() {
+                col_index(i)
+            } else {
+                Expr::Cast {
+                    expr: Box::new(col_index(i)),
+                    data_type: b.data_type().clone(),
+                }
+            };
+            column.alias(b.name())
+        })
+        .collect();
+    let exec = ProjectionExec::try_new(&expr, input)?;
+    Ok(Arc::new(PhysicalPlan::Projection(Arc::new(exec))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{ArrayRef, Int32Array, Int64Array};
+    use crate::arrow::record_batch::RecordBatch;
+    use crate::execution::operators::InMemoryTableScanExec;
+    use crate::execution::physical_plan::ColumnarBatch;
+
+    fn scan(data_type: DataType, nullable: bool) -> Result<Arc<PhysicalPlan>> {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "a",
+            data_type.clone(),
+            nullable,
+        )]));
+        let column: ArrayRef = match data_type {
+            DataType::Int32 => Arc::new(Int32Array::from(vec![1, 2])),
+            _ => Arc::new(Int64Array::from(vec![3])),
+        };
+        let batch = RecordBatch::try_new(schema, vec![column])?;
+        Ok(Arc::new(PhysicalPlan::InMemoryTableScan(Arc::new(
+            InMemoryTableScanExec::new(vec![ColumnarBatch::from_arrow(&batch)]),
+        ))))
+    }
+
+    #[test]
+    fn coerce_column_types() {
+        assert_eq!(
+            Some(DataType::Int64),
+            coerce_types(&DataType::Int32, &DataType::Int64)
+        );
+        assert_eq!(
+            Some(DataType::Int64),
+            coerce_types(&DataType::UInt32, &DataType::Int8)
+        );
+        assert_eq!(
+            Some(DataType::Float64),
+            coerce_types(&DataType::UInt64, &DataType::Int64)
+        );
+        assert_eq!(
+            Some(DataType::Float64),
+            coerce_types(&DataType::Int32, &DataType::Float32)
+        );
+        assert_eq!(None, coerce_types(&DataType::Utf8, &DataType::Int32));
+    }
+
+    #[test]
+    fn union_casts_inputs_to_common_schema() -> Result<()> {
+        let exec = UnionExec::try_new(vec![
+            scan(DataType::Int32, false)?,
+            scan(DataType::Int64, true)?,
+        ])?;
+        let field = exec.schema().field(0).clone();
+        assert_eq!(&DataType::Int64, field.data_type());
+        assert!(field.is_nullable());
+        assert!(matches!(
+            exec.inputs[0].as_ref(),
+            PhysicalPlan::Projection(_)
+        ));
+        assert!(matches!(
+            exec.inputs[1].as_ref(),
+            PhysicalPlan::InMemoryTableScan(_)
+        ));
+
+        let other = Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Int32, false),
+        ]);
+        assert!(union_schema(&[exec.schema().as_ref(), &other]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,688 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Window operator. Window functions such as `ROW_NUMBER() OVER (PARTITION BY a ORDER BY b)` are
+//! evaluated by clustering the input on the partition keys, sorting each window partition on the
+//! order keys, and computing one value per row from the rows of its window partition.
+//!
+//! DataFusion's logical plan has no representation of window functions, so a window function is
+//! carried through the logical plan as a scalar function named `OVER` whose arguments encode the
+//! function, its arguments, the partition keys, the order keys and the window frame.
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef};
+use crate::arrow::compute;
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::cast_array;
+use crate::datafusion::logicalplan::{Expr, ScalarValue};
+use crate::error::{ballista_error, Result};
+use crate::execution::operators::hash_join::{concat_batches, take_columns};
+use crate::execution::operators::in_memory::InMemoryTableScanIter;
+use crate::execution::operators::sort::{
+    compare_rows, compile_sort_keys, evaluate_sort_keys, sort_indices,
+};
+use crate::execution::physical_plan::{
+    compile_expression, ColumnarBatch, ColumnarBatchStream, Distribution, ExecutionContext,
+    ExecutionPlan, NullOrdering, Partitioning, PhysicalPlan, SortDirection, SortOrder,
+};
+
+use async_trait::async_trait;
+
+/// Name of the scalar function that window functions are encoded as in logical plans
+const WINDOW_FUNCTION_NAME: &str = "OVER";
+
+/// Number of literal arguments that precede the expressions of an encoded window function
+const WINDOW_HEADER_LEN: usize = 5;
+
+/// Window functions
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunction {
+    /// Sequential number of the row within its window partition, starting at 1
+    RowNumber,
+    /// Rank of the row within its window partition, with gaps after ties
+    Rank,
+    /// Rank of the row within its window partition, without gaps after ties
+    DenseRank,
+    /// Aggregate function (SUM, MIN, MAX, AVG or COUNT) over the window frame of the row
+    Aggregate(String),
+}
+
+impl WindowFunction {
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            WindowFunction::RowNumber => "ROW_NUMBER",
+            WindowFunction::Rank => "RANK",
+            WindowFunction::DenseRank => "DENSE_RANK",
+            WindowFunction::Aggregate(name) => name,
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Result<Self> {
+        match name.to_uppercase().as_str() {
+            "ROW_NUMBER" => Ok(WindowFunction::RowNumber),
+            "RANK" => Ok(WindowFunction::Rank),
+            "DENSE_RANK" => Ok(WindowFunction::DenseRank),
+            "SUM" | "MIN" | "MAX" | "AVG" | "COUNT" => {
+                Ok(WindowFunction::Aggregate(name.to_uppercase()))
+            }
+            other => Err(ballista_error(&format!(
+                "Unsupported window function '{}'",
+                other
+            ))),
+        }
+    }
+
+    fn return_type(&self) -> DataType {
+        match self {
+            WindowFunction::Aggregate(name) if name != "COUNT" => DataType::Float64,
+            _ => DataType::UInt64,
+        }
+    }
+}
+
+/// Frame of rows that an aggregate window function is computed over, relative to the current
+/// row, i.e. `ROWS BETWEEN preceding PRECEDING AND following FOLLOWING`, where `None` means
+/// unbounded
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowFrame {
+    pub preceding: Option<usize>,
+    pub following: Option<usize>,
+}
+
+/// A window function together with its window specification
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowExpr {
+    pub(crate) function: WindowFunction,
+    pub(crate) args: Vec<Expr>,
+    pub(crate) partition_by: Vec<Expr>,
+    /// Sort expressions, each an `Expr::Sort`
+    pub(crate) order_by: Vec<Expr>,
+    pub(crate) frame: Option<WindowFrame>,
+    pub(crate) alias: Option<String>,
+}
+
+impl WindowExpr {
+    pub fn new(function: WindowFunction, args: Vec<Expr>) -> Self {
+        Self {
+            function,
+            args,
+            partition_by: vec![],
+            order_by: vec![],
+            frame: None,
+            alias: None,
+        }
+    }
+
+    /// Evaluate an aggregate expression, such as `sum(col("a"))`, over a window
+    pub fn aggregate(expr: Expr) -> Result<Self> {
+        match expr {
+            Expr::AggregateFunction { name, args, .. } => {
+                Ok(Self::new(WindowFunction::from_name(&name)?, args))
+            }
+            Expr::Alias(expr, alias) => Ok(Self::aggregate(*expr)?.alias(&alias)),
+            other => Err(ballista_error(&format!(
+                "Expected aggregate expression but found {:?}",
+                other
+            ))),
+        }
+    }
+
+    pub fn partition_by(mut self, partition_by: Vec<Expr>) -> Self {
+        self.partition_by = partition_by;
+        self
+    }
+
+    /// Order the rows of each window partition. Expressions that are not sort expressions sort
+    /// in ascending order with nulls last.
+    pub fn order_by(mut self, order_by: Vec<Expr>) -> Self {
+        self.order_by = order_by
+            .into_iter()
+            .map(|e| match e {
+                Expr::Sort { .. } => e,
+                _ => Expr::Sort {
+                    expr: Box::new(e),
+                    asc: true,
+                    nulls_first: false,
+                },
+            })
+            .collect();
+        self
+    }
+
+    /// Compute aggregates over the rows from `preceding` rows before the current row to
+    /// `following` rows after it, where `None` means unbounded
+    pub fn rows_between(mut self, preceding: Option<usize>, following: Option<usize>) -> Self {
+        self.frame = Some(WindowFrame {
+            preceding,
+            following,
+        });
+        self
+    }
+
+    pub fn alias(mut self, name: &str) -> Self {
+        self.alias = Some(name.to_owned());
+        self
+    }
+
+    /// The window frame, which defaults to the rows up to and including the current row when
+    /// the window is ordered and to the whole window partition otherwise
+    pub fn frame(&self) -> WindowFrame {
+        match &self.frame {
+            Some(frame) => frame.clone(),
+            None if self.order_by.is_empty() => WindowFrame {
+                preceding: None,
+                following: None,
+            },
+            None => WindowFrame {
+                preceding: None,
+                following: Some(0),
+            },
+        }
+    }
+
+    /// Name of the column that the window function produces
+    pub fn name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => format!("{}() OVER", self.function.name()),
+        }
+    }
+
+    /// Encode the window function as a logical expression
+    pub fn to_expr(&self) -> Expr {
+        let frame = self.frame();
+        let bound =
+            |b: Option<usize>| Expr::Literal(ScalarValue::Int64(b.map(|n| n as i64).unwrap_or(-1)));
+        let mut args = vec![
+            Expr::Literal(ScalarValue::Utf8(self.function.name().to_owned())),
+            Expr::Literal(ScalarValue::UInt32(self.args.len() as u32)),
+            Expr::Literal(ScalarValue::UInt32(self.partition_by.len() as u32)),
+            bound(frame.preceding),
+            bound(frame.following),
+        ];
+        args.extend(self.args.iter().cloned());
+        args.extend(self.partition_by.iter().cloned());
+        args.extend(self.order_by.iter().cloned());
+        let expr = Expr::ScalarFunction {
+            name: WINDOW_FUNCTION_NAME.to_owned(),
+            args,
+            return_type: self.function.return_type(),
+        };
+        match &self.alias {
+            Some(alias) => Expr::Alias(Box::new(expr), alias.clone()),
+            None => expr,
+        }
+    }
+
+    /// Determine whether a logical expression is an encoded window function
+    pub fn is_window_expr(expr: &Expr) -> bool {
+        match expr {
+            Expr::Alias(expr, _) => Self::is_window_expr(expr),
+            Expr::ScalarFunction { name, .. } => name == WINDOW_FUNCTION_NAME,
+            _ => false,
+        }
+    }
+
+    /// Decode a window function from a logical expression
+    pub fn try_from_expr(expr: &Expr) -> Result<Self> {
+        match expr {
+            Expr::Alias(expr, alias) => Ok(Self::try_from_expr(expr)?.alias(alias)),
+            Expr::ScalarFunction { name, args, .. }
+                if name == WINDOW_FUNCTION_NAME && args.len() >= WINDOW_HEADER_LEN =>
+            {
+                let invalid = || ballista_error(&format!("Invalid window function {:?}", expr));
+                let function = match &args[0] {
+                    Expr::Literal(ScalarValue::Utf8(name)) => WindowFunction::from_name(name)?,
+                    _ => return Err(invalid()),
+                };
+                let count = |e: &Expr| match e {
+                    Expr::Literal(ScalarValue::UInt32(n)) => Ok(*n as usize),
+                    _ => Err(invalid()),
+                };
+                let bound = |e: &Expr| match e {
+                    Expr::Literal(ScalarValue::Int64(n)) if *n < 0 => Ok(None),
+                    Expr::Literal(ScalarValue::Int64(n)) => Ok(Some(*n as usize)),
+                    _ => Err(invalid()),
+                };
+                let arg_count = count(&args[1])?;
+                let partition_count = count(&args[2])?;
+                let exprs = &args[WINDOW_HEADER_LEN..];
+                if exprs.len() < arg_count + partition_count {
+                    return Err(invalid());
+                }
+                Ok(Self {
+                    function,
+                    args: exprs[..arg_count].to_vec(),
+                    partition_by: exprs[arg_count..arg_count + partition_count].to_vec(),
+                    order_by: exprs[arg_count + partition_count..].to_vec(),
+                    frame: Some(WindowFrame {
+                        preceding: bound(&args[3])?,
+                        following: bound(&args[4])?,
+                    }),
+                    alias: None,
+                })
+            }
+            other => Err(ballista_error(&format!(
+                "Expected window function but found {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// WindowExec evaluates a window function against its input, producing the input columns
+/// followed by a column containing the result of the window function. The output of each
+/// partition is sorted on the partition keys and then the order keys.
+#[derive(Debug)]
+pub struct WindowExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    pub(crate) window_expr: WindowExpr,
+    schema: Arc<Schema>,
+}
+
+impl WindowExec {
+    pub fn try_new(child: Arc<PhysicalPlan>, window_expr: WindowExpr) -> Result<Self> {
+        let input_schema = child.as_execution_plan().schema();
+        // fail early on expressions that cannot be evaluated against the input
+        for expr in window_expr.args.iter().chain(&window_expr.partition_by) {
+            compile_expression(expr, &input_schema)?;
+        }
+        compile_sort_keys(&window_expr.order_by, &input_schema)?;
+        match &window_expr.function {
+            WindowFunction::Aggregate(_) if window_expr.args.len() != 1 => {
+                return Err(ballista_error(&format!(
+                    "Window function {} requires one argument",
+                    window_expr.function.name()
+                )))
+            }
+            _ => {}
+        }
+
+        // resolve the default frame so that it is explicit in the plan
+        let window_expr = WindowExpr {
+            frame: Some(window_expr.frame()),
+            ..window_expr
+        };
+
+        let mut fields = input_schema.fields().clone();
+        fields.push(Field::new(
+            &window_expr.name(),
+            window_expr.function.return_type(),
+            window_expr.function.return_type() == DataType::Float64,
+        ));
+        Ok(Self {
+            child,
+            window_expr,
+            schema: Arc::new(Schema::new(fields)),
+        })
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> WindowExec {
+        assert!(new_children.len() == 1);
+        WindowExec {
+            child: new_children[0].clone(),
+            window_expr: self.window_expr.clone(),
+            schema: self.schema.clone(),
+        }
+    }
+
+    /// Keys that the input is clustered on
+    /// Sort expressions that order each partition of the output, with the partition keys first
+    fn sort_expr(&self) -> Vec<Expr> {
+        self.window_expr
+            .partition_by
+            .iter()
+            .map(|e| Expr::Sort {
+                expr: Box::new(e.clone()),
+                asc: true,
+                nulls_first: true,
+            })
+            .chain(self.window_expr.order_by.iter().cloned())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for WindowExec {
+    fn schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.child.as_execution_plan().output_partitioning()
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        if self.window_expr.partition_by.is_empty() {
+            Distribution::SinglePartition
+        } else {
+            Distribution::HashClusteredDistribution {
+                required_num_partitions: self
+                    .child
+                    .as_execution_plan()
+                    .output_partitioning()
+                    .partition_count(),
+                clustering: self.window_expr.partition_by.clone(),
+            }
+        }
+    }
+
+    fn output_ordering(&self) -> Option<Vec<SortOrder>> {
+        Some(
+            self.sort_expr()
+                .iter()
+                .filter_map(|e| match e {
+                    Expr::Sort {
+                        expr,
+                        asc,
+                        nulls_first,
+                    } => Some(SortOrder::new(
+                        Arc::new(expr.as_ref().clone()),
+                        if *asc {
+                            SortDirection::Ascending
+                        } else {
+                            SortDirection::Descending
+                        },
+                        if *nulls_first {
+                            NullOrdering::NullsFirst
+                        } else {
+                            NullOrdering::NullsLast
+                        },
+                    )),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let input_schema = self.child.as_execution_plan().schema();
+        let keys = compile_sort_keys(&self.sort_expr(), &input_schema)?;
+        let partition_key_count = self.window_expr.partition_by.len();
+
+        let input = self.child.execute(ctx.clone(), partition_index).await?;
+        let mut batches = vec![];
+        while let Some(batch) = input.next().await? {
+            ctx.cancellation_token().check()?;
+            batches.push(batch.to_arrow()?);
+        }
+        let columns = concat_batches(&input_schema, &batches)?;
+        if columns.is_empty() {
+            return Ok(Arc::new(InMemoryTableScanIter::with_schema(
+                self.schema.clone(),
+                vec![],
+            )));
+        }
+
+        // sort the rows so that each window partition is contiguous and ordered
+        let batch = ColumnarBatch::from_arrow(&RecordBatch::try_new(input_schema, columns)?);
+        let key_columns = evaluate_sort_keys(&keys, &batch)?;
+        let mut indices: Vec<u32> = (0..batch.num_rows() as u32).collect();
+        sort_indices(&mut indices, &key_columns, &keys)?;
+
+        // find the window partitions and the peer groups of rows with equal order keys
+        let (partition_keys, order_keys) = key_columns.split_at(partition_key_count);
+        let (partition_sort_keys, order_sort_keys) = keys.split_at(partition_key_count);
+        let mut partition_starts = vec![0];
+        let mut peer_starts = vec![0];
+        for i in 1..indices.len() {
+            let (a, b) = (indices[i - 1] as usize, indices[i] as usize);
+            if compare_rows(partition_keys, a, partition_keys, b, partition_sort_keys)?
+                != Ordering::Equal
+            {
+                partition_starts.push(i);
+                peer_starts.push(i);
+            } else if compare_rows(order_keys, a, order_keys, b, order_sort_keys)?
+                != Ordering::Equal
+            {
+                peer_starts.push(i);
+            }
+        }
+
+        let args = self
+            .window_expr
+            .args
+            .iter()
+            .map(|e| {
+                compile_expression(e, &batch.schema())?
+                    .evaluate(&batch)?
+                    .to_arrow()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let values = evaluate_window_function(
+            &self.window_expr.function,
+            &self.window_expr.frame(),
+            &args,
+            &indices,
+            &partition_starts,
+            &peer_starts,
+        )?;
+
+        let batch = batch.to_arrow()?;
+        let mut columns = take_columns(
+            &batch.schema(),
+            batch.columns(),
+            indices.into_iter().map(Some).collect(),
+        )?;
+        columns.push(values);
+        let output = RecordBatch::try_new(self.schema.clone(), columns)?;
+        Ok(Arc::new(InMemoryTableScanIter::with_schema(
+            self.schema.clone(),
+            vec![ColumnarBatch::from_arrow(&output)],
+        )))
+    }
+}
+
+/// Compute the window function for each row, in the sorted order of the rows given by
+/// `indices`. Window partitions and peer groups are given by the sorted positions that they
+/// start at.
+fn evaluate_window_function(
+    function: &WindowFunction,
+    frame: &WindowFrame,
+    args: &[ArrayRef],
+    indices: &[u32],
+    partition_starts: &[usize],
+    peer_starts: &[usize],
+) -> Result<ArrayRef> {
+    let num_rows = indices.len();
+    let partition_ranges: Vec<(usize, usize)> = partition_starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| (*start, *partition_starts.get(i + 1).unwrap_or(&num_rows)))
+        .collect();
+
+    match function {
+        WindowFunction::RowNumber => {
+            let mut values = Vec::with_capacity(num_rows);
+            for (start, end) in &partition_ranges {
+                values.extend(1..=(end - start) as u64);
+            }
+            Ok(Arc::new(array::UInt64Array::from(values)))
+        }
+        WindowFunction::Rank | WindowFunction::DenseRank => {
+            let dense = *function == WindowFunction::DenseRank;
+            let mut values = Vec::with_capacity(num_rows);
+            let mut peers = peer_starts.iter().peekable();
+            for (start, end) in &partition_ranges {
+                let mut rank = 0;
+                let mut dense_rank = 0;
+                for i in *start..*end {
+                    if peers.peek() == Some(&&i) {
+                        peers.next();
+                        rank = (i - start + 1) as u64;
+                        dense_rank += 1;
+                    }
+                    values.push(if dense { dense_rank } else { rank });
+                }
+            }
+            Ok(Arc::new(array::UInt64Array::from(values)))
+        }
+        WindowFunction::Aggregate(name) => {
+            // the argument in sorted order, as doubles
+            let arg = compute::cast(&args[0], &DataType::Float64)?;
+            let arg = cast_array!(arg, Float64Array)?;
+            let sorted: Vec<Option<f64>> = indices
+                .iter()
+                .map(|i| {
+                    let i = *i as usize;
+                    if arg.is_null(i) {
+                        None
+                    } else {
+                        Some(arg.value(i))
+                    }
+                })
+                .collect();
+
+            let mut counts = Vec::with_capacity(num_rows);
+            let mut values = Vec::with_capacity(num_rows);
+            for (start, end) in &partition_ranges {
+                for i in *start..*end {
+                    let lo = match frame.preceding {
+                        Some(p) => i.saturating_sub(p).max(*start),
+                        None => *start,
+                    };
+                    let hi = match frame.following {
+                        Some(f) => (i + f + 1).min(*end),
+                        None => *end,
+                    };
+                    let frame_values: Vec<f64> = sorted[lo..hi].iter().filter_map(|v| *v).collect();
+                    counts.push(frame_values.len() as u64);
+                    values.push(aggregate_frame(name, &frame_values)?);
+                }
+            }
+            if name == "COUNT" {
+                Ok(Arc::new(array::UInt64Array::from(counts)))
+            } else {
+                Ok(Arc::new(array::Float64Array::from(values)))
+            }
+        }
+    }
+}
+
+/// Aggregate the non-null values of a window frame
+fn aggregate_frame(name: &str, values: &[f64]) -> Result<Option<f64>> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    let sum: f64 = values.iter().sum();
+    match name {
+        "SUM" => Ok(Some(sum)),
+        "AVG" => Ok(Some(sum / values.len() as f64)),
+        "MIN" => Ok(Some(values.iter().cloned().fold(f64::INFINITY, f64::min))),
+        "MAX" => Ok(Some(
+            values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        )),
+        "COUNT" => Ok(Some(values.len() as f64)),
+        other => Err(ballista_error(&format!(
+            "Unsupported window aggregate '{}'",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datafusion::logicalplan::col;
+    use crate::execution::operators::test_utils::{collect, format_rows, int_batch, scan};
+
+    /// Rows of (g, o, v) in two batches, with two rows of the first window partition tied on o
+    fn input() -> Result<Arc<PhysicalPlan>> {
+        Ok(scan(&[
+            int_batch(
+                &["g", "o", "v"],
+                vec![
+                    vec![Some(1), Some(2), Some(1)],
+                    vec![Some(20), Some(5), Some(10)],
+                    vec![Some(2), Some(5), Some(1)],
+                ],
+            )?,
+            int_batch(
+                &["g", "o", "v"],
+                vec![
+                    vec![Some(1), Some(1), Some(2)],
+                    vec![Some(30), Some(20), Some(5)],
+                    vec![Some(4), Some(3), Some(6)],
+                ],
+            )?,
+        ]))
+    }
+
+    /// Evaluate the window function partitioned by g and ordered by o, returning the value that
+    /// it computes for each row
+    fn window(expr: WindowExpr) -> Result<Vec<String>> {
+        let expr = expr.partition_by(vec![col("g")]).order_by(vec![col("o")]);
+        let exec = WindowExec::try_new(input()?, expr)?;
+        let rows = format_rows(&collect(&PhysicalPlan::Window(Arc::new(exec)), 0)?)?;
+        assert_eq!(
+            vec!["1,10,1", "1,20,2", "1,20,3", "1,30,4", "2,5,5", "2,5,6"],
+            rows.iter()
+                .map(|row| row.rsplitn(2, ',').nth(1).unwrap_or(""))
+                .collect::<Vec<_>>()
+        );
+        Ok(rows
+            .iter()
+            .map(|row| row.rsplit(',').next().unwrap_or("").to_owned())
+            .collect())
+    }
+
+    #[test]
+    fn row_number_and_ranks_with_ties() -> Result<()> {
+        assert_eq!(
+            vec!["1", "2", "3", "4", "1", "2"],
+            window(WindowExpr::new(WindowFunction::RowNumber, vec![]))?
+        );
+        assert_eq!(
+            vec!["1", "2", "2", "4", "1", "1"],
+            window(WindowExpr::new(WindowFunction::Rank, vec![]))?
+        );
+        assert_eq!(
+            vec!["1", "2", "2", "3", "1", "1"],
+            window(WindowExpr::new(WindowFunction::DenseRank, vec![]))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn aggregates_over_sliding_frames() -> Result<()> {
+        let aggregate = |name: &str| {
+            WindowExpr::new(WindowFunction::Aggregate(name.to_owned()), vec![col("v")])
+        };
+        assert_eq!(
+            vec!["3", "6", "9", "7", "11", "11"],
+            window(aggregate("SUM").rows_between(Some(1), Some(1)))?
+        );
+        assert_eq!(
+            vec!["1", "1.5", "2.5", "3.5", "5", "5.5"],
+            window(aggregate("AVG").rows_between(Some(1), Some(0)))?
+        );
+        // ordered windows default to the rows up to and including the current row
+        assert_eq!(
+            vec!["1", "2", "3", "4", "1", "2"],
+            window(aggregate("COUNT"))?
+        );
+        Ok(())
+    }
+}
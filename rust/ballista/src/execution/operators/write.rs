@@ -0,0 +1,330 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Write operator, which writes each partition of its input to a file in a target directory
+//! and produces a summary row per file rather than the data itself. Files are named after the
+//! partition that they contain, so a task that is retried replaces the file of its earlier
+//! attempt.
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::arrow::array::{Array, StringArray, StringBuilder, UInt64Array, UInt64Builder};
+use crate::arrow::csv;
+use crate::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::operators::in_memory::InMemoryTableScanIter;
+use crate::execution::physical_plan::{
+    ColumnarBatch, ColumnarBatchStream, ExecutionContext, ExecutionPlan, Partitioning, PhysicalPlan,
+};
+use crate::object_store;
+
+use async_trait::async_trait;
+use log::info;
+use parquet::arrow::ArrowWriter;
+
+/// File formats that query results can be written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteFormat {
+    Csv,
+    Parquet,
+}
+
+impl WriteFormat {
+    /// Name of the format, as used in serialized plans
+    pub fn name(&self) -> &'static str {
+        match self {
+            WriteFormat::Csv => "csv",
+            WriteFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "csv" => Ok(WriteFormat::Csv),
+            "parquet" => Ok(WriteFormat::Parquet),
+            other => Err(ballista_error(&format!(
+                "Unsupported file format '{}' for writing",
+                other
+            ))),
+        }
+    }
+}
+
+/// Execution plan that writes each partition of its input to a file
+#[derive(Debug, Clone)]
+pub struct WriteExec {
+    pub(crate) child: Arc<PhysicalPlan>,
+    /// Directory that the files are written to
+    pub(crate) path: String,
+    pub(crate) format: WriteFormat,
+}
+
+impl WriteExec {
+    pub fn new(child: Arc<PhysicalPlan>, path: &str, format: WriteFormat) -> Self {
+        Self {
+            child,
+            path: path.to_owned(),
+            format,
+        }
+    }
+
+    pub fn with_new_children(&self, new_children: Vec<Arc<PhysicalPlan>>) -> WriteExec {
+        assert!(new_children.len() == 1);
+        WriteExec {
+            child: new_children[0].clone(),
+            path: self.path.clone(),
+            format: self.format,
+        }
+    }
+
+    /// Schema of the summary that is produced for each file
+    pub fn summary_schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("num_rows", DataType::UInt64, false),
+            Field::new("num_bytes", DataType::UInt64, false),
+        ]))
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for WriteExec {
+    fn schema(&self) -> SchemaRef {
+        Self::summary_schema()
+    }
+
+    fn children(&self) -> Vec<Arc<PhysicalPlan>> {
+        vec![self.child.clone()]
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.child.as_execution_plan().output_partitioning()
+    }
+
+    async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        if object_store::is_remote(&self.path) {
+            return Err(BallistaError::NotImplemented(format!(
+                "Writing to object stores such as {}",
+                self.path
+            )));
+        }
+        fs::create_dir_all(&self.path)?;
+        let filename = Path::new(&self.path)
+            .join(format!(
+                "part-{:05}.{}",
+                partition_index,
+                self.format.name()
+            ))
+            .to_str()
+            .ok_or_else(|| ballista_error("Invalid path"))?
+            .to_owned();
+
+        let input = self.child.execute(ctx.clone(), partition_index).await?;
+        let mut sink: Option<FileSink> = None;
+        let mut num_rows = 0;
+        while let Some(batch) = input.next().await? {
+            ctx.cancellation_token().check()?;
+            let batch = batch.to_arrow()?;
+            num_rows += batch.num_rows() as u64;
+            if sink.is_none() {
+                sink = Some(FileSink::try_new(self.format, &filename, batch.schema())?);
+            }
+            if let Some(sink) = sink.as_mut() {
+                sink.write(&batch)?;
+            }
+        }
+        // partitions without data are written as files with no rows
+        let sink = match sink {
+            Some(sink) => sink,
+            None => FileSink::try_new(
+                self.format,
+                &filename,
+                self.child.as_execution_plan().schema(),
+            )?,
+        };
+        sink.close()?;
+        let num_bytes = fs::metadata(&filename)?.len();
+        info!(
+            "Wrote file path={} num_rows={} num_bytes={}",
+            filename, num_rows, num_bytes
+        );
+
+        let summary = WriteSummary {
+            files: vec![WrittenFile {
+                path: filename,
+                num_rows,
+                num_bytes,
+            }],
+        };
+        let batch = summary.to_batch()?;
+        Ok(Arc::new(InMemoryTableScanIter::with_schema(
+            batch.schema(),
+            vec![ColumnarBatch::from_arrow(&batch)],
+        )))
+    }
+}
+
+/// Writer for one of the supported file formats
+enum FileSink {
+    Csv(csv::Writer<File>),
+    Parquet(ArrowWriter<File>),
+}
+
+impl FileSink {
+    fn try_new(format: WriteFormat, filename: &str, schema: SchemaRef) -> Result<Self> {
+        let file = File::create(filename)?;
+        match format {
+            WriteFormat::Csv => Ok(FileSink::Csv(csv::Writer::new(file))),
+            WriteFormat::Parquet => Ok(FileSink::Parquet(
+                ArrowWriter::try_new(file, schema, None).map_err(parquet_error)?,
+            )),
+        }
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            FileSink::Csv(writer) => Ok(writer.write(batch)?),
+            FileSink::Parquet(writer) => writer.write(batch).map_err(parquet_error),
+        }
+    }
+
+    fn close(self) -> Result<()> {
+        match self {
+            // the CSV writer flushes when it is dropped
+            FileSink::Csv(_) => Ok(()),
+            FileSink::Parquet(mut writer) => writer.close().map_err(parquet_error),
+        }
+    }
+}
+
+fn parquet_error(e: parquet::errors::ParquetError) -> BallistaError {
+    BallistaError::General(format!("Error writing Parquet: {:?}", e))
+}
+
+/// A file that was written by a write operator
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrittenFile {
+    pub path: String,
+    pub num_rows: u64,
+    pub num_bytes: u64,
+}
+
+/// Summary of the files that a query wrote
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteSummary {
+    pub files: Vec<WrittenFile>,
+}
+
+impl WriteSummary {
+    /// Read a summary from the batches that write operators produce
+    pub fn try_from_batches(batches: &[RecordBatch]) -> Result<Self> {
+        let mut files = vec![];
+        for batch in batches {
+            let column = |i: usize| batch.column(i).as_any();
+            let paths = column(0).downcast_ref::<StringArray>();
+            let num_rows = column(1).downcast_ref::<UInt64Array>();
+            let num_bytes = column(2).downcast_ref::<UInt64Array>();
+            match (paths, num_rows, num_bytes) {
+                (Some(paths), Some(num_rows), Some(num_bytes)) => {
+                    for i in 0..batch.num_rows() {
+                        files.push(WrittenFile {
+                            path: paths.value(i).to_owned(),
+                            num_rows: num_rows.value(i),
+                            num_bytes: num_bytes.value(i),
+                        });
+                    }
+                }
+                _ => return Err(ballista_error("Invalid write summary")),
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Self { files })
+    }
+
+    pub fn to_batch(&self) -> Result<RecordBatch> {
+        let mut paths = StringBuilder::new(self.files.len());
+        let mut num_rows = UInt64Builder::new(self.files.len());
+        let mut num_bytes = UInt64Builder::new(self.files.len());
+        for file in &self.files {
+            paths.append_value(&file.path)?;
+            num_rows.append_value(file.num_rows)?;
+            num_bytes.append_value(file.num_bytes)?;
+        }
+        Ok(RecordBatch::try_new(
+            WriteExec::summary_schema(),
+            vec![
+                Arc::new(paths.finish()),
+                Arc::new(num_rows.finish()),
+                Arc::new(num_bytes.finish()),
+            ],
+        )?)
+    }
+
+    /// Total number of rows written
+    pub fn num_rows(&self) -> u64 {
+        self.files.iter().map(|f| f.num_rows).sum()
+    }
+
+    /// Total number of bytes written
+    pub fn num_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.num_bytes).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+
+    #[test]
+    fn write_files_and_summarize() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+
+        let dir = std::env::temp_dir().join(format!("ballista-write-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        let mut files = vec![];
+        for format in &[WriteFormat::Csv, WriteFormat::Parquet] {
+            let filename = dir.join(format!("part-00000.{}", format.name()));
+            let filename = filename.to_str().unwrap();
+            let mut sink = FileSink::try_new(*format, filename, schema.clone())?;
+            sink.write(&batch)?;
+            sink.close()?;
+            files.push(WrittenFile {
+                path: filename.to_owned(),
+                num_rows: 3,
+                num_bytes: fs::metadata(filename)?.len(),
+            });
+        }
+        assert_eq!("a\n1\n2\n3\n", fs::read_to_string(&files[0].path)?);
+
+        let summary = WriteSummary { files };
+        let parsed = WriteSummary::try_from_batches(&[summary.to_batch()?])?;
+        assert_eq!(summary, parsed);
+        assert_eq!(6, parsed.num_rows());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -23,11 +23,15 @@
 //! The physical plan also accounts for partitioning and ordering of data between operators.
 
 use std::fmt::{self, Debug};
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::arrow::array::{
-    ArrayRef, Float32Builder, Float64Builder, Int16Builder, Int32Builder, Int64Builder,
-    Int8Builder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder, UInt8Builder,
+    ArrayRef, BooleanBuilder, Float32Builder, Float64Builder, Int16Builder, Int32Builder,
+    Int64Builder, Int8Builder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder,
+    UInt8Builder,
 };
 use crate::arrow::datatypes::{DataType, Field, Schema};
 use crate::arrow::record_batch::RecordBatch;
@@ -35,24 +39,39 @@ use crate::datafusion::logicalplan::Expr;
 use crate::datafusion::logicalplan::LogicalPlan;
 use crate::datafusion::logicalplan::Operator;
 use crate::datafusion::logicalplan::ScalarValue;
-use crate::distributed::scheduler::ExecutionTask;
-use crate::error::{ballista_error, Result};
+use crate::distributed::scheduler::{ExecutionTask, QuerySettings};
+use crate::error::{ballista_error, BallistaError, Result};
 use crate::execution::expressions::{
-    add, alias, aliased_aggr, avg, col, compare, count, div, lit, max, min, mult, subtract, sum,
+    add, alias, aliased_aggr, approx_percentile, avg, between, boolean, case, cast, coalesce, col,
+    compare, count, count_distinct, decode_predicate, div, in_list, is_not_null, is_null, lit, max,
+    min, mult, not, parameter_index, scalar_function, scalar_udf, stddev, stddev_pop, subtract,
+    sum, unbound_parameter, variance, variance_pop, CaseParts, ScalarFunction,
+    BETWEEN_FUNCTION_NAME, CASE_FUNCTION_NAME, COALESCE_FUNCTION_NAME, IN_LIST_FUNCTION_NAME,
+    PARAMETER_FUNCTION_NAME,
 };
+use crate::execution::memory::TaskMemory;
 use crate::execution::operators::{
-    CsvScanExec, FilterExec, HashAggregateExec, InMemoryTableScanExec, ParquetScanExec,
-    ProjectionExec, ShuffleExchangeExec, ShuffleReaderExec,
+    AvroScanExec, CsvScanExec, FilterExec, GlobalLimitExec, HashAggregateExec, HashJoinExec,
+    InMemoryTableScanExec, IpcScanExec, JsonScanExec, LocalLimitExec, ParquetScanExec,
+    ProjectionExec, RepartitionExec, ShuffleExchangeExec, ShuffleReaderExec, SortExec,
+    SortMergeJoinExec, TopKExec, UnionExec, WindowExec, WriteExec, WriteFormat,
 };
+use crate::execution::udf::udf_registry;
 
 use crate::distributed::executor::ExecutorConfig;
+use crate::distributed::job_state::JobStateStore;
+use crate::distributed::progress::ProgressTracker;
+use crate::distributed::registry::ExecutorRegistration;
+use crate::distributed::resources::TaskResources;
+use crate::distributed::tracing::TraceContext;
 use async_trait::async_trait;
+use futures::Stream;
 use uuid::Uuid;
 
 /// Stream of columnar batches using futures
 pub type ColumnarBatchStream = Arc<dyn ColumnarBatchIter>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ExecutorMeta {
     pub id: String,
     pub host: String,
@@ -80,9 +99,237 @@ pub trait ExecutionContext: Send + Sync {
         &self,
         executor_id: ExecutorMeta,
         task: ExecutionTask,
-    ) -> Result<ShuffleId>;
+    ) -> Result<(ShuffleId, TaskMetrics)>;
     async fn read_shuffle(&self, shuffle_id: &ShuffleId) -> Result<Vec<ColumnarBatch>>;
+    async fn cancel_task(
+        &self,
+        executor_id: ExecutorMeta,
+        job_uuid: Uuid,
+        stage_id: usize,
+        partition_id: usize,
+    ) -> Result<()>;
+    /// Take back a task that is still queued on an executor, failing if it has started
+    async fn withdraw_task(
+        &self,
+        executor_id: ExecutorMeta,
+        job_uuid: Uuid,
+        stage_id: usize,
+        partition_id: usize,
+    ) -> Result<()>;
+    async fn release_job(&self, executor_id: ExecutorMeta, job_uuid: Uuid) -> Result<()>;
+    /// Keep shuffle partitions on an executor when they are fetched, until the given job and
+    /// the jobs that produced them have been released
+    async fn retain_shuffles(
+        &self,
+        executor_id: ExecutorMeta,
+        job_uuid: Uuid,
+        shuffle_ids: Vec<ShuffleId>,
+    ) -> Result<()>;
+    /// Subscribe to the transitions of the tasks of a job on an executor
+    async fn watch_tasks(
+        &self,
+        executor_id: ExecutorMeta,
+        job_uuid: Uuid,
+    ) -> Result<TaskUpdateStream>;
+    /// Cores and memory that an executor advertises for running tasks
+    async fn executor_capacity(&self, executor_id: ExecutorMeta) -> Result<TaskResources>;
+    /// Shuffle partitions that an executor holds, including ones handed off to it by executors
+    /// that have shut down
+    async fn held_shuffles(&self, executor_id: ExecutorMeta) -> Result<Vec<ShuffleId>>;
     fn config(&self) -> ExecutorConfig;
+    /// Token that operators check periodically to stop early when the task is cancelled
+    fn cancellation_token(&self) -> CancellationToken;
+    /// Collector that operators record their execution metrics in
+    fn metrics(&self) -> MetricsCollector;
+    /// Memory that operators reserve before buffering data, and spill from when it runs out
+    fn task_memory(&self) -> TaskMemory;
+    /// Number of threads that the operators of a task may use, such as to scan the row groups
+    /// of a file in parallel
+    fn parallelism(&self) -> usize;
+    /// Store that the progress of jobs is persisted in, if jobs are to survive a scheduler
+    /// restart
+    fn job_state_store(&self) -> Option<Arc<dyn JobStateStore>>;
+    /// Tracker that the progress of the stages and tasks of jobs is reported to, if clients
+    /// are watching the jobs run with this context
+    fn job_progress(&self) -> Option<ProgressTracker>;
+    /// Tenant that the jobs run with this context are scheduled as
+    fn tenant(&self) -> String;
+    /// Span of the job or task run with this context, which the spans of its stages and
+    /// shuffle fetches are children of
+    fn trace_context(&self) -> Option<TraceContext>;
+}
+
+/// Shared flag used to cancel a running task
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns an error if the task has been cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(BallistaError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Metrics for a single operator within a task
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OperatorMetrics {
+    /// Name of the operator
+    pub name: String,
+    pub output_rows: usize,
+    pub output_batches: usize,
+    /// Size in memory of the batches that the operator produced
+    pub output_bytes: usize,
+    /// Time spent producing output, including time spent in child operators
+    pub elapsed_ms: u64,
+}
+
+/// Metrics for a task, reported to the scheduler when the task completes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskMetrics {
+    pub output_rows: usize,
+    pub output_batches: usize,
+    /// Size of the shuffle partition produced by the task
+    pub shuffle_bytes: usize,
+    pub duration_ms: u64,
+    /// Metrics for each operator, in pre-order (parents before their children)
+    pub operators: Vec<OperatorMetrics>,
+    /// Number of rows in each partition of the hash-partitioned shuffle that the output of the
+    /// task feeds, or empty if the shuffle is not hash-partitioned
+    pub partition_rows: Vec<usize>,
+}
+
+impl TaskMetrics {
+    /// Add the metrics of another task that executed the same plan, e.g. to aggregate the
+    /// metrics of all tasks in a stage
+    pub fn merge(&mut self, other: &TaskMetrics) {
+        self.output_rows += other.output_rows;
+        self.output_batches += other.output_batches;
+        self.shuffle_bytes += other.shuffle_bytes;
+        self.duration_ms += other.duration_ms;
+        for (i, op) in other.operators.iter().enumerate() {
+            match self.operators.get_mut(i) {
+                Some(existing) if existing.name == op.name => {
+                    existing.output_rows += op.output_rows;
+                    existing.output_batches += op.output_batches;
+                    existing.output_bytes += op.output_bytes;
+                    existing.elapsed_ms += op.elapsed_ms;
+                }
+                _ => self.operators.push(op.clone()),
+            }
+        }
+        if self.partition_rows.len() < other.partition_rows.len() {
+            self.partition_rows.resize(other.partition_rows.len(), 0);
+        }
+        for (i, rows) in other.partition_rows.iter().enumerate() {
+            self.partition_rows[i] += rows;
+        }
+    }
+}
+
+/// Transition of a task that an executor pushes to the schedulers watching the job of the task
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskUpdate {
+    pub job_uuid: Uuid,
+    pub stage_id: usize,
+    pub partition_id: usize,
+    /// Attempt of the task that transitioned
+    pub attempt: usize,
+    pub state: TaskUpdateState,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskUpdateState {
+    Running,
+    Completed(TaskMetrics),
+    /// The task failed for the given reason
+    Failed(String),
+    Cancelled,
+}
+
+/// Stream of the transitions of the tasks of a job on an executor
+pub type TaskUpdateStream = Pin<Box<dyn Stream<Item = Result<TaskUpdate>> + Send>>;
+
+/// Collects operator metrics while a task executes
+#[derive(Debug, Clone, Default)]
+pub struct MetricsCollector {
+    operators: Arc<Mutex<Vec<OperatorMetrics>>>,
+}
+
+impl MetricsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an operator and return the index to record its metrics against
+    fn register(&self, name: &str) -> usize {
+        let mut operators = self.operators.lock().expect("failed to lock mutex");
+        operators.push(OperatorMetrics {
+            name: name.to_owned(),
+            ..OperatorMetrics::default()
+        });
+        operators.len() - 1
+    }
+
+    /// Record time spent in an operator and the batch that it produced, if any
+    fn record(&self, index: usize, batch: Option<&ColumnarBatch>, elapsed: Duration) {
+        let mut operators = self.operators.lock().expect("failed to lock mutex");
+        let op = &mut operators[index];
+        if let Some(batch) = batch {
+            op.output_rows += batch.num_rows();
+            op.output_batches += 1;
+            op.output_bytes += batch.memory_size();
+        }
+        op.elapsed_ms += elapsed.as_millis() as u64;
+    }
+
+    /// Get the metrics recorded so far
+    pub fn operators(&self) -> Vec<OperatorMetrics> {
+        self.operators.lock().expect("failed to lock mutex").clone()
+    }
+}
+
+/// Wraps the output of an operator to record its metrics
+struct MetricsIter {
+    input: ColumnarBatchStream,
+    metrics: MetricsCollector,
+    index: usize,
+}
+
+#[async_trait]
+impl ColumnarBatchIter for MetricsIter {
+    fn schema(&self) -> Arc<Schema> {
+        self.input.schema()
+    }
+
+    async fn next(&self) -> Result<Option<ColumnarBatch>> {
+        let start = Instant::now();
+        let batch = self.input.next().await?;
+        self.metrics
+            .record(self.index, batch.as_ref(), start.elapsed());
+        Ok(batch)
+    }
+
+    async fn close(&self) {
+        self.input.close().await
+    }
 }
 
 /// Base trait for all operators
@@ -164,6 +411,20 @@ pub trait AggregateExpr: Send + Sync + Debug {
             self.nullable(input_schema)?,
         ))
     }
+    /// Get the schema fields for the intermediate state that partial aggregation produces for
+    /// this expression. By default the state is the same as the final value.
+    fn state_fields(&self, input_schema: &Schema) -> Result<Vec<Field>> {
+        Ok(vec![self.to_schema_field(input_schema)?])
+    }
+    /// Evaluate the intermediate state columns that are merged by final aggregation
+    fn evaluate_state(&self, batch: &ColumnarBatch) -> Result<Vec<ColumnarValue>> {
+        Ok(vec![self.evaluate_input(batch)?])
+    }
+    /// Whether the intermediate state of the accumulators can be merged, which hash aggregates
+    /// rely on to spill groups to disk
+    fn mergeable(&self) -> bool {
+        true
+    }
 }
 
 /// Aggregate accumulator
@@ -172,17 +433,120 @@ pub trait Accumulator: Send + Sync {
     fn accumulate(&mut self, value: &ColumnarValue) -> Result<()>;
     /// Get the final value for the accumulator
     fn get_value(&self) -> Result<Option<ScalarValue>>;
+    /// Get the intermediate state of the accumulator, with one value per state field
+    fn state(&self) -> Result<Vec<Option<ScalarValue>>> {
+        Ok(vec![self.get_value()?])
+    }
+    /// Merge the intermediate state produced by a partial aggregation into this accumulator
+    fn merge(&mut self, state: &[ColumnarValue]) -> Result<()> {
+        match &state[0] {
+            ColumnarValue::Scalar(None, _) => Ok(()),
+            value => self.accumulate(value),
+        }
+    }
 }
 
 /// Action that can be sent to an executor
 #[derive(Debug, Clone)]
 pub enum Action {
     /// Execute the query with DataFusion and return the results
-    InteractiveQuery { plan: LogicalPlan },
+    InteractiveQuery {
+        plan: LogicalPlan,
+        settings: QuerySettings,
+    },
     /// Execute a query and store the results in memory
     Execute(ExecutionTask),
     /// Collect a shuffle
     FetchShuffle(ShuffleId),
+    /// Manage the executor
+    Manage(ExecutorAction),
+    /// Cancel a task that was previously submitted with `Execute`
+    CancelTask {
+        job_uuid: Uuid,
+        stage_id: usize,
+        partition_id: usize,
+    },
+    /// Remove a task that was previously submitted with `Execute` and is still queued, so that
+    /// it can be submitted to another executor. Fails if the task has started.
+    WithdrawTask {
+        job_uuid: Uuid,
+        stage_id: usize,
+        partition_id: usize,
+    },
+    /// Remove all state associated with a completed job
+    ReleaseJob(Uuid),
+    /// Keep shuffle partitions when they are fetched, until both the given job and the jobs
+    /// that produced them have been released, so that the job can read the output of stages
+    /// of other jobs
+    RetainShuffles {
+        job_uuid: Uuid,
+        shuffle_ids: Vec<ShuffleId>,
+    },
+    /// Announce an executor to the registry
+    RegisterExecutor(ExecutorRegistration),
+    /// Tell the registry that an executor is still alive
+    Heartbeat { executor_id: String },
+    /// Remove an executor that is shutting down from the registry
+    DeregisterExecutor { executor_id: String },
+    /// List the executors known to the registry
+    ListExecutors,
+    /// Register a named table with the executor so that queries can refer to it by name
+    RegisterTable { name: String, plan: LogicalPlan },
+    /// Execute the query and write each partition of the results to a file in a directory,
+    /// returning a summary of the files rather than the results
+    Write {
+        plan: LogicalPlan,
+        path: String,
+        format: WriteFormat,
+        settings: QuerySettings,
+    },
+    /// Execute the query and return statistics of its results rather than the results. The
+    /// executor keeps statistics of scans of whole tables for planning later queries.
+    Analyze {
+        plan: LogicalPlan,
+        settings: QuerySettings,
+    },
+    /// Describe the logical, physical, and distributed plans of the query rather than returning
+    /// its results. With `analyze`, the query is executed so that each operator in the
+    /// distributed plan is annotated with its metrics.
+    Explain {
+        plan: LogicalPlan,
+        analyze: bool,
+        settings: QuerySettings,
+    },
+    /// Stream the transitions of the tasks of a job, so that the scheduler learns of them
+    /// without polling
+    WatchTasks(Uuid),
+    /// Plan the query once so that it can be executed repeatedly with different values bound
+    /// to its parameters, returning a handle to the prepared query
+    Prepare {
+        plan: LogicalPlan,
+        settings: QuerySettings,
+    },
+    /// Execute a prepared query with the values bound to its parameters, in order
+    ExecutePrepared {
+        handle: String,
+        params: Vec<ScalarValue>,
+    },
+    /// Discard a prepared query
+    ClosePrepared(String),
+}
+
+/// Management action that can be sent to an executor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutorAction {
+    /// Stop accepting new tasks, wait for accepted tasks to complete for the shutdown grace
+    /// period and cancel the rest, hand off shuffle partitions to a peer if configured, leave
+    /// the cluster, and then shut down
+    Shutdown,
+    /// Stop accepting new tasks but keep running accepted tasks and serving shuffle partitions
+    Drain,
+    /// Discard all shuffle partitions held by the executor
+    ClearShuffleCache,
+    /// Report executor statistics
+    Stats,
+    /// List the shuffle partitions held by the executor
+    ListShuffles,
 }
 
 pub type MaybeColumnarBatch = Result<Option<ColumnarBatch>>;
@@ -301,6 +665,9 @@ impl ColumnarValue {
                 ScalarValue::Int64(_) => &DataType::Int64,
                 ScalarValue::Float32(_) => &DataType::Float32,
                 ScalarValue::Float64(_) => &DataType::Float64,
+                ScalarValue::Utf8(_) => &DataType::Utf8,
+                ScalarValue::Boolean(_) => &DataType::Boolean,
+                ScalarValue::Null => &DataType::Null,
                 _ => unimplemented!(),
             },
             _ => unimplemented!(),
@@ -322,6 +689,7 @@ impl ColumnarValue {
                 ScalarValue::Float32(value) => build_literal_array!(*n, Float32Builder, *value),
                 ScalarValue::Float64(value) => build_literal_array!(*n, Float64Builder, *value),
                 ScalarValue::Utf8(value) => build_literal_array!(*n, StringBuilder, value),
+                ScalarValue::Boolean(value) => build_literal_array!(*n, BooleanBuilder, *value),
                 other => Err(ballista_error(&format!(
                     "Unsupported literal type {:?}",
                     other
@@ -357,29 +725,117 @@ pub enum PhysicalPlan {
     Filter(Arc<FilterExec>),
     /// Hash aggregate
     HashAggregate(Arc<HashAggregateExec>),
+    /// Partitioned hash join
+    HashJoin(Arc<HashJoinExec>),
+    /// Sorts each partition
+    Sort(Arc<SortExec>),
+    /// Keeps the first rows of each partition in sort order
+    TopK(Arc<TopKExec>),
+    /// Limits the number of rows of a single partition
+    GlobalLimit(Arc<GlobalLimitExec>),
+    /// Limits the number of rows of each partition
+    LocalLimit(Arc<LocalLimitExec>),
+    /// Evaluates a window function
+    Window(Arc<WindowExec>),
+    /// Join of inputs that are sorted on the join keys
+    SortMergeJoin(Arc<SortMergeJoinExec>),
+    /// Concatenates the partitions of its inputs
+    Union(Arc<UnionExec>),
     /// Performs a shuffle that will result in the desired partitioning.
     ShuffleExchange(Arc<ShuffleExchangeExec>),
+    /// Redistributes rows evenly across a different number of partitions
+    Repartition(Arc<RepartitionExec>),
     /// Reads results from a ShuffleExchange
     ShuffleReader(Arc<ShuffleReaderExec>),
     /// Scans a partitioned Parquet data source
     ParquetScan(Arc<ParquetScanExec>),
     /// Scans a partitioned CSV data source
     CsvScan(Arc<CsvScanExec>),
+    /// Scans a partitioned newline-delimited JSON data source
+    JsonScan(Arc<JsonScanExec>),
+    /// Scans a partitioned Avro data source
+    AvroScan(Arc<AvroScanExec>),
+    /// Scans a partitioned Arrow IPC data source
+    IpcScan(Arc<IpcScanExec>),
     /// Scans an in-memory table
     InMemoryTableScan(Arc<InMemoryTableScanExec>),
+    /// Writes each partition to a file
+    Write(Arc<WriteExec>),
 }
 
 impl PhysicalPlan {
+    /// Name of the operator, as reported in metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Projection(_) => "Projection",
+            Self::Filter(_) => "Filter",
+            Self::HashAggregate(_) => "HashAggregate",
+            Self::HashJoin(_) => "HashJoin",
+            Self::Sort(_) => "Sort",
+            Self::TopK(_) => "TopK",
+            Self::GlobalLimit(_) => "GlobalLimit",
+            Self::LocalLimit(_) => "LocalLimit",
+            Self::Window(_) => "Window",
+            Self::SortMergeJoin(_) => "SortMergeJoin",
+            Self::Union(_) => "Union",
+            Self::ParquetScan(_) => "ParquetScan",
+            Self::CsvScan(_) => "CsvScan",
+            Self::JsonScan(_) => "JsonScan",
+            Self::AvroScan(_) => "AvroScan",
+            Self::IpcScan(_) => "IpcScan",
+            Self::ShuffleExchange(_) => "ShuffleExchange",
+            Self::Repartition(_) => "Repartition",
+            Self::ShuffleReader(_) => "ShuffleReader",
+            Self::InMemoryTableScan(_) => "InMemoryTableScan",
+            Self::Write(_) => "Write",
+        }
+    }
+
+    /// Execute this plan against one partition, recording the metrics of the operator in the
+    /// context's metrics collector
+    pub async fn execute(
+        &self,
+        ctx: Arc<dyn ExecutionContext>,
+        partition_index: usize,
+    ) -> Result<ColumnarBatchStream> {
+        let metrics = ctx.metrics();
+        let index = metrics.register(self.name());
+        let start = Instant::now();
+        let input = self
+            .as_execution_plan()
+            .execute(ctx, partition_index)
+            .await?;
+        metrics.record(index, None, start.elapsed());
+        Ok(Arc::new(MetricsIter {
+            input,
+            metrics,
+            index,
+        }))
+    }
+
     pub fn as_execution_plan(&self) -> Arc<dyn ExecutionPlan> {
         match self {
             Self::Projection(exec) => exec.clone(),
             Self::Filter(exec) => exec.clone(),
             Self::HashAggregate(exec) => exec.clone(),
+            Self::HashJoin(exec) => exec.clone(),
+            Self::Sort(exec) => exec.clone(),
+            Self::TopK(exec) => exec.clone(),
+            Self::GlobalLimit(exec) => exec.clone(),
+            Self::LocalLimit(exec) => exec.clone(),
+            Self::Window(exec) => exec.clone(),
+            Self::SortMergeJoin(exec) => exec.clone(),
+            Self::Union(exec) => exec.clone(),
             Self::ParquetScan(exec) => exec.clone(),
             Self::CsvScan(exec) => exec.clone(),
+            Self::JsonScan(exec) => exec.clone(),
+            Self::AvroScan(exec) => exec.clone(),
+            Self::IpcScan(exec) => exec.clone(),
             Self::ShuffleExchange(exec) => exec.clone(),
+            Self::Repartition(exec) => exec.clone(),
             Self::ShuffleReader(exec) => exec.clone(),
             Self::InMemoryTableScan(exec) => exec.clone(),
+            Self::Write(exec) => exec.clone(),
         }
     }
 
@@ -388,6 +844,28 @@ impl PhysicalPlan {
             Self::HashAggregate(exec) => {
                 Self::HashAggregate(Arc::new(exec.with_new_children(new_children)))
             }
+            Self::HashJoin(exec) => Self::HashJoin(Arc::new(exec.with_new_children(new_children))),
+            Self::Sort(exec) => Self::Sort(Arc::new(exec.with_new_children(new_children))),
+            Self::TopK(exec) => Self::TopK(Arc::new(exec.with_new_children(new_children))),
+            Self::GlobalLimit(exec) => {
+                Self::GlobalLimit(Arc::new(exec.with_new_children(new_children)))
+            }
+            Self::LocalLimit(exec) => {
+                Self::LocalLimit(Arc::new(exec.with_new_children(new_children)))
+            }
+            Self::Window(exec) => Self::Window(Arc::new(exec.with_new_children(new_children))),
+            Self::Filter(exec) => Self::Filter(Arc::new(exec.with_new_children(new_children))),
+            Self::Projection(exec) => {
+                Self::Projection(Arc::new(exec.with_new_children(new_children)))
+            }
+            Self::SortMergeJoin(exec) => {
+                Self::SortMergeJoin(Arc::new(exec.with_new_children(new_children)))
+            }
+            Self::Union(exec) => Self::Union(Arc::new(exec.with_new_children(new_children))),
+            Self::Write(exec) => Self::Write(Arc::new(exec.with_new_children(new_children))),
+            Self::Repartition(exec) => {
+                Self::Repartition(Arc::new(exec.with_new_children(new_children)))
+            }
             _ => unimplemented!(),
         }
     }
@@ -399,6 +877,34 @@ impl PhysicalPlan {
                 write!(f, "  ")?;
             }
         }
+        self.fmt_operator(f)?;
+        for child in self.display_children() {
+            child.fmt_with_indent(f, indent + 1)?;
+        }
+        Ok(())
+    }
+
+    /// The inputs of this operator, including the input of a shuffle, which is not a child
+    /// because it runs in a separate stage
+    pub fn display_children(&self) -> Vec<Arc<PhysicalPlan>> {
+        match self {
+            PhysicalPlan::ShuffleExchange(exec) => vec![exec.child.clone()],
+            _ => self.as_execution_plan().children(),
+        }
+    }
+
+    /// Describe this operator, without its inputs
+    pub fn describe_operator(&self) -> String {
+        struct OperatorDescription<'a>(&'a PhysicalPlan);
+        impl fmt::Display for OperatorDescription<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt_operator(f)
+            }
+        }
+        OperatorDescription(self).to_string()
+    }
+
+    fn fmt_operator(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             PhysicalPlan::CsvScan(exec) => write!(
                 f,
@@ -407,30 +913,83 @@ impl PhysicalPlan {
                 exec.filenames.len(),
                 exec.projection
             ),
-            PhysicalPlan::ParquetScan(exec) => write!(
+            PhysicalPlan::JsonScan(exec) => write!(
+                f,
+                "JsonScan: {:?}, partitions={}; projection={:?}",
+                exec.path,
+                exec.splits.len(),
+                exec.projection
+            ),
+            PhysicalPlan::AvroScan(exec) => write!(
+                f,
+                "AvroScan: {:?}, partitions={}; projection={:?}",
+                exec.path,
+                exec.splits.len(),
+                exec.projection
+            ),
+            PhysicalPlan::IpcScan(exec) => write!(
                 f,
-                "ParquetScan: {:?}, partitions={}; projection={:?}",
+                "IpcScan: {:?}, partitions={}; projection={:?}",
                 exec.path,
                 exec.filenames.len(),
                 exec.projection
             ),
-            PhysicalPlan::HashAggregate(exec) => {
-                write!(
-                    f,
-                    "HashAggregate: mode={:?}, groupExpr={:?}, aggrExpr={:?}",
-                    exec.mode, exec.group_expr, exec.aggr_expr
-                )?;
-                exec.child.fmt_with_indent(f, indent + 1)
-            }
+            PhysicalPlan::ParquetScan(exec) => write!(
+                f,
+                "ParquetScan: {:?}, partitions={}; projection={:?}; predicate={:?}",
+                exec.path,
+                exec.filenames.len(),
+                exec.projection,
+                exec.predicate
+            ),
+            PhysicalPlan::HashAggregate(exec) => write!(
+                f,
+                "HashAggregate: mode={:?}, groupExpr={:?}, aggrExpr={:?}",
+                exec.mode, exec.group_expr, exec.aggr_expr
+            ),
+            PhysicalPlan::HashJoin(exec) => write!(
+                f,
+                "HashJoin: joinType={:?}, on={:?}, partitions={}, broadcast={}",
+                exec.join_type, exec.on, exec.partition_count, exec.broadcast
+            ),
+            PhysicalPlan::Sort(exec) => write!(f, "Sort: sortExpr={:?}", exec.sort_expr),
+            PhysicalPlan::TopK(exec) => write!(
+                f,
+                "TopK: k={}, partial={}, sortExpr={:?}",
+                exec.k, exec.partial, exec.sort_expr
+            ),
+            PhysicalPlan::GlobalLimit(exec) => write!(f, "GlobalLimit: limit={}", exec.limit),
+            PhysicalPlan::LocalLimit(exec) => write!(f, "LocalLimit: limit={}", exec.limit),
+            PhysicalPlan::Window(exec) => write!(f, "Window: windowExpr={:?}", exec.window_expr),
+            PhysicalPlan::SortMergeJoin(exec) => write!(
+                f,
+                "SortMergeJoin: joinType={:?}, on={:?}",
+                exec.join_type, exec.on
+            ),
+            PhysicalPlan::Union(exec) => write!(
+                f,
+                "Union: partitions={}",
+                exec.output_partitioning().partition_count()
+            ),
             PhysicalPlan::ShuffleExchange(exec) => {
-                write!(f, "Shuffle: {:?}", exec.as_ref().output_partitioning())?;
-                exec.as_ref().child.fmt_with_indent(f, indent + 1)
+                write!(f, "Shuffle: {:?}", exec.as_ref().output_partitioning())
             }
-            PhysicalPlan::ShuffleReader(exec) => {
-                write!(f, "ShuffleReader: shuffle_id={:?}", exec.shuffle_id)
+            PhysicalPlan::Repartition(exec) => {
+                write!(f, "Repartition: partitions={}", exec.partition_count)
             }
-            PhysicalPlan::Projection(_exec) => write!(f, "Projection:"),
-            PhysicalPlan::Filter(_exec) => write!(f, "Filter:"),
+            PhysicalPlan::ShuffleReader(exec) => write!(
+                f,
+                "ShuffleReader: shuffle_id={:?}, partitioning={:?}",
+                exec.shuffle_id, exec.partitioning
+            ),
+            PhysicalPlan::Write(exec) => write!(
+                f,
+                "Write: path={:?}, format={}",
+                exec.path,
+                exec.format.name()
+            ),
+            PhysicalPlan::Projection(exec) => write!(f, "Projection: expr={:?}", exec.expr),
+            PhysicalPlan::Filter(exec) => write!(f, "Filter: expr={:?}", exec.filter_expr),
             _ => write!(f, "???"),
         }
     }
@@ -461,6 +1020,9 @@ pub enum Distribution {
 #[derive(Debug, Clone)]
 pub enum JoinType {
     Inner,
+    Left,
+    Right,
+    Full,
 }
 
 #[derive(Debug, Clone)]
@@ -486,6 +1048,10 @@ pub enum AggregateMode {
     /// Perform complete aggregation in one pass. This is used when there is only a single
     /// partition to operate on.
     Complete,
+    /// Perform final aggregation on input that has been hash partitioned on the grouping
+    /// expressions, producing one output partition per input partition. This is used for
+    /// aggregates such as COUNT(DISTINCT) that cannot be merged from partial results.
+    FinalPartitioned,
 }
 
 #[derive(Debug, Clone)]
@@ -495,6 +1061,16 @@ pub struct SortOrder {
     null_ordering: NullOrdering,
 }
 
+impl SortOrder {
+    pub fn new(child: Arc<Expr>, direction: SortDirection, null_ordering: NullOrdering) -> Self {
+        Self {
+            child,
+            direction,
+            null_ordering,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum NullOrdering {
     NullsFirst,
@@ -506,6 +1082,9 @@ pub enum NullOrdering {
 pub enum Partitioning {
     UnknownPartitioning(usize),
     HashPartitioning(usize, Vec<Arc<Expr>>),
+    /// Partitioning into ranges of the values of sort expressions (each an `Expr::Sort`), so
+    /// that every row of a partition sorts before every row of the next partition
+    RangePartitioning(usize, Vec<Arc<Expr>>),
 }
 
 impl Partitioning {
@@ -514,6 +1093,7 @@ impl Partitioning {
         match self {
             UnknownPartitioning(n) => *n,
             HashPartitioning(n, _) => *n,
+            RangePartitioning(n, _) => *n,
         }
     }
 }
@@ -536,7 +1116,21 @@ impl ShuffleId {
     }
 }
 
-pub struct ShuffleLocation {}
+/// Location of a shuffle partition within the cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShuffleLocation {
+    pub shuffle_id: ShuffleId,
+    pub executor_meta: ExecutorMeta,
+}
+
+impl ShuffleLocation {
+    pub fn new(shuffle_id: ShuffleId, executor_meta: ExecutorMeta) -> Self {
+        Self {
+            shuffle_id,
+            executor_meta,
+        }
+    }
+}
 
 /// Translate a logical expression into a physical expression that can be evaluated against
 /// input data.
@@ -560,12 +1154,98 @@ pub fn compile_expression(expr: &Expr, input: &Schema) -> Result<Arc<dyn Express
                 | Operator::GtEq
                 | Operator::Eq
                 | Operator::NotEq => Ok(compare(l, op, r)),
+                Operator::And | Operator::Or => Ok(boolean(l, op, r)),
                 other => Err(ballista_error(&format!(
                     "Unsupported binary operator in compile_expression {:?}",
                     other
                 ))),
             }
         }
+        Expr::Cast { expr, data_type } => {
+            Ok(cast(compile_expression(expr, input)?, data_type.clone()))
+        }
+        Expr::IsNull(expr) => Ok(is_null(compile_expression(expr, input)?)),
+        Expr::IsNotNull(expr) => Ok(is_not_null(compile_expression(expr, input)?)),
+        Expr::Not(expr) => Ok(not(compile_expression(expr, input)?)),
+        Expr::ScalarFunction { name, args, .. } if name == CASE_FUNCTION_NAME => {
+            let parts = CaseParts::try_from_args(args)?;
+            let expr = match &parts.expr {
+                Some(expr) => Some(compile_expression(expr, input)?),
+                None => None,
+            };
+            let when_then_expr = parts
+                .when_then_expr
+                .iter()
+                .map(|(when, then)| {
+                    Ok((
+                        compile_expression(when, input)?,
+                        compile_expression(then, input)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let else_expr = match &parts.else_expr {
+                Some(else_expr) => Some(compile_expression(else_expr, input)?),
+                None => None,
+            };
+            let expr = case(expr, when_then_expr, else_expr)?;
+            // fail early on results that have no type rather than during execution
+            expr.data_type(input)?;
+            Ok(expr)
+        }
+        Expr::ScalarFunction { name, args, .. } if name == IN_LIST_FUNCTION_NAME => {
+            let (negated, expr, list) = decode_predicate(args)?;
+            Ok(in_list(
+                compile_expression(expr, input)?,
+                compile_expressions(list, input)?,
+                negated,
+            ))
+        }
+        Expr::ScalarFunction { name, args, .. } if name == BETWEEN_FUNCTION_NAME => {
+            match decode_predicate(args)? {
+                (negated, expr, [low, high]) => Ok(between(
+                    compile_expression(expr, input)?,
+                    compile_expression(low, input)?,
+                    compile_expression(high, input)?,
+                    negated,
+                )),
+                _ => Err(ballista_error("BETWEEN requires a low and a high bound")),
+            }
+        }
+        Expr::ScalarFunction { name, .. } if name == PARAMETER_FUNCTION_NAME => {
+            match parameter_index(expr) {
+                Some((index, data_type)) => Ok(unbound_parameter(index, data_type.clone())),
+                None => Err(ballista_error("PARAMETER requires an index")),
+            }
+        }
+        Expr::ScalarFunction { name, args, .. } if name == COALESCE_FUNCTION_NAME => {
+            let expr = coalesce(compile_expressions(args, input)?)?;
+            expr.data_type(input)?;
+            Ok(expr)
+        }
+        Expr::ScalarFunction { name, args, .. } => match ScalarFunction::from_name(name) {
+            Some(fun) => {
+                let expr = scalar_function(fun, compile_expressions(args, input)?);
+                // fail early on invalid arguments rather than during execution
+                expr.data_type(input)?;
+                Ok(expr)
+            }
+            None => {
+                let registry = udf_registry();
+                match (registry.signature(name), registry.implementation(name)) {
+                    (Some(signature), Some(fun)) => {
+                        scalar_udf(signature, fun, compile_expressions(args, input)?)
+                    }
+                    (Some(_), None) => Err(ballista_error(&format!(
+                        "No implementation of UDF '{}' is registered on this executor",
+                        name
+                    ))),
+                    _ => Err(ballista_error(&format!(
+                        "Unsupported scalar function in compile_expression '{}'",
+                        name
+                    ))),
+                }
+            }
+        },
         other => Err(ballista_error(&format!(
             "Unsupported expression in compile_expression {:?}",
             other
@@ -591,11 +1271,24 @@ pub fn compile_aggregate_expression(
             alias,
         )),
         Expr::AggregateFunction { name, args, .. } => match name.to_lowercase().as_ref() {
-            "avg" => Ok(avg(compile_expression(&args[0], input_schema)?)),
+            "avg" => Ok(avg(compile_expressions(args, input_schema)?)),
             "count" => Ok(count(compile_expression(&args[0], input_schema)?)),
+            "count_distinct" => Ok(count_distinct(compile_expression(&args[0], input_schema)?)),
             "max" => Ok(max(compile_expression(&args[0], input_schema)?)),
             "min" => Ok(min(compile_expression(&args[0], input_schema)?)),
             "sum" => Ok(sum(compile_expression(&args[0], input_schema)?)),
+            "var" => Ok(variance(compile_expressions(args, input_schema)?)),
+            "var_pop" => Ok(variance_pop(compile_expressions(args, input_schema)?)),
+            "stddev" => Ok(stddev(compile_expressions(args, input_schema)?)),
+            "stddev_pop" => Ok(stddev_pop(compile_expressions(args, input_schema)?)),
+            "approx_percentile" => match args.last() {
+                Some(Expr::Literal(ScalarValue::Float64(percentile))) if args.len() == 2 => {
+                    approx_percentile(compile_expression(&args[0], input_schema)?, *percentile)
+                }
+                _ => Err(ballista_error(
+                    "APPROX_PERCENTILE requires an expression and a percentile literal",
+                )),
+            },
             other => Err(ballista_error(&format!(
                 "Unsupported aggregate function in compile_aggregate_expression '{}'",
                 other
@@ -0,0 +1,144 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Temporary files that operators spill batches to in Arrow IPC format when their memory
+//! reservation cannot grow.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::arrow::datatypes::Schema;
+use crate::arrow::ipc::reader::FileReader;
+use crate::arrow::ipc::writer::FileWriter;
+use crate::arrow::record_batch::RecordBatch;
+use crate::error::{BallistaError, Result};
+use crate::execution::physical_plan::ColumnarBatch;
+
+use log::warn;
+use uuid::Uuid;
+
+/// Writes batches to a new spill file
+pub struct SpillWriter {
+    writer: FileWriter<File>,
+    path: PathBuf,
+    num_rows: usize,
+    num_bytes: usize,
+}
+
+impl SpillWriter {
+    /// Create a spill file in `dir`, named after the operator that spills to it
+    pub fn try_new(dir: &Path, prefix: &str, schema: &Schema) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{}.arrow", prefix, Uuid::new_v4()));
+        let writer = FileWriter::try_new(File::create(&path)?, schema)?;
+        Ok(Self {
+            writer,
+            path,
+            num_rows: 0,
+            num_bytes: 0,
+        })
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.num_rows += batch.num_rows();
+        self.num_bytes += ColumnarBatch::from_arrow(batch).memory_size();
+        Ok(self.writer.write(batch)?)
+    }
+
+    /// Finish writing, returning the file to read the batches back from
+    pub fn finish(mut self) -> Result<SpillFile> {
+        self.writer.finish()?;
+        Ok(SpillFile {
+            path: self.path.clone(),
+            num_rows: self.num_rows,
+            num_bytes: self.num_bytes,
+        })
+    }
+}
+
+/// Batches that were spilled to disk. The file is deleted when this is dropped.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    num_rows: usize,
+    /// Size in memory of the batches that were written
+    num_bytes: usize,
+}
+
+impl SpillFile {
+    /// Write batches to a new spill file in `dir`
+    pub fn write(
+        dir: &Path,
+        prefix: &str,
+        schema: &Schema,
+        batches: &[RecordBatch],
+    ) -> Result<Self> {
+        let mut writer = SpillWriter::try_new(dir, prefix, schema)?;
+        for batch in batches {
+            writer.write(batch)?;
+        }
+        writer.finish()
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    /// Read the batches back in the order that they were written
+    pub fn read(&self) -> Result<impl Iterator<Item = Result<RecordBatch>>> {
+        let reader = FileReader::try_new(File::open(&self.path)?)?;
+        Ok(reader.map(|batch| batch.map_err(BallistaError::from)))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to delete spill file {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::Int32Array;
+    use crate::arrow::datatypes::{DataType, Field};
+    use std::sync::Arc;
+
+    #[test]
+    fn roundtrip_spill_file() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let dir = std::env::temp_dir().join("ballista-spill-test");
+        let file = SpillFile::write(&dir, "test", &schema, &[batch.clone(), batch])?;
+        assert_eq!(6, file.num_rows());
+
+        let batches = file.read()?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(2, batches.len());
+        assert_eq!(3, batches[1].num_rows());
+
+        let path = file.path.clone();
+        drop(file);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Statistics of tables and of the outputs of plans, which the scheduler uses to estimate the
+//! cost of alternative plans. Statistics of Parquet files are read from their metadata, and
+//! statistics of any table can be collected by ANALYZE, which scans all of its rows.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::arrow::array::{self, Array, ArrayRef, StringBuilder, UInt64Builder};
+use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::record_batch::RecordBatch;
+use crate::cast_array;
+use crate::datafusion::logicalplan::ScalarValue;
+use crate::error::{ballista_error, Result};
+use crate::execution::physical_plan::ColumnarBatch;
+
+/// Statistics of one column. Each statistic is `None` when it is not known.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnStatistics {
+    pub null_count: Option<u64>,
+    pub min_value: Option<ScalarValue>,
+    pub max_value: Option<ScalarValue>,
+    /// Number of distinct non-null values
+    pub distinct_count: Option<u64>,
+}
+
+impl ColumnStatistics {
+    /// Combine the statistics of a column in two disjoint sets of rows. The number of distinct
+    /// values cannot be combined without knowing which values the sets share.
+    fn merge(&self, other: &ColumnStatistics) -> ColumnStatistics {
+        let pick = |a: &Option<ScalarValue>, b: &Option<ScalarValue>, keep: Ordering| match (a, b) {
+            (Some(a), Some(b)) => match compare_scalars(a, b)? {
+                ordering if ordering == keep => Some(a.clone()),
+                _ => Some(b.clone()),
+            },
+            _ => None,
+        };
+        ColumnStatistics {
+            null_count: add(self.null_count, other.null_count),
+            min_value: pick(&self.min_value, &other.min_value, Ordering::Less),
+            max_value: pick(&self.max_value, &other.max_value, Ordering::Greater),
+            distinct_count: None,
+        }
+    }
+}
+
+/// Statistics of a table or of the output of a plan
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    pub num_rows: Option<u64>,
+    /// Size of the rows in memory
+    pub total_bytes: Option<u64>,
+    pub columns: Vec<ColumnStatistics>,
+}
+
+impl Statistics {
+    /// Statistics of a table with the given number of columns about which nothing is known
+    pub fn unknown(num_columns: usize) -> Self {
+        Self {
+            num_rows: None,
+            total_bytes: None,
+            columns: vec![ColumnStatistics::default(); num_columns],
+        }
+    }
+
+    /// Statistics of a subset of the columns, given by their indices. The size is assumed to
+    /// be divided evenly between the columns.
+    pub fn project(&self, projection: &[usize]) -> Self {
+        let num_columns = self.columns.len().max(1) as u64;
+        Self {
+            num_rows: self.num_rows,
+            total_bytes: self
+                .total_bytes
+                .map(|bytes| bytes * projection.len() as u64 / num_columns),
+            columns: projection
+                .iter()
+                .map(|i| self.columns.get(*i).cloned().unwrap_or_default())
+                .collect(),
+        }
+    }
+
+    /// Combine the statistics of two disjoint sets of rows of the same table, such as two row
+    /// groups of a Parquet file
+    pub fn merge(&self, other: &Statistics) -> Self {
+        Self {
+            num_rows: add(self.num_rows, other.num_rows),
+            total_bytes: add(self.total_bytes, other.total_bytes),
+            columns: self
+                .columns
+                .iter()
+                .zip(&other.columns)
+                .map(|(a, b)| a.merge(b))
+                .collect(),
+        }
+    }
+
+    /// Collect exact statistics of a table from all of its rows, as ANALYZE does
+    pub fn from_batches(schema: &Schema, batches: &[RecordBatch]) -> Result<Self> {
+        let mut columns = Vec::with_capacity(schema.fields().len());
+        for i in 0..schema.fields().len() {
+            let arrays: Vec<&ArrayRef> = batches.iter().map(|batch| batch.column(i)).collect();
+            columns.push(column_statistics(&arrays)?);
+        }
+        Ok(Self {
+            num_rows: Some(batches.iter().map(|b| b.num_rows() as u64).sum()),
+            total_bytes: Some(
+                batches
+                    .iter()
+                    .map(|b| ColumnarBatch::from_arrow(b).memory_size() as u64)
+                    .sum(),
+            ),
+            columns,
+        })
+    }
+
+    /// Convert the statistics to a batch with one row per column of the table, for returning
+    /// the results of ANALYZE to clients. Min and max values are formatted as strings.
+    pub fn to_batch(&self, schema: &Schema) -> Result<RecordBatch> {
+        let mut names = StringBuilder::new(self.columns.len());
+        let mut num_rows = UInt64Builder::new(self.columns.len());
+        let mut null_counts = UInt64Builder::new(self.columns.len());
+        let mut min_values = StringBuilder::new(self.columns.len());
+        let mut max_values = StringBuilder::new(self.columns.len());
+        let mut distinct_counts = UInt64Builder::new(self.columns.len());
+        for (field, column) in schema.fields().iter().zip(&self.columns) {
+            names.append_value(field.name())?;
+            num_rows.append_option(self.num_rows)?;
+            null_counts.append_option(column.null_count)?;
+            match column.min_value.as_ref().and_then(format_scalar) {
+                Some(value) => min_values.append_value(&value)?,
+                None => min_values.append_null()?,
+            }
+            match column.max_value.as_ref().and_then(format_scalar) {
+                Some(value) => max_values.append_value(&value)?,
+                None => max_values.append_null()?,
+            }
+            distinct_counts.append_option(column.distinct_count)?;
+        }
+        let schema = Schema::new(vec![
+            Field::new("column_name", DataType::Utf8, false),
+            Field::new("num_rows", DataType::UInt64, true),
+            Field::new("null_count", DataType::UInt64, true),
+            Field::new("min_value", DataType::Utf8, true),
+            Field::new("max_value", DataType::Utf8, true),
+            Field::new("distinct_count", DataType::UInt64, true),
+        ]);
+        Ok(RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(names.finish()),
+                Arc::new(num_rows.finish()),
+                Arc::new(null_counts.finish()),
+                Arc::new(min_values.finish()),
+                Arc::new(max_values.finish()),
+                Arc::new(distinct_counts.finish()),
+            ],
+        )?)
+    }
+}
+
+fn add(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    Some(a? + b?)
+}
+
+/// Hashable form of the values whose distinct count is collected
+#[derive(PartialEq, Eq, Hash)]
+enum DistinctValue {
+    Boolean(bool),
+    Int(i64),
+    UInt(u64),
+    /// Bits of a floating point value
+    Float(u64),
+    Utf8(String),
+}
+
+impl DistinctValue {
+    fn from_scalar(value: &ScalarValue) -> Option<Self> {
+        match value {
+            ScalarValue::Boolean(v) => Some(DistinctValue::Boolean(*v)),
+            ScalarValue::Int8(v) => Some(DistinctValue::Int(*v as i64)),
+            ScalarValue::Int16(v) => Some(DistinctValue::Int(*v as i64)),
+            ScalarValue::Int32(v) => Some(DistinctValue::Int(*v as i64)),
+            ScalarValue::Int64(v) => Some(DistinctValue::Int(*v)),
+            ScalarValue::UInt8(v) => Some(DistinctValue::UInt(*v as u64)),
+            ScalarValue::UInt16(v) => Some(DistinctValue::UInt(*v as u64)),
+            ScalarValue::UInt32(v) => Some(DistinctValue::UInt(*v as u64)),
+            ScalarValue::UInt64(v) => Some(DistinctValue::UInt(*v)),
+            ScalarValue::Float32(v) => Some(DistinctValue::Float((*v as f64).to_bits())),
+            ScalarValue::Float64(v) => Some(DistinctValue::Float(v.to_bits())),
+            ScalarValue::Utf8(v) => Some(DistinctValue::Utf8(v.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Statistics of a column from all of its values. Only the null count is collected for columns
+/// of types that have no scalar representation here.
+fn column_statistics(arrays: &[&ArrayRef]) -> Result<ColumnStatistics> {
+    let null_count = arrays.iter().map(|a| a.null_count() as u64).sum();
+    let mut min_value: Option<ScalarValue> = None;
+    let mut max_value: Option<ScalarValue> = None;
+    let mut distinct = HashSet::new();
+    for array in arrays {
+        for row in 0..array.len() {
+            if array.is_null(row) {
+                continue;
+            }
+            let value = match scalar_value(array, row)? {
+                Some(value) => value,
+                None => {
+                    return Ok(ColumnStatistics {
+                        null_count: Some(null_count),
+                        ..ColumnStatistics::default()
+                    })
+                }
+            };
+            if let Some(key) = DistinctValue::from_scalar(&value) {
+                distinct.insert(key);
+            }
+            let is_min = match &min_value {
+                Some(min) => compare_scalars(&value, min) == Some(Ordering::Less),
+                None => true,
+            };
+            if is_min {
+                min_value = Some(value.clone());
+            }
+            let is_max = match &max_value {
+                Some(max) => compare_scalars(&value, max) == Some(Ordering::Greater),
+                None => true,
+            };
+            if is_max {
+                max_value = Some(value);
+            }
+        }
+    }
+    Ok(ColumnStatistics {
+        null_count: Some(null_count),
+        min_value,
+        max_value,
+        distinct_count: Some(distinct.len() as u64),
+    })
+}
+
+/// The value of a row of an array, or `None` if statistics are not collected for its type
+fn scalar_value(array: &ArrayRef, row: usize) -> Result<Option<ScalarValue>> {
+    Ok(Some(match array.data_type() {
+        DataType::Boolean => ScalarValue::Boolean(cast_array!(array, BooleanArray)?.value(row)),
+        DataType::Int8 => ScalarValue::Int8(cast_array!(array, Int8Array)?.value(row)),
+        DataType::Int16 => ScalarValue::Int16(cast_array!(array, Int16Array)?.value(row)),
+        DataType::Int32 => ScalarValue::Int32(cast_array!(array, Int32Array)?.value(row)),
+        DataType::Int64 => ScalarValue::Int64(cast_array!(array, Int64Array)?.value(row)),
+        DataType::UInt8 => ScalarValue::UInt8(cast_array!(array, UInt8Array)?.value(row)),
+        DataType::UInt16 => ScalarValue::UInt16(cast_array!(array, UInt16Array)?.value(row)),
+        DataType::UInt32 => ScalarValue::UInt32(cast_array!(array, UInt32Array)?.value(row)),
+        DataType::UInt64 => ScalarValue::UInt64(cast_array!(array, UInt64Array)?.value(row)),
+        DataType::Float32 => ScalarValue::Float32(cast_array!(array, Float32Array)?.value(row)),
+        DataType::Float64 => ScalarValue::Float64(cast_array!(array, Float64Array)?.value(row)),
+        DataType::Utf8 => ScalarValue::Utf8(cast_array!(array, StringArray)?.value(row).to_owned()),
+        _ => return Ok(None),
+    }))
+}
+
+fn format_scalar(value: &ScalarValue) -> Option<String> {
+    match value {
+        ScalarValue::Boolean(v) => Some(v.to_string()),
+        ScalarValue::Utf8(v) => Some(v.clone()),
+        other => Some(as_f64(other)?.to_string()),
+    }
+}
+
+/// The value of a numeric scalar as a double
+pub fn as_f64(value: &ScalarValue) -> Option<f64> {
+    match value {
+        ScalarValue::Int8(v) => Some(*v as f64),
+        ScalarValue::Int16(v) => Some(*v as f64),
+        ScalarValue::Int32(v) => Some(*v as f64),
+        ScalarValue::Int64(v) => Some(*v as f64),
+        ScalarValue::UInt8(v) => Some(*v as f64),
+        ScalarValue::UInt16(v) => Some(*v as f64),
+        ScalarValue::UInt32(v) => Some(*v as f64),
+        ScalarValue::UInt64(v) => Some(*v as f64),
+        ScalarValue::Float32(v) => Some(*v as f64),
+        ScalarValue::Float64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+/// Compare two scalar values, where integers and floating point values are comparable with each
+/// other
+pub fn compare_scalars(a: &ScalarValue, b: &ScalarValue) -> Option<Ordering> {
+    match (a, b) {
+        (ScalarValue::Utf8(a), ScalarValue::Utf8(b)) => Some(a.cmp(b)),
+        (ScalarValue::Boolean(a), ScalarValue::Boolean(b)) => Some(a.cmp(b)),
+        _ => as_f64(a)?.partial_cmp(&as_f64(b)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrow::array::{Int32Array, StringArray};
+
+    #[test]
+    fn collect_column_statistics() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("state", DataType::Utf8, true),
+        ]));
+        let batch = |ids: Vec<i32>, states: Vec<Option<&str>>| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int32Array::from(ids)),
+                    Arc::new(StringArray::from(states)),
+                ],
+            )
+        };
+        let batches = vec![
+            batch(vec![3, 1, 2], vec![Some("CO"), None, Some("CA")])?,
+            batch(vec![7, 1], vec![Some("CO"), Some("NY")])?,
+        ];
+
+        let stats = Statistics::from_batches(&schema, &batches)?;
+        assert_eq!(Some(5), stats.num_rows);
+        assert_eq!(
+            ColumnStatistics {
+                null_count: Some(0),
+                min_value: Some(ScalarValue::Int32(1)),
+                max_value: Some(ScalarValue::Int32(7)),
+                distinct_count: Some(4),
+            },
+            stats.columns[0]
+        );
+        assert_eq!(
+            ColumnStatistics {
+                null_count: Some(1),
+                min_value: Some(ScalarValue::Utf8("CA".to_owned())),
+                max_value: Some(ScalarValue::Utf8("NY".to_owned())),
+                distinct_count: Some(3),
+            },
+            stats.columns[1]
+        );
+
+        // merged statistics keep the bounds but not the distinct counts
+        let merged = stats.merge(&stats.project(&[0, 1]));
+        assert_eq!(Some(10), merged.num_rows);
+        assert_eq!(Some(2), merged.columns[1].null_count);
+        assert_eq!(Some(ScalarValue::Int32(7)), merged.columns[0].max_value);
+        assert_eq!(None, merged.columns[0].distinct_count);
+
+        let batch = stats.to_batch(&schema)?;
+        assert_eq!(2, batch.num_rows());
+        assert_eq!(6, batch.num_columns());
+        Ok(())
+    }
+}
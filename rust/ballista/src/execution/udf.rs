@@ -0,0 +1,207 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! User-defined scalar functions.
+//!
+//! A client registers the signature of a UDF so that it can be used in queries, and plans refer
+//! to the UDF by name. Executors resolve the name against the implementations in their own
+//! registry, which are either linked into the executor binary and registered at startup or
+//! loaded from a plugin library with `UdfRegistry::load_plugin`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::arrow::array::ArrayRef;
+use crate::arrow::datatypes::{DataType, Field};
+use crate::datafusion::logicalplan::{FunctionMeta, FunctionType};
+use crate::error::{ballista_error, Result};
+
+use lazy_static::lazy_static;
+use libloading::Library;
+
+/// Name of the function that plugin libraries export to register their UDFs
+pub const PLUGIN_REGISTER_SYMBOL: &[u8] = b"ballista_register_udfs\0";
+
+/// Implementation of a UDF, invoked with one array per argument
+pub type ScalarUdfFn = Arc<dyn Fn(&[ArrayRef]) -> Result<ArrayRef> + Send + Sync>;
+
+/// Function exported by plugin libraries. Plugins must be built with the same compiler and
+/// Ballista version as the executor that loads them.
+pub type PluginRegisterFn = fn(&UdfRegistry) -> Result<()>;
+
+/// Name, argument types, and return type of a UDF
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalarUdfSignature {
+    pub name: String,
+    pub arg_types: Vec<DataType>,
+    pub return_type: DataType,
+}
+
+impl ScalarUdfSignature {
+    pub fn new(name: &str, arg_types: Vec<DataType>, return_type: DataType) -> Self {
+        Self {
+            name: name.to_owned(),
+            arg_types,
+            return_type,
+        }
+    }
+}
+
+/// Registry of UDF signatures and the implementations that are available in this process
+#[derive(Default)]
+pub struct UdfRegistry {
+    signatures: RwLock<HashMap<String, ScalarUdfSignature>>,
+    implementations: RwLock<HashMap<String, ScalarUdfFn>>,
+    /// Plugin libraries stay loaded for as long as the registry refers to their functions
+    plugins: Mutex<Vec<Library>>,
+}
+
+impl fmt::Debug for UdfRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UdfRegistry")
+            .field("signatures", &self.signatures.read().unwrap().keys())
+            .field(
+                "implementations",
+                &self.implementations.read().unwrap().keys(),
+            )
+            .finish()
+    }
+}
+
+impl UdfRegistry {
+    /// Register the signature of a UDF. Registering the same signature again has no effect but
+    /// registering a different signature under an existing name is an error.
+    pub fn register_signature(&self, signature: ScalarUdfSignature) -> Result<()> {
+        let mut signatures = self.signatures.write().unwrap();
+        let key = signature.name.to_uppercase();
+        match signatures.get(&key) {
+            Some(existing)
+                if existing.arg_types != signature.arg_types
+                    || existing.return_type != signature.return_type =>
+            {
+                Err(ballista_error(&format!(
+                    "UDF {} is already registered with a different signature: {:?}",
+                    signature.name, existing
+                )))
+            }
+            Some(_) => Ok(()),
+            None => {
+                signatures.insert(key, signature);
+                Ok(())
+            }
+        }
+    }
+
+    /// Register the signature and implementation of a UDF
+    pub fn register(&self, signature: ScalarUdfSignature, fun: ScalarUdfFn) -> Result<()> {
+        let key = signature.name.to_uppercase();
+        self.register_signature(signature)?;
+        self.implementations.write().unwrap().insert(key, fun);
+        Ok(())
+    }
+
+    /// Look up the signature of a UDF by name, ignoring case
+    pub fn signature(&self, name: &str) -> Option<ScalarUdfSignature> {
+        self.signatures
+            .read()
+            .unwrap()
+            .get(&name.to_uppercase())
+            .cloned()
+    }
+
+    /// Describe a UDF to the SQL planner, looking it up by name and ignoring case
+    pub fn function_meta(&self, name: &str) -> Option<Arc<FunctionMeta>> {
+        self.signature(name).map(|signature| {
+            let args = signature
+                .arg_types
+                .iter()
+                .enumerate()
+                .map(|(i, data_type)| Field::new(&format!("arg{}", i), data_type.clone(), true))
+                .collect();
+            Arc::new(FunctionMeta::new(
+                signature.name,
+                args,
+                signature.return_type,
+                FunctionType::Scalar,
+            ))
+        })
+    }
+
+    /// Look up the implementation of a UDF by name, ignoring case
+    pub fn implementation(&self, name: &str) -> Option<ScalarUdfFn> {
+        self.implementations
+            .read()
+            .unwrap()
+            .get(&name.to_uppercase())
+            .cloned()
+    }
+
+    /// Load a plugin library and register the UDFs that it provides. The library must export
+    /// a `ballista_register_udfs` function, which `declare_udf_plugin!` generates.
+    pub fn load_plugin(&self, path: &str) -> Result<()> {
+        let library = Library::new(path)
+            .map_err(|e| ballista_error(&format!("Failed to load UDF plugin {}: {}", path, e)))?;
+        let register: PluginRegisterFn = unsafe {
+            *library
+                .get::<PluginRegisterFn>(PLUGIN_REGISTER_SYMBOL)
+                .map_err(|e| ballista_error(&format!("UDF plugin {} is not valid: {}", path, e)))?
+        };
+        register(self)?;
+        self.plugins.lock().unwrap().push(library);
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref UDF_REGISTRY: UdfRegistry = UdfRegistry::default();
+}
+
+/// Get the registry of UDFs for this process
+pub fn udf_registry() -> &'static UdfRegistry {
+    &UDF_REGISTRY
+}
+
+/// Export a function with the signature `fn(&UdfRegistry) -> Result<()>` as the entry point of
+/// a UDF plugin library, which must be built with `crate-type = ["cdylib"]`.
+#[macro_export]
+macro_rules! declare_udf_plugin {
+    ($REGISTER:path) => {
+        #[no_mangle]
+        pub fn ballista_register_udfs(
+            registry: &$crate::execution::udf::UdfRegistry,
+        ) -> $crate::error::Result<()> {
+            $REGISTER(registry)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_signatures() -> Result<()> {
+        let registry = UdfRegistry::default();
+        let signature = ScalarUdfSignature::new("plus_one", vec![DataType::Int64], DataType::Int64);
+        registry.register_signature(signature.clone())?;
+        registry.register_signature(signature)?;
+        assert!(registry.signature("PLUS_ONE").is_some());
+        assert!(registry.implementation("plus_one").is_none());
+
+        let other = ScalarUdfSignature::new("PLUS_ONE", vec![DataType::Float64], DataType::Int64);
+        assert!(registry.register_signature(other).is_err());
+        Ok(())
+    }
+}
@@ -28,14 +28,22 @@ pub use sqlparser;
 #[allow(clippy::all)]
 pub mod protobuf {
     include!(concat!(env!("OUT_DIR"), "/ballista.protobuf.rs"));
+
+    /// Messages of the Arrow Flight SQL protocol
+    pub mod flight_sql {
+        include!(concat!(env!("OUT_DIR"), "/arrow.flight.protocol.sql.rs"));
+    }
 }
 
 pub const BALLISTA_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+pub mod benchmarks;
+pub mod config;
 pub mod dataframe;
 pub mod distributed;
 pub mod error;
 pub mod execution;
+pub mod object_store;
 pub mod serde;
 
 #[macro_use]
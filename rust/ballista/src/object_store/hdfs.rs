@@ -0,0 +1,231 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Object store for HDFS, accessed through the WebHDFS REST API of the name node so that no
+//! native Hadoop libraries are needed. Reads are redirected by the name node to a data node
+//! that holds the block.
+
+use std::env;
+
+use crate::error::{ballista_error, Result};
+use crate::object_store::{ObjectMeta, ObjectStore};
+
+use log::debug;
+use reqwest::{Client, Response};
+use serde_json::Value;
+
+/// Default port of the name node HTTP server, which serves the WebHDFS API
+pub const DEFAULT_WEBHDFS_PORT: u16 = 9870;
+
+/// Object store for `hdfs://namenode:port/path` paths
+#[derive(Debug, Clone)]
+pub struct HdfsObjectStore {
+    /// Port of the WebHDFS API, which differs from the RPC port in `hdfs://` paths
+    webhdfs_port: u16,
+    /// User to access files as, when the cluster uses simple authentication
+    user: Option<String>,
+    client: Client,
+}
+
+impl HdfsObjectStore {
+    pub fn new(webhdfs_port: u16, user: Option<String>) -> Self {
+        Self {
+            webhdfs_port,
+            user,
+            client: Client::new(),
+        }
+    }
+
+    /// Create a store from the `HDFS_WEBHDFS_PORT` and `HADOOP_USER_NAME` environment variables
+    pub fn from_env() -> Result<Self> {
+        let port = match env::var("HDFS_WEBHDFS_PORT") {
+            Ok(port) => port
+                .parse()
+                .map_err(|_| ballista_error(&format!("Invalid HDFS_WEBHDFS_PORT {}", port)))?,
+            Err(_) => DEFAULT_WEBHDFS_PORT,
+        };
+        Ok(Self::new(port, env::var("HADOOP_USER_NAME").ok()))
+    }
+
+    /// Split a path into the name node address and the absolute path within the file system
+    fn parse_path<'a>(&self, path: &'a str) -> Result<(&'a str, &'a str)> {
+        let prefix = "hdfs://";
+        if !path.starts_with(prefix) {
+            return Err(ballista_error(&format!("Path {} is not in HDFS", path)));
+        }
+        let rest = &path[prefix.len()..];
+        let (authority, file_path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(ballista_error(&format!(
+                "Path {} does not specify a name node",
+                path
+            )));
+        }
+        Ok((authority, file_path))
+    }
+
+    /// Send a WebHDFS request for an operation on a path
+    fn request(
+        &self,
+        authority: &str,
+        path: &str,
+        op: &str,
+        params: &[(&str, String)],
+    ) -> Result<Response> {
+        // the port in the path is the RPC port of the name node
+        let host = authority.split(':').next().unwrap_or(authority);
+        let mut url = format!(
+            "http://{}:{}/webhdfs/v1{}?op={}",
+            host,
+            self.webhdfs_port,
+            encode_path(path),
+            op
+        );
+        for (k, v) in params {
+            url.push_str(&format!("&{}={}", k, v));
+        }
+        if let Some(user) = &self.user {
+            url.push_str(&format!("&user.name={}", user));
+        }
+
+        // the client follows the redirect from the name node to a data node for reads
+        let mut response = self.client.get(url.as_str()).send()?;
+        if !response.status().is_success() {
+            return Err(ballista_error(&format!(
+                "WebHDFS {} request for hdfs://{}{} failed with status {}: {}",
+                op,
+                authority,
+                path,
+                response.status(),
+                response.text().unwrap_or_default()
+            )));
+        }
+        Ok(response)
+    }
+
+    fn file_status(&self, authority: &str, path: &str) -> Result<Value> {
+        let json: Value = self
+            .request(authority, path, "GETFILESTATUS", &[])?
+            .json()?;
+        Ok(json["FileStatus"].clone())
+    }
+
+    /// Recursively list the files in a directory
+    fn list_dir(&self, authority: &str, dir: &str, objects: &mut Vec<ObjectMeta>) -> Result<()> {
+        let json: Value = self.request(authority, dir, "LISTSTATUS", &[])?.json()?;
+        let statuses = json["FileStatuses"]["FileStatus"]
+            .as_array()
+            .ok_or_else(|| {
+                ballista_error(&format!("Invalid listing of hdfs://{}{}", authority, dir))
+            })?;
+        for status in statuses {
+            let name = status["pathSuffix"].as_str().unwrap_or_default();
+            let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+            match status["type"].as_str() {
+                Some("DIRECTORY") => self.list_dir(authority, &path, objects)?,
+                Some("FILE") => objects.push(ObjectMeta {
+                    path: format!("hdfs://{}{}", authority, path),
+                    size: status["length"].as_u64().unwrap_or(0),
+                }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ObjectStore for HdfsObjectStore {
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let (authority, path) = self.parse_path(prefix)?;
+        let status = self.file_status(authority, path)?;
+        let mut objects = vec![];
+        if status["type"].as_str() == Some("FILE") {
+            objects.push(ObjectMeta {
+                path: format!("hdfs://{}{}", authority, path),
+                size: status["length"].as_u64().unwrap_or(0),
+            });
+        } else {
+            self.list_dir(authority, path, &mut objects)?;
+        }
+        debug!("Listed {} files in {}", objects.len(), prefix);
+        Ok(objects)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let (authority, file_path) = self.parse_path(path)?;
+        self.file_status(authority, file_path)?["length"]
+            .as_u64()
+            .ok_or_else(|| ballista_error(&format!("Unknown size of file {}", path)))
+    }
+
+    fn read_range(&self, path: &str, start: u64, length: usize) -> Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(vec![]);
+        }
+        let (authority, file_path) = self.parse_path(path)?;
+        let params = [
+            ("offset", start.to_string()),
+            ("length", length.to_string()),
+        ];
+        let mut response = self.request(authority, file_path, "OPEN", &params)?;
+        let mut buf = Vec::with_capacity(length);
+        response.copy_to(&mut buf)?;
+        if buf.len() != length {
+            return Err(ballista_error(&format!(
+                "Expected {} bytes from {} but received {}",
+                length,
+                path,
+                buf.len()
+            )));
+        }
+        Ok(buf)
+    }
+}
+
+/// Percent-encode the characters of a path that are not allowed in a URL, keeping slashes
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hdfs_path() {
+        let store = HdfsObjectStore::new(DEFAULT_WEBHDFS_PORT, None);
+        assert_eq!(
+            ("namenode:8020", "/data/part-0.parquet"),
+            store
+                .parse_path("hdfs://namenode:8020/data/part-0.parquet")
+                .unwrap()
+        );
+        assert_eq!(
+            ("namenode", "/"),
+            store.parse_path("hdfs://namenode").unwrap()
+        );
+        assert!(store.parse_path("s3://bucket/key").is_err());
+        assert_eq!("/data/a%20b%3D1/x.csv", encode_path("/data/a b=1/x.csv"));
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::datafusion::execution::physical_plan::common;
+use crate::error::Result;
+use crate::object_store::{ObjectMeta, ObjectStore};
+
+/// Object store backed by the local file system, where a prefix is a file or a directory
+#[derive(Debug, Default)]
+pub struct LocalFileSystem {}
+
+impl ObjectStore for LocalFileSystem {
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let mut filenames: Vec<String> = vec![];
+        common::build_file_list(prefix, &mut filenames, "")?;
+        filenames
+            .into_iter()
+            .map(|path| {
+                let size = fs::metadata(&path)?.len();
+                Ok(ObjectMeta { path, size })
+            })
+            .collect()
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn read_range(&self, path: &str, start: u64, length: usize) -> Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![0; length];
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first so that readers on other hosts, such as those
+        // sharing the directory over NFS, never see a partially written object
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        Ok(fs::remove_file(path)?)
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Object stores that data sources can be read from.
+//!
+//! Paths are either local paths or URIs of the form `s3://bucket/key` for Amazon S3,
+//! `gs://bucket/key` for Google Cloud Storage, and `hdfs://namenode:port/path` for HDFS. Scans
+//! resolve their paths with `object_store` on the executor that runs them, so executors need
+//! credentials for the stores that they read from.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::error::{ballista_error, Result};
+
+mod hdfs;
+mod local;
+mod s3;
+
+pub use hdfs::HdfsObjectStore;
+pub use local::LocalFileSystem;
+pub use s3::{S3Credentials, S3ObjectStore};
+
+/// Metadata of an object in an object store
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMeta {
+    /// Full path of the object, including the scheme for remote stores
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// A store of objects that can be listed and read in ranges, and written by stores that
+/// support it
+pub trait ObjectStore: Debug + Send + Sync {
+    /// List the objects whose paths start with the prefix
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>>;
+
+    /// Get the size in bytes of an object
+    fn size(&self, path: &str) -> Result<u64>;
+
+    /// Read `length` bytes of an object starting at byte `start`
+    fn read_range(&self, path: &str, start: u64, length: usize) -> Result<Vec<u8>>;
+
+    /// Read a whole object
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let size = self.size(path)?;
+        self.read_range(path, 0, size as usize)
+    }
+
+    /// Write a whole object, replacing it if it exists
+    fn write(&self, path: &str, _data: &[u8]) -> Result<()> {
+        Err(ballista_error(&format!(
+            "Object store does not support writing {}",
+            path
+        )))
+    }
+
+    /// Delete an object
+    fn delete(&self, path: &str) -> Result<()> {
+        Err(ballista_error(&format!(
+            "Object store does not support deleting {}",
+            path
+        )))
+    }
+}
+
+/// Returns true if the path refers to a remote object store rather than the local file system
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("s3://") || path.starts_with("gs://") || path.starts_with("hdfs://")
+}
+
+/// Get the object store for a path, configured from the environment
+pub fn object_store(path: &str) -> Result<Arc<dyn ObjectStore>> {
+    if path.starts_with("s3://") {
+        Ok(Arc::new(S3ObjectStore::s3_from_env()?))
+    } else if path.starts_with("gs://") {
+        Ok(Arc::new(S3ObjectStore::gcs_from_env()?))
+    } else if path.starts_with("hdfs://") {
+        Ok(Arc::new(HdfsObjectStore::from_env()?))
+    } else {
+        Ok(Arc::new(LocalFileSystem::default()))
+    }
+}
+
+/// Find the files under a path that have the given extension, in order of their paths
+pub fn list_files(path: &str, extension: &str) -> Result<Vec<String>> {
+    let mut filenames: Vec<String> = object_store(path)?
+        .list(path)?
+        .into_iter()
+        .map(|meta| meta.path)
+        .filter(|p| p.ends_with(extension))
+        .collect();
+    filenames.sort();
+    Ok(filenames)
+}
+
+/// Get the size in bytes of a file
+pub fn file_size(path: &str) -> Result<u64> {
+    object_store(path)?.size(path)
+}
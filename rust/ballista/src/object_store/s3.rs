@@ -0,0 +1,451 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Object store for the S3 REST API, with requests signed using AWS Signature Version 4. Google
+//! Cloud Storage is accessed through its S3-compatible XML API using HMAC keys.
+
+use std::env;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{ballista_error, BallistaError, Result};
+use crate::execution::expressions::civil_from_days;
+use crate::object_store::{ObjectMeta, ObjectStore};
+
+use hmac::{Hmac, Mac, NewMac};
+use log::debug;
+use reqwest::{Client, Method, Response};
+use sha2::{Digest, Sha256};
+
+/// Ranges larger than this are fetched as several parts in parallel
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum number of parts of a range that are fetched at the same time
+pub const MAX_CONCURRENT_PARTS: usize = 8;
+
+/// Credentials used to sign requests
+#[derive(Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl fmt::Debug for S3Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S3Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish()
+    }
+}
+
+/// Object store for buckets that are accessed through the S3 API
+#[derive(Debug, Clone)]
+pub struct S3ObjectStore {
+    /// URI scheme of the paths in this store, such as `s3`
+    scheme: String,
+    /// Endpoint URL, such as `https://s3.us-east-1.amazonaws.com`
+    endpoint: String,
+    region: String,
+    credentials: S3Credentials,
+    client: Client,
+}
+
+impl S3ObjectStore {
+    pub fn new(scheme: &str, endpoint: &str, region: &str, credentials: S3Credentials) -> Self {
+        Self {
+            scheme: scheme.to_owned(),
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            region: region.to_owned(),
+            credentials,
+            client: Client::new(),
+        }
+    }
+
+    /// Create a store for `s3://` paths from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+    /// `AWS_SESSION_TOKEN`, and `AWS_REGION` environment variables. `AWS_ENDPOINT` overrides the
+    /// endpoint, for S3-compatible stores such as MinIO.
+    pub fn s3_from_env() -> Result<Self> {
+        let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_owned());
+        let endpoint = env::var("AWS_ENDPOINT")
+            .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+        let credentials = S3Credentials {
+            access_key_id: required_env("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: required_env("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        };
+        Ok(Self::new("s3", &endpoint, &region, credentials))
+    }
+
+    /// Create a store for `gs://` paths from the HMAC key in the `GCS_ACCESS_KEY_ID` and
+    /// `GCS_SECRET_ACCESS_KEY` environment variables
+    pub fn gcs_from_env() -> Result<Self> {
+        let credentials = S3Credentials {
+            access_key_id: required_env("GCS_ACCESS_KEY_ID")?,
+            secret_access_key: required_env("GCS_SECRET_ACCESS_KEY")?,
+            session_token: None,
+        };
+        Ok(Self::new(
+            "gs",
+            "https://storage.googleapis.com",
+            "auto",
+            credentials,
+        ))
+    }
+
+    /// Split a path into its bucket and key
+    fn parse_path<'a>(&self, path: &'a str) -> Result<(&'a str, &'a str)> {
+        let prefix = format!("{}://", self.scheme);
+        if !path.starts_with(&prefix) {
+            return Err(ballista_error(&format!(
+                "Path {} is not in a {} bucket",
+                path, self.scheme
+            )));
+        }
+        let path = &path[prefix.len()..];
+        match path.find('/') {
+            Some(i) => Ok((&path[..i], &path[i + 1..])),
+            None => Ok((path, "")),
+        }
+    }
+
+    /// Send a signed request for an object, or for the bucket when the key is empty
+    fn request(
+        &self,
+        method: Method,
+        bucket: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        range: Option<(u64, usize)>,
+        body: Option<&[u8]>,
+    ) -> Result<Response> {
+        let uri = if key.is_empty() {
+            format!("/{}", uri_encode(bucket, true))
+        } else {
+            format!("/{}/{}", uri_encode(bucket, true), uri_encode(key, false))
+        };
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let host = self
+            .endpoint
+            .splitn(2, "://")
+            .last()
+            .unwrap_or(&self.endpoint)
+            .to_owned();
+        let (date, timestamp) = amz_date(SystemTime::now());
+        let payload_hash = hex::encode(Sha256::digest(body.unwrap_or(b"")));
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", timestamp.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method.as_str(),
+            uri,
+            query,
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = [date.as_str(), &self.region, "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", self.credentials.secret_access_key).into_bytes(),
+                |key, data| hmac_sha256(&key, data),
+            );
+        let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id, scope, signed_headers, signature
+        );
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, uri)
+        } else {
+            format!("{}{}?{}", self.endpoint, uri, query)
+        };
+        let mut request = self
+            .client
+            .request(method, url.as_str())
+            .header("Authorization", authorization);
+        for (k, v) in headers.into_iter().filter(|(k, _)| *k != "host") {
+            request = request.header(k, v);
+        }
+        if let Some((start, length)) = range {
+            let end = start + length as u64 - 1;
+            request = request.header("Range", format!("bytes={}-{}", start, end));
+        }
+        if let Some(body) = body {
+            request = request.body(body.to_vec());
+        }
+
+        let mut response = request.send()?;
+        if !response.status().is_success() {
+            return Err(ballista_error(&format!(
+                "Request for {}://{}/{} failed with status {}: {}",
+                self.scheme,
+                bucket,
+                key,
+                response.status(),
+                response.text().unwrap_or_default()
+            )));
+        }
+        Ok(response)
+    }
+
+    /// Read a range of an object with a single request
+    fn read_part(&self, bucket: &str, key: &str, start: u64, length: usize) -> Result<Vec<u8>> {
+        let mut response =
+            self.request(Method::GET, bucket, key, &[], Some((start, length)), None)?;
+        let mut buf = Vec::with_capacity(length);
+        response.copy_to(&mut buf)?;
+        if buf.len() != length {
+            return Err(ballista_error(&format!(
+                "Expected {} bytes from {}://{}/{} but received {}",
+                length,
+                self.scheme,
+                bucket,
+                key,
+                buf.len()
+            )));
+        }
+        Ok(buf)
+    }
+}
+
+impl ObjectStore for S3ObjectStore {
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let (bucket, key_prefix) = self.parse_path(prefix)?;
+        let mut objects = vec![];
+        let mut marker = String::new();
+        loop {
+            let mut query = vec![("prefix", key_prefix)];
+            if !marker.is_empty() {
+                query.push(("marker", marker.as_str()));
+            }
+            let xml = self
+                .request(Method::GET, bucket, "", &query, None, None)?
+                .text()?;
+            for contents in xml_elements(&xml, "Contents") {
+                let key = xml_elements(contents, "Key")
+                    .first()
+                    .map(|k| xml_unescape(k))
+                    .ok_or_else(|| ballista_error("Object listing is missing a key"))?;
+                let size = xml_elements(contents, "Size")
+                    .first()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0);
+                // skip the placeholder objects that some tools create for directories
+                if !key.ends_with('/') {
+                    objects.push(ObjectMeta {
+                        path: format!("{}://{}/{}", self.scheme, bucket, key),
+                        size,
+                    });
+                }
+                marker = key;
+            }
+            let truncated = xml_elements(&xml, "IsTruncated").first() == Some(&"true");
+            if !truncated {
+                break;
+            }
+        }
+        debug!("Listed {} objects in {}", objects.len(), prefix);
+        Ok(objects)
+    }
+
+    fn size(&self, path: &str) -> Result<u64> {
+        let (bucket, key) = self.parse_path(path)?;
+        let response = self.request(Method::HEAD, bucket, key, &[], None, None)?;
+        response
+            .headers()
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| ballista_error(&format!("Unknown size of object {}", path)))
+    }
+
+    fn read_range(&self, path: &str, start: u64, length: usize) -> Result<Vec<u8>> {
+        let (bucket, key) = self.parse_path(path)?;
+        if length == 0 {
+            return Ok(vec![]);
+        }
+        if length <= PART_SIZE {
+            return self.read_part(bucket, key, start, length);
+        }
+
+        // fetch large ranges as parts in parallel
+        let parts: Vec<(u64, usize)> = (0..length)
+            .step_by(PART_SIZE)
+            .map(|offset| (start + offset as u64, PART_SIZE.min(length - offset)))
+            .collect();
+        let mut buf = Vec::with_capacity(length);
+        for parts in parts.chunks(MAX_CONCURRENT_PARTS) {
+            let results = crossbeam::scope(|s| {
+                let handles: Vec<_> = parts
+                    .iter()
+                    .map(|(start, length)| {
+                        s.spawn(move |_| self.read_part(bucket, key, *start, *length))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| {
+                        h.join().unwrap_or_else(|_| {
+                            Err(ballista_error("Thread reading object part panicked"))
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .map_err(|_| ballista_error("Thread reading object part panicked"))?;
+            for part in results {
+                buf.extend(part?);
+            }
+        }
+        Ok(buf)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let (bucket, key) = self.parse_path(path)?;
+        self.request(Method::PUT, bucket, key, &[], None, Some(data))?;
+        debug!("Wrote {} bytes to {}", data.len(), path);
+        Ok(())
+    }
+
+    fn delete(&self, path: &str) -> Result<()> {
+        let (bucket, key) = self.parse_path(path)?;
+        self.request(Method::DELETE, bucket, key, &[], None, None)?;
+        Ok(())
+    }
+}
+
+fn required_env(name: &str) -> Result<String> {
+    env::var(name)
+        .map_err(|_| BallistaError::General(format!("Environment variable {} must be set", name)))
+}
+
+/// Format a time as the date and the timestamp used in signatures
+fn amz_date(time: SystemTime) -> (String, String) {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let secs = secs.rem_euclid(86400);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let timestamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    (date, timestamp)
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode all characters except unreserved characters and, optionally, slashes
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_owned(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Find the contents of all elements with the given name. Elements must not be nested in
+/// elements with the same name, which holds for the responses of the S3 API.
+fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let mut elements = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                elements.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    elements
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn signing_helpers() {
+        assert_eq!("a%20b/c%2Bd.parquet", uri_encode("a b/c+d.parquet", false));
+        assert_eq!("a%2Fb", uri_encode("a/b", true));
+
+        let (date, timestamp) = amz_date(UNIX_EPOCH + Duration::from_secs(1_596_240_000));
+        assert_eq!("20200801", date);
+        assert_eq!("20200801T000000Z", timestamp);
+
+        let xml = "<ListBucketResult><Contents><Key>a&amp;b</Key><Size>3</Size></Contents>\
+                   <Contents><Key>c</Key><Size>5</Size></Contents></ListBucketResult>";
+        let contents = xml_elements(xml, "Contents");
+        assert_eq!(2, contents.len());
+        assert_eq!("a&b", xml_unescape(xml_elements(contents[0], "Key")[0]));
+    }
+}
@@ -17,19 +17,40 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 
-use crate::arrow::datatypes::{DataType, Field, Schema};
+use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
 use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
 use crate::datafusion::logicalplan::{
     Expr, LogicalPlan, LogicalPlanBuilder, Operator, ScalarValue,
 };
-use crate::distributed::scheduler::ExecutionTask;
+use crate::distributed::compression::ShuffleCompression;
+use crate::distributed::job_state::{JobRecord, StageRecord};
+use crate::distributed::progress::{JobProgress, StageProgress, TaskCounts};
+use crate::distributed::registry::ExecutorRegistration;
+use crate::distributed::resources::TaskResources;
+use crate::distributed::scheduler::{ExecutionTask, QuerySettings};
+use crate::distributed::scheduler_server::{JobState, JobStatus};
+use crate::distributed::scheduling::DEFAULT_TENANT;
+use crate::distributed::table_store::TableDefinition;
 use crate::error::{ballista_error, BallistaError};
+use crate::execution::expressions::{
+    encode_predicate, parameter, CaseParts, ScalarFunction, BETWEEN_FUNCTION_NAME,
+    COALESCE_FUNCTION_NAME, IN_LIST_FUNCTION_NAME,
+};
 use crate::execution::operators::{
-    CsvScanExec, FilterExec, HashAggregateExec, ParquetScanExec, ShuffleReaderExec,
+    AvroScanExec, CsvCompression, CsvFormatOptions, CsvScanExec, FilterExec, GlobalLimitExec,
+    HashAggregateExec, HashJoinExec, IpcScanExec, JoinNode, JsonScanExec, LocalLimitExec,
+    ParquetScanExec, ProjectionExec, ShuffleReaderExec, SortExec, SortMergeJoinExec, TopKExec,
+    UnionExec, UnionNode, WindowExec, WindowExpr, WindowFunction, WriteExec, WriteFormat,
+    ARROW_SCHEMA_NAME, AVRO_SCHEMA_NAME, JSON_SCHEMA_NAME,
+};
+use crate::execution::physical_plan::{
+    Action, ExecutorAction, ExecutorMeta, OperatorMetrics, ShuffleId, ShuffleLocation, TaskMetrics,
+    TaskUpdate, TaskUpdateState,
 };
-use crate::execution::physical_plan::{Action, ExecutorMeta, ShuffleId, ShuffleLocation};
-use crate::execution::physical_plan::{AggregateMode, PhysicalPlan};
+use crate::execution::physical_plan::{AggregateMode, JoinType, Partitioning, PhysicalPlan};
+use crate::execution::udf::{udf_registry, ScalarUdfSignature};
 use crate::protobuf;
 
 use uuid::Uuid;
@@ -72,13 +93,7 @@ impl TryInto<LogicalPlan> for &protobuf::LogicalPlanNode {
         } else if let Some(selection) = &self.selection {
             let input: LogicalPlan = convert_box_required!(self.input)?;
             LogicalPlanBuilder::from(&input)
-                .filter(
-                    selection
-                        .expr
-                        .as_ref()
-                        .expect("expression required")
-                        .try_into()?,
-                )?
+                .filter(convert_required!(selection.expr)?)?
                 .build()
                 .map_err(|e| e.into())
         } else if let Some(aggregate) = &self.aggregate {
@@ -97,6 +112,40 @@ impl TryInto<LogicalPlan> for &protobuf::LogicalPlanNode {
                 .aggregate(group_expr, aggr_expr)?
                 .build()
                 .map_err(|e| e.into())
+        } else if let Some(sort) = &self.sort {
+            let input: LogicalPlan = convert_box_required!(self.input)?;
+            let sort_expr = sort
+                .expr
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<Vec<_>, _>>()?;
+            LogicalPlanBuilder::from(&input)
+                .sort(sort_expr)?
+                .build()
+                .map_err(|e| e.into())
+        } else if let Some(limit) = &self.limit {
+            let input: LogicalPlan = convert_box_required!(self.input)?;
+            LogicalPlanBuilder::from(&input)
+                .limit(limit.limit as usize)?
+                .build()
+                .map_err(|e| e.into())
+        } else if let Some(join) = &self.join {
+            let left: LogicalPlan = convert_box_required!(join.left)?;
+            let right: LogicalPlan = convert_box_required!(join.right)?;
+            Ok(JoinNode::try_new(
+                left,
+                right,
+                join_on_from_proto(&join.on),
+                join_type_from_proto(join.join_type)?,
+            )?
+            .into_plan())
+        } else if let Some(union) = &self.union {
+            let inputs = union
+                .inputs
+                .iter()
+                .map(|input| input.try_into())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(UnionNode::try_new(inputs, union.all)?.into_plan())
         } else if let Some(scan) = &self.scan {
             let schema: Schema = convert_required!(scan.schema)?;
 
@@ -108,23 +157,68 @@ impl TryInto<LogicalPlan> for &protobuf::LogicalPlanNode {
 
             match scan.file_format.as_str() {
                 "csv" => {
+                    // the format options are carried in the schema metadata of logical scans
+                    let (delimiter, format) = csv_options_from_proto(&scan.csv_options)?;
+                    let schema =
+                        Schema::new_with_metadata(schema.fields().clone(), format.to_metadata());
                     let options = CsvReadOptions::new()
                         .schema(&schema)
-                        .has_header(scan.has_header);
+                        .has_header(scan.has_header)
+                        .delimiter(delimiter);
                     LogicalPlanBuilder::scan_csv(
                         &scan.path, options, None, //TODO projection
                     )?
                     .build()
                     .map_err(|e| e.into())
                 }
-                "parquet" => LogicalPlanBuilder::scan_parquet(&scan.path, None)? //TODO projection
-                    .build()
-                    .map_err(|e| e.into()),
+                // the schema includes any partition columns, which DataFusion does not discover
+                "parquet" => Ok(LogicalPlan::ParquetScan {
+                    path: scan.path.clone(),
+                    schema: Box::new(schema.clone()),
+                    projection: None, //TODO projection
+                    projected_schema: Box::new(schema),
+                }),
+                "json" | "avro" | "arrow" => {
+                    let schema_name = match scan.file_format.as_str() {
+                        "json" => JSON_SCHEMA_NAME,
+                        "avro" => AVRO_SCHEMA_NAME,
+                        _ => ARROW_SCHEMA_NAME,
+                    };
+                    let projection = if scan.projection.is_empty() {
+                        None
+                    } else {
+                        Some(
+                            scan.projection
+                                .iter()
+                                .map(|name| schema.index_of(name))
+                                .collect::<Result<Vec<_>, _>>()?,
+                        )
+                    };
+                    LogicalPlanBuilder::scan(schema_name, &scan.path, &schema, projection)?
+                        .build()
+                        .map_err(|e| e.into())
+                }
                 other => Err(ballista_error(&format!(
                     "Unsupported file format '{}' for file scan",
                     other
                 ))),
             }
+        } else if let Some(table_scan) = &self.table_scan {
+            let schema: Schema = convert_required!(table_scan.schema)?;
+            let projection = if table_scan.projection.is_empty() {
+                None
+            } else {
+                Some(
+                    table_scan
+                        .projection
+                        .iter()
+                        .map(|name| schema.index_of(name))
+                        .collect::<Result<Vec<_>, _>>()?,
+                )
+            };
+            LogicalPlanBuilder::scan("default", &table_scan.table_name, &schema, projection)?
+                .build()
+                .map_err(|e| e.into())
         } else {
             Err(ballista_error(&format!(
                 "Unsupported logical plan '{:?}'",
@@ -179,15 +273,27 @@ impl TryInto<Expr> for &protobuf::LogicalExprNode {
                 f if f == protobuf::AggregateFunction::Sum as i32 => Ok("SUM"),
                 f if f == protobuf::AggregateFunction::Avg as i32 => Ok("AVG"),
                 f if f == protobuf::AggregateFunction::Count as i32 => Ok("COUNT"),
+                f if f == protobuf::AggregateFunction::CountDistinct as i32 => Ok("COUNT_DISTINCT"),
+                f if f == protobuf::AggregateFunction::Var as i32 => Ok("VAR"),
+                f if f == protobuf::AggregateFunction::VarPop as i32 => Ok("VAR_POP"),
+                f if f == protobuf::AggregateFunction::Stddev as i32 => Ok("STDDEV"),
+                f if f == protobuf::AggregateFunction::StddevPop as i32 => Ok("STDDEV_POP"),
+                f if f == protobuf::AggregateFunction::ApproxPercentile as i32 => {
+                    Ok("APPROX_PERCENTILE")
+                }
                 other => Err(ballista_error(&format!(
                     "Unsupported aggregate function '{:?}'",
                     other
                 ))),
             }?;
 
+            let mut args = vec![parse_required_expr(&aggregate_expr.expr)?];
+            for arg in &aggregate_expr.args {
+                args.push(arg.try_into()?);
+            }
             Ok(Expr::AggregateFunction {
                 name: name.to_owned(),
-                args: vec![parse_required_expr(&aggregate_expr.expr)?],
+                args,
                 return_type: DataType::Boolean, //TODO
             })
         } else if let Some(alias) = &self.alias {
@@ -195,6 +301,163 @@ impl TryInto<Expr> for &protobuf::LogicalExprNode {
                 Box::new(parse_required_expr(&alias.expr)?),
                 alias.alias.clone(),
             ))
+        } else if let Some(window) = &self.window {
+            let window_expr: WindowExpr = window.try_into()?;
+            Ok(window_expr.to_expr())
+        } else if let Some(scalar_function) = &self.scalar_function {
+            let fun = match scalar_function.fun {
+                f if f == protobuf::ScalarFunction::Abs as i32 => Ok(ScalarFunction::Abs),
+                f if f == protobuf::ScalarFunction::Ceil as i32 => Ok(ScalarFunction::Ceil),
+                f if f == protobuf::ScalarFunction::Floor as i32 => Ok(ScalarFunction::Floor),
+                f if f == protobuf::ScalarFunction::Round as i32 => Ok(ScalarFunction::Round),
+                f if f == protobuf::ScalarFunction::Sqrt as i32 => Ok(ScalarFunction::Sqrt),
+                f if f == protobuf::ScalarFunction::Ln as i32 => Ok(ScalarFunction::Ln),
+                f if f == protobuf::ScalarFunction::Exp as i32 => Ok(ScalarFunction::Exp),
+                f if f == protobuf::ScalarFunction::Upper as i32 => Ok(ScalarFunction::Upper),
+                f if f == protobuf::ScalarFunction::Lower as i32 => Ok(ScalarFunction::Lower),
+                f if f == protobuf::ScalarFunction::Trim as i32 => Ok(ScalarFunction::Trim),
+                f if f == protobuf::ScalarFunction::Ltrim as i32 => Ok(ScalarFunction::Ltrim),
+                f if f == protobuf::ScalarFunction::Rtrim as i32 => Ok(ScalarFunction::Rtrim),
+                f if f == protobuf::ScalarFunction::Length as i32 => Ok(ScalarFunction::Length),
+                f if f == protobuf::ScalarFunction::Substring as i32 => {
+                    Ok(ScalarFunction::Substring)
+                }
+                f if f == protobuf::ScalarFunction::Concat as i32 => Ok(ScalarFunction::Concat),
+                f if f == protobuf::ScalarFunction::DateTrunc as i32 => {
+                    Ok(ScalarFunction::DateTrunc)
+                }
+                f if f == protobuf::ScalarFunction::Extract as i32 => Ok(ScalarFunction::Extract),
+                other => Err(ballista_error(&format!(
+                    "Unsupported scalar function '{:?}'",
+                    other
+                ))),
+            }?;
+            Ok(Expr::ScalarFunction {
+                name: fun.name().to_owned(),
+                args: scalar_function
+                    .args
+                    .iter()
+                    .map(|e| e.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                return_type: fun.logical_return_type(),
+            })
+        } else if let Some(scalar_udf) = &self.scalar_udf {
+            let signature = ScalarUdfSignature::new(
+                &scalar_udf.name,
+                scalar_udf
+                    .arg_types
+                    .iter()
+                    .map(|t| from_proto_arrow_type(*t))
+                    .collect::<Result<Vec<_>, _>>()?,
+                from_proto_arrow_type(scalar_udf.return_type)?,
+            );
+            // record the signature so that plans containing this UDF can be serialized again
+            udf_registry().register_signature(signature.clone())?;
+            Ok(Expr::ScalarFunction {
+                name: signature.name,
+                args: scalar_udf
+                    .args
+                    .iter()
+                    .map(|e| e.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                return_type: signature.return_type,
+            })
+        } else if let Some(cast) = &self.cast {
+            let data_type = match from_proto_arrow_type(cast.arrow_type)? {
+                DataType::Timestamp(_, tz) => DataType::Timestamp(
+                    match cast.time_unit {
+                        u if u == protobuf::TimeUnit::Second as i32 => Ok(TimeUnit::Second),
+                        u if u == protobuf::TimeUnit::Millisecond as i32 => {
+                            Ok(TimeUnit::Millisecond)
+                        }
+                        u if u == protobuf::TimeUnit::Microsecond as i32 => {
+                            Ok(TimeUnit::Microsecond)
+                        }
+                        u if u == protobuf::TimeUnit::Nanosecond as i32 => Ok(TimeUnit::Nanosecond),
+                        other => Err(ballista_error(&format!(
+                            "Unsupported time unit '{:?}'",
+                            other
+                        ))),
+                    }?,
+                    tz,
+                ),
+                other => other,
+            };
+            Ok(Expr::Cast {
+                expr: Box::new(parse_required_expr(&cast.expr)?),
+                data_type,
+            })
+        } else if let Some(in_list) = &self.in_list {
+            let mut args = vec![parse_required_expr(&in_list.expr)?];
+            for e in &in_list.list {
+                args.push(e.try_into()?);
+            }
+            Ok(encode_predicate(
+                IN_LIST_FUNCTION_NAME,
+                in_list.negated,
+                args,
+            ))
+        } else if let Some(between) = &self.between {
+            Ok(encode_predicate(
+                BETWEEN_FUNCTION_NAME,
+                between.negated,
+                vec![
+                    parse_required_expr(&between.expr)?,
+                    parse_required_expr(&between.low)?,
+                    parse_required_expr(&between.high)?,
+                ],
+            ))
+        } else if let Some(is_null) = &self.is_null_expr {
+            Ok(Expr::IsNull(Box::new(parse_required_expr(&is_null.expr)?)))
+        } else if let Some(is_not_null) = &self.is_not_null_expr {
+            Ok(Expr::IsNotNull(Box::new(parse_required_expr(
+                &is_not_null.expr,
+            )?)))
+        } else if let Some(not) = &self.not_expr {
+            Ok(Expr::Not(Box::new(parse_required_expr(&not.expr)?)))
+        } else if let Some(case) = &self.case_expr {
+            let parts = CaseParts {
+                expr: match &case.expr {
+                    Some(expr) => Some(expr.as_ref().try_into()?),
+                    None => None,
+                },
+                when_then_expr: case
+                    .when_then_expr
+                    .iter()
+                    .map(|when_then| {
+                        Ok((
+                            convert_required!(when_then.when_expr)?,
+                            convert_required!(when_then.then_expr)?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, BallistaError>>()?,
+                else_expr: match &case.else_expr {
+                    Some(else_expr) => Some(else_expr.as_ref().try_into()?),
+                    None => None,
+                },
+            };
+            Ok(parts.to_expr(from_proto_arrow_type(case.return_type)?))
+        } else if let Some(param) = &self.parameter {
+            Ok(parameter(
+                param.index as usize,
+                from_proto_arrow_type(param.arrow_type)?,
+            ))
+        } else if let Some(coalesce) = &self.coalesce {
+            Ok(Expr::ScalarFunction {
+                name: COALESCE_FUNCTION_NAME.to_owned(),
+                args: coalesce
+                    .args
+                    .iter()
+                    .map(|e| e.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+                return_type: from_proto_arrow_type(coalesce.return_type)?,
+            })
+        } else if let Some(sort) = &self.sort {
+            Ok(Expr::Sort {
+                expr: Box::new(parse_required_expr(&sort.expr)?),
+                asc: sort.asc,
+                nulls_first: sort.nulls_first,
+            })
         } else {
             Err(ballista_error(&format!(
                 "Unsupported logical expression '{:?}'",
@@ -208,15 +471,143 @@ impl TryInto<Action> for &protobuf::Action {
     type Error = BallistaError;
 
     fn try_into(self) -> Result<Action, Self::Error> {
+        let settings: QuerySettings = match &self.settings {
+            Some(settings) => settings.try_into()?,
+            None => QuerySettings::default(),
+        };
         if self.query.is_some() {
             let plan: LogicalPlan = convert_required!(self.query)?;
-            Ok(Action::InteractiveQuery { plan })
+            Ok(Action::InteractiveQuery { plan, settings })
         } else if self.task.is_some() {
             let task: ExecutionTask = convert_required!(self.task)?;
             Ok(Action::Execute(task))
         } else if self.fetch_shuffle.is_some() {
             let shuffle_id: ShuffleId = convert_required!(self.fetch_shuffle)?;
             Ok(Action::FetchShuffle(shuffle_id))
+        } else if let Some(action) = &self.executor_action {
+            let action = match action.action_type {
+                t if t == protobuf::ExecutorActionType::Shutdown as i32 => ExecutorAction::Shutdown,
+                t if t == protobuf::ExecutorActionType::Drain as i32 => ExecutorAction::Drain,
+                t if t == protobuf::ExecutorActionType::ClearShuffleCache as i32 => {
+                    ExecutorAction::ClearShuffleCache
+                }
+                t if t == protobuf::ExecutorActionType::Stats as i32 => ExecutorAction::Stats,
+                t if t == protobuf::ExecutorActionType::ListShuffles as i32 => {
+                    ExecutorAction::ListShuffles
+                }
+                t if t == protobuf::ExecutorActionType::Unspecified as i32 => {
+                    return Err(BallistaError::General(
+                        "Executor action type is not specified".to_owned(),
+                    ))
+                }
+                other => {
+                    return Err(BallistaError::General(format!(
+                        "Invalid executor action type {}",
+                        other
+                    )))
+                }
+            };
+            Ok(Action::Manage(action))
+        } else if let Some(cancel_task) = &self.cancel_task {
+            Ok(Action::CancelTask {
+                job_uuid: Uuid::parse_str(&cancel_task.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+                stage_id: cancel_task.stage_id as usize,
+                partition_id: cancel_task.partition_id as usize,
+            })
+        } else if let Some(withdraw_task) = &self.withdraw_task {
+            Ok(Action::WithdrawTask {
+                job_uuid: Uuid::parse_str(&withdraw_task.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+                stage_id: withdraw_task.stage_id as usize,
+                partition_id: withdraw_task.partition_id as usize,
+            })
+        } else if let Some(release_job) = &self.release_job {
+            Ok(Action::ReleaseJob(
+                Uuid::parse_str(&release_job.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+            ))
+        } else if let Some(retain_shuffles) = &self.retain_shuffles {
+            Ok(Action::RetainShuffles {
+                job_uuid: Uuid::parse_str(&retain_shuffles.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+                shuffle_ids: retain_shuffles
+                    .shuffle_ids
+                    .iter()
+                    .map(|shuffle_id| shuffle_id.try_into())
+                    .collect::<Result<_, _>>()?,
+            })
+        } else if let Some(watch_tasks) = &self.watch_tasks {
+            Ok(Action::WatchTasks(
+                Uuid::parse_str(&watch_tasks.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+            ))
+        } else if let Some(registration) = &self.register_executor {
+            Ok(Action::RegisterExecutor(ExecutorRegistration {
+                meta: ExecutorMeta {
+                    id: registration.id.clone(),
+                    host: registration.host.clone(),
+                    port: registration.port as usize,
+                },
+                cores: registration.cores as usize,
+                memory_bytes: registration.memory_bytes,
+            }))
+        } else if let Some(heartbeat) = &self.heartbeat {
+            Ok(Action::Heartbeat {
+                executor_id: heartbeat.executor_id.clone(),
+            })
+        } else if let Some(deregistration) = &self.deregister_executor {
+            Ok(Action::DeregisterExecutor {
+                executor_id: deregistration.executor_id.clone(),
+            })
+        } else if self.list_executors.is_some() {
+            Ok(Action::ListExecutors)
+        } else if let Some(register_table) = &self.register_table {
+            Ok(Action::RegisterTable {
+                name: register_table.name.clone(),
+                plan: convert_required!(register_table.plan)?,
+            })
+        } else if let Some(write_query) = &self.write_query {
+            Ok(Action::Write {
+                plan: convert_required!(write_query.plan)?,
+                path: write_query.path.clone(),
+                format: WriteFormat::from_name(&write_query.file_format)?,
+                settings,
+            })
+        } else if let Some(analyze) = &self.analyze {
+            Ok(Action::Analyze {
+                plan: convert_required!(analyze.plan)?,
+                settings,
+            })
+        } else if let Some(explain) = &self.explain {
+            Ok(Action::Explain {
+                plan: convert_required!(explain.plan)?,
+                analyze: explain.analyze,
+                settings,
+            })
+        } else if self.prepare.is_some() {
+            Ok(Action::Prepare {
+                plan: convert_required!(self.prepare)?,
+                settings,
+            })
+        } else if let Some(execute_prepared) = &self.execute_prepared {
+            let params = execute_prepared
+                .params
+                .iter()
+                .map(|param| match param.try_into()? {
+                    Expr::Literal(value) => Ok(value),
+                    other => Err(ballista_error(&format!(
+                        "Parameter values must be literals, not {:?}",
+                        other
+                    ))),
+                })
+                .collect::<Result<Vec<_>, BallistaError>>()?;
+            Ok(Action::ExecutePrepared {
+                handle: execute_prepared.handle.clone(),
+                params,
+            })
+        } else if let Some(close_prepared) = &self.close_prepared {
+            Ok(Action::ClosePrepared(close_prepared.handle.clone()))
         } else {
             Err(BallistaError::NotImplemented(format!(
                 "from_proto(Action) {:?}",
@@ -238,6 +629,8 @@ fn from_proto_binary_op(op: &str) -> Result<Operator, BallistaError> {
         "Minus" => Ok(Operator::Minus),
         "Multiply" => Ok(Operator::Multiply),
         "Divide" => Ok(Operator::Divide),
+        "And" => Ok(Operator::And),
+        "Or" => Ok(Operator::Or),
         other => Err(ballista_error(&format!(
             "Unsupported binary operator '{:?}'",
             other
@@ -247,6 +640,8 @@ fn from_proto_binary_op(op: &str) -> Result<Operator, BallistaError> {
 
 fn from_proto_arrow_type(dt: i32) -> Result<DataType, BallistaError> {
     match dt {
+        dt if dt == protobuf::ArrowType::None as i32 => Ok(DataType::Null),
+        dt if dt == protobuf::ArrowType::Bool as i32 => Ok(DataType::Boolean),
         dt if dt == protobuf::ArrowType::Uint8 as i32 => Ok(DataType::UInt8),
         dt if dt == protobuf::ArrowType::Int8 as i32 => Ok(DataType::Int8),
         dt if dt == protobuf::ArrowType::Uint16 as i32 => Ok(DataType::UInt16),
@@ -258,6 +653,13 @@ fn from_proto_arrow_type(dt: i32) -> Result<DataType, BallistaError> {
         dt if dt == protobuf::ArrowType::Float as i32 => Ok(DataType::Float32),
         dt if dt == protobuf::ArrowType::Double as i32 => Ok(DataType::Float64),
         dt if dt == protobuf::ArrowType::Utf8 as i32 => Ok(DataType::Utf8),
+        dt if dt == protobuf::ArrowType::Date32 as i32 => Ok(DataType::Date32(DateUnit::Day)),
+        dt if dt == protobuf::ArrowType::Date64 as i32 => {
+            Ok(DataType::Date64(DateUnit::Millisecond))
+        }
+        dt if dt == protobuf::ArrowType::Timestamp as i32 => {
+            Ok(DataType::Timestamp(TimeUnit::Millisecond, None))
+        }
         other => Err(BallistaError::General(format!(
             "Unsupported data type {:?}",
             other
@@ -272,7 +674,8 @@ impl TryInto<ExecutionTask> for &protobuf::Task {
         let mut shuffle_locations: HashMap<ShuffleId, ExecutorMeta> = HashMap::new();
         for loc in &self.shuffle_loc {
             let shuffle_id = ShuffleId::new(
-                Uuid::parse_str(&loc.job_uuid).expect("error parsing uuid in from_proto"),
+                Uuid::parse_str(&loc.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
                 loc.stage_id as usize,
                 loc.partition_id as usize,
             );
@@ -286,13 +689,38 @@ impl TryInto<ExecutionTask> for &protobuf::Task {
             shuffle_locations.insert(shuffle_id, exec);
         }
 
-        Ok(ExecutionTask::new(
-            Uuid::parse_str(&self.job_uuid).expect("error parsing uuid in from_proto"),
+        let mut task = ExecutionTask::new(
+            Uuid::parse_str(&self.job_uuid)
+                .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
             self.stage_id as usize,
             self.partition_id as usize,
             convert_required!(self.plan)?,
             shuffle_locations,
-        ))
+        )
+        .with_shuffle_compression(ShuffleCompression::from_name(&self.shuffle_compression)?)
+        .with_attempt(self.attempt as usize)
+        .with_resources(TaskResources {
+            cores: (self.cores as usize).max(1),
+            memory_bytes: self.memory_bytes,
+        });
+        if !self.output_partition_expr.is_empty() {
+            let exprs = self
+                .output_partition_expr
+                .iter()
+                .map(|expr| Ok(Arc::new(expr.try_into()?)))
+                .collect::<Result<Vec<Arc<Expr>>, BallistaError>>()?;
+            task = task.with_output_partitioning(Partitioning::HashPartitioning(
+                self.output_partition_count as usize,
+                exprs,
+            ));
+        }
+        if self.memory_limit > 0 {
+            task = task.with_memory_limit(self.memory_limit as usize);
+        }
+        if self.deadline_ms > 0 {
+            task = task.with_deadline(UNIX_EPOCH + Duration::from_millis(self.deadline_ms));
+        }
+        Ok(task)
     }
 }
 
@@ -300,7 +728,145 @@ impl TryInto<ShuffleLocation> for &protobuf::ShuffleLocation {
     type Error = BallistaError;
 
     fn try_into(self) -> Result<ShuffleLocation, Self::Error> {
-        Ok(ShuffleLocation {}) //TODO why empty?
+        Ok(ShuffleLocation::new(
+            ShuffleId::new(
+                Uuid::parse_str(&self.job_uuid)
+                    .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+                self.stage_id as usize,
+                self.partition_id as usize,
+            ),
+            ExecutorMeta {
+                id: self.executor_id.to_owned(),
+                host: self.executor_host.to_owned(),
+                port: self.executor_port as usize,
+            },
+        ))
+    }
+}
+
+impl TryInto<JobStatus> for &protobuf::JobStatus {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<JobStatus, Self::Error> {
+        let state = match self.state {
+            s if s == protobuf::JobState::Queued as i32 => JobState::Queued,
+            s if s == protobuf::JobState::Running as i32 => JobState::Running,
+            s if s == protobuf::JobState::Completed as i32 => JobState::Completed(
+                self.partition_location
+                    .iter()
+                    .map(|p| p.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            s if s == protobuf::JobState::Failed as i32 => JobState::Failed(self.error.clone()),
+            s if s == protobuf::JobState::Cancelled as i32 => JobState::Cancelled,
+            other => {
+                return Err(BallistaError::General(format!(
+                    "Invalid job state {}",
+                    other
+                )))
+            }
+        };
+        Ok(JobStatus {
+            job_uuid: Uuid::parse_str(&self.job_uuid)
+                .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+            state,
+            submitted_at: UNIX_EPOCH + Duration::from_millis(self.submitted_at_ms),
+            progress: JobProgress {
+                stages: self
+                    .stage_progress
+                    .iter()
+                    .map(|stage| StageProgress {
+                        stage_id: stage.stage_id as usize,
+                        total_tasks: stage.total_tasks as usize,
+                        tasks: TaskCounts {
+                            pending: stage.pending_tasks as usize,
+                            queued: stage.queued_tasks as usize,
+                            running: stage.running_tasks as usize,
+                            completed: stage.completed_tasks as usize,
+                            failed: stage.failed_tasks as usize,
+                        },
+                        shuffle_bytes: stage.shuffle_bytes as usize,
+                        completed: stage.completed,
+                    })
+                    .collect(),
+            },
+        })
+    }
+}
+
+impl TryInto<JobRecord> for &protobuf::JobRecord {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<JobRecord, Self::Error> {
+        Ok(JobRecord {
+            status: convert_required!(self.status)?,
+            root_stage_id: self.root_stage_id as usize,
+            stages: self
+                .stages
+                .iter()
+                .map(|stage| stage.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            settings: match &self.settings {
+                Some(settings) => settings.try_into()?,
+                None => QuerySettings::default(),
+            },
+            tenant: match self.tenant.as_str() {
+                "" => DEFAULT_TENANT.to_owned(),
+                tenant => tenant.to_owned(),
+            },
+        })
+    }
+}
+
+impl TryInto<TableDefinition> for &protobuf::TableDefinition {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<TableDefinition, Self::Error> {
+        Ok(TableDefinition {
+            name: self.name.clone(),
+            location: self.location.clone(),
+            format: self.file_format.clone(),
+            schema: convert_required!(self.schema)?,
+            partition_columns: self.partition_columns.clone(),
+        })
+    }
+}
+
+impl TryInto<QuerySettings> for &protobuf::QuerySettings {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<QuerySettings, Self::Error> {
+        let positive = |n: u64| if n > 0 { Some(n) } else { None };
+        Ok(QuerySettings {
+            batch_size: positive(self.batch_size).map(|n| n as usize),
+            target_partitions: positive(self.target_partitions as u64).map(|n| n as usize),
+            memory_limit: positive(self.memory_limit).map(|n| n as usize),
+            timeout: positive(self.timeout_ms).map(Duration::from_millis),
+            task_timeout: positive(self.task_timeout_ms).map(Duration::from_millis),
+        })
+    }
+}
+
+impl TryInto<StageRecord> for &protobuf::StageRecord {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<StageRecord, Self::Error> {
+        Ok(StageRecord {
+            stage_id: self.stage_id as usize,
+            prior_stages: self.prior_stages.iter().map(|id| *id as usize).collect(),
+            plan: convert_required!(self.plan)?,
+            completed: self.completed,
+            shuffle_locations: self
+                .shuffle_location
+                .iter()
+                .map(|loc| loc.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            task_assignments: self
+                .task_assignment
+                .iter()
+                .map(|a| (a.partition_id as usize, a.executor_id.clone()))
+                .collect(),
+        })
     }
 }
 
@@ -309,13 +875,70 @@ impl TryInto<ShuffleId> for &protobuf::ShuffleId {
 
     fn try_into(self) -> Result<ShuffleId, Self::Error> {
         Ok(ShuffleId::new(
-            Uuid::parse_str(&self.job_uuid).expect("error parsing uuid in from_proto"),
+            Uuid::parse_str(&self.job_uuid)
+                .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
             self.stage_id as usize,
             self.partition_id as usize,
         ))
     }
 }
 
+impl TryInto<TaskMetrics> for &protobuf::TaskMetrics {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<TaskMetrics, Self::Error> {
+        Ok(TaskMetrics {
+            output_rows: self.output_rows as usize,
+            output_batches: self.output_batches as usize,
+            shuffle_bytes: self.shuffle_bytes as usize,
+            duration_ms: self.duration_ms,
+            operators: self
+                .operators
+                .iter()
+                .map(|op| OperatorMetrics {
+                    name: op.name.clone(),
+                    output_rows: op.output_rows as usize,
+                    output_batches: op.output_batches as usize,
+                    output_bytes: op.output_bytes as usize,
+                    elapsed_ms: op.elapsed_ms,
+                })
+                .collect(),
+            partition_rows: self.partition_rows.iter().map(|n| *n as usize).collect(),
+        })
+    }
+}
+
+impl TryInto<TaskUpdate> for &protobuf::TaskStatusUpdate {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<TaskUpdate, Self::Error> {
+        let state = match protobuf::TaskUpdateState::from_i32(self.state) {
+            Some(protobuf::TaskUpdateState::TaskRunning) => TaskUpdateState::Running,
+            Some(protobuf::TaskUpdateState::TaskCompleted) => {
+                TaskUpdateState::Completed(convert_required!(self.metrics)?)
+            }
+            Some(protobuf::TaskUpdateState::TaskFailed) => {
+                TaskUpdateState::Failed(self.error.clone())
+            }
+            Some(protobuf::TaskUpdateState::TaskCancelled) => TaskUpdateState::Cancelled,
+            None => {
+                return Err(BallistaError::General(format!(
+                    "Invalid task update state {}",
+                    self.state
+                )))
+            }
+        };
+        Ok(TaskUpdate {
+            job_uuid: Uuid::parse_str(&self.job_uuid)
+                .map_err(|e| BallistaError::General(format!("{:?}", e)))?,
+            stage_id: self.stage_id as usize,
+            partition_id: self.partition_id as usize,
+            attempt: self.attempt as usize,
+            state,
+        })
+    }
+}
+
 impl TryInto<Schema> for &protobuf::Schema {
     type Error = BallistaError;
 
@@ -323,20 +946,101 @@ impl TryInto<Schema> for &protobuf::Schema {
         let fields = self
             .columns
             .iter()
-            .map(|c| {
-                let dt: Result<DataType, _> = from_proto_arrow_type(c.arrow_type);
-                dt.and_then(|dt| Ok(Field::new(&c.name, dt, c.nullable)))
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+            .map(|c| Ok(Field::new(&c.name, from_proto_field_type(c)?, c.nullable)))
+            .collect::<Result<Vec<_>, BallistaError>>()?;
         Ok(Schema::new(fields))
     }
 }
 
+/// Dictionary-encoded fields have the key and value types as their children
+fn from_proto_field_type(field: &protobuf::Field) -> Result<DataType, BallistaError> {
+    if field.arrow_type == protobuf::ArrowType::Dictionary as i32 {
+        match field.children.as_slice() {
+            [key, value] => Ok(DataType::Dictionary(
+                Box::new(from_proto_field_type(key)?),
+                Box::new(from_proto_field_type(value)?),
+            )),
+            _ => Err(ballista_error(&format!(
+                "Dictionary field '{}' must have key and value children",
+                field.name
+            ))),
+        }
+    } else {
+        from_proto_arrow_type(field.arrow_type)
+    }
+}
+
+impl TryInto<WindowExpr> for &protobuf::WindowExprNode {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<WindowExpr, Self::Error> {
+        let from_proto = |exprs: &[protobuf::LogicalExprNode]| {
+            exprs
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<Vec<Expr>, BallistaError>>()
+        };
+        let window_expr = WindowExpr::new(
+            WindowFunction::from_name(&self.function)?,
+            from_proto(&self.args)?,
+        )
+        .partition_by(from_proto(&self.partition_by)?)
+        .order_by(from_proto(&self.order_by)?)
+        .rows_between(
+            if self.has_preceding {
+                Some(self.preceding as usize)
+            } else {
+                None
+            },
+            if self.has_following {
+                Some(self.following as usize)
+            } else {
+                None
+            },
+        );
+        if self.alias.is_empty() {
+            Ok(window_expr)
+        } else {
+            Ok(window_expr.alias(&self.alias))
+        }
+    }
+}
+
+fn join_on_from_proto(on: &[protobuf::JoinOn]) -> Vec<(String, String)> {
+    on.iter()
+        .map(|on| (on.left.clone(), on.right.clone()))
+        .collect()
+}
+
+fn join_type_from_proto(join_type: i32) -> Result<JoinType, BallistaError> {
+    match join_type {
+        t if t == protobuf::JoinType::Inner as i32 => Ok(JoinType::Inner),
+        t if t == protobuf::JoinType::Left as i32 => Ok(JoinType::Left),
+        t if t == protobuf::JoinType::Right as i32 => Ok(JoinType::Right),
+        t if t == protobuf::JoinType::Full as i32 => Ok(JoinType::Full),
+        other => Err(ballista_error(&format!(
+            "Unsupported join type '{}' for join",
+            other
+        ))),
+    }
+}
+
 impl TryInto<PhysicalPlan> for &protobuf::PhysicalPlanNode {
     type Error = BallistaError;
 
     fn try_into(self) -> Result<PhysicalPlan, Self::Error> {
-        if let Some(selection) = &self.selection {
+        if let Some(projection) = &self.projection {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            let expr = projection
+                .expr
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(PhysicalPlan::Projection(Arc::new(ProjectionExec::try_new(
+                &expr,
+                Arc::new(input),
+            )?)))
+        } else if let Some(selection) = &self.selection {
             let input: PhysicalPlan = convert_box_required!(self.input)?;
             match selection.expr {
                 Some(ref protobuf_expr) => {
@@ -347,6 +1051,56 @@ impl TryInto<PhysicalPlan> for &protobuf::PhysicalPlanNode {
                 }
                 _ => Err(ballista_error("from_proto: Selection expr missing")),
             }
+        } else if let Some(sort) = &self.sort {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            let sort_expr = sort
+                .expr
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(PhysicalPlan::Sort(Arc::new(SortExec::try_new(
+                Arc::new(input),
+                sort_expr,
+            )?)))
+        } else if let Some(write) = &self.write {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            Ok(PhysicalPlan::Write(Arc::new(WriteExec::new(
+                Arc::new(input),
+                &write.path,
+                WriteFormat::from_name(&write.file_format)?,
+            ))))
+        } else if let Some(top_k) = &self.top_k {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            let sort_expr = top_k
+                .expr
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(PhysicalPlan::TopK(Arc::new(TopKExec::try_new(
+                Arc::new(input),
+                sort_expr,
+                top_k.k as usize,
+                top_k.partial,
+            )?)))
+        } else if let Some(window) = &self.window {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            let window_expr: WindowExpr = convert_required!(window.window_expr)?;
+            Ok(PhysicalPlan::Window(Arc::new(WindowExec::try_new(
+                Arc::new(input),
+                window_expr,
+            )?)))
+        } else if let Some(limit) = &self.global_limit {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            Ok(PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(
+                Arc::new(input),
+                limit.limit as usize,
+            ))))
+        } else if let Some(limit) = &self.local_limit {
+            let input: PhysicalPlan = convert_box_required!(self.input)?;
+            Ok(PhysicalPlan::LocalLimit(Arc::new(LocalLimitExec::new(
+                Arc::new(input),
+                limit.limit as usize,
+            ))))
         } else if let Some(aggregate) = &self.hash_aggregate {
             let input: PhysicalPlan = convert_box_required!(self.input)?;
             let mode = match aggregate.mode {
@@ -357,6 +1111,9 @@ impl TryInto<PhysicalPlan> for &protobuf::PhysicalPlanNode {
                 mode if mode == protobuf::AggregateMode::Complete as i32 => {
                     Ok(AggregateMode::Complete)
                 }
+                mode if mode == protobuf::AggregateMode::FinalPartitioned as i32 => {
+                    Ok(AggregateMode::FinalPartitioned)
+                }
                 other => Err(ballista_error(&format!(
                     "Unsupported aggregate mode '{}' for hash aggregate",
                     other
@@ -375,25 +1132,90 @@ impl TryInto<PhysicalPlan> for &protobuf::PhysicalPlanNode {
             Ok(PhysicalPlan::HashAggregate(Arc::new(
                 HashAggregateExec::try_new(mode, group_expr, aggr_expr, Arc::new(input))?,
             )))
+        } else if let Some(join) = &self.hash_join {
+            let left: PhysicalPlan = convert_box_required!(join.left)?;
+            let right: PhysicalPlan = convert_box_required!(join.right)?;
+            let left = Arc::new(left);
+            let right = Arc::new(right);
+            let exec = HashJoinExec::try_new(
+                left.clone(),
+                right.clone(),
+                join_on_from_proto(&join.on),
+                join_type_from_proto(join.join_type)?,
+                join.partition_count as usize,
+            )?;
+            let exec = if join.broadcast {
+                exec.to_broadcast(left, right)?
+            } else {
+                exec
+            };
+            Ok(PhysicalPlan::HashJoin(Arc::new(exec)))
+        } else if let Some(join) = &self.sort_merge_join {
+            let left: PhysicalPlan = convert_box_required!(join.left)?;
+            let right: PhysicalPlan = convert_box_required!(join.right)?;
+            Ok(PhysicalPlan::SortMergeJoin(Arc::new(
+                SortMergeJoinExec::try_new(
+                    Arc::new(left),
+                    Arc::new(right),
+                    join_on_from_proto(&join.on),
+                    join_type_from_proto(join.join_type)?,
+                    join.batch_size as usize,
+                )?,
+            )))
+        } else if let Some(union) = &self.union {
+            let inputs = union
+                .inputs
+                .iter()
+                .map(|input| input.try_into().map(Arc::new))
+                .collect::<Result<Vec<_>, BallistaError>>()?;
+            Ok(PhysicalPlan::Union(Arc::new(UnionExec::try_new(inputs)?)))
         } else if let Some(scan) = &self.scan {
             match scan.file_format.as_str() {
                 "csv" => {
-                    //TODO read options from proto
-                    let options = CsvReadOptions::new();
+                    let schema: Schema = convert_required!(scan.schema)?;
+                    let (delimiter, format) = csv_options_from_proto(&scan.csv_options)?;
+                    let options = CsvReadOptions::new()
+                        .schema(&schema)
+                        .has_header(scan.has_header)
+                        .delimiter(delimiter);
                     Ok(PhysicalPlan::CsvScan(Arc::new(CsvScanExec::try_new(
                         &scan.path,
                         options,
+                        format,
                         Some(scan.projection.iter().map(|n| *n as usize).collect()),
                         scan.batch_size as usize,
                     )?)))
                 }
-                "parquet" => Ok(PhysicalPlan::ParquetScan(Arc::new(
-                    ParquetScanExec::try_new(
+                "parquet" => {
+                    let exec = ParquetScanExec::try_new(
                         &scan.path,
                         Some(scan.projection.iter().map(|n| *n as usize).collect()),
                         scan.batch_size as usize,
-                    )?,
-                ))),
+                    )?;
+                    let exec = match &scan.predicate {
+                        Some(predicate) => exec.with_predicate(predicate.try_into()?),
+                        None => exec,
+                    };
+                    Ok(PhysicalPlan::ParquetScan(Arc::new(exec)))
+                }
+                "json" => {
+                    let schema: Schema = convert_required!(scan.schema)?;
+                    Ok(PhysicalPlan::JsonScan(Arc::new(JsonScanExec::try_new(
+                        &scan.path,
+                        Arc::new(schema),
+                        Some(scan.projection.iter().map(|n| *n as usize).collect()),
+                        scan.batch_size as usize,
+                    )?)))
+                }
+                "avro" => Ok(PhysicalPlan::AvroScan(Arc::new(AvroScanExec::try_new(
+                    &scan.path,
+                    Some(scan.projection.iter().map(|n| *n as usize).collect()),
+                    scan.batch_size as usize,
+                )?))),
+                "arrow" => Ok(PhysicalPlan::IpcScan(Arc::new(IpcScanExec::try_new(
+                    &scan.path,
+                    Some(scan.projection.iter().map(|n| *n as usize).collect()),
+                )?))),
                 other => Err(ballista_error(&format!(
                     "Unsupported file format '{}' for file scan",
                     other
@@ -404,10 +1226,46 @@ impl TryInto<PhysicalPlan> for &protobuf::PhysicalPlanNode {
             for s in &shuffle_reader.shuffle_id {
                 shuffle_ids.push(s.try_into()?);
             }
+            let partition_count = shuffle_reader.partition_count as usize;
+            let from_proto = |exprs: &[protobuf::LogicalExprNode]| {
+                exprs
+                    .iter()
+                    .map(|expr| Ok(Arc::new(expr.try_into()?)))
+                    .collect::<Result<Vec<Arc<Expr>>, BallistaError>>()
+            };
+            let partitioning = if !shuffle_reader.partition_expr.is_empty() {
+                Partitioning::HashPartitioning(
+                    partition_count,
+                    from_proto(&shuffle_reader.partition_expr)?,
+                )
+            } else if !shuffle_reader.range_partition_expr.is_empty() {
+                Partitioning::RangePartitioning(
+                    partition_count,
+                    from_proto(&shuffle_reader.range_partition_expr)?,
+                )
+            } else {
+                Partitioning::UnknownPartitioning(partition_count.max(1))
+            };
             Ok(PhysicalPlan::ShuffleReader(Arc::new(
                 ShuffleReaderExec::new(
                     Arc::new(convert_required!(shuffle_reader.schema)?),
                     shuffle_ids,
+                )
+                .with_partitioning(partitioning)
+                .with_skew_splits(
+                    shuffle_reader
+                        .skew_splits
+                        .iter()
+                        .map(|n| *n as usize)
+                        .collect(),
+                    shuffle_reader.skew_replicated.clone(),
+                )
+                .with_coalesced_partitions(
+                    shuffle_reader
+                        .coalesced_partitions
+                        .iter()
+                        .map(|n| *n as usize)
+                        .collect(),
                 ),
             )))
         } else {
@@ -419,6 +1277,27 @@ impl TryInto<PhysicalPlan> for &protobuf::PhysicalPlanNode {
     }
 }
 
+/// The delimiter and format options of a CSV scan, which are the defaults when not provided
+fn csv_options_from_proto(
+    options: &Option<protobuf::CsvScanOptions>,
+) -> Result<(u8, CsvFormatOptions), BallistaError> {
+    let options = match options {
+        Some(options) => options,
+        None => return Ok((b',', CsvFormatOptions::new())),
+    };
+    let format = CsvFormatOptions {
+        quote: options.quote as u8,
+        escape: match options.escape {
+            0 => None,
+            c => Some(c as u8),
+        },
+        null_values: options.null_values.clone(),
+        compression: CsvCompression::from_name(&options.compression)?,
+        malformed_rows: MalformedRowPolicy::from_name(&options.malformed_rows)?,
+    };
+    Ok((options.delimiter as u8, format))
+}
+
 fn parse_required_expr(p: &Option<Box<protobuf::LogicalExprNode>>) -> Result<Expr, BallistaError> {
     match p {
         Some(expr) => expr.as_ref().try_into(),
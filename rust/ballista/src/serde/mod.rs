@@ -16,6 +16,8 @@
 //! protocol buffer representations. Ballista is designed to support multiple programming languages
 //! which is why protocol buffers was chosen for all communication between processes.
 
+use crate::distributed::job_state::JobRecord;
+use crate::distributed::table_store::TableDefinition;
 use crate::error::BallistaError;
 use crate::execution::physical_plan::Action;
 use crate::protobuf;
@@ -35,15 +37,78 @@ pub fn decode_protobuf(bytes: &[u8]) -> Result<Action, BallistaError> {
         .and_then(|node| (&node).try_into())
 }
 
+pub fn encode_protobuf(action: &Action) -> Result<Vec<u8>, BallistaError> {
+    let serialized_action: protobuf::Action = action.try_into()?;
+    let mut buf: Vec<u8> = Vec::with_capacity(serialized_action.encoded_len());
+    serialized_action
+        .encode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    Ok(buf)
+}
+
+pub fn decode_job_record(bytes: &[u8]) -> Result<JobRecord, BallistaError> {
+    let mut buf = Cursor::new(bytes);
+    protobuf::JobRecord::decode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+        .and_then(|node| (&node).try_into())
+}
+
+pub fn encode_job_record(job: &JobRecord) -> Result<Vec<u8>, BallistaError> {
+    let serialized_job: protobuf::JobRecord = job.try_into()?;
+    let mut buf: Vec<u8> = Vec::with_capacity(serialized_job.encoded_len());
+    serialized_job
+        .encode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    Ok(buf)
+}
+
+pub fn decode_table_definition(bytes: &[u8]) -> Result<TableDefinition, BallistaError> {
+    let mut buf = Cursor::new(bytes);
+    protobuf::TableDefinition::decode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))
+        .and_then(|node| (&node).try_into())
+}
+
+pub fn encode_table_definition(table: &TableDefinition) -> Result<Vec<u8>, BallistaError> {
+    let serialized_table: protobuf::TableDefinition = table.try_into()?;
+    let mut buf: Vec<u8> = Vec::with_capacity(serialized_table.encoded_len());
+    serialized_table
+        .encode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    Ok(buf)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::arrow::datatypes::{DataType, Field, Schema};
+    use crate::arrow::datatypes::{DataType, DateUnit, Field, Schema, TimeUnit};
+    use crate::dataframe::{
+        approx_percentile, between, case, cast, coalesce, concat, count_distinct, date_trunc,
+        in_list, not_between, over, param, rank, round, stddev, substring, udf, upper, when,
+    };
     use crate::datafusion::execution::physical_plan::csv::CsvReadOptions;
-    use crate::datafusion::logicalplan::{col, lit_str, Expr, LogicalPlanBuilder};
+    use crate::datafusion::logicalplan::{
+        col, lit_str, Expr, LogicalPlanBuilder, Operator, ScalarValue,
+    };
+    use crate::distributed::progress::{JobProgress, StageProgress, TaskCounts};
+    use crate::distributed::registry::ExecutorRegistration;
+    use crate::distributed::scheduler::QuerySettings;
+    use crate::distributed::scheduler_server::{JobState, JobStatus};
     use crate::error::Result;
-    use crate::execution::physical_plan::Action;
+    use crate::execution::operators::{
+        GlobalLimitExec, HashAggregateExec, HashJoinExec, JoinNode, ShuffleReaderExec,
+        SortMergeJoinExec, TopKExec, UnionExec, UnionNode, WindowExec, WriteFormat,
+    };
+    use crate::execution::physical_plan::{
+        Action, AggregateMode, ExecutorAction, ExecutorMeta, JoinType, OperatorMetrics,
+        Partitioning, PhysicalPlan, ShuffleId, ShuffleLocation, TaskMetrics, TaskUpdate,
+        TaskUpdateState,
+    };
+    use crate::execution::udf::{udf_registry, ScalarUdfSignature};
     use crate::protobuf;
     use std::convert::TryInto;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+    use uuid::Uuid;
 
     #[test]
     fn roundtrip() -> Result<()> {
@@ -73,6 +138,7 @@ mod tests {
             //     path: "/foo/bar.csv".to_owned(),
             //     schema: schema.clone(),
             // }],
+            settings: QuerySettings::default(),
         };
 
         let proto: protobuf::Action = action.try_into()?;
@@ -84,6 +150,238 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn roundtrip_table_scan() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("state", DataType::Utf8, false),
+        ]);
+
+        let table = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| plan.build())
+        .unwrap();
+        let register = &Action::RegisterTable {
+            name: "employee".to_owned(),
+            plan: table,
+        };
+
+        let plan = LogicalPlanBuilder::scan("default", "employee", &schema, None)
+            .and_then(|plan| plan.filter(col("state").eq(&lit_str("CO"))))
+            .and_then(|plan| plan.project(vec![col("id")]))
+            .and_then(|plan| plan.build())
+            .unwrap();
+        let settings = QuerySettings::new()
+            .with_batch_size(1024)
+            .with_target_partitions(8)
+            .with_memory_limit(64 * 1024 * 1024)
+            .with_timeout(Duration::from_secs(30))
+            .with_task_timeout(Duration::from_secs(10));
+        let query = &Action::InteractiveQuery {
+            plan: plan.clone(),
+            settings,
+        };
+        let analyze = &Action::Analyze {
+            plan: plan.clone(),
+            settings: QuerySettings::new().with_batch_size(4096),
+        };
+        let explain = &Action::Explain {
+            plan: plan.clone(),
+            analyze: true,
+            settings,
+        };
+        let write = &Action::Write {
+            plan,
+            path: "/tmp/output".to_owned(),
+            format: WriteFormat::Parquet,
+            settings: QuerySettings::default(),
+        };
+
+        for action in &[register, query, analyze, explain, write] {
+            let proto: protobuf::Action = (*action).try_into()?;
+            let action2: Action = (&proto).try_into()?;
+            assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_join() -> Result<()> {
+        let job_uuid = Uuid::new_v4();
+        let reader = |stage_id: usize, schema: Schema| {
+            let shuffle_id = vec![ShuffleId::new(job_uuid, stage_id, 0)];
+            Arc::new(PhysicalPlan::ShuffleReader(Arc::new(
+                ShuffleReaderExec::new(Arc::new(schema), shuffle_id).with_partitioning(
+                    Partitioning::HashPartitioning(4, vec![Arc::new(Expr::Column(0))]),
+                ),
+            )))
+        };
+        let left = reader(
+            1,
+            Schema::new(vec![
+                Field::new("id", DataType::Int32, false),
+                Field::new("name", DataType::Utf8, false),
+            ]),
+        );
+        let right = reader(
+            2,
+            Schema::new(vec![
+                Field::new("employee_id", DataType::Int32, false),
+                Field::new("salary", DataType::Int64, false),
+            ]),
+        );
+
+        for join_type in vec![
+            JoinType::Inner,
+            JoinType::Left,
+            JoinType::Right,
+            JoinType::Full,
+        ] {
+            let plan = PhysicalPlan::HashJoin(Arc::new(HashJoinExec::try_new(
+                left.clone(),
+                right.clone(),
+                vec![("id".to_owned(), "employee_id".to_owned())],
+                join_type,
+                4,
+            )?));
+            let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+            let plan2: PhysicalPlan = (&proto).try_into()?;
+            assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+        }
+
+        let exec = HashJoinExec::try_new(
+            left.clone(),
+            right.clone(),
+            vec![("id".to_owned(), "employee_id".to_owned())],
+            JoinType::Inner,
+            4,
+        )?;
+        let plan =
+            PhysicalPlan::HashJoin(Arc::new(exec.to_broadcast(left.clone(), right.clone())?));
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+        let plan2: PhysicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+
+        let plan = PhysicalPlan::SortMergeJoin(Arc::new(SortMergeJoinExec::try_new(
+            left,
+            right,
+            vec![("id".to_owned(), "employee_id".to_owned())],
+            JoinType::Full,
+            1024,
+        )?));
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+        let plan2: PhysicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_logical_join() -> Result<()> {
+        let scan = |path: &str, schema: &Schema| {
+            LogicalPlanBuilder::scan_csv(
+                path,
+                CsvReadOptions::new().schema(schema).has_header(true),
+                None,
+            )
+            .and_then(|plan| plan.build())
+            .unwrap()
+        };
+        let left = scan(
+            "employee.csv",
+            &Schema::new(vec![
+                Field::new("id", DataType::Int32, false),
+                Field::new("name", DataType::Utf8, false),
+            ]),
+        );
+        let right = scan(
+            "salary.csv",
+            &Schema::new(vec![
+                Field::new("employee_id", DataType::Int32, false),
+                Field::new("salary", DataType::Int64, false),
+            ]),
+        );
+
+        let join = JoinNode::try_new(
+            left,
+            right,
+            vec![("id".to_owned(), "employee_id".to_owned())],
+            JoinType::Left,
+        )?
+        .into_plan();
+        // rows of the left input that match nothing have nulls for the columns of the right
+        assert_eq!(4, join.schema().fields().len());
+        assert!(join.schema().field(3).is_nullable());
+
+        let plan = LogicalPlanBuilder::from(&join)
+            .limit(10)
+            .and_then(|plan| plan.build())
+            .unwrap();
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_union() -> Result<()> {
+        let scan = |path: &str, data_type: DataType| {
+            let schema = Schema::new(vec![
+                Field::new("id", data_type, false),
+                Field::new("name", DataType::Utf8, true),
+            ]);
+            LogicalPlanBuilder::scan_csv(
+                path,
+                CsvReadOptions::new().schema(&schema).has_header(true),
+                None,
+            )
+            .and_then(|plan| plan.build())
+            .unwrap()
+        };
+        let inputs = vec![
+            scan("employee.csv", DataType::Int32),
+            scan("contractor.csv", DataType::Int64),
+        ];
+        for all in vec![true, false] {
+            let plan = UnionNode::try_new(inputs.clone(), all)?.into_plan();
+            assert_eq!(&DataType::Int64, plan.schema().field(0).data_type());
+            let action = &Action::InteractiveQuery {
+                plan,
+                settings: QuerySettings::default(),
+            };
+            let proto: protobuf::Action = action.try_into()?;
+            let action2: Action = (&proto).try_into()?;
+            assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        }
+
+        let job_uuid = Uuid::new_v4();
+        let reader = |stage_id: usize, data_type: DataType| {
+            let schema = Schema::new(vec![Field::new("id", data_type, false)]);
+            let shuffle_id = vec![ShuffleId::new(job_uuid, stage_id, 0)];
+            Arc::new(PhysicalPlan::ShuffleReader(Arc::new(
+                ShuffleReaderExec::new(Arc::new(schema), shuffle_id),
+            )))
+        };
+        let plan = PhysicalPlan::Union(Arc::new(UnionExec::try_new(vec![
+            reader(1, DataType::Int32),
+            reader(2, DataType::Int64),
+        ])?));
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+        let plan2: PhysicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+
+        Ok(())
+    }
+
     #[test]
     fn roundtrip_aggregate() -> Result<()> {
         let schema = Schema::new(vec![
@@ -99,7 +397,16 @@ mod tests {
             CsvReadOptions::new().schema(&schema).has_header(true),
             None,
         )
-        .and_then(|plan| plan.aggregate(vec![col("state")], vec![max(col("salary"))]))
+        .and_then(|plan| {
+            plan.aggregate(
+                vec![col("state")],
+                vec![
+                    max(col("salary")),
+                    stddev(col("salary")),
+                    approx_percentile(col("salary"), 0.9),
+                ],
+            )
+        })
         .and_then(|plan| plan.build())
         .unwrap();
 
@@ -111,6 +418,7 @@ mod tests {
             //     path: "/foo/bar.csv".to_owned(),
             //     schema: schema.clone(),
             // }],
+            settings: QuerySettings::default(),
         };
 
         let proto: protobuf::Action = action.try_into()?;
@@ -122,6 +430,545 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn roundtrip_count_distinct() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("state", DataType::Utf8, false),
+            Field::new("salary", DataType::Int32, false),
+        ]);
+
+        let reader =
+            ShuffleReaderExec::new(Arc::new(schema), vec![ShuffleId::new(Uuid::new_v4(), 1, 0)])
+                .with_partitioning(Partitioning::HashPartitioning(
+                    4,
+                    vec![Arc::new(col("state"))],
+                ));
+        let exec = HashAggregateExec::try_new(
+            AggregateMode::FinalPartitioned,
+            vec![col("state")],
+            vec![count_distinct(col("salary")).alias("salaries")],
+            Arc::new(PhysicalPlan::ShuffleReader(Arc::new(reader))),
+        )?;
+        let plan = PhysicalPlan::HashAggregate(Arc::new(exec));
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+        let plan2: PhysicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_scalar_functions() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("salary", DataType::Float64, false),
+            Field::new("hired", DataType::Date32(DateUnit::Day), false),
+        ]);
+
+        let plan = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| {
+            plan.project(vec![
+                concat(vec![
+                    upper(substring(col("name"), 1, Some(3))),
+                    lit_str("-"),
+                ]),
+                round(col("salary"), 2),
+                date_trunc("month", col("hired")),
+            ])
+        })
+        .and_then(|plan| plan.build())
+        .unwrap();
+
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_conditional_expressions() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("state", DataType::Utf8, true),
+            Field::new("salary", DataType::Float64, true),
+            Field::new("bonus", DataType::Float64, true),
+        ]);
+
+        let plan = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| {
+            plan.project(vec![
+                when(Expr::IsNull(Box::new(col("state"))), lit_str("unknown"))
+                    .when(col("state").eq(&lit_str("CO")), lit_str("Colorado"))
+                    .otherwise(col("state")),
+                case(col("state")).when(lit_str("CA"), col("bonus")).end(),
+                coalesce(vec![col("bonus"), col("salary")]),
+                Expr::IsNotNull(Box::new(col("bonus"))),
+                Expr::Not(Box::new(Expr::BinaryExpr {
+                    left: Box::new(col("bonus").gt(&col("salary"))),
+                    op: Operator::Or,
+                    right: Box::new(Expr::IsNull(Box::new(col("bonus")))),
+                })),
+            ])
+        })
+        .and_then(|plan| plan.build())
+        .unwrap();
+
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_predicates() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("state", DataType::Utf8, false),
+            Field::new("salary", DataType::Int32, false),
+        ]);
+
+        let plan = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| {
+            plan.filter(in_list(
+                col("state"),
+                vec![lit_str("CA"), lit_str("CO")],
+                true,
+            ))
+        })
+        .and_then(|plan| {
+            plan.project(vec![
+                between(
+                    col("salary"),
+                    Expr::Literal(ScalarValue::Int32(1000)),
+                    Expr::Literal(ScalarValue::Int32(2000)),
+                ),
+                not_between(
+                    col("salary"),
+                    Expr::Literal(ScalarValue::Int32(0)),
+                    Expr::Literal(ScalarValue::Int32(10)),
+                ),
+            ])
+        })
+        .and_then(|plan| plan.build())
+        .unwrap();
+
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_cast() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Utf8, false),
+            Field::new("salary", DataType::Float64, false),
+            Field::new("hired", DataType::Utf8, false),
+        ]);
+
+        let plan = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| {
+            plan.project(vec![
+                cast(col("id"), DataType::Int64),
+                cast(col("salary"), DataType::Int32),
+                cast(
+                    col("hired"),
+                    DataType::Timestamp(TimeUnit::Microsecond, None),
+                ),
+                cast(col("hired"), DataType::Date32(DateUnit::Day)),
+            ])
+        })
+        .and_then(|plan| plan.build())
+        .unwrap();
+
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_scalar_udf() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("salary", DataType::Float64, false),
+        ]);
+        udf_registry().register_signature(ScalarUdfSignature::new(
+            "tax_band",
+            vec![DataType::Utf8, DataType::Float64],
+            DataType::Int32,
+        ))?;
+        let tax_band = udf("tax_band", vec![col("name"), col("salary")])?;
+
+        let plan = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| plan.project(vec![tax_band]))
+        .and_then(|plan| plan.build())
+        .unwrap();
+
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_sort() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("state", DataType::Utf8, false),
+            Field::new("salary", DataType::Int32, false),
+        ]);
+
+        let sort_expr = vec![
+            Expr::Sort {
+                expr: Box::new(col("state")),
+                asc: true,
+                nulls_first: false,
+            },
+            Expr::Sort {
+                expr: Box::new(col("salary")),
+                asc: false,
+                nulls_first: true,
+            },
+        ];
+        let plan = LogicalPlanBuilder::scan_csv(
+            "employee.csv",
+            CsvReadOptions::new().schema(&schema).has_header(true),
+            None,
+        )
+        .and_then(|plan| plan.sort(sort_expr.clone()))
+        .and_then(|plan| plan.limit(10))
+        .and_then(|plan| plan.build())
+        .unwrap();
+
+        let action = &Action::InteractiveQuery {
+            plan,
+            settings: QuerySettings::default(),
+        };
+        let proto: protobuf::Action = action.try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        let reader =
+            ShuffleReaderExec::new(Arc::new(schema), vec![ShuffleId::new(Uuid::new_v4(), 1, 0)])
+                .with_partitioning(Partitioning::RangePartitioning(
+                    4,
+                    sort_expr.iter().cloned().map(Arc::new).collect(),
+                ));
+        let plan = PhysicalPlan::ShuffleReader(Arc::new(reader));
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+        let plan2: PhysicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+
+        let top_k = TopKExec::try_new(Arc::new(plan), sort_expr, 10, true)?;
+        let plan = PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(
+            Arc::new(PhysicalPlan::TopK(Arc::new(top_k))),
+            10,
+        )));
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+        let plan2: PhysicalPlan = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_window() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("state", DataType::Utf8, false),
+            Field::new("salary", DataType::Int32, false),
+        ]);
+        let reader =
+            ShuffleReaderExec::new(Arc::new(schema), vec![ShuffleId::new(Uuid::new_v4(), 1, 0)]);
+
+        let window_expr = vec![
+            rank()
+                .partition_by(vec![col("state")])
+                .order_by(vec![col("salary")])
+                .alias("salary_rank"),
+            over(max(col("salary")))?
+                .partition_by(vec![col("state")])
+                .order_by(vec![col("id")])
+                .rows_between(Some(2), None),
+        ];
+        for window_expr in window_expr {
+            let expr = window_expr.to_expr();
+            let proto: protobuf::LogicalExprNode = (&expr).try_into()?;
+            let expr2: Expr = (&proto).try_into()?;
+            assert_eq!(format!("{:?}", expr), format!("{:?}", expr2));
+
+            let plan = PhysicalPlan::Window(Arc::new(WindowExec::try_new(
+                Arc::new(PhysicalPlan::ShuffleReader(Arc::new(reader.clone()))),
+                window_expr,
+            )?));
+            let proto: protobuf::PhysicalPlanNode = (&plan).try_into()?;
+            let plan2: PhysicalPlan = (&proto).try_into()?;
+            assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_executor_action() -> Result<()> {
+        for executor_action in &[
+            ExecutorAction::Shutdown,
+            ExecutorAction::Drain,
+            ExecutorAction::ClearShuffleCache,
+            ExecutorAction::Stats,
+            ExecutorAction::ListShuffles,
+        ] {
+            let action = &Action::Manage(*executor_action);
+
+            let proto: protobuf::Action = action.try_into()?;
+
+            let action2: Action = (&proto).try_into()?;
+
+            assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        }
+
+        // a message without an action type must not be taken as a shutdown
+        let mut proto: protobuf::Action = (&Action::Manage(ExecutorAction::Shutdown)).try_into()?;
+        if let Some(executor_action) = proto.executor_action.as_mut() {
+            executor_action.action_type = protobuf::ExecutorActionType::Unspecified as i32;
+        }
+        let action: Result<Action> = (&proto).try_into();
+        assert!(action.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_registry_actions() -> Result<()> {
+        for action in &[
+            Action::RegisterExecutor(ExecutorRegistration {
+                meta: ExecutorMeta {
+                    id: "executor-1".to_owned(),
+                    host: "localhost".to_owned(),
+                    port: 50051,
+                },
+                cores: 8,
+                memory_bytes: 1 << 30,
+            }),
+            Action::Heartbeat {
+                executor_id: "executor-1".to_owned(),
+            },
+            Action::DeregisterExecutor {
+                executor_id: "executor-1".to_owned(),
+            },
+            Action::ListExecutors,
+        ] {
+            let proto: protobuf::Action = action.try_into()?;
+
+            let action2: Action = (&proto).try_into()?;
+
+            assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_prepared_actions() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("id", DataType::Int64, false)]);
+        let plan = LogicalPlanBuilder::scan("default", "employee", &schema, None)
+            .and_then(|plan| plan.filter(col("id").gt(&param(0, DataType::Int64))))
+            .and_then(|plan| plan.build())?;
+
+        for action in &[
+            Action::Prepare {
+                plan,
+                settings: QuerySettings::default(),
+            },
+            Action::ExecutePrepared {
+                handle: "handle-1".to_owned(),
+                params: vec![ScalarValue::Int64(5), ScalarValue::Utf8("CO".to_owned())],
+            },
+            Action::ClosePrepared("handle-1".to_owned()),
+        ] {
+            let proto: protobuf::Action = action.try_into()?;
+
+            let action2: Action = (&proto).try_into()?;
+
+            assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_job_status() -> Result<()> {
+        let job_uuid = Uuid::new_v4();
+        let status = JobStatus {
+            job_uuid,
+            state: JobState::Completed(vec![ShuffleLocation::new(
+                ShuffleId::new(job_uuid, 1, 0),
+                ExecutorMeta {
+                    id: "executor-1".to_owned(),
+                    host: "localhost".to_owned(),
+                    port: 50051,
+                },
+            )]),
+            submitted_at: UNIX_EPOCH + Duration::from_millis(1_600_000_000_000),
+            progress: JobProgress {
+                stages: vec![StageProgress {
+                    stage_id: 1,
+                    total_tasks: 4,
+                    tasks: TaskCounts {
+                        completed: 3,
+                        running: 1,
+                        ..TaskCounts::default()
+                    },
+                    shuffle_bytes: 4096,
+                    completed: false,
+                }],
+            },
+        };
+
+        let proto: protobuf::JobStatus = (&status).try_into()?;
+
+        let status2: JobStatus = (&proto).try_into()?;
+
+        assert_eq!(status, status2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_task_metrics() -> Result<()> {
+        let metrics = TaskMetrics {
+            output_rows: 100,
+            output_batches: 2,
+            shuffle_bytes: 4096,
+            duration_ms: 12,
+            operators: vec![
+                OperatorMetrics {
+                    name: "Projection".to_owned(),
+                    output_rows: 100,
+                    output_batches: 2,
+                    output_bytes: 1600,
+                    elapsed_ms: 10,
+                },
+                OperatorMetrics {
+                    name: "CsvScan".to_owned(),
+                    output_rows: 100,
+                    output_batches: 2,
+                    output_bytes: 3200,
+                    elapsed_ms: 8,
+                },
+            ],
+            partition_rows: vec![70, 30],
+        };
+
+        let proto: protobuf::TaskMetrics = (&metrics).try_into()?;
+
+        let metrics2: TaskMetrics = (&proto).try_into()?;
+
+        assert_eq!(metrics, metrics2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_task_updates() -> Result<()> {
+        let job_uuid = Uuid::new_v4();
+        let action = Action::WatchTasks(job_uuid);
+        let proto: protobuf::Action = (&action).try_into()?;
+        let action2: Action = (&proto).try_into()?;
+        assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+
+        for state in vec![
+            TaskUpdateState::Running,
+            TaskUpdateState::Completed(TaskMetrics {
+                output_rows: 100,
+                shuffle_bytes: 4096,
+                ..TaskMetrics::default()
+            }),
+            TaskUpdateState::Failed("out of memory".to_owned()),
+            TaskUpdateState::Cancelled,
+        ] {
+            let update = TaskUpdate {
+                job_uuid,
+                stage_id: 2,
+                partition_id: 3,
+                attempt: 1,
+                state,
+            };
+
+            let proto: protobuf::TaskStatusUpdate = (&update).try_into()?;
+
+            let update2: TaskUpdate = (&proto).try_into()?;
+
+            assert_eq!(update, update2);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_dictionary_schema() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new(
+                "name",
+                DataType::Dictionary(Box::new(DataType::Int16), Box::new(DataType::Utf8)),
+                true,
+            ),
+        ]);
+
+        let proto: protobuf::Schema = (&schema).try_into()?;
+
+        let schema2: Schema = (&proto).try_into()?;
+
+        assert_eq!(schema, schema2);
+
+        Ok(())
+    }
+
     fn max(expr: Expr) -> Expr {
         Expr::AggregateFunction {
             name: "MAX".to_owned(),
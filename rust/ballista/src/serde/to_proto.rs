@@ -15,13 +15,31 @@
 //! Serde code to convert from Rust data structures to protocol buffers.
 
 use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::arrow::datatypes::{DataType, Schema};
+use crate::arrow::datatypes::{DataType, DateUnit, Schema, TimeUnit};
 use crate::datafusion::logicalplan::{Expr, LogicalPlan, ScalarValue};
-use crate::distributed::scheduler::ExecutionTask;
-use crate::error::BallistaError;
-use crate::execution::physical_plan::{Action, ExecutionPlan, ShuffleId};
-use crate::execution::physical_plan::{AggregateMode, PhysicalPlan};
+use crate::distributed::job_history::JobHistoryEntry;
+use crate::distributed::job_state::{JobRecord, StageRecord};
+use crate::distributed::scheduler::{ExecutionTask, QuerySettings};
+use crate::distributed::scheduler_server::{JobState, JobStatus};
+use crate::distributed::table_store::TableDefinition;
+use crate::error::{ballista_error, BallistaError};
+use crate::execution::expressions::{
+    decode_predicate, parameter_index, CaseParts, ScalarFunction, BETWEEN_FUNCTION_NAME,
+    CASE_FUNCTION_NAME, COALESCE_FUNCTION_NAME, IN_LIST_FUNCTION_NAME, PARAMETER_FUNCTION_NAME,
+};
+use crate::execution::operators::{
+    CsvFormatOptions, JoinNode, UnionNode, WindowExpr, ARROW_SCHEMA_NAME, AVRO_SCHEMA_NAME,
+    JSON_SCHEMA_NAME,
+};
+use crate::execution::physical_plan::{
+    Action, ExecutionPlan, ExecutorAction, ShuffleId, ShuffleLocation, TaskMetrics, TaskUpdate,
+    TaskUpdateState,
+};
+use crate::execution::physical_plan::{AggregateMode, JoinType, Partitioning, PhysicalPlan};
+use crate::execution::udf::udf_registry;
 use crate::protobuf;
 
 impl TryInto<protobuf::Action> for &Action {
@@ -29,23 +47,547 @@ impl TryInto<protobuf::Action> for &Action {
 
     fn try_into(self) -> Result<protobuf::Action, Self::Error> {
         match self {
-            Action::InteractiveQuery { ref plan } => {
+            Action::InteractiveQuery { plan, settings } => {
                 let plan_proto: protobuf::LogicalPlanNode = plan.try_into()?;
                 Ok(protobuf::Action {
                     query: Some(plan_proto),
                     task: None,
                     fetch_shuffle: None,
+                    executor_action: None,
+                    cancel_task: None,
+                    release_job: None,
+                    register_executor: None,
+                    heartbeat: None,
+                    list_executors: None,
+                    register_table: None,
+                    write_query: None,
+                    analyze: None,
+                    explain: None,
+                    settings: Some(settings.try_into()?),
+                    watch_tasks: None,
+                    withdraw_task: None,
+                    retain_shuffles: None,
+                    prepare: None,
+                    execute_prepared: None,
+                    close_prepared: None,
+                    deregister_executor: None,
                 })
             }
             Action::Execute(task) => Ok(protobuf::Action {
                 query: None,
                 task: Some(task.try_into()?),
                 fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
             }),
             Action::FetchShuffle(shuffle_id) => Ok(protobuf::Action {
                 query: None,
                 task: None,
                 fetch_shuffle: Some(shuffle_id.try_into()?),
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::Manage(action) => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: Some(protobuf::ExecutorAction {
+                    action_type: match action {
+                        ExecutorAction::Shutdown => protobuf::ExecutorActionType::Shutdown,
+                        ExecutorAction::Drain => protobuf::ExecutorActionType::Drain,
+                        ExecutorAction::ClearShuffleCache => {
+                            protobuf::ExecutorActionType::ClearShuffleCache
+                        }
+                        ExecutorAction::Stats => protobuf::ExecutorActionType::Stats,
+                        ExecutorAction::ListShuffles => protobuf::ExecutorActionType::ListShuffles,
+                    }
+                    .into(),
+                }),
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::CancelTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: Some(protobuf::CancelTask {
+                    job_uuid: job_uuid.to_string(),
+                    stage_id: *stage_id as u32,
+                    partition_id: *partition_id as u32,
+                }),
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::ReleaseJob(job_uuid) => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: Some(protobuf::ReleaseJob {
+                    job_uuid: job_uuid.to_string(),
+                }),
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::RegisterExecutor(registration) => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: Some(protobuf::ExecutorRegistration {
+                    id: registration.meta.id.clone(),
+                    host: registration.meta.host.clone(),
+                    port: registration.meta.port as u32,
+                    cores: registration.cores as u32,
+                    memory_bytes: registration.memory_bytes,
+                }),
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::Heartbeat { executor_id } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: Some(protobuf::Heartbeat {
+                    executor_id: executor_id.clone(),
+                }),
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::RegisterTable { name, plan } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: Some(protobuf::RegisterTable {
+                    name: name.clone(),
+                    plan: Some(plan.try_into()?),
+                }),
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::Write {
+                plan,
+                path,
+                format,
+                settings,
+            } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: Some(protobuf::WriteQuery {
+                    plan: Some(plan.try_into()?),
+                    path: path.clone(),
+                    file_format: format.name().to_owned(),
+                }),
+                analyze: None,
+                explain: None,
+                settings: Some(settings.try_into()?),
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::Analyze { plan, settings } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: Some(protobuf::AnalyzeQuery {
+                    plan: Some(plan.try_into()?),
+                }),
+                explain: None,
+                settings: Some(settings.try_into()?),
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::Explain {
+                plan,
+                analyze,
+                settings,
+            } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: Some(protobuf::ExplainQuery {
+                    plan: Some(plan.try_into()?),
+                    analyze: *analyze,
+                }),
+                settings: Some(settings.try_into()?),
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::ListExecutors => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: Some(protobuf::ListExecutors {}),
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::WatchTasks(job_uuid) => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: Some(protobuf::WatchTasks {
+                    job_uuid: job_uuid.to_string(),
+                }),
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::WithdrawTask {
+                job_uuid,
+                stage_id,
+                partition_id,
+            } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: Some(protobuf::CancelTask {
+                    job_uuid: job_uuid.to_string(),
+                    stage_id: *stage_id as u32,
+                    partition_id: *partition_id as u32,
+                }),
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::RetainShuffles {
+                job_uuid,
+                shuffle_ids,
+            } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: Some(protobuf::RetainShuffles {
+                    job_uuid: job_uuid.to_string(),
+                    shuffle_ids: shuffle_ids
+                        .iter()
+                        .map(|shuffle_id| shuffle_id.try_into())
+                        .collect::<Result<_, _>>()?,
+                }),
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::Prepare { plan, settings } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: Some(settings.try_into()?),
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: Some(plan.try_into()?),
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::ExecutePrepared { handle, params } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: Some(protobuf::ExecutePrepared {
+                    handle: handle.clone(),
+                    params: params
+                        .iter()
+                        .map(|value| (&Expr::Literal(value.clone())).try_into())
+                        .collect::<Result<_, _>>()?,
+                }),
+                close_prepared: None,
+                deregister_executor: None,
+            }),
+            Action::ClosePrepared(handle) => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: Some(protobuf::ClosePrepared {
+                    handle: handle.clone(),
+                }),
+                deregister_executor: None,
+            }),
+            Action::DeregisterExecutor { executor_id } => Ok(protobuf::Action {
+                query: None,
+                task: None,
+                fetch_shuffle: None,
+                executor_action: None,
+                cancel_task: None,
+                release_job: None,
+                register_executor: None,
+                heartbeat: None,
+                list_executors: None,
+                register_table: None,
+                write_query: None,
+                analyze: None,
+                explain: None,
+                settings: None,
+                watch_tasks: None,
+                withdraw_task: None,
+                retain_shuffles: None,
+                prepare: None,
+                execute_prepared: None,
+                close_prepared: None,
+                deregister_executor: Some(protobuf::Heartbeat {
+                    executor_id: executor_id.clone(),
+                }),
             }),
         }
     }
@@ -59,24 +601,41 @@ impl TryInto<protobuf::Schema> for &Schema {
             columns: self
                 .fields()
                 .iter()
-                .map(|field| {
-                    let proto = to_proto_arrow_type(&field.data_type());
-                    proto.and_then(|arrow_type| {
-                        Ok(protobuf::Field {
-                            name: field.name().to_owned(),
-                            arrow_type: arrow_type.into(),
-                            nullable: field.is_nullable(),
-                            children: vec![],
-                        })
-                    })
-                })
+                .map(|field| to_proto_field(field.name(), field.data_type(), field.is_nullable()))
                 .collect::<Result<Vec<_>, _>>()?,
         })
     }
 }
 
+/// Dictionary-encoded fields have the key and value types as their children
+fn to_proto_field(
+    name: &str,
+    data_type: &DataType,
+    nullable: bool,
+) -> Result<protobuf::Field, BallistaError> {
+    match data_type {
+        DataType::Dictionary(key_type, value_type) => Ok(protobuf::Field {
+            name: name.to_owned(),
+            arrow_type: protobuf::ArrowType::Dictionary.into(),
+            nullable,
+            children: vec![
+                to_proto_field("key", key_type, false)?,
+                to_proto_field("value", value_type, true)?,
+            ],
+        }),
+        other => Ok(protobuf::Field {
+            name: name.to_owned(),
+            arrow_type: to_proto_arrow_type(other)?.into(),
+            nullable,
+            children: vec![],
+        }),
+    }
+}
+
 fn to_proto_arrow_type(dt: &DataType) -> Result<protobuf::ArrowType, BallistaError> {
     match dt {
+        DataType::Null => Ok(protobuf::ArrowType::None),
+        DataType::Boolean => Ok(protobuf::ArrowType::Bool),
         DataType::Int8 => Ok(protobuf::ArrowType::Int8),
         DataType::Int16 => Ok(protobuf::ArrowType::Int16),
         DataType::Int32 => Ok(protobuf::ArrowType::Int32),
@@ -88,6 +647,10 @@ fn to_proto_arrow_type(dt: &DataType) -> Result<protobuf::ArrowType, BallistaErr
         DataType::Float32 => Ok(protobuf::ArrowType::Float),
         DataType::Float64 => Ok(protobuf::ArrowType::Double),
         DataType::Utf8 => Ok(protobuf::ArrowType::Utf8),
+        DataType::Date32(DateUnit::Day) => Ok(protobuf::ArrowType::Date32),
+        DataType::Date64(DateUnit::Millisecond) => Ok(protobuf::ArrowType::Date64),
+        // the time unit is not part of the protobuf type so only milliseconds are supported
+        DataType::Timestamp(TimeUnit::Millisecond, None) => Ok(protobuf::ArrowType::Timestamp),
         other => Err(BallistaError::General(format!(
             "Unsupported data type {:?}",
             other
@@ -105,6 +668,7 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                 schema,
                 projection,
                 has_header,
+                delimiter,
                 ..
             } => {
                 let mut node = empty_logical_plan_node();
@@ -114,6 +678,7 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                     _ => vec![],
                 };
 
+                let format = CsvFormatOptions::from_metadata(schema.metadata())?;
                 let schema: protobuf::Schema = schema.as_ref().try_into()?;
 
                 node.scan = Some(protobuf::ScanNode {
@@ -122,6 +687,7 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                     schema: Some(schema),
                     has_header: *has_header,
                     file_format: "csv".to_owned(),
+                    csv_options: Some(csv_options_to_proto(delimiter.unwrap_or(b','), &format)),
                 });
                 Ok(node)
             }
@@ -145,10 +711,53 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                     projection: projected_field_names,
                     schema: Some(schema),
                     has_header: false,
+                    csv_options: None,
                     file_format: "parquet".to_owned(),
                 });
                 Ok(node)
             }
+            LogicalPlan::TableScan {
+                schema_name,
+                table_name,
+                table_schema,
+                projection,
+                ..
+            } => {
+                let mut node = empty_logical_plan_node();
+
+                let projected_field_names = match projection {
+                    Some(p) => p
+                        .iter()
+                        .map(|i| table_schema.field(*i).name().clone())
+                        .collect(),
+                    _ => vec![],
+                };
+
+                let file_format = match schema_name.as_str() {
+                    JSON_SCHEMA_NAME => Some("json"),
+                    AVRO_SCHEMA_NAME => Some("avro"),
+                    ARROW_SCHEMA_NAME => Some("arrow"),
+                    _ => None,
+                };
+                if let Some(file_format) = file_format {
+                    node.scan = Some(protobuf::ScanNode {
+                        path: table_name.to_owned(),
+                        projection: projected_field_names,
+                        schema: Some(table_schema.as_ref().try_into()?),
+                        has_header: false,
+                        csv_options: None,
+                        file_format: file_format.to_owned(),
+                    });
+                    return Ok(node);
+                }
+
+                node.table_scan = Some(protobuf::TableScanNode {
+                    table_name: table_name.to_owned(),
+                    schema: Some(table_schema.as_ref().try_into()?),
+                    projection: projected_field_names,
+                });
+                Ok(node)
+            }
             LogicalPlan::Projection { expr, input, .. } => {
                 let input: protobuf::LogicalPlanNode = input.as_ref().try_into()?;
                 let mut node = empty_logical_plan_node();
@@ -191,6 +800,52 @@ impl TryInto<protobuf::LogicalPlanNode> for &LogicalPlan {
                 });
                 Ok(node)
             }
+            LogicalPlan::Sort { expr, input, .. } => {
+                let input: protobuf::LogicalPlanNode = input.as_ref().try_into()?;
+                let mut node = empty_logical_plan_node();
+                node.input = Some(Box::new(input));
+                node.sort = Some(protobuf::SortNode {
+                    expr: expr
+                        .iter()
+                        .map(|expr| expr.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                });
+                Ok(node)
+            }
+            LogicalPlan::Limit { n, input, .. } => {
+                let input: protobuf::LogicalPlanNode = input.as_ref().try_into()?;
+                let mut node = empty_logical_plan_node();
+                node.input = Some(Box::new(input));
+                node.limit = Some(protobuf::LimitNode { limit: *n as u32 });
+                Ok(node)
+            }
+            LogicalPlan::Extension { .. } => {
+                if let Some(union) = UnionNode::from_plan(self) {
+                    let mut node = empty_logical_plan_node();
+                    node.union = Some(protobuf::UnionNode {
+                        inputs: union
+                            .inputs
+                            .iter()
+                            .map(|input| input.try_into())
+                            .collect::<Result<Vec<_>, BallistaError>>()?,
+                        all: union.all,
+                    });
+                    return Ok(node);
+                }
+                let join = JoinNode::from_plan(self).ok_or_else(|| {
+                    BallistaError::NotImplemented(format!("logical plan to_proto {:?}", self))
+                })?;
+                let left: protobuf::LogicalPlanNode = (&join.left).try_into()?;
+                let right: protobuf::LogicalPlanNode = (&join.right).try_into()?;
+                let mut node = empty_logical_plan_node();
+                node.join = Some(Box::new(protobuf::JoinNode {
+                    left: Some(Box::new(left)),
+                    right: Some(Box::new(right)),
+                    on: join_on_to_proto(&join.on),
+                    join_type: join_type_to_proto(&join.join_type).into(),
+                }));
+                Ok(node)
+            }
             _ => Err(BallistaError::NotImplemented(format!(
                 "logical plan to_proto {:?}",
                 self
@@ -224,6 +879,19 @@ impl TryInto<protobuf::LogicalExprNode> for &Expr {
                 }));
                 Ok(expr_node)
             }
+            Expr::Sort {
+                expr,
+                asc,
+                nulls_first,
+            } => {
+                let mut expr_node = empty_expr_node();
+                expr_node.sort = Some(Box::new(protobuf::SortExprNode {
+                    expr: Some(Box::new(expr.as_ref().try_into()?)),
+                    asc: *asc,
+                    nulls_first: *nulls_first,
+                }));
+                Ok(expr_node)
+            }
             Expr::Literal(value) => match value {
                 ScalarValue::Utf8(s) => {
                     let mut expr = empty_expr_node();
@@ -305,6 +973,192 @@ impl TryInto<protobuf::LogicalExprNode> for &Expr {
                 }));
                 Ok(expr)
             }
+            Expr::Cast { expr, data_type } => {
+                // the arrow type only describes timestamps in milliseconds
+                let (arrow_type, time_unit) = match data_type {
+                    DataType::Timestamp(unit, None) => (
+                        protobuf::ArrowType::Timestamp,
+                        match unit {
+                            TimeUnit::Second => protobuf::TimeUnit::Second,
+                            TimeUnit::Millisecond => protobuf::TimeUnit::Millisecond,
+                            TimeUnit::Microsecond => protobuf::TimeUnit::Microsecond,
+                            TimeUnit::Nanosecond => protobuf::TimeUnit::Nanosecond,
+                        },
+                    ),
+                    other => (to_proto_arrow_type(other)?, protobuf::TimeUnit::Millisecond),
+                };
+                let mut expr_node = empty_expr_node();
+                expr_node.cast = Some(Box::new(protobuf::CastNode {
+                    expr: Some(Box::new(expr.as_ref().try_into()?)),
+                    arrow_type: arrow_type.into(),
+                    time_unit: time_unit.into(),
+                }));
+                Ok(expr_node)
+            }
+            Expr::IsNull(expr) => {
+                let mut expr_node = empty_expr_node();
+                expr_node.is_null_expr = Some(Box::new(protobuf::IsNull {
+                    expr: Some(Box::new(expr.as_ref().try_into()?)),
+                }));
+                Ok(expr_node)
+            }
+            Expr::IsNotNull(expr) => {
+                let mut expr_node = empty_expr_node();
+                expr_node.is_not_null_expr = Some(Box::new(protobuf::IsNotNull {
+                    expr: Some(Box::new(expr.as_ref().try_into()?)),
+                }));
+                Ok(expr_node)
+            }
+            Expr::Not(expr) => {
+                let mut expr_node = empty_expr_node();
+                expr_node.not_expr = Some(Box::new(protobuf::Not {
+                    expr: Some(Box::new(expr.as_ref().try_into()?)),
+                }));
+                Ok(expr_node)
+            }
+            Expr::ScalarFunction {
+                name,
+                args,
+                return_type,
+            } if name == CASE_FUNCTION_NAME => {
+                let parts = CaseParts::try_from_args(args)?;
+                let mut expr_node = empty_expr_node();
+                expr_node.case_expr = Some(Box::new(protobuf::CaseNode {
+                    expr: match &parts.expr {
+                        Some(expr) => Some(Box::new(expr.try_into()?)),
+                        None => None,
+                    },
+                    when_then_expr: parts
+                        .when_then_expr
+                        .iter()
+                        .map(|(when, then)| {
+                            Ok(protobuf::WhenThen {
+                                when_expr: Some(when.try_into()?),
+                                then_expr: Some(then.try_into()?),
+                            })
+                        })
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                    else_expr: match &parts.else_expr {
+                        Some(else_expr) => Some(Box::new(else_expr.try_into()?)),
+                        None => None,
+                    },
+                    return_type: to_proto_arrow_type(return_type)?.into(),
+                }));
+                Ok(expr_node)
+            }
+            Expr::ScalarFunction { name, args, .. } if name == IN_LIST_FUNCTION_NAME => {
+                let (negated, expr, list) = decode_predicate(args)?;
+                let mut expr_node = empty_expr_node();
+                expr_node.in_list = Some(Box::new(protobuf::InListNode {
+                    expr: Some(Box::new(expr.try_into()?)),
+                    list: list
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                    negated,
+                }));
+                Ok(expr_node)
+            }
+            Expr::ScalarFunction { name, args, .. } if name == BETWEEN_FUNCTION_NAME => {
+                match decode_predicate(args)? {
+                    (negated, expr, [low, high]) => {
+                        let mut expr_node = empty_expr_node();
+                        expr_node.between = Some(Box::new(protobuf::BetweenNode {
+                            expr: Some(Box::new(expr.try_into()?)),
+                            negated,
+                            low: Some(Box::new(low.try_into()?)),
+                            high: Some(Box::new(high.try_into()?)),
+                        }));
+                        Ok(expr_node)
+                    }
+                    _ => Err(ballista_error("BETWEEN requires a low and a high bound")),
+                }
+            }
+            Expr::ScalarFunction {
+                name,
+                args,
+                return_type,
+            } if name == COALESCE_FUNCTION_NAME => {
+                let mut expr_node = empty_expr_node();
+                expr_node.coalesce = Some(protobuf::CoalesceNode {
+                    args: args
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                    return_type: to_proto_arrow_type(return_type)?.into(),
+                });
+                Ok(expr_node)
+            }
+            Expr::ScalarFunction { .. } if WindowExpr::is_window_expr(self) => {
+                let mut expr_node = empty_expr_node();
+                expr_node.window = Some((&WindowExpr::try_from_expr(self)?).try_into()?);
+                Ok(expr_node)
+            }
+            Expr::ScalarFunction { name, .. } if name == PARAMETER_FUNCTION_NAME => {
+                let (index, data_type) =
+                    parameter_index(self).ok_or_else(|| ballista_error("Invalid parameter"))?;
+                let mut expr_node = empty_expr_node();
+                expr_node.parameter = Some(protobuf::ParameterNode {
+                    index: index as u32,
+                    arrow_type: to_proto_arrow_type(data_type)?.into(),
+                });
+                Ok(expr_node)
+            }
+            Expr::ScalarFunction { name, args, .. }
+                if ScalarFunction::from_name(name).is_none() =>
+            {
+                let signature = udf_registry().signature(name).ok_or_else(|| {
+                    BallistaError::NotImplemented(format!("Scalar function {:?}", name))
+                })?;
+                let mut expr = empty_expr_node();
+                expr.scalar_udf = Some(protobuf::ScalarUdfNode {
+                    name: signature.name.clone(),
+                    args: args
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                    arg_types: signature
+                        .arg_types
+                        .iter()
+                        .map(|t| to_proto_arrow_type(t).map(|t| t as i32))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    return_type: to_proto_arrow_type(&signature.return_type)?.into(),
+                });
+                Ok(expr)
+            }
+            Expr::ScalarFunction { name, args, .. } => {
+                let fun = ScalarFunction::from_name(name).ok_or_else(|| {
+                    BallistaError::NotImplemented(format!("Scalar function {:?}", name))
+                })?;
+                let fun = match fun {
+                    ScalarFunction::Abs => protobuf::ScalarFunction::Abs,
+                    ScalarFunction::Ceil => protobuf::ScalarFunction::Ceil,
+                    ScalarFunction::Floor => protobuf::ScalarFunction::Floor,
+                    ScalarFunction::Round => protobuf::ScalarFunction::Round,
+                    ScalarFunction::Sqrt => protobuf::ScalarFunction::Sqrt,
+                    ScalarFunction::Ln => protobuf::ScalarFunction::Ln,
+                    ScalarFunction::Exp => protobuf::ScalarFunction::Exp,
+                    ScalarFunction::Upper => protobuf::ScalarFunction::Upper,
+                    ScalarFunction::Lower => protobuf::ScalarFunction::Lower,
+                    ScalarFunction::Trim => protobuf::ScalarFunction::Trim,
+                    ScalarFunction::Ltrim => protobuf::ScalarFunction::Ltrim,
+                    ScalarFunction::Rtrim => protobuf::ScalarFunction::Rtrim,
+                    ScalarFunction::Length => protobuf::ScalarFunction::Length,
+                    ScalarFunction::Substring => protobuf::ScalarFunction::Substring,
+                    ScalarFunction::Concat => protobuf::ScalarFunction::Concat,
+                    ScalarFunction::DateTrunc => protobuf::ScalarFunction::DateTrunc,
+                    ScalarFunction::Extract => protobuf::ScalarFunction::Extract,
+                };
+                let mut expr = empty_expr_node();
+                expr.scalar_function = Some(protobuf::ScalarFunctionNode {
+                    fun: fun.into(),
+                    args: args
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
+                });
+                Ok(expr)
+            }
             Expr::AggregateFunction { name, ref args, .. } => {
                 let mut expr = empty_expr_node();
 
@@ -314,6 +1168,12 @@ impl TryInto<protobuf::LogicalExprNode> for &Expr {
                     "SUM" => Ok(protobuf::AggregateFunction::Sum),
                     "AVG" => Ok(protobuf::AggregateFunction::Avg),
                     "COUNT" => Ok(protobuf::AggregateFunction::Count),
+                    "COUNT_DISTINCT" => Ok(protobuf::AggregateFunction::CountDistinct),
+                    "VAR" => Ok(protobuf::AggregateFunction::Var),
+                    "VAR_POP" => Ok(protobuf::AggregateFunction::VarPop),
+                    "STDDEV" => Ok(protobuf::AggregateFunction::Stddev),
+                    "STDDEV_POP" => Ok(protobuf::AggregateFunction::StddevPop),
+                    "APPROX_PERCENTILE" => Ok(protobuf::AggregateFunction::ApproxPercentile),
                     other => Err(BallistaError::NotImplemented(format!(
                         "Aggregate function {:?}",
                         other
@@ -324,6 +1184,10 @@ impl TryInto<protobuf::LogicalExprNode> for &Expr {
                 expr.aggregate_expr = Some(Box::new(protobuf::AggregateExprNode {
                     aggr_function: aggr_function.into(),
                     expr: Some(Box::new(arg.try_into()?)),
+                    args: args[1..]
+                        .iter()
+                        .map(|e| e.try_into())
+                        .collect::<Result<Vec<_>, _>>()?,
                 }));
                 Ok(expr)
             }
@@ -340,6 +1204,29 @@ impl TryInto<protobuf::PhysicalPlanNode> for &PhysicalPlan {
 
     fn try_into(self) -> Result<protobuf::PhysicalPlanNode, Self::Error> {
         match self {
+            PhysicalPlan::Projection(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.projection = Some(protobuf::ProjectionExecNode {
+                    expr: exec
+                        .expr
+                        .iter()
+                        .map(|expr| expr.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                });
+                Ok(node)
+            }
+            PhysicalPlan::Write(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.write = Some(protobuf::WriteExecNode {
+                    path: exec.path.clone(),
+                    file_format: exec.format.name().to_owned(),
+                });
+                Ok(node)
+            }
             PhysicalPlan::Filter(exec) => {
                 let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
                 let mut node = empty_physical_plan_node();
@@ -349,6 +1236,61 @@ impl TryInto<protobuf::PhysicalPlanNode> for &PhysicalPlan {
                 });
                 Ok(node)
             }
+            PhysicalPlan::Sort(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.sort = Some(protobuf::SortExecNode {
+                    expr: exec
+                        .sort_expr
+                        .iter()
+                        .map(|expr| expr.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                });
+                Ok(node)
+            }
+            PhysicalPlan::TopK(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.top_k = Some(protobuf::TopKExecNode {
+                    expr: exec
+                        .sort_expr
+                        .iter()
+                        .map(|expr| expr.try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                    k: exec.k as u32,
+                    partial: exec.partial,
+                });
+                Ok(node)
+            }
+            PhysicalPlan::Window(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.window = Some(protobuf::WindowExecNode {
+                    window_expr: Some((&exec.window_expr).try_into()?),
+                });
+                Ok(node)
+            }
+            PhysicalPlan::GlobalLimit(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.global_limit = Some(protobuf::GlobalLimitExecNode {
+                    limit: exec.limit as u32,
+                });
+                Ok(node)
+            }
+            PhysicalPlan::LocalLimit(exec) => {
+                let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.input = Some(Box::new(input));
+                node.local_limit = Some(protobuf::LocalLimitExecNode {
+                    limit: exec.limit as u32,
+                });
+                Ok(node)
+            }
             PhysicalPlan::HashAggregate(exec) => {
                 let input: protobuf::PhysicalPlanNode = exec.child.as_ref().try_into()?;
                 let mut node = empty_physical_plan_node();
@@ -358,6 +1300,9 @@ impl TryInto<protobuf::PhysicalPlanNode> for &PhysicalPlan {
                         AggregateMode::Partial => protobuf::AggregateMode::Partial,
                         AggregateMode::Final => protobuf::AggregateMode::Final,
                         AggregateMode::Complete => protobuf::AggregateMode::Complete,
+                        AggregateMode::FinalPartitioned => {
+                            protobuf::AggregateMode::FinalPartitioned
+                        }
                     }
                     .into(),
                     group_expr: exec
@@ -373,21 +1318,59 @@ impl TryInto<protobuf::PhysicalPlanNode> for &PhysicalPlan {
                 });
                 Ok(node)
             }
+            PhysicalPlan::HashJoin(exec) => {
+                let left: protobuf::PhysicalPlanNode = exec.left.as_ref().try_into()?;
+                let right: protobuf::PhysicalPlanNode = exec.right.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.hash_join = Some(Box::new(protobuf::HashJoinExecNode {
+                    left: Some(Box::new(left)),
+                    right: Some(Box::new(right)),
+                    on: join_on_to_proto(&exec.on),
+                    join_type: join_type_to_proto(&exec.join_type).into(),
+                    partition_count: exec.partition_count as u32,
+                    broadcast: exec.broadcast,
+                }));
+                Ok(node)
+            }
+            PhysicalPlan::SortMergeJoin(exec) => {
+                let left: protobuf::PhysicalPlanNode = exec.left.as_ref().try_into()?;
+                let right: protobuf::PhysicalPlanNode = exec.right.as_ref().try_into()?;
+                let mut node = empty_physical_plan_node();
+                node.sort_merge_join = Some(Box::new(protobuf::SortMergeJoinExecNode {
+                    left: Some(Box::new(left)),
+                    right: Some(Box::new(right)),
+                    on: join_on_to_proto(&exec.on),
+                    join_type: join_type_to_proto(&exec.join_type).into(),
+                    batch_size: exec.batch_size as u32,
+                }));
+                Ok(node)
+            }
+            PhysicalPlan::Union(exec) => {
+                let mut node = empty_physical_plan_node();
+                node.union = Some(protobuf::UnionExecNode {
+                    inputs: exec
+                        .inputs
+                        .iter()
+                        .map(|input| input.as_ref().try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()?,
+                });
+                Ok(node)
+            }
             PhysicalPlan::CsvScan(exec) => {
                 let mut node = empty_physical_plan_node();
+                let projection = match &exec.projection {
+                    Some(p) => p.iter().map(|n| *n as u32).collect(),
+                    None => (0..exec.file_schema().fields().len() as u32).collect(),
+                };
                 node.scan = Some(protobuf::ScanExecNode {
                     path: exec.path.clone(),
-                    projection: exec
-                        .projection
-                        .as_ref()
-                        .unwrap()
-                        .iter()
-                        .map(|n| *n as u32)
-                        .collect(),
+                    projection,
                     file_format: "csv".to_owned(),
-                    schema: Some(exec.schema().as_ref().try_into()?),
-                    has_header: false,
+                    schema: Some(exec.file_schema().as_ref().try_into()?),
+                    has_header: exec.has_header,
+                    csv_options: Some(csv_options_to_proto(exec.delimiter, &exec.format)),
                     batch_size: exec.batch_size as u32,
+                    predicate: None,
                 });
                 Ok(node)
             }
@@ -410,7 +1393,66 @@ impl TryInto<protobuf::PhysicalPlanNode> for &PhysicalPlan {
                     file_format: "parquet".to_owned(),
                     schema: None,
                     has_header: false,
+                    csv_options: None,
+                    batch_size: exec.batch_size as u32,
+                    predicate: match &exec.predicate {
+                        Some(predicate) => Some(predicate.try_into()?),
+                        None => None,
+                    },
+                });
+                Ok(node)
+            }
+            PhysicalPlan::JsonScan(exec) => {
+                let mut node = empty_physical_plan_node();
+                let projection = match &exec.projection {
+                    Some(p) => p.iter().map(|n| *n as u32).collect(),
+                    None => (0..exec.schema.fields().len() as u32).collect(),
+                };
+                node.scan = Some(protobuf::ScanExecNode {
+                    path: exec.path.clone(),
+                    projection,
+                    file_format: "json".to_owned(),
+                    schema: Some(exec.schema.as_ref().try_into()?),
+                    has_header: false,
+                    csv_options: None,
+                    batch_size: exec.batch_size as u32,
+                    predicate: None,
+                });
+                Ok(node)
+            }
+            PhysicalPlan::AvroScan(exec) => {
+                let mut node = empty_physical_plan_node();
+                let projection = match &exec.projection {
+                    Some(p) => p.iter().map(|n| *n as u32).collect(),
+                    None => (0..exec.schema().fields().len() as u32).collect(),
+                };
+                node.scan = Some(protobuf::ScanExecNode {
+                    path: exec.path.clone(),
+                    projection,
+                    file_format: "avro".to_owned(),
+                    schema: None,
+                    has_header: false,
+                    csv_options: None,
                     batch_size: exec.batch_size as u32,
+                    predicate: None,
+                });
+                Ok(node)
+            }
+            PhysicalPlan::IpcScan(exec) => {
+                let mut node = empty_physical_plan_node();
+                let projection = match &exec.projection {
+                    Some(p) => p.iter().map(|n| *n as u32).collect(),
+                    None => (0..exec.schema().fields().len() as u32).collect(),
+                };
+                node.scan = Some(protobuf::ScanExecNode {
+                    path: exec.path.clone(),
+                    projection,
+                    file_format: "arrow".to_owned(),
+                    schema: None,
+                    has_header: false,
+                    csv_options: None,
+                    batch_size: 0,
+                    predicate: None,
                 });
                 Ok(node)
             }
@@ -423,9 +1465,31 @@ impl TryInto<protobuf::PhysicalPlanNode> for &PhysicalPlan {
                     .map(|s| s.try_into())
                     .collect::<Result<_, _>>()?;
 
+                let to_proto = |exprs: &[Arc<Expr>]| {
+                    exprs
+                        .iter()
+                        .map(|expr| expr.as_ref().try_into())
+                        .collect::<Result<Vec<_>, BallistaError>>()
+                };
+                let (partition_expr, range_partition_expr) = match &exec.partitioning {
+                    Partitioning::HashPartitioning(_, exprs) => (to_proto(exprs)?, vec![]),
+                    Partitioning::RangePartitioning(_, exprs) => (vec![], to_proto(exprs)?),
+                    Partitioning::UnknownPartitioning(_) => (vec![], vec![]),
+                };
+
                 node.shuffle_reader = Some(protobuf::ShuffleReaderExecNode {
                     schema: Some(exec.schema().as_ref().try_into()?),
                     shuffle_id,
+                    partition_count: exec.partitioning.partition_count() as u32,
+                    partition_expr,
+                    range_partition_expr,
+                    skew_splits: exec.skew_splits.iter().map(|n| *n as u32).collect(),
+                    skew_replicated: exec.skew_replicated.clone(),
+                    coalesced_partitions: exec
+                        .coalesced_partitions
+                        .iter()
+                        .map(|n| *n as u32)
+                        .collect(),
                 });
                 Ok(node)
             }
@@ -449,6 +1513,239 @@ impl TryInto<protobuf::ShuffleId> for &ShuffleId {
     }
 }
 
+impl TryInto<protobuf::TaskMetrics> for &TaskMetrics {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::TaskMetrics, Self::Error> {
+        Ok(protobuf::TaskMetrics {
+            output_rows: self.output_rows as u64,
+            output_batches: self.output_batches as u64,
+            shuffle_bytes: self.shuffle_bytes as u64,
+            duration_ms: self.duration_ms,
+            operators: self
+                .operators
+                .iter()
+                .map(|op| protobuf::OperatorMetrics {
+                    name: op.name.clone(),
+                    output_rows: op.output_rows as u64,
+                    output_batches: op.output_batches as u64,
+                    elapsed_ms: op.elapsed_ms,
+                    output_bytes: op.output_bytes as u64,
+                })
+                .collect(),
+            partition_rows: self.partition_rows.iter().map(|n| *n as u64).collect(),
+        })
+    }
+}
+
+impl TryInto<protobuf::TaskStatusUpdate> for &TaskUpdate {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::TaskStatusUpdate, Self::Error> {
+        let (state, error, metrics) = match &self.state {
+            TaskUpdateState::Running => (protobuf::TaskUpdateState::TaskRunning, "", None),
+            TaskUpdateState::Completed(metrics) => (
+                protobuf::TaskUpdateState::TaskCompleted,
+                "",
+                Some(metrics.try_into()?),
+            ),
+            TaskUpdateState::Failed(error) => {
+                (protobuf::TaskUpdateState::TaskFailed, error.as_str(), None)
+            }
+            TaskUpdateState::Cancelled => (protobuf::TaskUpdateState::TaskCancelled, "", None),
+        };
+        Ok(protobuf::TaskStatusUpdate {
+            job_uuid: self.job_uuid.to_string(),
+            stage_id: self.stage_id as u32,
+            partition_id: self.partition_id as u32,
+            attempt: self.attempt as u32,
+            state: state as i32,
+            error: error.to_owned(),
+            metrics,
+        })
+    }
+}
+
+impl TryInto<protobuf::ShuffleLocation> for &ShuffleLocation {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::ShuffleLocation, Self::Error> {
+        Ok(protobuf::ShuffleLocation {
+            job_uuid: self.shuffle_id.job_uuid.to_string(),
+            stage_id: self.shuffle_id.stage_id as u32,
+            partition_id: self.shuffle_id.partition_id as u32,
+            executor_id: self.executor_meta.id.clone(),
+            executor_host: self.executor_meta.host.clone(),
+            executor_port: self.executor_meta.port as u32,
+        })
+    }
+}
+
+impl TryInto<protobuf::JobStatus> for &JobStatus {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::JobStatus, Self::Error> {
+        let (state, error, partition_location) = match &self.state {
+            JobState::Queued => (protobuf::JobState::Queued, String::new(), vec![]),
+            JobState::Running => (protobuf::JobState::Running, String::new(), vec![]),
+            JobState::Completed(partitions) => (
+                protobuf::JobState::Completed,
+                String::new(),
+                partitions
+                    .iter()
+                    .map(|p| p.try_into())
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            JobState::Failed(error) => (protobuf::JobState::Failed, error.clone(), vec![]),
+            JobState::Cancelled => (protobuf::JobState::Cancelled, String::new(), vec![]),
+        };
+        let submitted_at_ms = self
+            .submitted_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Ok(protobuf::JobStatus {
+            job_uuid: self.job_uuid.to_string(),
+            state: state.into(),
+            error,
+            partition_location,
+            submitted_at_ms,
+            stage_progress: self
+                .progress
+                .stages
+                .iter()
+                .map(|stage| protobuf::StageProgress {
+                    stage_id: stage.stage_id as u32,
+                    total_tasks: stage.total_tasks as u64,
+                    pending_tasks: stage.tasks.pending as u64,
+                    queued_tasks: stage.tasks.queued as u64,
+                    running_tasks: stage.tasks.running as u64,
+                    completed_tasks: stage.tasks.completed as u64,
+                    failed_tasks: stage.tasks.failed as u64,
+                    shuffle_bytes: stage.shuffle_bytes as u64,
+                    completed: stage.completed,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl TryInto<protobuf::JobHistoryEntry> for &JobHistoryEntry {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::JobHistoryEntry, Self::Error> {
+        let (state, error) = match &self.state {
+            JobState::Queued => (protobuf::JobState::Queued, String::new()),
+            JobState::Running => (protobuf::JobState::Running, String::new()),
+            JobState::Completed(_) => (protobuf::JobState::Completed, String::new()),
+            JobState::Failed(error) => (protobuf::JobState::Failed, error.clone()),
+            JobState::Cancelled => (protobuf::JobState::Cancelled, String::new()),
+        };
+        let millis = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0)
+        };
+        Ok(protobuf::JobHistoryEntry {
+            job_uuid: self.job_uuid.to_string(),
+            tenant: self.tenant.clone(),
+            sql: self.sql.clone().unwrap_or_default(),
+            plan: self.plan.clone(),
+            fingerprint: self.fingerprint,
+            state: state.into(),
+            error,
+            submitted_at_ms: millis(self.submitted_at),
+            finished_at_ms: self.finished_at.map(millis).unwrap_or(0),
+            output_rows: self.output_rows as u64,
+            shuffle_bytes: self.shuffle_bytes as u64,
+            stages: self
+                .stages
+                .iter()
+                .map(|stage| protobuf::StageHistory {
+                    stage_id: stage.stage_id as u32,
+                    tasks: stage.tasks as u64,
+                    duration_ms: stage.duration_ms,
+                    output_rows: stage.output_rows as u64,
+                    shuffle_bytes: stage.shuffle_bytes as u64,
+                    plan: stage.plan.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl TryInto<protobuf::JobRecord> for &JobRecord {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::JobRecord, Self::Error> {
+        Ok(protobuf::JobRecord {
+            status: Some((&self.status).try_into()?),
+            root_stage_id: self.root_stage_id as u32,
+            stages: self
+                .stages
+                .iter()
+                .map(|stage| stage.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            settings: Some((&self.settings).try_into()?),
+            tenant: self.tenant.clone(),
+        })
+    }
+}
+
+impl TryInto<protobuf::TableDefinition> for &TableDefinition {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::TableDefinition, Self::Error> {
+        Ok(protobuf::TableDefinition {
+            name: self.name.clone(),
+            location: self.location.clone(),
+            file_format: self.format.clone(),
+            schema: Some((&self.schema).try_into()?),
+            partition_columns: self.partition_columns.clone(),
+        })
+    }
+}
+
+impl TryInto<protobuf::QuerySettings> for &QuerySettings {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::QuerySettings, Self::Error> {
+        Ok(protobuf::QuerySettings {
+            batch_size: self.batch_size.unwrap_or(0) as u64,
+            target_partitions: self.target_partitions.unwrap_or(0) as u32,
+            memory_limit: self.memory_limit.unwrap_or(0) as u64,
+            timeout_ms: self.timeout.map(|t| t.as_millis() as u64).unwrap_or(0),
+            task_timeout_ms: self.task_timeout.map(|t| t.as_millis() as u64).unwrap_or(0),
+        })
+    }
+}
+
+impl TryInto<protobuf::StageRecord> for &StageRecord {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::StageRecord, Self::Error> {
+        Ok(protobuf::StageRecord {
+            stage_id: self.stage_id as u32,
+            prior_stages: self.prior_stages.iter().map(|id| *id as u32).collect(),
+            plan: Some((&self.plan).try_into()?),
+            completed: self.completed,
+            shuffle_location: self
+                .shuffle_locations
+                .iter()
+                .map(|loc| loc.try_into())
+                .collect::<Result<Vec<_>, _>>()?,
+            task_assignment: self
+                .task_assignments
+                .iter()
+                .map(|(partition_id, executor_id)| protobuf::TaskAssignment {
+                    partition_id: *partition_id as u32,
+                    executor_id: executor_id.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
 impl TryInto<protobuf::Task> for &ExecutionTask {
     type Error = BallistaError;
 
@@ -466,6 +1763,17 @@ impl TryInto<protobuf::Task> for &ExecutionTask {
             });
         }
 
+        let (output_partition_count, output_partition_expr) = match &self.output_partitioning {
+            Some(Partitioning::HashPartitioning(n, exprs)) => (
+                *n as u32,
+                exprs
+                    .iter()
+                    .map(|expr| expr.as_ref().try_into())
+                    .collect::<Result<Vec<_>, BallistaError>>()?,
+            ),
+            _ => (0, vec![]),
+        };
+
         let plan = &self.plan;
         Ok(protobuf::Task {
             job_uuid: self.job_uuid.to_string(),
@@ -474,6 +1782,18 @@ impl TryInto<protobuf::Task> for &ExecutionTask {
             task_id: 0,
             plan: Some(plan.try_into()?),
             shuffle_loc,
+            shuffle_compression: self.shuffle_compression.name().to_owned(),
+            output_partition_count,
+            output_partition_expr,
+            memory_limit: self.memory_limit.unwrap_or(0) as u64,
+            deadline_ms: self
+                .deadline
+                .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            attempt: self.attempt as u32,
+            cores: self.resources.cores as u32,
+            memory_bytes: self.resources.memory_bytes,
         })
     }
 }
@@ -504,6 +1824,19 @@ fn empty_expr_node() -> protobuf::LogicalExprNode {
         has_column_index: false,
         binary_expr: None,
         aggregate_expr: None,
+        sort: None,
+        window: None,
+        scalar_function: None,
+        scalar_udf: None,
+        is_null_expr: None,
+        is_not_null_expr: None,
+        not_expr: None,
+        case_expr: None,
+        coalesce: None,
+        cast: None,
+        in_list: None,
+        between: None,
+        parameter: None,
     }
 }
 
@@ -511,11 +1844,69 @@ fn empty_expr_node() -> protobuf::LogicalExprNode {
 fn empty_logical_plan_node() -> protobuf::LogicalPlanNode {
     protobuf::LogicalPlanNode {
         scan: None,
+        table_scan: None,
         input: None,
         projection: None,
         selection: None,
         limit: None,
         aggregate: None,
+        sort: None,
+        join: None,
+        union: None,
+    }
+}
+
+impl TryInto<protobuf::WindowExprNode> for &WindowExpr {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::WindowExprNode, Self::Error> {
+        let to_proto = |exprs: &[Expr]| {
+            exprs
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<Vec<_>, BallistaError>>()
+        };
+        let frame = self.frame();
+        Ok(protobuf::WindowExprNode {
+            function: self.function.name().to_owned(),
+            args: to_proto(&self.args)?,
+            partition_by: to_proto(&self.partition_by)?,
+            order_by: to_proto(&self.order_by)?,
+            has_preceding: frame.preceding.is_some(),
+            preceding: frame.preceding.unwrap_or(0) as u64,
+            has_following: frame.following.is_some(),
+            following: frame.following.unwrap_or(0) as u64,
+            alias: self.alias.clone().unwrap_or_default(),
+        })
+    }
+}
+
+fn join_on_to_proto(on: &[(String, String)]) -> Vec<protobuf::JoinOn> {
+    on.iter()
+        .map(|(left, right)| protobuf::JoinOn {
+            left: left.clone(),
+            right: right.clone(),
+        })
+        .collect()
+}
+
+fn join_type_to_proto(join_type: &JoinType) -> protobuf::JoinType {
+    match join_type {
+        JoinType::Inner => protobuf::JoinType::Inner,
+        JoinType::Left => protobuf::JoinType::Left,
+        JoinType::Right => protobuf::JoinType::Right,
+        JoinType::Full => protobuf::JoinType::Full,
+    }
+}
+
+fn csv_options_to_proto(delimiter: u8, format: &CsvFormatOptions) -> protobuf::CsvScanOptions {
+    protobuf::CsvScanOptions {
+        delimiter: delimiter as u32,
+        quote: format.quote as u32,
+        escape: format.escape.map(|c| c as u32).unwrap_or_default(),
+        null_values: format.null_values.clone(),
+        compression: format.compression.name().to_owned(),
+        malformed_rows: format.malformed_rows.name().to_owned(),
     }
 }
 
@@ -528,7 +1919,14 @@ fn empty_physical_plan_node() -> protobuf::PhysicalPlanNode {
         selection: None,
         global_limit: None,
         local_limit: None,
+        sort: None,
+        top_k: None,
+        window: None,
         shuffle_reader: None,
         hash_aggregate: None,
+        hash_join: None,
+        sort_merge_join: None,
+        union: None,
+        write: None,
     }
 }
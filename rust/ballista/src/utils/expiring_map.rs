@@ -0,0 +1,175 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Map with time-to-live and least-recently-updated eviction
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Map from string keys to values that evicts entries once they have not been updated for
+/// longer than the time-to-live, and evicts the least recently updated entries once the map
+/// holds more than the maximum number of entries. Only entries accepted by the `evictable`
+/// predicate are ever evicted, so that entries which are still in use are retained.
+pub struct ExpiringMap<V> {
+    entries: HashMap<String, (V, Instant)>,
+    ttl: Duration,
+    max_entries: usize,
+    evictable: fn(&V) -> bool,
+    last_sweep: Instant,
+}
+
+impl<V> ExpiringMap<V> {
+    pub fn new(ttl: Duration, max_entries: usize, evictable: fn(&V) -> bool) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+            max_entries,
+            evictable,
+            last_sweep: Instant::now(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Insert or update an entry, evicting old entries if necessary
+    pub fn insert(&mut self, key: String, value: V) {
+        let now = Instant::now();
+        self.entries.insert(key, (value, now));
+        // sweeping is linear in the size of the map, so avoid doing it on every insert
+        if self.entries.len() > self.max_entries || now - self.last_sweep >= self.ttl / 10 {
+            self.evict(now);
+        }
+    }
+
+    /// Get an entry that has not expired and mark it as updated, so that entries which are in
+    /// use are kept alive. Expired entries are removed.
+    pub fn touch(&mut self, key: &str) -> Option<&V> {
+        let now = Instant::now();
+        let ttl = self.ttl;
+        let evictable = self.evictable;
+        let expired = match self.entries.get(key) {
+            Some((value, updated)) => evictable(value) && now - *updated >= ttl,
+            None => return None,
+        };
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+        self.entries.get_mut(key).map(|(value, updated)| {
+            *updated = now;
+            &*value
+        })
+    }
+
+    /// Evict the entries that have expired, returning the number that were evicted
+    pub fn evict_expired(&mut self) -> usize {
+        let len = self.entries.len();
+        self.remove_expired(Instant::now());
+        len - self.entries.len()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        self.entries.remove(key).map(|(value, _)| value)
+    }
+
+    /// Remove the entries for which the predicate returns false
+    pub fn retain<F: FnMut(&str, &V) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|key, (value, _)| f(key, value));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter().map(|(key, (value, _))| (key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(value, _)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Evict expired entries and then, if the map is still too large, the least recently
+    /// updated evictable entries
+    fn evict(&mut self, now: Instant) {
+        self.remove_expired(now);
+
+        let evictable = self.evictable;
+        if self.entries.len() > self.max_entries {
+            let mut candidates: Vec<(String, Instant)> = self
+                .entries
+                .iter()
+                .filter(|(_, (value, _))| evictable(value))
+                .map(|(key, (_, updated))| (key.clone(), *updated))
+                .collect();
+            candidates.sort_by_key(|(_, updated)| *updated);
+            let excess = self.entries.len() - self.max_entries;
+            for (key, _) in candidates.into_iter().take(excess) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        self.last_sweep = now;
+        let ttl = self.ttl;
+        let evictable = self.evictable;
+        self.entries
+            .retain(|_, (value, updated)| !evictable(value) || now - *updated < ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_updated() {
+        let mut map = ExpiringMap::new(Duration::from_secs(3600), 2, |v: &i32| *v > 0);
+        map.insert("a".to_owned(), 1);
+        map.insert("b".to_owned(), 0);
+        map.insert("c".to_owned(), 1);
+        map.insert("d".to_owned(), 1);
+        // "b" is not evictable so the oldest evictable entries are removed instead
+        assert_eq!(2, map.len());
+        assert!(map.get("b").is_some());
+    }
+
+    #[test]
+    fn evict_expired() {
+        let mut map = ExpiringMap::new(Duration::from_millis(0), 10, |_: &i32| true);
+        map.insert("a".to_owned(), 1);
+        map.insert("b".to_owned(), 1);
+        assert!(map.get("a").is_none());
+    }
+
+    #[test]
+    fn touch_expired() {
+        let mut map = ExpiringMap::new(Duration::from_millis(0), 10, |_: &i32| true);
+        map.insert("a".to_owned(), 1);
+        assert!(map.touch("a").is_none());
+        assert!(map.is_empty());
+
+        let mut map = ExpiringMap::new(Duration::from_secs(3600), 10, |_: &i32| true);
+        map.insert("a".to_owned(), 1);
+        assert_eq!(Some(&1), map.touch("a"));
+        assert_eq!(0, map.evict_expired());
+    }
+}
@@ -15,5 +15,7 @@
 //! Misc utils
 
 pub mod datagen;
+pub mod expiring_map;
 pub mod macros;
 pub mod pretty;
+pub mod test_cluster;
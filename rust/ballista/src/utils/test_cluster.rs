@@ -0,0 +1,257 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for end-to-end tests that run queries on a cluster of a scheduler and executors in
+//! the test process, and compare plans and results against golden files.
+//!
+//! Each `TestCluster` listens on its own block of free localhost ports, so tests that start
+//! clusters can run in parallel. Golden files live under `tests/golden` of the crate, and are
+//! rewritten rather than compared when `BALLISTA_UPDATE_GOLDEN` is set.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::arrow::record_batch::RecordBatch;
+use crate::dataframe::{Context, DataFrame};
+use crate::distributed::explain::DISTRIBUTED_PLAN;
+use crate::distributed::flight_service::BallistaFlightService;
+use crate::distributed::standalone::{StandaloneCluster, StandaloneConfig, STANDALONE_HOST};
+use crate::error::{ballista_error, Result};
+use crate::utils::pretty::result_str;
+
+/// Environment variable that makes golden assertions rewrite the golden files
+pub const UPDATE_GOLDEN_ENV: &str = "BALLISTA_UPDATE_GOLDEN";
+
+/// First port that test clusters try to listen on
+const FIRST_TEST_PORT: usize = 41000;
+
+/// Offset from the first test port of the next block of ports to try
+static NEXT_PORT_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// A scheduler and executors running in the test process on localhost
+pub struct TestCluster {
+    cluster: StandaloneCluster,
+}
+
+impl TestCluster {
+    /// Start a cluster of the given number of executors, which must be called in a tokio
+    /// runtime, and wait for the executors to join it
+    pub async fn new(executors: usize) -> Result<Self> {
+        Self::with_config(StandaloneConfig::new(executors)).await
+    }
+
+    /// Start a cluster with the given layout, listening on free ports rather than the ports of
+    /// the layout
+    pub async fn with_config(config: StandaloneConfig) -> Result<Self> {
+        let first_port = reserve_ports(config.executors + 1)?;
+        let config = config.with_ports(first_port, first_port + 1);
+        let cluster = StandaloneCluster::start(config).await?;
+        Ok(Self { cluster })
+    }
+
+    pub fn cluster(&self) -> &StandaloneCluster {
+        &self.cluster
+    }
+
+    /// Port of the first executor, which clients connect to
+    pub fn port(&self) -> usize {
+        self.cluster.config().executor_port
+    }
+
+    /// Flight services of the executors
+    pub fn executors(&self) -> &[BallistaFlightService] {
+        self.cluster.executors()
+    }
+
+    /// A context that runs queries on the cluster
+    pub fn context(&self) -> Context {
+        Context::remote(STANDALONE_HOST, self.port(), HashMap::new())
+    }
+
+    /// Run a SQL query on the cluster and collect its results
+    pub async fn sql(&self, ctx: &Context, sql: &str) -> Result<Vec<RecordBatch>> {
+        ctx.sql(sql)?.collect().await
+    }
+
+    /// Shut an executor down gracefully, to test how jobs recover from losing it. The first
+    /// executor serves the registry and the client connections, so it can't be stopped.
+    pub fn stop_executor(&self, index: usize) -> Result<()> {
+        if index == 0 {
+            return Err(ballista_error(
+                "The first executor of a test cluster can't be stopped",
+            ));
+        }
+        let executor = self.executors().get(index).ok_or_else(|| {
+            ballista_error(&format!("The test cluster has no executor {}", index))
+        })?;
+        executor.shutdown();
+        Ok(())
+    }
+
+    /// Replace the ports of the cluster in a description with placeholders, so that it doesn't
+    /// depend on the ports that the cluster happened to get
+    pub fn normalize(&self, text: &str) -> String {
+        let config = self.cluster.config();
+        let mut text = text.replace(
+            &format!("{}:{}", STANDALONE_HOST, config.scheduler_port),
+            &format!("{}:<scheduler>", STANDALONE_HOST),
+        );
+        for (i, port) in config.executor_ports().iter().enumerate() {
+            text = text.replace(
+                &format!("{}:{}", STANDALONE_HOST, port),
+                &format!("{}:<executor{}>", STANDALONE_HOST, i),
+            );
+        }
+        normalize_uuids(&text)
+    }
+
+    /// Compare the distributed plan of a query against a golden file
+    pub async fn assert_plan_golden(&self, df: &DataFrame, name: &str) -> Result<()> {
+        let explanation = df.explain_plans().await?;
+        let plan = explanation
+            .plan(DISTRIBUTED_PLAN)
+            .ok_or_else(|| ballista_error("The explanation has no distributed plan"))?;
+        assert_golden(name, &self.normalize(plan));
+        Ok(())
+    }
+
+    /// Compare the results of a query against a golden file, ignoring the order of the rows
+    pub async fn assert_results_golden(&self, df: &DataFrame, name: &str) -> Result<()> {
+        let results = df.collect().await?;
+        assert_golden(name, &format_results(&results)?);
+        Ok(())
+    }
+
+    pub fn shutdown(&self) {
+        self.cluster.shutdown();
+    }
+}
+
+/// Format the rows of record batches as sorted lines of tab-separated values, so that the text
+/// doesn't depend on how the rows were partitioned
+pub fn format_results(results: &[RecordBatch]) -> Result<String> {
+    let mut rows = result_str(results)?;
+    rows.sort();
+    let mut text = rows.join("\n");
+    text.push('\n');
+    Ok(text)
+}
+
+/// Path of the golden file of the given name
+pub fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.txt", name))
+}
+
+/// Assert that text matches the golden file of the given name, or write it to the golden file
+/// when `BALLISTA_UPDATE_GOLDEN` is set
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = golden_path(name);
+    if std::env::var(UPDATE_GOLDEN_ENV).is_ok() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).expect("failed to create golden directory");
+        }
+        fs::write(&path, actual).expect("failed to write golden file");
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read golden file {:?}: {:?}; run with {}=1 to create it",
+            path, e, UPDATE_GOLDEN_ENV
+        )
+    });
+    assert!(
+        expected == actual,
+        "Output does not match golden file {:?}; run with {}=1 to update it\n\
+         --- expected\n{}\n--- actual\n{}",
+        path,
+        UPDATE_GOLDEN_ENV,
+        expected,
+        actual
+    );
+}
+
+/// Replace UUIDs, such as the ids of jobs, with a placeholder
+pub fn normalize_uuids(text: &str) -> String {
+    const UUID_LEN: usize = 36;
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        if rest.len() >= UUID_LEN && rest.is_char_boundary(UUID_LEN) && is_uuid(&rest[..UUID_LEN]) {
+            out.push_str("<uuid>");
+            rest = &rest[UUID_LEN..];
+        } else {
+            let c = rest.chars().next().expect("rest is not empty");
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// Returns true for a UUID in its hyphenated form
+fn is_uuid(s: &str) -> bool {
+    s.char_indices().all(|(i, c)| match i {
+        8 | 13 | 18 | 23 => c == '-',
+        _ => c.is_ascii_hexdigit(),
+    })
+}
+
+/// Find a block of consecutive free ports and return the first of them. The ports are free
+/// when checked, so a cluster that starts on them right away is unlikely to lose them.
+fn reserve_ports(count: usize) -> Result<usize> {
+    for _ in 0..100 {
+        let offset = NEXT_PORT_OFFSET.fetch_add(count, Ordering::SeqCst);
+        // processes of different test binaries start at different ports
+        let first = FIRST_TEST_PORT + (std::process::id() as usize % 50) * 200 + offset % 10_000;
+        let free = (first..first + count)
+            .all(|port| TcpListener::bind(("127.0.0.1", port as u16)).is_ok());
+        if free {
+            return Ok(first);
+        }
+    }
+    Err(ballista_error(&format!(
+        "Failed to find {} free consecutive ports",
+        count
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_job_uuids() {
+        let text = "Job 67e55044-10b1-426f-9247-bb680e5fe0c8 has 2 stages:\nStage 1";
+        assert_eq!("Job <uuid> has 2 stages:\nStage 1", normalize_uuids(text));
+        assert_eq!("not-a-uuid", normalize_uuids("not-a-uuid"));
+    }
+
+    #[test]
+    fn results_are_sorted() -> Result<()> {
+        use crate::arrow::array::Int32Array;
+        use crate::arrow::datatypes::{DataType, Field, Schema};
+        use std::sync::Arc;
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![3, 1, 2]))])?;
+        assert_eq!("1\n2\n3\n", format_results(&[batch])?);
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+extern crate ballista;
+
+use std::fs;
+use std::path::PathBuf;
+
+use ballista::dataframe::{Context, CsvReadOptions};
+use ballista::error::Result;
+use ballista::utils::test_cluster::{assert_golden, format_results, TestCluster};
+
+const GROUP_BY_SQL: &str = "SELECT c, SUM(v), COUNT(v) FROM t GROUP BY c";
+
+/// Write a small CSV file for a test and return its path
+fn write_csv(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ballista-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("t.csv");
+    fs::write(&path, "c,v\na,1\nb,2\na,3\nc,4\nb,5\n").unwrap();
+    path
+}
+
+fn register_table(ctx: &mut Context, path: &PathBuf) -> Result<()> {
+    ctx.register_csv(
+        "t",
+        path.to_str().unwrap(),
+        CsvReadOptions::new().has_header(true),
+    )
+}
+
+#[test]
+fn group_by_on_cluster() {
+    smol::run(async {
+        let path = write_csv("group-by");
+        let cluster = TestCluster::new(2).await.unwrap();
+        let mut ctx = cluster.context();
+        register_table(&mut ctx, &path).unwrap();
+
+        let df = ctx.sql(GROUP_BY_SQL).unwrap();
+        cluster
+            .assert_results_golden(&df, "group_by_sum_count")
+            .await
+            .unwrap();
+        cluster.shutdown();
+    });
+}
+
+#[test]
+fn group_by_after_losing_executor() {
+    smol::run(async {
+        let path = write_csv("lost-executor");
+        let cluster = TestCluster::new(3).await.unwrap();
+        let mut ctx = cluster.context();
+        register_table(&mut ctx, &path).unwrap();
+
+        cluster.stop_executor(2).unwrap();
+        let results = cluster.sql(&ctx, GROUP_BY_SQL).await.unwrap();
+        assert_golden("group_by_sum_count", &format_results(&results).unwrap());
+        cluster.shutdown();
+    });
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn group_by_with_injected_faults() {
+    use ballista::distributed::fault_injection::FaultInjectionConfig;
+    use ballista::distributed::standalone::StandaloneConfig;
+
+    smol::run(async {
+        let path = write_csv("faults");
+        let faults = FaultInjectionConfig::new(7)
+            .with_task_failure(0.5)
+            .with_dropped_fetch(0.5);
+        let config = StandaloneConfig::new(3).with_fault_injection(faults);
+        let cluster = TestCluster::with_config(config).await.unwrap();
+        let mut ctx = cluster.context();
+        register_table(&mut ctx, &path).unwrap();
+
+        // first attempts fail, and their retries run without faults
+        let results = cluster.sql(&ctx, GROUP_BY_SQL).await.unwrap();
+        assert_golden("group_by_sum_count", &format_results(&results).unwrap());
+        cluster.shutdown();
+    });
+}
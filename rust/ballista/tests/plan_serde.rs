@@ -0,0 +1,293 @@
+extern crate ballista;
+
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use ballista::arrow::datatypes::{DataType, Field, Schema};
+use ballista::dataframe::{count, max, min, sum};
+use ballista::datafusion::execution::physical_plan::csv::CsvReadOptions;
+use ballista::datafusion::logicalplan::{
+    col, Expr, LogicalPlan, LogicalPlanBuilder, Operator, ScalarValue,
+};
+use ballista::distributed::scheduler::QuerySettings;
+use ballista::execution::operators::{
+    FilterExec, GlobalLimitExec, HashAggregateExec, ProjectionExec, ShuffleReaderExec, SortExec,
+};
+use ballista::execution::physical_plan::{
+    Action, AggregateMode, Partitioning, PhysicalPlan, ShuffleId,
+};
+use ballista::protobuf;
+use ballista::serde::{decode_protobuf, encode_protobuf};
+
+use proptest::prelude::*;
+use uuid::Uuid;
+
+/// An operator to apply to a plan, with columns chosen by index modulo the number of columns
+/// of its input, so that every operator can be applied to any input
+#[derive(Debug, Clone)]
+enum PlanOp {
+    Filter {
+        column: usize,
+        op: Operator,
+        value: i64,
+    },
+    Project(Vec<usize>),
+    Aggregate {
+        group: usize,
+        column: usize,
+        function: usize,
+    },
+    Sort {
+        column: usize,
+        asc: bool,
+    },
+    Limit(usize),
+}
+
+fn data_type() -> impl Strategy<Value = DataType> {
+    prop_oneof![
+        Just(DataType::Int32),
+        Just(DataType::Int64),
+        Just(DataType::Float64),
+        Just(DataType::Utf8),
+    ]
+}
+
+fn schema() -> impl Strategy<Value = Schema> {
+    prop::collection::vec((data_type(), any::<bool>()), 1..6).prop_map(|columns| {
+        Schema::new(
+            columns
+                .into_iter()
+                .enumerate()
+                .map(|(i, (data_type, nullable))| {
+                    Field::new(&format!("c{}", i), data_type, nullable)
+                })
+                .collect(),
+        )
+    })
+}
+
+fn operator() -> impl Strategy<Value = Operator> {
+    prop_oneof![
+        Just(Operator::Eq),
+        Just(Operator::NotEq),
+        Just(Operator::Lt),
+        Just(Operator::LtEq),
+        Just(Operator::Gt),
+        Just(Operator::GtEq),
+    ]
+}
+
+fn plan_op() -> impl Strategy<Value = PlanOp> {
+    prop_oneof![
+        (any::<usize>(), operator(), any::<i64>()).prop_map(|(column, op, value)| PlanOp::Filter {
+            column,
+            op,
+            value
+        }),
+        prop::collection::vec(any::<usize>(), 1..4).prop_map(PlanOp::Project),
+        (any::<usize>(), any::<usize>(), 0..4usize).prop_map(|(group, column, function)| {
+            PlanOp::Aggregate {
+                group,
+                column,
+                function,
+            }
+        }),
+        (any::<usize>(), any::<bool>()).prop_map(|(column, asc)| PlanOp::Sort { column, asc }),
+        (0..1000usize).prop_map(PlanOp::Limit),
+    ]
+}
+
+fn plan_ops() -> impl Strategy<Value = Vec<PlanOp>> {
+    prop::collection::vec(plan_op(), 0..6)
+}
+
+fn column(schema: &Schema, index: usize) -> &Field {
+    schema.field(index % schema.fields().len())
+}
+
+/// A literal of the type of a field, so that filters compare values of the same type
+fn literal(field: &Field, value: i64) -> Expr {
+    Expr::Literal(match field.data_type() {
+        DataType::Int32 => ScalarValue::Int32(value as i32),
+        DataType::Float64 => ScalarValue::Float64(value as f64),
+        DataType::Utf8 => ScalarValue::Utf8(value.to_string()),
+        _ => ScalarValue::Int64(value),
+    })
+}
+
+fn aggregate(field: &Field, function: usize) -> Expr {
+    let expr = col(field.name());
+    match (function, field.data_type()) {
+        (_, DataType::Utf8) | (0, _) => count(expr),
+        (1, _) => min(expr),
+        (2, _) => max(expr),
+        _ => sum(expr),
+    }
+}
+
+fn sort(field: &Field, asc: bool) -> Expr {
+    Expr::Sort {
+        expr: Box::new(col(field.name())),
+        asc,
+        nulls_first: !asc,
+    }
+}
+
+/// Build a logical plan that scans a CSV file and applies the operators in turn, skipping
+/// any that cannot be applied to its input
+fn logical_plan(schema: &Schema, ops: &[PlanOp]) -> LogicalPlan {
+    let options = CsvReadOptions::new().schema(schema).has_header(true);
+    let mut builder = LogicalPlanBuilder::scan_csv("test.csv", options, None).unwrap();
+    for op in ops {
+        let plan = builder.build().unwrap();
+        let input = plan.schema();
+        let next = match op {
+            PlanOp::Filter {
+                column: i,
+                op,
+                value,
+            } => {
+                let field = column(input, *i);
+                builder.filter(Expr::BinaryExpr {
+                    left: Box::new(col(field.name())),
+                    op: op.clone(),
+                    right: Box::new(literal(field, *value)),
+                })
+            }
+            PlanOp::Project(columns) => builder.project(
+                columns
+                    .iter()
+                    .map(|i| col(column(input, *i).name()))
+                    .collect(),
+            ),
+            PlanOp::Aggregate {
+                group,
+                column: i,
+                function,
+            } => builder.aggregate(
+                vec![col(column(input, *group).name())],
+                vec![aggregate(column(input, *i), *function)],
+            ),
+            PlanOp::Sort { column: i, asc } => builder.sort(vec![sort(column(input, *i), *asc)]),
+            PlanOp::Limit(n) => builder.limit(*n),
+        };
+        if let Ok(next) = next {
+            builder = next;
+        }
+    }
+    builder.build().unwrap()
+}
+
+/// Build a physical plan that reads shuffle partitions and applies the operators in turn,
+/// skipping any that cannot be applied to its input
+fn physical_plan(schema: Schema, partitions: usize, ops: &[PlanOp]) -> PhysicalPlan {
+    let job_uuid = Uuid::new_v4();
+    let shuffle_ids = (0..partitions)
+        .map(|i| ShuffleId::new(job_uuid, 1, i))
+        .collect();
+    let reader = ShuffleReaderExec::new(Arc::new(schema), shuffle_ids)
+        .with_partitioning(Partitioning::UnknownPartitioning(partitions));
+    let mut plan = PhysicalPlan::ShuffleReader(Arc::new(reader));
+    for op in ops {
+        let input = plan.as_execution_plan().schema();
+        let child = Arc::new(plan.clone());
+        let next = match op {
+            PlanOp::Filter {
+                column: i,
+                op,
+                value,
+            } => {
+                let field = column(&input, *i);
+                let predicate = Expr::BinaryExpr {
+                    left: Box::new(col(field.name())),
+                    op: op.clone(),
+                    right: Box::new(literal(field, *value)),
+                };
+                Ok(PhysicalPlan::Filter(Arc::new(FilterExec::new(
+                    &plan, &predicate,
+                ))))
+            }
+            PlanOp::Project(columns) => {
+                let exprs: Vec<_> = columns
+                    .iter()
+                    .map(|i| col(column(&input, *i).name()))
+                    .collect();
+                ProjectionExec::try_new(&exprs, child)
+                    .map(|exec| PhysicalPlan::Projection(Arc::new(exec)))
+            }
+            PlanOp::Aggregate {
+                group,
+                column: i,
+                function,
+            } => HashAggregateExec::try_new(
+                AggregateMode::Partial,
+                vec![col(column(&input, *group).name())],
+                vec![aggregate(column(&input, *i), *function)],
+                child,
+            )
+            .map(|exec| PhysicalPlan::HashAggregate(Arc::new(exec))),
+            PlanOp::Sort { column: i, asc } => {
+                SortExec::try_new(child, vec![sort(column(&input, *i), *asc)])
+                    .map(|exec| PhysicalPlan::Sort(Arc::new(exec)))
+            }
+            PlanOp::Limit(n) => Ok(PhysicalPlan::GlobalLimit(Arc::new(GlobalLimitExec::new(
+                child, *n,
+            )))),
+        };
+        if let Ok(next) = next {
+            plan = next;
+        }
+    }
+    plan
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn logical_plan_roundtrip(schema in schema(), ops in plan_ops()) {
+        let action = Action::InteractiveQuery {
+            plan: logical_plan(&schema, &ops),
+            settings: QuerySettings::default(),
+        };
+        let bytes = encode_protobuf(&action).unwrap();
+        let action2 = decode_protobuf(&bytes).unwrap();
+        prop_assert_eq!(format!("{:?}", action), format!("{:?}", action2));
+    }
+
+    #[test]
+    fn physical_plan_roundtrip(schema in schema(), partitions in 1..8usize, ops in plan_ops()) {
+        let plan = physical_plan(schema, partitions, &ops);
+        let proto: protobuf::PhysicalPlanNode = (&plan).try_into().unwrap();
+        let plan2: PhysicalPlan = (&proto).try_into().unwrap();
+        prop_assert_eq!(format!("{:?}", plan), format!("{:?}", plan2));
+    }
+
+    #[test]
+    fn decode_arbitrary_bytes(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        // errors are expected, but decoding must not panic
+        let _ = decode_protobuf(&bytes);
+    }
+
+    #[test]
+    fn decode_corrupted_plan(
+        schema in schema(),
+        ops in plan_ops(),
+        corruptions in prop::collection::vec((any::<usize>(), any::<u8>()), 1..8),
+        truncate in any::<usize>(),
+    ) {
+        let action = Action::InteractiveQuery {
+            plan: logical_plan(&schema, &ops),
+            settings: QuerySettings::default(),
+        };
+        let mut bytes = encode_protobuf(&action).unwrap();
+        for (i, byte) in corruptions {
+            let len = bytes.len();
+            bytes[i % len] = byte;
+        }
+        let len = bytes.len();
+        bytes.truncate(truncate % (len + 1));
+        let _ = decode_protobuf(&bytes);
+    }
+}
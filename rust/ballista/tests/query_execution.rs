@@ -2,14 +2,21 @@ extern crate ballista;
 
 use std::sync::Arc;
 
+use ballista::arrow::array::{Array, Int32Array, Int64Array};
 use ballista::arrow::datatypes::{DataType, Field, Schema};
 use ballista::dataframe::{avg, count, max, min, sum};
 use ballista::datafusion::logicalplan::col_index;
+use ballista::datafusion::logicalplan::Expr;
 use ballista::distributed::executor::{DefaultContext, DiscoveryMode, ExecutorConfig};
+use ballista::execution::memory::MemoryManager;
 use ballista::execution::operators::FilterExec;
 use ballista::execution::operators::HashAggregateExec;
+use ballista::execution::operators::HashJoinExec;
 use ballista::execution::operators::InMemoryTableScanExec;
-use ballista::execution::physical_plan::{AggregateMode, ColumnarBatchStream, PhysicalPlan};
+use ballista::execution::operators::SortExec;
+use ballista::execution::physical_plan::{
+    AggregateMode, ColumnarBatchStream, ExecutionContext, JoinType, PhysicalPlan,
+};
 use ballista::utils::datagen::DataGen;
 use std::collections::HashMap;
 use std::time::Instant;
@@ -73,14 +80,16 @@ async fn execute(use_filter: bool) {
     let batch = &results[0];
 
     assert_eq!(251, batch.num_rows());
-    assert_eq!(6, batch.num_columns());
+    // partial aggregation produces the sum and count for AVG so that they can be merged
+    assert_eq!(7, batch.num_columns());
 
     assert_eq!(batch.column(0).data_type(), &DataType::Int8);
     assert_eq!(batch.column(1).data_type(), &DataType::Int64);
     assert_eq!(batch.column(2).data_type(), &DataType::Int64);
     assert_eq!(batch.column(3).data_type(), &DataType::Float64);
-    assert_eq!(batch.column(4).data_type(), &DataType::Int64);
-    assert_eq!(batch.column(5).data_type(), &DataType::UInt64);
+    assert_eq!(batch.column(4).data_type(), &DataType::UInt64);
+    assert_eq!(batch.column(5).data_type(), &DataType::Int64);
+    assert_eq!(batch.column(6).data_type(), &DataType::UInt64);
 }
 
 #[test]
@@ -98,3 +107,172 @@ fn hash_aggregate_with_filter() -> std::io::Result<()> {
         std::io::Result::Ok(())
     })
 }
+
+/// Count the rows of a full outer join of two generated tables
+async fn join_rows(ctx: Arc<dyn ExecutionContext>) -> usize {
+    let mut gen = DataGen::default();
+    let schema = Schema::new(vec![
+        Field::new("c0", DataType::Int8, true),
+        Field::new("c1", DataType::Int32, false),
+    ]);
+    let scan = |gen: &mut DataGen| {
+        let batches = (0..4)
+            .map(|_| gen.create_batch(&schema, 1024).unwrap())
+            .collect();
+        Arc::new(PhysicalPlan::InMemoryTableScan(Arc::new(
+            InMemoryTableScanExec::new(batches),
+        )))
+    };
+    let join = HashJoinExec::try_new(
+        scan(&mut gen),
+        scan(&mut gen),
+        vec![("c0".to_owned(), "c0".to_owned())],
+        JoinType::Full,
+        1,
+    )
+    .unwrap();
+
+    let stream = join.execute(ctx, 0).await.unwrap();
+    let mut rows = 0;
+    while let Some(batch) = stream.next().await.unwrap() {
+        rows += batch.num_rows();
+    }
+    rows
+}
+
+#[test]
+fn hash_join_spills_when_memory_runs_out() -> std::io::Result<()> {
+    smol::run(async {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "", 0, "");
+        let in_memory = join_rows(Arc::new(DefaultContext::new(&config, HashMap::new()))).await;
+
+        // too little memory for the left input, but enough for each part of it
+        let memory = MemoryManager::new(64 * 1024, std::env::temp_dir().join("ballista-test"));
+        let ctx =
+            DefaultContext::new(&config, HashMap::new()).with_task_memory(memory.task_memory(None));
+        let spilled = join_rows(Arc::new(ctx)).await;
+
+        assert!(in_memory > 0);
+        assert_eq!(in_memory, spilled);
+        assert_eq!(0, memory.used());
+        std::io::Result::Ok(())
+    })
+}
+
+/// Sort a generated table on an integer column and return the values of the column in the order
+/// that they were produced
+async fn sorted_values(ctx: Arc<dyn ExecutionContext>) -> Vec<i32> {
+    let mut gen = DataGen::default();
+    let schema = Schema::new(vec![
+        Field::new("c0", DataType::Int8, true),
+        Field::new("c1", DataType::Int32, false),
+    ]);
+    let batches = (0..8)
+        .map(|_| gen.create_batch(&schema, 1024).unwrap())
+        .collect();
+    let scan = PhysicalPlan::InMemoryTableScan(Arc::new(InMemoryTableScanExec::new(batches)));
+    let sort_expr = Expr::Sort {
+        expr: Box::new(col_index(1)),
+        asc: true,
+        nulls_first: false,
+    };
+    let sort = SortExec::try_new(Arc::new(scan), vec![sort_expr]).unwrap();
+
+    let stream = sort.execute(ctx, 0).await.unwrap();
+    let mut values = vec![];
+    while let Some(batch) = stream.next().await.unwrap() {
+        let batch = batch.to_arrow().unwrap();
+        let column = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        values.extend((0..column.len()).map(|i| column.value(i)));
+    }
+    values
+}
+
+#[test]
+fn sort_spills_runs_when_memory_runs_out() -> std::io::Result<()> {
+    smol::run(async {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "", 0, "");
+        let in_memory = sorted_values(Arc::new(DefaultContext::new(&config, HashMap::new()))).await;
+
+        // room for a few input batches at a time, so the input is sorted in several runs
+        let memory = MemoryManager::new(16 * 1024, std::env::temp_dir().join("ballista-test"));
+        let ctx =
+            DefaultContext::new(&config, HashMap::new()).with_task_memory(memory.task_memory(None));
+        let external = sorted_values(Arc::new(ctx)).await;
+
+        assert_eq!(8 * 1024, in_memory.len());
+        assert!(in_memory.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(in_memory, external);
+        assert_eq!(0, memory.used());
+        std::io::Result::Ok(())
+    })
+}
+
+/// Aggregate a generated table grouped by an integer column with few repeated values, and
+/// return the groups sorted by key
+async fn grouped_sums(ctx: Arc<dyn ExecutionContext>) -> Vec<(i32, i64, i64)> {
+    let mut gen = DataGen::default();
+    let schema = Schema::new(vec![
+        Field::new("c0", DataType::Int8, false),
+        Field::new("c1", DataType::Int32, false),
+    ]);
+    let batches = (0..8)
+        .map(|_| gen.create_batch(&schema, 1024).unwrap())
+        .collect();
+    let scan = PhysicalPlan::InMemoryTableScan(Arc::new(InMemoryTableScanExec::new(batches)));
+    let hash_agg = HashAggregateExec::try_new(
+        AggregateMode::Complete,
+        vec![col_index(1)],
+        vec![sum(col_index(0)), max(col_index(0))],
+        Arc::new(scan),
+    )
+    .unwrap();
+
+    let stream = hash_agg.execute(ctx, 0).await.unwrap();
+    let mut groups = vec![];
+    while let Some(batch) = stream.next().await.unwrap() {
+        let batch = batch.to_arrow().unwrap();
+        let keys = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        let sums = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        let maxes = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        groups
+            .extend((0..batch.num_rows()).map(|i| (keys.value(i), sums.value(i), maxes.value(i))));
+    }
+    groups.sort();
+    groups
+}
+
+#[test]
+fn hash_aggregate_spills_groups_when_memory_runs_out() -> std::io::Result<()> {
+    smol::run(async {
+        let config = ExecutorConfig::new(DiscoveryMode::Standalone, "", 0, "");
+        let in_memory = grouped_sums(Arc::new(DefaultContext::new(&config, HashMap::new()))).await;
+
+        // too little memory for all of the groups, but enough for each partition of them
+        let memory = MemoryManager::new(256 * 1024, std::env::temp_dir().join("ballista-test"));
+        let ctx =
+            DefaultContext::new(&config, HashMap::new()).with_task_memory(memory.task_memory(None));
+        let spilled = grouped_sums(Arc::new(ctx)).await;
+
+        assert!(in_memory.len() > 1024);
+        assert_eq!(in_memory, spilled);
+        assert_eq!(0, memory.used());
+        std::io::Result::Ok(())
+    })
+}
@@ -0,0 +1,97 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Python class for the Ballista client context
+
+use std::collections::HashMap;
+
+use ballista::dataframe::{Context, CsvReadOptions};
+use ballista::execution::operators::JsonReadOptions;
+use pyo3::prelude::*;
+
+use crate::dataframe::PyDataFrame;
+use crate::errors::{to_py_err, BallistaError};
+
+/// Context for running SQL queries on a Ballista cluster, given the host and port of an
+/// executor and optional settings such as `ballista.auth.token`
+#[pyclass(name = BallistaContext)]
+pub struct PyContext {
+    ctx: Context,
+}
+
+#[pymethods]
+impl PyContext {
+    #[new]
+    #[args(port = "50051", settings = "None")]
+    fn new(host: &str, port: usize, settings: Option<HashMap<String, String>>) -> Self {
+        let settings = settings.unwrap_or_default();
+        let settings = settings
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        Self {
+            ctx: Context::remote(host, port, settings),
+        }
+    }
+
+    /// Plan a SQL query against the registered tables, returning a DataFrame that runs the
+    /// query when its results are requested
+    fn sql(&self, query: &str) -> PyResult<PyDataFrame> {
+        let df = self.ctx.sql(query).map_err(to_py_err)?;
+        Ok(PyDataFrame::new(df))
+    }
+
+    /// Register a CSV file, or directory of CSV files, as a table
+    #[args(has_header = "true", delimiter = "\",\"")]
+    fn register_csv(
+        &mut self,
+        name: &str,
+        path: &str,
+        has_header: bool,
+        delimiter: &str,
+    ) -> PyResult<()> {
+        let delimiter = match delimiter.as_bytes() {
+            [d] => *d,
+            _ => {
+                return Err(BallistaError::py_err(format!(
+                    "Delimiter must be a single byte, not '{}'",
+                    delimiter
+                )))
+            }
+        };
+        let options = CsvReadOptions::new()
+            .has_header(has_header)
+            .delimiter(delimiter);
+        self.ctx
+            .register_csv(name, path, options)
+            .map_err(to_py_err)
+    }
+
+    /// Register a Parquet file, or directory of Parquet files, as a table
+    fn register_parquet(&mut self, name: &str, path: &str) -> PyResult<()> {
+        self.ctx.register_parquet(name, path).map_err(to_py_err)
+    }
+
+    /// Register a newline-delimited JSON file, or directory of JSON files, as a table
+    fn register_json(&mut self, name: &str, path: &str) -> PyResult<()> {
+        self.ctx
+            .register_json(name, path, JsonReadOptions::new())
+            .map_err(to_py_err)
+    }
+
+    /// Register an Avro file, or directory of Avro files, as a table
+    fn register_avro(&mut self, name: &str, path: &str) -> PyResult<()> {
+        self.ctx.register_avro(name, path).map_err(to_py_err)
+    }
+}
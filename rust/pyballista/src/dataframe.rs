@@ -0,0 +1,99 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Python class for queries planned with the Ballista client context
+
+use ballista::arrow::datatypes::Schema;
+use ballista::arrow::error::ArrowError;
+use ballista::arrow::ipc::writer::StreamWriter;
+use ballista::arrow::record_batch::RecordBatch;
+use ballista::dataframe::DataFrame;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::errors::{wait_for, BallistaError};
+
+/// A query that runs on the cluster when its results are requested
+#[pyclass(name = DataFrame)]
+pub struct PyDataFrame {
+    df: DataFrame,
+}
+
+impl PyDataFrame {
+    pub fn new(df: DataFrame) -> Self {
+        Self { df }
+    }
+}
+
+#[pymethods]
+impl PyDataFrame {
+    /// Run the query and return the results as a list of `pyarrow.RecordBatch`
+    fn collect(&self, py: Python) -> PyResult<PyObject> {
+        let table = self.to_arrow(py)?;
+        Ok(table.call_method0(py, "to_batches")?)
+    }
+
+    /// Run the query and return the results as a `pyarrow.Table`
+    fn to_arrow(&self, py: Python) -> PyResult<PyObject> {
+        let df = &self.df;
+        let batches = wait_for(py, || df.collect())?;
+        let schema = match batches.first() {
+            Some(batch) => batch.schema().as_ref().clone(),
+            None => df.schema().clone(),
+        };
+        to_pyarrow(py, &schema, &batches)
+    }
+
+    /// Run the query and return the results as a `pandas.DataFrame`
+    fn to_pandas(&self, py: Python) -> PyResult<PyObject> {
+        let table = self.to_arrow(py)?;
+        Ok(table.call_method0(py, "to_pandas")?)
+    }
+
+    /// The schema of the results as a `pyarrow.Schema`
+    fn schema(&self, py: Python) -> PyResult<PyObject> {
+        let table = to_pyarrow(py, self.df.schema(), &[])?;
+        Ok(table.getattr(py, "schema")?)
+    }
+
+    /// Describe the optimized logical plan, physical plan, and stages of the query
+    fn explain(&self, py: Python) -> PyResult<String> {
+        let df = &self.df;
+        let explanation = wait_for(py, || df.explain_plans())?;
+        Ok(explanation
+            .plans
+            .iter()
+            .map(|(plan_type, plan)| format!("{}:\n{}", plan_type, plan))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+/// Pass record batches to pyarrow as an Arrow IPC stream, returning a `pyarrow.Table`
+fn to_pyarrow(py: Python, schema: &Schema, batches: &[RecordBatch]) -> PyResult<PyObject> {
+    let mut buf: Vec<u8> = vec![];
+    {
+        let to_py_err =
+            |e: ArrowError| BallistaError::py_err(format!("Failed to encode results: {:?}", e));
+        let mut writer = StreamWriter::try_new(&mut buf, schema).map_err(to_py_err)?;
+        for batch in batches {
+            writer.write(batch).map_err(to_py_err)?;
+        }
+        writer.finish().map_err(to_py_err)?;
+    }
+    let reader = py
+        .import("pyarrow.ipc")?
+        .call1("open_stream", (PyBytes::new(py, &buf),))?;
+    Ok(reader.call_method0("read_all")?.to_object(py))
+}
@@ -0,0 +1,43 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Errors raised in Python
+
+use std::future::Future;
+
+use pyo3::create_exception;
+use pyo3::exceptions::Exception;
+use pyo3::prelude::*;
+
+create_exception!(pyballista, BallistaError, Exception);
+
+/// Raise a Ballista error as a `pyballista.BallistaError`
+pub fn to_py_err(e: ballista::error::BallistaError) -> PyErr {
+    BallistaError::py_err(e.to_string())
+}
+
+/// Run the future that a function creates to completion on a new runtime without holding the
+/// GIL, so that other Python threads run while the cluster executes a query
+pub fn wait_for<C, F, T>(py: Python, create: C) -> PyResult<T>
+where
+    C: FnOnce() -> F + Send,
+    F: Future<Output = ballista::error::Result<T>>,
+    T: Send,
+{
+    py.allow_threads(|| {
+        let mut runtime = tokio::runtime::Runtime::new()
+            .map_err(|e| BallistaError::py_err(format!("Failed to start runtime: {:?}", e)))?;
+        runtime.block_on(create()).map_err(to_py_err)
+    })
+}
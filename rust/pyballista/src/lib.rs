@@ -0,0 +1,38 @@
+// Copyright 2020 Andy Grove
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Python bindings for Ballista.
+//!
+//! The `pyballista` module exposes a `BallistaContext` that registers tables and plans SQL
+//! queries, and runs them on a Ballista cluster over the Flight protocol. Results are returned
+//! as pyarrow tables, or pandas data frames, by passing them to pyarrow in the Arrow IPC
+//! streaming format.
+
+use pyo3::prelude::*;
+
+mod context;
+mod dataframe;
+mod errors;
+
+use context::PyContext;
+use dataframe::PyDataFrame;
+
+#[pymodule]
+fn pyballista(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyContext>()?;
+    m.add_class::<PyDataFrame>()?;
+    m.add("BallistaError", py.get_type::<errors::BallistaError>())?;
+    m.add("__version__", ballista::BALLISTA_VERSION)?;
+    Ok(())
+}